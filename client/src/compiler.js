@@ -23,9 +23,15 @@ class Compiler {
         parts.push(`(${value.map(f => this.compileFilter(f)).join(" OR ")})`);
       } else if (key === '$and' && Array.isArray(value)) {
         parts.push(`(${value.map(f => this.compileFilter(f)).join(" AND ")})`);
+      } else if (key === '$not' && typeof value === 'object' && value !== null) {
+        parts.push(`NOT (${this.compileFilter(value)})`);
       } else if (typeof value === 'object' && value !== null) {
         // Handle operators: { $gt: 10 }
         for (const [op, val] of Object.entries(value)) {
+          if (op === '$between' && Array.isArray(val)) {
+            parts.push(`${key} BETWEEN ${this._escape(val[0])} AND ${this._escape(val[1])}`);
+            continue;
+          }
           const symbol = this._mapOperator(op);
           parts.push(`${key} ${symbol} ${this._escape(val)}`);
         }
@@ -74,7 +80,7 @@ class Compiler {
   static _mapOperator(op) {
     const maps = {
       '$gt': '>', '$gte': '>=', '$lt': '<', '$lte': '<=',
-      '$ne': '!=', '$eq': '=', '$like': 'LIKE', '$in': 'IN'
+      '$ne': '!=', '$eq': '=', '$like': 'LIKE', '$ilike': 'ILIKE', '$in': 'IN'
     };
     return maps[op] || op;
   }
@@ -228,6 +228,9 @@ class ToriDB {
     async beginTransaction() { return this.execute("BEGIN"); }
     async commit() { return this.execute("COMMIT"); }
     async rollback() { return this.execute("ROLLBACK"); }
+    /** Aborts the next commit (it resolves to null) if one of the keys is written first. */
+    async watch(...keys) { return this.execute("WATCH", ...keys); }
+    async unwatch() { return this.execute("UNWATCH"); }
 
     /**
      * Sends a PING command to the server.
@@ -291,6 +294,28 @@ class ToriDB {
      */
     async decr(key) { return this.execute("DECR", key); }
 
+    /**
+     * Runs a server-side script atomically.
+     * @param {string} script - The script source.
+     * @param {string[]} keys - The keys the script may touch (KEYS).
+     * @param {Array} args - Further arguments (ARGV).
+     * @returns {Promise<any>} The value of the script's last expression.
+     */
+    async eval(script, keys = [], args = []) {
+        return this.execute("EVAL", script, String(keys.length), ...keys, ...args.map(String));
+    }
+
+    /**
+     * Runs a script cached by `EVAL` or `SCRIPT LOAD`, by its SHA1.
+     * @param {string} sha - The script's SHA1.
+     * @param {string[]} keys - The keys the script may touch (KEYS).
+     * @param {Array} args - Further arguments (ARGV).
+     * @returns {Promise<any>} The value of the script's last expression.
+     */
+    async evalSha(sha, keys = [], args = []) {
+        return this.execute("EVALSHA", sha, String(keys.length), ...keys, ...args.map(String));
+    }
+
     // --- NoSQL Structures ---
     /**
      * Accesses list operations for a given key.
@@ -345,6 +370,70 @@ class ToriDB {
         };
     }
 
+    /**
+     * Accesses geospatial operations for a given key (a sorted set of locations).
+     * @param {string} key - The geo key.
+     * @returns {Object} An object with geo operations (add, dist, search).
+     */
+    geo(key) {
+        return {
+            add: (lon, lat, member) => this.execute("GEOADD", key, String(lon), String(lat), member),
+            dist: (member1, member2, unit = "m") => this.execute("GEODIST", key, member1, member2, unit),
+            search: ({ member = null, lon, lat, radius, unit = "m", count = null, withDist = false, withCoord = false }) => {
+                const from = member !== null ? ["FROMMEMBER", member] : ["FROMLONLAT", String(lon), String(lat)];
+                const args = [...from, "BYRADIUS", String(radius), unit, "ASC"];
+                if (count !== null) args.push("COUNT", String(count));
+                if (withDist) args.push("WITHDIST");
+                if (withCoord) args.push("WITHCOORD");
+                return this.execute("GEOSEARCH", key, ...args);
+            }
+        };
+    }
+
+    /**
+     * Accesses bitmap operations for a given key.
+     * @param {string} key - The bitmap key.
+     * @returns {Object} An object with bitmap operations (set, get, count).
+     */
+    bitmap(key) {
+        return {
+            set: (offset, value = 1) => this.execute("SETBIT", key, String(offset), value ? "1" : "0"),
+            get: (offset) => this.execute("GETBIT", key, String(offset)),
+            count: (start = null, end = null) => start === null
+                ? this.execute("BITCOUNT", key)
+                : this.execute("BITCOUNT", key, String(start), String(end))
+        };
+    }
+
+    /**
+     * Accesses HyperLogLog operations for a given key.
+     * @param {string} key - The HyperLogLog key.
+     * @returns {Object} An object with HyperLogLog operations (add, count, merge).
+     */
+    hll(key) {
+        return {
+            add: (...elements) => this.execute("PFADD", key, ...elements.map(String)),
+            count: (...others) => this.execute("PFCOUNT", key, ...others),
+            merge: (...sources) => this.execute("PFMERGE", key, ...sources)
+        };
+    }
+
+    /**
+     * Accesses stream operations for a given key.
+     * @param {string} key - The stream key.
+     * @returns {Object} An object with stream operations (add, range, len, read).
+     */
+    stream(key) {
+        return {
+            add: (fields, id = "*") => this.execute("XADD", key, id, ...Object.entries(fields).flat().map(String)),
+            range: (start = "-", end = "+") => this.execute("XRANGE", key, start, end),
+            len: () => this.execute("XLEN", key),
+            read: (after = "$", blockMs = null) => blockMs === null
+                ? this.execute("XREAD", "STREAMS", key, after)
+                : this.execute("XREAD", "BLOCK", String(blockMs), "STREAMS", key, after)
+        };
+    }
+
     /**
      * Executes a background AOF rewrite.
      * @returns {Promise<string>}
@@ -389,9 +478,13 @@ class ToriDB {
             },
             delete: (filter) => this.execute("DELETE", "FROM", name, "WHERE", Compiler.compileFilter(filter)),
             createIndex: (idxName, col) => this.execute("CREATE", "INDEX", idxName, "ON", name, `(${col})`),
+            createVectorIndex: (col, dims, metric = "cosine") => this.execute("CREATE", "VECTOR", "INDEX", "ON", `${name}(${col})`, "DIMS", String(dims), "METRIC", metric),
+            dropIndex: (idxName) => this.execute("DROP", "INDEX", idxName, "ON", name),
+            indexes: () => this.execute("SHOW", "INDEXES", "FROM", name),
+            describe: () => this.execute("DESCRIBE", name),
             addColumn: (col, type) => this.execute("ALTER", "TABLE", name, "ADD", `${col}:${type}`),
             dropColumn: (col) => this.execute("ALTER", "TABLE", name, "DROP", col),
-            search: (col, vec, k) => new QueryBuilder(this, name).search(col, vec, k),
+            search: (col, vec, k, options) => new QueryBuilder(this, name).search(col, vec, k, options),
             count: () => new QueryBuilder(this, name).count(),
             sum: (col) => new QueryBuilder(this, name).sum(col),
             avg: (col) => new QueryBuilder(this, name).avg(col),
@@ -418,7 +511,7 @@ class ToriDB {
             },
             delete: (filter) => this.execute("DELETE", "FROM", name, "WHERE", Compiler.compileFilter(filter)),
             select: (cols) => new QueryBuilder(this, name).select(cols),
-            search: (col, vec, k) => new QueryBuilder(this, name).search(col, vec, k),
+            search: (col, vec, k, options) => new QueryBuilder(this, name).search(col, vec, k, options),
             count: () => new QueryBuilder(this, name).count(),
             sum: (col) => new QueryBuilder(this, name).sum(col),
             avg: (col) => new QueryBuilder(this, name).avg(col),
@@ -627,13 +720,18 @@ class QueryBuilder {
      * @param {string} column - The vector column name.
      * @param {number[]} vector - The query vector.
      * @param {number} [limit=10] - Number of nearest neighbors to return.
+     * @param {Object} [options] - `metric` ("cosine", "euclidean" or "dot"), `threshold` and a `where` filter.
      * @returns {Promise<any[]>}
      */
-    async search(column, vector, limit = 10) {
-        // Syntax: SEARCH table col [v] k
+    async search(column, vector, limit = 10, { metric, threshold, where } = {}) {
+        // Syntax: SEARCH table col [v] k [METRIC m] [THRESHOLD x] [WHERE filter]
         // We remove spaces to avoid server-side quoting issues in RESP parsing
         const vecStr = JSON.stringify(vector).replace(/\s+/g, '');
-        return this.client.execute("SEARCH", this.target, column, vecStr, String(limit));
+        const args = ["SEARCH", this.target, column, vecStr, String(limit)];
+        if (metric) args.push("METRIC", metric);
+        if (threshold !== undefined) args.push("THRESHOLD", String(threshold));
+        if (where) args.push("WHERE", Compiler.compileFilter(where));
+        return this.client.execute(...args);
     }
 }
 
@@ -0,0 +1,300 @@
+//! # AOF Check and Repair
+//!
+//! `toridb --check-aof <file> [--fix | --skip]` scans an AOF without starting
+//! the server and reports the corrupt records (checksum mismatch, or bytes
+//! that are not UTF-8), the malformed lines and a transaction left open at
+//! the end of the file. It can then repair the file, keeping the original as
+//! `<file>.bak`:
+//! - `--fix` truncates it before the first corrupt record, or before the
+//!   `BEGIN` of the transaction holding it. That is what a load replays
+//!   anyway, so nothing that used to load is lost.
+//! - `--skip` removes the corrupt and malformed lines only, with any
+//!   transaction holding a corrupt record, and keeps what follows them.
+//!
+//! Either way a transaction left open at the end of the file is removed too.
+//! The server itself can load past corrupt records, with the same result as
+//! `--skip`, when `aof_skip_corrupt` is on.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Read};
+use std::ops::Range;
+use crate::core::persistence::{decode_line, AofEntry};
+
+const USAGE: &str = "Usage: toridb --check-aof <file> [--fix | --skip]";
+
+/// How to repair a damaged AOF.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Repair {
+    /// Cut the file before the first corrupt record
+    Truncate,
+    /// Remove the bad lines and keep the rest
+    Skip,
+}
+
+/// What a scan found in an AOF file.
+#[derive(Debug, Default, PartialEq)]
+pub struct AofCheck {
+    pub size_bytes: u64,
+    pub records: usize,
+    /// Runs of consecutive corrupt lines, as their first and last line
+    pub corrupt: Vec<(usize, usize)>,
+    pub malformed: Vec<usize>,
+    /// Line of a `BEGIN` never committed
+    pub open_transaction: Option<usize>,
+    /// Where `--fix` cuts the file, and the records it keeps
+    pub truncate_at: Option<(u64, usize)>,
+    /// Byte ranges `--skip` removes, in order
+    skipped: Vec<Range<u64>>,
+}
+
+/// A transaction being read: where its `BEGIN` starts, and whether it holds
+/// a corrupt record.
+struct OpenTx {
+    start: u64,
+    line: usize,
+    records_before: usize,
+    damaged: bool,
+}
+
+impl AofCheck {
+    /// Scans a whole AOF file, following transactions like a load does.
+    pub fn scan(path: &str) -> io::Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut check = Self::default();
+        let mut tx: Option<OpenTx> = None;
+        let (mut offset, mut line) = (0u64, 0usize);
+        loop {
+            let mut bytes = Vec::new();
+            let read = reader.read_until(b'\n', &mut bytes)?;
+            if read == 0 {
+                break;
+            }
+            let start = offset;
+            offset += read as u64;
+            line += 1;
+            match decode_line(line, bytes) {
+                None => {}
+                Some(AofEntry::Record(record)) => {
+                    if record.command == "BEGIN" {
+                        // The load discards a transaction that never committed
+                        if let Some(open) = tx.take() {
+                            check.skip(open.start..start);
+                        }
+                        tx = Some(OpenTx { start, line, records_before: check.records, damaged: false });
+                    } else if record.command == "COMMIT"
+                        && let Some(open) = tx.take()
+                        && open.damaged
+                    {
+                        check.skip(open.start..offset);
+                    }
+                    check.records += 1;
+                }
+                Some(AofEntry::Corrupt(_)) => {
+                    match check.corrupt.last_mut() {
+                        Some((_, last)) if *last + 1 == line => *last = line,
+                        _ => check.corrupt.push((line, line)),
+                    }
+                    if check.truncate_at.is_none() {
+                        check.truncate_at = Some(match &tx {
+                            Some(open) => (open.start, open.records_before),
+                            None => (start, check.records),
+                        });
+                    }
+                    match &mut tx {
+                        Some(open) => open.damaged = true,
+                        None => check.skip(start..offset),
+                    }
+                }
+                Some(AofEntry::Malformed(_)) => {
+                    check.malformed.push(line);
+                    check.skip(start..offset);
+                }
+            }
+        }
+        if let Some(open) = tx {
+            check.open_transaction = Some(open.line);
+            check.truncate_at.get_or_insert((open.start, open.records_before));
+            check.skip(open.start..offset);
+        }
+        check.size_bytes = offset;
+        Ok(check)
+    }
+
+    /// Marks a range for `--skip`, replacing the ranges it covers.
+    fn skip(&mut self, range: Range<u64>) {
+        self.skipped.retain(|r| r.start < range.start);
+        self.skipped.push(range);
+    }
+
+    pub fn is_clean(&self) -> bool {
+        self.corrupt.is_empty() && self.malformed.is_empty() && self.open_transaction.is_none()
+    }
+
+    /// Human-readable summary, with the repair options if the file is damaged.
+    pub fn report(&self, path: &str) -> String {
+        let lines = |runs: Vec<String>| if runs.is_empty() { "none".to_string() } else { runs.join(", ") };
+        let corrupt = self.corrupt.iter()
+            .map(|(first, last)| if first == last { first.to_string() } else { format!("{}-{}", first, last) })
+            .collect();
+        let mut out = format!(
+            "AOF {} ({} bytes, {} records)\ncorrupt lines: {}\nmalformed lines: {}\nopen transaction: {}\n",
+            path, self.size_bytes, self.records, lines(corrupt),
+            lines(self.malformed.iter().map(usize::to_string).collect()),
+            self.open_transaction.map_or("none".to_string(), |line| format!("BEGIN at line {}", line)),
+        );
+        if self.is_clean() {
+            out.push_str("The AOF is valid.\n");
+        } else if let Some((at, kept)) = self.truncate_at {
+            out.push_str(&format!(
+                "--fix truncates the file to {} bytes, keeping {} of {} records; --skip removes the bad lines only.\n",
+                at, kept, self.records
+            ));
+        } else {
+            out.push_str("The load skips malformed lines; --skip removes them.\n");
+        }
+        out
+    }
+
+    /// Repairs the file this check was made of, saving it as `<path>.bak` first.
+    pub fn repair(&self, path: &str, how: Repair) -> io::Result<()> {
+        fs::copy(path, format!("{}.bak", path))?;
+        match how {
+            Repair::Truncate => {
+                let at = self.truncate_at.map_or(self.size_bytes, |(at, _)| at);
+                OpenOptions::new().write(true).open(path)?.set_len(at)
+            }
+            Repair::Skip => {
+                let tmp = format!("{}.tmp", path);
+                let mut src = BufReader::new(File::open(path)?);
+                let mut dst = BufWriter::new(File::create(&tmp)?);
+                let mut pos = 0;
+                for range in &self.skipped {
+                    io::copy(&mut src.by_ref().take(range.start - pos), &mut dst)?;
+                    io::copy(&mut src.by_ref().take(range.end - range.start), &mut io::sink())?;
+                    pos = range.end;
+                }
+                io::copy(&mut src, &mut dst)?;
+                dst.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+                fs::rename(&tmp, path)
+            }
+        }
+    }
+}
+
+/// Runs `--check-aof` with the arguments after it. The exit code is 0 for a
+/// valid or repaired file, 1 for a damaged one and 2 for a usage or I/O error.
+pub fn run(args: &[String]) -> i32 {
+    let mut path = None;
+    let mut repair = None;
+    for arg in args {
+        match arg.as_str() {
+            "--fix" if repair.is_none() => repair = Some(Repair::Truncate),
+            "--skip" if repair.is_none() => repair = Some(Repair::Skip),
+            file if !file.starts_with("--") && path.is_none() => path = Some(file),
+            _ => {
+                eprintln!("{}", USAGE);
+                return 2;
+            }
+        }
+    }
+    let Some(path) = path else {
+        eprintln!("{}", USAGE);
+        return 2;
+    };
+
+    let check = match AofCheck::scan(path) {
+        Ok(check) => check,
+        Err(e) => {
+            eprintln!("Cannot read {}: {}", path, e);
+            return 2;
+        }
+    };
+    print!("{}", check.report(path));
+    match repair {
+        _ if check.is_clean() => 0,
+        None => 1,
+        Some(how) => match check.repair(path, how) {
+            Ok(()) => {
+                println!("Repaired {}; the original is saved as {}.bak", path, path);
+                0
+            }
+            Err(e) => {
+                eprintln!("Repair of {} failed: {}", path, e);
+                2
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::persistence::{encode_record, AofLogger};
+
+    fn write_aof(name: &str, lines: &[&str]) -> String {
+        let path = std::env::temp_dir().join(format!("toridb_check_{}_{}.db", name, std::process::id()));
+        let mut text = String::new();
+        for line in lines {
+            // Lines starting with ! are written with a broken checksum
+            match line.strip_prefix('!') {
+                Some(command) => text.push_str(&encode_record(command, 1).replace("@1:", "@2:")),
+                None => text.push_str(&encode_record(line, 1)),
+            }
+            text.push('\n');
+        }
+        fs::write(&path, text).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    fn commands(path: &str) -> Vec<String> {
+        AofLogger::load_from(path, 0, false).unwrap().commands
+    }
+
+    #[test]
+    fn reports_damage() {
+        let path = write_aof("report", &["SET a 1", "!SET b 2", "!SET c 3", "SET d 4", "BEGIN", "SET e 5", "!SET f 6", "COMMIT", "SET g 7", "BEGIN", "SET h 8"]);
+        let check = AofCheck::scan(&path).unwrap();
+        assert_eq!(check.records, 8);
+        assert_eq!(check.corrupt, vec![(2, 3), (7, 7)]);
+        assert_eq!(check.open_transaction, Some(10));
+        assert_eq!(check.truncate_at.map(|(_, kept)| kept), Some(1));
+        assert!(check.report(&path).contains("corrupt lines: 2-3, 7"));
+        assert!(!check.is_clean());
+
+        // Skipping on load gives what --skip leaves
+        let skipped = AofLogger::load_from(&path, 0, true).unwrap();
+        assert_eq!(skipped.commands, vec!["SET a 1", "SET d 4", "SET g 7"]);
+        assert!(skipped.issues.iter().all(|issue| !issue.fatal));
+        assert_eq!(commands(&path), vec!["SET a 1"]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn truncates_before_the_first_corrupt_record() {
+        let path = write_aof("fix", &["SET a 1", "BEGIN", "SET b 2", "!SET c 3", "COMMIT", "SET d 4"]);
+        let check = AofCheck::scan(&path).unwrap();
+        check.repair(&path, Repair::Truncate).unwrap();
+        assert_eq!(commands(&path), vec!["SET a 1"]);
+        assert!(AofCheck::scan(&path).unwrap().is_clean());
+        assert_eq!(commands(&format!("{}.bak", path)), vec!["SET a 1"]);
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(format!("{}.bak", path)).unwrap();
+    }
+
+    #[test]
+    fn skips_bad_lines_and_keeps_the_rest() {
+        let path = write_aof("skip", &["SET a 1", "!SET b 2", "BEGIN", "SET c 3", "!SET d 4", "COMMIT", "BEGIN", "SET e 5", "COMMIT", "SET f 6", "BEGIN", "SET g 7"]);
+        let mut text = fs::read(&path).unwrap();
+        text.extend_from_slice(b"CRC32:zz\n\xff\xfe torn\n");
+        fs::write(&path, text).unwrap();
+
+        let check = AofCheck::scan(&path).unwrap();
+        assert_eq!(check.malformed, vec![13]);
+        assert_eq!(check.corrupt, vec![(2, 2), (5, 5), (14, 14)]);
+        check.repair(&path, Repair::Skip).unwrap();
+        assert!(AofCheck::scan(&path).unwrap().is_clean());
+        assert_eq!(commands(&path), vec!["SET a 1", "SET e 5", "SET f 6"]);
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(format!("{}.bak", path)).unwrap();
+    }
+}
@@ -0,0 +1,140 @@
+//! # Authentication Providers
+//!
+//! `AUTH` checks the credentials of local users against the
+//! [`SecurityStore`](crate::core::security::SecurityStore), and those of
+//! any other username against an external [`AuthProvider`]: a local user's
+//! password never leaves the server. External users are not stored in
+//! ToriDB: the provider vouches for them on every `AUTH` and supplies their
+//! ACL rules.
+//!
+//! The bundled external provider is [`OidcProvider`], which validates an
+//! access token through OAuth 2.0 token introspection (RFC 7662). Embedders
+//! can install their own (an LDAP bind, for instance) with
+//! [`SecurityStore::set_provider`](crate::core::security::SecurityStore::set_provider).
+
+use crate::core::security::User;
+use serde::Deserialize;
+use std::time::Duration;
+
+pub trait AuthProvider: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// The user `username` logs in as with `password`, or `Ok(None)` if the
+    /// credentials are rejected. Errors mean the provider could not decide
+    /// (unreachable, bad reply).
+    fn verify(&self, username: &str, password: &str) -> anyhow::Result<Option<User>>;
+}
+
+/// The `[oidc]` table of `toridb.toml`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OidcSettings {
+    /// Token introspection endpoint of the identity provider
+    pub introspection_url: String,
+    /// Credentials ToriDB presents to the endpoint (sent in the form), if it needs any
+    #[serde(default)]
+    pub client_id: String,
+    #[serde(default)]
+    pub client_secret: String,
+    /// Claim holding the user's rules, as a space-separated string or an array
+    #[serde(default = "default_rules_claim")]
+    pub rules_claim: String,
+    /// Rules of users whose token has no rules claim
+    #[serde(default)]
+    pub default_rules: Vec<String>,
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+fn default_rules_claim() -> String {
+    "toridb_rules".to_string()
+}
+
+fn default_timeout_ms() -> u64 {
+    5000
+}
+
+/// Logs in with an OAuth access token as the password: `AUTH <username>
+/// <token>`, for a username that is not a local user. The token must be
+/// active and the username must match its `username` (or `sub`) claim.
+pub struct OidcProvider {
+    settings: OidcSettings,
+    agent: ureq::Agent,
+}
+
+impl OidcProvider {
+    pub fn new(settings: OidcSettings) -> Self {
+        let agent = ureq::AgentBuilder::new()
+            .timeout(Duration::from_millis(settings.timeout_ms))
+            .build();
+        Self { settings, agent }
+    }
+
+    /// Turns an introspection reply into the user it vouches for.
+    fn user_from(&self, username: &str, reply: &serde_json::Value) -> Option<User> {
+        if reply.get("active").and_then(|v| v.as_bool()) != Some(true) {
+            return None;
+        }
+        let identity = ["username", "sub"].iter()
+            .find_map(|claim| reply.get(*claim).and_then(|v| v.as_str()))?;
+        // Without local users, a bare `AUTH <token>` arrives as the default user
+        if username != "default" && username != identity {
+            return None;
+        }
+        let rules = match reply.get(&self.settings.rules_claim) {
+            Some(serde_json::Value::String(rules)) => rules.split_whitespace().map(String::from).collect(),
+            Some(serde_json::Value::Array(rules)) => rules.iter().filter_map(|r| r.as_str().map(String::from)).collect(),
+            _ => self.settings.default_rules.clone(),
+        };
+        Some(User { username: identity.to_string(), password: String::new(), rules })
+    }
+}
+
+impl AuthProvider for OidcProvider {
+    fn name(&self) -> &'static str {
+        "oidc"
+    }
+
+    fn verify(&self, username: &str, password: &str) -> anyhow::Result<Option<User>> {
+        let mut form = vec![("token", password), ("token_type_hint", "access_token")];
+        if !self.settings.client_id.is_empty() {
+            form.push(("client_id", &self.settings.client_id));
+            form.push(("client_secret", &self.settings.client_secret));
+        }
+        let reply: serde_json::Value = self.agent.post(&self.settings.introspection_url)
+            .send_form(&form)
+            .map_err(|e| anyhow::anyhow!("Token introspection failed: {}", e))?
+            .into_json()
+            .map_err(|e| anyhow::anyhow!("Invalid token introspection reply: {}", e))?;
+        Ok(self.user_from(username, &reply))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn introspection_replies() {
+        let provider = OidcProvider::new(OidcSettings {
+            introspection_url: "http://127.0.0.1:1/introspect".to_string(),
+            client_id: String::new(),
+            client_secret: String::new(),
+            rules_claim: default_rules_claim(),
+            default_rules: vec!["+@read".to_string()],
+            timeout_ms: default_timeout_ms(),
+        });
+
+        let user = provider.user_from("default", &json!({"active": true, "sub": "alice", "toridb_rules": "+@all -@admin"})).unwrap();
+        assert_eq!(user.username, "alice");
+        assert_eq!(user.rules, ["+@all", "-@admin"]);
+
+        let user = provider.user_from("bob", &json!({"active": true, "sub": "id-1", "username": "bob"})).unwrap();
+        assert_eq!(user.rules, ["+@read"]);
+
+        assert!(provider.user_from("bob", &json!({"active": true, "sub": "alice"})).is_none());
+        assert!(provider.user_from("default", &json!({"active": false, "sub": "alice"})).is_none());
+        assert!(provider.verify("default", "token").is_err());
+    }
+}
@@ -0,0 +1,1277 @@
+//! # Key-Value Backends
+//!
+//! The flexible store keeps its keyspace (strings, lists, sets, hashes and JSON
+//! documents, each a JSON value with an optional expiry) in a [`KvBackend`].
+//! Sorted sets and Top-K sketches stay in the store's own maps.
+//!
+//! Two backends ship with the server, selected per database when it is created
+//! (`backend` / `[backends]` in `toridb.toml`):
+//! - [`MemoryBackend`] (`memory`, the default): `DashMap`s with approximated LRU
+//!   eviction, a background sweep that drops expired keys nobody reads again,
+//!   plus the optional cold tier (see `core::cold`) that moves idle keys to disk.
+//! - [`DiskBackend`] (`disk`): only key names and expiries stay in RAM; every
+//!   value lives in its own file.
+//!
+//! Embedders can plug in their own through
+//! [`DatabaseRegistry::with_backend_factory`](crate::core::registry::DatabaseRegistry::with_backend_factory).
+//! The AOF and snapshots stay the source of truth either way: backends are
+//! filled by recovery at startup, so the bundled ones clear their directory
+//! when opened.
+//!
+//! Expiries cross the trait as absolute Unix times in milliseconds.
+
+use crate::core::cold::{ColdRecord, ColdStore};
+use crate::core::config::{self, Config};
+use crate::core::eviction::{self, EvictionPolicy, Frequency};
+use dashmap::DashMap;
+use serde::Deserialize;
+use serde_json::Value;
+use std::cell::RefCell;
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Storage for the flexible keyspace.
+///
+/// An expired key must behave as missing in every method. Scan cursors must
+/// stay below 2^63 (the flexible store uses the top bit for its own maps).
+pub trait KvBackend: Send + Sync {
+    /// Name shown in `CONFIG GET backend` and logs
+    fn name(&self) -> &'static str;
+
+    fn get(&self, key: &str) -> Option<Value>;
+
+    /// Stores a value with its absolute expiry, replacing the key. A backend
+    /// that cannot store it reports it through [`write_failed`].
+    fn set(&self, key: &str, value: Value, expire_at_ms: Option<u64>);
+
+    /// Removes a key. Returns false if it did not exist.
+    fn del(&self, key: &str) -> bool;
+
+    /// Expiry of a key: `None` if the key is missing, `Some(None)` if it never expires.
+    fn ttl(&self, key: &str) -> Option<Option<u64>>;
+
+    /// Changes the expiry of an existing key; a time in the past removes it.
+    /// Returns false if the key does not exist.
+    fn set_ttl(&self, key: &str, expire_at_ms: Option<u64>) -> bool;
+
+    /// One step of an incremental iteration (SCAN). Returns the next cursor,
+    /// 0 once done, and the live keys matching `pattern`. Keys present for the
+    /// whole iteration must be returned at least once.
+    fn scan(&self, cursor: u64, pattern: Option<&str>, count: usize) -> (u64, Vec<String>);
+
+    /// Every live key with its value and expiry (snapshots, AOF rewrites, exports).
+    fn entries(&self) -> Box<dyn Iterator<Item = (String, Value, Option<u64>)> + '_> {
+        self.prefixed_entries("")
+    }
+
+    /// The live keys starting with `prefix`, with their value and expiry;
+    /// the values of other keys are not read.
+    fn prefixed_entries<'a>(&'a self, prefix: &'a str) -> Box<dyn Iterator<Item = (String, Value, Option<u64>)> + 'a>;
+
+    fn key_count(&self) -> usize;
+
+    fn clear(&self);
+
+    /// Extra `INFO` section, if the backend has metrics.
+    fn info(&self) -> Option<String> {
+        None
+    }
+
+    /// Rough bytes of RAM the keyspace uses (metrics); 0 if unknown.
+    fn memory_bytes(&self) -> usize {
+        0
+    }
+
+    /// Approximate bytes of a key and its value (`MEMORY USAGE`), `None` if missing.
+    fn memory_usage(&self, key: &str) -> Option<usize> {
+        self.get(key).map(|value| key.len() + value_size(&value))
+    }
+
+    /// True once the keyspace is at its limits and cannot evict to make room,
+    /// so writes that could add data must be refused.
+    fn is_full(&self) -> bool {
+        false
+    }
+
+    fn contains(&self, key: &str) -> bool {
+        self.ttl(key).is_some()
+    }
+
+    /// Calls `f` once with the value of a key, without copying it where possible.
+    fn inspect(&self, key: &str, f: &mut dyn FnMut(Option<&Value>)) {
+        f(self.get(key).as_ref())
+    }
+
+    /// Read-modify-write of one key. `f` is called once with the current value
+    /// (`None` if missing); leaving `None` removes the key. The expiry is kept.
+    fn update(&self, key: &str, f: &mut dyn FnMut(&mut Option<Value>)) {
+        let expire_at_ms = self.ttl(key).flatten();
+        let mut value = self.get(key);
+        let existed = value.is_some();
+        f(&mut value);
+        match value {
+            Some(value) => self.set(key, value, expire_at_ms),
+            None if existed => { self.del(key); }
+            None => {}
+        }
+    }
+}
+
+/// Bundled backends, as named in the config file.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    #[default]
+    Memory,
+    Disk,
+}
+
+impl BackendKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BackendKind::Memory => "memory",
+            BackendKind::Disk => "disk",
+        }
+    }
+}
+
+impl std::str::FromStr for BackendKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "memory" => Ok(BackendKind::Memory),
+            "disk" => Ok(BackendKind::Disk),
+            other => Err(format!("Unknown backend '{}' (memory, disk)", other)),
+        }
+    }
+}
+
+/// Opens the backend of a database being created.
+pub fn open(kind: BackendKind, db_name: &str) -> anyhow::Result<Arc<dyn KvBackend>> {
+    Ok(match kind {
+        BackendKind::Memory => Arc::new(MemoryBackend::for_database(db_name)),
+        BackendKind::Disk => Arc::new(DiskBackend::open(db_name)?),
+    })
+}
+
+/// How often the expiry sweep runs.
+const EXPIRY_SWEEP_PERIOD: Duration = Duration::from_millis(250);
+/// Time a single expiry sweep may spend before yielding until the next period.
+const EXPIRY_SWEEP_BUDGET: Duration = Duration::from_millis(5);
+
+// Internal entry to track access time and frequency
+struct Entry {
+    value: Value,
+    last_accessed: Instant,
+    frequency: Frequency,
+    /// Approximate bytes of key and value, as counted in `Keyspace::bytes`
+    size: usize,
+}
+
+impl Entry {
+    fn new(key: &str, value: Value) -> Self {
+        Self { size: entry_size(key, &value), value, last_accessed: Instant::now(), frequency: Frequency::default() }
+    }
+
+    fn touch(&mut self) {
+        self.frequency.hit(self.last_accessed);
+        self.last_accessed = Instant::now();
+    }
+}
+
+fn entry_size(key: &str, value: &Value) -> usize {
+    key.len() + std::mem::size_of::<Entry>() + value_size(value)
+}
+
+/// The resident keys, with a running total of their approximate size.
+///
+/// `insert`, `remove`, `remove_if` and `clear` keep the total up to date;
+/// changes made through the map's entry API report theirs with `resized`.
+#[derive(Default)]
+struct Keyspace {
+    map: DashMap<String, Entry>,
+    bytes: AtomicUsize,
+}
+
+impl Keyspace {
+    fn insert(&self, key: String, entry: Entry) {
+        self.resized(0, entry.size);
+        if let Some(old) = self.map.insert(key, entry) {
+            self.resized(old.size, 0);
+        }
+    }
+
+    fn remove(&self, key: &str) -> Option<(String, Entry)> {
+        let removed = self.map.remove(key);
+        if let Some((_, entry)) = &removed {
+            self.resized(entry.size, 0);
+        }
+        removed
+    }
+
+    fn remove_if(&self, key: &str, f: impl FnOnce(&String, &Entry) -> bool) -> Option<(String, Entry)> {
+        let removed = self.map.remove_if(key, f);
+        if let Some((_, entry)) = &removed {
+            self.resized(entry.size, 0);
+        }
+        removed
+    }
+
+    fn clear(&self) {
+        self.map.clear();
+        self.bytes.store(0, Ordering::Relaxed);
+    }
+
+    fn resized(&self, old: usize, new: usize) {
+        if new >= old {
+            self.bytes.fetch_add(new - old, Ordering::Relaxed);
+        } else {
+            self.bytes.fetch_sub(old - new, Ordering::Relaxed);
+        }
+    }
+
+    fn bytes(&self) -> usize {
+        self.bytes.load(Ordering::Relaxed)
+    }
+}
+
+impl Deref for Keyspace {
+    type Target = DashMap<String, Entry>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.map
+    }
+}
+
+/// The in-memory keyspace.
+///
+/// Reads are lock-free and writes lock one `DashMap` shard. When `max_keys` is
+/// reached, or a write would take the keyspace past `max_memory` bytes, keys
+/// are evicted as the database's eviction policy says (see `core::eviction`).
+pub struct MemoryBackend {
+    /// Database name, for its eviction policy
+    db: String,
+    /// key -> {json_value, last_accessed, frequency, size}
+    data: Arc<Keyspace>,
+    /// key -> expiration time
+    expiry: Arc<DashMap<String, Instant>>,
+    /// Server settings; `max_keys` is the key count at which eviction kicks in
+    config: Arc<Config>,
+    /// Disk tier for idle keys (disabled unless `cold_after_secs` is set)
+    cold: Option<Arc<ColdStore>>,
+    /// Counters of the expiry sweep
+    sweep: Arc<SweepStats>,
+    /// Keys evicted to stay under `max_keys` / `max_memory`
+    evicted: AtomicU64,
+}
+
+#[derive(Default)]
+struct SweepStats {
+    /// Expired keys removed before anyone read them again
+    expired: AtomicU64,
+    /// Expiries left behind by keys that no longer exist
+    orphans: AtomicU64,
+}
+
+impl Default for MemoryBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryBackend {
+    pub fn new() -> Self {
+        Self {
+            db: String::new(),
+            data: Arc::new(Keyspace::default()),
+            expiry: Arc::new(DashMap::new()),
+            config: config::shared(),
+            cold: None,
+            sweep: Arc::new(SweepStats::default()),
+            evicted: AtomicU64::new(0),
+        }
+    }
+
+    /// The backend of a database: starts the expiry sweep, and enables the cold
+    /// tier with its own sweeper if `cold_after_secs` is configured.
+    pub fn for_database(db_name: &str) -> Self {
+        let mut backend = Self { db: db_name.to_string(), ..Self::new() };
+        backend.spawn_expiry_sweep();
+        let Some(idle) = config::shared().cold_after() else {
+            return backend;
+        };
+
+        let cold = match ColdStore::open(db_name) {
+            Ok(c) => Arc::new(c),
+            Err(e) => {
+                crate::core::logger::error(&format!("Cold tier disabled for {}: {}", db_name, e));
+                return backend;
+            }
+        };
+        backend.cold = Some(cold.clone());
+
+        // The sweeper only holds weak references and stops once the backend is dropped
+        let period = (idle / 4).clamp(Duration::from_secs(1), Duration::from_secs(60));
+        let data = Arc::downgrade(&backend.data);
+        let expiry = Arc::downgrade(&backend.expiry);
+        let cold = Arc::downgrade(&cold);
+        std::thread::spawn(move || loop {
+            std::thread::sleep(period);
+            match (Weak::upgrade(&data), Weak::upgrade(&expiry), Weak::upgrade(&cold)) {
+                (Some(data), Some(expiry), Some(cold)) => {
+                    offload_idle(&data, &expiry, &cold, idle);
+                }
+                _ => break,
+            }
+        });
+        backend
+    }
+
+    // Like the cold sweeper, this one stops once the backend is dropped
+    fn spawn_expiry_sweep(&self) {
+        let data = Arc::downgrade(&self.data);
+        let expiry = Arc::downgrade(&self.expiry);
+        let sweep = Arc::downgrade(&self.sweep);
+        std::thread::spawn(move || {
+            let mut shard = 0;
+            loop {
+                std::thread::sleep(EXPIRY_SWEEP_PERIOD);
+                match (Weak::upgrade(&data), Weak::upgrade(&expiry), Weak::upgrade(&sweep)) {
+                    (Some(data), Some(expiry), Some(sweep)) => {
+                        shard = compact_expiry(&data, &expiry, &sweep, shard, EXPIRY_SWEEP_BUDGET);
+                    }
+                    _ => break,
+                }
+            }
+        });
+    }
+
+    fn policy(&self) -> EvictionPolicy {
+        self.config.eviction_policy_for(&self.db)
+    }
+
+    /// True if a write of `incoming` bytes (`new_key` if it adds a key) would
+    /// go past `max_keys` or `max_memory`.
+    fn over_limit(&self, new_key: bool, incoming: usize) -> bool {
+        let max_memory = self.config.max_memory();
+        (new_key && self.data.len() >= self.config.max_keys())
+            || (max_memory > 0 && self.data.bytes() + incoming > max_memory)
+    }
+
+    /// Makes room for an entry of `size` bytes replacing one of `current`
+    /// (`new_key` if it adds a key), as far as the eviction policy allows.
+    /// An entry larger than `max_memory` on its own could never fit: it is
+    /// refused (see [`write_too_large`]) with nothing evicted, and this
+    /// returns false.
+    fn evict_if_needed(&self, new_key: bool, size: usize, current: usize) -> bool {
+        let max_memory = self.config.max_memory();
+        if max_memory > 0 && size > max_memory {
+            write_too_large(size, max_memory);
+            return false;
+        }
+        let incoming = size.saturating_sub(current);
+        let policy = self.policy();
+        while !self.data.is_empty() && self.over_limit(new_key, incoming) {
+            // The sample's read lock is released before the victim is removed
+            let victim = match policy {
+                EvictionPolicy::AllkeysLru => eviction::sample(&self.data, |e| e.last_accessed).map(|(key, _)| key),
+                EvictionPolicy::AllkeysLfu => {
+                    eviction::sample(&self.data, |e| (e.frequency.decayed(e.last_accessed), e.last_accessed)).map(|(key, _)| key)
+                }
+                EvictionPolicy::VolatileTtl => eviction::sample(&self.expiry, |at| *at).map(|(key, _)| key),
+                EvictionPolicy::Noeviction => None,
+            };
+            let Some(key) = victim else { break };
+            if self.data.remove(&key).is_some() {
+                self.evicted.fetch_add(1, Ordering::Relaxed);
+            }
+            self.expiry.remove(&key);
+        }
+        true
+    }
+
+    /// Brings an offloaded key back into memory. The data shard stays write-locked
+    /// while the record is read, so concurrent accessors never observe a gap.
+    fn reload_if_cold(&self, key: &str) {
+        let Some(cold) = &self.cold else { return };
+        if !cold.contains(key) || self.data.contains_key(key) {
+            return;
+        }
+
+        let started = Instant::now();
+        if let dashmap::Entry::Vacant(slot) = self.data.entry(key.to_string())
+            && let Some(record) = cold.take(key)
+        {
+            if let Some(ms) = record.expire_at_ms {
+                match unix_ms_to_instant(ms) {
+                    Some(at) => { self.expiry.insert(key.to_string(), at); }
+                    None => return, // Expired while on disk
+                }
+            }
+            let entry = slot.insert(Entry::new(key, record.value));
+            self.data.resized(0, entry.size);
+            cold.record_reload(started.elapsed());
+        }
+    }
+
+    fn is_expired(&self, key: &str) -> bool {
+        self.expiry.get(key).map(|exp| Instant::now() > *exp).unwrap_or(false)
+    }
+
+    /// Resolves a key for access: reloads it from the cold tier and drops it if expired.
+    fn live(&self, key: &str) -> bool {
+        self.reload_if_cold(key);
+        if self.is_expired(key) {
+            self.data.remove(key);
+            self.expiry.remove(key);
+            return false;
+        }
+        self.data.contains_key(key)
+    }
+
+    // Offloaded keys starting with `prefix` that are not (or no longer) resident in memory
+    fn cold_records<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = (String, ColdRecord)> + 'a {
+        self.cold.iter().flat_map(move |cold| {
+            cold.keys().iter().filter_map(move |kv| {
+                let key = kv.key();
+                if !key.starts_with(prefix) || self.data.contains_key(key) {
+                    return None;
+                }
+                cold.peek(key).map(|record| (key.clone(), record))
+            })
+        })
+    }
+}
+
+impl KvBackend for MemoryBackend {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    fn get(&self, key: &str) -> Option<Value> {
+        if !self.live(key) {
+            return None;
+        }
+        let mut entry = self.data.get_mut(key)?;
+        entry.touch();
+        Some(entry.value.clone())
+    }
+
+    fn set(&self, key: &str, value: Value, expire_at_ms: Option<u64>) {
+        let entry = Entry::new(key, value);
+        let new_key = !self.live(key);
+        // An overwrite only needs room for the growth
+        let current = self.data.get(key).map_or(0, |e| e.size);
+        if !self.evict_if_needed(new_key, entry.size, current) {
+            return;
+        }
+        self.data.insert(key.to_string(), entry);
+        match expire_at_ms.map(unix_ms_to_instant) {
+            Some(Some(at)) => { self.expiry.insert(key.to_string(), at); }
+            Some(None) => {
+                self.data.remove(key);
+                self.expiry.remove(key);
+            }
+            None => { self.expiry.remove(key); }
+        }
+    }
+
+    fn del(&self, key: &str) -> bool {
+        let existed = self.live(key);
+        self.data.remove(key);
+        self.expiry.remove(key);
+        existed
+    }
+
+    fn ttl(&self, key: &str) -> Option<Option<u64>> {
+        if !self.live(key) {
+            return None;
+        }
+        Some(self.expiry.get(key).map(|exp| instant_to_unix_ms(*exp)))
+    }
+
+    fn set_ttl(&self, key: &str, expire_at_ms: Option<u64>) -> bool {
+        if !self.live(key) {
+            return false;
+        }
+        match expire_at_ms.map(unix_ms_to_instant) {
+            Some(Some(at)) => { self.expiry.insert(key.to_string(), at); }
+            Some(None) => {
+                self.data.remove(key);
+                self.expiry.remove(key);
+            }
+            None => { self.expiry.remove(key); }
+        }
+        true
+    }
+
+    /// The cursor is the index of the next shard to visit, counting the shards
+    /// of the data map first, then the cold tier index. Whole shards are
+    /// read-locked one at a time until at least `count` keys were collected, so
+    /// a call never blocks writers on other shards.
+    fn scan(&self, cursor: u64, pattern: Option<&str>, count: usize) -> (u64, Vec<String>) {
+        // The resident keys, then the cold ones
+        let total_parts = if self.cold.is_some() { 2 * SCAN_PARTS } else { SCAN_PARTS };
+        let mut part = cursor as usize;
+        let mut keys = Vec::new();
+
+        while part < total_parts && keys.len() < count.max(1) {
+            let wanted = count.max(1) - keys.len();
+            let (part_keys, next) = match &self.cold {
+                _ if part < SCAN_PARTS => scan_parts(&self.data, part, wanted),
+                Some(cold) => {
+                    let (part_keys, next) = scan_parts(cold.keys(), part - SCAN_PARTS, wanted);
+                    (part_keys, next + SCAN_PARTS)
+                }
+                None => (Vec::new(), total_parts),
+            };
+
+            keys.extend(part_keys.into_iter().filter(|k| {
+                !self.is_expired(k) && pattern.is_none_or(|p| crate::core::glob::glob_match(p, k))
+            }));
+            part = next;
+        }
+
+        let next = if part >= total_parts { 0 } else { part as u64 };
+        (next, keys)
+    }
+
+    fn prefixed_entries<'a>(&'a self, prefix: &'a str) -> Box<dyn Iterator<Item = (String, Value, Option<u64>)> + 'a> {
+        let resident = self.data.iter().filter(move |kv| kv.key().starts_with(prefix)).filter_map(move |kv| {
+            let expire_at_ms = match self.expiry.get(kv.key()).map(|exp| *exp) {
+                Some(exp) if Instant::now() > exp => return None, // Skip expired
+                exp => exp.map(instant_to_unix_ms),
+            };
+            Some((kv.key().clone(), kv.value().value.clone(), expire_at_ms))
+        });
+        let cold = self.cold_records(prefix)
+            .filter(|(_, record)| record.expire_at_ms.is_none_or(|ms| ms > now_unix_ms()))
+            .map(|(key, record)| (key, record.value, record.expire_at_ms));
+        Box::new(resident.chain(cold))
+    }
+
+    fn key_count(&self) -> usize {
+        self.data.len() + self.cold.as_ref().map_or(0, |c| c.key_count())
+    }
+
+    fn clear(&self) {
+        self.data.clear();
+        self.expiry.clear();
+        if let Some(cold) = &self.cold {
+            cold.clear();
+        }
+    }
+
+    fn info(&self) -> Option<String> {
+        let mut info = format!(
+            "# Keyspace\r\neviction_policy:{}\r\nexpiry_keys:{}\r\nexpired_keys_total:{}\r\nexpiry_orphans_removed_total:{}\r\nevicted_keys_total:{}\r\n",
+            self.policy().as_str(),
+            self.expiry.len(),
+            self.sweep.expired.load(Ordering::Relaxed),
+            self.sweep.orphans.load(Ordering::Relaxed),
+            self.evicted.load(Ordering::Relaxed)
+        );
+        if let Some(cold) = &self.cold {
+            info.push_str(&cold.info());
+        }
+        Some(info)
+    }
+
+    fn memory_bytes(&self) -> usize {
+        self.data.bytes() + self.expiry.iter().map(|kv| kv.key().len() + std::mem::size_of::<Instant>()).sum::<usize>()
+    }
+
+    fn memory_usage(&self, key: &str) -> Option<usize> {
+        if !self.live(key) {
+            return None;
+        }
+        self.data.get(key).map(|entry| entry.size)
+    }
+
+    fn is_full(&self) -> bool {
+        let stuck = match self.policy() {
+            EvictionPolicy::Noeviction => true,
+            EvictionPolicy::VolatileTtl => self.expiry.is_empty(),
+            EvictionPolicy::AllkeysLru | EvictionPolicy::AllkeysLfu => false,
+        };
+        stuck && self.over_limit(true, 0)
+    }
+
+    fn contains(&self, key: &str) -> bool {
+        (self.data.contains_key(key) && !self.is_expired(key))
+            || self.cold.as_ref().is_some_and(|c| c.contains(key))
+    }
+
+    fn inspect(&self, key: &str, f: &mut dyn FnMut(Option<&Value>)) {
+        if !self.live(key) {
+            return f(None);
+        }
+        match self.data.get_mut(key) {
+            Some(mut entry) => {
+                entry.touch();
+                f(Some(&entry.value))
+            }
+            None => f(None),
+        }
+    }
+
+    fn update(&self, key: &str, f: &mut dyn FnMut(&mut Option<Value>)) {
+        if !self.live(key) {
+            let mut value = None;
+            f(&mut value);
+            if let Some(value) = value {
+                let entry = Entry::new(key, value);
+                if !self.evict_if_needed(true, entry.size, 0) {
+                    return;
+                }
+                self.data.insert(key.to_string(), entry);
+                // A new key never inherits a stale expiry
+                self.expiry.remove(key);
+            }
+            return;
+        }
+        // The shard stays locked from read to write
+        if let dashmap::Entry::Occupied(mut slot) = self.data.entry(key.to_string()) {
+            let mut value = Some(std::mem::take(&mut slot.get_mut().value));
+            f(&mut value);
+            match value {
+                Some(value) => {
+                    let entry = slot.get_mut();
+                    let old_size = entry.size;
+                    entry.size = entry_size(key, &value);
+                    entry.value = value;
+                    entry.touch();
+                    self.data.resized(old_size, entry.size);
+                }
+                None => {
+                    self.data.resized(slot.remove().size, 0);
+                    self.expiry.remove(key);
+                }
+            }
+        } else {
+            f(&mut None);
+        }
+    }
+}
+
+/// Walks the expiry map part by part (see [`SCAN_PARTS`]) from `part`,
+/// dropping keys that have expired and expiries whose key is gone, until
+/// `budget` is spent. Returns the part to resume from (0 after a full pass).
+///
+/// Each key's data shard is locked before its expiry is touched, the same order
+/// the access paths use, so a key being written concurrently is never lost.
+fn compact_expiry(data: &Keyspace, expiry: &DashMap<String, Instant>, stats: &SweepStats, mut part: usize, budget: Duration) -> usize {
+    let started = Instant::now();
+    while part < SCAN_PARTS {
+        let now = Instant::now();
+        for key in part_keys(expiry, part..part + 1) {
+            match data.entry(key) {
+                dashmap::Entry::Occupied(slot) => {
+                    if expiry.get(slot.key()).is_some_and(|exp| now > *exp) {
+                        expiry.remove(slot.key());
+                        data.resized(slot.remove().size, 0);
+                        stats.expired.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+                dashmap::Entry::Vacant(slot) => {
+                    if expiry.remove(slot.key()).is_some() {
+                        stats.orphans.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            }
+        }
+        part += 1;
+        if started.elapsed() >= budget {
+            break;
+        }
+    }
+    if part >= SCAN_PARTS { 0 } else { part }
+}
+
+/// Moves keys idle for longer than `idle` to the cold tier.
+///
+/// The record is written and registered before the key leaves memory, and the
+/// removal only happens if the key was not touched in the meantime, so readers
+/// always find the key in one of the two tiers.
+fn offload_idle(data: &Keyspace, expiry: &DashMap<String, Instant>, cold: &ColdStore, idle: Duration) {
+    let candidates: Vec<(String, Value)> = data.iter()
+        .filter(|kv| kv.value().last_accessed.elapsed() > idle && ColdStore::can_hold(kv.key()))
+        .map(|kv| (kv.key().clone(), kv.value().value.clone()))
+        .collect();
+
+    for (key, value) in candidates {
+        let expire_at_ms = match expiry.get(&key).map(|exp| *exp) {
+            Some(exp) if Instant::now() > exp => continue, // Expired: left for lazy removal
+            Some(exp) => Some(instant_to_unix_ms(exp)),
+            None => None,
+        };
+
+        if let Err(e) = cold.put(&key, &ColdRecord { value, expire_at_ms }) {
+            crate::core::logger::error(&format!("Cold tier write failed for {}: {}", key, e));
+            continue;
+        }
+
+        if data.remove_if(&key, |_, entry| entry.last_accessed.elapsed() > idle).is_some() {
+            expiry.remove(&key);
+            cold.record_offload();
+        } else {
+            // Touched while being written out; keep the in-memory copy
+            cold.discard(&key);
+        }
+    }
+}
+
+// Where a key's value is stored on disk
+struct Slot {
+    file: u64,
+    expire_at_ms: Option<u64>,
+}
+
+/// Keyspace with values on disk, one JSON file per key under
+/// `{data_dir}/{db}_kv/`. Files are numbered, so any key length works; the
+/// key index and expiries stay in memory. Files are replaced by renaming a
+/// temporary file over them, and a key's index shard stays locked while its
+/// file is written or read.
+pub struct DiskBackend {
+    dir: PathBuf,
+    index: DashMap<String, Slot>,
+    next_file: AtomicU64,
+}
+
+impl DiskBackend {
+    pub fn open(db_name: &str) -> anyhow::Result<Self> {
+        let dir = PathBuf::from(format!("{}/{}_kv", config::shared().data_dir(), db_name));
+        // Recovery refills the backend from the AOF or snapshot
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir)?;
+        }
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self { dir, index: DashMap::new(), next_file: AtomicU64::new(0) })
+    }
+
+    fn path(&self, file: u64) -> PathBuf {
+        self.dir.join(format!("{}.json", file))
+    }
+
+    fn read(&self, file: u64) -> Option<Value> {
+        let bytes = std::fs::read(self.path(file))
+            .map_err(|e| crate::core::logger::error(&format!("Disk backend read failed: {}", e)))
+            .ok()?;
+        serde_json::from_slice(&bytes).ok()
+    }
+
+    /// Replaces a value file through a temporary file and a rename, so a
+    /// reader sees the old value or the new one. False, with the error
+    /// reported through [`write_failed`], if it could not be written.
+    fn write(&self, file: u64, value: &Value) -> bool {
+        let path = self.path(file);
+        let tmp = path.with_extension("json.tmp");
+        let result = serde_json::to_vec(value)
+            .map_err(anyhow::Error::from)
+            .and_then(|bytes| {
+                std::fs::write(&tmp, bytes)?;
+                Ok(std::fs::rename(&tmp, &path)?)
+            });
+        match result {
+            Ok(()) => true,
+            Err(e) => {
+                let _ = std::fs::remove_file(&tmp);
+                write_failed(format_args!("disk backend: {}", e));
+                false
+            }
+        }
+    }
+
+    fn remove(&self, key: &str) -> bool {
+        match self.index.remove(key) {
+            Some((_, slot)) => {
+                let _ = std::fs::remove_file(self.path(slot.file));
+                slot.expire_at_ms.is_none_or(|ms| ms > now_unix_ms())
+            }
+            None => false,
+        }
+    }
+
+    /// File of a live key; expired keys are removed on the way.
+    fn live(&self, key: &str) -> Option<u64> {
+        let (file, expired) = {
+            let slot = self.index.get(key)?;
+            (slot.file, slot.expire_at_ms.is_some_and(|ms| ms <= now_unix_ms()))
+        };
+        if expired {
+            self.remove(key);
+            return None;
+        }
+        Some(file)
+    }
+}
+
+impl KvBackend for DiskBackend {
+    fn name(&self) -> &'static str {
+        "disk"
+    }
+
+    fn get(&self, key: &str) -> Option<Value> {
+        self.live(key)?;
+        // The shard stays read-locked while the file is read, so a concurrent
+        // delete cannot unlink it halfway
+        let slot = self.index.get(key)?;
+        self.read(slot.file)
+    }
+
+    fn set(&self, key: &str, value: Value, expire_at_ms: Option<u64>) {
+        match self.index.entry(key.to_string()) {
+            dashmap::Entry::Occupied(mut slot) => {
+                // A failed write keeps the old value and expiry
+                if self.write(slot.get().file, &value) {
+                    slot.get_mut().expire_at_ms = expire_at_ms;
+                }
+            }
+            dashmap::Entry::Vacant(slot) => {
+                let file = self.next_file.fetch_add(1, Ordering::Relaxed);
+                if self.write(file, &value) {
+                    slot.insert(Slot { file, expire_at_ms });
+                }
+            }
+        }
+    }
+
+    fn del(&self, key: &str) -> bool {
+        self.remove(key)
+    }
+
+    fn ttl(&self, key: &str) -> Option<Option<u64>> {
+        self.live(key)?;
+        self.index.get(key).map(|slot| slot.expire_at_ms)
+    }
+
+    fn set_ttl(&self, key: &str, expire_at_ms: Option<u64>) -> bool {
+        if self.live(key).is_none() {
+            return false;
+        }
+        if expire_at_ms.is_some_and(|ms| ms <= now_unix_ms()) {
+            self.remove(key);
+        } else if let Some(mut slot) = self.index.get_mut(key) {
+            slot.expire_at_ms = expire_at_ms;
+        }
+        true
+    }
+
+    fn scan(&self, cursor: u64, pattern: Option<&str>, count: usize) -> (u64, Vec<String>) {
+        let mut part = cursor as usize;
+        let mut keys = Vec::new();
+        while part < SCAN_PARTS && keys.len() < count.max(1) {
+            let (part_keys, next) = scan_parts(&self.index, part, count.max(1) - keys.len());
+            keys.extend(part_keys.into_iter().filter(|k| {
+                self.contains(k) && pattern.is_none_or(|p| crate::core::glob::glob_match(p, k))
+            }));
+            part = next;
+        }
+        let next = if part >= SCAN_PARTS { 0 } else { part as u64 };
+        (next, keys)
+    }
+
+    fn prefixed_entries<'a>(&'a self, prefix: &'a str) -> Box<dyn Iterator<Item = (String, Value, Option<u64>)> + 'a> {
+        let now = now_unix_ms();
+        Box::new(self.index.iter().filter(move |kv| kv.key().starts_with(prefix)).filter_map(move |kv| {
+            let slot = kv.value();
+            if slot.expire_at_ms.is_some_and(|ms| ms <= now) {
+                return None;
+            }
+            Some((kv.key().clone(), self.read(slot.file)?, slot.expire_at_ms))
+        }))
+    }
+
+    fn key_count(&self) -> usize {
+        self.index.len()
+    }
+
+    fn clear(&self) {
+        let keys: Vec<String> = self.index.iter().map(|kv| kv.key().clone()).collect();
+        for key in keys {
+            self.remove(&key);
+        }
+    }
+
+    fn info(&self) -> Option<String> {
+        Some(format!("# Disk Backend\r\ndisk_keys:{}\r\n", self.key_count()))
+    }
+
+    /// Just the index; values are on disk.
+    fn memory_bytes(&self) -> usize {
+        self.index.iter().map(|kv| kv.key().len() + std::mem::size_of::<Slot>()).sum()
+    }
+
+    fn contains(&self, key: &str) -> bool {
+        self.index.get(key).is_some_and(|slot| slot.expire_at_ms.is_none_or(|ms| ms > now_unix_ms()))
+    }
+
+    fn update(&self, key: &str, f: &mut dyn FnMut(&mut Option<Value>)) {
+        if self.live(key).is_none() {
+            let mut value = None;
+            f(&mut value);
+            if let Some(value) = value {
+                self.set(key, value, None);
+            }
+            return;
+        }
+        // The index shard stays locked from read to write
+        if let dashmap::Entry::Occupied(slot) = self.index.entry(key.to_string()) {
+            let file = slot.get().file;
+            let mut value = self.read(file);
+            f(&mut value);
+            match value {
+                Some(value) => {
+                    self.write(file, &value);
+                }
+                None => {
+                    slot.remove();
+                    let _ = std::fs::remove_file(self.path(file));
+                }
+            }
+        } else {
+            f(&mut None);
+        }
+    }
+}
+
+/// How many parts, by key hash, SCAN splits a map into; cursors count parts.
+pub(crate) const SCAN_PARTS: usize = 64;
+
+fn scan_part(key: &str) -> usize {
+    crc32fast::hash(key.as_bytes()) as usize % SCAN_PARTS
+}
+
+/// Copies the keys of `map` in parts `parts`. Walks the whole map, holding
+/// one shard's read lock at a time.
+pub(crate) fn part_keys<V>(map: &DashMap<String, V>, parts: std::ops::Range<usize>) -> Vec<String> {
+    map.iter().filter(|kv| parts.contains(&scan_part(kv.key()))).map(|kv| kv.key().clone()).collect()
+}
+
+/// The keys of `map` in parts `from..`, whole parts at a time until at least
+/// `count` are gathered, and the part to go on from (`SCAN_PARTS` at the end).
+pub(crate) fn scan_parts<V>(map: &DashMap<String, V>, from: usize, count: usize) -> (Vec<String>, usize) {
+    let end = if count >= map.len() {
+        SCAN_PARTS
+    } else {
+        let mut sizes = [0; SCAN_PARTS];
+        for kv in map.iter() {
+            sizes[scan_part(kv.key())] += 1;
+        }
+        let (mut end, mut total) = (from, 0);
+        while end < SCAN_PARTS && total < count {
+            total += sizes[end];
+            end += 1;
+        }
+        end
+    };
+    (part_keys(map, from..end), end)
+}
+
+/// Rough bytes held by a JSON value, heap included.
+pub(crate) fn value_size(value: &Value) -> usize {
+    std::mem::size_of::<Value>() + match value {
+        Value::String(s) => s.len(),
+        Value::Array(items) => items.iter().map(value_size).sum(),
+        Value::Object(map) => map.iter().map(|(k, v)| k.len() + value_size(v)).sum(),
+        _ => 0,
+    }
+}
+
+thread_local! {
+    /// Why the last backend write on this thread failed
+    static WRITE_ERROR: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Records that a write (`set` or `update`) could not be stored. The command
+/// running on this thread then fails instead of replying OK, and is not logged.
+pub fn write_failed(error: impl std::fmt::Display) {
+    let error = error.to_string();
+    crate::core::logger::error(&format!("Backend write failed: {}", error));
+    WRITE_ERROR.with(|e| *e.borrow_mut() = Some(format!("Write failed: {}", error)));
+}
+
+/// Records that a write was refused as larger than `max_memory` allows, like
+/// [`write_failed`] but replying `OOM`.
+fn write_too_large(size: usize, max_memory: usize) {
+    let reply = format!("OOM value of {} bytes is larger than max_memory ({} bytes)", size, max_memory);
+    WRITE_ERROR.with(|e| *e.borrow_mut() = Some(reply));
+}
+
+/// Takes the error reply of a failed write on this thread, if any.
+pub(crate) fn take_write_error() -> Option<String> {
+    WRITE_ERROR.with(|e| e.borrow_mut().take())
+}
+
+pub(crate) fn now_unix_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+// Expiries are tracked as monotonic Instants in memory; these convert to and
+// from wall-clock Unix milliseconds.
+fn instant_to_unix_ms(at: Instant) -> u64 {
+    let remaining = at.saturating_duration_since(Instant::now());
+    now_unix_ms() + remaining.as_millis() as u64
+}
+
+// Returns None if the timestamp is already in the past.
+fn unix_ms_to_instant(unix_ms: u64) -> Option<Instant> {
+    let now = now_unix_ms();
+    if unix_ms <= now {
+        return None;
+    }
+    Some(Instant::now() + Duration::from_millis(unix_ms - now))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Same behavior from every bundled backend
+    fn exercise(backend: &dyn KvBackend) {
+        backend.set("a", Value::from(1), None);
+        backend.set("b", Value::from("x"), Some(now_unix_ms() + 60_000));
+        backend.set("gone", Value::from(true), Some(now_unix_ms() + 60_000));
+        assert!(backend.set_ttl("gone", Some(1)));
+        assert_eq!(backend.get("a"), Some(Value::from(1)));
+        assert!(backend.get("gone").is_none() && !backend.contains("gone"));
+        assert!(matches!(backend.ttl("b"), Some(Some(_))));
+        assert_eq!(backend.ttl("a"), Some(None));
+        assert_eq!(backend.ttl("nope"), None);
+
+        backend.update("a", &mut |v| *v = v.take().and_then(|n| n.as_i64()).map(|n| Value::from(n + 1)));
+        backend.update("list", &mut |v| v.get_or_insert_with(|| Value::Array(vec![])).as_array_mut().unwrap().push("i".into()));
+        assert_eq!(backend.get("a"), Some(Value::from(2)));
+        assert_eq!(backend.get("list"), Some(serde_json::json!(["i"])));
+        let mut seen = None;
+        backend.inspect("b", &mut |v| seen = v.cloned());
+        assert_eq!(seen, Some(Value::from("x")));
+
+        let (mut cursor, mut keys) = (0, Vec::new());
+        loop {
+            let (next, batch) = backend.scan(cursor, None, 1);
+            keys.extend(batch);
+            if next == 0 { break; }
+            cursor = next;
+        }
+        keys.sort();
+        assert_eq!(keys, ["a", "b", "list"]);
+        assert_eq!(backend.entries().count(), 3);
+
+        assert!(backend.del("a") && !backend.del("a"));
+        backend.clear();
+        assert_eq!(backend.key_count(), 0);
+    }
+
+    #[test]
+    fn memory_backend() {
+        exercise(&MemoryBackend::new());
+    }
+
+    #[test]
+    fn expiries_never_outlive_their_keys() {
+        let backend = MemoryBackend::new();
+        let orphans = |b: &MemoryBackend| b.expiry.iter().filter(|kv| !b.data.contains_key(kv.key())).count();
+
+        backend.set("short", Value::from(1), Some(now_unix_ms() + 20));
+        backend.set("long", Value::from(2), Some(now_unix_ms() + 60_000));
+        backend.set("deleted", Value::from(3), Some(now_unix_ms() + 60_000));
+        assert!(backend.del("deleted"));
+        backend.set("overwritten", Value::from(4), Some(now_unix_ms() + 60_000));
+        backend.set("overwritten", Value::from(5), None);
+        assert_eq!(orphans(&backend), 0);
+
+        // A stale expiry is neither inherited by a new key nor kept by the sweep
+        backend.expiry.insert("fresh".to_string(), Instant::now() + Duration::from_secs(60));
+        backend.update("fresh", &mut |v| *v = Some(Value::from(6)));
+        assert_eq!(backend.ttl("fresh"), Some(None));
+        backend.expiry.insert("ghost".to_string(), Instant::now() + Duration::from_secs(60));
+
+        std::thread::sleep(Duration::from_millis(30));
+        assert_eq!(compact_expiry(&backend.data, &backend.expiry, &backend.sweep, 0, Duration::MAX), 0);
+        assert_eq!(backend.sweep.expired.load(Ordering::Relaxed), 1);
+        assert_eq!(backend.sweep.orphans.load(Ordering::Relaxed), 1);
+        assert_eq!(orphans(&backend), 0);
+        assert!(!backend.data.contains_key("short"));
+        let mut keys: Vec<String> = backend.expiry.iter().map(|kv| kv.key().clone()).collect();
+        keys.sort();
+        assert_eq!(keys, ["long"]);
+
+        // A zero budget still makes progress, one part per run
+        assert_eq!(compact_expiry(&backend.data, &backend.expiry, &backend.sweep, 0, Duration::ZERO), 1);
+    }
+
+    #[test]
+    fn byte_accounting_and_eviction() {
+        let settings = config::Settings { max_memory: 4096, ..config::Settings::default() };
+        let backend = MemoryBackend { config: Arc::new(Config::new(settings)), ..MemoryBackend::new() };
+        let recounted = |b: &MemoryBackend| b.data.iter().map(|kv| entry_size(kv.key(), &kv.value().value)).sum::<usize>();
+
+        for i in 0..20 {
+            backend.set(&format!("k{}", i), Value::from("x".repeat(400)), None);
+        }
+        assert!(backend.data.bytes() <= 4096, "{} bytes", backend.data.bytes());
+        assert!(backend.evicted.load(Ordering::Relaxed) > 0);
+        assert!(backend.contains("k19"));
+        assert_eq!(backend.data.bytes(), recounted(&backend));
+
+        backend.update("k19", &mut |v| *v = Some(Value::from("y")));
+        backend.update("new", &mut |v| *v = Some(Value::from(1)));
+        backend.del("new");
+        assert_eq!(backend.data.bytes(), recounted(&backend));
+        assert_eq!(backend.memory_usage("k19"), Some(entry_size("k19", &Value::from("y"))));
+        assert_eq!(backend.memory_usage("nope"), None);
+
+        // A value that could never fit is refused, and evicts nothing
+        take_write_error();
+        let keys = backend.data.len();
+        backend.set("huge", Value::from("z".repeat(5000)), None);
+        backend.update("huge", &mut |v| *v = Some(Value::from("z".repeat(5000))));
+        assert!(take_write_error().is_some_and(|e| e.starts_with("OOM")));
+        assert!(!backend.contains("huge"));
+        assert_eq!(backend.data.len(), keys);
+
+        backend.clear();
+        assert_eq!(backend.data.bytes(), 0);
+    }
+
+    #[test]
+    fn eviction_policies() {
+        let with_policy = |policy| {
+            let settings = config::Settings { max_keys: 10, eviction_policy: policy, ..config::Settings::default() };
+            MemoryBackend { config: Arc::new(Config::new(settings)), ..MemoryBackend::new() }
+        };
+        let now_ms = now_unix_ms();
+
+        let lfu = with_policy(EvictionPolicy::AllkeysLfu);
+        for i in 0..10 {
+            lfu.set(&format!("k{}", i), Value::from(i), None);
+        }
+        for _ in 0..200 {
+            lfu.get("k0");
+        }
+        for i in 10..30 {
+            lfu.set(&format!("k{}", i), Value::from(i), None);
+        }
+        assert_eq!(lfu.key_count(), 10);
+        assert!(lfu.contains("k0"));
+
+        let ttl = with_policy(EvictionPolicy::VolatileTtl);
+        for i in 0..9 {
+            ttl.set(&format!("k{}", i), Value::from(i), None);
+        }
+        ttl.set("soon", Value::from(1), Some(now_ms + 60_000));
+        ttl.set("new", Value::from(2), None);
+        assert!(!ttl.contains("soon") && ttl.contains("new"));
+        assert_eq!(ttl.evicted.load(Ordering::Relaxed), 1);
+        assert!(ttl.is_full());
+
+        let none = with_policy(EvictionPolicy::Noeviction);
+        for i in 0..10 {
+            none.set(&format!("k{}", i), Value::from(i), None);
+        }
+        assert!(none.is_full());
+        none.set("k10", Value::from(10), None);
+        assert_eq!(none.evicted.load(Ordering::Relaxed), 0);
+        none.del("k10");
+        none.del("k0");
+        assert!(!none.is_full());
+    }
+
+    #[test]
+    fn idle_keys_move_to_disk_and_back() {
+        let dir = std::env::temp_dir().join(format!("toridb_cold_{}", std::process::id()));
+        let cold = Arc::new(ColdStore::at(dir.clone()).unwrap());
+        let backend = MemoryBackend { cold: Some(cold.clone()), ..MemoryBackend::new() };
+        let doc = serde_json::json!({"x": [1, 2]});
+        backend.set("a", Value::from(1), None);
+        backend.set("b", doc.clone(), Some(now_unix_ms() + 60_000));
+        backend.set("soon", Value::from(3), Some(now_unix_ms() + 60_000));
+        backend.set(&"k".repeat(200), Value::from(4), None);
+
+        offload_idle(&backend.data, &backend.expiry, &cold, Duration::ZERO);
+        // A key too long for a file name stays in memory
+        assert_eq!(backend.data.len(), 1);
+        assert!(backend.expiry.is_empty());
+        assert_eq!(cold.key_count(), 3);
+        assert_eq!(backend.key_count(), 4);
+        assert!(backend.contains("a"));
+        // Listed without being reloaded
+        let (mut cursor, mut keys) = (0, Vec::new());
+        loop {
+            let (next, batch) = backend.scan(cursor, Some("?"), 1);
+            keys.extend(batch);
+            if next == 0 { break; }
+            cursor = next;
+        }
+        keys.sort();
+        assert_eq!(keys, ["a", "b"]);
+        assert_eq!(backend.entries().count(), 4);
+        assert_eq!(cold.key_count(), 3);
+
+        // Touching a key brings it back with its expiry
+        assert_eq!(backend.get("b"), Some(doc));
+        assert!(matches!(backend.ttl("b"), Some(Some(_))));
+        assert!(!cold.contains("b") && backend.data.contains_key("b"));
+        let info = cold.info();
+        assert!(info.contains("cold_offloaded_total:3") && info.contains("cold_reloads_total:1"), "{}", info);
+
+        // One that expired on disk does not
+        cold.put("soon", &ColdRecord { value: Value::from(3), expire_at_ms: Some(now_unix_ms() - 1) }).unwrap();
+        assert_eq!(backend.get("soon"), None);
+        assert!(!backend.contains("soon") && !cold.contains("soon"));
+
+        // Writes and deletes of a cold key leave no copy on disk
+        backend.set("a", Value::from(10), None);
+        assert!(!cold.contains("a"));
+        assert_eq!(backend.get("a"), Some(Value::from(10)));
+        offload_idle(&backend.data, &backend.expiry, &cold, Duration::ZERO);
+        assert!(backend.del("b") && !backend.contains("b"));
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        backend.clear();
+        assert_eq!(backend.key_count(), 0);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn disk_backend() {
+        let backend = DiskBackend {
+            dir: std::env::temp_dir().join(format!("toridb_kv_{}", std::process::id())),
+            index: DashMap::new(),
+            next_file: AtomicU64::new(0),
+        };
+        std::fs::create_dir_all(&backend.dir).unwrap();
+        exercise(&backend);
+        std::fs::remove_dir_all(&backend.dir).unwrap();
+    }
+
+    #[test]
+    fn disk_write_failures_keep_the_old_value() {
+        let backend = DiskBackend {
+            dir: std::env::temp_dir().join(format!("toridb_kv_fail_{}", std::process::id())),
+            index: DashMap::new(),
+            next_file: AtomicU64::new(0),
+        };
+        std::fs::create_dir_all(&backend.dir).unwrap();
+        backend.set("a", Value::from(1), None);
+        assert!(take_write_error().is_none());
+        // Leaves no temporary file behind
+        assert_eq!(std::fs::read_dir(&backend.dir).unwrap().count(), 1);
+
+        // A directory where the temporary file goes makes every write fail
+        std::fs::create_dir(backend.path(0).with_extension("json.tmp")).unwrap();
+        std::fs::create_dir(backend.path(1).with_extension("json.tmp")).unwrap();
+        backend.set("a", Value::from(2), Some(now_unix_ms() + 60_000));
+        assert!(take_write_error().is_some());
+        assert_eq!((backend.get("a"), backend.ttl("a")), (Some(Value::from(1)), Some(None)));
+        backend.set("b", Value::from(3), None);
+        assert!(take_write_error().is_some());
+        assert!(!backend.contains("b"));
+        std::fs::remove_dir_all(&backend.dir).unwrap();
+    }
+}
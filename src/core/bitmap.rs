@@ -0,0 +1,144 @@
+//! # Bitmaps
+//!
+//! A bitmap is a byte buffer addressed bit by bit, most significant bit of
+//! the first byte first (bit 0), as in Redis. Setting a bit past the end
+//! grows the buffer with zero bytes; reading past the end gives 0. One bit
+//! per ID makes them a compact way to track flags or daily active users.
+
+/// Highest bit offset accepted (a 512 MB bitmap).
+pub const MAX_OFFSET: u64 = (1 << 32) - 1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BitOp {
+    And,
+    Or,
+    Xor,
+    Not,
+}
+
+impl std::str::FromStr for BitOp {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "AND" => Ok(BitOp::And),
+            "OR" => Ok(BitOp::Or),
+            "XOR" => Ok(BitOp::Xor),
+            "NOT" => Ok(BitOp::Not),
+            other => Err(format!("Unknown BITOP operation '{}'", other)),
+        }
+    }
+}
+
+impl std::fmt::Display for BitOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            BitOp::And => "AND",
+            BitOp::Or => "OR",
+            BitOp::Xor => "XOR",
+            BitOp::Not => "NOT",
+        })
+    }
+}
+
+/// Sets bit `offset` to `value`, growing `bytes` as needed. Returns the
+/// previous value.
+pub fn set_bit(bytes: &mut Vec<u8>, offset: u64, value: bool) -> bool {
+    let (byte, mask) = position(offset);
+    if byte >= bytes.len() {
+        bytes.resize(byte + 1, 0);
+    }
+    let old = bytes[byte] & mask != 0;
+    if value {
+        bytes[byte] |= mask;
+    } else {
+        bytes[byte] &= !mask;
+    }
+    old
+}
+
+pub fn get_bit(bytes: &[u8], offset: u64) -> bool {
+    let (byte, mask) = position(offset);
+    bytes.get(byte).is_some_and(|b| b & mask != 0)
+}
+
+fn position(offset: u64) -> (usize, u8) {
+    ((offset / 8) as usize, 0x80 >> (offset % 8))
+}
+
+/// Set bits from `start` to `end`, both included and counted from the end
+/// when negative, in bytes or with `bits` in bits (BITCOUNT). The whole
+/// bitmap without a range.
+pub fn count(bytes: &[u8], range: Option<(i64, i64)>, bits: bool) -> u64 {
+    let Some((start, end)) = range else {
+        return bytes.iter().map(|b| b.count_ones() as u64).sum();
+    };
+    let len = if bits { bytes.len() as i64 * 8 } else { bytes.len() as i64 };
+    let start = if start < 0 { (len + start).max(0) } else { start };
+    let end = if end < 0 { len + end } else { end.min(len - 1) };
+    if start > end || len == 0 {
+        return 0;
+    }
+    if bits {
+        (start as u64..=end as u64).filter(|&offset| get_bit(bytes, offset)).count() as u64
+    } else {
+        bytes[start as usize..=end as usize].iter().map(|b| b.count_ones() as u64).sum()
+    }
+}
+
+/// Combines `sources` byte by byte (BITOP). Shorter sources count as zero
+/// bytes up to the longest; `NOT` takes exactly one source.
+pub fn combine(op: BitOp, sources: &[Vec<u8>]) -> Vec<u8> {
+    let len = sources.iter().map(Vec::len).max().unwrap_or(0);
+    let byte = |source: &Vec<u8>, i: usize| source.get(i).copied().unwrap_or(0);
+    (0..len).map(|i| {
+        let mut bytes = sources.iter().map(|source| byte(source, i));
+        let first = bytes.next().unwrap_or(0);
+        match op {
+            BitOp::And => bytes.fold(first, |acc, b| acc & b),
+            BitOp::Or => bytes.fold(first, |acc, b| acc | b),
+            BitOp::Xor => bytes.fold(first, |acc, b| acc ^ b),
+            BitOp::Not => !first,
+        }
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bits_grow_the_buffer() {
+        let mut bytes = Vec::new();
+        assert!(!set_bit(&mut bytes, 7, true));
+        assert!(set_bit(&mut bytes, 7, true));
+        assert!(!set_bit(&mut bytes, 20, true));
+        assert_eq!(bytes, [0x01, 0x00, 0x08]);
+        assert!(get_bit(&bytes, 20) && !get_bit(&bytes, 21) && !get_bit(&bytes, 1000));
+        assert!(set_bit(&mut bytes, 20, false));
+        assert_eq!(bytes, [0x01, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn counts_in_byte_and_bit_ranges() {
+        let bytes = b"foobar";
+        assert_eq!(count(bytes, None, false), 26);
+        assert_eq!(count(bytes, Some((0, 0)), false), 4);
+        assert_eq!(count(bytes, Some((1, 1)), false), 6);
+        assert_eq!(count(bytes, Some((-2, -1)), false), 7);
+        assert_eq!(count(bytes, Some((5, 30)), true), 17);
+        assert_eq!(count(bytes, Some((3, 1)), false), 0);
+        assert_eq!(count(&[], Some((0, -1)), false), 0);
+    }
+
+    #[test]
+    fn combines_sources() {
+        let a = vec![0b1100_0000, 0xff];
+        let b = vec![0b1010_0000];
+        assert_eq!(combine(BitOp::And, &[a.clone(), b.clone()]), [0b1000_0000, 0x00]);
+        assert_eq!(combine(BitOp::Or, &[a.clone(), b.clone()]), [0b1110_0000, 0xff]);
+        assert_eq!(combine(BitOp::Xor, &[a.clone(), b]), [0b0110_0000, 0xff]);
+        assert_eq!(combine(BitOp::Not, &[a]), [0b0011_1111, 0x00]);
+        assert!(combine(BitOp::Or, &[]).is_empty());
+    }
+}
@@ -1,5 +1,11 @@
 use std::sync::{Arc, RwLock};
 use dashmap::DashMap;
+use crate::query::SlotState;
+use crate::net::resp::{decode, RespValue};
+use bytes::BytesMut;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
 
 const TOTAL_SLOTS: u16 = 16384; // Redis-compatible 16384 slots
 
@@ -22,6 +28,10 @@ pub struct ClusterManager {
     pub role: Arc<RwLock<ClusterRole>>,
     pub nodes: Arc<DashMap<String, Vec<SlotRange>>>, // node_addr -> slots
     pub self_addr: Arc<RwLock<String>>,
+    /// Slots being moved away (`CLUSTER SETSLOT MIGRATING`), to the importing node
+    pub migrating: Arc<DashMap<u16, String>>,
+    /// Slots being moved here (`CLUSTER SETSLOT IMPORTING`), from the migrating node
+    pub importing: Arc<DashMap<u16, String>>,
 }
 
 impl ClusterManager {
@@ -29,23 +39,48 @@ impl ClusterManager {
         Self {
             role: Arc::new(RwLock::new(ClusterRole::Standalone)),
             nodes: Arc::new(DashMap::new()),
-            self_addr: Arc::new(RwLock::new("127.0.0.1:8569".to_string())),
+            self_addr: Arc::new(RwLock::new(format!("127.0.0.1:{}", crate::core::config::shared().settings().port))),
+            migrating: Arc::new(DashMap::new()),
+            importing: Arc::new(DashMap::new()),
         }
     }
 
+    /// Part of a key that decides its slot: the text between the first `{`
+    /// and the next `}` if not empty (`{user:1}:cart` hashes as `user:1`),
+    /// otherwise the whole key.
+    pub fn hash_tag(key: &str) -> &str {
+        if let Some(open) = key.find('{')
+            && let Some(len) = key[open + 1..].find('}')
+            && len > 0
+        {
+            return &key[open + 1..open + 1 + len];
+        }
+        key
+    }
+
     /// Calculate the slot for a given key using CRC16
     pub fn key_slot(key: &str) -> u16 {
         // Simple hash: CRC16 mod 16384
         let mut crc: u16 = 0;
-        for byte in key.bytes() {
+        for byte in Self::hash_tag(key).bytes() {
             crc = ((crc << 8) ^ CRC16_TABLE[((crc >> 8) as u8 ^ byte) as usize]) & 0xFFFF;
         }
         crc % TOTAL_SLOTS
     }
 
+    /// True if all `keys` hash to one slot.
+    pub fn same_slot<'a>(keys: impl IntoIterator<Item = &'a str>) -> bool {
+        let mut slots = keys.into_iter().map(Self::key_slot);
+        slots.next().is_none_or(|first| slots.all(|slot| slot == first))
+    }
+
     /// Check if this node owns the slot for a key
     pub fn owns_slot(&self, key: &str) -> bool {
-        let slot = Self::key_slot(key);
+        self.owns_slot_number(Self::key_slot(key))
+    }
+
+    /// Check if this node serves the given slot
+    pub fn owns_slot_number(&self, slot: u16) -> bool {
         match &*self.role.read().unwrap() {
             ClusterRole::Standalone => true, // Single node owns all
             ClusterRole::Master(ranges) => {
@@ -68,6 +103,30 @@ impl ClusterManager {
         None
     }
 
+    /// True once this node serves slots or knows other nodes.
+    pub fn in_cluster(&self) -> bool {
+        !matches!(*self.role.read().unwrap(), ClusterRole::Standalone) || !self.nodes.is_empty()
+    }
+
+    /// Where a command on `key` must be sent instead, if not served here.
+    ///
+    /// A replica in a cluster (`master` is the node it follows) holds a copy
+    /// of its master's slots: it serves reads of them when `replica_reads`
+    /// (the client sent READONLY) and sends everything else to the master.
+    /// Keys whose owner is unknown are served locally.
+    pub fn redirect(&self, key: &str, master: Option<&str>, replica_reads: bool) -> Option<String> {
+        let owner = self.get_redirect(key);
+        match master {
+            Some(master) if self.in_cluster() => match owner {
+                Some(owner) if owner != master => Some(owner),
+                _ if replica_reads => None,
+                _ => Some(master.to_string()),
+            },
+            _ if self.owns_slot(key) => None,
+            _ => owner,
+        }
+    }
+
     /// Initialize cluster mode with this node as master for all slots
     pub fn _init_as_single_master(&self) {
         let addr = self.self_addr.read().unwrap().clone();
@@ -100,6 +159,124 @@ impl ClusterManager {
         }
     }
 
+    /// Remove a node from the routing table. Its slots become unassigned.
+    pub fn forget_node(&self, addr: &str) -> anyhow::Result<()> {
+        if *self.self_addr.read().unwrap() == addr {
+            return Err(anyhow::anyhow!("Cannot forget the current node"));
+        }
+        self.nodes.remove(addr)
+            .map(|_| ())
+            .ok_or_else(|| anyhow::anyhow!("Unknown node {}", addr))
+    }
+
+    /// Stop serving the given slots on this node. Returns how many were owned.
+    pub fn del_slots(&self, slots: &[u16]) -> usize {
+        let mut role = self.role.write().unwrap();
+        let addr = self.self_addr.read().unwrap().clone();
+
+        let ClusterRole::Master(ref mut ranges) = *role else {
+            return 0;
+        };
+
+        let owned = slots.iter()
+            .filter(|&&s| ranges.iter().any(|r| s >= r.start && s <= r.end))
+            .count();
+        *ranges = subtract_slots(ranges, slots);
+        self.nodes.insert(addr, ranges.clone());
+        owned
+    }
+
+    /// `CLUSTER SETSLOT`: starts, ends or completes moving `slot` between
+    /// this node and another. `Node` hands the slot to a node (this one
+    /// included) and ends any migration of it.
+    pub fn set_slot(&self, slot: u16, state: SlotState) -> anyhow::Result<()> {
+        if slot >= TOTAL_SLOTS {
+            return Err(anyhow::anyhow!("Invalid slot {}", slot));
+        }
+        match state {
+            SlotState::Migrating(node) => {
+                if !matches!(*self.role.read().unwrap(), ClusterRole::Master(_)) || !self.owns_slot_number(slot) {
+                    return Err(anyhow::anyhow!("I'm not the owner of hash slot {}", slot));
+                }
+                self.migrating.insert(slot, node);
+            }
+            SlotState::Importing(node) => {
+                if matches!(*self.role.read().unwrap(), ClusterRole::Master(_)) && self.owns_slot_number(slot) {
+                    return Err(anyhow::anyhow!("I'm already the owner of hash slot {}", slot));
+                }
+                self.importing.insert(slot, node);
+            }
+            SlotState::Stable => {
+                self.migrating.remove(&slot);
+                self.importing.remove(&slot);
+            }
+            SlotState::Node(node) => {
+                self.migrating.remove(&slot);
+                self.importing.remove(&slot);
+                let me = self.self_addr.read().unwrap().clone();
+                for mut entry in self.nodes.iter_mut().filter(|e| *e.key() != node && *e.key() != me) {
+                    *entry = subtract_slots(entry.value(), &[slot]);
+                }
+                if node == me {
+                    if !self.owns_slot_number(slot) || matches!(*self.role.read().unwrap(), ClusterRole::Standalone) {
+                        self.add_slots(vec![slot]);
+                    }
+                } else {
+                    self.del_slots(&[slot]);
+                    let mut ranges = self.nodes.entry(node.clone()).or_default();
+                    if !ranges.iter().any(|r| slot >= r.start && slot <= r.end) {
+                        ranges.push(SlotRange { start: slot, end: slot, _node_addr: node });
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The node `slot` is being moved to, if it is migrating away.
+    pub fn migrating_to(&self, slot: u16) -> Option<String> {
+        self.migrating.get(&slot).map(|node| node.clone())
+    }
+
+    /// True if `slot` is being moved to this node.
+    pub fn is_importing(&self, slot: u16) -> bool {
+        self.importing.contains_key(&slot)
+    }
+
+    /// Manual failover: this node takes over every slot served by `master_addr`.
+    pub fn failover(&self, master_addr: &str) -> anyhow::Result<usize> {
+        let addr = self.self_addr.read().unwrap().clone();
+        let (_, master_ranges) = self.nodes.remove(master_addr)
+            .ok_or_else(|| anyhow::anyhow!("Master {} is not a known cluster node", master_addr))?;
+
+        let mut role = self.role.write().unwrap();
+        let mut ranges = match &*role {
+            ClusterRole::Master(r) => r.clone(),
+            _ => Vec::new(),
+        };
+        let taken: usize = master_ranges.iter().map(|r| (r.end - r.start) as usize + 1).sum();
+        ranges.extend(master_ranges.into_iter().map(|r| SlotRange { _node_addr: addr.clone(), ..r }));
+
+        *role = ClusterRole::Master(ranges.clone());
+        self.nodes.insert(addr, ranges);
+        // The old master stays known (without slots) so it can be re-added as a replica
+        self.nodes.insert(master_addr.to_string(), Vec::new());
+        Ok(taken)
+    }
+
+    /// Number of distinct slots assigned to any known node
+    fn assigned_slots(&self) -> usize {
+        let mut assigned = vec![false; TOTAL_SLOTS as usize];
+        for entry in self.nodes.iter() {
+            for range in entry.value() {
+                for slot in range.start..=range.end.min(TOTAL_SLOTS - 1) {
+                    assigned[slot as usize] = true;
+                }
+            }
+        }
+        assigned.iter().filter(|&&a| a).count()
+    }
+
     /// Get cluster info string
     pub fn get_info(&self) -> String {
         let role = match &*self.role.read().unwrap() {
@@ -107,9 +284,72 @@ impl ClusterManager {
             ClusterRole::Master(_) => "master",
             ClusterRole::Replica(_) => "replica",
         };
-        format!("cluster_enabled:1\ncluster_state:ok\ncluster_slots_assigned:{}\ncluster_known_nodes:{}\ncluster_role:{}",
-            TOTAL_SLOTS, self.nodes.len(), role)
+        let assigned = match &*self.role.read().unwrap() {
+            ClusterRole::Standalone => TOTAL_SLOTS as usize,
+            _ => self.assigned_slots(),
+        };
+        let state = if assigned == TOTAL_SLOTS as usize { "ok" } else { "fail" };
+        format!("cluster_enabled:1\ncluster_state:{}\ncluster_slots_assigned:{}\ncluster_known_nodes:{}\ncluster_role:{}",
+            state, assigned, self.nodes.len(), role)
+    }
+}
+
+/// How long MIGRATE waits to connect to the target, and for each reply.
+const MIGRATE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Runs `commands` (argument lists) on the node at `addr` over one
+/// connection, failing on the first error reply.
+pub fn send_commands(addr: &str, commands: &[Vec<String>]) -> anyhow::Result<()> {
+    let target = addr.to_socket_addrs()?.next().ok_or_else(|| anyhow::anyhow!("Cannot resolve {}", addr))?;
+    let mut stream = TcpStream::connect_timeout(&target, MIGRATE_TIMEOUT)?;
+    stream.set_read_timeout(Some(MIGRATE_TIMEOUT))?;
+    let mut frames = Vec::new();
+    for args in commands {
+        let parts = args.iter().map(|a| RespValue::BulkString(Some(a.as_bytes().to_vec()))).collect();
+        frames.extend(RespValue::Array(Some(parts)).serialize());
+    }
+    stream.write_all(&frames)?;
+
+    let mut buffer = BytesMut::new();
+    let mut chunk = [0u8; 4096];
+    let mut replies = 0;
+    while replies < commands.len() {
+        match stream.read(&mut chunk)? {
+            0 => return Err(anyhow::anyhow!("{} closed the connection", addr)),
+            n => buffer.extend_from_slice(&chunk[..n]),
+        }
+        while let Some(reply) = decode(&mut buffer)? {
+            if let RespValue::Error(e) = reply {
+                return Err(anyhow::anyhow!("{} replied: {}", addr, e));
+            }
+            replies += 1;
+        }
+    }
+    Ok(())
+}
+
+/// Removes individual slots from a set of ranges, splitting ranges as needed.
+fn subtract_slots(ranges: &[SlotRange], slots: &[u16]) -> Vec<SlotRange> {
+    let mut result = Vec::new();
+    for range in ranges {
+        let mut start = range.start;
+        let mut removed: Vec<u16> = slots.iter().copied()
+            .filter(|&s| s >= range.start && s <= range.end)
+            .collect();
+        removed.sort_unstable();
+        removed.dedup();
+
+        for slot in removed {
+            if slot > start {
+                result.push(SlotRange { start, end: slot - 1, _node_addr: range._node_addr.clone() });
+            }
+            start = slot + 1;
+        }
+        if start <= range.end {
+            result.push(SlotRange { start, end: range.end, _node_addr: range._node_addr.clone() });
+        }
     }
+    result
 }
 
 // CRC16 lookup table (XMODEM polynomial)
@@ -147,3 +387,96 @@ static CRC16_TABLE: [u16; 256] = [
     0xef1f, 0xff3e, 0xcf5d, 0xdf7c, 0xaf9b, 0xbfba, 0x8fd9, 0x9ff8,
     0x6e17, 0x7e36, 0x4e55, 0x5e74, 0x2e93, 0x3eb2, 0x0ed1, 0x1ef0,
 ];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(start: u16, end: u16) -> SlotRange {
+        SlotRange { start, end, _node_addr: "n".to_string() }
+    }
+
+    #[test]
+    fn test_subtract_slots_splits_ranges() {
+        let result = subtract_slots(&[range(0, 10)], &[0, 5, 10]);
+        let bounds: Vec<(u16, u16)> = result.iter().map(|r| (r.start, r.end)).collect();
+        assert_eq!(bounds, vec![(1, 4), (6, 9)]);
+
+        let untouched = subtract_slots(&[range(100, 200)], &[5]);
+        assert_eq!((untouched[0].start, untouched[0].end), (100, 200));
+    }
+
+    #[test]
+    fn test_forget_and_failover() {
+        let cluster = ClusterManager::new();
+        cluster.add_node("10.0.0.2:8569".to_string());
+        cluster.nodes.insert("10.0.0.2:8569".to_string(), vec![range(0, 99)]);
+
+        let err = cluster.forget_node("127.0.0.1:8569").unwrap_err();
+        assert_eq!(err.to_string(), "Cannot forget the current node");
+        assert_eq!(cluster.failover("10.0.0.2:8569").unwrap(), 100);
+        assert!(cluster.owns_slot_number(42));
+        assert!(cluster.nodes.get("10.0.0.2:8569").unwrap().is_empty());
+
+        assert_eq!(cluster.del_slots(&[42, 500]), 1);
+        assert!(!cluster.owns_slot_number(42));
+
+        assert!(cluster.forget_node("10.0.0.2:8569").is_ok());
+        assert!(cluster.forget_node("10.0.0.2:8569").is_err());
+    }
+
+    #[test]
+    fn test_replica_redirects_unless_readonly() {
+        let master = "10.0.0.2:8569";
+        let cluster = ClusterManager::new();
+        // Outside a cluster a replica serves every key
+        assert_eq!(cluster.redirect("k", Some(master), false), None);
+
+        cluster.add_node(master.to_string());
+        assert_eq!(cluster.redirect("k", Some(master), false).as_deref(), Some(master));
+        assert_eq!(cluster.redirect("k", Some(master), true), None);
+
+        // Slots of another master go there, READONLY or not
+        let slot = ClusterManager::key_slot("k");
+        cluster.nodes.insert("10.0.0.3:8569".to_string(), vec![range(slot, slot)]);
+        assert_eq!(cluster.redirect("k", Some(master), true).as_deref(), Some("10.0.0.3:8569"));
+    }
+
+    #[test]
+    fn test_set_slot_moves_ownership() {
+        let cluster = ClusterManager::new();
+        let other = "10.0.0.2:8569";
+        cluster.add_slots(vec![5, 6]);
+        assert!(cluster.set_slot(7, SlotState::Migrating(other.to_string())).is_err());
+        cluster.set_slot(5, SlotState::Migrating(other.to_string())).unwrap();
+        assert_eq!(cluster.migrating_to(5).as_deref(), Some(other));
+
+        cluster.set_slot(5, SlotState::Node(other.to_string())).unwrap();
+        assert_eq!(cluster.migrating_to(5), None);
+        assert!(!cluster.owns_slot_number(5));
+        assert!(cluster.owns_slot_number(6));
+        let ranges: Vec<(u16, u16)> = cluster.nodes.get(other).unwrap().iter().map(|r| (r.start, r.end)).collect();
+        assert_eq!(ranges, vec![(5, 5)]);
+
+        // And back: the slot leaves the other node's ranges
+        cluster.set_slot(5, SlotState::Importing(other.to_string())).unwrap();
+        assert!(cluster.is_importing(5));
+        let me = cluster.self_addr.read().unwrap().clone();
+        cluster.set_slot(5, SlotState::Node(me)).unwrap();
+        assert!(cluster.owns_slot_number(5) && !cluster.is_importing(5));
+        assert!(cluster.nodes.get(other).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_hash_tags_share_a_slot() {
+        assert_eq!(ClusterManager::hash_tag("{user:1}:profile"), "user:1");
+        assert_eq!(ClusterManager::hash_tag("a{b}{c}"), "b");
+        assert_eq!(ClusterManager::hash_tag("{}:x"), "{}:x");
+        assert_eq!(ClusterManager::hash_tag("a{b"), "a{b");
+        assert_eq!(ClusterManager::key_slot("{user:1}:cart"), ClusterManager::key_slot("user:1"));
+        assert!(ClusterManager::same_slot(["{user:1}:profile", "{user:1}:cart"]));
+        assert!(!ClusterManager::same_slot(["user:1:profile", "user:2:profile"]));
+        // Redis-compatible slot numbers
+        assert_eq!(ClusterManager::key_slot("foo"), 12182);
+    }
+}
@@ -0,0 +1,182 @@
+//! # Cold Storage Tier
+//!
+//! Keys of the flexible store that stay idle longer than `DB_COLD_AFTER_SECS`
+//! are serialized to disk and dropped from RAM. They are reloaded transparently
+//! the next time a command touches them.
+//!
+//! ## Format
+//! One file per key under `{DB_DATA_DIR}/{db}_cold/`, named after the hex-encoded
+//! key and holding a JSON `ColdRecord`. Only key names stay in memory.
+//!
+//! The tier is a cache, not a source of truth: the AOF and snapshots include
+//! cold keys, so the directory is wiped whenever a store attaches to it.
+
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Longest key that fits a hex-encoded file name (255 byte limit minus ".cold")
+const MAX_KEY_BYTES: usize = 125;
+
+/// On-disk representation of an offloaded key.
+#[derive(Serialize, Deserialize)]
+pub struct ColdRecord {
+    pub value: Value,
+    /// Absolute expiry as Unix time in milliseconds
+    pub expire_at_ms: Option<u64>,
+}
+
+pub struct ColdStore {
+    dir: PathBuf,
+    /// Names of the keys currently held on disk
+    keys: DashMap<String, ()>,
+    offloaded_total: AtomicU64,
+    reloads_total: AtomicU64,
+    reload_micros_total: AtomicU64,
+}
+
+impl ColdStore {
+    pub fn open(db_name: &str) -> anyhow::Result<Self> {
+        let data_dir = crate::core::config::shared().data_dir();
+        Self::at(PathBuf::from(format!("{}/{}_cold", data_dir, db_name)))
+    }
+
+    /// A tier kept in `dir`, emptied first.
+    pub fn at(dir: PathBuf) -> anyhow::Result<Self> {
+        // Leftovers from a previous run are already covered by the AOF/snapshot
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir)?;
+        }
+        std::fs::create_dir_all(&dir)?;
+
+        Ok(Self {
+            dir,
+            keys: DashMap::new(),
+            offloaded_total: AtomicU64::new(0),
+            reloads_total: AtomicU64::new(0),
+            reload_micros_total: AtomicU64::new(0),
+        })
+    }
+
+    fn path_for(&self, key: &str) -> PathBuf {
+        let name: String = key.bytes().map(|b| format!("{:02x}", b)).collect();
+        self.dir.join(format!("{}.cold", name))
+    }
+
+    /// Keys too long for a file name simply stay in memory.
+    pub fn can_hold(key: &str) -> bool {
+        key.len() <= MAX_KEY_BYTES
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.keys.contains_key(key)
+    }
+
+    pub fn key_count(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn keys(&self) -> &DashMap<String, ()> {
+        &self.keys
+    }
+
+    /// Writes a record to disk and registers the key as cold.
+    pub fn put(&self, key: &str, record: &ColdRecord) -> anyhow::Result<()> {
+        let bytes = serde_json::to_vec(record)?;
+        std::fs::write(self.path_for(key), bytes)?;
+        self.keys.insert(key.to_string(), ());
+        Ok(())
+    }
+
+    /// Reads a record without removing it.
+    pub fn peek(&self, key: &str) -> Option<ColdRecord> {
+        let bytes = std::fs::read(self.path_for(key)).ok()?;
+        serde_json::from_slice(&bytes).ok()
+    }
+
+    /// Removes a key from the tier, returning its record if it was present.
+    pub fn take(&self, key: &str) -> Option<ColdRecord> {
+        self.keys.remove(key)?;
+        let record = self.peek(key);
+        let _ = std::fs::remove_file(self.path_for(key));
+        record
+    }
+
+    /// Drops a key from the tier without reading it.
+    pub fn discard(&self, key: &str) {
+        if self.keys.remove(key).is_some() {
+            let _ = std::fs::remove_file(self.path_for(key));
+        }
+    }
+
+    pub fn clear(&self) {
+        let keys: Vec<String> = self.keys.iter().map(|kv| kv.key().clone()).collect();
+        for key in keys {
+            self.discard(&key);
+        }
+    }
+
+    pub fn record_offload(&self) {
+        self.offloaded_total.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_reload(&self, latency: Duration) {
+        self.reloads_total.fetch_add(1, Ordering::Relaxed);
+        self.reload_micros_total.fetch_add(latency.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// INFO section with tier metrics.
+    pub fn info(&self) -> String {
+        let reloads = self.reloads_total.load(Ordering::Relaxed);
+        let avg_us = self.reload_micros_total.load(Ordering::Relaxed).checked_div(reloads).unwrap_or(0);
+        format!(
+            "# Cold Storage\r\ncold_keys:{}\r\ncold_offloaded_total:{}\r\ncold_reloads_total:{}\r\ncold_reload_avg_us:{}\r\n",
+            self.key_count(),
+            self.offloaded_total.load(Ordering::Relaxed),
+            reloads,
+            avg_us
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn records_live_in_one_file_per_key() {
+        let dir = std::env::temp_dir().join(format!("toridb_cold_files_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("stale.cold"), "{}").unwrap();
+        let cold = ColdStore::at(dir.clone()).unwrap();
+        // Leftovers of a previous run are wiped
+        assert!(!dir.join("stale.cold").exists());
+
+        cold.put("ab", &ColdRecord { value: json!([1]), expire_at_ms: Some(5) }).unwrap();
+        assert!(dir.join("6162.cold").exists());
+        let record = cold.peek("ab").unwrap();
+        assert_eq!((record.value, record.expire_at_ms), (json!([1]), Some(5)));
+        assert!(cold.contains("ab"));
+        assert_eq!(cold.take("ab").map(|r| r.value), Some(json!([1])));
+        assert!(!cold.contains("ab") && !dir.join("6162.cold").exists());
+        assert!(cold.take("ab").is_none());
+
+        for key in ["x", "y", "z"] {
+            cold.put(key, &ColdRecord { value: json!(key), expire_at_ms: None }).unwrap();
+        }
+        cold.discard("x");
+        assert_eq!(cold.key_count(), 2);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+        cold.clear();
+        assert_eq!(cold.key_count(), 0);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+
+        assert!(ColdStore::can_hold(&"k".repeat(MAX_KEY_BYTES)));
+        assert!(!ColdStore::can_hold(&"k".repeat(MAX_KEY_BYTES + 1)));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
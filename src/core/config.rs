@@ -0,0 +1,513 @@
+//! # Server Configuration
+//!
+//! One [`Config`] per process, shared via `Arc` (see [`shared`]). Settings
+//! are resolved at startup from, in increasing precedence:
+//! 1. built-in defaults,
+//! 2. the config file: `DB_CONFIG`, or `toridb.toml` in the working directory if present,
+//! 3. `DB_*` environment variables,
+//! 4. `DB_URI` (host, port, database and query options).
+//!
+//! `CONFIG GET` / `CONFIG SET` read and change settings at runtime. `max_keys`,
+//! `max_memory`, `eviction_policy`, `max_connections`, `fsync`, the
+//! `auto_aof_rewrite_*` thresholds, `aof_rewrite_budget` and `ttl_jitter` take
+//! effect immediately, `aof_skip_corrupt` and `cold_after_secs` from the next
+//! database loaded; `host`, `port`, `metrics_port`, `reuse_port`,
+//! `max_frame_bytes`, `workers`, `data_dir` and `backend` are fixed once the
+//! server has started. `password` (`DB_PASSWORD`) is not a `CONFIG`
+//! parameter, so it is never listed.
+
+use crate::core::auth::OidcSettings;
+use crate::core::backend::BackendKind;
+use crate::core::eviction::EvictionPolicy;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock, RwLock};
+
+/// When the AOF is forced to disk (`fsync`).
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FsyncPolicy {
+    /// After every batch of writes
+    Always,
+    /// At most once per second
+    #[default]
+    Everysec,
+    /// Left to the OS
+    No,
+}
+
+impl FsyncPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FsyncPolicy::Always => "always",
+            FsyncPolicy::Everysec => "everysec",
+            FsyncPolicy::No => "no",
+        }
+    }
+}
+
+impl std::str::FromStr for FsyncPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "always" => Ok(FsyncPolicy::Always),
+            "everysec" => Ok(FsyncPolicy::Everysec),
+            "no" => Ok(FsyncPolicy::No),
+            other => Err(format!("Unknown fsync policy '{}' (always, everysec, no)", other)),
+        }
+    }
+}
+
+/// Every setting, as read from `toridb.toml`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    /// Bind address, or a comma-separated list
+    pub host: String,
+    pub port: u16,
+    /// Port of the Prometheus endpoint; 0 disables it
+    pub metrics_port: u16,
+    /// Set `SO_REUSEPORT` so several servers can share the port
+    pub reuse_port: bool,
+    /// Most bytes a client may buffer for one incomplete frame
+    pub max_frame_bytes: usize,
+    /// Password of the `default` user, used until `users.json` exists
+    pub password: String,
+    /// Worker pool size
+    pub workers: usize,
+    /// Keys per database before eviction
+    pub max_keys: usize,
+    /// Approximate bytes of key-value data per database before eviction; 0 disables
+    pub max_memory: usize,
+    /// What happens once `max_keys` or `max_memory` is reached
+    pub eviction_policy: EvictionPolicy,
+    /// Per-database overrides of `eviction_policy` (`[eviction_policies]` table)
+    pub eviction_policies: BTreeMap<String, EvictionPolicy>,
+    pub max_connections: usize,
+    pub fsync: FsyncPolicy,
+    pub data_dir: String,
+    /// Keyspace backend of new databases
+    pub backend: BackendKind,
+    /// Per-database overrides of `backend` (`[backends]` table)
+    pub backends: BTreeMap<String, BackendKind>,
+    /// External login through token introspection (`[oidc]` table)
+    pub oidc: Option<OidcSettings>,
+    /// Seconds a replica waits for an unreachable master before promoting
+    /// itself; 0 disables automatic failover
+    pub failover_timeout: u64,
+    /// Growth of the AOF since its last rewrite, in percent, that triggers
+    /// another one; 0 disables automatic rewrites
+    pub auto_aof_rewrite_percentage: u64,
+    /// Smallest AOF rewritten automatically, in bytes
+    pub auto_aof_rewrite_min_size: usize,
+    /// Most bytes of an AOF rewrite buffered at once
+    pub aof_rewrite_budget: usize,
+    /// Load past AOF records failing their checksum instead of stopping at
+    /// the first one
+    pub aof_skip_corrupt: bool,
+    /// Most a `SETEX` or `EXPIRE` without `JITTER` stretches its TTL, in
+    /// percent (at most 100)
+    pub ttl_jitter: u8,
+    /// Seconds a key stays idle before it is offloaded to disk; 0 disables
+    /// the cold tier
+    pub cold_after_secs: u64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            host: "127.0.0.1".to_string(),
+            port: 8569,
+            metrics_port: 0,
+            reuse_port: false,
+            max_frame_bytes: crate::net::resp::MAX_BULK_LEN + 1024,
+            password: "secret".to_string(),
+            workers: 50,
+            max_keys: 10_000,
+            max_memory: 0,
+            eviction_policy: EvictionPolicy::default(),
+            eviction_policies: BTreeMap::new(),
+            max_connections: 100,
+            fsync: FsyncPolicy::default(),
+            data_dir: "data".to_string(),
+            backend: BackendKind::default(),
+            backends: BTreeMap::new(),
+            oidc: None,
+            failover_timeout: 0,
+            auto_aof_rewrite_percentage: 100,
+            auto_aof_rewrite_min_size: 64 << 20,
+            aof_rewrite_budget: 64 << 20,
+            aof_skip_corrupt: false,
+            ttl_jitter: 0,
+            cold_after_secs: 0,
+        }
+    }
+}
+
+/// Parameter names, in `CONFIG GET *` order, and whether `CONFIG SET` may change them.
+const PARAMS: &[(&str, bool)] = &[
+    ("host", false),
+    ("port", false),
+    ("metrics_port", false),
+    ("reuse_port", false),
+    ("max_frame_bytes", false),
+    ("workers", false),
+    ("max_keys", true),
+    ("max_memory", true),
+    ("eviction_policy", true),
+    ("max_connections", true),
+    ("fsync", true),
+    ("data_dir", false),
+    ("backend", false),
+    ("failover_timeout", true),
+    ("auto_aof_rewrite_percentage", true),
+    ("auto_aof_rewrite_min_size", true),
+    ("aof_rewrite_budget", true),
+    ("aof_skip_corrupt", true),
+    ("ttl_jitter", true),
+    ("cold_after_secs", true),
+];
+
+impl Settings {
+    /// Reads a TOML config file; absent keys keep their defaults.
+    pub fn from_file(path: &str) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Cannot read config file {}: {}", path, e))?;
+        toml::from_str(&text).map_err(|e| anyhow::anyhow!("Invalid config file {}: {}", path, e))
+    }
+
+    /// Defaults, then the config file, then `DB_*` variables (not `DB_URI`,
+    /// which the server entry point applies).
+    pub fn load() -> anyhow::Result<Self> {
+        let mut settings = match std::env::var("DB_CONFIG") {
+            Ok(path) => Self::from_file(&path)?,
+            Err(_) if std::path::Path::new("toridb.toml").exists() => Self::from_file("toridb.toml")?,
+            Err(_) => Self::default(),
+        };
+        for (param, var) in [
+            ("host", "DB_HOST"),
+            ("port", "DB_PORT"),
+            ("metrics_port", "DB_METRICS_PORT"),
+            ("reuse_port", "DB_REUSE_PORT"),
+            ("max_frame_bytes", "DB_MAX_FRAME_BYTES"),
+            ("workers", "DB_WORKERS"),
+            ("max_keys", "DB_MAX_KEYS"),
+            ("max_memory", "DB_MAX_MEMORY"),
+            ("eviction_policy", "DB_EVICTION_POLICY"),
+            ("max_connections", "DB_MAX_CONNECTIONS"),
+            ("fsync", "DB_FSYNC"),
+            ("data_dir", "DB_DATA_DIR"),
+            ("backend", "DB_BACKEND"),
+            ("failover_timeout", "DB_FAILOVER_TIMEOUT"),
+            ("auto_aof_rewrite_percentage", "DB_AUTO_AOF_REWRITE_PERCENTAGE"),
+            ("auto_aof_rewrite_min_size", "DB_AUTO_AOF_REWRITE_MIN_SIZE"),
+            ("aof_rewrite_budget", "DB_AOF_REWRITE_BUDGET"),
+            ("aof_skip_corrupt", "DB_AOF_SKIP_CORRUPT"),
+            ("ttl_jitter", "DB_TTL_JITTER"),
+            ("cold_after_secs", "DB_COLD_AFTER_SECS"),
+        ] {
+            if let Ok(value) = std::env::var(var) {
+                settings.set(param, &value).map_err(|e| anyhow::anyhow!("{}: {}", var, e))?;
+            }
+        }
+        if let Ok(password) = std::env::var("DB_PASSWORD") {
+            settings.password = password;
+        }
+        Ok(settings)
+    }
+
+    pub fn get(&self, param: &str) -> Option<String> {
+        Some(match param {
+            "host" => self.host.clone(),
+            "port" => self.port.to_string(),
+            "metrics_port" => self.metrics_port.to_string(),
+            "reuse_port" => if self.reuse_port { "yes" } else { "no" }.to_string(),
+            "max_frame_bytes" => self.max_frame_bytes.to_string(),
+            "workers" => self.workers.to_string(),
+            "max_keys" => self.max_keys.to_string(),
+            "max_memory" => self.max_memory.to_string(),
+            "eviction_policy" => self.eviction_policy.as_str().to_string(),
+            "max_connections" => self.max_connections.to_string(),
+            "fsync" => self.fsync.as_str().to_string(),
+            "data_dir" => self.data_dir.clone(),
+            "backend" => self.backend.as_str().to_string(),
+            "failover_timeout" => self.failover_timeout.to_string(),
+            "auto_aof_rewrite_percentage" => self.auto_aof_rewrite_percentage.to_string(),
+            "auto_aof_rewrite_min_size" => self.auto_aof_rewrite_min_size.to_string(),
+            "aof_rewrite_budget" => self.aof_rewrite_budget.to_string(),
+            "aof_skip_corrupt" => if self.aof_skip_corrupt { "yes" } else { "no" }.to_string(),
+            "ttl_jitter" => self.ttl_jitter.to_string(),
+            "cold_after_secs" => self.cold_after_secs.to_string(),
+            _ => return None,
+        })
+    }
+
+    pub fn set(&mut self, param: &str, value: &str) -> Result<(), String> {
+        fn number<T: std::str::FromStr>(param: &str, value: &str) -> Result<T, String> {
+            value.parse().map_err(|_| format!("Invalid value '{}' for '{}'", value, param))
+        }
+        // A byte count, optionally in kb, mb or gb
+        fn bytes(param: &str, value: &str) -> Result<usize, String> {
+            let lower = value.trim().to_ascii_lowercase();
+            let (digits, unit) = match lower.find(|c: char| !c.is_ascii_digit()) {
+                Some(i) => lower.split_at(i),
+                None => (lower.as_str(), ""),
+            };
+            let unit = match unit.trim() {
+                "" | "b" => 1,
+                "kb" | "k" => 1 << 10,
+                "mb" | "m" => 1 << 20,
+                "gb" | "g" => 1 << 30,
+                _ => return Err(format!("Invalid value '{}' for '{}'", value, param)),
+            };
+            number::<usize>(param, digits)?.checked_mul(unit).ok_or_else(|| format!("Invalid value '{}' for '{}'", value, param))
+        }
+        fn flag(param: &str, value: &str) -> Result<bool, String> {
+            match value.trim().to_ascii_lowercase().as_str() {
+                "yes" | "true" | "1" | "on" => Ok(true),
+                "no" | "false" | "0" | "off" => Ok(false),
+                _ => Err(format!("Invalid value '{}' for '{}', use yes or no", value, param)),
+            }
+        }
+        match param {
+            "host" => self.host = value.to_string(),
+            "port" => self.port = number(param, value)?,
+            "metrics_port" => self.metrics_port = number(param, value)?,
+            "reuse_port" => self.reuse_port = flag(param, value)?,
+            "max_frame_bytes" => self.max_frame_bytes = bytes(param, value)?,
+            "workers" => self.workers = number(param, value)?,
+            "max_keys" => self.max_keys = number(param, value)?,
+            "max_memory" => self.max_memory = bytes(param, value)?,
+            "eviction_policy" => self.eviction_policy = value.parse()?,
+            "max_connections" => self.max_connections = number(param, value)?,
+            "fsync" => self.fsync = value.parse()?,
+            "data_dir" => self.data_dir = value.to_string(),
+            "backend" => self.backend = value.parse()?,
+            "failover_timeout" => self.failover_timeout = number(param, value)?,
+            "auto_aof_rewrite_percentage" => self.auto_aof_rewrite_percentage = number(param, value)?,
+            "auto_aof_rewrite_min_size" => self.auto_aof_rewrite_min_size = bytes(param, value)?,
+            "aof_rewrite_budget" => self.aof_rewrite_budget = bytes(param, value)?,
+            "aof_skip_corrupt" => self.aof_skip_corrupt = flag(param, value)?,
+            "ttl_jitter" => match number(param, value)? {
+                percent @ 0..=100 => self.ttl_jitter = percent,
+                _ => return Err(format!("Invalid value '{}' for '{}', use a percentage up to 100", value, param)),
+            },
+            "cold_after_secs" => self.cold_after_secs = number(param, value)?,
+            _ => return Err(format!("Unknown config parameter '{}'", param)),
+        }
+        Ok(())
+    }
+}
+
+/// The live settings of a server.
+#[derive(Debug, Default)]
+pub struct Config {
+    settings: RwLock<Settings>,
+}
+
+static SHARED: OnceLock<Arc<Config>> = OnceLock::new();
+
+/// Installs the process-wide config. Returns false if one was already in use.
+pub fn init(settings: Settings) -> bool {
+    SHARED.set(Arc::new(Config::new(settings))).is_ok()
+}
+
+/// The process-wide config; loaded from file and environment on first use if
+/// [`init`] was not called (tools, tests).
+pub fn shared() -> Arc<Config> {
+    SHARED.get_or_init(|| {
+        let settings = Settings::load().unwrap_or_else(|e| {
+            crate::core::logger::error(&format!("{}; using defaults", e));
+            Settings::default()
+        });
+        Arc::new(Config::new(settings))
+    }).clone()
+}
+
+impl Config {
+    pub fn new(settings: Settings) -> Self {
+        Self { settings: RwLock::new(settings) }
+    }
+
+    pub fn settings(&self) -> Settings {
+        self.settings.read().unwrap().clone()
+    }
+
+    pub fn max_keys(&self) -> usize {
+        self.settings.read().unwrap().max_keys
+    }
+
+    pub fn max_memory(&self) -> usize {
+        self.settings.read().unwrap().max_memory
+    }
+
+    /// Eviction policy of a database.
+    pub fn eviction_policy_for(&self, db_name: &str) -> EvictionPolicy {
+        let settings = self.settings.read().unwrap();
+        settings.eviction_policies.get(db_name).copied().unwrap_or(settings.eviction_policy)
+    }
+
+    pub fn max_connections(&self) -> usize {
+        self.settings.read().unwrap().max_connections
+    }
+
+    pub fn fsync(&self) -> FsyncPolicy {
+        self.settings.read().unwrap().fsync
+    }
+
+    pub fn data_dir(&self) -> String {
+        self.settings.read().unwrap().data_dir.clone()
+    }
+
+    /// Resolves a file path given by a client (`BACKUP VERIFY`, `EXPORT
+    /// KEYS`) inside the data directory. Relative paths start there; a path
+    /// whose directory, links followed, is outside it is refused.
+    pub fn data_path(&self, path: &str) -> Result<PathBuf, String> {
+        let outside = || format!("Path '{}' is outside the data directory", path);
+        let dir = std::fs::canonicalize(self.data_dir()).map_err(|e| format!("Cannot open the data directory: {}", e))?;
+        let full = dir.join(path);
+        let name = full.file_name().filter(|_| !path.ends_with('/')).ok_or_else(outside)?;
+        let parent = full.parent().and_then(|p| std::fs::canonicalize(p).ok()).ok_or_else(outside)?;
+        if !parent.starts_with(&dir) {
+            return Err(outside());
+        }
+        Ok(parent.join(name))
+    }
+
+    /// How long a replica waits for an unreachable master before promoting
+    /// itself, if automatic failover is on.
+    pub fn failover_timeout(&self) -> Option<std::time::Duration> {
+        let secs = self.settings.read().unwrap().failover_timeout;
+        (secs > 0).then(|| std::time::Duration::from_secs(secs))
+    }
+
+    /// `auto_aof_rewrite_min_size` and `auto_aof_rewrite_percentage`.
+    pub fn auto_aof_rewrite(&self) -> (u64, u64) {
+        let settings = self.settings.read().unwrap();
+        (settings.auto_aof_rewrite_min_size as u64, settings.auto_aof_rewrite_percentage)
+    }
+
+    /// Whether AOF loading skips records failing their checksum.
+    pub fn aof_skip_corrupt(&self) -> bool {
+        self.settings.read().unwrap().aof_skip_corrupt
+    }
+
+    /// Most bytes of an AOF rewrite buffered at once.
+    pub fn aof_rewrite_budget(&self) -> usize {
+        self.settings.read().unwrap().aof_rewrite_budget
+    }
+
+    /// Idle time before a key moves to the cold tier, if it is enabled.
+    pub fn cold_after(&self) -> Option<std::time::Duration> {
+        let secs = self.settings.read().unwrap().cold_after_secs;
+        (secs > 0).then(|| std::time::Duration::from_secs(secs))
+    }
+
+    /// Default TTL jitter of `SETEX` and `EXPIRE`, in percent.
+    pub fn ttl_jitter(&self) -> u8 {
+        self.settings.read().unwrap().ttl_jitter
+    }
+
+    /// Backend for a database being created.
+    pub fn backend_for(&self, db_name: &str) -> BackendKind {
+        let settings = self.settings.read().unwrap();
+        settings.backends.get(db_name).copied().unwrap_or(settings.backend)
+    }
+
+    /// `CONFIG GET`: name/value pairs of the parameters matching a glob pattern.
+    pub fn get(&self, pattern: &str) -> Vec<(String, String)> {
+        let settings = self.settings.read().unwrap();
+        PARAMS.iter()
+            .filter(|(name, _)| crate::core::glob::glob_match(&pattern.to_ascii_lowercase(), name))
+            .filter_map(|(name, _)| settings.get(name).map(|value| (name.to_string(), value)))
+            .collect()
+    }
+
+    /// `CONFIG SET`: changes a runtime parameter.
+    pub fn set(&self, param: &str, value: &str) -> Result<(), String> {
+        let param = param.to_ascii_lowercase();
+        match PARAMS.iter().find(|(name, _)| *name == param) {
+            None => Err(format!("Unknown config parameter '{}'", param)),
+            Some((_, false)) => Err(format!("'{}' can only be set at startup", param)),
+            Some(_) => self.settings.write().unwrap().set(&param, value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_settings_and_runtime_changes() {
+        let settings: Settings = toml::from_str("port = 9000\nfsync = \"always\"\nmax_keys = 5\n[backends]\narchive = \"disk\"\n[eviction_policies]\ncache = \"allkeys-lfu\"\n[oidc]\nintrospection_url = \"https://idp/introspect\"").unwrap();
+        assert_eq!(settings.port, 9000);
+        assert_eq!(settings.fsync, FsyncPolicy::Always);
+        assert_eq!(settings.workers, Settings::default().workers);
+        assert_eq!(settings.oidc.as_ref().unwrap().rules_claim, "toridb_rules");
+        assert!(toml::from_str::<Settings>("prot = 9000").is_err());
+
+        let config = Config::new(settings);
+        assert_eq!(config.backend_for("archive"), BackendKind::Disk);
+        assert_eq!(config.backend_for("data"), BackendKind::Memory);
+        assert_eq!(config.get("max_*"), vec![
+            ("max_frame_bytes".to_string(), (crate::net::resp::MAX_BULK_LEN + 1024).to_string()),
+            ("max_keys".to_string(), "5".to_string()),
+            ("max_memory".to_string(), "0".to_string()),
+            ("max_connections".to_string(), "100".to_string()),
+        ]);
+        assert!(config.set("MAX_KEYS", "50").is_ok());
+        assert_eq!(config.max_keys(), 50);
+        assert!(config.set("max_memory", "64mb").is_ok());
+        assert_eq!(config.max_memory(), 64 << 20);
+        assert!(config.set("max_memory", "64 parsecs").is_err());
+        assert_eq!(config.eviction_policy_for("cache"), EvictionPolicy::AllkeysLfu);
+        assert!(config.set("eviction_policy", "noeviction").is_ok());
+        assert_eq!(config.eviction_policy_for("data"), EvictionPolicy::Noeviction);
+        assert_eq!(config.eviction_policy_for("cache"), EvictionPolicy::AllkeysLfu);
+        assert!(config.set("eviction_policy", "random").is_err());
+        assert!(config.set("fsync", "sometimes").is_err());
+        assert_eq!(config.auto_aof_rewrite(), (64 << 20, 100));
+        assert!(config.set("auto_aof_rewrite_min_size", "1kb").is_ok());
+        assert!(config.set("auto_aof_rewrite_percentage", "0").is_ok());
+        assert_eq!(config.auto_aof_rewrite(), (1 << 10, 0));
+        assert!(!config.aof_skip_corrupt());
+        assert!(config.set("aof_skip_corrupt", "yes").is_ok());
+        assert!(config.aof_skip_corrupt());
+        assert_eq!(config.get("aof_skip_corrupt"), vec![("aof_skip_corrupt".to_string(), "yes".to_string())]);
+        assert!(config.set("aof_skip_corrupt", "maybe").is_err());
+        assert!(config.set("ttl_jitter", "25").is_ok());
+        assert_eq!(config.ttl_jitter(), 25);
+        assert!(config.set("ttl_jitter", "101").is_err());
+        assert_eq!(config.ttl_jitter(), 25);
+        assert!(config.set("aof_rewrite_budget", "8mb").is_ok());
+        assert_eq!(config.aof_rewrite_budget(), 8 << 20);
+        assert_eq!(config.cold_after(), None);
+        assert!(config.set("cold_after_secs", "30").is_ok());
+        assert_eq!(config.cold_after(), Some(std::time::Duration::from_secs(30)));
+        assert!(config.set("reuse_port", "yes").unwrap_err().contains("startup"));
+        assert!(config.set("password", "x").is_err());
+        assert!(config.get("pass*").is_empty());
+        assert!(config.set("port", "1").unwrap_err().contains("startup"));
+        assert!(config.set("nope", "1").is_err());
+        assert_eq!(config.get("*").len(), PARAMS.len());
+    }
+
+    #[test]
+    fn client_paths_stay_in_the_data_dir() {
+        let dir = std::env::temp_dir().join(format!("toridb_paths_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("backups")).unwrap();
+        let dir = std::fs::canonicalize(dir).unwrap();
+        let config = Config::new(Settings { data_dir: dir.to_string_lossy().into_owned(), ..Settings::default() });
+
+        assert_eq!(config.data_path("out.resp").unwrap(), dir.join("out.resp"));
+        assert_eq!(config.data_path("backups/../backups/shop").unwrap(), dir.join("backups/shop"));
+        assert_eq!(config.data_path(&dir.join("shop.db").to_string_lossy()).unwrap(), dir.join("shop.db"));
+        for path in ["../out.resp", "/etc/passwd", "backups/../../x", "missing/x", "..", "backups/"] {
+            assert!(config.data_path(path).unwrap_err().contains("outside the data directory"), "{}", path);
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,100 @@
+//! # Striped Counters
+//!
+//! `INCR` updates its key under the write lock of one keyspace shard, so a
+//! single hot key (page views, rate counters) serializes every client on that
+//! lock. `CINCR` keys are [`StripedCounter`]s instead: the count is split
+//! across [`stripes`] cells, each on its own cache line, and every thread
+//! adds to its own cell under a shared read lock. A read sums the cells.
+//!
+//! Cells wrap on overflow; since addition is modular, their sum is still the
+//! exact total whenever the total itself fits in an `i64`.
+
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicI64, AtomicUsize, Ordering};
+
+/// Most cells per counter, whatever the number of cores.
+const MAX_STRIPES: usize = 64;
+
+/// A cell alone on its cache line, so neighbours don't contend.
+#[derive(Debug, Default)]
+#[repr(align(64))]
+struct Cell(AtomicI64);
+
+/// Cells per counter: the number of cores, rounded up to a power of two.
+pub fn stripes() -> usize {
+    static STRIPES: OnceLock<usize> = OnceLock::new();
+    *STRIPES.get_or_init(|| {
+        let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+        cores.next_power_of_two().clamp(1, MAX_STRIPES)
+    })
+}
+
+/// The cell index of the calling thread, handed out round-robin.
+fn stripe() -> usize {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    thread_local! {
+        static STRIPE: usize = NEXT.fetch_add(1, Ordering::Relaxed);
+    }
+    STRIPE.with(|s| *s) & (stripes() - 1)
+}
+
+#[derive(Debug)]
+pub struct StripedCounter {
+    cells: Box<[Cell]>,
+}
+
+impl StripedCounter {
+    /// A counter holding `value`.
+    pub fn new(value: i64) -> Self {
+        let cells: Box<[Cell]> = (0..stripes()).map(|_| Cell::default()).collect();
+        cells[0].0.store(value, Ordering::Relaxed);
+        Self { cells }
+    }
+
+    /// Adds `delta` to the calling thread's cell and returns the new total.
+    pub fn add(&self, delta: i64) -> i64 {
+        self.cells[stripe() % self.cells.len()].0.fetch_add(delta, Ordering::Relaxed);
+        self.total()
+    }
+
+    /// Sum of the cells. Increments racing with the read may or may not be counted.
+    pub fn total(&self) -> i64 {
+        self.cells.iter().fold(0i64, |sum, cell| sum.wrapping_add(cell.0.load(Ordering::Relaxed)))
+    }
+
+    /// Rough bytes held by the counter.
+    pub fn memory_bytes(&self) -> usize {
+        std::mem::size_of::<Self>() + std::mem::size_of_val(&*self.cells)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn increments_from_many_threads_sum_exactly() {
+        let counter = Arc::new(StripedCounter::new(5));
+        let threads: Vec<_> = (0..8).map(|_| {
+            let counter = Arc::clone(&counter);
+            std::thread::spawn(move || {
+                for _ in 0..1000 {
+                    counter.add(1);
+                }
+            })
+        }).collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+        assert_eq!(counter.total(), 8005);
+        assert_eq!(counter.add(-10), 7995);
+
+        // Cells wrap, but the total is exact while it fits
+        let counter = StripedCounter::new(i64::MAX);
+        std::thread::scope(|s| {
+            s.spawn(|| counter.add(1));
+        });
+        assert_eq!(counter.add(-1), i64::MAX);
+    }
+}
@@ -0,0 +1,180 @@
+//! # Date and Time Values
+//!
+//! `datetime` columns hold Unix timestamps in seconds (UTC). Literals for
+//! them may be written as a timestamp or as an ISO-8601 date or date-time:
+//! `1704067200`, `2024-01-01`, `2024-01-01T12:30:00Z`,
+//! `2024-01-01 12:30`, `2024-01-01T12:30:00.250+02:00`. A date-time without
+//! an offset is UTC, and fractions of a second are dropped.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SECS_PER_DAY: i64 = 86_400;
+
+/// Unit of `DATE_ADD` and `DATE_DIFF`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimeUnit {
+    Second,
+    Minute,
+    Hour,
+    Day,
+    Week,
+}
+
+impl TimeUnit {
+    /// Seconds in one unit.
+    pub fn seconds(self) -> i64 {
+        match self {
+            TimeUnit::Second => 1,
+            TimeUnit::Minute => 60,
+            TimeUnit::Hour => 3_600,
+            TimeUnit::Day => SECS_PER_DAY,
+            TimeUnit::Week => 7 * SECS_PER_DAY,
+        }
+    }
+}
+
+impl std::str::FromStr for TimeUnit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().trim_end_matches('S') {
+            "SECOND" => Ok(TimeUnit::Second),
+            "MINUTE" => Ok(TimeUnit::Minute),
+            "HOUR" => Ok(TimeUnit::Hour),
+            "DAY" => Ok(TimeUnit::Day),
+            "WEEK" => Ok(TimeUnit::Week),
+            _ => Err(format!("Unsupported time unit '{}', use SECOND, MINUTE, HOUR, DAY or WEEK", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for TimeUnit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            TimeUnit::Second => "SECOND",
+            TimeUnit::Minute => "MINUTE",
+            TimeUnit::Hour => "HOUR",
+            TimeUnit::Day => "DAY",
+            TimeUnit::Week => "WEEK",
+        })
+    }
+}
+
+/// The current Unix time in seconds.
+pub fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
+}
+
+/// Reads a timestamp or an ISO-8601 date / date-time.
+pub fn parse(literal: &str) -> Option<i64> {
+    let literal = literal.trim();
+    if let Ok(ts) = literal.parse::<i64>() {
+        return Some(ts);
+    }
+    let (date, time) = match literal.find(['T', 't', ' ']) {
+        Some(i) => (&literal[..i], Some(&literal[i + 1..])),
+        None => (literal, None),
+    };
+
+    let mut parts = date.splitn(3, '-');
+    let (year, month, day) = (parts.next()?, parts.next()?, parts.next()?);
+    if year.len() != 4 || month.len() != 2 || day.len() != 2 {
+        return None;
+    }
+    let (year, month, day): (i64, u32, u32) = (year.parse().ok()?, month.parse().ok()?, day.parse().ok()?);
+    if !(1..=12).contains(&month) || day == 0 || day > days_in_month(year, month) {
+        return None;
+    }
+    let mut ts = days_from_civil(year, month, day) * SECS_PER_DAY;
+
+    if let Some(time) = time {
+        // The offset starts at Z, + or the - after the clock
+        let (clock, offset) = match time.find(['Z', 'z', '+', '-']) {
+            Some(i) => (&time[..i], &time[i..]),
+            None => (time, ""),
+        };
+        let clock = clock.split('.').next()?;
+        let mut fields = clock.split(':');
+        let hour: i64 = two_digits(fields.next()?)?;
+        let minute: i64 = two_digits(fields.next()?)?;
+        let second: i64 = fields.next().map_or(Some(0), two_digits)?;
+        if fields.next().is_some() || hour > 23 || minute > 59 || second > 60 {
+            return None;
+        }
+        ts += hour * 3_600 + minute * 60 + second;
+        ts -= parse_offset(offset)?;
+    }
+    Some(ts)
+}
+
+fn two_digits(field: &str) -> Option<i64> {
+    (field.len() == 2).then(|| field.parse().ok()).flatten()
+}
+
+/// Seconds east of UTC of `Z`, `+HH:MM`, `-HHMM` or `+HH`; an empty offset is UTC.
+fn parse_offset(offset: &str) -> Option<i64> {
+    if offset.is_empty() || offset.eq_ignore_ascii_case("z") {
+        return Some(0);
+    }
+    let sign = if offset.starts_with('-') { -1 } else { 1 };
+    let digits: String = offset[1..].chars().filter(|c| *c != ':').collect();
+    let (hours, minutes) = match digits.len() {
+        2 => (two_digits(&digits)?, 0),
+        4 => (two_digits(&digits[..2])?, two_digits(&digits[2..])?),
+        _ => return None,
+    };
+    (hours <= 23 && minutes <= 59).then_some(sign * (hours * 3_600 + minutes * 60))
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date (Howard Hinnant's
+/// `days_from_civil`).
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = month as i64;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_iso_dates() {
+        assert_eq!(parse("1704067200"), Some(1_704_067_200));
+        assert_eq!(parse("1970-01-01"), Some(0));
+        assert_eq!(parse("2024-01-01"), Some(1_704_067_200));
+        assert_eq!(parse("2024-02-29T12:30:15Z"), Some(1_709_209_815));
+        assert_eq!(parse("2024-02-29 12:30"), Some(1_709_209_800));
+        assert_eq!(parse("2024-02-29T14:30:15.999+02:00"), Some(1_709_209_815));
+        assert_eq!(parse("2024-02-29T07:30:15-0500"), Some(1_709_209_815));
+        assert_eq!(parse("1969-12-31T23:59:59Z"), Some(-1));
+        assert_eq!(parse("2000-03-01"), Some(951_868_800));
+    }
+
+    #[test]
+    fn rejects_malformed_dates() {
+        for bad in ["", "yesterday", "2023-02-29", "2024-13-01", "2024-1-1", "2024-01-01T25:00", "2024-01-01T10", "2024-01-01T10:00+2"] {
+            assert_eq!(parse(bad), None, "{}", bad);
+        }
+    }
+
+    #[test]
+    fn units() {
+        assert_eq!("days".parse::<TimeUnit>(), Ok(TimeUnit::Day));
+        assert_eq!("Hour".parse::<TimeUnit>().unwrap().seconds(), 3_600);
+        assert!("month".parse::<TimeUnit>().is_err());
+    }
+}
@@ -0,0 +1,97 @@
+//! # Replica Digests
+//!
+//! `REPLCHECK` looks for replicas that silently drifted from their master
+//! without moving any data: both sides reduce the keyspace, and each table,
+//! to a 64-bit xxh3 digest, and the master compares the replica's digests
+//! with its own.
+//!
+//! Every entry (a key with its value, a table row) is hashed on its own, and
+//! the sorted entry hashes are hashed again, so a digest depends neither on
+//! iteration order nor on row ids. Expiry times are left out, as each node
+//! converts them to its own clock.
+
+use crate::core::memory::DatabaseEngine;
+use serde::Serialize;
+use xxhash_rust::xxh3::{Xxh3, xxh3_64};
+
+/// Named digests of a database, as [`digests`] lists them.
+pub type Digests = Vec<(String, u64)>;
+
+/// Digest of one entry, from its JSON form.
+pub fn entry(value: &impl Serialize) -> u64 {
+    xxh3_64(&serde_json::to_vec(value).unwrap_or_default())
+}
+
+/// Digest of a set of entry digests, whatever their order.
+pub fn combine(mut hashes: Vec<u64>) -> u64 {
+    hashes.sort_unstable();
+    let mut hasher = Xxh3::new();
+    for hash in hashes {
+        hasher.update(&hash.to_le_bytes());
+    }
+    hasher.digest()
+}
+
+/// Digests of a database: `keyspace`, then `table:<name>` for each table by name.
+pub fn digests(engine: &DatabaseEngine) -> Digests {
+    let mut digests = vec![("keyspace".to_string(), engine.flexible.digest())];
+    for name in engine.structured.table_names() {
+        if let Some(digest) = engine.structured.digest(&name) {
+            digests.push((format!("table:{}", name), digest));
+        }
+    }
+    digests
+}
+
+/// `name=<hex>` pairs separated by spaces, as a replica reports them.
+pub fn format(digests: &[(String, u64)]) -> String {
+    digests.iter().map(|(name, digest)| format!("{}={:016x}", name, digest)).collect::<Vec<_>>().join(" ")
+}
+
+/// Reads what [`format`] wrote.
+pub fn parse<'a>(words: impl IntoIterator<Item = &'a str>) -> Option<Digests> {
+    words.into_iter()
+        .map(|word| {
+            let (name, hex) = word.split_once('=')?;
+            Some((name.to_string(), u64::from_str_radix(hex, 16).ok()?))
+        })
+        .collect()
+}
+
+/// How `replica` differs from `master`, one line per digest; empty if they match.
+pub fn compare(master: &[(String, u64)], replica: &[(String, u64)]) -> Vec<String> {
+    let find = |digests: &[(String, u64)], name: &str| digests.iter().find(|(n, _)| n == name).map(|(_, d)| *d);
+    let mut differences = Vec::new();
+    for (name, digest) in master {
+        match find(replica, name) {
+            Some(other) if other != *digest => differences.push(format!("{}: master {:016x}, replica {:016x}", name, digest, other)),
+            Some(_) => {}
+            None => differences.push(format!("{}: missing on replica", name)),
+        }
+    }
+    for (name, _) in replica.iter().filter(|(name, _)| find(master, name).is_none()) {
+        differences.push(format!("{}: only on replica", name));
+    }
+    differences
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn digests_ignore_order_and_report_differences() {
+        assert_eq!(combine(vec![entry(&"a"), entry(&"b")]), combine(vec![entry(&"b"), entry(&"a")]));
+        assert_ne!(combine(vec![entry(&"a")]), combine(vec![entry(&"a"), entry(&"a")]));
+
+        let master = vec![("keyspace".to_string(), 1), ("table:a".to_string(), 2), ("table:b".to_string(), 3)];
+        let replica = vec![("keyspace".to_string(), 1), ("table:a".to_string(), 9), ("table:c".to_string(), 4)];
+        assert_eq!(parse(format(&master).split(' ')), Some(master.clone()));
+        assert!(compare(&master, &master).is_empty());
+        assert_eq!(compare(&master, &replica), [
+            "table:a: master 0000000000000002, replica 0000000000000009",
+            "table:b: missing on replica",
+            "table:c: only on replica",
+        ]);
+    }
+}
@@ -0,0 +1,144 @@
+//! # Eviction Policies
+//!
+//! What the `memory` backend does once a database reaches `max_keys` or
+//! `max_memory` (`eviction_policy`, per database in `[eviction_policies]`):
+//! - `allkeys-lru` (default): evicts the least recently used key.
+//! - `allkeys-lfu`: evicts the least frequently used key.
+//! - `volatile-ttl`: evicts the key with an expiry closest to it; keys
+//!   without one are never evicted.
+//! - `noeviction`: evicts nothing; commands that could add data fail instead.
+//!
+//! Like Redis, the policies are approximated: each eviction compares
+//! [`SAMPLES`] neighbouring keys from a random place in the map.
+//!
+//! Access frequency is a logarithmic 8-bit counter ([`Frequency`]): every
+//! hit increments it with a probability that shrinks as it grows, and it
+//! decays by one for every minute the key stays idle, so keys that were
+//! popular once do not stay forever.
+
+use dashmap::DashMap;
+use serde::Deserialize;
+use std::time::Instant;
+
+/// Keys compared per eviction.
+pub const SAMPLES: usize = 5;
+
+/// Counter of a newly written key, so it is not the first one evicted.
+const LFU_INIT: u8 = 5;
+/// Higher values make the counter grow more slowly.
+const LFU_LOG_FACTOR: f64 = 10.0;
+/// Idle seconds per decrement of the counter.
+const LFU_DECAY_SECS: u64 = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EvictionPolicy {
+    #[default]
+    AllkeysLru,
+    AllkeysLfu,
+    VolatileTtl,
+    Noeviction,
+}
+
+impl EvictionPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EvictionPolicy::AllkeysLru => "allkeys-lru",
+            EvictionPolicy::AllkeysLfu => "allkeys-lfu",
+            EvictionPolicy::VolatileTtl => "volatile-ttl",
+            EvictionPolicy::Noeviction => "noeviction",
+        }
+    }
+}
+
+impl std::str::FromStr for EvictionPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "allkeys-lru" => Ok(EvictionPolicy::AllkeysLru),
+            "allkeys-lfu" => Ok(EvictionPolicy::AllkeysLfu),
+            "volatile-ttl" => Ok(EvictionPolicy::VolatileTtl),
+            "noeviction" => Ok(EvictionPolicy::Noeviction),
+            other => Err(format!("Unknown eviction policy '{}' (allkeys-lru, allkeys-lfu, volatile-ttl, noeviction)", other)),
+        }
+    }
+}
+
+/// Logarithmic access counter of a key.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frequency(u8);
+
+impl Default for Frequency {
+    fn default() -> Self {
+        Frequency(LFU_INIT)
+    }
+}
+
+impl Frequency {
+    /// The counter, decayed for the time since `last_access`.
+    pub fn decayed(self, last_access: Instant) -> u8 {
+        let periods = last_access.elapsed().as_secs() / LFU_DECAY_SECS;
+        self.0.saturating_sub(periods.min(u8::MAX as u64) as u8)
+    }
+
+    /// Records a hit on a key last accessed at `last_access`.
+    pub fn hit(&mut self, last_access: Instant) {
+        let counter = self.decayed(last_access);
+        let base = counter.saturating_sub(LFU_INIT) as f64;
+        let grows = counter < u8::MAX && rand::random::<f64>() < 1.0 / (base * LFU_LOG_FACTOR + 1.0);
+        self.0 = if grows { counter + 1 } else { counter };
+    }
+}
+
+/// Samples [`SAMPLES`] entries of `map` from a random place and returns the
+/// one with the lowest score: the one to evict.
+pub fn sample<V, S: Ord>(map: &DashMap<String, V>, score: impl Fn(&V) -> S) -> Option<(String, S)> {
+    let len = map.len();
+    if len == 0 {
+        return None;
+    }
+    // Neighbouring entries, as Redis samples them: the map's order is that of
+    // the key hashes, which has nothing to do with how keys are used. Reaching
+    // the start costs a walk through the entries before it
+    let start = rand::random_range(0..len);
+    map.iter().skip(start).chain(map.iter())
+        .take(SAMPLES.min(len))
+        .map(|kv| (kv.key().clone(), score(kv.value())))
+        .min_by(|(_, a), (_, b)| a.cmp(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn policies_and_frequency() {
+        assert_eq!("ALLKEYS-LFU".parse::<EvictionPolicy>(), Ok(EvictionPolicy::AllkeysLfu));
+        assert!("volatile-lru".parse::<EvictionPolicy>().is_err());
+        assert_eq!(toml::from_str::<std::collections::BTreeMap<String, EvictionPolicy>>("a = \"volatile-ttl\"").unwrap()["a"], EvictionPolicy::VolatileTtl);
+
+        let now = Instant::now();
+        let mut hot = Frequency::default();
+        for _ in 0..1000 {
+            hot.hit(now);
+        }
+        assert!(hot.decayed(now) > LFU_INIT && hot.decayed(now) < 40, "{:?}", hot);
+        let long_ago = now.checked_sub(Duration::from_secs(LFU_DECAY_SECS * 3)).unwrap();
+        assert_eq!(Frequency::default().decayed(long_ago), LFU_INIT - 3);
+
+        let map: DashMap<String, u32> = (0..100).map(|i| (format!("k{}", i), i)).collect();
+        let (key, score) = sample(&map, |v| *v).unwrap();
+        assert_eq!(map.get(&key).map(|v| *v), Some(score));
+        let single: DashMap<String, u32> = [("only".to_string(), 1)].into_iter().collect();
+        assert_eq!(sample(&single, |v| *v), Some(("only".to_string(), 1)));
+        assert!(sample(&DashMap::<String, u32>::new(), |v| *v).is_none());
+        // Every key gets sampled sooner or later
+        let mut seen = std::collections::HashSet::new();
+        for _ in 0..2000 {
+            seen.insert(sample(&map, |_| 0).unwrap().0);
+        }
+        assert!(seen.len() > 90, "{}", seen.len());
+    }
+}
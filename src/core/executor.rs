@@ -5,12 +5,58 @@
 //! underlying storage engines.
 
 use crate::core::memory::DatabaseEngine;
-use crate::query::Command;
-use crate::core::structured::{Column, DataType};
+use crate::query::{Command, ExpiryUpdate, Filter, GeoOrigin, Operator};
+use crate::query::like;
+use crate::core::locks::WriteGuard;
+use crate::core::structured::{Column, DataType, KV_TABLE, ReadView, ResultSet, VectorQuery};
+use crate::core::types::UnifiedValue;
 use crate::core::persistence::AofLogger;
-use crate::core::security::User;
+use crate::core::security::{self, User};
+use crate::core::cluster::ClusterManager;
+use crate::core::backend;
+use crate::core::digest;
+use crate::core::dump::{self, TableDump};
+use crate::core::flexible::KeyValue;
+use crate::core::geo;
 use crate::core::logger;
-use std::sync::Arc;
+use crate::core::queries;
+use crate::core::scripting::{self, Script};
+use crate::core::worker;
+use crate::core::output::{self, OutputFormat};
+use crate::net::binding::{self, ArgBinding};
+use crate::net::parser::parse_command;
+use crate::net::variables::Variables;
+use crate::net::resp::RespValue;
+use crate::core::replication::{MasterLink, ValidRole};
+use crate::core::schedule::ScheduledWrite;
+use crate::core::stream::{Fields, StreamId};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+/// Most records one `AOF CAT` returns.
+const AOF_CAT_MAX: usize = 1000;
+/// How often `WAIT` and `FAILOVER` check the replicas' acknowledgements, and
+/// a blocking pop looks for a member again.
+pub const WAIT_POLL: std::time::Duration = std::time::Duration::from_millis(10);
+/// Longest a blocking command waits; a timeout of 0 stands for this long.
+pub const MAX_WAIT: std::time::Duration = std::time::Duration::from_secs(300);
+/// Reply to a multi-key command or transaction spanning several slots.
+const CROSSSLOT: &str = "CROSSSLOT Keys in request don't hash to the same slot";
+/// Cursors a session can keep open at once.
+pub const MAX_CURSORS: usize = 16;
+/// Reply to a write that would add to a full keyspace.
+const OOM: &str = "OOM command not allowed when the keyspace is full and the eviction policy cannot evict";
+
+/// An open cursor (`DECLARE ... CURSOR`): its query and how far `FETCH` got.
+/// Each `FETCH` runs the query for its page only, so no rows are kept.
+pub struct Cursor {
+    pub query: Command,
+    pub columns: Vec<String>,
+    /// Rows fetched so far
+    pub fetched: usize,
+    /// Set once a page came back short
+    pub done: bool,
+}
 
 /// Tracks the state of an individual client connection.
 pub struct Session {
@@ -24,6 +70,186 @@ pub struct Session {
     pub current_db: String,
     /// Buffer for staged commands during an active transaction (`BEGIN`).
     pub tx_buffer: Option<Vec<Command>>,
+    /// AOF lines of the writes staged in the transaction, logged on `COMMIT`.
+    pub tx_log: Vec<String>,
+    /// Keys under `WATCH`: database, key and the write version seen.
+    pub watched: Vec<(String, String, u64)>,
+    /// Reply format for result sets (`SET output = json|text`).
+    pub output: OutputFormat,
+    /// How RESP array arguments become a command (`SET binding = strict|parsed`).
+    pub binding: ArgBinding,
+    /// User variables (`SET @name = value`), substituted into parsed commands.
+    pub variables: Variables,
+    /// Reads of the slots of this replica's master are served here instead
+    /// of redirected (`READONLY` / `READWRITE`).
+    pub readonly: bool,
+    /// The next command may use a slot being imported here (`ASKING`).
+    pub asking: bool,
+    /// Cursors opened by `DECLARE`, by name, until `CLOSE` or disconnect.
+    pub cursors: HashMap<String, Cursor>,
+    /// Set when the command that just ran has to wait; the worker pool
+    /// finishes it with [`resume`].
+    pub waiting: Option<Waiting>,
+}
+
+/// A blocking command that could not complete at once. It is retried every
+/// [`WAIT_POLL`] until it does or its deadline passes, with no worker held
+/// in between.
+#[derive(Debug)]
+pub struct Waiting {
+    deadline: std::time::Instant,
+    state: WaitState,
+}
+
+#[derive(Debug)]
+enum WaitState {
+    /// WAIT: `replicas` acknowledging the replication offset `offset`
+    Acks { replicas: usize, offset: u64 },
+    /// BZPOPMIN/BZPOPMAX: the pop, retried until a key has a member
+    Pop(Command),
+    /// XREAD BLOCK: the read, its `$` IDs pinned to the last entries there
+    /// were when it started
+    Read(Command),
+    /// FAILOVER: handing over to the replica linked as `target` (serving
+    /// clients at `target_addr`) once it acknowledged `offset`; `promoted`
+    /// once it was told to take over
+    Failover { target: String, target_addr: String, offset: u64, promoted: bool },
+    /// REPLCHECK: comparing with the replica linked as `target` (`shown` in
+    /// replies); `sent` holds this node's digests and the check's id once
+    /// the check went out
+    Check { target: String, shown: String, sent: Option<(digest::Digests, u64)> },
+}
+
+impl Waiting {
+    fn new(timeout_ms: u64, state: WaitState) -> Self {
+        let wait = match timeout_ms {
+            0 => MAX_WAIT,
+            ms => std::time::Duration::from_millis(ms).min(MAX_WAIT),
+        };
+        Self { deadline: std::time::Instant::now() + wait, state }
+    }
+}
+
+impl Session {
+    /// Session of a newly connected client: unauthenticated, default options.
+    pub fn connection(addr: &str, db: &str) -> Self {
+        Self {
+            user: None,
+            _addr: addr.to_string(),
+            connected_at: std::time::Instant::now(),
+            current_db: db.to_string(),
+            tx_buffer: None,
+            tx_log: Vec::new(),
+            watched: Vec::new(),
+            output: OutputFormat::default(),
+            binding: ArgBinding::default(),
+            variables: Variables::new(),
+            readonly: false,
+            asking: false,
+            cursors: HashMap::new(),
+            waiting: None,
+        }
+    }
+
+    /// Internal session with full permissions, used to replay persisted commands.
+    pub fn system(addr: &str, db: &str) -> Self {
+        Self {
+            user: Some(User {
+                username: "system".to_string(),
+                password: "".to_string(),
+                rules: vec!["+@all".to_string()],
+            }),
+            _addr: addr.to_string(),
+            connected_at: std::time::Instant::now(),
+            current_db: db.to_string(),
+            tx_buffer: None,
+            tx_log: Vec::new(),
+            watched: Vec::new(),
+            output: OutputFormat::Text,
+            binding: ArgBinding::Parsed,
+            variables: Variables::new(),
+            readonly: false,
+            asking: false,
+            cursors: HashMap::new(),
+            waiting: None,
+        }
+    }
+}
+
+/// Result of a command, before it is encoded for the client.
+#[derive(Debug)]
+pub enum CommandOutput {
+    /// `+OK`
+    Ok,
+    /// Other simple-string replies (`PONG`, `QUEUED`)
+    Status(String),
+    Int(i64),
+    Bulk(String),
+    /// Null bulk string: missing key, field or member
+    Nil,
+    Array(Vec<CommandOutput>),
+    /// Result set with column names, encoded per the session's output format
+    Rows(ResultSet),
+    /// Error reply, without the `ERR` prefix
+    Err(String),
+    /// PSYNC accepted: the connection becomes a replication stream
+    FullResync,
+}
+
+impl CommandOutput {
+    pub fn err(message: impl std::fmt::Display) -> Self {
+        CommandOutput::Err(message.to_string())
+    }
+
+    pub fn bulk(s: impl Into<String>) -> Self {
+        CommandOutput::Bulk(s.into())
+    }
+
+    /// Array of bulk strings.
+    pub fn strings(items: impl IntoIterator<Item = String>) -> Self {
+        CommandOutput::Array(items.into_iter().map(CommandOutput::Bulk).collect())
+    }
+
+    pub fn is_error(&self) -> bool {
+        matches!(self, CommandOutput::Err(_))
+    }
+
+    /// Renders the reply for logs and reports (`(integer) 3`, `["a", "b"]`,
+    /// `ERROR: ...`). RESP-formatted result sets fall back to text lines.
+    pub fn to_text(&self, format: OutputFormat) -> String {
+        match self {
+            CommandOutput::Ok => "OK".to_string(),
+            CommandOutput::Status(s) | CommandOutput::Bulk(s) => s.clone(),
+            CommandOutput::Int(n) => format!("(integer) {}", n),
+            CommandOutput::Nil => "nil".to_string(),
+            CommandOutput::Array(items) => {
+                format!("{:?}", items.iter().map(|item| item.to_text(format)).collect::<Vec<_>>())
+            }
+            CommandOutput::Rows(result) => match format {
+                OutputFormat::Json => output::rows_json(result),
+                OutputFormat::Text | OutputFormat::Resp => output::rows_text(result),
+            },
+            CommandOutput::Err(e) => format!("ERROR: {}", e),
+            CommandOutput::FullResync => "FULLRESYNC".to_string(),
+        }
+    }
+
+    /// Encodes the reply for the wire. Result sets are native arrays in
+    /// `resp` output mode and a single bulk string otherwise.
+    pub fn to_resp(&self, format: OutputFormat) -> RespValue {
+        match self {
+            CommandOutput::Ok => RespValue::SimpleString("OK".to_string()),
+            CommandOutput::Status(s) => RespValue::SimpleString(s.clone()),
+            CommandOutput::Int(n) => RespValue::Integer(*n),
+            CommandOutput::Bulk(s) => RespValue::BulkString(Some(s.as_bytes().to_vec())),
+            CommandOutput::Nil => RespValue::BulkString(None),
+            CommandOutput::Array(items) => RespValue::Array(Some(items.iter().map(|item| item.to_resp(format)).collect())),
+            CommandOutput::Rows(result) if format == OutputFormat::Resp => output::rows_resp(result),
+            CommandOutput::Rows(_) => RespValue::BulkString(Some(self.to_text(format).into_bytes())),
+            CommandOutput::Err(e) => RespValue::Error(e.clone()),
+            CommandOutput::FullResync => RespValue::SimpleString("FULLRESYNC".to_string()),
+        }
+    }
 }
 
 /// The primary entry point for command processing.
@@ -31,71 +257,112 @@ pub struct Session {
 /// Performs authentication checks, permission validation, sharding redirection, 
 /// and finally executes the command against the appropriate engine.
 /// 
-/// Returns a tuple of `(ResponseString, AOFCommandString)`.
-pub fn execute_command(engine: &Arc<DatabaseEngine>, cmd: Command, aof: &AofLogger, session: &mut Session) -> (String, Option<String>) {
+/// Returns a tuple of `(CommandOutput, AOFCommandString)`.
+pub fn execute_command(engine: &Arc<DatabaseEngine>, cmd: Command, aof: &AofLogger, session: &mut Session) -> (CommandOutput, Option<String>) {
     // 1. Handle AUTH (always allowed to attempt)
     if let Command::Auth { ref username, ref password } = cmd {
         let target_user = username.as_deref().unwrap_or("default");
-        if engine.security.authenticate(target_user, password) {
-            session.user = engine.security.get_user(target_user);
-            logger::info(&format!("Client {} authenticated as user '{}'", session._addr, target_user));
-            return ("OK".to_string(), None);
+        if let Some(user) = engine.security.login(target_user, password) {
+            logger::info(&format!("Client {} authenticated as user '{}'", session._addr, user.username));
+            session.user = Some(user);
+            return (CommandOutput::Ok, None);
         } else {
             logger::warn(&format!("Authentication failed for client {} as user '{}'", session._addr, target_user));
-            return ("ERROR: Invalid password".to_string(), None);
+            return (CommandOutput::err("Invalid password"), None);
         }
     }
 
     // 2. Check if authenticated
     let user = match &session.user {
         Some(u) => u,
-        None => return ("ERROR: Authentication required".to_string(), None),
+        None => return (CommandOutput::err("Authentication required"), None),
     };
 
     // 3. Check permissions
     if !user.can_execute(&cmd) {
         logger::warn(&format!("Permission denied: client {} (user '{}') attempted unauthorized command: {:?}", session._addr, user.username, cmd));
-        return (format!("ERROR: User '{}' has no permissions for this command", user.username), None);
+        return (CommandOutput::err(format!("User '{}' has no permissions for this command", user.username)), None);
     }
     
-    // 4. Check Sharding Slot Ownership
-    if let Some(key) = cmd.get_key() {
-        if !engine.cluster.owns_slot(key) {
-            let slot = crate::core::cluster::ClusterManager::key_slot(key);
-            if let Some(addr) = engine.cluster.get_redirect(key) {
-                return (format!("MOVED {} {}", slot, addr), None);
-            } else {
-                // If we don't know who owns it, return internal error or assume we should have it?
+    // 4. Check Sharding Slot Ownership (tables shard by name)
+    let keys = cmd.slot_keys();
+    if engine.cluster.in_cluster() && !ClusterManager::same_slot(keys.iter().copied()) {
+        return (CommandOutput::err(CROSSSLOT), None);
+    }
+    // ASKING covers the command right after it only
+    let asking = std::mem::take(&mut session.asking);
+    if let Some(&key) = keys.first() {
+        let slot = ClusterManager::key_slot(key);
+        // While a slot moves away, keys already moved (or new ones) are served by
+        // the target; MIGRATE leaves tables in place, so only new ones go there
+        if let Some(target) = engine.cluster.migrating_to(slot) {
+            let kv_keys = cmd.keys();
+            let present = engine.flexible.exists(&kv_keys.iter().map(|k| k.to_string()).collect::<Vec<_>>())
+                + cmd.tables().into_iter().filter(|table| engine.structured.has_table(table)).count();
+            if present == 0 {
+                return (CommandOutput::err(format!("ASK {} {}", slot, target)), None);
+            } else if present < keys.len() {
+                return (CommandOutput::err("TRYAGAIN Multiple keys request during rehashing of slot"), None);
             }
         }
+        let master = engine.replication.master();
+        let replica_reads = session.readonly && !cmd.is_write();
+        if !(asking && engine.cluster.is_importing(slot))
+            && let Some(addr) = engine.cluster.redirect(key, master.as_deref(), replica_reads)
+        {
+            return (CommandOutput::err(format!("MOVED {} {}", slot, addr)), None);
+        }
     }
 
     // 5. Check Replica Read-Only Mode
+    if cmd.is_write() && !matches!(cmd, Command::ReplicaOf { .. }) && engine.replication.is_failing_over() {
+        return (CommandOutput::err("READONLY Failover in progress, writes are paused"), None);
+    }
     if !engine.replication.is_master() {
         if cmd.is_write() {
             if let Command::ReplicaOf { .. } = cmd {
                 // Allowed
             } else {
-                 return ("ERROR: READONLY You can't write against a read only replica.".to_string(), None);
+                 return (CommandOutput::err("READONLY You can't write against a read only replica."), None);
             }
         }
     }
 
+    // 5b. Refuse growth once a noeviction keyspace is full
+    if cmd.grows_keyspace() && engine.flexible.is_full() {
+        return (CommandOutput::err(OOM), None);
+    }
+
     // 6. Transaction Handling
     match cmd {
         Command::Begin => {
             if session.tx_buffer.is_some() {
-                return ("ERROR: Transaction already started".to_string(), None);
+                return (CommandOutput::err("Transaction already started"), None);
             }
             session.tx_buffer = Some(Vec::new());
-            return ("OK".to_string(), None);
+            return (CommandOutput::Ok, None);
         }
         Command::Rollback => {
             if session.tx_buffer.is_none() {
-                return ("ERROR: No transaction active".to_string(), None);
+                return (CommandOutput::err("No transaction active"), None);
             }
             session.tx_buffer = None;
-            return ("OK".to_string(), None);
+            session.watched.clear();
+            return (CommandOutput::Ok, None);
+        }
+        Command::Watch { keys } => {
+            if session.tx_buffer.is_some() {
+                return (CommandOutput::err("WATCH inside a transaction is not allowed"), None);
+            }
+            for key in keys {
+                let version = engine.key_versions.version(&key);
+                session.watched.push((engine.db_name.clone(), key, version));
+            }
+            return (CommandOutput::Ok, None);
+        }
+        Command::Unwatch => {
+            session.watched.clear();
+            return (CommandOutput::Ok, None);
         }
         Command::Save => {
             // Can we save during transaction? 
@@ -105,171 +372,595 @@ pub fn execute_command(engine: &Arc<DatabaseEngine>, cmd: Command, aof: &AofLogg
             // But for *current* transaction buffer, it's not applied yet. So snapshot won't have it. Correct.
             
             use super::snapshot::SnapshotManager;
-            let data_dir = std::env::var("DB_DATA_DIR").unwrap_or_else(|_| "data".to_string());
+            let data_dir = crate::core::config::shared().data_dir();
             let path = format!("{}/{}_dump.json", data_dir, engine.db_name);
             
-            return match SnapshotManager::save(engine, &path) {
-                Ok(_) => ("OK Snapshot saved".to_string(), None),
-                Err(e) => (format!("ERR Snapshot failed: {}", e), None)
+            return match SnapshotManager::save(engine, aof, &path) {
+                Ok(_) => (CommandOutput::Status("OK Snapshot saved".to_string()), None),
+                Err(e) => (CommandOutput::err(format!("Snapshot failed: {}", e)), None)
             };
         }
         Command::Commit => {
             if let Some(buffer) = session.tx_buffer.take() {
-                // ATOMIC COMMIT
-                let _guard = engine.transaction_lock.lock().unwrap();
-                
-                // 1. Log BEGIN
-                // 1. Log BEGIN (Logged by worker)
-
-
-                let mut results = Vec::new();
-                for buffered_cmd in buffer {
-                     // We must log inside dispatch or here? 
-                     // dispatch_direct normally logs? 
-                     // Current implementation: dispatch_direct does NOT log automatically in the snippet provided.
-                     // The snippet provided earlier calculates results but I don't see explict aof.log() calls inside the match arms 
-                     // EXCEPT for specific commands?
-                     // Ah, I need to check the original code again. 
-                     // The original code DID NOT HAVE AOF LOGGING inside the match arms!
-                     // It seems logging was missing or implicit? 
-                     // Wait, Step 21 view_file of persistence.rs shows AofLogger but where is it called?
-                     // Ah, Step 20 executor.rs: execute_command takes `aof: &AofLogger`.
-                     // BUT I don't see `aof.log(...)` calls inside the match arms in the provided Step 20 code!
-                     // WAIT. This is a critical discovery. The previous user might have "implemented" AOF logger but not hooked it up?
-                     // Or I missed it.
-                     // Let's re-read Step 20.
-                     // Command::RewriteAof calls aof.rewrite. 
-                     // Command::Set calls engine.flexible.set. 
-                     // THERE ARE NO aof.log calls in Step 20!
-                     // The "Autopsy" (Step 14 User Request) said: "AOF + snapshots funciona...".
-                     // Maybe it was hooked up in `main.rs`? 
-                     // If main.rs calls execute and then logs?
-                     // I need to check `main.rs`.
-                     
-                     // Assuming I need to add logging now if it's missing.
-                     // For CREDIBILITY, I must ensure it logs.
-                     
-                     // Let's assume dispatch_direct executes. I should log if it was successful.
-                     // Since I am refactoring, I should add logging in dispatch_direct or the wrapper.
-                     
-                     let (res, _) = dispatch_direct(engine, buffered_cmd.clone(), session, aof); 
-                     // Note: dispatch_direct shouldn't double log if wrapper logs. 
-                     // But strictly, AOF should log the *command*, not the result.
-                     // And only if successful.
-                     
-                     // For simplicity in Phase 1:
-                     // Log command before or after? Usually after success.
-                     if !res.starts_with("ERROR") {
-                         // Reconstruct command string? `cmd` is enum. 
-                         // To log, I need serialization of Command -> String.
-                         // For now, I'll allow dispatch_direct to handle logging if it did, 
-                         // or I'll add logging to the wrapper.
-                         
-                         // Since I don't have a clean "Command to String" serializer (except Debug), 
-                         // and parsing uses specific syntax...
-                         // This is a gap. I should probably implement Display for Command or similar.
-                         // Or use Debug format for now as a fallback, assuming parser can handle it? 
-                         // No, parser needs RESP or SQL-like.
-                         // Use `format!("{:?}", cmd)` is risky if parser doesn't match Debug.
-                         
-                         // Use a temporary "log via Debug" strategy, 
-                         // but acknowledging this is a tech debt.
-                         
-                         if buffered_cmd.is_write() {
-                             let cmd_str = format!("{:?}", buffered_cmd); 
-                             let _ = aof.log(&cmd_str);
-                         }
-                     }
-                     results.push(res);
+                // ATOMIC COMMIT: the keys and tables of every staged command (and
+                // the watched keys) stay locked until it is done
+                let watched = std::mem::take(&mut session.watched);
+                let watched_here = watched.iter().filter(|(db, _, _)| *db == engine.db_name).map(|(_, key, _)| key.as_str());
+                let tables = engine.structured.dependents(buffer.iter().flat_map(Command::tables));
+                let _guard = engine.locks.lock(buffer.iter().flat_map(Command::keys).chain(watched_here), tables.iter().map(String::as_str));
+
+                // A watched key written since WATCH aborts the transaction
+                if watched.iter().any(|(db, key, version)| watched_key_changed(engine, db, key, *version)) {
+                    return (CommandOutput::Nil, None);
                 }
 
+                // All-or-nothing: the first failure puts the keys and tables the
+                // transaction touched back as they were
+                let key_savepoint = engine.flexible.savepoint(buffer.iter().flat_map(Command::keys));
+                let table_savepoint = engine.structured.savepoint(buffer.iter().filter(|c| c.is_write()).flat_map(Command::tables));
+
+                // A kill cannot stop a transaction halfway through; the worker
+                // logs the staged writes once all of them went through
+                let outcome = queries::uninterruptible(|| {
+                    let mut results = Vec::new();
+                    for (i, buffered_cmd) in buffer.iter().enumerate() {
+                        touch_keys(engine, buffered_cmd);
+                        let (res, _) = dispatch_direct(engine, buffered_cmd.clone(), session, aof);
+                        if let CommandOutput::Err(e) = &res {
+                            return Err(format!("Transaction rolled back: command {} ({}) failed: {}", i + 1, buffered_cmd.name(), e));
+                        }
+                        results.push(res);
+                    }
+                    Ok(results)
+                });
+
+                let results = match outcome {
+                    Ok(results) => {
+                        engine.structured.release(table_savepoint);
+                        results
+                    }
+                    Err(e) => {
+                        engine.flexible.rollback_to(key_savepoint);
+                        engine.structured.rollback_to(table_savepoint);
+                        return (CommandOutput::err(e), None);
+                    }
+                };
+
                 // 2. Log COMMIT
                 // 2. Log COMMIT (Logged by worker)
 
                 
-                // Return results as array? Or last result? 
-                // Redis returns Array of results. 
-                // Our protocol is simple strings. 
-                // Let's return a joined string or just count?
-                // For now: Return "OK <count>" or join lines.
-                return (format!("OK Transaction Executed. Results: {:?}", results), None);
+                // One reply per queued command, like Redis' EXEC
+                return (CommandOutput::Array(results), None);
             } else {
-                return ("ERROR: No transaction active".to_string(), None);
+                return (CommandOutput::err("No transaction active"), None);
             }
         }
         _ => {
              // Buffering
-             if session.tx_buffer.is_some() {
+             if let Some(buffer) = &session.tx_buffer {
+                 // Only writes to keys and tables can be undone if COMMIT fails
+                 // and a script's writes are only known once it ran; a FAILOVER
+                 // or REPLCHECK has to wait for its replica, which a transaction cannot
+                 if (cmd.is_write() && cmd.slot_keys().is_empty()) || matches!(cmd, Command::Eval { .. } | Command::EvalSha { .. } | Command::Failover { .. } | Command::ReplCheck { .. }) {
+                     return (CommandOutput::err(format!("{} cannot run inside a transaction", cmd.name())), None);
+                 }
+                 // In a cluster the whole transaction must run on one node
+                 if engine.cluster.in_cluster() && !ClusterManager::same_slot(buffer.iter().flat_map(Command::slot_keys).chain(cmd.slot_keys())) {
+                     return (CommandOutput::err(CROSSSLOT), None);
+                 }
                  session.tx_buffer.as_mut().unwrap().push(cmd);
-                 return ("QUEUED".to_string(), None);
+                 return (CommandOutput::Status("QUEUED".to_string()), None);
              }
         }
     }
 
     // Normal Execution (Auto-Commit)
-    if cmd.is_write() {
-        let _guard = engine.transaction_lock.lock().unwrap();
-        let (res, redirect) = dispatch_direct(engine, cmd.clone(), session, aof);
-        
+    dispatch(engine, cmd, session, aof)
+}
 
-        (res, redirect)
+fn dispatch(engine: &Arc<DatabaseEngine>, cmd: Command, session: &mut Session, aof: &AofLogger) -> (CommandOutput, Option<String>) {
+    // A command that has to wait makes one attempt now; the worker pool
+    // retries it without holding a worker
+    if let Some(mut waiting) = begin_wait(engine, &cmd) {
+        return match attempt(engine, &mut waiting, session, aof, false) {
+            Some(done) => done,
+            None => {
+                session.waiting = Some(waiting);
+                (CommandOutput::Nil, None)
+            }
+        };
+    }
+    // Incrementing a striped counter that exists takes no key lock: that is
+    // its point. Creating one does, as it checks the key is free.
+    if let Command::CIncr { key, by } = &cmd && let Some(total) = engine.flexible.cincr_existing(key, *by) {
+        touch_keys(engine, &cmd);
+        return (CommandOutput::Int(total), None);
+    }
+    if cmd.needs_write_lock() {
+        let _guard = lock_write(engine, &cmd);
+        touch_keys(engine, &cmd);
+        dispatch_direct(engine, cmd, session, aof)
     } else {
         dispatch_direct(engine, cmd, session, aof)
     }
 }
 
-fn dispatch_direct(engine: &Arc<DatabaseEngine>, cmd: Command, session: &mut Session, aof: &AofLogger) -> (String, Option<String>) {
+/// The wait of a blocking command, `None` for any other command or one
+/// that fails right away.
+fn begin_wait(engine: &Arc<DatabaseEngine>, cmd: &Command) -> Option<Waiting> {
+    match *cmd {
+        Command::Wait { replicas, timeout_ms } if engine.replication.is_master() => {
+            // Every write already answered to this client is at or before the current offset
+            let offset = engine.replication.offset();
+            engine.replication.request_acks();
+            Some(Waiting::new(timeout_ms, WaitState::Acks { replicas, offset }))
+        }
+        Command::BZPop { timeout_ms, .. } => Some(Waiting::new(timeout_ms, WaitState::Pop(cmd.clone()))),
+        Command::XRead { count, block_ms: Some(block_ms), ref keys, ref ids } => {
+            let ids = keys.iter().zip(ids).map(|(key, id)| match id.as_str() {
+                "$" => engine.flexible.stream_last_id(key).to_string(),
+                _ => id.clone(),
+            }).collect();
+            let read = Command::XRead { count, block_ms: Some(block_ms), keys: keys.clone(), ids };
+            Some(Waiting::new(block_ms, WaitState::Read(read)))
+        }
+        Command::Failover { timeout_ms } if engine.replication.is_master() => {
+            let (target, target_addr) = engine.replication.failover_target()?;
+            // Writes stop until the replica has them all, then it takes over
+            // and points this node at itself
+            engine.replication.set_failing_over(true);
+            let offset = engine.replication.offset();
+            engine.replication.request_acks();
+            Some(Waiting::new(timeout_ms, WaitState::Failover { target, target_addr, offset, promoted: false }))
+        }
+        Command::ReplCheck { ref replica, timeout_ms } if engine.replication.is_master() => {
+            let target = engine.replication.find_replica(replica.as_deref())?;
+            let shown = replica.clone().unwrap_or_else(|| target.clone());
+            Some(Waiting::new(timeout_ms, WaitState::Check { target, shown, sent: None }))
+        }
+        _ => None,
+    }
+}
+
+/// Checks once whether a waiting command can complete, and returns its
+/// reply if it did. With `last`, the wait is over either way: the command
+/// replies with what it has.
+fn attempt(engine: &Arc<DatabaseEngine>, waiting: &mut Waiting, session: &mut Session, aof: &AofLogger, last: bool) -> Option<(CommandOutput, Option<String>)> {
+    match &mut waiting.state {
+        WaitState::Acks { replicas, offset } => {
+            let acked = engine.replication.acked_replicas(*offset);
+            (acked >= *replicas || last).then_some((CommandOutput::Int(acked as i64), None))
+        }
+        // The lock is taken for each attempt only, so the writes a pop
+        // waits for get through
+        WaitState::Pop(cmd) => {
+            let _guard = lock_write(engine, cmd);
+            let popped = dispatch_direct(engine, cmd.clone(), session, aof);
+            let done = !matches!(popped.0, CommandOutput::Nil);
+            if done {
+                touch_keys(engine, cmd);
+            }
+            (done || last).then_some(popped)
+        }
+        // A read changes nothing, so it takes no lock
+        WaitState::Read(cmd) => {
+            let read = dispatch_direct(engine, cmd.clone(), session, aof);
+            (!matches!(read.0, CommandOutput::Nil) || last).then_some(read)
+        }
+        WaitState::Failover { target, target_addr, offset, promoted } => {
+            if !engine.replication.is_master() {
+                logger::info(&format!("Failover: handed over to {}", target_addr));
+                return Some((CommandOutput::Ok, None));
+            }
+            let mut failed = last;
+            if !failed && !*promoted && engine.replication.acked_offset(target).is_some_and(|acked| acked >= *offset) {
+                *promoted = engine.replication.send_promote(target);
+                failed = !*promoted;
+            }
+            if !failed {
+                return None;
+            }
+            engine.replication.set_failing_over(false);
+            let reason = if *promoted { "did not take over" } else { "did not catch up" };
+            Some((CommandOutput::err(format!("FAILOVER timed out: replica {} {}", target_addr, reason)), None))
+        }
+        WaitState::Check { target, shown, sent } => {
+            // The digests are taken between writes: with every write so far
+            // forwarded, the check reaches the replica right after them. The
+            // lock is only taken once there is such a moment
+            if sent.is_none() && engine.replication.unforwarded() == 0 {
+                let _guard = engine.locks.lock_all();
+                if engine.replication.unforwarded() == 0 {
+                    let master = digest::digests(engine);
+                    let Some(id) = engine.replication.send_check(target, &engine.db_name) else {
+                        return Some((CommandOutput::err(format!("REPLCHECK: replica {} is gone", shown)), None));
+                    };
+                    *sent = Some((master, id));
+                }
+            }
+            let Some((master, id)) = sent else {
+                return last.then(|| (CommandOutput::err("REPLCHECK timed out: writes did not pause"), None));
+            };
+            let Some(digests) = engine.replication.check_result(target, *id) else {
+                return last.then(|| (CommandOutput::err(format!("REPLCHECK timed out: replica {} did not answer", shown)), None));
+            };
+            let differences = digest::compare(master, &digests);
+            if differences.is_empty() {
+                return Some((CommandOutput::Ok, None));
+            }
+            Some((CommandOutput::Bulk(format!("{} differences with replica {}\n{}", differences.len(), shown, differences.join("\n"))), None))
+        }
+    }
+}
+
+/// Retries a waiting command ([`Session::waiting`]). Returns its reply once
+/// it completed, its deadline passed, or it was `killed`.
+pub fn resume(engine: &Arc<DatabaseEngine>, waiting: &mut Waiting, session: &mut Session, aof: &AofLogger, killed: bool) -> Option<(CommandOutput, Option<String>)> {
+    let last = killed || std::time::Instant::now() >= waiting.deadline;
+    attempt(engine, waiting, session, aof, last)
+}
+
+/// Locks what a write touches: its keys, or its tables along with the
+/// tables whose rows its foreign key actions can change. A write naming
+/// neither, an AOF rewrite or an export locks everything.
+fn lock_write<'a>(engine: &'a DatabaseEngine, cmd: &Command) -> WriteGuard<'a> {
+    if cmd.slot_keys().is_empty() || matches!(cmd, Command::RewriteAof | Command::ExportKeys { .. }) {
+        return engine.locks.lock_all();
+    }
+    let tables = engine.structured.dependents(cmd.tables());
+    engine.locks.lock(cmd.keys(), tables.iter().map(String::as_str))
+}
+
+/// Stream entries as `[[id, [field, value, ...]], ...]`.
+fn stream_entries(entries: Vec<(StreamId, Fields)>) -> CommandOutput {
+    CommandOutput::Array(entries.into_iter().map(|(id, fields)| {
+        let pairs = CommandOutput::strings(fields.into_iter().flat_map(|(field, value)| [field, value]));
+        CommandOutput::Array(vec![CommandOutput::Bulk(id.to_string()), pairs])
+    }).collect())
+}
+
+/// Records the keys a write names as written, for `WATCH`; migrated keys
+/// are not known in advance, so `MIGRATE` counts as a write to every key.
+fn touch_keys(engine: &DatabaseEngine, cmd: &Command) {
     match cmd {
+        Command::Migrate { .. } => engine.key_versions.touch_all(),
+        _ if cmd.is_write() => cmd.keys().into_iter().for_each(|key| engine.key_versions.touch(key)),
+        _ => {}
+    }
+}
+
+/// True if a key watched at `version` was written since, or its database
+/// is gone.
+fn watched_key_changed(engine: &Arc<DatabaseEngine>, db: &str, key: &str, version: u64) -> bool {
+    if db == engine.db_name {
+        return engine.key_versions.version(key) != version;
+    }
+    engine.registry.upgrade()
+        .and_then(|registry| registry.get(db))
+        .is_none_or(|(other, _)| other.key_versions.version(key) != version)
+}
+
+/// Applies a command of the master's replication stream on a replica,
+/// bypassing the read-only check, and logs a successful write to the local
+/// AOF as the master sent it.
+pub fn apply_replicated(engine: &Arc<DatabaseEngine>, cmd: Command, cmd_str: &str, aof: &AofLogger, session: &mut Session) -> CommandOutput {
+    let is_logged = cmd.is_logged();
+    let (res, _) = dispatch(engine, cmd, session, aof);
+    if is_logged && !res.is_error() && let Err(e) = aof.log(cmd_str) {
+        logger::error(&format!("AOF Error: {}", e));
+    }
+    res
+}
+
+/// Runs a command; one whose value the backend could not store fails, so it
+/// is neither acknowledged nor logged.
+fn dispatch_direct(engine: &Arc<DatabaseEngine>, cmd: Command, session: &mut Session, aof: &AofLogger) -> (CommandOutput, Option<String>) {
+    backend::take_write_error();
+    let res = dispatch_command(engine, cmd, session, aof);
+    match backend::take_write_error() {
+        Some(e) => (CommandOutput::err(e), None),
+        None => res,
+    }
+}
+
+fn dispatch_command(engine: &Arc<DatabaseEngine>, cmd: Command, session: &mut Session, aof: &AofLogger) -> (CommandOutput, Option<String>) {
+    match cmd {
+        Command::Select { table, alias, selector, join, filter, group_by, having, order_by, limit, offset } => {
+            let store = select_store(engine, session, &table, join.as_deref(), filter.as_ref());
+            match store.select(&table, alias.as_deref(), selector, join, filter, group_by, having, order_by, limit, offset) {
+                Ok(result) => (CommandOutput::Rows(result), None),
+                Err(e) => (CommandOutput::err(e), None),
+            }
+        }
+        Command::ShowIndexes { table } => {
+            match engine.structured.list_indexes(table.as_deref()) {
+                Ok(indexes) => {
+                    let rows = indexes.into_iter()
+                        .map(|index| vec![
+                            UnifiedValue::String(index.table),
+                            UnifiedValue::String(index.name),
+                            UnifiedValue::String(index.column),
+                            index.constraint.map(|c| UnifiedValue::String(c.to_string())).unwrap_or(UnifiedValue::Null),
+                            UnifiedValue::String(match index.vector {
+                                Some((dims, metric)) => format!("hnsw dims={} metric={}", dims, metric),
+                                None => "btree".to_string(),
+                            }),
+                        ])
+                        .collect();
+                    let columns = ["table", "index", "column", "constraint", "using"].map(String::from).to_vec();
+                    (CommandOutput::Rows(ResultSet { columns, rows }), None)
+                }
+                Err(e) => (CommandOutput::err(e), None),
+            }
+        }
+        Command::ShowVariables => {
+            let rows = session.variables.iter()
+                .map(|(name, value)| vec![
+                    UnifiedValue::String(format!("@{}", name)),
+                    value.clone(),
+                    UnifiedValue::String(value.type_name().to_string()),
+                ])
+                .collect();
+            let columns = ["variable", "value", "type"].map(String::from).to_vec();
+            (CommandOutput::Rows(ResultSet { columns, rows }), None)
+        }
+        Command::ShowTables => {
+            let rows = engine.structured.list_tables().into_iter()
+                .map(|table| vec![
+                    UnifiedValue::String(table.name),
+                    UnifiedValue::Integer(table.columns as i64),
+                    UnifiedValue::Integer(table.rows as i64),
+                ])
+                .collect();
+            let columns = ["table", "columns", "rows"].map(String::from).to_vec();
+            (CommandOutput::Rows(ResultSet { columns, rows }), None)
+        }
+        Command::ShowDatabases => {
+            let registry = engine.registry.upgrade();
+            let names = registry.as_ref().map_or_else(|| vec![engine.db_name.clone()], |r| r.names());
+            // Databases not opened since the start are listed without counts
+            let rows = names.into_iter()
+                .map(|name| {
+                    let open = match &registry {
+                        Some(registry) => registry.get(&name).map(|(engine, _)| engine),
+                        None => Some(engine.clone()),
+                    };
+                    let count = |f: fn(&DatabaseEngine) -> usize| open.as_deref().map_or(UnifiedValue::Null, |db| UnifiedValue::Integer(f(db) as i64));
+                    vec![UnifiedValue::String(name), count(|db| db.flexible.key_count()), count(|db| db.structured.table_names().len())]
+                })
+                .collect();
+            let columns = ["database", "keys", "tables"].map(String::from).to_vec();
+            (CommandOutput::Rows(ResultSet { columns, rows }), None)
+        }
+        Command::DropDatabase { name } => {
+            if name == session.current_db {
+                return (CommandOutput::err("Cannot drop the database in use; USE another one first"), None);
+            }
+            let Some(registry) = engine.registry.upgrade() else {
+                return (CommandOutput::err("DROP DATABASE needs a server"), None);
+            };
+            match registry.drop_database(&name) {
+                Ok(true) => (CommandOutput::Ok, None),
+                Ok(false) => (CommandOutput::err(format!("Database '{}' does not exist", name)), None),
+                Err(e) => (CommandOutput::err(e), None),
+            }
+        }
+        Command::Describe { table } => {
+            match engine.structured.describe_table(&table) {
+                Ok(cols) => {
+                    let text = |s: Option<String>| s.map_or(UnifiedValue::Null, UnifiedValue::String);
+                    let rows = cols.into_iter()
+                        .map(|col| vec![
+                            UnifiedValue::String(col.name),
+                            UnifiedValue::String(col.data_type.keyword()),
+                            UnifiedValue::Boolean(col.is_primary_key),
+                            text(col.references.map(|(t, c)| match col.fk_action.keyword() {
+                                Some(action) => format!("{}.{} {}", t, c, action),
+                                None => format!("{}.{}", t, c),
+                            })),
+                            UnifiedValue::Boolean(col.not_null),
+                            UnifiedValue::Boolean(col.unique),
+                            text(col.default),
+                        ])
+                        .collect();
+                    let columns = ["column", "type", "primary_key", "references", "not_null", "unique", "default"]
+                        .map(String::from).to_vec();
+                    (CommandOutput::Rows(ResultSet { columns, rows }), None)
+                }
+                Err(e) => (CommandOutput::err(e), None),
+            }
+        }
         Command::ReplicaOf { host, port } => {
             if host.to_uppercase() == "NO" && port.to_uppercase() == "ONE" {
                 engine.replication.set_master();
-                ("OK".to_string(), None)
+                (CommandOutput::Ok, None)
+            } else if host.eq_ignore_ascii_case("PROMOTE") && port.is_empty() {
+                match crate::core::replication::promote(engine) {
+                    Ok(()) => (CommandOutput::Ok, None),
+                    Err(e) => (CommandOutput::err(e), None),
+                }
             } else if host.starts_with("db://") {
                 match crate::core::uri::ConnectionUri::parse(&host) {
                     Ok(uri) => {
                          engine.replication.set_replica_of(uri.host.clone(), uri.port);
-                         crate::core::replication::start_replication_task(engine.clone(), aof.clone().into(), uri.host, uri.port);
-                        ("OK".to_string(), Some("_CONNECT_TO_MASTER".to_string()))
+                         let link = MasterLink { host: uri.host, port: uri.port, username: uri.username, password: uri.password };
+                         crate::core::replication::start_replication_task(engine.clone(), aof.clone().into(), link);
+                        (CommandOutput::Ok, Some("_CONNECT_TO_MASTER".to_string()))
                     }
-                    Err(e) => (format!("ERROR: Invalid URI: {}", e), None)
+                    Err(e) => (CommandOutput::err(format!("Invalid URI: {}", e)), None)
                 }
             } else {
                 if let Ok(p) = port.parse::<u16>() {
                     engine.replication.set_replica_of(host.clone(), p);
-                    crate::core::replication::start_replication_task(engine.clone(), aof.clone().into(), host.clone(), p);
-                     ("OK".to_string(), Some("_CONNECT_TO_MASTER".to_string()))
+                    let link = MasterLink { host, port: p, username: None, password: None };
+                    crate::core::replication::start_replication_task(engine.clone(), aof.clone().into(), link);
+                     (CommandOutput::Ok, Some("_CONNECT_TO_MASTER".to_string()))
                 } else {
-                     ("ERROR: Invalid port".to_string(), None)
+                     (CommandOutput::err("Invalid port"), None)
                 }
             }
         }
         Command::Psync => {
-            ("_PSYNC_OK".to_string(), None)
+            (CommandOutput::FullResync, None)
+        }
+        // Inside a transaction WAIT does not wait: it counts the replicas
+        // that have everything so far
+        Command::Wait { .. } => {
+            if !engine.replication.is_master() {
+                return (CommandOutput::err("WAIT cannot be used with replica instances"), None);
+            }
+            let acked = engine.replication.acked_replicas(engine.replication.offset());
+            (CommandOutput::Int(acked as i64), None)
+        }
+        // Reached only when FAILOVER cannot start; otherwise it waits (see `begin_wait`)
+        Command::Failover { .. } => {
+            if !engine.replication.is_master() {
+                return (CommandOutput::err("FAILOVER must be run on a master; promote a replica with REPLICAOF PROMOTE"), None);
+            }
+            (CommandOutput::err("FAILOVER requires a connected replica"), None)
+        }
+        // Reached only when REPLCHECK cannot start; otherwise it waits (see `begin_wait`)
+        Command::ReplCheck { replica, .. } => {
+            if !engine.replication.is_master() {
+                return (CommandOutput::err("REPLCHECK must be run on a master"), None);
+            }
+            (CommandOutput::err(match &replica {
+                Some(name) => format!("REPLCHECK: no replica {}", name),
+                None if engine.replication.replicas.is_empty() => "REPLCHECK requires a connected replica".to_string(),
+                None => "REPLCHECK: several replicas are connected, name one".to_string(),
+            }), None)
+        }
+        Command::Ping => (CommandOutput::Status("PONG".to_string()), None),
+        // Answered by the connection handler, which also closes the socket (QUIT)
+        // or starts the session over (RESET)
+        Command::Quit => (CommandOutput::Ok, None),
+        Command::Reset => (CommandOutput::Status("RESET".to_string()), None),
+        Command::ReadOnly => {
+            session.readonly = true;
+            (CommandOutput::Ok, None)
+        }
+        Command::ReadWrite => {
+            session.readonly = false;
+            (CommandOutput::Ok, None)
+        }
+        Command::Asking => {
+            session.asking = true;
+            (CommandOutput::Ok, None)
+        }
+        Command::ObjectHelp => {
+            let help = [
+                "OBJECT <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+                "HELP",
+                "    Print this help.",
+            ];
+            (CommandOutput::strings(help.map(String::from)), None)
         }
-        Command::Ping => ("PONG".to_string(), None),
 
         Command::RewriteAof => {
-            let cmds = engine.generate_rewrite_commands();
-            match aof.rewrite(cmds) {
-                 Ok(_) => ("OK".to_string(), None),
+            match aof.rewrite(engine.dump_commands()) {
+                 Ok(_) => (CommandOutput::Ok, None),
                  Err(e) => {
                     logger::error(&format!("AOF Rewrite failed: {}", e));
-                    (format!("ERROR: AOF Rewrite failed: {}", e), None)
+                    (CommandOutput::err(format!("AOF Rewrite failed: {}", e)), None)
                  },
             }
         }
+        Command::BackupVerify { path } => {
+            let path = match engine.config.data_path(&path) {
+                Ok(path) => path,
+                Err(e) => return (CommandOutput::err(e), None),
+            };
+            match crate::core::backup::verify(&path.to_string_lossy()) {
+                Ok(report) => (CommandOutput::bulk(report.to_string().trim_end()), None),
+                Err(e) => (CommandOutput::err(e), None),
+            }
+        }
+        Command::AofStats => match crate::core::persistence::AofStats::scan(aof.path()) {
+            Ok(stats) => (CommandOutput::bulk(stats.report(aof.path())), None),
+            Err(e) => (CommandOutput::err(format!("Cannot read AOF {}: {}", aof.path(), e)), None),
+        },
+        Command::AofCat { from, count } => {
+            match crate::core::persistence::page(aof.path(), from, count.min(AOF_CAT_MAX)) {
+                Ok(records) => {
+                    let rows = records.into_iter()
+                        .map(|record| vec![
+                            UnifiedValue::Integer(record.line as i64),
+                            record.logged_at_ms.map_or(UnifiedValue::Null, |ms| UnifiedValue::Integer(ms as i64)),
+                            UnifiedValue::String(record.command),
+                        ])
+                        .collect();
+                    let columns = ["line", "logged_at_ms", "command"].map(String::from).to_vec();
+                    (CommandOutput::Rows(ResultSet { columns, rows }), None)
+                }
+                Err(e) => (CommandOutput::err(format!("Cannot read AOF {}: {}", aof.path(), e)), None),
+            }
+        }
         Command::Info => {
-            let role = engine.replication.get_role_string();
             let clients = engine.clients.len();
-            let max_clients = engine.max_connections;
-            let info = format!(
-                "# Server\r\nversion:0.1.0\r\n\r\n# Clients\r\nconnected_clients:{}\r\nmax_clients:{}\r\n\r\n# Replication\r\n{}\r\nconnected_replicas:{}\r\n",
-                clients, max_clients, role, engine.replication.replicas.len()
+            let max_clients = engine.config.max_connections();
+            let mut info = format!(
+                "# Server\r\nversion:0.1.0\r\n\r\n# Clients\r\nconnected_clients:{}\r\nmax_clients:{}\r\n\r\n# Replication\r\n{}\r\n",
+                clients, max_clients, engine.replication.info()
             );
-            (info, None)
+            info.push_str("\r\n");
+            info.push_str(&crate::net::resp::PROTOCOL_STATS.info());
+            info.push_str(&crate::core::metrics::METRICS.info());
+            info.push_str("\r\n");
+            info.push_str(&memory_info(engine));
+            info.push_str("\r\n");
+            info.push_str(&engine.flexible.schedule_info());
+            info.push_str("\r\n");
+            info.push_str(&aof.info());
+            if let Some(storage) = engine.flexible.backend_info() {
+                info.push_str("\r\n");
+                info.push_str(&storage);
+            }
+            (CommandOutput::Bulk(info), None)
+        }
+        Command::Metrics => (CommandOutput::Rows(crate::core::metrics::METRICS.table()), None),
+        Command::MetricsReset => {
+            crate::core::metrics::METRICS.reset();
+            (CommandOutput::Ok, None)
+        }
+        Command::MemoryUsage { key } => match engine.flexible.memory_usage(&key) {
+            Some(bytes) => (CommandOutput::Int(bytes as i64), None),
+            None => (CommandOutput::Nil, None),
+        },
+        Command::MemoryStats => {
+            let mut rows: Vec<Vec<UnifiedValue>> = engine.flexible.key_counts().into_iter()
+                .zip(engine.flexible.memory_bytes())
+                .map(|((store, keys), (_, bytes))| vec![
+                    UnifiedValue::String(store.to_string()),
+                    UnifiedValue::Null,
+                    UnifiedValue::Integer(keys as i64),
+                    UnifiedValue::Integer(bytes as i64),
+                ])
+                .collect();
+            for table in engine.structured.list_tables() {
+                let bytes = engine.structured.table_memory_bytes(&table.name).unwrap_or(0);
+                rows.push(vec![
+                    UnifiedValue::String("table".to_string()),
+                    UnifiedValue::String(table.name),
+                    UnifiedValue::Integer(table.rows as i64),
+                    UnifiedValue::Integer(bytes as i64),
+                ]);
+            }
+            let columns = ["store", "name", "entries", "bytes"].map(String::from).to_vec();
+            (CommandOutput::Rows(ResultSet { columns, rows }), None)
+        }
+        Command::ConfigGet { pattern } => {
+            let pairs: Vec<String> = engine.config.get(&pattern).into_iter().flat_map(|(name, value)| [name, value]).collect();
+            (CommandOutput::strings(pairs), None)
+        }
+        Command::ConfigSet { param, value } => {
+            match engine.config.set(&param, &value) {
+                Ok(()) => (CommandOutput::Ok, None),
+                Err(e) => (CommandOutput::err(e), None),
+            }
         }
         Command::ClusterInfo => {
-            (engine.cluster.get_info(), None)
+            (CommandOutput::Bulk(engine.cluster.get_info()), None)
         }
         Command::ClusterSlots => {
             let mut result = String::new();
@@ -281,87 +972,239 @@ fn dispatch_direct(engine: &Arc<DatabaseEngine>, cmd: Command, session: &mut Ses
             if result.is_empty() {
                 result = "0-16383 127.0.0.1:8569 (standalone)\n".to_string();
             }
-            (result, None)
+            (CommandOutput::Bulk(result), None)
         }
         Command::ClusterMeet { host, port } => {
             let addr = format!("{}:{}", host, port);
             engine.cluster.add_node(addr);
-            ("OK".to_string(), None)
+            (CommandOutput::Ok, None)
         }
         Command::ClusterAddSlots { slots } => {
             engine.cluster.add_slots(slots);
-            ("OK".to_string(), None)
+            (CommandOutput::Ok, None)
+        }
+        Command::ClusterDelSlots { slots } => {
+            let removed = engine.cluster.del_slots(&slots);
+            (CommandOutput::Int(removed as i64), None)
+        }
+        Command::ClusterForget { node } => {
+            match engine.cluster.forget_node(&node) {
+                Ok(_) => (CommandOutput::Ok, None),
+                Err(e) => (CommandOutput::err(e), None),
+            }
+        }
+        Command::ClusterSetSlot { slot, state } => {
+            match engine.cluster.set_slot(slot, state) {
+                Ok(()) => (CommandOutput::Ok, None),
+                Err(e) => (CommandOutput::err(e), None),
+            }
+        }
+        // Moves up to `count` keys of a migrating slot to the node importing it;
+        // the keys are deleted here once the target stored them all. Reports
+        // the moved keys, which the worker logs as their deletion
+        Command::Migrate { host, port, slot, count, auth } => {
+            let target = format!("{}:{}", host, port);
+            if engine.cluster.migrating_to(slot).is_none() {
+                return (CommandOutput::err(format!("Slot {} is not migrating (CLUSTER SETSLOT {} MIGRATING {})", slot, slot, target)), None);
+            }
+            let keys: Vec<(String, Vec<Vec<String>>)> = engine.flexible
+                .key_commands(|key| ClusterManager::key_slot(key) == slot)
+                .take(count.unwrap_or(usize::MAX))
+                .collect();
+            if keys.is_empty() {
+                return (CommandOutput::Int(0), None);
+            }
+            let words = |words: &[&str]| words.iter().map(|w| w.to_string()).collect::<Vec<String>>();
+            let mut commands = Vec::new();
+            if let Some((username, password)) = &auth {
+                commands.push(words(&["AUTH", username, password]));
+            }
+            commands.push(words(&["USE", &session.current_db]));
+            commands.push(words(&["SET", "binding", "=", "strict"]));
+            for cmd in keys.iter().flat_map(|(_, cmds)| cmds) {
+                commands.push(words(&["ASKING"]));
+                commands.push(cmd.clone());
+            }
+            if let Err(e) = crate::core::cluster::send_commands(&target, &commands) {
+                return (CommandOutput::err(format!("IOERR Migration to {} failed: {}", target, e)), None);
+            }
+            let names: Vec<String> = keys.into_iter().map(|(key, _)| key).collect();
+            engine.flexible.del(&names);
+            logger::info(&format!("Migrated {} keys of slot {} to {}", names.len(), slot, target));
+            let migrated = names.len() as i64;
+            (CommandOutput::Int(migrated), Some(Command::Del { keys: names }.to_string()))
+        }
+        Command::ClusterFailover => {
+            let master = match &*engine.replication.role.read().unwrap() {
+                ValidRole::Replica { master_addr, master_port } => format!("{}:{}", master_addr, master_port),
+                ValidRole::Master => return (CommandOutput::err("CLUSTER FAILOVER must be run on a replica"), None),
+            };
+            match engine.cluster.failover(&master) {
+                Ok(slots) => {
+                    // Stop following the old master and start accepting writes
+                    engine.replication.set_master();
+                    logger::info(&format!("Failover: took over {} slots from {}", slots, master));
+                    (CommandOutput::Ok, None)
+                }
+                Err(e) => (CommandOutput::err(e), None),
+            }
+        }
+        Command::SetOutput { format } => {
+            session.output = format;
+            (CommandOutput::Ok, None)
+        }
+        Command::SetBinding { mode } => {
+            session.binding = mode;
+            (CommandOutput::Ok, None)
+        }
+        Command::SetVariable { name, value } => {
+            session.variables.insert(name, value);
+            (CommandOutput::Ok, None)
         }
         Command::Use { db_name } => {
             if session.current_db != db_name {
                 logger::info(&format!("Client {} switched to database: {}", session._addr, db_name));
                 session.current_db = db_name;
             }
-            ("OK".to_string(), None)
+            (CommandOutput::Ok, None)
         }
         Command::AclSetUser { username, password, rules } => {
+            if let Some(rule) = security::unknown_category(&rules) {
+                return (CommandOutput::err(format!("Unknown ACL category in rule '{}'", rule)), None);
+            }
             let hash = engine.security.set_user(User { username, password, rules });
-            ("OK".to_string(), Some(hash))
+            (CommandOutput::Ok, Some(hash))
         }
         Command::AclGetUser { username } => {
             (match engine.security.get_user(&username) {
-                Some(u) => format!("username: {}\nrules: {:?}", u.username, u.rules),
-                None => "ERROR: User not found".to_string(),
+                Some(u) => CommandOutput::Array(vec![
+                    CommandOutput::bulk("username"),
+                    CommandOutput::Bulk(u.username),
+                    CommandOutput::bulk("rules"),
+                    CommandOutput::strings(u.rules),
+                ]),
+                None => CommandOutput::err("User not found"),
             }, None)
         }
         Command::AclList => {
-            (format!("{:?}", engine.security.list_users()), None)
+            (CommandOutput::strings(engine.security.list_users()), None)
         }
         Command::AclDelUser { username } => {
             engine.security.delete_user(&username);
-            ("OK".to_string(), None)
+            (CommandOutput::Ok, None)
+        }
+        Command::AclUsage { username } => {
+            let report = |name: String| {
+                let usage = engine.security.usage(&name);
+                let user = engine.security.get_user(&name);
+                let quota = |kind| user.as_ref().and_then(|u| u.quota(kind)).map_or(CommandOutput::Nil, |q| CommandOutput::Int(q as i64));
+                CommandOutput::Array(vec![
+                    CommandOutput::bulk("username"), CommandOutput::Bulk(name),
+                    CommandOutput::bulk("commands"), CommandOutput::Int(usage.commands as i64),
+                    CommandOutput::bulk("bytes"), CommandOutput::Int(usage.bytes as i64),
+                    CommandOutput::bulk("quota_cmds"), quota("cmds"),
+                    CommandOutput::bulk("quota_bytes"), quota("bytes"),
+                ])
+            };
+            (match username {
+                Some(name) if engine.security.get_user(&name).is_none() => CommandOutput::err("User not found"),
+                Some(name) => report(name),
+                None => {
+                    let mut names = engine.security.list_users();
+                    names.sort();
+                    CommandOutput::Array(names.into_iter().map(report).collect())
+                }
+            }, None)
         }
         Command::Set { key, value } => {
             let json_val = serde_json::from_str(&value).unwrap_or(serde_json::Value::String(value));
             engine.flexible.set(key, json_val);
-            ("OK".to_string(), None)
+            (CommandOutput::Ok, None)
         }
         Command::Get { key } => {
             (match engine.flexible.get(&key) {
                 Some(val) => {
-                    if let Some(s) = val.as_str() { s.to_string() } else { format!("{}", val) }
+                    CommandOutput::Bulk(if let Some(s) = val.as_str() { s.to_string() } else { format!("{}", val) })
                 }
-                None => "nil".to_string(),
+                None => CommandOutput::Nil,
             }, None)
         }
+        Command::GetRange { key, start, end } => {
+            let value = engine.flexible.get(&key)
+                .map(|val| if let Some(s) = val.as_str() { s.to_string() } else { format!("{}", val) })
+                .unwrap_or_default();
+            // Byte offsets, negative ones counted from the end, both ends inclusive.
+            // An offset inside a multi-byte character takes in the whole character.
+            let len = value.len() as i64;
+            let from = if start < 0 { (len + start).max(0) } else { start };
+            let to = if end < 0 { len + end } else { end.min(len - 1) };
+            if from > to || from >= len {
+                return (CommandOutput::bulk(""), None);
+            }
+            let range = value.floor_char_boundary(from as usize)..value.ceil_char_boundary(to as usize + 1);
+            (CommandOutput::Bulk(value[range].to_string()), None)
+        }
+        Command::GetEx { key, expiry } => {
+            let Some(val) = engine.flexible.get(&key) else {
+                return (CommandOutput::Nil, None);
+            };
+            let reply = CommandOutput::Bulk(if let Some(s) = val.as_str() { s.to_string() } else { format!("{}", val) });
+            match expiry {
+                // Reported like EXPIRE's, so the worker logs the absolute expiry
+                Some(ExpiryUpdate::After(ms)) => {
+                    engine.flexible.pexpire(&key, ms);
+                    (reply, engine.flexible.expire_time_ms(&key).map(|ms| ms.to_string()))
+                }
+                Some(ExpiryUpdate::At(ms)) => {
+                    engine.flexible.expire_at(&key, ms);
+                    (reply, None)
+                }
+                Some(ExpiryUpdate::Persist) => {
+                    engine.flexible.persist(&key);
+                    (reply, None)
+                }
+                None => (reply, None),
+            }
+        }
         Command::LPush { key, values } => {
             let len = engine.flexible.lpush(&key, values);
-            (format!("(integer) {}", len), None)
+            (CommandOutput::Int(len as i64), None)
         }
         Command::RPush { key, values } => {
             let len = engine.flexible.rpush(&key, values);
-            (format!("(integer) {}", len), None)
+            (CommandOutput::Int(len as i64), None)
         }
         Command::LPop { key, count } => {
             let res = engine.flexible.lpop(&key, count.unwrap_or(1));
-            (format!("{:?}", res), None)
+            (pop_reply(res, count), None)
         }
         Command::RPop { key, count } => {
             let res = engine.flexible.rpop(&key, count.unwrap_or(1));
-            (format!("{:?}", res), None)
+            (pop_reply(res, count), None)
         }
         Command::LRange { key, start, stop } => {
             let res = engine.flexible.lrange(&key, start, stop);
-             (format!("{:?}", res), None)
+            match session.output {
+                OutputFormat::Text | OutputFormat::Resp => (CommandOutput::strings(res), None),
+                OutputFormat::Json => (CommandOutput::Bulk(output::list_json(&res)), None),
+            }
         }
         Command::HSet { key, field, value } => {
             let new = engine.flexible.hset(&key, field, value);
-            (format!("(integer) {}", new), None)
+            (CommandOutput::Int(new as i64), None)
         }
         Command::HGet { key, field } => {
             (match engine.flexible.hget(&key, &field) {
-                Some(val) => val,
-                None => "nil".to_string(),
+                Some(val) => CommandOutput::Bulk(val),
+                None => CommandOutput::Nil,
             }, None)
         }
         Command::HGetAll { key } => {
             let res = engine.flexible.hgetall(&key);
-            (format!("{:?}", res), None)
+            match session.output {
+                OutputFormat::Text | OutputFormat::Resp => (CommandOutput::strings(res), None),
+                OutputFormat::Json => (CommandOutput::Bulk(output::hash_json(&res)), None),
+            }
         }
         Command::ClientList => {
             let mut list = String::new();
@@ -370,150 +1213,1280 @@ fn dispatch_direct(engine: &Arc<DatabaseEngine>, cmd: Command, session: &mut Ses
                 list.push_str(&format!("addr={} user={} age={}s\n", 
                     info.addr, info.user, info.connected_at.elapsed().as_secs()));
             }
-            (list, None)
+            (CommandOutput::Bulk(list), None)
         }
         Command::ClientKill { addr } => {
             engine.clients.remove(&addr);
-            ("OK".to_string(), None)
+            (CommandOutput::Ok, None)
+        }
+        Command::ActiveQueries => {
+            let mut list = String::new();
+            for query in engine.queries.list() {
+                list.push_str(&format!("id={} db={} addr={} user={} elapsed={}ms cmd={}\n",
+                    query.id, query.db, query.addr, query.user, query.started.elapsed().as_millis(), query.command));
+            }
+            (CommandOutput::Bulk(list), None)
+        }
+        Command::KillQuery { id } => {
+            if engine.queries.kill(id) {
+                (CommandOutput::Ok, None)
+            } else {
+                (CommandOutput::err("No such query"), None)
+            }
         }
         Command::SAdd { key, members } => {
             let added = engine.flexible.sadd(&key, members);
-            (format!("(integer) {}", added), None)
+            (CommandOutput::Int(added as i64), None)
         }
         Command::SMembers { key } => {
             let res = engine.flexible.smembers(&key);
-            (format!("{:?}", res), None)
+            (CommandOutput::strings(res), None)
         }
-        Command::ZAdd { key, score, member } => {
-            let added = engine.flexible.zadd(&key, score, member);
-            (format!("(integer) {}", added), None)
+        Command::ZAdd { key, flags, members } => {
+            if let Err(e) = flags.validate() {
+                return (CommandOutput::err(e), None);
+            }
+            if members.iter().any(|(score, _)| score.is_nan()) {
+                return (CommandOutput::err("Score is not a number (NaN)"), None);
+            }
+            let added = engine.flexible.zadd(&key, members, flags);
+            (CommandOutput::Int(added), None)
         }
         Command::ZRange { key, start, stop } => {
             let res = engine.flexible.zrange(&key, start, stop);
-            (format!("{:?}", res), None)
+            (CommandOutput::strings(res), None)
         }
         Command::ZScore { key, member } => {
             match engine.flexible.zscore(&key, &member) {
-                Some(score) => (format!("{}", score), None),
-                None => ("nil".to_string(), None),
+                Some(score) => (CommandOutput::Bulk(score.to_string()), None),
+                None => (CommandOutput::Nil, None),
+            }
+        }
+        Command::ZPop { key, count, max } => {
+            let popped = engine.flexible.zpop(&key, count.unwrap_or(1), max);
+            (CommandOutput::strings(popped.into_iter().flat_map(|(member, score)| [member, score.to_string()])), None)
+        }
+        // One attempt; the worker pool retries until the timeout. Reports the key
+        // popped from, so the worker can log the pop as ZPOPMIN/ZPOPMAX
+        Command::BZPop { keys, max, .. } => {
+            for key in keys {
+                if let Some((member, score)) = engine.flexible.zpop(&key, 1, max).pop() {
+                    return (CommandOutput::strings([key.clone(), member, score.to_string()]), Some(key));
+                }
+            }
+            (CommandOutput::Nil, None)
+        }
+        Command::GeoAdd { key, flags, members } => {
+            if let Err(e) = flags.validate() {
+                return (CommandOutput::err(e), None);
+            }
+            if let Some(Err(e)) = members.iter().map(|(lon, lat, _)| geo::validate(*lon, *lat)).find(Result::is_err) {
+                return (CommandOutput::err(e), None);
+            }
+            (CommandOutput::Int(engine.flexible.geoadd(&key, members, flags)), None)
+        }
+        Command::GeoDist { key, member1, member2, unit } => {
+            match (engine.flexible.geopos(&key, &member1), engine.flexible.geopos(&key, &member2)) {
+                (Some((lon1, lat1)), Some((lon2, lat2))) => {
+                    let dist = geo::distance(lon1, lat1, lon2, lat2) / unit.meters();
+                    (CommandOutput::Bulk(format!("{:.4}", dist)), None)
+                }
+                _ => (CommandOutput::Nil, None),
+            }
+        }
+        Command::GeoSearch { key, from, radius, unit, desc, count, with_coord, with_dist } => {
+            let (lon, lat) = match from {
+                GeoOrigin::Member(member) => match engine.flexible.geopos(&key, &member) {
+                    Some(pos) => pos,
+                    None => return (CommandOutput::err(format!("Member '{}' has no position in '{}'", member, key)), None),
+                },
+                GeoOrigin::LonLat(lon, lat) => {
+                    if let Err(e) = geo::validate(lon, lat) {
+                        return (CommandOutput::err(e), None);
+                    }
+                    (lon, lat)
+                }
+            };
+            if !(radius >= 0.0 && radius.is_finite()) {
+                return (CommandOutput::err("Radius must be a non-negative number"), None);
+            }
+            let mut found = engine.flexible.geosearch(&key, lon, lat, radius * unit.meters());
+            found.sort_by(|a, b| if desc { b.1.total_cmp(&a.1) } else { a.1.total_cmp(&b.1) });
+            found.truncate(count.unwrap_or(usize::MAX));
+            let res = found.into_iter().map(|(member, dist, (lon, lat))| {
+                if !with_coord && !with_dist {
+                    return CommandOutput::Bulk(member);
+                }
+                let mut item = vec![CommandOutput::Bulk(member)];
+                if with_dist {
+                    item.push(CommandOutput::Bulk(format!("{:.4}", dist / unit.meters())));
+                }
+                if with_coord {
+                    item.push(CommandOutput::strings([lon.to_string(), lat.to_string()]));
+                }
+                CommandOutput::Array(item)
+            }).collect();
+            (CommandOutput::Array(res), None)
+        }
+        Command::TopKReserve { key, k, width, depth } => {
+            match engine.flexible.topk_reserve(&key, k, width, depth) {
+                Ok(()) => (CommandOutput::Ok, None),
+                Err(e) => (CommandOutput::err(e), None),
+            }
+        }
+        Command::TopKAdd { key, items } => {
+            match engine.flexible.topk_add(&key, items.into_iter().map(|item| (item, 1)).collect()) {
+                Ok(expelled) => (CommandOutput::strings(expelled), None),
+                Err(e) => (CommandOutput::err(e), None),
+            }
+        }
+        Command::TopKIncrBy { key, items } => {
+            match engine.flexible.topk_add(&key, items) {
+                Ok(expelled) => (CommandOutput::strings(expelled), None),
+                Err(e) => (CommandOutput::err(e), None),
+            }
+        }
+        Command::TopKList { key, with_count } => {
+            let items = engine.flexible.topk_list(&key);
+            let res: Vec<String> = if with_count {
+                items.into_iter().flat_map(|(item, count)| [item, count.to_string()]).collect()
+            } else {
+                items.into_iter().map(|(item, _)| item).collect()
+            };
+            (CommandOutput::strings(res), None)
+        }
+        Command::SetBit { key, offset, value } => (CommandOutput::Int(engine.flexible.setbit(&key, offset, value) as i64), None),
+        Command::GetBit { key, offset } => (CommandOutput::Int(engine.flexible.getbit(&key, offset) as i64), None),
+        Command::BitCount { key, range, bits } => (CommandOutput::Int(engine.flexible.bitcount(&key, range, bits) as i64), None),
+        Command::BitOp { op, dest, sources } => (CommandOutput::Int(engine.flexible.bitop(op, &dest, &sources) as i64), None),
+        Command::PfAdd { key, elements } => {
+            match engine.flexible.pfadd(&key, &elements) {
+                Ok(changed) => (CommandOutput::Int(changed as i64), None),
+                Err(e) => (CommandOutput::err(e), None),
+            }
+        }
+        Command::PfCount { keys } => {
+            match engine.flexible.pfcount(&keys) {
+                Ok(count) => (CommandOutput::Int(count as i64), None),
+                Err(e) => (CommandOutput::err(e), None),
+            }
+        }
+        Command::PfMerge { dest, sources } => {
+            match engine.flexible.pfmerge(&dest, &sources) {
+                Ok(()) => (CommandOutput::Ok, None),
+                Err(e) => (CommandOutput::err(e), None),
+            }
+        }
+        // Reports the ID the entry got, so the worker logs it instead of `*`
+        Command::XAdd { key, id, fields } => {
+            match engine.flexible.xadd(&key, &id, fields) {
+                Ok(id) => (CommandOutput::Bulk(id.to_string()), Some(id.to_string())),
+                Err(e) => (CommandOutput::err(e), None),
+            }
+        }
+        Command::XRange { key, start, end, count } => {
+            let bound = |id: &str, missing_seq| match id {
+                "-" => Ok(StreamId::MIN),
+                "+" => Ok(StreamId::MAX),
+                _ => StreamId::parse(id, missing_seq),
+            };
+            match (bound(&start, 0), bound(&end, u64::MAX)) {
+                (Ok(start), Ok(end)) => (stream_entries(engine.flexible.xrange(&key, start, end, count)), None),
+                (Err(e), _) | (_, Err(e)) => (CommandOutput::err(e), None),
+            }
+        }
+        Command::XLen { key } => (CommandOutput::Int(engine.flexible.xlen(&key) as i64), None),
+        // One attempt; `dispatch` retries until the timeout, with `$`
+        // already replaced by the last IDs at the time of the call
+        Command::XRead { count, keys, ids, .. } => {
+            let mut streams = Vec::new();
+            for (key, id) in keys.into_iter().zip(ids) {
+                let after = match id.as_str() {
+                    "$" => engine.flexible.stream_last_id(&key),
+                    _ => match StreamId::parse(&id, 0) {
+                        Ok(id) => id,
+                        Err(e) => return (CommandOutput::err(e), None),
+                    },
+                };
+                let entries = engine.flexible.xread(&key, after, count);
+                if !entries.is_empty() {
+                    streams.push(CommandOutput::Array(vec![CommandOutput::Bulk(key), stream_entries(entries)]));
+                }
+            }
+            if streams.is_empty() {
+                (CommandOutput::Nil, None)
+            } else {
+                (CommandOutput::Array(streams), None)
             }
         }
         Command::Del { keys } => {
             let count = engine.flexible.del(&keys);
-            (format!("(integer) {}", count), None)
+            (CommandOutput::Int(count as i64), None)
+        }
+        // Keys outside the user's key patterns are not listed
+        Command::Scan { cursor, pattern, count } => {
+            let (next, mut keys) = engine.flexible.scan(cursor, pattern.as_deref(), count.unwrap_or(10));
+            keys.retain(|key| session.user.as_ref().is_none_or(|u| u.can_access_key(key)));
+            (CommandOutput::Array(vec![CommandOutput::Bulk(next.to_string()), CommandOutput::strings(keys)]), None)
+        }
+        Command::DbSize => (CommandOutput::Int(engine.flexible.key_count() as i64), None),
+        Command::Keys { pattern } => {
+            let mut keys = engine.flexible.keys(&pattern);
+            keys.retain(|key| session.user.as_ref().is_none_or(|u| u.can_access_key(key)));
+            (CommandOutput::strings(keys), None)
+        }
+        Command::ExportKeys { pattern, path } => {
+            let include = |key: &str| {
+                pattern.as_deref().is_none_or(|p| crate::core::glob::glob_match(p, key))
+                    && session.user.as_ref().is_none_or(|u| u.can_access_key(key))
+            };
+            let path = match engine.config.data_path(&path) {
+                Ok(path) => path,
+                Err(e) => return (CommandOutput::err(e), None),
+            };
+            match engine.flexible.export_to(&path.to_string_lossy(), include) {
+                Ok(count) => (CommandOutput::Int(count as i64), None),
+                Err(e) => (CommandOutput::err(format!("Export failed: {}", e)), None),
+            }
+        }
+        Command::Dump { key } => match engine.flexible.dump_key(&key) {
+            Some(value) => match dump::encode(&value) {
+                Ok(payload) => (CommandOutput::Bulk(payload), None),
+                Err(e) => (CommandOutput::err(format!("DUMP failed: {}", e)), None),
+            },
+            None => (CommandOutput::Nil, None),
+        },
+        Command::Restore { key, ttl, payload, replace, absttl } => {
+            if !replace && engine.flexible.key_type(&key) != "none" {
+                return (CommandOutput::err("BUSYKEY Target key name already exists"), None);
+            }
+            let value = match dump::decode::<KeyValue>(&payload) {
+                Ok(value) => value,
+                Err(e) => return (CommandOutput::err(format!("Bad payload: {}", e)), None),
+            };
+            let expire_at_ms = match ttl {
+                0 => None,
+                at if absttl => Some(at),
+                ttl => Some(crate::core::backend::now_unix_ms() + ttl),
+            };
+            engine.flexible.restore_key(&key, value, expire_at_ms);
+            // A relative TTL is pinned in the AOF, as for SETEX
+            (CommandOutput::Ok, expire_at_ms.filter(|_| !absttl).map(|ms| ms.to_string()))
+        }
+        Command::Type { key } => {
+            (CommandOutput::Status(engine.flexible.key_type(&key).to_string()), None)
+        }
+        Command::Exists { keys } => {
+            (CommandOutput::Int(engine.flexible.exists(&keys) as i64), None)
+        }
+        Command::Eval { script, keys, args } => match scripting::load(&script) {
+            Ok((_, script)) => eval_script(engine, &script, keys, args, session, aof),
+            Err(e) => (CommandOutput::err(e), None),
+        },
+        Command::EvalSha { sha, keys, args } => match scripting::cached(&sha) {
+            Some(script) => eval_script(engine, &script, keys, args, session, aof),
+            None => (CommandOutput::err("NOSCRIPT No matching script, use EVAL"), None),
+        },
+        Command::ScriptLoad { script } => match scripting::load(&script) {
+            Ok((sha, _)) => (CommandOutput::Bulk(sha), None),
+            Err(e) => (CommandOutput::err(e), None),
+        },
+        Command::ScriptExists { shas } => {
+            (CommandOutput::Array(shas.iter().map(|sha| CommandOutput::Int(scripting::cached(sha).is_some() as i64)).collect()), None)
+        }
+        Command::ScriptFlush => {
+            scripting::flush();
+            (CommandOutput::Ok, None)
         }
         Command::JsonGet { key, path } => {
             (match engine.flexible.json_get(&key, path.as_deref()) {
-                Some(val) => format!("{}", val),
-                None => "nil".to_string(),
+                Some(val) => CommandOutput::Bulk(val.to_string()),
+                None => CommandOutput::Nil,
             }, None)
         }
         Command::JsonSet { key, path, value } => {
             if let Ok(json_val) = serde_json::from_str(&value) {
                 let res = engine.flexible.json_set(&key, &path, json_val);
-                (format!("(integer) {}", res), None)
+                (CommandOutput::Int(res as i64), None)
             } else {
-                 (format!("ERROR: Invalid JSON value"), None)
+                 (CommandOutput::err("Invalid JSON value"), None)
             }
         }
         Command::CreateTable { name, columns } => {
-            let cols: Vec<Column> = columns.iter().map(|(n, t, pk, fk)| {
-                let dt = match t.to_uppercase().as_str() {
-                    "INT" | "INTEGER" => DataType::Integer,
-                    "BOOL" | "BOOLEAN" => DataType::Boolean,
-                    "FLOAT" | "DOUBLE" => DataType::Float,
-                    "DATETIME" | "TIMESTAMP" => DataType::DateTime,
-                    "BLOB" | "BYTES" => DataType::Blob,
-                    "JSON" => DataType::Json,
-                    _ => DataType::String,
+            let mut cols = Vec::with_capacity(columns.len());
+            for def in columns {
+                let dt = match DataType::parse(&def.data_type) {
+                    Ok(dt) => dt,
+                    Err(e) => return (CommandOutput::err(e), None),
                 };
-                Column {
-                    name: n.clone(), 
+                cols.push(Column {
+                    name: def.name, 
                     data_type: dt,
-                    is_primary_key: *pk,
-                    references: fk.clone(),
-                }
-            }).collect();
+                    is_primary_key: def.primary_key,
+                    references: def.references,
+                    fk_action: def.fk_action,
+                    not_null: def.not_null,
+                    unique: def.unique,
+                    default: def.default,
+                });
+            }
             
             match engine.structured.create_table(name, cols) {
-                Ok(_) => ("OK".to_string(), None),
-                Err(e) => (format!("ERROR: {}", e), None),
+                Ok(_) => (CommandOutput::Ok, None),
+                Err(e) => (CommandOutput::err(e), None),
             }
         }
         Command::AlterTable { table, op } => {
             match engine.structured.alter_table(&table, op) {
-                Ok(_) => ("OK".to_string(), None),
-                Err(e) => (format!("ERROR: {}", e), None),
+                Ok(_) => (CommandOutput::Ok, None),
+                Err(e) => (CommandOutput::err(e), None),
             }
         }
-        Command::Insert { table, values } => {
-            match engine.structured.insert(&table, values) {
-                Ok(_) => ("OK".to_string(), None),
-                Err(e) => (format!("ERROR: {}", e), None),
+        Command::Insert { table, rows } => {
+            match engine.structured.insert_many(&table, rows) {
+                Ok(_) => (CommandOutput::Ok, None),
+                Err(e) => (CommandOutput::err(e), None),
             }
         }
-        Command::Select { table, selector, join, filter, group_by, having, order_by, limit, offset } => {
-            match engine.structured.select(&table, selector, join, filter, group_by, having, order_by, limit, offset) {
-                Ok(rows) => {
-                    let mut res = String::new();
-                    for row in rows {
-                        res.push_str(&format!("{:?}\n", row));
+        Command::Explain { query, analyze } => {
+            match *query {
+                Command::Select { table, alias, selector, join, filter, group_by, having, order_by, limit, offset } => {
+                    let store = select_store(engine, session, &table, join.as_deref(), filter.as_ref());
+                    let plan = match store.explain(&table, alias.as_deref(), join.as_deref(), filter.as_ref(), group_by.as_deref(), order_by.as_deref(), limit, offset) {
+                        Ok(plan) => plan,
+                        Err(e) => return (CommandOutput::err(e), None),
+                    };
+                    if !analyze {
+                        return (CommandOutput::bulk(plan.to_string().trim_end()), None);
                     }
-                    (if res.is_empty() { "EMPTY".to_string() } else { res.trim_end().to_string() }, None)
-                },
-                Err(e) => (format!("ERROR: {}", e), None),
+                    let (result, mut profile) = crate::core::profile::run(|| {
+                        store.select(&table, alias.as_deref(), selector, join, filter, group_by, having, order_by, limit, offset)
+                    });
+                    match result {
+                        Ok(rows) => {
+                            profile.rows = rows.rows.len();
+                            (CommandOutput::bulk(format!("{}{}", plan, profile).trim_end()), None)
+                        }
+                        Err(e) => (CommandOutput::err(e), None),
+                    }
+                }
+                _ => (CommandOutput::err("EXPLAIN only supports SELECT"), None),
+            }
+        }
+        Command::Declare { name, query } => {
+            if !matches!(*query, Command::Select { .. }) {
+                return (CommandOutput::err("DECLARE only supports SELECT"), None);
+            }
+            if session.cursors.contains_key(&name) {
+                return (CommandOutput::err(format!("Cursor '{}' already exists", name)), None);
+            }
+            if session.cursors.len() >= MAX_CURSORS {
+                return (CommandOutput::err(format!("Too many open cursors (at most {})", MAX_CURSORS)), None);
+            }
+            // An empty page checks the query and names its columns
+            let mut probe = (*query).clone();
+            if let Command::Select { limit, .. } = &mut probe {
+                *limit = Some(0);
+            }
+            match dispatch_direct(engine, probe, session, aof) {
+                (CommandOutput::Rows(result), _) => {
+                    session.cursors.insert(name, Cursor { query: *query, columns: result.columns, fetched: 0, done: false });
+                    (CommandOutput::Ok, None)
+                }
+                (other, _) => (other, None),
             }
         }
-        Command::VectorSearch { table, column, vector, limit } => {
-            match engine.structured.vector_search(&table, &column, &vector, limit) {
-                Ok(results) => {
-                    let mut res = String::new();
-                    for row in results {
-                        res.push_str(&format!("{}\n", row));
+        Command::Fetch { name, count } => {
+            let Some(cursor) = session.cursors.get(&name) else {
+                return (CommandOutput::err(format!("Cursor '{}' does not exist", name)), None);
+            };
+            // The next page, within the query's own LIMIT and OFFSET
+            let mut page = cursor.query.clone();
+            let mut wanted = count;
+            if let Command::Select { limit, offset, .. } = &mut page {
+                let left = limit.map(|l| l.saturating_sub(cursor.fetched));
+                wanted = match (count, left) {
+                    (Some(n), Some(left)) => Some(n.min(left)),
+                    (n, left) => n.or(left),
+                };
+                *limit = wanted;
+                *offset = Some(offset.unwrap_or(0) + cursor.fetched);
+            }
+            if cursor.done || wanted == Some(0) {
+                return (CommandOutput::Rows(ResultSet { columns: cursor.columns.clone(), rows: Vec::new() }), None);
+            }
+            match dispatch_direct(engine, page, session, aof) {
+                (CommandOutput::Rows(result), _) => {
+                    if let Some(cursor) = session.cursors.get_mut(&name) {
+                        cursor.fetched += result.rows.len();
+                        cursor.done = wanted.is_none_or(|n| result.rows.len() < n);
                     }
-                    (if res.is_empty() { "EMPTY".to_string() } else { res.trim_end().to_string() }, None)
-                },
-                Err(e) => (format!("ERROR: {}", e), None),
+                    (CommandOutput::Rows(result), None)
+                }
+                (other, _) => (other, None),
+            }
+        }
+        Command::Close { name: Some(name) } => match session.cursors.remove(&name) {
+            Some(_) => (CommandOutput::Ok, None),
+            None => (CommandOutput::err(format!("Cursor '{}' does not exist", name)), None),
+        },
+        Command::Close { name: None } => {
+            session.cursors.clear();
+            (CommandOutput::Ok, None)
+        }
+        Command::VectorSearch { table, column, vector, limit, metric, threshold, filter } => {
+            let query = VectorQuery { vector: &vector, limit, metric, threshold, filter: filter.as_ref() };
+            match engine.structured.vector_search(&table, &column, &query) {
+                Ok(result) => (CommandOutput::Rows(result), None),
+                Err(e) => (CommandOutput::err(e), None),
             }
         }
         Command::Update { table, filter, set } => {
             match engine.structured.update(&table, filter, set) {
-                Ok(_) => ("OK".to_string(), None),
-                Err(e) => (format!("ERROR: {}", e), None),
+                Ok(count) => (CommandOutput::Int(count as i64), None),
+                Err(e) => (CommandOutput::err(e), None),
             }
         }
         Command::Delete { table, filter } => {
             match engine.structured.delete(&table, filter) {
-                Ok(_) => ("OK".to_string(), None),
-                Err(e) => (format!("ERROR: {}", e), None),
+                Ok(count) => (CommandOutput::Int(count as i64), None),
+                Err(e) => (CommandOutput::err(e), None),
             }
         }
         Command::CreateIndex { index_name, table, column } => {
             match engine.structured.create_index(&index_name, &table, &column) {
-                Ok(_) => ("OK".to_string(), None),
-                Err(e) => (format!("ERROR: {}", e), None),
+                Ok(_) => (CommandOutput::Ok, None),
+                Err(e) => (CommandOutput::err(e), None),
+            }
+        }
+        Command::CreateVectorIndex { index_name, table, column, dims, metric } => {
+            match engine.structured.create_vector_index(&index_name, &table, &column, dims, metric) {
+                Ok(_) => (CommandOutput::Ok, None),
+                Err(e) => (CommandOutput::err(e), None),
+            }
+        }
+        Command::DropIndex { index_name, table } => {
+            match engine.structured.drop_index(&index_name, &table) {
+                Ok(_) => (CommandOutput::Ok, None),
+                Err(e) => (CommandOutput::err(e), None),
+            }
+        }
+        Command::Reindex { table, column } => {
+            match engine.structured.reindex(&table, column.as_deref()) {
+                Ok(n) => (CommandOutput::Status(format!("OK Rebuilt {} index(es)", n)), None),
+                Err(e) => (CommandOutput::err(e), None),
+            }
+        }
+        Command::CheckTable { table } => {
+            match engine.structured.check_table(&table) {
+                Ok(issues) if issues.is_empty() => (CommandOutput::Ok, None),
+                Ok(issues) => (CommandOutput::Bulk(format!("{} discrepancies in {}\n{}", issues.len(), table, issues.join("\n"))), None),
+                Err(e) => (CommandOutput::err(e), None),
             }
         }
-        Command::SetEx { key, value, ttl } => {
+        Command::ExportTable { table } => {
+            match engine.structured.export_table(&table).and_then(|(table, indexes)| dump::encode(&TableDump { table, indexes })) {
+                Ok(payload) => (CommandOutput::Bulk(payload), None),
+                Err(e) => (CommandOutput::err(e), None),
+            }
+        }
+        Command::ImportTable { table, payload, replace } => {
+            let dump = match dump::decode::<TableDump>(&payload) {
+                Ok(dump) => dump,
+                Err(e) => return (CommandOutput::err(format!("Bad payload: {}", e)), None),
+            };
+            match engine.structured.import_table(&table, dump.table, dump.indexes, replace) {
+                Ok(()) => (CommandOutput::Ok, None),
+                Err(e) => (CommandOutput::err(e), None),
+            }
+        }
+        Command::ShowCreateTable { table } => {
+            match engine.structured.show_create_table(&table) {
+                Ok(statements) => (CommandOutput::Bulk(statements.join("\n")), None),
+                Err(e) => (CommandOutput::err(e), None),
+            }
+        }
+        Command::AdvisorSuggest => {
+            let suggestions = engine.structured.suggest_indexes();
+            if suggestions.is_empty() {
+                (CommandOutput::bulk("EMPTY"), None)
+            } else {
+                (CommandOutput::Bulk(suggestions.iter().map(|s| s.to_string()).collect::<Vec<_>>().join("\n")), None)
+            }
+        }
+        Command::SetEx { key, value, ttl, jitter } => {
+            if jitter.is_some_and(|j| j > 100) {
+                return (CommandOutput::err("Jitter must be a percentage between 0 and 100"), None);
+            }
             let json_val = serde_json::from_str(&value).unwrap_or(serde_json::Value::String(value));
-            engine.flexible.set_with_ttl(key, json_val, ttl);
-            ("OK".to_string(), None)
+            engine.flexible.set_with_ttl(key.clone(), json_val, ttl, jitter.unwrap_or_else(|| engine.config.ttl_jitter()));
+            // Report the absolute expiry so the worker can pin it in the AOF and replication stream
+            (CommandOutput::Ok, engine.flexible.expire_time_ms(&key).map(|ms| ms.to_string()))
+        }
+        Command::Expire { key, seconds, jitter } => {
+            if jitter.is_some_and(|j| j > 100) {
+                return (CommandOutput::err("Jitter must be a percentage between 0 and 100"), None);
+            }
+            if engine.flexible.expire(&key, seconds, jitter.unwrap_or_else(|| engine.config.ttl_jitter())) {
+                (CommandOutput::Int(1), engine.flexible.expire_time_ms(&key).map(|ms| ms.to_string()))
+            } else {
+                (CommandOutput::Int(0), None)
+            }
+        }
+        Command::PExpireAt { key, timestamp_ms } => {
+            let res = if engine.flexible.expire_at(&key, timestamp_ms) { 1 } else { 0 };
+            (CommandOutput::Int(res as i64), None)
+        }
+        Command::Schedule { key, value, at_ms, push } => {
+            engine.flexible.schedule(ScheduledWrite { key, value, at_ms, push });
+            (CommandOutput::Ok, None)
+        }
+        Command::Unschedule { key, at_ms } => {
+            (CommandOutput::Int(engine.flexible.unschedule(&key, at_ms) as i64), None)
         }
         Command::Ttl { key } => {
             (match engine.flexible.ttl(&key) {
-                Some(ttl) => format!("{}", ttl),
-                None => "-2".to_string(),
+                Some(ttl) => CommandOutput::Int(ttl),
+                None => CommandOutput::Int(-2),
             }, None)
         }
-        Command::Auth { .. } => ("OK".to_string(), None),
+        Command::Auth { .. } => (CommandOutput::Ok, None),
         Command::Incr { key } => {
-            let val = engine.flexible.incr(&key);
-            (format!("{}", val), None)
+            (engine.flexible.incr(&key).map_or_else(CommandOutput::err, CommandOutput::Int), None)
         }
         Command::Decr { key } => {
-            let val = engine.flexible.decr(&key);
-            (format!("{}", val), None)
+            (engine.flexible.decr(&key).map_or_else(CommandOutput::err, CommandOutput::Int), None)
+        }
+        Command::CIncr { key, by } => {
+            (engine.flexible.cincr(&key, by).map_or_else(CommandOutput::err, CommandOutput::Int), None)
+        }
+        _ => (CommandOutput::err("Unknown or unsupported command"), None),
+    }
+}
+
+/// What a running script has done so far, to undo or log it.
+struct ScriptState {
+    /// Runs the script's commands as the caller, in the caller's database.
+    session: Session,
+    key_savepoints: Vec<crate::core::flexible::KeySavepoint>,
+    table_savepoints: Vec<crate::core::structured::Savepoint>,
+    /// Keys and tables already in a savepoint.
+    saved_keys: HashSet<String>,
+    saved_tables: HashSet<String>,
+    /// AOF lines of the writes made.
+    log: Vec<String>,
+}
+
+/// Runs a script for `EVAL` / `EVALSHA`, under the locks `dispatch` took:
+/// the declared keys, or everything if it declares none.
+///
+/// Its commands run like those of a committed transaction: a failure puts
+/// back every key and table the script wrote. The writes it made are
+/// returned as the lines to log, one per line, so a replay or a replica
+/// applies them instead of running the script again.
+fn eval_script(engine: &Arc<DatabaseEngine>, script: &Script, keys: Vec<String>, args: Vec<String>, session: &Session, aof: &AofLogger) -> (CommandOutput, Option<String>) {
+    let state = Arc::new(Mutex::new(ScriptState {
+        session: Session { user: session.user.clone(), ..Session::system(&session._addr, &session.current_db) },
+        key_savepoints: Vec::new(),
+        table_savepoints: Vec::new(),
+        saved_keys: HashSet::new(),
+        saved_tables: HashSet::new(),
+        log: Vec::new(),
+    }));
+    let call = {
+        let (engine, aof, state, declared) = (engine.clone(), aof.clone(), state.clone(), keys.clone());
+        move |words: Vec<String>| script_command(&engine, &aof, &mut state.lock().unwrap_or_else(|e| e.into_inner()), &declared, words)
+    };
+    let outcome = scripting::run(script, keys, args, Arc::new(call));
+
+    let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+    match outcome {
+        Ok(reply) => {
+            state.table_savepoints.drain(..).for_each(|savepoint| engine.structured.release(savepoint));
+            (reply, (!state.log.is_empty()).then(|| state.log.join("\n")))
+        }
+        Err(e) => {
+            state.key_savepoints.drain(..).rev().for_each(|savepoint| engine.flexible.rollback_to(savepoint));
+            state.table_savepoints.drain(..).rev().for_each(|savepoint| engine.structured.rollback_to(savepoint));
+            (CommandOutput::err(e), None)
         }
-        _ => ("ERROR: Unknown or unsupported command".to_string(), None),
+    }
+}
+
+/// One command of a script, given as its words; a single word is a whole
+/// command line, such as a SQL statement.
+fn script_command(engine: &Arc<DatabaseEngine>, aof: &AofLogger, state: &mut ScriptState, declared: &[String], words: Vec<String>) -> Result<CommandOutput, String> {
+    let (cmd, line) = match binding::bind(&words) {
+        Some(bound) => bound?,
+        None => {
+            let line = match words.as_slice() {
+                [line] => line.clone(),
+                _ => RespValue::Array(Some(words.into_iter().map(|w| RespValue::BulkString(Some(w.into_bytes()))).collect()))
+                    .to_command_string()
+                    .unwrap_or_default(),
+            };
+            match parse_command(&line) {
+                Ok((rest, cmd)) if rest.trim().is_empty() => (cmd, line),
+                _ => return Err(format!("Syntax Error in script command '{}'", line)),
+            }
+        }
+    };
+
+    let user = state.session.user.as_ref().ok_or("Authentication required")?;
+    if !user.can_execute(&cmd) {
+        return Err(format!("User '{}' has no permissions for this command", user.username));
+    }
+    // Only writes to keys and tables can be undone, and a blocking command
+    // would wait with the script's locks held
+    if cmd.slot_keys().is_empty() || matches!(cmd,
+        Command::BZPop { .. } | Command::XRead { block_ms: Some(_), .. } | Command::Failover { .. } | Command::Watch { .. } | Command::Migrate { .. } |
+        Command::Eval { .. } | Command::EvalSha { .. })
+    {
+        return Err(format!("{} cannot run inside a script", cmd.name()));
+    }
+    // The locks cover the declared keys only; in a cluster they must also
+    // be in the slot the script was sent to
+    if !declared.is_empty() || engine.cluster.in_cluster() {
+        if !cmd.tables().is_empty() {
+            return Err(format!("{} cannot run in a script that declares keys", cmd.name()));
+        }
+        if let Some(key) = cmd.keys().into_iter().find(|key| !declared.iter().any(|d| d == key)) {
+            return Err(format!("Script accessed undeclared key '{}'", key));
+        }
+    }
+    if cmd.grows_keyspace() && engine.flexible.is_full() {
+        return Err(OOM.to_string());
+    }
+
+    let writes = cmd.is_write();
+    // A write's line goes to the AOF, which has one command per line
+    if writes && line.contains(['\r', '\n']) {
+        return Err(format!("{} in a script must fit on one line", cmd.name()));
+    }
+    if writes {
+        let keys: Vec<&str> = cmd.keys().into_iter().filter(|key| state.saved_keys.insert(key.to_string())).collect();
+        if !keys.is_empty() {
+            state.key_savepoints.push(engine.flexible.savepoint(keys));
+        }
+        let tables: Vec<&str> = cmd.tables().into_iter().filter(|table| state.saved_tables.insert(table.to_string())).collect();
+        if !tables.is_empty() {
+            state.table_savepoints.push(engine.structured.savepoint(tables));
+        }
+        touch_keys(engine, &cmd);
+    }
+    let logged = writes.then(|| cmd.clone());
+    let (res, hash) = dispatch_direct(engine, cmd, &mut state.session, aof);
+    if let Some(cmd) = logged && !res.is_error() {
+        state.log.extend(worker::log_lines(&cmd, &line, hash.as_deref()));
+    }
+    Ok(res)
+}
+
+/// The store a SELECT runs against. A query naming [`KV_TABLE`] gets a view
+/// with that table built from the keys the session may access, narrowed to
+/// the prefix a `key = ..` or `key LIKE '..%'` condition of the WHERE allows.
+fn select_store<'a>(engine: &'a DatabaseEngine, session: &Session, table: &str, join: Option<&[crate::query::JoinClause]>, filter: Option<&Filter>) -> ReadView<'a> {
+    let view = engine.structured.read_view();
+    let joined = join.unwrap_or_default();
+    if table != KV_TABLE && !joined.iter().any(|j| j.table == KV_TABLE) {
+        return view;
+    }
+    // An unqualified `key` may belong to a joined table
+    let unqualified = table == KV_TABLE && joined.is_empty();
+    let prefix = filter.and_then(|f| kv_key_prefix(f, unqualified)).unwrap_or_default();
+    let kv = engine.flexible.to_table(&prefix, |key| session.user.as_ref().is_none_or(|u| u.can_access_key(key)));
+    let named: Vec<&str> = std::iter::once(table).chain(joined.iter().map(|j| j.table.as_str())).collect();
+    view.with_table(kv, &named)
+}
+
+/// Literal prefix every key matching `filter` starts with, from its
+/// top-level AND conditions on the key column.
+fn kv_key_prefix(filter: &Filter, unqualified: bool) -> Option<String> {
+    match filter {
+        Filter::And(l, r) => kv_key_prefix(l, unqualified).or_else(|| kv_key_prefix(r, unqualified)),
+        Filter::Condition(col, op, value) if col == &format!("{}.key", KV_TABLE) || (unqualified && col == "key") => match op {
+            Operator::Eq => Some(value.clone()),
+            Operator::Like => Some(like::literal_prefix(value).to_string()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// `# Memory` section of INFO: approximate bytes per store of this database.
+fn memory_info(engine: &DatabaseEngine) -> String {
+    let mut stores = engine.flexible.memory_bytes().to_vec();
+    stores.push(("sql", engine.structured.memory_bytes()));
+    let mut info = format!("# Memory\r\nused_memory:{}\r\n", stores.iter().map(|(_, bytes)| bytes).sum::<usize>());
+    for (store, bytes) in stores {
+        info.push_str(&format!("used_memory_{}:{}\r\n", store, bytes));
+    }
+    if let Some(rss) = crate::core::metrics::resident_memory_bytes() {
+        info.push_str(&format!("used_memory_rss:{}\r\n", rss));
+    }
+    info.push_str(&format!("maxmemory:{}\r\n", engine.config.max_memory()));
+    info
+}
+
+/// LPOP/RPOP reply: the element (or nil) without a count, an array with one.
+fn pop_reply(popped: Vec<String>, count: Option<usize>) -> CommandOutput {
+    match count {
+        Some(_) => CommandOutput::strings(popped),
+        None => popped.into_iter().next().map_or(CommandOutput::Nil, CommandOutput::Bulk),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn engine() -> Arc<DatabaseEngine> {
+        Arc::new(DatabaseEngine::ephemeral("test".to_string()))
+    }
+
+    /// Runs one command line the way the worker does, minus the logging.
+    fn run(engine: &Arc<DatabaseEngine>, session: &mut Session, line: &str) -> CommandOutput {
+        let (_, cmd) = parse_command(line).unwrap_or_else(|e| panic!("{}: {:?}", line, e));
+        execute_command(engine, cmd, &AofLogger::detached("test.db"), session).0
+    }
+
+    /// The first column of the rows a query returns, as text.
+    fn first_column(engine: &Arc<DatabaseEngine>, session: &mut Session, query: &str) -> Vec<String> {
+        match run(engine, session, query) {
+            CommandOutput::Rows(set) => set.rows.iter().map(|row| row[0].to_string()).collect(),
+            other => panic!("{}: {}", query, other.to_text(OutputFormat::Text)),
+        }
+    }
+
+    #[test]
+    fn null_matches_only_null_tests() {
+        let engine = engine();
+        let mut session = Session::system("test", "test");
+        run(&engine, &mut session, "CREATE TABLE people id:int:pk name:string age:int");
+        run(&engine, &mut session, "INSERT INTO people VALUES (1, 'Ana', 30), (2, NULL, 40), (3, \"NULL\", null)");
+        let ids = |session: &mut Session, filter: &str| first_column(&engine, session, &format!("SELECT id FROM people WHERE {}", filter));
+
+        assert_eq!(ids(&mut session, "name IS NULL"), ["2"]);
+        assert_eq!(ids(&mut session, "age is not null"), ["1", "2"]);
+        // A quoted NULL is a string
+        assert_eq!(ids(&mut session, "name = 'NULL'"), ["3"]);
+        // Comparing with NULL, or comparing a NULL, never holds
+        assert!(ids(&mut session, "name = NULL").is_empty());
+        assert!(ids(&mut session, "age != NULL").is_empty());
+        assert_eq!(ids(&mut session, "age < 35"), ["1"]);
+        assert_eq!(ids(&mut session, "age != 30"), ["2"]);
+
+        run(&engine, &mut session, "UPDATE people SET age = NULL WHERE id = 1");
+        assert_eq!(ids(&mut session, "age IS NULL"), ["1", "3"]);
+    }
+
+    #[test]
+    fn update_and_delete_reply_with_the_rows_they_changed() {
+        let engine = engine();
+        let mut session = Session::system("test", "test");
+        run(&engine, &mut session, "CREATE TABLE tasks id:int:pk done:bool");
+        run(&engine, &mut session, "INSERT INTO tasks VALUES (1, false), (2, false), (3, true)");
+
+        let update = run(&engine, &mut session, "UPDATE tasks SET done = true WHERE done = false");
+        assert!(matches!(update, CommandOutput::Int(2)));
+        assert!(matches!(update.to_resp(OutputFormat::Text), RespValue::Integer(2)));
+        assert!(matches!(run(&engine, &mut session, "UPDATE tasks SET done = false WHERE id = 9"), CommandOutput::Int(0)));
+        assert!(matches!(run(&engine, &mut session, "DELETE FROM tasks WHERE id = 9"), CommandOutput::Int(0)));
+        assert!(matches!(run(&engine, &mut session, "DELETE FROM tasks WHERE id >= 2"), CommandOutput::Int(2)));
+        assert!(matches!(run(&engine, &mut session, "DELETE FROM tasks"), CommandOutput::Int(1)));
+        assert!(run(&engine, &mut session, "DELETE FROM missing").is_error());
+    }
+
+    #[test]
+    fn commit_replies_with_one_result_per_command() {
+        let engine = engine();
+        let mut session = Session::system("test", "test");
+        assert!(matches!(run(&engine, &mut session, "BEGIN"), CommandOutput::Ok));
+        for line in ["SET a 1", "INCR a", "RPUSH l x y", "GET a"] {
+            assert!(matches!(run(&engine, &mut session, line), CommandOutput::Status(s) if s == "QUEUED"));
+        }
+        let CommandOutput::Array(results) = run(&engine, &mut session, "COMMIT") else {
+            panic!("COMMIT should reply with an array");
+        };
+        assert_eq!(results.len(), 4);
+        assert!(matches!(results[0], CommandOutput::Ok));
+        assert!(matches!(results[1], CommandOutput::Int(2)));
+        assert!(matches!(results[2], CommandOutput::Int(2)));
+        assert!(matches!(&results[3], CommandOutput::Bulk(s) if s == "2"));
+
+        // An empty transaction commits to an empty array
+        run(&engine, &mut session, "BEGIN");
+        assert!(matches!(run(&engine, &mut session, "COMMIT"), CommandOutput::Array(items) if items.is_empty()));
+        assert!(run(&engine, &mut session, "COMMIT").is_error());
+    }
+
+    #[test]
+    fn failed_transactions_undo_schema_changes() {
+        let engine = engine();
+        let mut session = Session::system("test", "test");
+        run(&engine, &mut session, "CREATE TABLE accounts id:int:pk owner:string");
+        run(&engine, &mut session, "INSERT accounts 1 ana");
+
+        run(&engine, &mut session, "BEGIN");
+        for line in [
+            "CREATE TABLE migrated id:int:pk owner:string", "CREATE INDEX by_owner ON accounts(owner)",
+            "INSERT accounts 2 bob", "INSERT migrated 1 bob", "INSERT accounts 1 eve",
+        ] {
+            run(&engine, &mut session, line);
+        }
+        assert!(matches!(run(&engine, &mut session, "COMMIT"), CommandOutput::Err(e) if e.contains("command 5 (INSERT)")));
+
+        assert!(!engine.structured.table_names().contains(&"migrated".to_string()));
+        assert_eq!(first_column(&engine, &mut session, "SELECT id FROM accounts"), ["1"]);
+        assert!(engine.structured.list_indexes(Some("accounts")).unwrap().iter().all(|i| i.name != "by_owner"));
+        assert!(engine.structured.check_table("accounts").unwrap().is_empty());
+    }
+
+    #[test]
+    fn topk_sketches_survive_a_rewrite_with_their_counters() {
+        let engine = engine();
+        let mut session = Session::system("test", "test");
+        run(&engine, &mut session, "TOPK.RESERVE top 1 64 2");
+        run(&engine, &mut session, "TOPK.INCRBY top a 5 b 3");
+        assert!(run(&engine, &mut session, "TOPK.RESERVE top 1").is_error());
+
+        let restored = Arc::new(DatabaseEngine::ephemeral("restored".to_string()));
+        for line in engine.dump_commands() {
+            assert!(!run(&restored, &mut session, &line).is_error(), "{}", line);
+        }
+        // b was never in the top list, but its count was kept
+        let list = |engine| match run(engine, &mut Session::system("test", "test"), "TOPK.LIST top WITHCOUNT") {
+            CommandOutput::Array(items) => items.iter().map(|i| i.to_text(OutputFormat::Text)).collect::<Vec<_>>(),
+            other => panic!("{}", other.to_text(OutputFormat::Text)),
+        };
+        assert_eq!(list(&restored), ["a", "5"]);
+        assert!(matches!(run(&restored, &mut session, "TOPK.INCRBY top b 3"), CommandOutput::Array(items) if items.len() == 1));
+        assert_eq!(list(&restored), ["b", "6"]);
+
+        run(&engine, &mut session, "SET name ana");
+        for line in ["TOPK.RESERVE name 3", "TOPK.ADD name x", "TOPK.INCRBY name x 2"] {
+            assert!(matches!(run(&engine, &mut session, line), CommandOutput::Err(e) if e.starts_with("WRONGTYPE")), "{}", line);
+        }
+        assert_eq!(engine.flexible.key_type("name"), "string");
+    }
+
+    #[test]
+    fn replies_keep_their_resp_types() {
+        let bulk = |s: &str| RespValue::BulkString(Some(s.as_bytes().to_vec()));
+        for format in [OutputFormat::Text, OutputFormat::Resp] {
+            assert_eq!(CommandOutput::Ok.to_resp(format), RespValue::SimpleString("OK".into()));
+            assert_eq!(CommandOutput::Status("QUEUED".into()).to_resp(format), RespValue::SimpleString("QUEUED".into()));
+            assert_eq!(CommandOutput::Int(-3).to_resp(format), RespValue::Integer(-3));
+            assert_eq!(CommandOutput::Bulk("a b".into()).to_resp(format), bulk("a b"));
+            assert_eq!(CommandOutput::Nil.to_resp(format), RespValue::BulkString(None));
+            assert_eq!(CommandOutput::err("boom").to_resp(format), RespValue::Error("boom".into()));
+            let nested = CommandOutput::Array(vec![CommandOutput::Int(1), CommandOutput::Nil, CommandOutput::strings(["x".to_string()])]);
+            assert_eq!(nested.to_resp(format), RespValue::Array(Some(vec![
+                RespValue::Integer(1), RespValue::BulkString(None), RespValue::Array(Some(vec![bulk("x")])),
+            ])));
+        }
+
+        // Result sets are native arrays only in resp mode
+        let engine = engine();
+        let mut session = Session::system("test", "test");
+        run(&engine, &mut session, "CREATE TABLE t id:int:pk name:string");
+        run(&engine, &mut session, "INSERT INTO t VALUES (1, 'ana')");
+        let rows = run(&engine, &mut session, "SELECT * FROM t");
+        let RespValue::Array(Some(items)) = rows.to_resp(OutputFormat::Resp) else { panic!("rows should be an array") };
+        assert_eq!(items[1], RespValue::Array(Some(vec![RespValue::Integer(1), bulk("ana")])));
+        assert!(matches!(rows.to_resp(OutputFormat::Text), RespValue::BulkString(Some(text)) if String::from_utf8_lossy(&text).contains("ana")));
+    }
+
+    #[test]
+    fn pops_reply_with_an_element_or_an_array() {
+        assert!(matches!(pop_reply(vec!["a".into()], None), CommandOutput::Bulk(s) if s == "a"));
+        assert!(matches!(pop_reply(Vec::new(), None), CommandOutput::Nil));
+        assert!(matches!(pop_reply(vec!["a".into(), "b".into()], Some(2)), CommandOutput::Array(items) if items.len() == 2));
+        assert!(matches!(pop_reply(Vec::new(), Some(2)), CommandOutput::Array(items) if items.is_empty()));
+
+        let engine = engine();
+        let mut session = Session::system("test", "test");
+        run(&engine, &mut session, "RPUSH l a b c d");
+        assert_eq!(run(&engine, &mut session, "LPOP l").to_resp(OutputFormat::Text), RespValue::BulkString(Some(b"a".to_vec())));
+        let RespValue::Array(Some(popped)) = run(&engine, &mut session, "RPOP l 2").to_resp(OutputFormat::Text) else {
+            panic!("a pop with a count should reply with an array");
+        };
+        assert_eq!(popped, [RespValue::BulkString(Some(b"d".to_vec())), RespValue::BulkString(Some(b"c".to_vec()))]);
+        run(&engine, &mut session, "LPOP l");
+        assert_eq!(run(&engine, &mut session, "LPOP l").to_resp(OutputFormat::Text), RespValue::BulkString(None));
+    }
+
+    #[test]
+    fn explain_analyze_reports_each_stage() {
+        let engine = engine();
+        let mut session = Session::system("test", "test");
+        run(&engine, &mut session, "CREATE TABLE users id:int:pk name:string");
+        run(&engine, &mut session, "CREATE TABLE orders id:int:pk user_id:int total:int");
+        run(&engine, &mut session, "INSERT INTO users VALUES (1, 'ana'), (2, 'bob')");
+        run(&engine, &mut session, "INSERT INTO orders VALUES (1, 1, 10), (2, 1, 20), (3, 2, 30), (4, 2, 40)");
+        let analyze = |session: &mut Session, query: &str| match run(&engine, session, query) {
+            CommandOutput::Bulk(text) => text,
+            other => panic!("{}: {}", query, other.to_text(OutputFormat::Text)),
+        };
+
+        let text = analyze(&mut session, "EXPLAIN ANALYZE SELECT name FROM users WHERE id >= 1 ORDER BY id LIMIT 1 OFFSET 1");
+        assert!(text.contains("Actual (1 rows in "), "{}", text);
+        for stage in ["-> Access users: 2 rows in, 2 rows out", "-> Sort: 2 rows in, 2 rows out", "-> Project: 2 rows in, 2 rows out", "-> Limit: 2 rows in, 1 rows out ("] {
+            assert!(text.contains(stage), "{} in {}", stage, text);
+        }
+        // Unsorted, the scan itself skips the OFFSET and stops at the LIMIT
+        let text = analyze(&mut session, "EXPLAIN ANALYZE SELECT name FROM users WHERE id >= 1 LIMIT 1 OFFSET 1");
+        for stage in ["-> Access users: 2 rows in, 1 rows out", "-> Project: 1 rows in, 1 rows out", "-> Limit: 1 rows in, 1 rows out ("] {
+            assert!(text.contains(stage), "{} in {}", stage, text);
+        }
+
+        let text = analyze(&mut session, "EXPLAIN ANALYZE SELECT DISTINCT u.name FROM orders o JOIN users u ON o.user_id = u.id WHERE o.total > 10 LIMIT 5");
+        assert!(text.contains("Actual (2 rows in "), "{}", text);
+        for stage in ["-> Filter: 4 rows in, 3 rows out", "-> Project: 3 rows in, 3 rows out", "-> Distinct: 3 rows in, 2 rows out", "-> Limit: 2 rows in, 2 rows out ("] {
+            assert!(text.contains(stage), "{} in {}", stage, text);
+        }
+        // Without LIMIT or OFFSET there is no Limit stage
+        assert!(!analyze(&mut session, "EXPLAIN ANALYZE SELECT * FROM users").contains("Limit"));
+    }
+
+    #[test]
+    fn readonly_replica_sessions_only_read() {
+        let engine = engine();
+        let mut session = Session::system("test", "test");
+        run(&engine, &mut session, "SET k 1");
+        engine.replication.set_replica_of("10.0.0.2".to_string(), 8569);
+        run(&engine, &mut session, "READONLY");
+        let refused = |session: &mut Session, line: &str, prefix: &str| {
+            let reply = run(&engine, session, line);
+            assert!(matches!(&reply, CommandOutput::Err(e) if e.starts_with(prefix)), "{}: {}", line, reply.to_text(OutputFormat::Text));
+        };
+        refused(&mut session, "DEL k", "READONLY");
+        refused(&mut session, "SET k 2", "READONLY");
+        assert!(matches!(run(&engine, &mut session, "GET k"), CommandOutput::Bulk(s) if s == "1"));
+
+        // In a cluster, writes to the master's slots go to the master
+        engine.cluster.add_node("10.0.0.2:8569".to_string());
+        refused(&mut session, "DEL k", "MOVED");
+        assert!(matches!(run(&engine, &mut session, "GET k"), CommandOutput::Bulk(s) if s == "1"));
+    }
+
+    #[test]
+    fn cursors_fetch_one_page_at_a_time() {
+        let engine = engine();
+        let mut session = Session::system("test", "test");
+        run(&engine, &mut session, "CREATE TABLE events id:int:pk kind:string");
+        for id in 1..=10 {
+            run(&engine, &mut session, &format!("INSERT INTO events VALUES ({}, 'e')", id));
+        }
+        let fetch = |session: &mut Session, line: &str| first_column(&engine, session, line);
+
+        // Pages stay within the query's own OFFSET and LIMIT
+        assert!(matches!(run(&engine, &mut session, "DECLARE c CURSOR FOR SELECT id FROM events WHERE id > 2 LIMIT 5 OFFSET 1"), CommandOutput::Ok));
+        assert_eq!(fetch(&mut session, "FETCH 2 FROM c"), ["4", "5"]);
+        // Each FETCH reads the table as it is now
+        run(&engine, &mut session, "DELETE FROM events WHERE id = 6");
+        assert_eq!(fetch(&mut session, "FETCH 2 FROM c"), ["7", "8"]);
+        assert_eq!(fetch(&mut session, "FETCH 2 FROM c"), ["9"]);
+        assert!(fetch(&mut session, "FETCH ALL FROM c").is_empty());
+        let CommandOutput::Rows(done) = run(&engine, &mut session, "FETCH FROM c") else { panic!("no rows") };
+        assert_eq!(done.columns, ["id"]);
+
+        assert!(matches!(run(&engine, &mut session, "DECLARE s CURSOR FOR SELECT id FROM events ORDER BY id DESC"), CommandOutput::Ok));
+        assert_eq!(fetch(&mut session, "FETCH NEXT FROM s"), ["10"]);
+        assert_eq!(fetch(&mut session, "FETCH ALL FROM s"), ["9", "8", "7", "5", "4", "3", "2", "1"]);
+
+        // Errors show at DECLARE, and a session keeps a bounded number of cursors
+        assert!(matches!(run(&engine, &mut session, "DECLARE m CURSOR FOR SELECT * FROM missing"), CommandOutput::Err(_)));
+        assert!(matches!(run(&engine, &mut session, "FETCH FROM m"), CommandOutput::Err(e) if e.contains("does not exist")));
+        for i in session.cursors.len()..MAX_CURSORS {
+            assert!(matches!(run(&engine, &mut session, &format!("DECLARE c{} CURSOR FOR SELECT * FROM events", i)), CommandOutput::Ok));
+        }
+        let err = run(&engine, &mut session, "DECLARE extra CURSOR FOR SELECT * FROM events");
+        assert!(matches!(&err, CommandOutput::Err(e) if e.starts_with("Too many open cursors")), "{}", err.to_text(OutputFormat::Text));
+        assert!(matches!(run(&engine, &mut session, "CLOSE ALL"), CommandOutput::Ok));
+        assert!(session.cursors.is_empty());
+    }
+
+    #[test]
+    fn getrange_keeps_characters_whole() {
+        let engine = engine();
+        let mut session = Session::system("test", "test");
+        run(&engine, &mut session, "SET word añob");
+        for (line, expected) in [("GETRANGE word 0 1", "añ"), ("GETRANGE word 2 2", "ñ"), ("GETRANGE word 2 -1", "ñob"), ("GETRANGE word -3 -1", "ñob"), ("GETRANGE word 0 -1", "añob"), ("GETRANGE word 4 9", "b")] {
+            assert!(matches!(run(&engine, &mut session, line), CommandOutput::Bulk(s) if s == expected), "{}", line);
+        }
+    }
+
+    #[test]
+    fn counters_and_plain_keys_do_not_mix() {
+        let engine = engine();
+        let mut session = Session::system("test", "test");
+        run(&engine, &mut session, "SET name ana");
+        run(&engine, &mut session, "RPUSH list a");
+        for line in ["CINCR name", "CINCR list 2"] {
+            assert!(matches!(run(&engine, &mut session, line), CommandOutput::Err(e) if e.starts_with("WRONGTYPE")), "{}", line);
+        }
+        assert!(matches!(run(&engine, &mut session, "CINCR views 2"), CommandOutput::Int(2)));
+        assert!(matches!(run(&engine, &mut session, "CINCR views"), CommandOutput::Int(3)));
+        for line in ["INCR views", "DECR views"] {
+            assert!(matches!(run(&engine, &mut session, line), CommandOutput::Err(e) if e.starts_with("WRONGTYPE")), "{}", line);
+        }
+        assert_eq!(engine.flexible.key_type("views"), "counter");
+        assert!(matches!(run(&engine, &mut session, "GET views"), CommandOutput::Bulk(s) if s == "3"));
+    }
+
+    #[test]
+    fn waits_are_left_to_the_caller() {
+        let engine = engine();
+        let mut session = Session::system("test", "test");
+        let aof = AofLogger::detached("test.db");
+        // Nothing to wait for
+        assert!(matches!(run(&engine, &mut session, "WAIT 0 0"), CommandOutput::Int(0)));
+        assert!(session.waiting.is_none());
+
+        run(&engine, &mut session, "WAIT 1 50");
+        let mut waiting = session.waiting.take().expect("WAIT 1 waits for a replica");
+        assert!(resume(&engine, &mut waiting, &mut session, &aof, false).is_none());
+        std::thread::sleep(std::time::Duration::from_millis(60));
+        assert!(matches!(resume(&engine, &mut waiting, &mut session, &aof, false), Some((CommandOutput::Int(0), None))));
+
+        // A timeout of 0 is capped, and a kill ends the wait at once
+        run(&engine, &mut session, "WAIT 1 0");
+        let mut waiting = session.waiting.take().unwrap();
+        assert!(waiting.deadline <= std::time::Instant::now() + MAX_WAIT);
+        assert!(matches!(resume(&engine, &mut waiting, &mut session, &aof, true), Some((CommandOutput::Int(0), None))));
+
+        // Inside a transaction WAIT replies right away
+        for line in ["BEGIN", "WAIT 1 0"] {
+            run(&engine, &mut session, line);
+        }
+        assert!(matches!(run(&engine, &mut session, "COMMIT"), CommandOutput::Array(results) if matches!(results[..], [CommandOutput::Int(0)])));
+        assert!(session.waiting.is_none());
+    }
+
+    #[test]
+    fn replchecks_wait_without_a_worker() {
+        use crate::core::replication::{ReplConf, ReplicaMessage};
+        let engine = engine();
+        let mut session = Session::system("test", "test");
+        let aof = AofLogger::detached("test.db");
+        assert!(matches!(run(&engine, &mut session, "REPLCHECK"), CommandOutput::Err(e) if e == "REPLCHECK requires a connected replica"));
+        let (tx, mut rx) = tokio::sync::mpsc::channel(8);
+        engine.replication.add_replica("r1".to_string(), tx);
+        let mut sent_check = || std::iter::from_fn(|| rx.try_recv().ok()).find_map(|message| match message {
+            ReplicaMessage::Check { id, .. } => Some(id),
+            _ => None,
+        });
+
+        // The check goes out at once, and the reply waits for the answer
+        run(&engine, &mut session, "SET k 1");
+        assert!(matches!(run(&engine, &mut session, "REPLCHECK r1"), CommandOutput::Nil));
+        let mut waiting = session.waiting.take().expect("REPLCHECK waits for the replica");
+        let id = sent_check().expect("the check was sent");
+        assert!(resume(&engine, &mut waiting, &mut session, &aof, false).is_none());
+        engine.replication.replconf("r1", ReplConf::Digest { id, digests: digest::digests(&engine) });
+        assert!(matches!(resume(&engine, &mut waiting, &mut session, &aof, false), Some((CommandOutput::Ok, None))));
+
+        // The digests are those of when the check went out
+        run(&engine, &mut session, "REPLCHECK");
+        let mut waiting = session.waiting.take().unwrap();
+        let id = sent_check().unwrap();
+        let before = digest::digests(&engine);
+        run(&engine, &mut session, "SET k 2");
+        engine.replication.replconf("r1", ReplConf::Digest { id, digests: before });
+        assert!(matches!(resume(&engine, &mut waiting, &mut session, &aof, false), Some((CommandOutput::Ok, None))));
+
+        // No check goes out while writes are on their way to the replicas
+        engine.replication.forwarding();
+        run(&engine, &mut session, "REPLCHECK TIMEOUT 20");
+        let mut waiting = session.waiting.take().unwrap();
+        assert!(resume(&engine, &mut waiting, &mut session, &aof, false).is_none());
+        std::thread::sleep(std::time::Duration::from_millis(30));
+        let timed_out = resume(&engine, &mut waiting, &mut session, &aof, false);
+        assert!(matches!(timed_out, Some((CommandOutput::Err(e), None)) if e.contains("writes did not pause")));
+        assert!(sent_check().is_none());
+        engine.replication.forwarded();
+
+        for line in ["BEGIN", "REPLCHECK"] {
+            run(&engine, &mut session, line);
+        }
+        assert!(session.tx_buffer.as_ref().is_some_and(|buffer| buffer.is_empty()));
+    }
+
+    #[test]
+    fn acl_rules_name_known_categories() {
+        let engine = engine();
+        let mut session = Session::system("test", "test");
+        let refused = run(&engine, &mut session, "ACL SETUSER bob secret +@read -@scriptin");
+        assert!(matches!(refused, CommandOutput::Err(e) if e == "Unknown ACL category in rule '-@scriptin'"));
+        assert!(engine.security.get_user("bob").is_none());
+        assert!(matches!(run(&engine, &mut session, "ACL SETUSER bob secret +@read -@scripting"), CommandOutput::Ok));
+    }
+
+    #[test]
+    fn blocking_pops_wait_for_a_member() {
+        let engine = engine();
+        let mut session = Session::system("test", "test");
+        let aof = AofLogger::detached("test.db");
+        run(&engine, &mut session, "ZADD ready 1 a");
+        assert!(matches!(run(&engine, &mut session, "BZPOPMIN empty ready 0"), CommandOutput::Array(items) if items.len() == 3));
+        assert!(session.waiting.is_none());
+
+        assert!(matches!(run(&engine, &mut session, "BZPOPMAX jobs 0"), CommandOutput::Nil));
+        let mut waiting = session.waiting.take().expect("an empty key makes BZPOP wait");
+        assert!(waiting.deadline <= std::time::Instant::now() + MAX_WAIT);
+        assert!(resume(&engine, &mut waiting, &mut session, &aof, false).is_none());
+        run(&engine, &mut session, "ZADD jobs 1 low 2 high");
+        let Some((CommandOutput::Array(items), Some(key))) = resume(&engine, &mut waiting, &mut session, &aof, false) else {
+            panic!("the pop should go through once the key has members");
+        };
+        assert_eq!(key, "jobs");
+        assert!(matches!(&items[1], CommandOutput::Bulk(member) if member == "high"));
+
+        // Its timeout ends it with nil
+        run(&engine, &mut session, "BZPOPMIN none 0.01");
+        let mut waiting = session.waiting.take().unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        assert!(matches!(resume(&engine, &mut waiting, &mut session, &aof, false), Some((CommandOutput::Nil, None))));
+    }
+
+    #[test]
+    fn blocking_pops_follow_the_slot_owner() {
+        let engine = engine();
+        let mut session = Session::system("test", "test");
+        let slot = ClusterManager::key_slot("jobs");
+        engine.cluster.add_slots(vec![(slot + 1) % 16384]);
+        engine.cluster.set_slot(slot, crate::query::SlotState::Node("10.0.0.2:8569".into())).unwrap();
+
+        let expected = format!("MOVED {} 10.0.0.2:8569", slot);
+        assert!(matches!(run(&engine, &mut session, "BZPOPMIN jobs 0"), CommandOutput::Err(e) if e == expected));
+        assert!(session.waiting.is_none());
+        assert!(matches!(run(&engine, &mut session, "BZPOPMIN jobs other 0"), CommandOutput::Err(e) if e == CROSSSLOT));
+    }
+
+    #[test]
+    fn migrated_keys_are_logged_as_one_del() {
+        use std::io::{Read, Write};
+        let engine = engine();
+        let mut session = Session::system("test", "test");
+        // A target that accepts every command
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let target = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all("+OK\r\n".repeat(16).as_bytes()).unwrap();
+            let _ = stream.read_to_end(&mut Vec::new());
+        });
+        let slot = ClusterManager::key_slot("a b");
+        engine.cluster.add_slots(vec![slot]);
+        engine.cluster.set_slot(slot, crate::query::SlotState::Migrating(target.to_string())).unwrap();
+        engine.flexible.set("a b".to_string(), serde_json::json!("1"));
+
+        let migrate = format!("MIGRATE {} {} SLOT {}", target.ip(), target.port(), slot);
+        let (_, cmd) = parse_command(&migrate).unwrap();
+        let (output, hash) = execute_command(&engine, cmd.clone(), &AofLogger::detached("test.db"), &mut session);
+        server.join().unwrap();
+        assert!(matches!(output, CommandOutput::Int(1)));
+        assert!(engine.flexible.get("a b").is_none());
+
+        // The key keeps its space on replay
+        let lines = worker::log_lines(&cmd, &migrate, hash.as_deref());
+        assert_eq!(lines, ["DEL \"a b\""]);
+        assert!(matches!(parse_command(&lines[0]), Ok((_, Command::Del { keys })) if keys == ["a b"]));
+    }
+
+    #[test]
+    fn tables_follow_the_slot_of_their_name() {
+        let engine = engine();
+        let mut session = Session::system("test", "test");
+        let (users, orders) = (ClusterManager::key_slot("users"), ClusterManager::key_slot("orders"));
+        assert!(users != orders && users != ClusterManager::key_slot("stock"));
+        engine.cluster.add_slots(vec![users, ClusterManager::key_slot("stock")]);
+        engine.cluster.set_slot(orders, crate::query::SlotState::Node("10.0.0.2:8569".into())).unwrap();
+
+        assert!(matches!(run(&engine, &mut session, "CREATE TABLE users id:int:pk name:string"), CommandOutput::Ok));
+        run(&engine, &mut session, "INSERT INTO users VALUES (1, 'Ana')");
+        assert!(matches!(run(&engine, &mut session, "SELECT * FROM users u JOIN users m ON u.id = m.id"), CommandOutput::Rows(_)));
+
+        let moved = format!("MOVED {} 10.0.0.2:8569", orders);
+        for line in [
+            "CREATE TABLE orders id:int:pk user_id:int",
+            "INSERT INTO orders VALUES (1, 1)",
+            "SELECT * FROM orders",
+            "UPDATE orders SET user_id = 2 WHERE id = 1",
+            "DELETE FROM orders WHERE id = 1",
+            "DESCRIBE orders",
+        ] {
+            assert!(matches!(run(&engine, &mut session, line), CommandOutput::Err(e) if e == moved), "{}", line);
+        }
+
+        // A join, or a transaction, reaching tables of two slots runs nowhere
+        let join = "SELECT * FROM users JOIN orders ON users.id = orders.user_id";
+        assert!(matches!(run(&engine, &mut session, join), CommandOutput::Err(e) if e == CROSSSLOT));
+        run(&engine, &mut session, "BEGIN");
+        run(&engine, &mut session, "INSERT INTO users VALUES (2, 'Bo')");
+        assert!(matches!(run(&engine, &mut session, "SET stock 1"), CommandOutput::Err(e) if e == CROSSSLOT));
+        run(&engine, &mut session, "ROLLBACK");
+    }
+
+    #[test]
+    fn blocking_reads_return_entries_added_since_they_began() {
+        let engine = engine();
+        let mut session = Session::system("test", "test");
+        let aof = AofLogger::detached("test.db");
+        run(&engine, &mut session, "XADD orders * item old");
+        assert!(matches!(run(&engine, &mut session, "XREAD BLOCK 0 STREAMS orders $"), CommandOutput::Nil));
+        let mut waiting = session.waiting.take().expect("nothing new makes XREAD BLOCK wait");
+        assert!(resume(&engine, &mut waiting, &mut session, &aof, false).is_none());
+
+        // `$` stays the last ID from when the read began
+        run(&engine, &mut session, "XADD orders * item new");
+        let Some((CommandOutput::Array(streams), None)) = resume(&engine, &mut waiting, &mut session, &aof, false) else {
+            panic!("the read should return the new entry");
+        };
+        let text = CommandOutput::Array(streams).to_text(OutputFormat::Text);
+        assert!(text.contains("new") && !text.contains("old"), "{}", text);
+
+        // A kill ends it with nil
+        run(&engine, &mut session, "XREAD BLOCK 0 STREAMS orders $");
+        let mut waiting = session.waiting.take().unwrap();
+        assert!(matches!(resume(&engine, &mut waiting, &mut session, &aof, true), Some((CommandOutput::Nil, None))));
+    }
+
+    #[test]
+    fn failover_waits_for_its_replica_to_take_over() {
+        let engine = engine();
+        let mut session = Session::system("test", "test");
+        let aof = AofLogger::detached("test.db");
+        assert!(matches!(run(&engine, &mut session, "FAILOVER"), CommandOutput::Err(e) if e.contains("requires a connected replica")));
+        let (tx, mut rx) = tokio::sync::mpsc::channel(8);
+        engine.replication.add_replica("10.0.0.2:40000".to_string(), tx);
+        engine.replication.replconf("10.0.0.2:40000", crate::core::replication::ReplConf::ListeningPort(8570));
+
+        // A transaction cannot wait for it
+        run(&engine, &mut session, "BEGIN");
+        assert!(matches!(run(&engine, &mut session, "FAILOVER"), CommandOutput::Err(e) if e.contains("inside a transaction")));
+        run(&engine, &mut session, "ROLLBACK");
+
+        run(&engine, &mut session, "FAILOVER TIMEOUT 0");
+        let mut waiting = session.waiting.take().expect("FAILOVER waits for the replica");
+        assert!(engine.replication.is_failing_over());
+        assert!(resume(&engine, &mut waiting, &mut session, &aof, false).is_none());
+        // Once the replica caught up it is told to take over...
+        engine.replication.ack("10.0.0.2:40000", engine.replication.offset());
+        assert!(resume(&engine, &mut waiting, &mut session, &aof, false).is_none());
+        assert!(std::iter::from_fn(|| rx.try_recv().ok()).any(|message| message == crate::core::replication::ReplicaMessage::Promote));
+        // ...and FAILOVER is done when this node follows it
+        engine.replication.set_replica_of("10.0.0.2".to_string(), 8570);
+        assert!(matches!(resume(&engine, &mut waiting, &mut session, &aof, false), Some((CommandOutput::Ok, None))));
+
+        // A replica that never takes over ends it with an error, and writes resume
+        engine.replication.set_master();
+        engine.replication.set_failing_over(false);
+        run(&engine, &mut session, "FAILOVER");
+        let mut waiting = session.waiting.take().unwrap();
+        assert!(resume(&engine, &mut waiting, &mut session, &aof, false).is_none());
+        assert!(matches!(resume(&engine, &mut waiting, &mut session, &aof, true), Some((CommandOutput::Err(e), None)) if e.ends_with("did not take over")));
+        assert!(!engine.replication.is_failing_over());
     }
 }
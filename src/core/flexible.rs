@@ -2,94 +2,235 @@
 //! 
 //! This module implements the schema-less, document-oriented storage engine.
 //! It handles raw Key-Value pairs, complex collections (Lists, Sets, Hashes, ZSets),
-//! and native JSON path-based manipulation.
+//! Top-K sketches, striped counters, streams, HyperLogLogs, bitmaps, and
+//! native JSON path-based manipulation.
 //! 
-//! ## Concurrency
-//! All data is stored in `DashMap` instances, providing concurrent, lock-free 
-//! reads and fine-grained locking for writes. 
-//! 
-//! ## Eviction
-//! Uses an approximated **LRU (Least Recently Used)** policy. When `max_keys` 
-//! is reached, a random sample of 5 keys is taken, and the oldest based on 
-//! `last_accessed` timestamp is evicted.
+//! ## Storage
+//! Strings, lists, sets, hashes, JSON documents and HyperLogLogs (encoded as
+//! strings, see `core::hll`) are JSON values kept in a
+//! pluggable [`KvBackend`] chosen per database (see `core::backend`): in
+//! memory by default, with LRU eviction and the optional cold tier, or on
+//! disk. Sorted sets, Top-K sketches, striped counters, streams and bitmaps
+//! live in `DashMap`s of their own.
 
+use crate::core::backend::{self, KvBackend, MemoryBackend};
+use crate::core::bitmap::{self, BitOp};
+use crate::core::counter::StripedCounter;
+use crate::core::digest;
+use crate::core::geo;
+use crate::core::hll::Hll;
+use crate::core::schedule::{Schedule, ScheduledWrite};
+use crate::core::stream::{Fields, Stream, StreamId};
+use crate::core::structured::{Column, DataType, KV_TABLE, Table};
+use crate::core::types::UnifiedValue;
+use crate::core::topk::{self, TopK};
+use crate::net::parser::quote_string;
+use crate::net::resp::RespValue;
+use crate::query::ZAddFlags;
 use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::collections::HashMap;
 use std::sync::Arc;
-use std::time::{Instant, Duration};
+use std::time::Duration;
 
-// Internal entry to track access time
-#[derive(Clone)]
-struct Entry {
-    value: Value,
-    last_accessed: Instant,
+/// Top bit of a SCAN cursor: set once the backend is done and the sorted set,
+/// Top-K, counter, stream and bitmap maps are being visited.
+const LOCAL_CURSOR: u64 = 1 << 63;
+
+/// Error of a command run against a key holding another type.
+pub const WRONGTYPE: &str = "WRONGTYPE Operation against a key holding the wrong kind of value";
+
+/// The flexible keyspace as a snapshot holds it. Snapshots written before
+/// expiries, sorted sets and sketches were recorded only have `flexible_data`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct KeyspaceSnapshot {
+    #[serde(rename = "flexible_data")]
+    pub values: HashMap<String, Value>,
+    /// key -> unix ms at which it expires
+    #[serde(default)]
+    pub expirations: HashMap<String, u64>,
+    #[serde(default)]
+    pub sorted_sets: HashMap<String, Vec<(f64, String)>>,
+    #[serde(default)]
+    pub topks: HashMap<String, TopK>,
+    /// Striped counters (CINCR) by their total
+    #[serde(default)]
+    pub counters: HashMap<String, i64>,
+    #[serde(default)]
+    pub streams: HashMap<String, Stream>,
+    #[serde(default)]
+    pub bitmaps: HashMap<String, Vec<u8>>,
+    /// Writes held back by SCHEDULE
+    #[serde(default)]
+    pub scheduled: Vec<ScheduledWrite>,
+}
+
+/// The value of one key, in whichever store holds it (DUMP / RESTORE).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyValue {
+    Value(Value),
+    SortedSet(Vec<(f64, String)>),
+    TopK(TopK),
+    /// A striped counter, by its total
+    Counter(i64),
+    Stream(Stream),
+    Bitmap(Vec<u8>),
+}
+
+/// Keys as they were before a transaction, put back if it fails.
+pub struct KeySavepoint {
+    keys: Vec<SavedKey>,
+    scheduled: Vec<ScheduledWrite>,
+}
+
+/// One key in every map it can live in; `None` where it was missing.
+struct SavedKey {
+    key: String,
+    value: Option<(Value, Option<u64>)>,
+    sorted_set: Option<Vec<(f64, String)>>,
+    topk: Option<TopK>,
+    counter: Option<i64>,
+    stream: Option<Stream>,
+    bitmap: Option<Vec<u8>>,
 }
 
 /// The core storage engine for NoSQL data.
 #[derive(Clone)]
 pub struct FlexibleStore {
-    /// Primary data store: key -> {json_value, last_accessed}
-    data: Arc<DashMap<String, Entry>>,
-    /// Expiration tracking: key -> expiration_time
-    expiry: Arc<DashMap<String, Instant>>,
+    /// Keyspace of JSON values: key -> value, with expiry
+    backend: Arc<dyn KvBackend>,
     /// Sorted Set storage: key -> [(score, member)]
     sorted_sets: Arc<DashMap<String, Vec<(f64, String)>>>, 
-    /// Maximum keys before eviction kicks in
-    max_keys: usize,
+    /// Top-K storage: key -> sketch
+    topks: Arc<DashMap<String, TopK>>,
+    /// Striped counters: key -> cells
+    counters: Arc<DashMap<String, StripedCounter>>,
+    /// Streams: key -> entries
+    streams: Arc<DashMap<String, Stream>>,
+    /// Bitmaps: key -> bytes
+    bitmaps: Arc<DashMap<String, Vec<u8>>>,
+    /// Writes waiting for their time (SCHEDULE)
+    schedule: Arc<Schedule>,
+}
+
+impl Default for FlexibleStore {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl FlexibleStore {
+    /// A store on a plain in-memory backend.
     pub fn new() -> Self {
-        // limit from env or default
-        let max = std::env::var("DB_MAX_KEYS")
-            .unwrap_or("10000".to_string())
-            .parse()
-            .unwrap_or(10_000);
-            
+        Self::with_backend(Arc::new(MemoryBackend::new()))
+    }
+
+    pub fn with_backend(backend: Arc<dyn KvBackend>) -> Self {
         Self {
-            data: Arc::new(DashMap::new()),
-            expiry: Arc::new(DashMap::new()),
+            backend,
             sorted_sets: Arc::new(DashMap::new()),
-            max_keys: max,
+            topks: Arc::new(DashMap::new()),
+            counters: Arc::new(DashMap::new()),
+            streams: Arc::new(DashMap::new()),
+            bitmaps: Arc::new(DashMap::new()),
+            schedule: Arc::new(Schedule::default()),
         }
     }
 
-    fn evict_if_needed(&self) {
-        if self.data.len() >= self.max_keys {
-            // Approximated LRU: Sample 5 keys, evict oldest
-            // let mut rng = rand::rng();
-            // DashMap iter is locking per shard, we need to be careful.
-            // But we just need random keys.
-            // DashMap doesn't support random sampling efficiently without iteration.
-            // Iterating whole map is slow.
-            // Strategy: 
-            // 1. We just iterate and take first 5? No, that's not random (hash order).
-            //    Hash order is effectively random enough for this? Maybe.
-            // 2. Or assume we only need to evict *some* old key.
-            // Let's take the first 5 entries from the iterator (pseudo-random due to hash).
-            
-            let victim = self.data.iter()
-                .take(5)
-                .min_by_key(|entry| entry.value().last_accessed);
-            
-            if let Some(v) = victim {
-                let key = v.key().clone();
-                // Drop ref before remove to avoid deadlock if any
-                drop(v); 
-                self.data.remove(&key);
-                self.expiry.remove(&key);
-            }
+    pub fn backend(&self) -> &Arc<dyn KvBackend> {
+        &self.backend
+    }
+
+    /// Metrics for INFO, if the backend reports any.
+    pub fn backend_info(&self) -> Option<String> {
+        self.backend.info()
+    }
+
+    /// Rough RAM used per store: the backend keyspace, sorted sets, Top-K
+    /// sketches, striped counters, streams and bitmaps.
+    pub fn memory_bytes(&self) -> [(&'static str, usize); 6] {
+        let zsets = self.sorted_sets.iter().map(|kv| zset_size(kv.key(), kv.value())).sum();
+        let topks = self.topks.iter().map(|kv| kv.key().len() + kv.value().memory_bytes()).sum();
+        let counters = self.counters.iter().map(|kv| kv.key().len() + kv.value().memory_bytes()).sum();
+        let streams = self.streams.iter().map(|kv| kv.key().len() + kv.value().memory_bytes()).sum();
+        let bitmaps = self.bitmaps.iter().map(|kv| kv.key().len() + kv.value().len()).sum();
+        [("kv", self.backend.memory_bytes()), ("zset", zsets), ("topk", topks), ("counter", counters), ("stream", streams), ("bitmap", bitmaps)]
+    }
+
+    /// Keys per store, in the order of [`memory_bytes`](Self::memory_bytes).
+    pub fn key_counts(&self) -> [(&'static str, usize); 6] {
+        [
+            ("kv", self.backend.key_count()), ("zset", self.sorted_sets.len()), ("topk", self.topks.len()),
+            ("counter", self.counters.len()), ("stream", self.streams.len()), ("bitmap", self.bitmaps.len()),
+        ]
+    }
+
+    /// Approximate bytes of one key and its value (MEMORY USAGE).
+    pub fn memory_usage(&self, key: &str) -> Option<usize> {
+        if let Some(members) = self.sorted_sets.get(key) {
+            return Some(zset_size(key, &members));
+        }
+        if let Some(topk) = self.topks.get(key) {
+            return Some(key.len() + topk.memory_bytes());
+        }
+        if let Some(counter) = self.counters.get(key) {
+            return Some(key.len() + counter.memory_bytes());
+        }
+        if let Some(stream) = self.streams.get(key) {
+            return Some(key.len() + stream.memory_bytes());
+        }
+        if let Some(bytes) = self.bitmaps.get(key) {
+            return Some(key.len() + bytes.len());
         }
+        self.backend.memory_usage(key)
     }
 
+    /// True if the keyspace is full and its eviction policy cannot make room.
+    pub fn is_full(&self) -> bool {
+        self.backend.is_full()
+    }
+
+    /// Keys across the backend and the maps of the other types.
+    pub fn key_count(&self) -> usize {
+        self.key_counts().iter().map(|(_, n)| n).sum()
+    }
 
+    /// Runs `f` on a key's value slot under the backend's lock for that key.
+    fn update<R>(&self, key: &str, f: impl FnOnce(&mut Option<Value>) -> R) -> R {
+        let mut f = Some(f);
+        let mut out = None;
+        self.backend.update(key, &mut |value| {
+            if let Some(f) = f.take() {
+                out = Some(f(value));
+            }
+        });
+        out.expect("backend calls update closure")
+    }
+
+    /// Runs `f` on a key's value, if the key exists.
+    fn read<R>(&self, key: &str, f: impl FnOnce(&Value) -> R) -> Option<R> {
+        let mut f = Some(f);
+        let mut out = None;
+        self.backend.inspect(key, &mut |value| {
+            if let (Some(value), Some(f)) = (value, f.take()) {
+                out = Some(f(value));
+            }
+        });
+        out
+    }
 
     pub fn del(&self, keys: &[String]) -> usize {
         let mut count = 0;
         for key in keys {
-            if self.data.remove(key).is_some() {
-                self.expiry.remove(key);
-                self.sorted_sets.remove(key);
+            let removed = self.backend.del(key);
+            let zset = self.sorted_sets.remove(key).is_some();
+            let topk = self.topks.remove(key).is_some();
+            let counter = self.counters.remove(key).is_some();
+            let stream = self.streams.remove(key).is_some();
+            let bitmap = self.bitmaps.remove(key).is_some();
+            if removed || zset || topk || counter || stream || bitmap {
                 count += 1;
             }
         }
@@ -97,174 +238,126 @@ impl FlexibleStore {
     }
 
     pub fn set(&self, key: String, value: Value) {
-        if !self.data.contains_key(&key) {
-            self.evict_if_needed();
-        }
-        
-        let entry = Entry {
-            value,
-            last_accessed: Instant::now(),
-        };
-        self.data.insert(key.clone(), entry);
-        self.expiry.remove(&key); 
+        self.backend.set(&key, value, None);
     }
 
-    pub fn set_with_ttl(&self, key: String, value: Value, ttl_secs: u64) {
-        if !self.data.contains_key(&key) {
-            self.evict_if_needed();
-        }
+    /// Stores a value expiring after `ttl_secs`, stretched by up to `jitter`
+    /// percent.
+    pub fn set_with_ttl(&self, key: String, value: Value, ttl_secs: u64, jitter: u8) {
+        let at = backend::now_unix_ms() + self.jittered(ttl_secs, jitter).as_millis() as u64;
+        self.backend.set(&key, value, Some(at));
+    }
 
-        let entry = Entry {
-            value,
-            last_accessed: Instant::now(),
-        };
-        self.data.insert(key.clone(), entry);
-        self.expiry.insert(key, Instant::now() + Duration::from_secs(ttl_secs));
+    /// Sets a relative expiry (EXPIRE), with the same jitter as `set_with_ttl`.
+    /// Returns false if the key does not exist.
+    pub fn expire(&self, key: &str, ttl_secs: u64, jitter: u8) -> bool {
+        let at = backend::now_unix_ms() + self.jittered(ttl_secs, jitter).as_millis() as u64;
+        self.backend.set_ttl(key, Some(at))
     }
 
-    pub fn get(&self, key: &str) -> Option<Value> {
-        // Check if expired
-        if let Some(exp) = self.expiry.get(key) {
-            if Instant::now() > *exp {
-                self.data.remove(key);
-                self.expiry.remove(key);
-                return None;
-            }
+    /// Adds a random 0..=`percent`% to a TTL, so keys written in a burst do not
+    /// all expire in the same instant.
+    fn jittered(&self, ttl_secs: u64, percent: u8) -> Duration {
+        let ttl = Duration::from_secs(ttl_secs);
+        let percent = percent.min(100) as u64;
+        let max_extra_ms = (ttl.as_millis() as u64).saturating_mul(percent) / 100;
+        if max_extra_ms == 0 {
+            return ttl;
         }
-        
-        // Update last_accessed
-        if let Some(mut entry) = self.data.get_mut(key) {
-             entry.last_accessed = Instant::now();
-             return Some(entry.value.clone());
-        }
-        None
+        ttl + Duration::from_millis(rand::random_range(0..=max_extra_ms))
+    }
+
+    /// The value of a key; a striped counter reads as its total.
+    pub fn get(&self, key: &str) -> Option<Value> {
+        self.backend.get(key).or_else(|| self.counters.get(key).map(|c| Value::from(c.total())))
     }
     
     #[allow(dead_code)]
     pub fn delete(&self, key: &str) {
-        self.data.remove(key);
-        self.expiry.remove(key);
+        self.backend.del(key);
+    }
+
+    /// Sets an absolute expiry expressed as Unix time in milliseconds (PEXPIREAT).
+    /// A timestamp in the past deletes the key. Returns false if the key does not exist.
+    pub fn expire_at(&self, key: &str, unix_ms: u64) -> bool {
+        self.backend.set_ttl(key, Some(unix_ms))
+    }
+
+    /// Sets a relative expiry in milliseconds, without jitter (GETEX EX/PX).
+    /// Returns false if the key does not exist.
+    pub fn pexpire(&self, key: &str, ttl_ms: u64) -> bool {
+        self.backend.set_ttl(key, Some(backend::now_unix_ms() + ttl_ms))
+    }
+
+    /// Removes a key's expiry. Returns false if it had none.
+    pub fn persist(&self, key: &str) -> bool {
+        matches!(self.backend.ttl(key), Some(Some(_))) && self.backend.set_ttl(key, None)
+    }
+
+    /// Absolute expiry of a key as Unix time in milliseconds, if it has one.
+    pub fn expire_time_ms(&self, key: &str) -> Option<u64> {
+        self.backend.ttl(key).flatten()
     }
 
     pub fn ttl(&self, key: &str) -> Option<i64> {
-        if let Some(exp) = self.expiry.get(key) {
-            let remaining = exp.saturating_duration_since(Instant::now());
-            Some(remaining.as_secs() as i64)
-        } else {
-            if self.data.contains_key(key) {
-                Some(-1) 
-            } else {
-                Some(-2) // Missing
-            }
-        }
+        Some(match self.backend.ttl(key) {
+            Some(Some(at)) => (at.saturating_sub(backend::now_unix_ms()) / 1000) as i64,
+            Some(None) => -1,
+            None => -2, // Missing
+        })
     }
 
-    pub fn incr(&self, key: &str) -> i64 {
-        let mut val = 0i64;
-        
-        // Optimistic update pattern or lock?
-        // DashMap get_mut locks the shard.
-        // We need to handle entry existence and eviction if new.
-        
-        if !self.data.contains_key(key) {
-            self.evict_if_needed();
-            let entry = Entry {
-                value: serde_json::Value::Number(1.into()),
-                last_accessed: Instant::now(),
-            };
-            self.data.insert(key.to_string(), entry);
-            return 1;
-        }
-
-        // Update existing
-        if let Some(mut entry) = self.data.get_mut(key) {
-             if let Some(n) = entry.value.as_i64() {
-                 val = n;
-             }
-             val += 1;
-             entry.value = serde_json::Value::Number(val.into());
-             entry.last_accessed = Instant::now();
-        }
-        val
-    }
-
-    pub fn decr(&self, key: &str) -> i64 {
-        let mut val = 0i64;
-        
-        if !self.data.contains_key(key) {
-            self.evict_if_needed();
-            let entry = Entry {
-                value: serde_json::Value::Number((-1).into()),
-                last_accessed: Instant::now(),
-            };
-            self.data.insert(key.to_string(), entry);
-            return -1;
-        }
+    pub fn incr(&self, key: &str) -> Result<i64, String> {
+        self.add(key, 1)
+    }
 
-        if let Some(mut entry) = self.data.get_mut(key) {
-             if let Some(n) = entry.value.as_i64() {
-                 val = n;
-             }
-             val -= 1;
-             entry.value = serde_json::Value::Number(val.into());
-             entry.last_accessed = Instant::now();
+    pub fn decr(&self, key: &str) -> Result<i64, String> {
+        self.add(key, -1)
+    }
+
+    // Non-integer values count as 0; striped counters take CINCR
+    fn add(&self, key: &str, delta: i64) -> Result<i64, String> {
+        if self.counters.contains_key(key) {
+            return Err(WRONGTYPE.to_string());
         }
-        val
+        Ok(self.update(key, |slot| {
+            let val = slot.as_ref().and_then(Value::as_i64).unwrap_or(0) + delta;
+            *slot = Some(Value::Number(val.into()));
+            val
+        }))
     }
 
     // LISTS
     pub fn lpush(&self, key: &str, values: Vec<String>) -> usize {
-        self.evict_if_needed();
-        
-        // Ensure key exists as Array or create new
-        if !self.data.contains_key(key) {
-             let entry = Entry {
-                value: Value::Array(Vec::new()),
-                last_accessed: Instant::now(),
-            };
-            self.data.insert(key.to_string(), entry);
-        }
-
-        if let Some(mut entry) = self.data.get_mut(key) {
-            entry.last_accessed = Instant::now();
-            if let Some(arr) = entry.value.as_array_mut() {
-                for v in values {
-                    arr.insert(0, Value::String(v));
+        self.update(key, |slot| {
+            match slot.get_or_insert_with(|| Value::Array(Vec::new())).as_array_mut() {
+                Some(arr) => {
+                    for v in values {
+                        arr.insert(0, Value::String(v));
+                    }
+                    arr.len()
                 }
-                return arr.len();
+                None => 0,
             }
-        }
-        0
+        })
     }
 
     pub fn rpush(&self, key: &str, values: Vec<String>) -> usize {
-        self.evict_if_needed();
-        
-        if !self.data.contains_key(key) {
-             let entry = Entry {
-                value: Value::Array(Vec::new()),
-                last_accessed: Instant::now(),
-            };
-            self.data.insert(key.to_string(), entry);
-        }
-
-        if let Some(mut entry) = self.data.get_mut(key) {
-            entry.last_accessed = Instant::now();
-            if let Some(arr) = entry.value.as_array_mut() {
-                for v in values {
-                    arr.push(Value::String(v));
+        self.update(key, |slot| {
+            match slot.get_or_insert_with(|| Value::Array(Vec::new())).as_array_mut() {
+                Some(arr) => {
+                    arr.extend(values.into_iter().map(Value::String));
+                    arr.len()
                 }
-                return arr.len();
+                None => 0,
             }
-        }
-        0
+        })
     }
 
     pub fn lpop(&self, key: &str, count: usize) -> Vec<String> {
-        let mut res = Vec::new();
-        if let Some(mut entry) = self.data.get_mut(key) {
-            entry.last_accessed = Instant::now();
-            if let Some(arr) = entry.value.as_array_mut() {
+        self.update(key, |slot| {
+            let mut res = Vec::new();
+            if let Some(arr) = slot.as_mut().and_then(Value::as_array_mut) {
                 for _ in 0..count {
                     if !arr.is_empty() {
                         if let Value::String(s) = arr.remove(0) {
@@ -275,15 +368,14 @@ impl FlexibleStore {
                     }
                 }
             }
-        }
-        res
+            res
+        })
     }
 
     pub fn rpop(&self, key: &str, count: usize) -> Vec<String> {
-        let mut res = Vec::new();
-        if let Some(mut entry) = self.data.get_mut(key) {
-            entry.last_accessed = Instant::now();
-            if let Some(arr) = entry.value.as_array_mut() {
+        self.update(key, |slot| {
+            let mut res = Vec::new();
+            if let Some(arr) = slot.as_mut().and_then(Value::as_array_mut) {
                 for _ in 0..count {
                      if let Some(Value::String(s)) = arr.pop() {
                         res.push(s);
@@ -292,78 +384,59 @@ impl FlexibleStore {
                      }
                 }
             }
-        }
-        res
+            res
+        })
     }
 
     pub fn lrange(&self, key: &str, start: i64, stop: i64) -> Vec<String> {
-        if let Some(mut entry) = self.data.get_mut(key) {
-            entry.last_accessed = Instant::now();
-            if let Some(arr) = entry.value.as_array() {
-                let len = arr.len() as i64;
-                if len == 0 { return Vec::new(); }
-
-                let start_idx = if start < 0 { (len + start).max(0) } else { start };
-                let stop_idx = if stop < 0 { (len + stop).max(0) } else { stop };
-                
-                let start_idx = (start_idx as usize).min(arr.len());
-                let stop_idx = (stop_idx as usize).min(arr.len().saturating_sub(1)); // inclusive stop conventional in redis
-
-                if start_idx > stop_idx { return Vec::new(); }
-
-                let mut res = Vec::new();
-                for i in start_idx..=stop_idx {
-                    if let Some(Value::String(s)) = arr.get(i) {
-                        res.push(s.clone());
-                    }
+        self.read(key, |value| {
+            let Some(arr) = value.as_array() else { return Vec::new() };
+            let len = arr.len() as i64;
+            if len == 0 { return Vec::new(); }
+
+            let start_idx = if start < 0 { (len + start).max(0) } else { start };
+            let stop_idx = if stop < 0 { (len + stop).max(0) } else { stop };
+
+            let start_idx = (start_idx as usize).min(arr.len());
+            let stop_idx = (stop_idx as usize).min(arr.len().saturating_sub(1)); // inclusive stop conventional in redis
+
+            if start_idx > stop_idx { return Vec::new(); }
+
+            let mut res = Vec::new();
+            for i in start_idx..=stop_idx {
+                if let Some(Value::String(s)) = arr.get(i) {
+                    res.push(s.clone());
                 }
-                return res;
             }
-        }
-        Vec::new()
+            res
+        }).unwrap_or_default()
     }
 
     // HASHES
     pub fn hset(&self, key: &str, field: String, value: String) -> usize {
-        self.evict_if_needed();
-        
-        if !self.data.contains_key(key) {
-             let entry = Entry {
-                value: Value::Object(serde_json::Map::new()),
-                last_accessed: Instant::now(),
-            };
-            self.data.insert(key.to_string(), entry);
-        }
-
-        if let Some(mut entry) = self.data.get_mut(key) {
-            entry.last_accessed = Instant::now();
-            if let Some(obj) = entry.value.as_object_mut() {
-                let is_new = !obj.contains_key(&field);
-                obj.insert(field, Value::String(value));
-                return if is_new { 1 } else { 0 };
+        self.update(key, |slot| {
+            match slot.get_or_insert_with(|| Value::Object(serde_json::Map::new())).as_object_mut() {
+                Some(obj) => {
+                    let is_new = !obj.contains_key(&field);
+                    obj.insert(field, Value::String(value));
+                    if is_new { 1 } else { 0 }
+                }
+                None => 0,
             }
-        }
-        0
+        })
     }
 
     pub fn hget(&self, key: &str, field: &str) -> Option<String> {
-        if let Some(mut entry) = self.data.get_mut(key) {
-            entry.last_accessed = Instant::now();
-            if let Some(obj) = entry.value.as_object() {
-                if let Some(Value::String(s)) = obj.get(field) {
-                    return Some(s.clone());
-                }
-            }
-        }
-        None
+        self.read(key, |value| {
+            value.as_object()?.get(field)?.as_str().map(String::from)
+        }).flatten()
     }
 
     pub fn hgetall(&self, key: &str) -> Vec<String> {
         // Returns [field1, val1, field2, val2...]
-        let mut res = Vec::new();
-        if let Some(mut entry) = self.data.get_mut(key) {
-            entry.last_accessed = Instant::now();
-            if let Some(obj) = entry.value.as_object() {
+        self.read(key, |value| {
+            let mut res = Vec::new();
+            if let Some(obj) = value.as_object() {
                 for (k, v) in obj {
                     if let Value::String(s) = v {
                         res.push(k.clone());
@@ -371,25 +444,15 @@ impl FlexibleStore {
                     }
                 }
             }
-        }
-        res
+            res
+        }).unwrap_or_default()
     }
 
     // SETS
     pub fn sadd(&self, key: &str, values: Vec<String>) -> usize {
-        self.evict_if_needed();
-         if !self.data.contains_key(key) {
-             let entry = Entry {
-                value: Value::Array(Vec::new()),
-                last_accessed: Instant::now(),
-            };
-            self.data.insert(key.to_string(), entry);
-        }
-
-        let mut added = 0;
-        if let Some(mut entry) = self.data.get_mut(key) {
-            entry.last_accessed = Instant::now();
-            if let Some(arr) = entry.value.as_array_mut() {
+        self.update(key, |slot| {
+            let mut added = 0;
+            if let Some(arr) = slot.get_or_insert_with(|| Value::Array(Vec::new())).as_array_mut() {
                 for v in values {
                     // Check existence (O(N) for JSON Array)
                     // Ideally use HashSet but we are backed by JSON Value
@@ -400,33 +463,76 @@ impl FlexibleStore {
                     }
                 }
             }
-        }
-        added
+            added
+        })
     }
 
     pub fn smembers(&self, key: &str) -> Vec<String> {
-        let mut res = Vec::new();
-        if let Some(mut entry) = self.data.get_mut(key) {
-            entry.last_accessed = Instant::now();
-            if let Some(arr) = entry.value.as_array() {
-                for v in arr {
-                    if let Value::String(s) = v {
-                        res.push(s.clone());
-                    }
-                }
-            }
-        }
-        res
+        self.read(key, |value| {
+            value.as_array()
+                .map(|arr| arr.iter().filter_map(Value::as_str).map(String::from).collect())
+                .unwrap_or_default()
+        }).unwrap_or_default()
+    }
+
+    // SCHEDULED WRITES
+    /// Queues `write` until its time (SCHEDULE); see `core::schedule` for
+    /// how it is delivered.
+    pub fn schedule(&self, write: ScheduledWrite) {
+        self.schedule.add(write);
+    }
+
+    /// Cancels pending writes of a key (UNSCHEDULE). Returns how many.
+    pub fn unschedule(&self, key: &str, at_ms: Option<u64>) -> usize {
+        self.schedule.cancel(key, at_ms)
+    }
+
+    /// Hands the scheduled writes that are due to `deliver`, oldest first.
+    pub fn deliver_due(&self, deliver: impl FnMut(ScheduledWrite)) {
+        self.schedule.deliver_due(backend::now_unix_ms(), deliver);
+    }
+
+    /// `# Schedule` section of INFO.
+    pub fn schedule_info(&self) -> String {
+        self.schedule.info(backend::now_unix_ms())
     }
 
     // SORTED SETS (ZSET)
-    pub fn zadd(&self, key: &str, score: f64, member: String) -> i64 {
+    /// Adds or updates members in order. Returns the number of members added,
+    /// or added plus updated with `CH`.
+    pub fn zadd(&self, key: &str, members: Vec<(f64, String)>, flags: ZAddFlags) -> i64 {
         let mut entry = self.sorted_sets.entry(key.to_string()).or_insert_with(Vec::new);
-        // Remove existing member if present
-        entry.retain(|(_, m)| m != &member);
-        entry.push((score, member));
+        let mut added = 0;
+        let mut changed = 0;
+        for (score, member) in members {
+            match entry.iter().position(|(_, m)| m == &member) {
+                Some(pos) => {
+                    let current = entry[pos].0;
+                    if flags.nx || (flags.gt && score <= current) || (flags.lt && score >= current) {
+                        continue;
+                    }
+                    if score != current {
+                        entry[pos].0 = score;
+                        changed += 1;
+                    }
+                }
+                None => {
+                    if flags.xx {
+                        continue;
+                    }
+                    entry.push((score, member));
+                    added += 1;
+                }
+            }
+        }
         entry.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
-        1
+        let empty = entry.is_empty();
+        drop(entry);
+        // XX on a missing key must not leave an empty set behind
+        if empty {
+            self.sorted_sets.remove_if(key, |_, v| v.is_empty());
+        }
+        if flags.ch { added + changed } else { added }
     }
 
     pub fn zrange(&self, key: &str, start: i64, stop: i64) -> Vec<String> {
@@ -446,165 +552,932 @@ impl FlexibleStore {
         None
     }
 
+    /// Removes and returns up to `count` members with the lowest scores, or
+    /// the highest with `max`, in pop order. An emptied set is deleted.
+    pub fn zpop(&self, key: &str, count: usize, max: bool) -> Vec<(String, f64)> {
+        let Some(mut entry) = self.sorted_sets.get_mut(key) else {
+            return Vec::new();
+        };
+        let n = count.min(entry.len());
+        let popped: Vec<(f64, String)> = if max {
+            let at = entry.len() - n;
+            entry.drain(at..).rev().collect()
+        } else {
+            entry.drain(..n).collect()
+        };
+        let empty = entry.is_empty();
+        drop(entry);
+        if empty {
+            self.sorted_sets.remove_if(key, |_, v| v.is_empty());
+        }
+        popped.into_iter().map(|(score, member)| (member, score)).collect()
+    }
+
+    // GEOSPATIAL
+    /// Adds or moves located members, scored by the geohash of valid
+    /// coordinates. Returns what [`zadd`](Self::zadd) does.
+    pub fn geoadd(&self, key: &str, members: Vec<(f64, f64, String)>, flags: ZAddFlags) -> i64 {
+        let members = members.into_iter().map(|(lon, lat, member)| (geo::encode(lon, lat), member)).collect();
+        self.zadd(key, members, flags)
+    }
+
+    /// Longitude and latitude of a member, if its score is a geohash.
+    pub fn geopos(&self, key: &str, member: &str) -> Option<(f64, f64)> {
+        self.zscore(key, member).and_then(geo::decode)
+    }
+
+    /// Members within `radius_m` meters of a point, with their distance in
+    /// meters and their position, in no particular order.
+    pub fn geosearch(&self, key: &str, lon: f64, lat: f64, radius_m: f64) -> Vec<(String, f64, (f64, f64))> {
+        let Some(entry) = self.sorted_sets.get(key) else {
+            return Vec::new();
+        };
+        let mut found = Vec::new();
+        for (min, max) in geo::search_ranges(lon, lat, radius_m) {
+            let start = entry.partition_point(|(score, _)| *score < min);
+            for (score, member) in entry[start..].iter().take_while(|(score, _)| *score < max) {
+                let Some(pos) = geo::decode(*score) else { continue };
+                let dist = geo::distance(lon, lat, pos.0, pos.1);
+                if dist <= radius_m {
+                    found.push((member.clone(), dist, pos));
+                }
+            }
+        }
+        found
+    }
+
+    // TOP-K
+    /// Creates an empty Top-K sketch. Fails if the key already holds one, or
+    /// a value of another type.
+    pub fn topk_reserve(&self, key: &str, k: usize, width: usize, depth: usize) -> Result<(), String> {
+        if !matches!(self.key_type(key), "none" | "topk") {
+            return Err(WRONGTYPE.to_string());
+        }
+        match self.topks.entry(key.to_string()) {
+            dashmap::Entry::Occupied(_) => Err("Key already exists".to_string()),
+            dashmap::Entry::Vacant(slot) => {
+                slot.insert(TopK::new(k, width, depth)?);
+                Ok(())
+            }
+        }
+    }
+
+    /// Counts items (with their increments), creating a default sketch if the
+    /// key is missing. Returns the items pushed out of the top list.
+    pub fn topk_add(&self, key: &str, items: Vec<(String, u64)>) -> Result<Vec<String>, String> {
+        if !matches!(self.key_type(key), "none" | "topk") {
+            return Err(WRONGTYPE.to_string());
+        }
+        let mut entry = self.topks.entry(key.to_string()).or_insert_with(|| {
+            TopK::new(topk::DEFAULT_K, topk::DEFAULT_WIDTH, topk::DEFAULT_DEPTH).expect("valid defaults")
+        });
+        Ok(items.iter().filter_map(|(item, incr)| entry.add(item, *incr)).collect())
+    }
+
+    pub fn topk_list(&self, key: &str) -> Vec<(String, u64)> {
+        self.topks.get(key).map(|t| t.list()).unwrap_or_default()
+    }
+
+    // STRIPED COUNTERS
+    /// Adds `delta` to a striped counter, creating it at 0 if the key is
+    /// free, and returns its total.
+    pub fn cincr(&self, key: &str, delta: i64) -> Result<i64, String> {
+        if let Some(total) = self.cincr_existing(key, delta) {
+            return Ok(total);
+        }
+        if self.key_type(key) != "none" {
+            return Err(WRONGTYPE.to_string());
+        }
+        Ok(self.counters.entry(key.to_string()).or_insert_with(|| StripedCounter::new(0)).add(delta))
+    }
+
+    /// Adds `delta` to a striped counter that exists, under a shared lock of
+    /// its shard only. `None` if there is no such counter.
+    pub fn cincr_existing(&self, key: &str, delta: i64) -> Option<i64> {
+        self.counters.get(key).map(|counter| counter.add(delta))
+    }
+
+    // HYPERLOGLOG
+    /// Adds elements to an HLL, creating it if missing. Returns true if it
+    /// was created or its estimate may have changed.
+    pub fn pfadd(&self, key: &str, elements: &[String]) -> Result<bool, String> {
+        self.update(key, |slot| {
+            let (mut hll, created) = match slot {
+                None => (Hll::default(), true),
+                Some(value) => (decode_hll(value)?, false),
+            };
+            let mut changed = created;
+            for element in elements {
+                changed |= hll.add(element);
+            }
+            if changed {
+                *slot = Some(Value::String(hll.encode()));
+            }
+            Ok(changed)
+        })
+    }
+
+    /// Estimated distinct elements across the HLLs of `keys`; missing keys
+    /// count as empty.
+    pub fn pfcount(&self, keys: &[String]) -> Result<u64, String> {
+        let mut union = Hll::default();
+        for key in keys {
+            if let Some(hll) = self.read(key, decode_hll) {
+                union.merge(&hll?);
+            }
+        }
+        Ok(union.count())
+    }
+
+    /// Merges the HLLs of `sources` into `dest`, keeping what `dest` held.
+    pub fn pfmerge(&self, dest: &str, sources: &[String]) -> Result<(), String> {
+        let mut union = Hll::default();
+        for key in sources {
+            if let Some(hll) = self.read(key, decode_hll) {
+                union.merge(&hll?);
+            }
+        }
+        self.update(dest, |slot| {
+            if let Some(value) = slot {
+                union.merge(&decode_hll(value)?);
+            }
+            *slot = Some(Value::String(union.encode()));
+            Ok(())
+        })
+    }
+
+    // BITMAPS
+    /// Sets or clears one bit, creating the bitmap if missing. Returns the
+    /// bit's previous value.
+    pub fn setbit(&self, key: &str, offset: u64, value: bool) -> bool {
+        bitmap::set_bit(&mut self.bitmaps.entry(key.to_string()).or_default(), offset, value)
+    }
+
+    pub fn getbit(&self, key: &str, offset: u64) -> bool {
+        self.bitmaps.get(key).is_some_and(|bytes| bitmap::get_bit(&bytes, offset))
+    }
+
+    /// Set bits of a bitmap, over a byte (or `bits`) range if given (BITCOUNT).
+    pub fn bitcount(&self, key: &str, range: Option<(i64, i64)>, bits: bool) -> u64 {
+        self.bitmaps.get(key).map_or(0, |bytes| bitmap::count(&bytes, range, bits))
+    }
+
+    /// Stores `op` of the `sources` bitmaps in `dest` and returns its length
+    /// in bytes (BITOP). An empty result deletes `dest`.
+    pub fn bitop(&self, op: BitOp, dest: &str, sources: &[String]) -> usize {
+        let sources: Vec<Vec<u8>> = sources.iter().map(|key| self.bitmaps.get(key).map(|b| b.clone()).unwrap_or_default()).collect();
+        let result = bitmap::combine(op, &sources);
+        let len = result.len();
+        self.del(&[dest.to_string()]);
+        if len > 0 {
+            self.bitmaps.insert(dest.to_string(), result);
+        }
+        len
+    }
+
+    // STREAMS
+    /// Appends an entry to a stream, creating it if missing, and returns the
+    /// ID it got (see [`Stream::add`] for `id`).
+    pub fn xadd(&self, key: &str, id: &str, fields: Fields) -> Result<StreamId, String> {
+        let mut entry = self.streams.entry(key.to_string()).or_default();
+        let added = entry.add(id, fields, backend::now_unix_ms());
+        let empty = entry.is_empty();
+        drop(entry);
+        // A refused first entry must not leave an empty stream behind
+        if empty {
+            self.streams.remove_if(key, |_, v| v.is_empty());
+        }
+        added
+    }
+
+    /// Entries from `start` to `end`, both included (XRANGE).
+    pub fn xrange(&self, key: &str, start: StreamId, end: StreamId, count: Option<usize>) -> Vec<(StreamId, Fields)> {
+        self.streams.get(key).map(|s| s.range(start, end, count)).unwrap_or_default()
+    }
+
+    /// Entries after `after` (XREAD).
+    pub fn xread(&self, key: &str, after: StreamId, count: Option<usize>) -> Vec<(StreamId, Fields)> {
+        self.streams.get(key).map(|s| s.read_after(after, count)).unwrap_or_default()
+    }
+
+    pub fn xlen(&self, key: &str) -> usize {
+        self.streams.get(key).map_or(0, |s| s.len())
+    }
+
+    /// ID of the last entry of a stream, `0-0` if there is none (XREAD `$`).
+    pub fn stream_last_id(&self, key: &str) -> StreamId {
+        self.streams.get(key).map_or(StreamId::MIN, |s| s.last_id())
+    }
+
     // JSON PATH
     pub fn json_get(&self, key: &str, path: Option<&str>) -> Option<String> {
-        if let Some(mut entry) = self.data.get_mut(key) {
-            entry.last_accessed = Instant::now();
-            
-            let mut current = &entry.value;
+        self.read(key, |value| {
+            let mut current = value;
             if let Some(p) = path {
                 // Simple path traversal: key->key1
-                let parts: Vec<&str> = p.split("->").filter(|s| !s.is_empty()).collect();
-                
-                for part in parts {
-                    match current {
-                        Value::Object(map) => {
-                            if let Some(v) = map.get(part) {
-                                current = v;
-                            } else {
-                                return None;
-                            }
-                        },
-                        Value::Array(arr) => {
-                            if let Ok(idx) = part.parse::<usize>() {
-                                if let Some(v) = arr.get(idx) {
-                                    current = v;
-                                } else {
-                                    return None;
-                                }
-                            } else {
-                                return None;
-                            }
-                        },
+                for part in p.split("->").filter(|s| !s.is_empty()) {
+                    current = match current {
+                        Value::Object(map) => map.get(part)?,
+                        Value::Array(arr) => arr.get(part.parse::<usize>().ok()?)?,
                         _ => return None,
-                    }
+                    };
                 }
             }
-            
-            // Return as stringified JSON or raw string if it is a string?
-            // Redis JSON returns stringified JSON usually.
-            // If it's a string, we might want raw string? Consistency says JSON string representation.
-            return Some(current.to_string());
-        }
-        None
+            // Stringified JSON, like RedisJSON
+            Some(current.to_string())
+        }).flatten()
     }
 
     pub fn json_set(&self, key: &str, path: &str, value: Value) -> usize {
-        self.evict_if_needed();
-        
-        // Ensure root exists (default to object)
-        if !self.data.contains_key(key) {
-             let entry = Entry {
-                // If path is root "." or empty, we replace root. 
-                // But usually JSON.SET root requires object? 
-                // We default to flexible Set logic which overwrites root.
-                // Assuming we are updating partial.
-                value: Value::Object(serde_json::Map::new()),
-                last_accessed: Instant::now(),
-            };
-            self.data.insert(key.to_string(), entry);
-        }
+        self.update(key, |slot| {
+            // A missing key starts as an empty object
+            let root = slot.get_or_insert_with(|| Value::Object(serde_json::Map::new()));
 
-        if let Some(mut entry) = self.data.get_mut(key) {
-            entry.last_accessed = Instant::now();
-            
             let parts: Vec<&str> = path.split("->").filter(|s| !s.is_empty()).collect();
-            if parts.is_empty() {
+            let Some((last_part, parents)) = parts.split_last() else {
                 // Replace root
-                entry.value = value;
+                *root = value;
                 return 1;
-            }
+            };
 
             // Use JSON Pointer syntax for nested updates
-            let ptr_path = format!("/{}", parts.join("/"));
-            if let Some(target) = entry.value.pointer_mut(&ptr_path) {
+            if let Some(target) = root.pointer_mut(&format!("/{}", parts.join("/"))) {
                 *target = value;
                 return 1;
             }
-            
+
             // Path doesn't exist - try to create the last segment if parent exists
-            if parts.len() >= 1 {
-                let parent_path = if parts.len() == 1 {
-                    String::new() // Root
-                } else {
-                    format!("/{}", parts[..parts.len()-1].join("/"))
-                };
-                let last_part = parts[parts.len()-1];
-                
-                let parent = if parent_path.is_empty() {
-                    Some(&mut entry.value)
-                } else {
-                    entry.value.pointer_mut(&parent_path)
-                };
-                
-                if let Some(p) = parent {
-                    if let Some(obj) = p.as_object_mut() {
-                        obj.insert(last_part.to_string(), value);
-                        return 1;
-                    }
-                }
+            let parent = if parents.is_empty() {
+                Some(root)
+            } else {
+                root.pointer_mut(&format!("/{}", parents.join("/")))
+            };
+            if let Some(obj) = parent.and_then(Value::as_object_mut) {
+                obj.insert(last_part.to_string(), value);
+                return 1;
+            }
+            0
+        })
+    }
+
+    // KEYSPACE INTROSPECTION
+
+    /// Incrementally iterates the keyspace (SCAN).
+    ///
+    /// Cursors below 2^63 belong to the backend. Once it is done, the cursor
+    /// has its top bit set and counts the shards of the sorted set, Top-K,
+    /// counter and stream maps, which are read-locked one at a time. Every key that exists for the
+    /// whole iteration is returned at least once; a returned cursor of 0 means
+    /// the iteration is complete.
+    pub fn scan(&self, cursor: u64, pattern: Option<&str>, count: usize) -> (u64, Vec<String>) {
+        let mut keys = Vec::new();
+        let mut part = 0;
+        if cursor & LOCAL_CURSOR == 0 {
+            let (next, batch) = self.backend.scan(cursor, pattern, count);
+            if next != 0 {
+                return (next, batch);
+            }
+            keys = batch;
+        } else {
+            part = (cursor & !LOCAL_CURSOR) as usize;
+        }
+
+        // Sorted sets, Top-K sketches, counters, streams, then bitmaps
+        let total_parts = 5 * backend::SCAN_PARTS;
+        while part < total_parts && keys.len() < count.max(1) {
+            let (map, from) = (part / backend::SCAN_PARTS, part % backend::SCAN_PARTS);
+            let wanted = count.max(1) - keys.len();
+            let (part_keys, next) = match map {
+                0 => backend::scan_parts(&self.sorted_sets, from, wanted),
+                1 => backend::scan_parts(&self.topks, from, wanted),
+                2 => backend::scan_parts(&self.counters, from, wanted),
+                3 => backend::scan_parts(&self.streams, from, wanted),
+                _ => backend::scan_parts(&self.bitmaps, from, wanted),
+            };
+            keys.extend(part_keys.into_iter().filter(|k| pattern.is_none_or(|p| crate::core::glob::glob_match(p, k))));
+            part = map * backend::SCAN_PARTS + next;
+        }
+
+        let next = if part >= total_parts { 0 } else { LOCAL_CURSOR | part as u64 };
+        (next, keys)
+    }
+
+    /// Returns every live key matching `pattern` (KEYS). Walks the whole keyspace.
+    pub fn keys(&self, pattern: &str) -> Vec<String> {
+        // A key being offloaded to the cold tier can briefly be listed twice
+        let mut seen = std::collections::HashSet::new();
+        let mut out = Vec::new();
+        let mut cursor = 0;
+        loop {
+            let (next, batch) = self.scan(cursor, Some(pattern), usize::MAX);
+            out.extend(batch.into_iter().filter(|k| seen.insert(k.clone())));
+            if next == 0 {
+                break;
             }
-            return 0;
+            cursor = next;
+        }
+        out
+    }
+
+    /// Reports the logical type of a key (TYPE).
+    /// Sets share the JSON array representation of lists and report as "list".
+    pub fn key_type(&self, key: &str) -> &'static str {
+        if self.sorted_sets.contains_key(key) {
+            return "zset";
+        }
+        if self.topks.contains_key(key) {
+            return "topk";
         }
-        0
+        if self.counters.contains_key(key) {
+            return "counter";
+        }
+        if self.streams.contains_key(key) {
+            return "stream";
+        }
+        if self.bitmaps.contains_key(key) {
+            return "bitmap";
+        }
+        self.read(key, |value| match value {
+            Value::Array(_) => "list",
+            Value::Object(_) => "hash",
+            _ => "string",
+        }).unwrap_or("none")
+    }
+
+    /// Counts how many of the given keys exist (EXISTS). Repeated keys count repeatedly.
+    pub fn exists(&self, keys: &[String]) -> usize {
+        keys.iter()
+            .filter(|k| {
+                self.backend.contains(k)
+                    || self.sorted_sets.contains_key(k.as_str())
+                    || self.topks.contains_key(k.as_str())
+                    || self.counters.contains_key(k.as_str())
+                    || self.streams.contains_key(k.as_str())
+                    || self.bitmaps.contains_key(k.as_str())
+            })
+            .count()
     }
 
     // For Snapshotting
-    pub fn export(&self) -> std::collections::HashMap<String, Value> {
-        self.data.iter().map(|kv| (kv.key().clone(), kv.value().value.clone())).collect()
-    }
-
-    // For AOF Rewrite
-    pub fn dump_commands(&self) -> Vec<String> {
-        let mut commands = Vec::new();
-        for kv in self.data.iter() {
-            let key = kv.key();
-            let entry = kv.value();
-            
-            // Check expiry
-            if let Some(exp) = self.expiry.get(key) {
-                if Instant::now() > *exp {
-                    continue; // Skip expired
-                }
-                let ttl = exp.duration_since(Instant::now()).as_secs();
-                commands.push(format!("SETEX {} {} {}", key, ttl, entry.value));
-            } else {
-                commands.push(format!("SET {} {}", key, entry.value));
+    pub fn export(&self) -> KeyspaceSnapshot {
+        let mut snapshot = KeyspaceSnapshot::default();
+        for (key, value, expire_at_ms) in self.backend.entries() {
+            if let Some(ms) = expire_at_ms {
+                snapshot.expirations.insert(key.clone(), ms);
             }
+            snapshot.values.insert(key, value);
         }
-        commands
+        snapshot.sorted_sets = self.sorted_sets.iter().map(|kv| (kv.key().clone(), kv.value().clone())).collect();
+        snapshot.topks = self.topks.iter().map(|kv| (kv.key().clone(), kv.value().clone())).collect();
+        snapshot.counters = self.counters.iter().map(|kv| (kv.key().clone(), kv.value().total())).collect();
+        snapshot.streams = self.streams.iter().map(|kv| (kv.key().clone(), kv.value().clone())).collect();
+        snapshot.bitmaps = self.bitmaps.iter().map(|kv| (kv.key().clone(), kv.value().clone())).collect();
+        snapshot.scheduled = self.schedule.pending();
+        snapshot
     }
 
-    pub fn import_from(map: std::collections::HashMap<String, Value>) -> Self {
-        let dash = DashMap::new();
-        for (k, v) in map {
-            dash.insert(k, Entry { value: v, last_accessed: Instant::now() });
+    // For AOF Rewrite (lazy: commands are produced one key at a time).
+    // Expiring keys are written as SET + PEXPIREAT with an absolute timestamp, so
+    // a replay later (or on another node) expires them at the same moment.
+    // Sorted sets are written in ZADD batches, Top-K sketches are restored
+    // whole with their counters, striped counters from their totals, streams
+    // entry by entry with their IDs, bitmaps bit by bit, and pending SCHEDULE
+    // writes are queued again.
+    pub fn dump_commands(&self) -> impl Iterator<Item = String> + '_ {
+        self.backend.entries().flat_map(|(key, value, expire_at_ms)| {
+            let set = format!("SET {} {}", key, value);
+            match expire_at_ms {
+                Some(ms) => vec![set, format!("PEXPIREAT {} {}", key, ms)],
+                None => vec![set],
+            }
+        }).chain(self.sorted_sets.iter().flat_map(|kv| {
+            kv.value().chunks(EXPORT_BATCH).map(|chunk| {
+                let pairs: Vec<String> = chunk.iter().map(|(score, member)| format!("{} {}", score, quote_string(member))).collect();
+                format!("ZADD {} {}", kv.key(), pairs.join(" "))
+            }).collect::<Vec<_>>()
+        })).chain(self.topks.iter().map(|kv| {
+            topk_restore(kv.key(), kv.value()).join(" ")
+        })).chain(self.counters.iter().map(|kv| {
+            format!("CINCR {} {}", kv.key(), kv.value().total())
+        })).chain(self.streams.iter().flat_map(|kv| {
+            kv.value().entries().map(|(id, fields)| xadd_command(kv.key(), *id, fields)).collect::<Vec<_>>()
+        })).chain(self.bitmaps.iter().flat_map(|kv| {
+            setbit_args(kv.key(), kv.value()).into_iter().map(|args| args.join(" ")).collect::<Vec<_>>()
+        })).chain(self.schedule.pending().into_iter().map(|write| write.to_command()))
+    }
+
+    /// Digest of every key with its value, and of the pending scheduled
+    /// writes (REPLCHECK).
+    pub fn digest(&self) -> u64 {
+        let mut hashes: Vec<u64> = self.backend.entries().map(|(key, value, _)| digest::entry(&(key, value))).collect();
+        hashes.extend(self.sorted_sets.iter().map(|kv| digest::entry(&("zset", kv.key(), kv.value()))));
+        hashes.extend(self.topks.iter().map(|kv| digest::entry(&("topk", kv.key(), kv.value().list()))));
+        hashes.extend(self.counters.iter().map(|kv| digest::entry(&("counter", kv.key(), kv.value().total()))));
+        hashes.extend(self.streams.iter().map(|kv| digest::entry(&("stream", kv.key(), kv.value()))));
+        hashes.extend(self.bitmaps.iter().map(|kv| digest::entry(&("bitmap", kv.key(), kv.value()))));
+        hashes.extend(self.schedule.pending().iter().map(digest::entry));
+        digest::combine(hashes)
+    }
+
+    /// Writes the keys accepted by `include` to `path` as RESP commands that
+    /// recreate them (EXPORT KEYS), returning how many keys were written.
+    ///
+    /// Values are passed verbatim: the file switches the replaying connection
+    /// to strict binding and back to parsed at the end. The file is written
+    /// next to `path` and renamed into place; on failure it is removed.
+    pub fn export_to(&self, path: &str, include: impl Fn(&str) -> bool) -> anyhow::Result<usize> {
+        let tmp = format!("{}.tmp", path);
+        let written = self.write_export(&tmp, include).and_then(|count| {
+            std::fs::rename(&tmp, path)?;
+            Ok(count)
+        });
+        if written.is_err() {
+            let _ = std::fs::remove_file(&tmp);
         }
-        // Limit
-        let max = std::env::var("DB_MAX_KEYS")
-            .unwrap_or("10000".to_string())
-            .parse()
-            .unwrap_or(10_000);
-            
-        Self { 
-            data: Arc::new(dash),
-            expiry: Arc::new(DashMap::new()),
-            sorted_sets: Arc::new(DashMap::new()),
-            max_keys: max,
+        written
+    }
+
+    fn write_export(&self, tmp: &str, include: impl Fn(&str) -> bool) -> anyhow::Result<usize> {
+        use std::io::Write;
+
+        let mut out = std::io::BufWriter::new(std::fs::File::create(tmp)?);
+        let mut write = |args: Vec<String>| -> std::io::Result<()> {
+            let parts = args.into_iter().map(|a| RespValue::BulkString(Some(a.into_bytes()))).collect();
+            out.write_all(&RespValue::Array(Some(parts)).serialize())
+        };
+        let words = |words: &[&str]| words.iter().map(|w| w.to_string()).collect::<Vec<String>>();
+
+        write(words(&["SET", "binding", "=", "strict"]))?;
+        let mut count = 0;
+        for (_, cmds) in self.key_commands(include) {
+            for cmd in cmds {
+                write(cmd)?;
+            }
+            count += 1;
+        }
+        write(words(&["SET", "binding", "=", "parsed"]))?;
+
+        out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        Ok(count)
+    }
+
+    /// The keys accepted by `include`, each with the commands (as strict
+    /// binding arguments) that recreate it on another store, for EXPORT KEYS
+    /// and MIGRATE.
+    ///
+    /// Unlike the AOF rewrite, collections get their own commands (RPUSH, HSET,
+    /// ZADD, XADD, SETBIT) and each key is deleted first, so replaying them replaces it.
+    /// Expiring keys are followed by PEXPIREAT; expired keys are skipped.
+    pub fn key_commands<'a>(&'a self, include: impl Fn(&str) -> bool + 'a) -> impl Iterator<Item = (String, Vec<Vec<String>>)> + 'a {
+        let include = std::rc::Rc::new(include);
+        let words = |words: &[&str]| words.iter().map(|w| w.to_string()).collect::<Vec<String>>();
+
+        // Collected first, so no backend lock is held while the commands are used
+        let entries: Vec<(String, Value, Option<u64>)> = self.backend.entries().filter(|(key, _, _)| include(key)).collect();
+        let values = entries.into_iter().map(move |(key, value, expire_at_ms)| {
+            let mut cmds = vec![words(&["DEL", &key])];
+            cmds.extend(value_commands(&key, &value));
+            if let Some(ms) = expire_at_ms {
+                cmds.push(words(&["PEXPIREAT", &key, &ms.to_string()]));
+            }
+            (key, cmds)
+        });
+        let sorted_sets = {
+            let include = include.clone();
+            self.sorted_sets.iter().filter(move |kv| include(kv.key()) && !kv.value().is_empty()).map(move |kv| {
+                let mut cmds = vec![words(&["DEL", kv.key()])];
+                for chunk in kv.value().chunks(EXPORT_BATCH) {
+                    let mut cmd = words(&["ZADD", kv.key()]);
+                    for (score, member) in chunk {
+                        cmd.extend([score.to_string(), member.clone()]);
+                    }
+                    cmds.push(cmd);
+                }
+                (kv.key().clone(), cmds)
+            })
+        };
+        let topks = {
+            let include = include.clone();
+            self.topks.iter().filter(move |kv| include(kv.key())).map(move |kv| {
+                (kv.key().clone(), vec![words(&["DEL", kv.key()]), topk_restore(kv.key(), kv.value())])
+            })
+        };
+        let counters = {
+            let include = include.clone();
+            self.counters.iter().filter(move |kv| include(kv.key())).map(move |kv| {
+                let cmds = vec![words(&["DEL", kv.key()]), words(&["CINCR", kv.key(), &kv.value().total().to_string()])];
+                (kv.key().clone(), cmds)
+            })
+        };
+        let streams = {
+            let include = include.clone();
+            self.streams.iter().filter(move |kv| include(kv.key())).map(move |kv| {
+                let mut cmds = vec![words(&["DEL", kv.key()])];
+                for (id, fields) in kv.value().entries() {
+                    let mut cmd = words(&["XADD", kv.key(), &id.to_string()]);
+                    for (field, value) in fields {
+                        cmd.extend([field.clone(), value.clone()]);
+                    }
+                    cmds.push(cmd);
+                }
+                (kv.key().clone(), cmds)
+            })
+        };
+        let bitmaps = self.bitmaps.iter().filter(move |kv| include(kv.key())).map(move |kv| {
+            let mut cmds = vec![words(&["DEL", kv.key()])];
+            cmds.extend(setbit_args(kv.key(), kv.value()));
+            (kv.key().clone(), cmds)
+        });
+        values.chain(sorted_sets).chain(topks).chain(counters).chain(streams).chain(bitmaps)
+    }
+
+    /// The keys starting with `prefix` and accepted by `include` as rows of
+    /// the [`KV_TABLE`] virtual table: `key` (primary key), `type`, `value`
+    /// (JSON, a sorted set as a member -> score object, a stream as an
+    /// ID -> fields object) and `expire_at` (unix ms, NULL without expiry).
+    /// Only the values of those keys are read. Top-K sketches and bitmaps
+    /// are left out.
+    pub fn to_table(&self, prefix: &str, include: impl Fn(&str) -> bool) -> Table {
+        let include = |key: &str| key.starts_with(prefix) && include(key);
+        let column = |name: &str, data_type| Column {
+            name: name.to_string(),
+            data_type,
+            is_primary_key: name == "key",
+            references: None,
+            fk_action: Default::default(),
+            not_null: name == "key",
+            unique: false,
+            default: None,
+        };
+        let row = |key: String, key_type: &str, value: Value, expire_at_ms: Option<u64>| vec![
+            UnifiedValue::String(key),
+            UnifiedValue::String(key_type.to_string()),
+            UnifiedValue::from(value),
+            expire_at_ms.map_or(UnifiedValue::Null, |ms| UnifiedValue::Integer(ms as i64)),
+        ];
+
+        let mut rows: Vec<Vec<UnifiedValue>> = self.backend.prefixed_entries(prefix)
+            .filter(|(key, _, _)| include(key))
+            .map(|(key, value, expire_at_ms)| {
+                let key_type = match value {
+                    Value::Array(_) => "list",
+                    Value::Object(_) => "hash",
+                    _ => "string",
+                };
+                row(key, key_type, value, expire_at_ms)
+            })
+            .collect();
+        for kv in self.sorted_sets.iter().filter(|kv| include(kv.key())) {
+            let members = kv.value().iter()
+                .map(|(score, member)| (member.clone(), serde_json::json!(score)))
+                .collect();
+            rows.push(row(kv.key().clone(), "zset", Value::Object(members), None));
+        }
+        for kv in self.counters.iter().filter(|kv| include(kv.key())) {
+            rows.push(row(kv.key().clone(), "counter", Value::from(kv.value().total()), None));
+        }
+        for kv in self.streams.iter().filter(|kv| include(kv.key())) {
+            let entries = kv.value().entries()
+                .map(|(id, fields)| (id.to_string(), Value::Object(fields.iter().map(|(f, v)| (f.clone(), Value::String(v.clone()))).collect())))
+                .collect();
+            rows.push(row(kv.key().clone(), "stream", Value::Object(entries), None));
+        }
+        rows.sort_by(|a, b| a[0].cmp(&b[0]));
+
+        Table {
+            name: KV_TABLE.to_string(),
+            columns: vec![
+                column("key", DataType::String),
+                column("type", DataType::String),
+                column("value", DataType::Json),
+                column("expire_at", DataType::Integer),
+            ],
+            next_row_id: rows.len() as u64 + 1,
+            rows: (1..).zip(rows).collect(),
         }
     }
 
-    pub fn restore(&self, data: std::collections::HashMap<String, Value>) {
-        self.data.clear();
-        self.expiry.clear();
+    pub fn import_from(snapshot: KeyspaceSnapshot) -> Self {
+        let store = Self::new();
+        store.restore(snapshot);
+        store
+    }
+
+    pub fn restore(&self, snapshot: KeyspaceSnapshot) {
+        self.backend.clear();
         self.sorted_sets.clear();
-        
-        for (k, v) in data {
-            let entry = Entry {
-                value: v,
-                last_accessed: Instant::now(),
-            };
-            self.data.insert(k, entry);
+        self.topks.clear();
+        self.counters.clear();
+        self.streams.clear();
+        self.bitmaps.clear();
+        self.schedule.clear();
+        let KeyspaceSnapshot { values, mut expirations, sorted_sets, topks, counters, streams, bitmaps, scheduled } = snapshot;
+        let now = backend::now_unix_ms();
+        for (k, v) in values {
+            let expire_at_ms = expirations.remove(&k);
+            if expire_at_ms.is_none_or(|ms| ms > now) {
+                self.backend.set(&k, v, expire_at_ms);
+            }
+        }
+        for (k, members) in sorted_sets {
+            self.sorted_sets.insert(k, members);
+        }
+        for (k, sketch) in topks {
+            self.topks.insert(k, sketch);
+        }
+        for (k, total) in counters {
+            self.counters.insert(k, StripedCounter::new(total));
+        }
+        for (k, stream) in streams {
+            self.streams.insert(k, stream);
+        }
+        for (k, bytes) in bitmaps {
+            self.bitmaps.insert(k, bytes);
+        }
+        for write in scheduled {
+            self.schedule.add(write);
+        }
+    }
+
+    /// The value of `key`, without its expiry (DUMP).
+    pub fn dump_key(&self, key: &str) -> Option<KeyValue> {
+        if let Some(members) = self.sorted_sets.get(key) {
+            return Some(KeyValue::SortedSet(members.clone()));
+        }
+        if let Some(sketch) = self.topks.get(key) {
+            return Some(KeyValue::TopK(sketch.clone()));
+        }
+        if let Some(counter) = self.counters.get(key) {
+            return Some(KeyValue::Counter(counter.total()));
+        }
+        if let Some(stream) = self.streams.get(key) {
+            return Some(KeyValue::Stream(stream.clone()));
+        }
+        if let Some(bytes) = self.bitmaps.get(key) {
+            return Some(KeyValue::Bitmap(bytes.clone()));
+        }
+        self.backend.get(key).map(KeyValue::Value)
+    }
+
+    /// Replaces `key` with a value from [`dump_key`](Self::dump_key)
+    /// (RESTORE). Only plain values take the expiry, as with EXPIRE; one
+    /// already past leaves the key deleted.
+    pub fn restore_key(&self, key: &str, value: KeyValue, expire_at_ms: Option<u64>) {
+        self.del(&[key.to_string()]);
+        match value {
+            KeyValue::Value(value) if expire_at_ms.is_none_or(|ms| ms > backend::now_unix_ms()) => self.backend.set(key, value, expire_at_ms),
+            KeyValue::Value(_) => {}
+            KeyValue::SortedSet(members) => { self.sorted_sets.insert(key.to_string(), members); }
+            KeyValue::TopK(sketch) => { self.topks.insert(key.to_string(), sketch); }
+            KeyValue::Counter(total) => { self.counters.insert(key.to_string(), StripedCounter::new(total)); }
+            KeyValue::Stream(stream) => { self.streams.insert(key.to_string(), stream); }
+            KeyValue::Bitmap(bytes) => { self.bitmaps.insert(key.to_string(), bytes); }
+        }
+    }
+
+    /// Copies `keys` (and their pending scheduled writes) so a failed
+    /// transaction can put them back with [`rollback_to`](Self::rollback_to).
+    pub fn savepoint<'a>(&self, keys: impl IntoIterator<Item = &'a str>) -> KeySavepoint {
+        let mut seen = std::collections::HashSet::new();
+        let keys: Vec<SavedKey> = keys.into_iter().filter(|key| seen.insert(*key)).map(|key| SavedKey {
+            key: key.to_string(),
+            value: self.backend.get(key).map(|value| (value, self.backend.ttl(key).flatten())),
+            sorted_set: self.sorted_sets.get(key).map(|z| z.clone()),
+            topk: self.topks.get(key).map(|t| t.clone()),
+            counter: self.counters.get(key).map(|c| c.total()),
+            stream: self.streams.get(key).map(|s| s.clone()),
+            bitmap: self.bitmaps.get(key).map(|b| b.clone()),
+        }).collect();
+        let scheduled = self.schedule.pending().into_iter().filter(|w| seen.contains(w.key.as_str())).collect();
+        KeySavepoint { keys, scheduled }
+    }
+
+    /// Puts the keys of a savepoint back as they were; keys created since
+    /// are removed.
+    pub fn rollback_to(&self, savepoint: KeySavepoint) {
+        let saved_keys: std::collections::HashSet<String> = savepoint.keys.iter().map(|saved| saved.key.clone()).collect();
+        self.schedule.retain(|write| !saved_keys.contains(&write.key));
+        for write in savepoint.scheduled {
+            self.schedule.add(write);
+        }
+        for saved in savepoint.keys {
+            self.del(std::slice::from_ref(&saved.key));
+            if let Some((value, expire_at_ms)) = saved.value {
+                self.backend.set(&saved.key, value, expire_at_ms);
+            }
+            if let Some(members) = saved.sorted_set {
+                self.sorted_sets.insert(saved.key.clone(), members);
+            }
+            if let Some(sketch) = saved.topk {
+                self.topks.insert(saved.key.clone(), sketch);
+            }
+            if let Some(total) = saved.counter {
+                self.counters.insert(saved.key.clone(), StripedCounter::new(total));
+            }
+            if let Some(stream) = saved.stream {
+                self.streams.insert(saved.key.clone(), stream);
+            }
+            if let Some(bytes) = saved.bitmap {
+                self.bitmaps.insert(saved.key, bytes);
+            }
         }
     }
 }
+
+/// Largest number of elements per exported RPUSH / ZADD.
+const EXPORT_BATCH: usize = 500;
+
+/// Commands (as strict-binding arguments) that store `value` under `key`.
+/// Lists and sets of strings become RPUSH, hashes of strings HSET, anything
+/// else a SET of the JSON value.
+fn value_commands(key: &str, value: &Value) -> Vec<Vec<String>> {
+    match value {
+        Value::Array(items) if !items.is_empty() && items.iter().all(Value::is_string) => {
+            items.chunks(EXPORT_BATCH).map(|chunk| {
+                let mut cmd = vec!["RPUSH".to_string(), key.to_string()];
+                cmd.extend(chunk.iter().filter_map(Value::as_str).map(String::from));
+                cmd
+            }).collect()
+        }
+        Value::Object(fields) if !fields.is_empty() && fields.values().all(Value::is_string) => {
+            fields.iter()
+                .filter_map(|(field, v)| v.as_str().map(|v| vec!["HSET".to_string(), key.to_string(), field.clone(), v.to_string()]))
+                .collect()
+        }
+        other => vec![vec!["SET".to_string(), key.to_string(), other.to_string()]],
+    }
+}
+
+fn decode_hll(value: &Value) -> Result<Hll, String> {
+    value.as_str().and_then(Hll::decode).ok_or_else(|| "Key does not hold a HyperLogLog".to_string())
+}
+
+/// SETBIT commands (as arguments) rebuilding a bitmap: one per set bit,
+/// and one clearing the last bit so trailing zero bytes are kept.
+fn setbit_args(key: &str, bytes: &[u8]) -> Vec<Vec<String>> {
+    let setbit = |offset: u64, value: &str| vec!["SETBIT".to_string(), key.to_string(), offset.to_string(), value.to_string()];
+    let mut cmds: Vec<Vec<String>> = bytes.iter().enumerate().filter(|(_, byte)| **byte != 0)
+        .flat_map(|(i, byte)| (0..8).filter(move |bit| byte & (0x80 >> bit) != 0).map(move |bit| i as u64 * 8 + bit))
+        .map(|offset| setbit(offset, "1"))
+        .collect();
+    let last = (bytes.len() as u64 * 8).checked_sub(1);
+    if let Some(last) = last.filter(|&last| !bitmap::get_bit(bytes, last)) {
+        cmds.push(setbit(last, "0"));
+    }
+    cmds
+}
+
+/// The RESTORE (as arguments) bringing a Top-K sketch back with all its
+/// counters, not just its top items, so the estimates carry on.
+fn topk_restore(key: &str, sketch: &TopK) -> Vec<String> {
+    let payload = crate::core::dump::encode(&KeyValue::TopK(sketch.clone())).expect("sketches serialize");
+    vec!["RESTORE".to_string(), key.to_string(), "0".to_string(), payload, "REPLACE".to_string()]
+}
+
+/// The XADD that adds one stream entry back, with its ID (AOF).
+pub fn xadd_command(key: &str, id: impl std::fmt::Display, fields: &[(String, String)]) -> String {
+    let pairs: Vec<String> = fields.iter().map(|(f, v)| format!("{} {}", quote_string(f), quote_string(v))).collect();
+    format!("XADD {} {} {}", key, id, pairs.join(" "))
+}
+
+fn zset_size(key: &str, members: &[(f64, String)]) -> usize {
+    key.len() + members.iter().map(|(_, m)| m.len() + std::mem::size_of::<(f64, String)>()).sum::<usize>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::BytesMut;
+
+    #[test]
+    fn ttl_jitter_stays_within_its_percentage() {
+        let store = FlexibleStore::new();
+        // Milliseconds from now to the expiry of `key`, give or take the clock
+        let remaining = |key: &str| store.expire_time_ms(key).unwrap() as i64 - backend::now_unix_ms() as i64;
+        let mut stretched = 0;
+        for i in 0..200 {
+            let key = format!("k{}", i);
+            store.set_with_ttl(key.clone(), Value::from(1), 100, 20);
+            assert!((99_000..=120_000).contains(&remaining(&key)), "{}", remaining(&key));
+            stretched += (remaining(&key) > 101_000) as usize;
+            store.expire(&key, 10, 0);
+            assert!((9_000..=10_000).contains(&remaining(&key)), "{}", remaining(&key));
+        }
+        assert!(stretched > 0);
+        // Past 100 it stretches by at most the TTL again
+        store.set_with_ttl("k".into(), Value::from(1), 10, 255);
+        assert!(remaining("k") <= 20_000);
+    }
+
+    #[test]
+    fn export_writes_replayable_commands() {
+        let store = FlexibleStore::new();
+        store.rpush("app:l", vec!["a".into(), "b c".into()]);
+        store.hset("app:h", "f".into(), "x\"y".into());
+        store.set("app:n".into(), serde_json::json!(42));
+        store.set_with_ttl("app:t".into(), Value::String("v".into()), 100, 0);
+        store.zadd("app:z", vec![(1.5, "one".into())], ZAddFlags::default());
+        store.set("other".into(), Value::String("skip".into()));
+
+        let path = std::env::temp_dir().join(format!("toridb_export_{}.resp", std::process::id()));
+        let path = path.to_str().unwrap();
+        assert_eq!(store.export_to(path, |key| key.starts_with("app:")).unwrap(), 5);
+
+        let mut buf = BytesMut::from(&std::fs::read(path).unwrap()[..]);
+        std::fs::remove_file(path).unwrap();
+        let mut cmds = Vec::new();
+        while let Ok(Some(value)) = crate::net::resp::decode(&mut buf) {
+            cmds.push(value.to_args().unwrap().join(" "));
+        }
+        assert_eq!(cmds.first().unwrap(), "SET binding = strict");
+        assert_eq!(cmds.last().unwrap(), "SET binding = parsed");
+        for expected in ["RPUSH app:l a b c", "HSET app:h f x\"y", "SET app:n 42", "SET app:t \"v\"", "ZADD app:z 1.5 one", "DEL app:z"] {
+            assert!(cmds.iter().any(|c| c == expected), "{} in {:?}", expected, cmds);
+        }
+        assert!(cmds.iter().any(|c| c.starts_with("PEXPIREAT app:t ")));
+        assert!(!cmds.iter().any(|c| c.contains("other")));
+
+        // A failed export leaves nothing behind
+        std::fs::create_dir_all(path).unwrap();
+        assert!(store.export_to(path, |_| true).is_err());
+        assert!(!std::path::Path::new(&format!("{}.tmp", path)).exists());
+        std::fs::remove_dir(path).unwrap();
+    }
+
+    #[test]
+    fn zpop_takes_from_either_end_and_drops_emptied_sets() {
+        let store = FlexibleStore::new();
+        store.zadd("z", vec![(3.0, "c".into()), (1.0, "a".into()), (2.0, "b".into())], ZAddFlags::default());
+        assert_eq!(store.zpop("z", 1, false), vec![("a".to_string(), 1.0)]);
+        assert_eq!(store.zpop("z", 1, true), vec![("c".to_string(), 3.0)]);
+        assert!(store.zpop("missing", 1, false).is_empty());
+        // A count past the size pops everything, and the key goes with it
+        assert_eq!(store.zpop("z", 5, true), vec![("b".to_string(), 2.0)]);
+        assert_eq!(store.key_type("z"), "none");
+        assert!(store.zpop("z", 1, false).is_empty());
+    }
+
+    #[test]
+    fn keys_as_a_virtual_table() {
+        use crate::query::{Filter, JoinClause, JoinType, Operator, Selector};
+        use crate::core::structured::StructuredStore;
+
+        let store = FlexibleStore::new();
+        store.hset("user:1", "name".into(), "ana".into());
+        store.hset("user:2", "name".into(), "bo".into());
+        store.set_with_ttl("session".into(), Value::String("x".into()), 100, 0);
+        store.zadd("board", vec![(2.0, "ana".into())], ZAddFlags::default());
+
+        let sql = StructuredStore::new();
+        sql.create_table("people".into(), vec![
+            Column { name: "name".into(), data_type: DataType::String, is_primary_key: true, references: None, fk_action: Default::default(), not_null: true, unique: false, default: None },
+        ]).unwrap();
+        sql.insert("people", vec![Some("bo".into())]).unwrap();
+        assert!(sql.create_table(KV_TABLE.into(), Vec::new()).is_err());
+
+        let view = sql.with_table(store.to_table("", |key| key != "board"), &["people"]);
+        let all = view.select(KV_TABLE, None, Selector::All, None, None, None, None, None, None, None).unwrap();
+        assert_eq!(all.columns, ["key", "type", "value", "expire_at"]);
+        assert_eq!(all.rows.len(), 3);
+        assert!(matches!(all.rows[0][3], UnifiedValue::Integer(_)));
+
+        let like = Filter::Condition("key".into(), Operator::Like, "user:%".into());
+        let names = view.select(KV_TABLE, None, Selector::Columns(vec![crate::query::Projection { expr: crate::query::Expr::Column("value->name".into()), alias: None }]), None, Some(like), None, None, None, None, None).unwrap();
+        assert_eq!(names.rows, vec![vec![UnifiedValue::String("ana".into())], vec![UnifiedValue::String("bo".into())]]);
+
+        let join = JoinClause { join_type: JoinType::Inner, table: "people".into(), alias: None, on_left: format!("{}.value->name", KV_TABLE), on_right: "people.name".into() };
+        let joined = view.select(KV_TABLE, None, Selector::All, Some(vec![join]), None, None, None, None, None, None).unwrap();
+        assert_eq!(joined.rows.len(), 1);
+        assert_eq!(joined.rows[0][0], UnifiedValue::String("user:2".into()));
+        assert!(sql.table_names().iter().all(|t| t != KV_TABLE));
+
+        // A prefix keeps the other keys out, and the view only the tables named
+        let users = store.to_table("user:", |_| true);
+        assert_eq!(users.rows.values().map(|row| row[0].to_string()).collect::<Vec<_>>(), ["user:1", "user:2"]);
+        assert!(store.to_table("board", |_| true).rows.values().all(|row| row[1] == UnifiedValue::String("zset".into())));
+        sql.create_table("other".into(), vec![
+            Column { name: "id".into(), data_type: DataType::Integer, is_primary_key: true, references: None, fk_action: Default::default(), not_null: true, unique: false, default: None },
+        ]).unwrap();
+        let view = sql.with_table(users, &["people", "missing"]);
+        assert!(view.select("other", None, Selector::All, None, None, None, None, None, None, None).is_err());
+        assert!(view.select("people", None, Selector::All, None, None, None, None, None, None, None).is_ok());
+    }
+
+    #[test]
+    fn rollback_restores_saved_keys() {
+        let store = FlexibleStore::new();
+        store.set_with_ttl("a".into(), Value::from(1), 100, 0);
+        store.zadd("board", vec![(1.0, "ana".into())], ZAddFlags::default());
+
+        let savepoint = store.savepoint(["a", "board", "new", "a"]);
+        store.set("a".into(), Value::from(2));
+        store.zadd("board", vec![(5.0, "bo".into())], ZAddFlags::default());
+        store.cincr("new", 3).unwrap();
+        store.rollback_to(savepoint);
+
+        assert_eq!(store.get("a"), Some(Value::from(1)));
+        assert!(store.ttl("a").is_some_and(|secs| secs > 0));
+        assert_eq!(store.zrange("board", 0, -1), ["ana"]);
+        assert_eq!(store.key_type("new"), "none");
+    }
+}
@@ -0,0 +1,220 @@
+//! # Geospatial Indexing
+//!
+//! Locations are sorted set members whose score is the geohash of their
+//! coordinates, as in Redis, so `GEOADD` data can also be read with `ZRANGE`
+//! and `ZSCORE` and persists like any sorted set.
+//!
+//! A geohash splits the map into a grid: each step halves the longitude and
+//! latitude ranges, and records on which side the point falls. With
+//! [`STEPS`] steps per axis, interleaved longitude first, the hash is a 52-bit
+//! integer that a `f64` score holds exactly. Points sharing a hash prefix lie
+//! in the same cell, so a cell is a range of scores.
+//!
+//! A radius search picks the smallest cells still larger than the radius,
+//! and scans the score ranges of the cell holding the center and of its 8
+//! neighbours, which together cover the circle; the members found are then
+//! filtered by their exact distance.
+
+/// Bits per coordinate.
+pub const STEPS: u32 = 26;
+
+pub const LON_MIN: f64 = -180.0;
+pub const LON_MAX: f64 = 180.0;
+/// Latitudes are limited to where the Web Mercator projection ends.
+pub const LAT_MIN: f64 = -85.05112878;
+pub const LAT_MAX: f64 = 85.05112878;
+
+/// Earth radius used for distances, the one Redis uses.
+const EARTH_RADIUS_M: f64 = 6372797.560856;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GeoUnit {
+    Meters,
+    Kilometers,
+    Feet,
+    Miles,
+}
+
+impl GeoUnit {
+    /// Meters in one unit.
+    pub fn meters(self) -> f64 {
+        match self {
+            GeoUnit::Meters => 1.0,
+            GeoUnit::Kilometers => 1000.0,
+            GeoUnit::Feet => 0.3048,
+            GeoUnit::Miles => 1609.34,
+        }
+    }
+}
+
+impl std::str::FromStr for GeoUnit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "M" => Ok(GeoUnit::Meters),
+            "KM" => Ok(GeoUnit::Kilometers),
+            "FT" => Ok(GeoUnit::Feet),
+            "MI" => Ok(GeoUnit::Miles),
+            other => Err(format!("Unsupported unit '{}', use M, KM, FT or MI", other)),
+        }
+    }
+}
+
+impl std::fmt::Display for GeoUnit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            GeoUnit::Meters => "M",
+            GeoUnit::Kilometers => "KM",
+            GeoUnit::Feet => "FT",
+            GeoUnit::Miles => "MI",
+        })
+    }
+}
+
+pub fn validate(lon: f64, lat: f64) -> Result<(), String> {
+    if (LON_MIN..=LON_MAX).contains(&lon) && (LAT_MIN..=LAT_MAX).contains(&lat) {
+        Ok(())
+    } else {
+        Err(format!("Invalid longitude,latitude pair {},{}", lon, lat))
+    }
+}
+
+/// The score of a valid point.
+pub fn encode(lon: f64, lat: f64) -> f64 {
+    let cells = (1u64 << STEPS) as f64;
+    let cell = |value: f64, min: f64, max: f64| (((value - min) / (max - min) * cells) as u64).min((1 << STEPS) - 1);
+    interleave(cell(lon, LON_MIN, LON_MAX), cell(lat, LAT_MIN, LAT_MAX)) as f64
+}
+
+/// The center of the cell a score stands for; `None` if it is not a geohash.
+pub fn decode(score: f64) -> Option<(f64, f64)> {
+    if score < 0.0 || score >= (1u64 << (2 * STEPS)) as f64 || score.fract() != 0.0 {
+        return None;
+    }
+    let (lon, lat) = deinterleave(score as u64);
+    let cells = (1u64 << STEPS) as f64;
+    let center = |cell: u64, min: f64, max: f64| min + (cell as f64 + 0.5) / cells * (max - min);
+    Some((center(lon, LON_MIN, LON_MAX), center(lat, LAT_MIN, LAT_MAX)))
+}
+
+/// Great-circle distance in meters (haversine formula).
+pub fn distance(lon1: f64, lat1: f64, lon2: f64, lat2: f64) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let half_dlat = (lat2 - lat1) / 2.0;
+    let half_dlon = (lon2 - lon1).to_radians() / 2.0;
+    let a = half_dlat.sin().powi(2) + lat1.cos() * lat2.cos() * half_dlon.sin().powi(2);
+    2.0 * EARTH_RADIUS_M * a.sqrt().asin()
+}
+
+/// Score ranges `[min, max)` covering every point within `radius_m` meters
+/// of the center.
+pub fn search_ranges(lon: f64, lat: f64, radius_m: f64) -> Vec<(f64, f64)> {
+    let radius_deg = (radius_m / EARTH_RADIUS_M).to_degrees();
+    // Cells are narrowest where the circle gets closest to a pole
+    let widest_lat = (lat.abs() + radius_deg).min(90.0).to_radians();
+    let step = (1..=STEPS).rev().find(|&step| {
+        let cells = (1u64 << step) as f64;
+        let height = (LAT_MAX - LAT_MIN) / cells;
+        let width = (LON_MAX - LON_MIN) / cells * widest_lat.cos();
+        height >= radius_deg && width >= radius_deg
+    }).unwrap_or(1);
+
+    let cells = 1i64 << step;
+    let cell = |value: f64, min: f64, max: f64| (((value - min) / (max - min) * cells as f64) as i64).min(cells - 1);
+    let (lon_cell, lat_cell) = (cell(lon, LON_MIN, LON_MAX), cell(lat, LAT_MIN, LAT_MAX));
+    let shift = 2 * (STEPS - step);
+    let mut ranges = Vec::new();
+    for dlat in -1..=1 {
+        let lat_cell = lat_cell + dlat;
+        if !(0..cells).contains(&lat_cell) {
+            continue;
+        }
+        for dlon in -1..=1 {
+            // Longitudes wrap around at the antimeridian
+            let lon_cell = (lon_cell + dlon).rem_euclid(cells);
+            let hash = interleave(lon_cell as u64, lat_cell as u64);
+            ranges.push(((hash << shift) as f64, ((hash + 1) << shift) as f64));
+        }
+    }
+    ranges.sort_by(|a, b| a.0.total_cmp(&b.0));
+    ranges.dedup();
+    ranges
+}
+
+/// Longitude bits in the odd positions, latitude bits in the even ones.
+fn interleave(lon: u64, lat: u64) -> u64 {
+    (spread(lon) << 1) | spread(lat)
+}
+
+fn deinterleave(hash: u64) -> (u64, u64) {
+    (squash(hash >> 1), squash(hash))
+}
+
+/// Puts the low 32 bits of `x` in the even bit positions.
+fn spread(x: u64) -> u64 {
+    let mut x = x & 0xFFFF_FFFF;
+    x = (x | (x << 16)) & 0x0000_FFFF_0000_FFFF;
+    x = (x | (x << 8)) & 0x00FF_00FF_00FF_00FF;
+    x = (x | (x << 4)) & 0x0F0F_0F0F_0F0F_0F0F;
+    x = (x | (x << 2)) & 0x3333_3333_3333_3333;
+    (x | (x << 1)) & 0x5555_5555_5555_5555
+}
+
+fn squash(x: u64) -> u64 {
+    let mut x = x & 0x5555_5555_5555_5555;
+    x = (x | (x >> 1)) & 0x3333_3333_3333_3333;
+    x = (x | (x >> 2)) & 0x0F0F_0F0F_0F0F_0F0F;
+    x = (x | (x >> 4)) & 0x00FF_00FF_00FF_00FF;
+    x = (x | (x >> 8)) & 0x0000_FFFF_0000_FFFF;
+    (x | (x >> 16)) & 0x0000_0000_FFFF_FFFF
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scores_match_redis() {
+        // GEOADD Sicily 13.361389 38.115556 Palermo, then ZSCORE
+        assert_eq!(encode(13.361389, 38.115556), 3479099956230698.0);
+        assert_eq!(encode(15.087269, 37.502669), 3479447370796909.0);
+        let (lon, lat) = decode(3479099956230698.0).unwrap();
+        assert!((lon - 13.361389).abs() < 1e-5 && (lat - 38.115556).abs() < 1e-5);
+        assert_eq!(decode(1.5), None);
+        assert_eq!(decode(-1.0), None);
+        assert!(validate(0.0, 85.06).is_err() && validate(180.0, -85.0).is_ok());
+    }
+
+    #[test]
+    fn distances() {
+        // Palermo to Catania, 166274.1516 m in Redis
+        let d = distance(13.361389, 38.115556, 15.087269, 37.502669);
+        assert!((d - 166274.15).abs() < 1.0, "{}", d);
+        assert_eq!(distance(1.0, 2.0, 1.0, 2.0), 0.0);
+    }
+
+    #[test]
+    fn ranges_cover_the_circle() {
+        let covered = |lon: f64, lat: f64, ranges: &[(f64, f64)]| {
+            let score = encode(lon, lat);
+            ranges.iter().any(|(min, max)| (*min..*max).contains(&score))
+        };
+        for (lon, lat, radius) in [(13.0, 38.0, 200_000.0), (179.99, 0.0, 5_000.0), (-0.001, 84.9, 300_000.0), (2.35, 48.85, 10.0)] {
+            let ranges = search_ranges(lon, lat, radius);
+            assert!(ranges.len() <= 9);
+            for bearing in 0..36 {
+                let angle = (bearing as f64 * 10.0).to_radians();
+                let dist = (radius * 0.999) / EARTH_RADIUS_M;
+                // Destination point along the bearing
+                let (lat1, lon1) = (lat.to_radians(), lon.to_radians());
+                let lat2 = (lat1.sin() * dist.cos() + lat1.cos() * dist.sin() * angle.cos()).asin();
+                let lon2 = lon1 + (angle.sin() * dist.sin() * lat1.cos()).atan2(dist.cos() - lat1.sin() * lat2.sin());
+                let (lon2, lat2) = ((lon2.to_degrees() + 540.0) % 360.0 - 180.0, lat2.to_degrees());
+                if validate(lon2, lat2).is_ok() {
+                    assert!(covered(lon2, lat2, &ranges), "{},{} from {},{} r {}", lon2, lat2, lon, lat, radius);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,151 @@
+//! # Glob Pattern Matching
+//!
+//! Redis-style glob matching used by `SCAN ... MATCH` and `KEYS`.
+//!
+//! Supported syntax:
+//! - `*` matches any sequence (including empty)
+//! - `?` matches exactly one character
+//! - `[abc]`, `[a-z]`, `[^a]` character classes
+//! - `\x` escapes `x`
+
+/// Returns true if `text` matches the glob `pattern`.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let t: Vec<char> = text.chars().collect();
+    match_from(&p, &t)
+}
+
+fn match_from(p: &[char], t: &[char]) -> bool {
+    let (mut pi, mut ti) = (0, 0);
+    // Backtracking point for the last '*': (pattern index after star, text index)
+    let mut star: Option<(usize, usize)> = None;
+
+    while ti < t.len() {
+        if pi < p.len() {
+            match p[pi] {
+                '*' => {
+                    star = Some((pi + 1, ti));
+                    pi += 1;
+                    continue;
+                }
+                '?' => {
+                    pi += 1;
+                    ti += 1;
+                    continue;
+                }
+                '[' => {
+                    if let Some((matched, next_pi)) = match_class(p, pi, t[ti]) {
+                        if matched {
+                            pi = next_pi;
+                            ti += 1;
+                            continue;
+                        }
+                    } else if t[ti] == '[' {
+                        // Unterminated class: treat '[' literally
+                        pi += 1;
+                        ti += 1;
+                        continue;
+                    }
+                }
+                '\\' if pi + 1 < p.len() => {
+                    if p[pi + 1] == t[ti] {
+                        pi += 2;
+                        ti += 1;
+                        continue;
+                    }
+                }
+                c => {
+                    if c == t[ti] {
+                        pi += 1;
+                        ti += 1;
+                        continue;
+                    }
+                }
+            }
+        }
+
+        // Mismatch: let the last '*' swallow one more character
+        match star {
+            Some((star_pi, star_ti)) => {
+                pi = star_pi;
+                ti = star_ti + 1;
+                star = Some((star_pi, star_ti + 1));
+            }
+            None => return false,
+        }
+    }
+
+    // Trailing stars match the empty remainder
+    p[pi..].iter().all(|&c| c == '*')
+}
+
+/// Evaluates the class starting at `p[start] == '['` against `c`.
+/// Returns (matched, index after the closing ']') or None if unterminated.
+fn match_class(p: &[char], start: usize, c: char) -> Option<(bool, usize)> {
+    let mut i = start + 1;
+    let negate = i < p.len() && p[i] == '^';
+    if negate {
+        i += 1;
+    }
+
+    let mut matched = false;
+    let mut first = true;
+    while i < p.len() {
+        if p[i] == ']' && !first {
+            return Some((matched != negate, i + 1));
+        }
+        first = false;
+
+        let lo = if p[i] == '\\' && i + 1 < p.len() {
+            i += 1;
+            p[i]
+        } else {
+            p[i]
+        };
+
+        if i + 2 < p.len() && p[i + 1] == '-' && p[i + 2] != ']' {
+            let hi = p[i + 2];
+            let (lo, hi) = if lo <= hi { (lo, hi) } else { (hi, lo) };
+            if c >= lo && c <= hi {
+                matched = true;
+            }
+            i += 3;
+        } else {
+            if lo == c {
+                matched = true;
+            }
+            i += 1;
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wildcards() {
+        assert!(glob_match("*", "anything"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("user:*", "user:42"));
+        assert!(!glob_match("user:*", "session:42"));
+        assert!(glob_match("*:profile", "user:1:profile"));
+        assert!(glob_match("h?llo", "hello"));
+        assert!(!glob_match("h?llo", "hllo"));
+        assert!(glob_match("a*b*c", "axxbyyc"));
+        assert!(!glob_match("a*b*c", "axxbyy"));
+    }
+
+    #[test]
+    fn test_classes_and_escapes() {
+        assert!(glob_match("h[ae]llo", "hallo"));
+        assert!(!glob_match("h[ae]llo", "hillo"));
+        assert!(glob_match("h[^e]llo", "hallo"));
+        assert!(!glob_match("h[^e]llo", "hello"));
+        assert!(glob_match("key[0-9]", "key7"));
+        assert!(!glob_match("key[0-9]", "keyx"));
+        assert!(glob_match("literal\\*", "literal*"));
+        assert!(!glob_match("literal\\*", "literalx"));
+    }
+}
@@ -0,0 +1,246 @@
+//! # HyperLogLog
+//!
+//! Counts the distinct elements added to it in a few kilobytes, whatever
+//! their number, with a standard error of about 0.8%.
+//!
+//! Every element is hashed; the first [`P`] bits of the hash pick one of
+//! [`REGISTERS`] registers, and the register keeps the longest run of
+//! leading zeros (plus one) seen in the rest of the hash. Long runs are rare,
+//! so their length tells how many distinct hashes went by. The estimate is a
+//! harmonic mean over the registers, with linear counting while many
+//! registers are still empty. Merging two HLLs keeps the larger register of
+//! each pair, giving the HLL of the union.
+//!
+//! ## Encoding
+//! An HLL is stored as a plain string value, so it expires, persists,
+//! replicates and migrates like any other string. The string starts with
+//! [`MAGIC`] and one character per 6 bits:
+//! - **Sparse** (`S`): while few registers are set, only those, as a 3
+//!   character index and a 1 character value each.
+//! - **Dense** (`D`): every register, one character each (16 KB).
+//!
+//! An HLL switches to dense once it sets more than [`SPARSE_MAX`] registers.
+
+use std::collections::BTreeMap;
+use xxhash_rust::xxh3::xxh3_64;
+
+/// Bits of the hash picking the register.
+const P: u32 = 14;
+
+pub const REGISTERS: usize = 1 << P;
+
+/// Most registers set in the sparse encoding; at 4 characters each it stays
+/// below the size of the dense one.
+const SPARSE_MAX: usize = 3000;
+
+/// Prefix of an encoded HLL.
+pub const MAGIC: &str = "HYLL";
+
+/// One character per 6 bits.
+const DIGITS: &[u8; 64] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz+/";
+
+#[derive(Debug, Clone, PartialEq)]
+enum Registers {
+    /// Set registers only: index -> value
+    Sparse(BTreeMap<u16, u8>),
+    Dense(Vec<u8>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hll {
+    registers: Registers,
+}
+
+impl Default for Hll {
+    fn default() -> Self {
+        Self { registers: Registers::Sparse(BTreeMap::new()) }
+    }
+}
+
+impl Hll {
+    /// Adds an element. Returns true if an estimate may have changed.
+    pub fn add(&mut self, element: &str) -> bool {
+        let hash = xxh3_64(element.as_bytes());
+        let index = (hash >> (64 - P)) as u16;
+        // Zeros after the index bits, with a stop bit so the run ends by 64 - P
+        let rank = ((hash << P) | (1 << (P - 1))).leading_zeros() as u8 + 1;
+        self.raise(index, rank)
+    }
+
+    /// Sets a register to `value` if that is higher.
+    fn raise(&mut self, index: u16, value: u8) -> bool {
+        match &mut self.registers {
+            Registers::Sparse(set) => {
+                let current = set.entry(index).or_insert(0);
+                if *current >= value {
+                    return false;
+                }
+                *current = value;
+                if set.len() > SPARSE_MAX {
+                    self.densify();
+                }
+                true
+            }
+            Registers::Dense(registers) => {
+                let current = &mut registers[index as usize];
+                if *current >= value {
+                    return false;
+                }
+                *current = value;
+                true
+            }
+        }
+    }
+
+    fn densify(&mut self) {
+        if let Registers::Sparse(set) = &self.registers {
+            let mut registers = vec![0; REGISTERS];
+            for (&index, &value) in set {
+                registers[index as usize] = value;
+            }
+            self.registers = Registers::Dense(registers);
+        }
+    }
+
+    /// Merges `other` in, making this the HLL of both. Returns true if a
+    /// register changed.
+    pub fn merge(&mut self, other: &Hll) -> bool {
+        let mut changed = false;
+        match &other.registers {
+            Registers::Sparse(set) => {
+                for (&index, &value) in set {
+                    changed |= self.raise(index, value);
+                }
+            }
+            Registers::Dense(registers) => {
+                self.densify();
+                for (index, &value) in registers.iter().enumerate() {
+                    changed |= self.raise(index as u16, value);
+                }
+            }
+        }
+        changed
+    }
+
+    /// Estimated number of distinct elements added.
+    pub fn count(&self) -> u64 {
+        let m = REGISTERS as f64;
+        let (sum, zeros) = match &self.registers {
+            Registers::Sparse(set) => {
+                let set_sum: f64 = set.values().map(|&v| 2f64.powi(-(v as i32))).sum();
+                let zeros = REGISTERS - set.len();
+                (set_sum + zeros as f64, zeros)
+            }
+            Registers::Dense(registers) => (
+                registers.iter().map(|&v| 2f64.powi(-(v as i32))).sum(),
+                registers.iter().filter(|&&v| v == 0).count(),
+            ),
+        };
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let estimate = alpha * m * m / sum;
+        if estimate <= 2.5 * m && zeros > 0 {
+            return (m * (m / zeros as f64).ln()).round() as u64;
+        }
+        estimate.round() as u64
+    }
+
+    /// The string form stored under the key.
+    pub fn encode(&self) -> String {
+        let digit = |v: usize| DIGITS[v & 63] as char;
+        let mut out = String::from(MAGIC);
+        match &self.registers {
+            Registers::Sparse(set) => {
+                out.push('S');
+                for (&index, &value) in set {
+                    let index = index as usize;
+                    out.extend([digit(index >> 12), digit(index >> 6), digit(index), digit(value as usize)]);
+                }
+            }
+            Registers::Dense(registers) => {
+                out.push('D');
+                out.extend(registers.iter().map(|&v| digit(v as usize)));
+            }
+        }
+        out
+    }
+
+    /// Reads back what [`encode`](Self::encode) wrote; `None` if `s` is not
+    /// an HLL.
+    pub fn decode(s: &str) -> Option<Self> {
+        let value = |c: u8| DIGITS.iter().position(|&d| d == c);
+        let body = s.strip_prefix(MAGIC)?;
+        let (kind, digits) = body.split_at_checked(1)?;
+        let digits: Vec<usize> = digits.bytes().map(value).collect::<Option<_>>()?;
+        let registers = match kind {
+            "S" if digits.len().is_multiple_of(4) => {
+                let mut set = BTreeMap::new();
+                for chunk in digits.chunks(4) {
+                    let index = (chunk[0] << 12) | (chunk[1] << 6) | chunk[2];
+                    if index >= REGISTERS {
+                        return None;
+                    }
+                    set.insert(index as u16, chunk[3] as u8);
+                }
+                Registers::Sparse(set)
+            }
+            "D" if digits.len() == REGISTERS => Registers::Dense(digits.into_iter().map(|v| v as u8).collect()),
+            _ => return None,
+        };
+        Some(Self { registers })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn within(estimate: u64, actual: u64, percent: f64) -> bool {
+        (estimate as f64 - actual as f64).abs() <= actual as f64 * percent / 100.0
+    }
+
+    #[test]
+    fn counts_distinct_elements() {
+        let mut hll = Hll::default();
+        assert_eq!(hll.count(), 0);
+        assert!(hll.add("a"));
+        assert!(!hll.add("a"));
+        assert_eq!(hll.count(), 1);
+        for i in 0..100_000 {
+            hll.add(&format!("user:{}", i));
+            hll.add(&format!("user:{}", i / 2));
+        }
+        assert!(matches!(hll.registers, Registers::Dense(_)));
+        assert!(within(hll.count(), 100_001, 3.0), "{}", hll.count());
+    }
+
+    #[test]
+    fn merge_is_the_union() {
+        let (mut a, mut b) = (Hll::default(), Hll::default());
+        for i in 0..1000 {
+            a.add(&i.to_string());
+            b.add(&(i + 500).to_string());
+        }
+        assert!(a.merge(&b));
+        assert!(!a.merge(&b));
+        assert!(within(a.count(), 1500, 3.0), "{}", a.count());
+    }
+
+    #[test]
+    fn encoding_round_trips() {
+        let mut hll = Hll::default();
+        for i in 0..10 {
+            hll.add(&i.to_string());
+        }
+        let sparse = hll.encode();
+        assert!(sparse.starts_with("HYLLS") && sparse.len() == 5 + 4 * 10);
+        assert_eq!(Hll::decode(&sparse), Some(hll.clone()));
+        for i in 0..50_000 {
+            hll.add(&i.to_string());
+        }
+        let dense = hll.encode();
+        assert_eq!(dense.len(), 5 + REGISTERS);
+        assert_eq!(Hll::decode(&dense), Some(hll));
+        assert_eq!(Hll::decode("plain string"), None);
+        assert_eq!(Hll::decode("HYLLS12"), None);
+    }
+}
@@ -0,0 +1,483 @@
+//! # HNSW Vector Index
+//!
+//! Approximate nearest-neighbour search over one vector column
+//! (`CREATE VECTOR INDEX`), using a Hierarchical Navigable Small World graph
+//! (Malkov & Yashunin): every row is a node linked to its nearest neighbours
+//! on layer 0 and, with geometrically falling odds, on the layers above. A
+//! search descends greedily from the top layer, then explores the
+//! neighbourhood it reached on layer 0.
+//!
+//! A node's top layer is drawn from a hash of its row id rather than a
+//! random number, so replaying the same writes (AOF, replicas) builds the
+//! same graph. Vectors are kept as `f32`; callers re-rank the candidates
+//! against the rows for exact scores.
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use xxhash_rust::xxh3::xxh3_64;
+
+/// Links per node on the upper layers; layer 0 keeps twice as many.
+const M: usize = 16;
+/// Candidates kept while looking for a new node's neighbours.
+const EF_CONSTRUCTION: usize = 100;
+/// Candidates a search keeps, at least.
+pub const EF_SEARCH: usize = 64;
+/// Largest `DIMS` an index accepts.
+pub const MAX_DIMS: usize = 65536;
+
+/// How the distance between two vectors is measured.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum Metric {
+    /// 1 - cosine similarity; vectors are normalized when indexed
+    Cosine,
+    /// Euclidean distance
+    L2,
+    /// Negated dot product, for embeddings whose length carries meaning
+    Dot,
+}
+
+impl Metric {
+    /// The score `SEARCH` reports for a row: cosine similarity, Euclidean
+    /// distance or dot product. `None` for vectors of different lengths,
+    /// and for zero vectors under cosine.
+    pub fn score(self, a: &[f64], b: &[f64]) -> Option<f64> {
+        if a.len() != b.len() || a.is_empty() {
+            return None;
+        }
+        let dot = || a.iter().zip(b).map(|(x, y)| x * y).sum::<f64>();
+        match self {
+            Metric::Cosine => {
+                let norm = |v: &[f64]| v.iter().map(|x| x * x).sum::<f64>().sqrt();
+                let (norm_a, norm_b) = (norm(a), norm(b));
+                (norm_a != 0.0 && norm_b != 0.0).then(|| dot() / (norm_a * norm_b))
+            }
+            Metric::L2 => Some(a.iter().zip(b).map(|(x, y)| (x - y) * (x - y)).sum::<f64>().sqrt()),
+            Metric::Dot => Some(dot()),
+        }
+    }
+
+    /// True when a higher score means closer: every metric but L2, whose
+    /// score is a distance.
+    pub fn higher_is_closer(self) -> bool {
+        self != Metric::L2
+    }
+}
+
+impl std::str::FromStr for Metric {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "cosine" => Ok(Metric::Cosine),
+            "l2" | "euclidean" => Ok(Metric::L2),
+            "dot" => Ok(Metric::Dot),
+            other => Err(format!("Unknown vector metric '{}'", other)),
+        }
+    }
+}
+
+impl std::fmt::Display for Metric {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Metric::Cosine => "cosine",
+            Metric::L2 => "l2",
+            Metric::Dot => "dot",
+        })
+    }
+}
+
+struct Node {
+    vector: Vec<f32>,
+    /// Neighbours per layer, from layer 0 up to the node's top layer
+    links: Vec<Vec<u64>>,
+}
+
+/// A node by its distance to some target; orders by distance, then id.
+#[derive(Clone, Copy, PartialEq)]
+struct Near(f32, u64);
+
+impl Eq for Near {}
+
+impl PartialOrd for Near {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Near {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0).then(self.1.cmp(&other.1))
+    }
+}
+
+pub struct Hnsw {
+    pub dims: usize,
+    pub metric: Metric,
+    nodes: HashMap<u64, Node>,
+    /// Node on the top layer where every search starts
+    entry: Option<u64>,
+}
+
+impl Hnsw {
+    pub fn new(dims: usize, metric: Metric) -> Self {
+        Self { dims, metric, nodes: HashMap::new(), entry: None }
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn contains(&self, id: u64) -> bool {
+        self.nodes.contains_key(&id)
+    }
+
+    /// Approximate bytes held by the graph.
+    pub fn memory_bytes(&self) -> usize {
+        self.nodes.values()
+            .map(|n| n.vector.len() * 4 + n.links.iter().map(|l| l.len() * 8 + 24).sum::<usize>() + 64)
+            .sum()
+    }
+
+    /// True if `vector` can be indexed: the right length, finite, and not
+    /// all zeros under the cosine metric.
+    pub fn accepts(&self, vector: &[f64]) -> bool {
+        self.prepare(vector).is_some()
+    }
+
+    fn prepare(&self, vector: &[f64]) -> Option<Vec<f32>> {
+        if vector.len() != self.dims || !vector.iter().all(|x| x.is_finite()) {
+            return None;
+        }
+        let mut v: Vec<f32> = vector.iter().map(|&x| x as f32).collect();
+        if self.metric == Metric::Cosine {
+            let norm = v.iter().map(|x| x * x).sum::<f32>().sqrt();
+            if norm == 0.0 || !norm.is_finite() {
+                return None;
+            }
+            v.iter_mut().for_each(|x| *x /= norm);
+        }
+        Some(v)
+    }
+
+    fn distance(&self, a: &[f32], b: &[f32]) -> f32 {
+        match self.metric {
+            Metric::Cosine => 1.0 - a.iter().zip(b).map(|(x, y)| x * y).sum::<f32>(),
+            Metric::L2 => a.iter().zip(b).map(|(x, y)| (x - y) * (x - y)).sum::<f32>().sqrt(),
+            Metric::Dot => -a.iter().zip(b).map(|(x, y)| x * y).sum::<f32>(),
+        }
+    }
+
+    fn distance_to(&self, target: &[f32], id: u64) -> Option<f32> {
+        self.nodes.get(&id).map(|n| self.distance(target, &n.vector))
+    }
+
+    /// The node's top layer: 0 with odds 1 - 1/M, and so on up.
+    fn level(id: u64) -> usize {
+        let uniform = ((xxh3_64(&id.to_le_bytes()) >> 11) as f64 + 1.0) / (1u64 << 53) as f64;
+        (-uniform.ln() / (M as f64).ln()) as usize
+    }
+
+    fn max_links(layer: usize) -> usize {
+        if layer == 0 { 2 * M } else { M }
+    }
+
+    /// Adds (or moves) a row. Returns false, leaving it out, if the vector
+    /// cannot be indexed (see [`accepts`](Self::accepts)).
+    pub fn insert(&mut self, id: u64, vector: &[f64]) -> bool {
+        self.remove(id);
+        let Some(vector) = self.prepare(vector) else { return false };
+        let level = Self::level(id);
+        let Some(entry) = self.entry else {
+            self.nodes.insert(id, Node { vector, links: vec![Vec::new(); level + 1] });
+            self.entry = Some(id);
+            return true;
+        };
+
+        let top = self.nodes[&entry].links.len() - 1;
+        let mut nearest = entry;
+        for layer in (level + 1..=top).rev() {
+            nearest = self.search_layer(&vector, &[nearest], 1, layer)[0].1;
+        }
+        let mut links = vec![Vec::new(); level + 1];
+        let mut starts = vec![nearest];
+        for layer in (0..=level.min(top)).rev() {
+            let found = self.search_layer(&vector, &starts, EF_CONSTRUCTION, layer);
+            links[layer] = self.select(&found, Self::max_links(layer));
+            starts = found.iter().map(|n| n.1).collect();
+        }
+
+        for (layer, neighbours) in links.iter().enumerate() {
+            for &n in neighbours {
+                self.link(n, id, &vector, layer);
+            }
+        }
+        self.nodes.insert(id, Node { vector, links });
+        if level > top {
+            self.entry = Some(id);
+        }
+        true
+    }
+
+    /// Adds a link from `from` to a new node, pruning `from`'s links back to
+    /// the best ones when it has too many.
+    fn link(&mut self, from: u64, to: u64, to_vector: &[f32], layer: usize) {
+        let Some(node) = self.nodes.get(&from) else { return };
+        let Some(current) = node.links.get(layer) else { return };
+        if current.len() < Self::max_links(layer) {
+            self.nodes.get_mut(&from).unwrap().links[layer].push(to);
+            return;
+        }
+        let from_vector = node.vector.clone();
+        let mut candidates: Vec<Near> = current.iter()
+            .filter_map(|&n| Some(Near(self.distance_to(&from_vector, n)?, n)))
+            .collect();
+        candidates.push(Near(self.distance(&from_vector, to_vector), to));
+        candidates.sort();
+        let kept = self.select_with(&candidates, Self::max_links(layer), Some((to, to_vector)));
+        self.nodes.get_mut(&from).unwrap().links[layer] = kept;
+    }
+
+    /// Removes a row, reconnecting its neighbours among themselves.
+    pub fn remove(&mut self, id: u64) {
+        let Some(removed) = self.nodes.remove(&id) else { return };
+        for (layer, neighbours) in removed.links.iter().enumerate() {
+            for &n in neighbours {
+                let Some(node) = self.nodes.get(&n) else { continue };
+                let Some(current) = node.links.get(layer) else { continue };
+                let vector = node.vector.clone();
+                let mut ids: HashSet<u64> = current.iter().copied().filter(|&c| c != id).collect();
+                ids.extend(neighbours.iter().copied().filter(|&c| c != n));
+                let mut candidates: Vec<Near> = ids.into_iter()
+                    .filter_map(|c| Some(Near(self.distance_to(&vector, c)?, c)))
+                    .collect();
+                candidates.sort();
+                let kept = self.select(&candidates, Self::max_links(layer));
+                self.nodes.get_mut(&n).unwrap().links[layer] = kept;
+            }
+        }
+        if self.entry == Some(id) {
+            self.entry = self.nodes.iter().max_by_key(|(id, n)| (n.links.len(), Reverse(**id))).map(|(id, _)| *id);
+        }
+    }
+
+    /// The `k` rows nearest to `query`, with their distances, nearest first;
+    /// `ef` (at least `k`) trades speed for recall.
+    pub fn search(&self, query: &[f64], k: usize, ef: usize) -> Vec<(u64, f32)> {
+        self.search_filtered(query, k, ef, |_| true)
+    }
+
+    /// Like [`search`](Self::search), keeping only rows `accept` lets
+    /// through. The walk still crosses the other rows, so the fewer rows are
+    /// accepted, the more of the graph it visits.
+    pub fn search_filtered(&self, query: &[f64], k: usize, ef: usize, accept: impl Fn(u64) -> bool) -> Vec<(u64, f32)> {
+        let (Some(query), Some(entry)) = (self.prepare(query), self.entry) else { return Vec::new() };
+        let top = self.nodes[&entry].links.len() - 1;
+        let mut nearest = entry;
+        for layer in (1..=top).rev() {
+            nearest = self.search_layer(&query, &[nearest], 1, layer)[0].1;
+        }
+        self.search_layer_with(&query, &[nearest], ef.max(k), 0, &accept)
+            .into_iter()
+            .take(k)
+            .map(|Near(distance, id)| (id, distance))
+            .collect()
+    }
+
+    /// Best-first search of one layer from `starts`, keeping the `ef`
+    /// nearest nodes found, nearest first.
+    fn search_layer(&self, target: &[f32], starts: &[u64], ef: usize, layer: usize) -> Vec<Near> {
+        self.search_layer_with(target, starts, ef, layer, &|_| true)
+    }
+
+    /// [`search_layer`](Self::search_layer) keeping only the nodes `accept`
+    /// lets through; the others are walked but not kept.
+    fn search_layer_with(&self, target: &[f32], starts: &[u64], ef: usize, layer: usize, accept: &dyn Fn(u64) -> bool) -> Vec<Near> {
+        let mut visited: HashSet<u64> = starts.iter().copied().collect();
+        let mut candidates = BinaryHeap::new();
+        let mut found = BinaryHeap::new();
+        for &id in starts {
+            if let Some(d) = self.distance_to(target, id) {
+                candidates.push(Reverse(Near(d, id)));
+                if accept(id) {
+                    found.push(Near(d, id));
+                }
+            }
+        }
+        while let Some(Reverse(current)) = candidates.pop() {
+            if found.len() >= ef && found.peek().is_some_and(|worst: &Near| current.0 > worst.0) {
+                break;
+            }
+            let Some(links) = self.nodes.get(&current.1).and_then(|n| n.links.get(layer)) else { continue };
+            for &n in links {
+                if !visited.insert(n) {
+                    continue;
+                }
+                // Links to a removed node can linger until the node linking is pruned
+                let Some(d) = self.distance_to(target, n) else { continue };
+                if found.len() < ef || found.peek().is_some_and(|worst| d < worst.0) {
+                    candidates.push(Reverse(Near(d, n)));
+                    if accept(n) {
+                        found.push(Near(d, n));
+                        if found.len() > ef {
+                            found.pop();
+                        }
+                    }
+                }
+            }
+        }
+        found.into_sorted_vec()
+    }
+
+    fn select(&self, candidates: &[Near], m: usize) -> Vec<u64> {
+        self.select_with(candidates, m, None)
+    }
+
+    /// Picks up to `m` links from `candidates` (nearest first), preferring
+    /// ones closer to the node than to any link already picked, so links
+    /// spread in every direction; the rest fill the remaining places.
+    /// `extra` is a candidate not in the graph yet.
+    fn select_with(&self, candidates: &[Near], m: usize, extra: Option<(u64, &[f32])>) -> Vec<u64> {
+        let vector_of = |id: u64| match extra {
+            Some((extra_id, v)) if extra_id == id => Some(v),
+            _ => self.nodes.get(&id).map(|n| n.vector.as_slice()),
+        };
+        let mut kept: Vec<u64> = Vec::with_capacity(m);
+        let mut skipped = Vec::new();
+        for &Near(d, id) in candidates {
+            if kept.len() == m {
+                break;
+            }
+            let Some(v) = vector_of(id) else { continue };
+            let diverse = kept.iter().all(|&k| vector_of(k).is_none_or(|kv| self.distance(v, kv) > d));
+            if diverse { kept.push(id) } else { skipped.push(id) }
+        }
+        let room = m - kept.len();
+        kept.extend(skipped.into_iter().take(room));
+        kept
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Deterministic pseudo-random vectors
+    fn vectors(n: usize, dims: usize, seed: u64) -> Vec<Vec<f64>> {
+        let mut state = seed;
+        let mut next = move || {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (state >> 33) as f64 / (1u64 << 31) as f64 - 0.5
+        };
+        (0..n).map(|_| (0..dims).map(|_| next()).collect()).collect()
+    }
+
+    fn exact(data: &[(u64, Vec<f64>)], index: &Hnsw, query: &[f64], k: usize) -> Vec<u64> {
+        let q = index.prepare(query).unwrap();
+        let mut all: Vec<Near> = data.iter().map(|(id, v)| Near(index.distance(&q, &index.prepare(v).unwrap()), *id)).collect();
+        all.sort();
+        all.into_iter().take(k).map(|n| n.1).collect()
+    }
+
+    fn recall(data: &[(u64, Vec<f64>)], index: &Hnsw, queries: &[Vec<f64>]) -> f64 {
+        let mut hits = 0;
+        for q in queries {
+            let expected = exact(data, index, q, 10);
+            let got: Vec<u64> = index.search(q, 10, EF_SEARCH).into_iter().map(|(id, _)| id).collect();
+            hits += expected.iter().filter(|id| got.contains(id)).count();
+        }
+        hits as f64 / (queries.len() * 10) as f64
+    }
+
+    #[test]
+    fn finds_nearest_neighbours() {
+        for metric in [Metric::Cosine, Metric::L2, Metric::Dot] {
+            let mut index = Hnsw::new(16, metric);
+            let data: Vec<(u64, Vec<f64>)> = vectors(500, 16, 7).into_iter().enumerate().map(|(i, v)| (i as u64, v)).collect();
+            for (id, v) in &data {
+                assert!(index.insert(*id, v));
+            }
+            assert_eq!(index.len(), 500);
+            let r = recall(&data, &index, &vectors(50, 16, 99));
+            assert!(r >= 0.9, "{} recall {}", metric, r);
+
+            // The nearest to a stored vector is itself (not so for dot products)
+            if metric == Metric::Dot {
+                continue;
+            }
+            let (id, distance) = index.search(&data[123].1, 1, EF_SEARCH)[0];
+            assert_eq!(id, 123);
+            assert!(distance.abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn removed_rows_are_not_found() {
+        let mut index = Hnsw::new(8, Metric::Cosine);
+        let mut data: Vec<(u64, Vec<f64>)> = vectors(400, 8, 3).into_iter().enumerate().map(|(i, v)| (i as u64, v)).collect();
+        for (id, v) in &data {
+            index.insert(*id, v);
+        }
+        let entry = index.entry.unwrap();
+        for id in (0..400).filter(|id| id % 2 == 0).chain([entry]) {
+            index.remove(id);
+        }
+        data.retain(|(id, _)| id % 2 == 1 && *id != entry);
+        assert_eq!(index.len(), data.len());
+        assert!(!index.contains(entry));
+        for q in vectors(20, 8, 11) {
+            assert!(index.search(&q, 10, EF_SEARCH).iter().all(|(id, _)| id % 2 == 1 && *id != entry));
+        }
+        assert!(recall(&data, &index, &vectors(50, 8, 12)) >= 0.9);
+
+        // Moving a row replaces its vector
+        index.insert(1, &[1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0]);
+        assert_eq!(index.search(&[1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0], 1, EF_SEARCH)[0].0, 1);
+    }
+
+    #[test]
+    fn filtered_search_keeps_accepted_rows() {
+        let mut index = Hnsw::new(8, Metric::L2);
+        let data: Vec<(u64, Vec<f64>)> = vectors(400, 8, 5).into_iter().enumerate().map(|(i, v)| (i as u64, v)).collect();
+        for (id, v) in &data {
+            index.insert(*id, v);
+        }
+        let third: Vec<(u64, Vec<f64>)> = data.iter().filter(|(id, _)| id % 3 == 0).cloned().collect();
+        let mut hits = 0;
+        for q in vectors(20, 8, 6) {
+            let got: Vec<u64> = index.search_filtered(&q, 10, EF_SEARCH, |id| id % 3 == 0).into_iter().map(|(id, _)| id).collect();
+            assert!(got.len() == 10 && got.iter().all(|id| id % 3 == 0));
+            hits += exact(&third, &index, &q, 10).iter().filter(|id| got.contains(id)).count();
+        }
+        assert!(hits as f64 / 200.0 >= 0.9);
+        assert!(index.search_filtered(&data[0].1, 5, EF_SEARCH, |_| false).is_empty());
+    }
+
+    #[test]
+    fn rejects_vectors_it_cannot_index() {
+        let mut index = Hnsw::new(3, Metric::Cosine);
+        assert!(!index.insert(1, &[1.0, 2.0]));
+        assert!(!index.insert(2, &[0.0, 0.0, 0.0]));
+        assert!(!index.insert(3, &[f64::NAN, 0.0, 1.0]));
+        assert!(index.is_empty());
+        assert!(Hnsw::new(3, Metric::L2).accepts(&[0.0, 0.0, 0.0]));
+        assert!(index.search(&[1.0, 0.0, 0.0], 5, EF_SEARCH).is_empty());
+        assert_eq!("COSINE".parse::<Metric>(), Ok(Metric::Cosine));
+        assert_eq!("euclidean".parse::<Metric>(), Ok(Metric::L2));
+        assert!("manhattan".parse::<Metric>().is_err());
+    }
+
+    #[test]
+    fn scores_per_metric() {
+        let (a, b) = ([3.0, 4.0], [3.0, 0.0]);
+        assert_eq!(Metric::Cosine.score(&a, &b), Some(0.6));
+        assert_eq!(Metric::L2.score(&a, &b), Some(4.0));
+        assert_eq!(Metric::Dot.score(&a, &b), Some(9.0));
+        assert_eq!(Metric::Cosine.score(&a, &[0.0, 0.0]), None);
+        assert_eq!(Metric::Dot.score(&a, &[1.0]), None);
+        assert!(!Metric::L2.higher_is_closer() && Metric::Dot.higher_is_closer());
+    }
+}
@@ -0,0 +1,109 @@
+//! # Write Locks
+//!
+//! Writes to different keys or tables run side by side. Each write locks the
+//! stripes of the keys and tables it names, out of [`KEY_STRIPES`] and
+//! [`TABLE_STRIPES`] mutexes picked by a hash of the name, and holds the
+//! global lock shared. Writes that can touch anything (ACL changes, AOF
+//! rewrites, restoring a snapshot...) hold the global lock exclusively
+//! instead, waiting for every other write to finish.
+//!
+//! Stripes are taken in one order, global lock first, then key stripes and
+//! table stripes by index, so two writes never wait on each other.
+
+use std::sync::{Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use xxhash_rust::xxh3::xxh3_64;
+
+/// Lock stripes for keys of the flexible store.
+pub const KEY_STRIPES: usize = 1024;
+
+/// Lock stripes for tables.
+pub const TABLE_STRIPES: usize = 64;
+
+/// Write locks of one database.
+pub struct LockManager {
+    global: RwLock<()>,
+    keys: Box<[Mutex<()>]>,
+    tables: Box<[Mutex<()>]>,
+}
+
+/// Locks held by a write, released on drop.
+pub struct WriteGuard<'a> {
+    // Stripes go first: fields drop in order, and the global lock must be
+    // let go of last
+    _stripes: Vec<MutexGuard<'a, ()>>,
+    _shared: Option<RwLockReadGuard<'a, ()>>,
+    _exclusive: Option<RwLockWriteGuard<'a, ()>>,
+}
+
+impl Default for LockManager {
+    fn default() -> Self {
+        Self {
+            global: RwLock::new(()),
+            keys: (0..KEY_STRIPES).map(|_| Mutex::new(())).collect(),
+            tables: (0..TABLE_STRIPES).map(|_| Mutex::new(())).collect(),
+        }
+    }
+}
+
+impl LockManager {
+    /// Locks `keys` and `tables` against other writes to them.
+    pub fn lock<'k, 't>(&self, keys: impl IntoIterator<Item = &'k str>, tables: impl IntoIterator<Item = &'t str>) -> WriteGuard<'_> {
+        let shared = self.global.read().unwrap_or_else(|e| e.into_inner());
+        let mut stripes = Vec::new();
+        for (names, locks) in [(stripe_indexes(keys, KEY_STRIPES), &self.keys), (stripe_indexes(tables, TABLE_STRIPES), &self.tables)] {
+            stripes.extend(names.into_iter().map(|i| locks[i].lock().unwrap_or_else(|e| e.into_inner())));
+        }
+        WriteGuard { _stripes: stripes, _shared: Some(shared), _exclusive: None }
+    }
+
+    /// Locks everything, once the writes under way are done.
+    pub fn lock_all(&self) -> WriteGuard<'_> {
+        WriteGuard { _stripes: Vec::new(), _shared: None, _exclusive: Some(self.global.write().unwrap_or_else(|e| e.into_inner())) }
+    }
+}
+
+/// Stripes of `names`, sorted and without repeats.
+fn stripe_indexes<'a>(names: impl IntoIterator<Item = &'a str>, stripes: usize) -> Vec<usize> {
+    let mut indexes: Vec<usize> = names.into_iter().map(|name| xxh3_64(name.as_bytes()) as usize % stripes).collect();
+    indexes.sort_unstable();
+    indexes.dedup();
+    indexes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    #[test]
+    fn unrelated_writes_do_not_wait() {
+        let locks = LockManager::default();
+        let _a = locks.lock(["a", "a"], ["t"]);
+        let (tx, rx) = mpsc::channel();
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                let other = (0..).map(|i| format!("k{}", i)).find(|k| stripe_indexes([k.as_str()], KEY_STRIPES) != stripe_indexes(["a"], KEY_STRIPES)).unwrap();
+                let _b = locks.lock([other.as_str()], []);
+                tx.send(()).unwrap();
+            });
+            assert!(rx.recv_timeout(Duration::from_secs(5)).is_ok());
+        });
+    }
+
+    #[test]
+    fn lock_all_waits_for_writes() {
+        let locks = LockManager::default();
+        let a = locks.lock(["a"], []);
+        let (tx, rx) = mpsc::channel();
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                let _all = locks.lock_all();
+                tx.send(()).unwrap();
+            });
+            assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+            drop(a);
+            assert!(rx.recv_timeout(Duration::from_secs(5)).is_ok());
+        });
+    }
+}
@@ -16,19 +16,47 @@ pub struct DatabaseEngine {
     pub clients: Arc<DashMap<String, ClientInfo>>,
     pub replication: Arc<ReplicationManager>,
     pub cluster: Arc<ClusterManager>,
-    pub max_connections: usize,
-    pub transaction_lock: Arc<Mutex<()>>,
+    pub config: Arc<Config>,
+    /// Write locks by key and table (see `core::locks`)
+    pub locks: Arc<LockManager>,
+    /// Write versions checked by `WATCH`
+    pub key_versions: Arc<KeyVersions>,
+    pub queries: Arc<QueryRegistry>,
+    /// Held shared by a client write from running until it is logged, and
+    /// alone by `SAVE`, so the AOF position of a snapshot matches its state
+    pub log_gate: Arc<RwLock<()>>,
+    /// The registry holding every database of the server, for commands
+    /// that reach beyond this one; dangling for an engine made on its own
+    pub registry: Weak<DatabaseRegistry>,
 }
 
 use super::flexible::FlexibleStore;
+use super::backend::{KvBackend, MemoryBackend};
 use super::structured::StructuredStore;
 use super::security::SecurityStore;
 use super::replication::ReplicationManager;
 use super::cluster::ClusterManager;
-use std::sync::{Arc, Mutex};
+use super::queries::QueryRegistry;
+use super::config::Config;
+use super::registry::DatabaseRegistry;
+use super::watch::KeyVersions;
+use super::locks::LockManager;
+use std::sync::{Arc, RwLock, Weak};
 
 impl DatabaseEngine {
+    /// An engine on the in-memory backend, with the cold tier if configured.
     pub fn new(db_name: String) -> Self {
+        let backend = Arc::new(MemoryBackend::for_database(&db_name));
+        Self::with_backend(db_name, backend)
+    }
+
+    /// An engine whose flexible keyspace lives in `backend`.
+    pub fn with_backend(db_name: String, backend: Arc<dyn KvBackend>) -> Self {
+        Self { flexible: FlexibleStore::with_backend(backend), ..Self::ephemeral(db_name) }
+    }
+
+    /// An engine with no disk footprint (no cold tier), for restore rehearsals.
+    pub fn ephemeral(db_name: String) -> Self {
         Self {
             db_name,
             flexible: FlexibleStore::new(),
@@ -37,20 +65,26 @@ impl DatabaseEngine {
             clients: Arc::new(DashMap::new()),
             replication: Arc::new(ReplicationManager::new()),
             cluster: Arc::new(ClusterManager::new()),
-            max_connections: 100, // Default limit
-            transaction_lock: Arc::new(Mutex::new(())),
+            config: crate::core::config::shared(),
+            locks: Arc::new(LockManager::default()),
+            key_versions: Arc::new(KeyVersions::default()),
+            queries: Arc::new(QueryRegistry::new()),
+            log_gate: Arc::default(),
+            registry: Weak::new(),
         }
     }
 
+    /// Lazily yields the commands needed to rebuild the current state.
+    pub fn dump_commands(&self) -> impl Iterator<Item = String> + '_ {
+        self.flexible.dump_commands().chain(self.structured.dump_commands())
+    }
+
     pub fn generate_rewrite_commands(&self) -> Vec<String> {
-        let mut commands = Vec::new();
-        commands.extend(self.flexible.dump_commands());
-        commands.extend(self.structured.dump_commands());
-        commands
+        self.dump_commands().collect()
     }
     
     pub fn save_snapshot(&self) -> std::io::Result<()> {
-        let data_dir = std::env::var("DB_DATA_DIR").unwrap_or_else(|_| "data".to_string());
+        let data_dir = crate::core::config::shared().data_dir();
         // mkdir loop handled in AofLogger/main, but redundant check ok
         let _ = std::fs::create_dir_all(&data_dir);
         let _path = format!("{}/{}_dump.json", data_dir, self.db_name);
@@ -68,12 +102,12 @@ impl DatabaseEngine {
 
 
     pub fn load_from_snapshot(&mut self, snapshot: crate::core::snapshot::SnapshotData) {
-        self.flexible = FlexibleStore::import_from(snapshot.flexible_data);
-        self.structured = StructuredStore::import_from(snapshot.structured_data);
+        self.flexible.restore(snapshot.keyspace);
+        self.structured = StructuredStore::import_from(snapshot.structured_data, snapshot.indexes);
         // We could also restore timestamp or other metadata if needed
     }
     pub fn restore_state(&self, snapshot: crate::core::snapshot::SnapshotData) {
-        self.flexible.restore(snapshot.flexible_data);
-        self.structured.restore(snapshot.structured_data);
+        self.flexible.restore(snapshot.keyspace);
+        self.structured.restore(snapshot.structured_data, snapshot.indexes);
     }
 }
@@ -0,0 +1,237 @@
+//! # Command Metrics
+//!
+//! Process-wide counters filled in by the worker pool and the connection
+//! handler: calls, errors, latency, and request/reply bytes per command, plus
+//! keyspace hits and misses of key lookups.
+//!
+//! Latencies go into a log2 histogram per command (bucket `i` holds calls
+//! that took `[2^i, 2^(i+1))` microseconds), so p50/p99 are estimates: the
+//! upper bound of the bucket the percentile falls into, capped by the slowest
+//! call seen. `METRICS` shows the table, `METRICS RESET` clears it.
+
+use crate::core::executor::CommandOutput;
+use crate::core::structured::ResultSet;
+use crate::core::types::UnifiedValue;
+use crate::net::prometheus::Exposition;
+use crate::query::Command;
+use dashmap::DashMap;
+use std::sync::LazyLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+const BUCKETS: usize = 32;
+
+/// Picks one counter out of a command's stats.
+type Counter = fn(&CommandStats) -> &AtomicU64;
+
+#[derive(Default)]
+struct CommandStats {
+    calls: AtomicU64,
+    errors: AtomicU64,
+    total_us: AtomicU64,
+    max_us: AtomicU64,
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    latency: [AtomicU64; BUCKETS],
+}
+
+impl CommandStats {
+    /// Estimated latency below which a fraction `q` of the calls finished.
+    fn percentile_us(&self, q: f64) -> u64 {
+        let counts: Vec<u64> = self.latency.iter().map(|b| b.load(Ordering::Relaxed)).collect();
+        let total: u64 = counts.iter().sum();
+        if total == 0 {
+            return 0;
+        }
+        let rank = ((total as f64 * q).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (i, count) in counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return ((1u64 << (i + 1)) - 1).min(self.max_us.load(Ordering::Relaxed));
+            }
+        }
+        self.max_us.load(Ordering::Relaxed)
+    }
+}
+
+pub struct Metrics {
+    commands: DashMap<&'static str, CommandStats>,
+    keyspace_hits: AtomicU64,
+    keyspace_misses: AtomicU64,
+    net_output_bytes: AtomicU64,
+}
+
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(|| Metrics {
+    commands: DashMap::new(),
+    keyspace_hits: AtomicU64::new(0),
+    keyspace_misses: AtomicU64::new(0),
+    net_output_bytes: AtomicU64::new(0),
+});
+
+impl Metrics {
+    /// Records one executed command: its run time, request size and outcome.
+    pub fn record(&self, cmd: &Command, elapsed: Duration, bytes_in: usize, output: &CommandOutput) {
+        let us = elapsed.as_micros().min(u64::MAX as u128) as u64;
+        let stats = self.commands.entry(cmd.name()).or_default();
+        stats.calls.fetch_add(1, Ordering::Relaxed);
+        if output.is_error() {
+            stats.errors.fetch_add(1, Ordering::Relaxed);
+        }
+        stats.total_us.fetch_add(us, Ordering::Relaxed);
+        stats.max_us.fetch_max(us, Ordering::Relaxed);
+        stats.bytes_in.fetch_add(bytes_in as u64, Ordering::Relaxed);
+        let bucket = (u64::BITS - us.leading_zeros()).saturating_sub(1) as usize;
+        stats.latency[bucket.min(BUCKETS - 1)].fetch_add(1, Ordering::Relaxed);
+
+        if let Some(hit) = keyspace_lookup(cmd, output) {
+            let counter = if hit { &self.keyspace_hits } else { &self.keyspace_misses };
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Records the size of a reply sent to a client.
+    pub fn record_reply(&self, name: &'static str, bytes: usize) {
+        self.net_output_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        if let Some(stats) = self.commands.get(name) {
+            stats.bytes_out.fetch_add(bytes as u64, Ordering::Relaxed);
+        }
+    }
+
+    pub fn reset(&self) {
+        self.commands.clear();
+        self.keyspace_hits.store(0, Ordering::Relaxed);
+        self.keyspace_misses.store(0, Ordering::Relaxed);
+        self.net_output_bytes.store(0, Ordering::Relaxed);
+    }
+
+    /// `METRICS`: one row per command seen, slowest p99 first.
+    pub fn table(&self) -> ResultSet {
+        let int = |n: u64| UnifiedValue::Integer(n.min(i64::MAX as u64) as i64);
+        let mut rows: Vec<(u64, Vec<UnifiedValue>)> = self.commands.iter().map(|kv| {
+            let stats = kv.value();
+            let calls = stats.calls.load(Ordering::Relaxed);
+            let p99 = stats.percentile_us(0.99);
+            (p99, vec![
+                UnifiedValue::String(kv.key().to_string()),
+                int(calls),
+                int(stats.errors.load(Ordering::Relaxed)),
+                int(stats.total_us.load(Ordering::Relaxed).checked_div(calls).unwrap_or(0)),
+                int(stats.percentile_us(0.5)),
+                int(p99),
+                int(stats.bytes_in.load(Ordering::Relaxed)),
+                int(stats.bytes_out.load(Ordering::Relaxed)),
+            ])
+        }).collect();
+        rows.sort_by_key(|(p99, _)| std::cmp::Reverse(*p99));
+        let columns = ["command", "calls", "errors", "avg_us", "p50_us", "p99_us", "bytes_in", "bytes_out"]
+            .map(String::from).to_vec();
+        ResultSet { columns, rows: rows.into_iter().map(|(_, row)| row).collect() }
+    }
+
+    /// Per-command counters and latency histograms, plus the keyspace counters.
+    pub fn prometheus(&self, out: &mut Exposition) {
+        let mut commands: Vec<_> = self.commands.iter().collect();
+        commands.sort_by_key(|kv| *kv.key());
+        let counters: [(&str, &str, Counter); 4] = [
+            ("toridb_commands_total", "Commands executed.", |s| &s.calls),
+            ("toridb_command_errors_total", "Commands that replied with an error.", |s| &s.errors),
+            ("toridb_command_input_bytes_total", "Bytes of command requests.", |s| &s.bytes_in),
+            ("toridb_command_output_bytes_total", "Bytes of command replies.", |s| &s.bytes_out),
+        ];
+        for (name, help, counter) in counters {
+            out.family(name, "counter", help);
+            for kv in &commands {
+                out.sample(name, &[("command", kv.key())], counter(kv.value()).load(Ordering::Relaxed));
+            }
+        }
+
+        let name = "toridb_command_duration_seconds";
+        out.family(name, "histogram", "Command execution time in the worker pool.");
+        for kv in &commands {
+            let (command, stats) = (*kv.key(), kv.value());
+            let mut cumulative = 0;
+            // The last bucket is open-ended, so it only shows up in +Inf
+            for (i, bucket) in stats.latency[..BUCKETS - 1].iter().enumerate() {
+                cumulative += bucket.load(Ordering::Relaxed);
+                let le = ((1u64 << (i + 1)) as f64 / 1e6).to_string();
+                out.sample(&format!("{}_bucket", name), &[("command", command), ("le", &le)], cumulative);
+            }
+            let calls = stats.calls.load(Ordering::Relaxed);
+            out.sample(&format!("{}_bucket", name), &[("command", command), ("le", "+Inf")], calls);
+            out.sample(&format!("{}_sum", name), &[("command", command)], stats.total_us.load(Ordering::Relaxed) as f64 / 1e6);
+            out.sample(&format!("{}_count", name), &[("command", command)], calls);
+        }
+
+        out.single("toridb_net_output_bytes_total", "counter", "Bytes of replies sent to clients.", self.net_output_bytes.load(Ordering::Relaxed));
+        out.single("toridb_keyspace_hits_total", "counter", "Key lookups that found their key.", self.keyspace_hits.load(Ordering::Relaxed));
+        out.single("toridb_keyspace_misses_total", "counter", "Key lookups that found nothing.", self.keyspace_misses.load(Ordering::Relaxed));
+    }
+
+    /// Lines for the `# Stats` section of INFO.
+    pub fn info(&self) -> String {
+        let calls: u64 = self.commands.iter().map(|kv| kv.value().calls.load(Ordering::Relaxed)).sum();
+        format!(
+            "total_commands_processed:{}\r\ntotal_net_output_bytes:{}\r\nkeyspace_hits:{}\r\nkeyspace_misses:{}\r\n",
+            calls,
+            self.net_output_bytes.load(Ordering::Relaxed),
+            self.keyspace_hits.load(Ordering::Relaxed),
+            self.keyspace_misses.load(Ordering::Relaxed),
+        )
+    }
+}
+
+/// Whether a single-key read found its key (`None` for other commands).
+fn keyspace_lookup(cmd: &Command, output: &CommandOutput) -> Option<bool> {
+    match cmd {
+        Command::Get { .. } | Command::GetEx { .. } | Command::HGet { .. } |
+        Command::JsonGet { .. } | Command::ZScore { .. } => match output {
+            CommandOutput::Err(_) => None,
+            CommandOutput::Nil => Some(false),
+            _ => Some(true),
+        },
+        _ => None,
+    }
+}
+
+/// Resident set size of the process, where `/proc` is available.
+#[cfg(unix)]
+pub fn resident_memory_bytes() -> Option<u64> {
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    // SAFETY: sysconf only reads a system constant
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    Some(pages * u64::try_from(page_size).ok()?)
+}
+
+#[cfg(not(unix))]
+pub fn resident_memory_bytes() -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles_come_from_the_histogram() {
+        let stats = CommandStats::default();
+        for us in [3u64, 5, 6, 7, 900] {
+            let bucket = (u64::BITS - us.leading_zeros()).saturating_sub(1) as usize;
+            stats.latency[bucket].fetch_add(1, Ordering::Relaxed);
+            stats.max_us.fetch_max(us, Ordering::Relaxed);
+        }
+        // 3 is in [2, 4), 5..7 in [4, 8), 900 in [512, 1024) capped by the max
+        assert_eq!(stats.percentile_us(0.5), 7);
+        assert_eq!(stats.percentile_us(0.99), 900);
+        assert_eq!(CommandStats::default().percentile_us(0.99), 0);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn resident_memory_is_whole_pages() {
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u64;
+        let rss = resident_memory_bytes().unwrap();
+        assert!(rss > 0 && rss % page_size == 0, "{} bytes", rss);
+    }
+}
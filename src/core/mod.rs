@@ -1,17 +1,43 @@
 pub mod memory;
 pub mod structured;
 pub mod flexible;
+pub mod backend;
+pub mod cold;
+pub mod eviction;
+pub mod topk;
+pub mod counter;
+pub mod stream;
+pub mod hll;
+pub mod bitmap;
+pub mod geo;
+pub mod datetime;
+pub mod hnsw;
+pub mod scripting;
 pub mod persistence;
+pub mod aof_check;
 pub mod snapshot;
+pub mod backup;
+pub mod dump;
 pub mod security;
+pub mod auth;
 pub mod executor;
+pub mod queries;
+pub mod profile;
+pub mod output;
 pub mod worker;
 pub mod replication;
+pub mod schedule;
 pub mod cluster;
+pub mod digest;
+pub mod watch;
+pub mod locks;
+pub mod metrics;
 
+pub mod config;
 pub mod logger;
 pub mod registry;
 pub mod uri;
+pub mod glob;
 pub mod types;
 
 
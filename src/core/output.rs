@@ -0,0 +1,149 @@
+//! # Output Formatting
+//!
+//! Per-session reply format, switched with `SET output = json|text|resp`.
+//!
+//! In JSON mode, SELECT, HGETALL and LRANGE reply with a single JSON document
+//! instead of one line per row. In RESP mode, SELECT replies with a native
+//! array whose first element describes the columns. The serializers only
+//! depend on result data, so any front end can share them.
+
+use crate::core::structured::ResultSet;
+use crate::core::types::UnifiedValue;
+use crate::net::resp::RespValue;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum OutputFormat {
+    /// Human-readable lines (default)
+    #[default]
+    Text,
+    Json,
+    /// RESP array: column header, then one array per row (SELECT only)
+    Resp,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            "resp" => Ok(OutputFormat::Resp),
+            other => Err(format!("Unknown output format '{}'", other)),
+        }
+    }
+}
+
+/// One line per row, e.g. `["1", "ann"]`.
+pub fn rows_text(result: &ResultSet) -> String {
+    if result.rows.is_empty() {
+        return "EMPTY".to_string();
+    }
+    result.rows.iter()
+        .map(|row| format!("{:?}", row.iter().map(|v| v.to_string()).collect::<Vec<_>>()))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Array of objects keyed by column name, in column order.
+pub fn rows_json(result: &ResultSet) -> String {
+    let rows: Vec<String> = result.rows.iter()
+        .map(|row| {
+            let fields: Vec<String> = result.columns.iter().zip(row)
+                .map(|(col, val)| format!("{}:{}", json_string(col), serde_json::Value::from(val)))
+                .collect();
+            format!("{{{}}}", fields.join(","))
+        })
+        .collect();
+    format!("[{}]", rows.join(","))
+}
+
+/// `[[name, type], ...]` followed by one array per row. NULLs are null bulk
+/// strings, integers are RESP integers and everything else is a bulk string.
+pub fn rows_resp(result: &ResultSet) -> RespValue {
+    let header = result.columns.iter().zip(result.column_types())
+        .map(|(name, ty)| RespValue::Array(Some(vec![bulk(name), bulk(ty)])))
+        .collect();
+    let mut items = vec![RespValue::Array(Some(header))];
+    items.extend(result.rows.iter().map(|row| {
+        RespValue::Array(Some(row.iter().map(value_resp).collect()))
+    }));
+    RespValue::Array(Some(items))
+}
+
+fn value_resp(val: &UnifiedValue) -> RespValue {
+    match val {
+        UnifiedValue::Null => RespValue::BulkString(None),
+        UnifiedValue::Integer(i) => RespValue::Integer(*i),
+        UnifiedValue::Blob(b) => bulk(b),
+        UnifiedValue::Array(_) | UnifiedValue::Object(_) | UnifiedValue::Vector(_) => {
+            bulk(&serde_json::Value::from(val).to_string())
+        }
+        other => bulk(&other.to_string()),
+    }
+}
+
+fn bulk(s: &str) -> RespValue {
+    RespValue::BulkString(Some(s.as_bytes().to_vec()))
+}
+
+/// Object built from a flat `[field, value, ...]` reply.
+pub fn hash_json(pairs: &[String]) -> String {
+    let fields: Vec<String> = pairs.chunks(2)
+        .filter(|kv| kv.len() == 2)
+        .map(|kv| format!("{}:{}", json_string(&kv[0]), json_string(&kv[1])))
+        .collect();
+    format!("{{{}}}", fields.join(","))
+}
+
+/// Array of string elements.
+pub fn list_json(items: &[String]) -> String {
+    serde_json::Value::from(items.to_vec()).to_string()
+}
+
+fn json_string(s: &str) -> String {
+    serde_json::Value::String(s.to_string()).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rows_json_keeps_column_order_and_types() {
+        let result = ResultSet {
+            columns: vec!["name".to_string(), "age".to_string()],
+            rows: vec![vec![UnifiedValue::String("ann".to_string()), UnifiedValue::Integer(30)]],
+        };
+        assert_eq!(rows_json(&result), r#"[{"name":"ann","age":30}]"#);
+        assert_eq!(rows_text(&result), r#"["ann", "30"]"#);
+    }
+
+    #[test]
+    fn rows_resp_starts_with_column_header() {
+        let result = ResultSet {
+            columns: vec!["id".to_string(), "name".to_string()],
+            rows: vec![
+                vec![UnifiedValue::Integer(1), UnifiedValue::Null],
+                vec![UnifiedValue::Integer(2), UnifiedValue::String("bo".to_string())],
+            ],
+        };
+        let expected = RespValue::Array(Some(vec![
+            RespValue::Array(Some(vec![
+                RespValue::Array(Some(vec![bulk("id"), bulk("int")])),
+                RespValue::Array(Some(vec![bulk("name"), bulk("string")])),
+            ])),
+            RespValue::Array(Some(vec![RespValue::Integer(1), RespValue::BulkString(None)])),
+            RespValue::Array(Some(vec![RespValue::Integer(2), bulk("bo")])),
+        ]));
+        assert_eq!(rows_resp(&result), expected);
+    }
+
+    #[test]
+    fn hash_and_list_json() {
+        let pairs = vec!["f".to_string(), "v\"1".to_string()];
+        assert_eq!(hash_json(&pairs), r#"{"f":"v\"1"}"#);
+        let items = vec!["a".to_string(), "1".to_string()];
+        assert_eq!(list_json(&items), r#"["a","1"]"#);
+    }
+}
@@ -1,23 +1,73 @@
 use std::fs::{OpenOptions, File};
-use std::io::{self, Write, BufReader, BufRead};
+use std::io::{self, Write, BufReader, BufRead, BufWriter, Read, Seek, SeekFrom};
+use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc;
+use crate::core::backend::now_unix_ms;
+use crate::core::config::FsyncPolicy;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 
+/// Number of rewrite chunks allowed to wait between the producer and the AOF thread.
+const REWRITE_QUEUE_DEPTH: usize = 4;
+/// Bytes before an [`AofPosition`] covered by its checksum.
+const POSITION_TAIL: u64 = 256;
 
 pub enum AofOp {
     Log(String),
-    Rewrite(Vec<String>),
+    /// Streamed rewrite: chunks arrive through the bounded receiver until `Complete`.
+    Rewrite(std::sync::mpsc::Receiver<RewriteChunk>),
+    /// Once-a-second tick, for the `everysec` fsync policy.
+    Sync,
+    /// Asks for the position of the file once everything queued before is written.
+    Mark(std::sync::mpsc::Sender<io::Result<AofPosition>>),
+}
+
+pub enum RewriteChunk {
+    Commands(Vec<String>),
+    Complete,
 }
 
 #[derive(Clone)]
 pub struct AofLogger {
     sender: mpsc::Sender<AofOp>,
     path: String,
+    rewrites: Arc<RewriteState>,
+}
+
+/// Rewrites of one AOF so far, shared with its thread.
+#[derive(Default)]
+struct RewriteState {
+    /// Size of the AOF after the last rewrite, or when it was opened
+    base_size: AtomicU64,
+    in_progress: AtomicBool,
+    completed: AtomicU64,
+    /// When the last rewrite ended (unix ms) and whether it succeeded
+    last: Mutex<Option<(u64, bool)>>,
+}
+
+impl RewriteState {
+    fn finish(&self, ok: bool, size: u64) {
+        if ok {
+            self.base_size.store(size, Ordering::Relaxed);
+            self.completed.fetch_add(1, Ordering::Relaxed);
+        }
+        *self.last.lock().unwrap_or_else(|e| e.into_inner()) = Some((now_unix_ms(), ok));
+        self.in_progress.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Whether an AOF of `size` bytes, `base` after its last rewrite, is due for
+/// an automatic one: at least `min_size` bytes and grown by `percentage`
+/// percent. A percentage of 0 turns automatic rewrites off.
+fn rewrite_due(size: u64, base: u64, min_size: u64, percentage: u64) -> bool {
+    percentage > 0 && size >= min_size && size.saturating_sub(base).saturating_mul(100) >= base.saturating_mul(percentage)
 }
 
 impl AofLogger {
     pub fn new(db_name: &str) -> io::Result<Self> {
         // User requested logs in /data. Defaulting to 'data'.
-        let dir = std::env::var("DB_DATA_DIR").unwrap_or_else(|_| "data".to_string());
+        let dir = crate::core::config::shared().data_dir();
         std::fs::create_dir_all(&dir)?;
         
         let path = format!("{}/{}.db", dir, db_name);
@@ -36,9 +86,26 @@ impl AofLogger {
             .open(&path)?;
 
         let worker_path = path.to_string();
+        let config = crate::core::config::shared();
+        let rewrites = Arc::new(RewriteState::default());
+        rewrites.base_size.store(file.metadata()?.len(), Ordering::Relaxed);
+        let worker_rewrites = rewrites.clone();
+
+        // Ticks for `everysec`; stops once the logger is gone
+        let ticks = tx.downgrade();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(std::time::Duration::from_secs(1));
+            match ticks.upgrade() {
+                Some(tx) => { let _ = tx.try_send(AofOp::Sync); }
+                None => break,
+            }
+        });
 
         // Use std::thread instead of tokio::spawn to isolate blocking I/O
         std::thread::spawn(move || {
+            // Written since the last fsync
+            let mut unsynced = false;
+            let mut last_sync = std::time::Instant::now();
             loop {
                 // 1. Fetch Batch
                 let mut batch = Vec::with_capacity(100);
@@ -64,33 +131,50 @@ impl AofLogger {
                 for op in batch {
                     match op {
                         AofOp::Log(command) => {
-                            let mut hasher = crc32fast::Hasher::new();
-                            hasher.update(command.as_bytes());
-                            let checksum = hasher.finalize();
-                            
-                            if let Err(e) = writeln!(file, "CRC32:{:x}:{}", checksum, command) {
+                            if let Err(e) = writeln!(file, "{}", encode_record(&command, now_unix_ms())) {
                                 crate::core::logger::error(&format!("AOF Write Error: {}", e));
                             }
                             needs_flush = true;
                         }
-                        AofOp::Rewrite(commands) => {
-                             if let Err(e) = Self::perform_rewrite(&worker_path, &commands) {
+                        AofOp::Sync => {}
+                        AofOp::Mark(reply) => {
+                            let position = file.metadata().and_then(|meta| AofPosition::at(&worker_path, meta.len()));
+                            let _ = reply.send(position);
+                        }
+                        AofOp::Rewrite(chunks) => {
+                             if let Err(e) = Self::perform_rewrite(&worker_path, chunks) {
                                  crate::core::logger::error(&format!("AOF Rewrite Error: {}", e));
+                                 worker_rewrites.finish(false, 0);
                              } else {
                                 match OpenOptions::new().create(true).append(true).open(&worker_path) {
                                     Ok(f) => file = f,
                                     Err(e) => crate::core::logger::error(&format!("AOF Re-open Error: {}", e)),
                                 }
+                                let size = std::fs::metadata(&worker_path).map_or(0, |m| m.len());
+                                worker_rewrites.finish(true, size);
                              }
                         }
                     }
                 }
 
-                // 3. Flush
+                // 3. Flush, and fsync as the policy asks
                 if needs_flush {
                     if let Err(e) = file.flush() {
                         crate::core::logger::error(&format!("AOF Flush Error: {}", e));
                     }
+                    unsynced = true;
+                }
+                let sync_due = match config.fsync() {
+                    FsyncPolicy::Always => true,
+                    FsyncPolicy::Everysec => last_sync.elapsed() >= std::time::Duration::from_secs(1),
+                    FsyncPolicy::No => false,
+                };
+                if unsynced && sync_due {
+                    if let Err(e) = file.sync_data() {
+                        crate::core::logger::error(&format!("AOF Fsync Error: {}", e));
+                    }
+                    unsynced = false;
+                    last_sync = std::time::Instant::now();
                 }
             }
         });
@@ -98,91 +182,223 @@ impl AofLogger {
         Ok(Self {
             sender: tx,
             path: path_owned,
+            rewrites,
         })
     }
 
+    /// A logger with no AOF thread behind it: `log` and `rewrite` fail, nothing
+    /// touches disk. Used to replay commands into throwaway engines.
+    pub fn detached(path: &str) -> Self {
+        let (tx, _) = mpsc::channel::<AofOp>(1);
+        Self {
+            sender: tx,
+            path: path.to_string(),
+            rewrites: Arc::default(),
+        }
+    }
+
     // Helper for rewrite logic (static/detached from self)
-    fn perform_rewrite(path: &str, commands: &Vec<String>) -> io::Result<()> {
+    // Consumes chunks as the producer generates them, so only a bounded
+    // number of commands is ever held in memory.
+    fn perform_rewrite(path: &str, chunks: std::sync::mpsc::Receiver<RewriteChunk>) -> io::Result<()> {
         let temp_path = format!("{}.rewrite", path);
+        let mut completed = false;
         {
-            let mut file = File::create(&temp_path)?;
-            for cmd in commands {
-                let mut hasher = crc32fast::Hasher::new();
-                hasher.update(cmd.as_bytes());
-                let checksum = hasher.finalize();
-                writeln!(file, "CRC32:{:x}:{}", checksum, cmd)?;
+            let mut file = BufWriter::new(File::create(&temp_path)?);
+            for chunk in chunks.iter() {
+                match chunk {
+                    RewriteChunk::Commands(commands) => {
+                        // Rewritten records carry the time of the rewrite
+                        let now = now_unix_ms();
+                        for cmd in commands {
+                            writeln!(file, "{}", encode_record(&cmd, now))?;
+                        }
+                    }
+                    RewriteChunk::Complete => {
+                        completed = true;
+                        break;
+                    }
+                }
             }
             file.flush()?;
         }
+
+        // Producer went away mid-stream: keep the old AOF untouched
+        if !completed {
+            let _ = std::fs::remove_file(&temp_path);
+            return Err(io::Error::other("rewrite stream ended before completion"));
+        }
+
         // Atomic rename
         std::fs::rename(&temp_path, path)?;
         Ok(())
     }
 
+    /// File the logger appends to.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Operations waiting for the AOF thread.
+    pub fn queue_depth(&self) -> usize {
+        self.sender.max_capacity() - self.sender.capacity()
+    }
+
+    /// Bytes in the AOF file, including records not yet fsynced.
+    pub fn size(&self) -> u64 {
+        std::fs::metadata(&self.path).map_or(0, |m| m.len())
+    }
+
+    /// Whether the AOF has grown enough since its last rewrite to be
+    /// rewritten automatically (`auto_aof_rewrite_min_size` and
+    /// `auto_aof_rewrite_percentage`). Never while a rewrite is running.
+    pub fn rewrite_due(&self, min_size: u64, percentage: u64) -> bool {
+        !self.rewrites.in_progress.load(Ordering::Relaxed)
+            && rewrite_due(self.size(), self.rewrites.base_size.load(Ordering::Relaxed), min_size, percentage)
+    }
+
+    /// `# Persistence` section of INFO.
+    pub fn info(&self) -> String {
+        let state = &self.rewrites;
+        let last = *state.last.lock().unwrap_or_else(|e| e.into_inner());
+        format!(
+            "# Persistence\r\naof_size:{}\r\naof_base_size:{}\r\naof_rewrite_in_progress:{}\r\naof_rewrites:{}\r\naof_last_rewrite_status:{}\r\naof_last_rewrite_time_ms:{}\r\n",
+            self.size(),
+            state.base_size.load(Ordering::Relaxed),
+            state.in_progress.load(Ordering::Relaxed) as u8,
+            state.completed.load(Ordering::Relaxed),
+            match last {
+                Some((_, true)) => "ok",
+                Some((_, false)) => "err",
+                None => "none",
+            },
+            last.map_or(-1, |(at_ms, _)| at_ms as i64),
+        )
+    }
+
     pub fn log(&self, command: &str) -> io::Result<()> {
         // Send to channel (async in background, but non-blocking here usually)
         // If buffer is full, this waits.
         // We act like it's sync IO Result for API compatibility, though we can't report write errors here immediately.
         let op = AofOp::Log(command.to_string());
-        self.sender.try_send(op).map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+        self.sender.try_send(op).map_err(|e| io::Error::other(e.to_string()))
     }
     
-    // Rewrite is now fire-and-forget from the caller's perspective (queued)
-    pub fn rewrite(&self, commands: Vec<String>) -> io::Result<()> {
-        let op = AofOp::Rewrite(commands);
-        self.sender.blocking_send(op).map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+    // Rewrite streams commands to the AOF thread in chunks. At most
+    // REWRITE_QUEUE_DEPTH + 2 chunks (being built, queued, being written) are alive
+    // at once, so memory stays within `aof_rewrite_budget` regardless of dataset size.
+    pub fn rewrite<I>(&self, commands: I) -> io::Result<()>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let budget = crate::core::config::shared().aof_rewrite_budget();
+        let chunk_bytes = (budget / (REWRITE_QUEUE_DEPTH + 2)).max(1);
+
+        let (chunk_tx, chunk_rx) = std::sync::mpsc::sync_channel(REWRITE_QUEUE_DEPTH);
+        let aborted = || io::Error::other("AOF rewrite aborted by writer thread");
+
+        // Sending blocks while the AOF thread catches up; keep the runtime responsive.
+        self.rewrites.in_progress.store(true, Ordering::Relaxed);
+        tokio::task::block_in_place(|| {
+            // Past this point the AOF thread reports how the rewrite went
+            if let Err(e) = self.sender.blocking_send(AofOp::Rewrite(chunk_rx)) {
+                self.rewrites.finish(false, 0);
+                return Err(io::Error::other(e.to_string()));
+            }
+
+            let mut chunk = Vec::new();
+            let mut chunk_size = 0;
+            for cmd in commands {
+                if cmd.len() > chunk_bytes {
+                    crate::core::logger::warn(&format!("AOF Rewrite: single command of {} bytes exceeds the {} byte chunk budget", cmd.len(), chunk_bytes));
+                }
+                chunk_size += cmd.len();
+                chunk.push(cmd);
+                if chunk_size >= chunk_bytes {
+                    chunk_tx.send(RewriteChunk::Commands(std::mem::take(&mut chunk))).map_err(|_| aborted())?;
+                    chunk_size = 0;
+                }
+            }
+            if !chunk.is_empty() {
+                chunk_tx.send(RewriteChunk::Commands(chunk)).map_err(|_| aborted())?;
+            }
+            chunk_tx.send(RewriteChunk::Complete).map_err(|_| aborted())
+        })
     }
 
-    pub fn load(&self) -> io::Result<Vec<String>> {
-        let file = File::open(&self.path)?;
-        let reader = BufReader::new(file);
-        
+    /// The position of the file once everything logged so far is written.
+    pub fn mark(&self) -> io::Result<AofPosition> {
+        let (reply, position) = std::sync::mpsc::channel();
+        tokio::task::block_in_place(|| {
+            self.sender.blocking_send(AofOp::Mark(reply)).map_err(|e| io::Error::other(e.to_string()))?;
+            position.recv().map_err(|_| io::Error::other("AOF thread stopped"))?
+        })
+    }
+
+    /// The commands to replay, from byte `from` of the file on.
+    pub fn load(&self, from: u64) -> io::Result<Vec<String>> {
+        let loaded = Self::load_from(&self.path, from, crate::core::config::shared().aof_skip_corrupt())?;
+        for issue in &loaded.issues {
+            let msg = format!("[CRASH RECOVERY] {}", issue.message);
+            if issue.fatal {
+                crate::core::logger::error(&msg);
+            } else {
+                crate::core::logger::warn(&msg);
+            }
+        }
+        Ok(loaded.commands)
+    }
+
+    /// Parses an AOF file without attaching to it. Problems are collected instead
+    /// of logged so callers such as `BACKUP VERIFY` can report them.
+    ///
+    /// Reading starts at byte `from`, the end of a record (see [`AofPosition`]).
+    /// A corrupt record stops the load unless `skip_corrupt` is set; then it is
+    /// left out, along with the transaction holding it.
+    pub fn load_from(path: &str, from: u64, skip_corrupt: bool) -> io::Result<AofLoad> {
         let mut commands = Vec::new();
+        let mut issues = Vec::new();
         let mut tx_buffer: Option<Vec<String>> = None;
         let mut in_transaction = false;
+        let mut tx_damaged = false;
 
-        for (i, line_res) in reader.lines().enumerate() {
-            let line = line_res?;
-            if line.trim().is_empty() { continue; }
-
-            let command_str = if line.starts_with("CRC32:") {
-                let parts: Vec<&str> = line.splitn(3, ':').collect();
-                if parts.len() == 3 {
-                    let stored_crc = u32::from_str_radix(parts[1], 16).unwrap_or(0);
-                    let cmd = parts[2];
-
-                    let mut hasher = crc32fast::Hasher::new();
-                    hasher.update(cmd.as_bytes());
-                    let computed_crc = hasher.finalize();
-
-                    if stored_crc != computed_crc {
-                        crate::core::logger::error(&format!("[CRASH RECOVERY] CRC mismatch at line {}. Corrupt data detected. Stopping load.", i + 1));
-                        break; 
-                    }
-                    cmd.to_string()
-                } else {
-                     crate::core::logger::warn(&format!("[CRASH RECOVERY] Malformed AOF line at {}. Skipping.", i + 1));
-                     continue;
+        for entry in records_from(path, from)? {
+            let (i, command_str) = match entry? {
+                AofEntry::Record(record) => (record.line - 1, record.command),
+                AofEntry::Corrupt(line) if skip_corrupt => {
+                    issues.push(AofIssue::warning(line, "CRC mismatch. Skipping corrupt record."));
+                    tx_damaged |= in_transaction;
+                    continue;
+                }
+                AofEntry::Corrupt(line) => {
+                    issues.push(AofIssue::fatal(line, "CRC mismatch. Corrupt data detected. Stopping load."));
+                    break;
+                }
+                AofEntry::Malformed(line) => {
+                    issues.push(AofIssue::warning(line, "Malformed AOF line. Skipping."));
+                    continue;
                 }
-            } else {
-                line
             };
 
             // Transaction Machine
             if command_str == "BEGIN" {
                 if in_transaction {
-                    crate::core::logger::warn(&format!("[CRASH RECOVERY] Found BEGIN inside active transaction at line {}. Discarding previous partial transaction.", i + 1));
+                    issues.push(AofIssue::warning(i + 1, "Found BEGIN inside active transaction. Discarding previous partial transaction."));
                 }
                 in_transaction = true;
+                tx_damaged = false;
                 tx_buffer = Some(Vec::new());
             } else if command_str == "COMMIT" {
                 if in_transaction {
-                    if let Some(buf) = tx_buffer.take() {
+                    if tx_damaged {
+                        issues.push(AofIssue::warning(i + 1, "Dropping transaction holding a corrupt record."));
+                    } else if let Some(buf) = tx_buffer.take() {
                         commands.extend(buf);
                     }
+                    tx_buffer = None;
                     in_transaction = false;
                 } else {
-                    crate::core::logger::warn(&format!("[CRASH RECOVERY] Found COMMIT without active transaction at line {}. Ignoring.", i + 1));
+                    issues.push(AofIssue::warning(i + 1, "Found COMMIT without active transaction. Ignoring."));
                 }
             } else {
                 if in_transaction {
@@ -196,9 +412,304 @@ impl AofLogger {
         }
         
         if in_transaction {
-            crate::core::logger::warn("[CRASH RECOVERY] End of file reached with active transaction. Dropping incomplete transaction.");
+            issues.push(AofIssue { fatal: false, message: "End of file reached with active transaction. Dropping incomplete transaction.".to_string() });
+        }
+
+        Ok(AofLoad { commands, issues })
+    }
+}
+
+/// Result of parsing an AOF file.
+pub struct AofLoad {
+    /// Commands to replay, with incomplete transactions already dropped
+    pub commands: Vec<String>,
+    pub issues: Vec<AofIssue>,
+}
+
+/// A recovery problem found while parsing an AOF file.
+pub struct AofIssue {
+    /// Fatal issues stop the load; everything after them is lost
+    pub fatal: bool,
+    pub message: String,
+}
+
+impl AofIssue {
+    fn fatal(line: usize, message: &str) -> Self {
+        Self { fatal: true, message: format!("{} (line {})", message, line) }
+    }
+
+    fn warning(line: usize, message: &str) -> Self {
+        Self { fatal: false, message: format!("{} (line {})", message, line) }
+    }
+}
+
+/// One logged command.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AofRecord {
+    /// 1-based line in the file
+    pub line: usize,
+    /// When the command was logged (Unix ms); `None` for records written
+    /// before timestamps were added
+    pub logged_at_ms: Option<u64>,
+    pub command: String,
+}
+
+/// A line of an AOF file.
+#[derive(Debug, PartialEq)]
+pub enum AofEntry {
+    Record(AofRecord),
+    /// Line whose checksum does not match; what follows cannot be trusted
+    Corrupt(usize),
+    Malformed(usize),
+}
+
+/// A record line: `CRC32:<crc>:@<unix_ms>:<command>`, the checksum covering
+/// everything after the second colon.
+pub(crate) fn encode_record(command: &str, logged_at_ms: u64) -> String {
+    let payload = format!("@{}:{}", logged_at_ms, command);
+    format!("CRC32:{:x}:{}", crc32fast::hash(payload.as_bytes()), payload)
+}
+
+fn decode_record(line: usize, text: String) -> AofEntry {
+    let Some(rest) = text.strip_prefix("CRC32:") else {
+        // Plain command, as written by hand or by old versions
+        return AofEntry::Record(AofRecord { line, logged_at_ms: None, command: text });
+    };
+    let Some((crc, payload)) = rest.split_once(':') else {
+        return AofEntry::Malformed(line);
+    };
+    if u32::from_str_radix(crc, 16).ok() != Some(crc32fast::hash(payload.as_bytes())) {
+        return AofEntry::Corrupt(line);
+    }
+    let stamped = payload.strip_prefix('@')
+        .and_then(|p| p.split_once(':'))
+        .and_then(|(ms, command)| Some((ms.parse().ok()?, command)));
+    let (logged_at_ms, command) = match stamped {
+        Some((ms, command)) => (Some(ms), command),
+        None => (None, payload),
+    };
+    AofEntry::Record(AofRecord { line, logged_at_ms, command: command.to_string() })
+}
+
+/// Reads the lines of an AOF file one at a time, skipping blank ones.
+pub fn records(path: &str) -> io::Result<impl Iterator<Item = io::Result<AofEntry>>> {
+    records_from(path, 0)
+}
+
+/// Like [`records`], from byte `from` on. Lines keep their number in the file.
+fn records_from(path: &str, from: u64) -> io::Result<impl Iterator<Item = io::Result<AofEntry>>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut skipped = 0;
+    let mut left = from;
+    while left > 0 {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            break;
         }
+        let n = buf.len().min(left as usize);
+        skipped += buf[..n].iter().filter(|b| **b == b'\n').count();
+        reader.consume(n);
+        left -= n as u64;
+    }
+    Ok(reader.split(b'\n').enumerate().filter_map(move |(i, line)| match line {
+        Ok(bytes) => decode_line(skipped + i + 1, bytes).map(Ok),
+        Err(e) => Some(Err(e)),
+    }))
+}
+
+/// Where an AOF stood when a snapshot was taken: its length, and a checksum
+/// of the bytes just before, which tells a file rewritten since apart.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct AofPosition {
+    pub offset: u64,
+    pub tail_crc: u32,
+}
+
+impl AofPosition {
+    /// The position `offset` bytes into the file at `path`.
+    pub fn at(path: &str, offset: u64) -> io::Result<Self> {
+        let tail = offset.min(POSITION_TAIL);
+        let mut file = File::open(path)?;
+        file.seek(SeekFrom::Start(offset - tail))?;
+        let mut bytes = vec![0; tail as usize];
+        file.read_exact(&mut bytes)?;
+        Ok(Self { offset, tail_crc: crc32fast::hash(&bytes) })
+    }
+
+    /// Whether the file at `path` still holds what it held up to this position.
+    pub fn matches(&self, path: &str) -> bool {
+        Self::at(path, self.offset).is_ok_and(|here| here == *self)
+    }
+}
+
+/// Decodes one line as read from the file, its line break included or not;
+/// `None` for a blank line. Bytes that are not UTF-8 (a torn or overwritten
+/// write) make the line corrupt.
+pub(crate) fn decode_line(line: usize, mut bytes: Vec<u8>) -> Option<AofEntry> {
+    if bytes.last() == Some(&b'\n') {
+        bytes.pop();
+    }
+    if bytes.last() == Some(&b'\r') {
+        bytes.pop();
+    }
+    match String::from_utf8(bytes) {
+        Ok(text) if text.trim().is_empty() => None,
+        Ok(text) => Some(decode_record(line, text)),
+        Err(_) => Some(AofEntry::Corrupt(line)),
+    }
+}
+
+/// Summary of an AOF file (`AOF STATS`).
+#[derive(Debug, Default)]
+pub struct AofStats {
+    pub size_bytes: u64,
+    pub records: usize,
+    pub malformed: usize,
+    /// Line of the first checksum mismatch; reading stops there
+    pub corrupt_at: Option<usize>,
+    pub first: Option<AofRecord>,
+    pub last: Option<AofRecord>,
+    /// Records per command keyword
+    pub commands: BTreeMap<String, usize>,
+}
+
+impl AofStats {
+    /// Scans a whole AOF file.
+    pub fn scan(path: &str) -> io::Result<Self> {
+        let mut stats = Self { size_bytes: std::fs::metadata(path)?.len(), ..Self::default() };
+        for entry in records(path)? {
+            match entry? {
+                AofEntry::Record(record) => {
+                    stats.records += 1;
+                    *stats.commands.entry(command_keyword(&record.command)).or_default() += 1;
+                    if stats.first.is_none() {
+                        stats.first = Some(record.clone());
+                    }
+                    stats.last = Some(record);
+                }
+                AofEntry::Malformed(_) => stats.malformed += 1,
+                AofEntry::Corrupt(line) => {
+                    stats.corrupt_at = Some(line);
+                    break;
+                }
+            }
+        }
+        Ok(stats)
+    }
+
+    /// INFO-style report.
+    pub fn report(&self, path: &str) -> String {
+        let time = |r: &Option<AofRecord>| r.as_ref().and_then(|r| r.logged_at_ms).map_or("-".to_string(), |ms| ms.to_string());
+        let command = |r: &Option<AofRecord>| r.as_ref().map_or("-".to_string(), |r| displayed_command(&r.command));
+        let mut out = format!(
+            "# AOF\r\npath:{}\r\nsize_bytes:{}\r\nrecords:{}\r\nmalformed_lines:{}\r\ncorrupt_at_line:{}\r\nfirst_logged_ms:{}\r\nlast_logged_ms:{}\r\nfirst_record:{}\r\nlast_record:{}\r\n\r\n# Commands\r\n",
+            path, self.size_bytes, self.records, self.malformed,
+            self.corrupt_at.map_or("-".to_string(), |l| l.to_string()),
+            time(&self.first), time(&self.last), command(&self.first), command(&self.last)
+        );
+        let mut commands: Vec<(&String, &usize)> = self.commands.iter().collect();
+        commands.sort_by_key(|(_, count)| std::cmp::Reverse(**count));
+        for (name, count) in commands {
+            out.push_str(&format!("{}:{}\r\n", name, count));
+        }
+        out
+    }
+}
+
+/// `count` records from line `from` on, for `AOF CAT`. Stops early at a
+/// corrupt line.
+pub fn page(path: &str, from: usize, count: usize) -> io::Result<Vec<AofRecord>> {
+    let mut page = Vec::new();
+    for entry in records(path)? {
+        match entry? {
+            AofEntry::Record(record) => {
+                if record.line >= from {
+                    page.push(AofRecord { command: displayed_command(&record.command), ..record });
+                    if page.len() >= count {
+                        break;
+                    }
+                }
+            }
+            AofEntry::Malformed(_) => {}
+            AofEntry::Corrupt(_) => break,
+        }
+    }
+    Ok(page)
+}
+
+/// Command keyword of a record (`SET`, `CREATE TABLE`), or its first word if
+/// it does not parse.
+fn command_keyword(command: &str) -> String {
+    match crate::net::parser::parse_command(command) {
+        Ok((_, cmd)) => cmd.name().to_string(),
+        Err(_) => command.split_whitespace().next().unwrap_or_default().to_ascii_uppercase(),
+    }
+}
+
+// Password hashes stay out of inspection output
+fn displayed_command(command: &str) -> String {
+    let words: Vec<&str> = command.splitn(4, ' ').collect();
+    if words.len() > 2 && words[0].eq_ignore_ascii_case("ACL") && words[1].eq_ignore_ascii_case("SETUSER") {
+        return format!("ACL SETUSER {} (credentials hidden)", words[2]);
+    }
+    command.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_round_trip_with_timestamps() {
+        let line = encode_record("SET a 1", 1_700_000_000_000);
+        assert_eq!(
+            decode_record(3, line.clone()),
+            AofEntry::Record(AofRecord { line: 3, logged_at_ms: Some(1_700_000_000_000), command: "SET a 1".into() })
+        );
+        // Records from before timestamps, and plain commands
+        let old = format!("CRC32:{:x}:SET a 1", crc32fast::hash(b"SET a 1"));
+        assert_eq!(decode_record(1, old), AofEntry::Record(AofRecord { line: 1, logged_at_ms: None, command: "SET a 1".into() }));
+        assert!(matches!(decode_record(1, "INCR a".into()), AofEntry::Record(AofRecord { logged_at_ms: None, .. })));
+        assert_eq!(decode_record(2, line.replace("SET a 1", "SET a 2")), AofEntry::Corrupt(2));
+        assert_eq!(decode_record(4, "CRC32:nonsense".into()), AofEntry::Malformed(4));
+
+        assert_eq!(command_keyword("CREATE TABLE t id:int:pk"), "CREATE TABLE");
+        assert_eq!(displayed_command("ACL SETUSER bob \"$2b$hash\" +@all"), "ACL SETUSER bob (credentials hidden)");
+    }
+
+    #[test]
+    fn loads_from_a_snapshot_position() {
+        let path = std::env::temp_dir().join(format!("toridb_position_{}.db", std::process::id())).to_string_lossy().into_owned();
+        let lines: Vec<String> = ["SET a 1", "SET b 2", "SET c 3"].iter().map(|c| encode_record(c, 1) + "\n").collect();
+        let offset = (lines[0].len() + lines[1].len()) as u64;
+        std::fs::write(&path, format!("{}{}{}CRC32:0:@1:SET d 4\n", lines[0], lines[1], lines[2])).unwrap();
+
+        let position = AofPosition::at(&path, offset).unwrap();
+        assert!(position.matches(&path));
+        let tail = AofLogger::load_from(&path, offset, false).unwrap();
+        assert_eq!(tail.commands, vec!["SET c 3"]);
+        // Lines keep their number in the file
+        assert!(tail.issues[0].message.ends_with("(line 4)"));
+
+        // A rewritten file, or a shorter one, no longer matches
+        std::fs::write(&path, format!("{}{}", lines[1], lines[0])).unwrap();
+        assert!(!position.matches(&path));
+        std::fs::write(&path, &lines[0]).unwrap();
+        assert!(!position.matches(&path));
+        std::fs::remove_file(&path).unwrap();
+    }
 
-        Ok(commands)
+    #[test]
+    fn automatic_rewrite_thresholds() {
+        const MB: u64 = 1 << 20;
+        // Doubled since the last rewrite, and past the minimum size
+        assert!(rewrite_due(128 * MB, 64 * MB, 64 * MB, 100));
+        assert!(!rewrite_due(127 * MB, 64 * MB, 64 * MB, 100));
+        assert!(!rewrite_due(60 * MB, 10 * MB, 64 * MB, 100));
+        assert!(rewrite_due(65 * MB, 0, 64 * MB, 100));
+        assert!(rewrite_due(15 * MB, 10 * MB, MB, 50));
+        // 0% is off, and a rewrite that left a larger file waits for growth from there
+        assert!(!rewrite_due(u64::MAX, 0, 0, 0));
+        assert!(!rewrite_due(64 * MB, 200 * MB, 64 * MB, 100));
     }
 }
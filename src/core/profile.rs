@@ -0,0 +1,89 @@
+//! # Query Profiling
+//!
+//! `EXPLAIN ANALYZE` runs a SELECT with profiling switched on for the worker
+//! thread. The structured store reports each stage it executes (access path,
+//! joins, filter, grouping, sort, limit) with the rows that went in and came
+//! out and the time it took; while no profile is being collected,
+//! [`stage`] is a no-op, so regular queries pay only a thread-local check.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// One executed step of a query.
+#[derive(Debug, Clone)]
+pub struct Stage {
+    pub name: String,
+    /// Rows the stage examined (table rows, index candidates, input rows)
+    pub rows_in: usize,
+    pub rows_out: usize,
+    /// Extra counters, such as index probes
+    pub detail: Option<String>,
+    pub elapsed: Duration,
+}
+
+/// Stages of a query in execution order.
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    pub stages: Vec<Stage>,
+    pub total: Duration,
+    pub rows: usize,
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<Vec<Stage>>> = const { RefCell::new(None) };
+}
+
+/// Runs `f` while collecting its stages.
+pub fn run<T>(f: impl FnOnce() -> T) -> (T, Profile) {
+    let saved = CURRENT.with(|c| c.borrow_mut().replace(Vec::new()));
+    let started = Instant::now();
+    let out = f();
+    let total = started.elapsed();
+    let stages = CURRENT.with(|c| std::mem::replace(&mut *c.borrow_mut(), saved)).unwrap_or_default();
+    (out, Profile { stages, total, rows: 0 })
+}
+
+/// Records a stage that started at `started`, if a profile is being collected.
+/// The name and detail are only built then.
+pub fn stage(started: Instant, name: impl FnOnce() -> String, rows_in: usize, rows_out: usize, detail: impl FnOnce() -> Option<String>) {
+    CURRENT.with(|c| {
+        if let Some(stages) = c.borrow_mut().as_mut() {
+            stages.push(Stage { name: name(), rows_in, rows_out, detail: detail(), elapsed: started.elapsed() });
+        }
+    });
+}
+
+fn millis(d: Duration) -> String {
+    format!("{:.3} ms", d.as_secs_f64() * 1000.0)
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Actual ({} rows in {}):", self.rows, millis(self.total))?;
+        for stage in &self.stages {
+            write!(f, "  -> {}: {} rows in, {} rows out", stage.name, stage.rows_in, stage.rows_out)?;
+            if let Some(detail) = &stage.detail {
+                write!(f, ", {}", detail)?;
+            }
+            writeln!(f, " ({})", millis(stage.elapsed))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stages_are_only_kept_while_profiling() {
+        stage(Instant::now(), || "ignored".to_string(), 1, 1, || None);
+        let ((), profile) = run(|| {
+            stage(Instant::now(), || "Access t".to_string(), 10, 2, || Some("index candidates: 2".to_string()));
+        });
+        assert_eq!(profile.stages.len(), 1);
+        let text = profile.to_string();
+        assert!(text.contains("-> Access t: 10 rows in, 2 rows out, index candidates: 2 ("), "{}", text);
+    }
+}
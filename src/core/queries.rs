@@ -0,0 +1,176 @@
+//! # Active Queries
+//!
+//! Every command a client sends is registered here for as long as it runs,
+//! under an id that `ACTIVE QUERIES` lists and `KILL QUERY <id>` targets.
+//! The databases of a server share one registry, so a query can be killed
+//! from a session on any of them.
+//!
+//! Killing only raises the query's cancellation flag. Commands run
+//! synchronously on a worker thread, so the flag of the running query is kept
+//! in a thread-local, and long loops in the executor poll it through
+//! [`cancelled`] / [`checkpoint`] and stop with a "Query cancelled" error.
+//! Writes poll it only before they modify anything, and a COMMIT runs its
+//! statements [`uninterruptible`], so a kill never leaves a write half done.
+
+use std::cell::{Cell, RefCell};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Instant;
+use dashmap::DashMap;
+
+/// Longer commands are listed truncated
+const MAX_SHOWN_CHARS: usize = 200;
+
+#[derive(Debug, Clone)]
+pub struct ActiveQuery {
+    pub id: u64,
+    pub db: String,
+    pub addr: String,
+    pub user: String,
+    pub command: String,
+    pub started: Instant,
+    cancelled: Arc<AtomicBool>,
+}
+
+#[derive(Debug, Default)]
+pub struct QueryRegistry {
+    next_id: AtomicU64,
+    active: DashMap<u64, ActiveQuery>,
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<Arc<AtomicBool>>> = const { RefCell::new(None) };
+    /// Set once a checkpoint has stopped the current query
+    static ABORTED: Cell<bool> = const { Cell::new(false) };
+}
+
+impl QueryRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a command as running on this thread until the guard drops.
+    pub fn start(self: &Arc<Self>, db: &str, addr: &str, user: &str, command: &str) -> QueryGuard {
+        let guard = self.register(db, addr, user, command);
+        CURRENT.with(|c| *c.borrow_mut() = Some(guard.cancelled.clone()));
+        ABORTED.with(|a| a.set(false));
+        guard
+    }
+
+    /// Registers a command that is not running on any thread, such as a
+    /// blocking command waiting between attempts; see [`QueryGuard::killed`].
+    pub fn register(self: &Arc<Self>, db: &str, addr: &str, user: &str, command: &str) -> QueryGuard {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let cancelled = Arc::new(AtomicBool::new(false));
+        self.active.insert(id, ActiveQuery {
+            id,
+            db: db.to_string(),
+            addr: addr.to_string(),
+            user: user.to_string(),
+            command: match command.char_indices().nth(MAX_SHOWN_CHARS) {
+                Some((end, _)) => format!("{}...", &command[..end]),
+                None => command.to_string(),
+            },
+            started: Instant::now(),
+            cancelled: cancelled.clone(),
+        });
+        QueryGuard { registry: self.clone(), id, cancelled }
+    }
+
+    /// Running queries, oldest first.
+    pub fn list(&self) -> Vec<ActiveQuery> {
+        let mut queries: Vec<ActiveQuery> = self.active.iter().map(|q| q.value().clone()).collect();
+        queries.sort_by_key(|q| q.id);
+        queries
+    }
+
+    /// Flags a running query for cancellation. False if no such query.
+    pub fn kill(&self, id: u64) -> bool {
+        match self.active.get(&id) {
+            Some(query) => {
+                query.cancelled.store(true, Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Unregisters its query when dropped.
+pub struct QueryGuard {
+    registry: Arc<QueryRegistry>,
+    id: u64,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl QueryGuard {
+    /// Whether the query was stopped by a kill (as opposed to a kill that
+    /// arrived too late to stop it).
+    pub fn aborted(&self) -> bool {
+        ABORTED.with(|a| a.get())
+    }
+
+    /// Whether the query has been killed, from any thread.
+    pub fn killed(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for QueryGuard {
+    fn drop(&mut self) {
+        CURRENT.with(|c| {
+            let mut current = c.borrow_mut();
+            if current.as_ref().is_some_and(|flag| Arc::ptr_eq(flag, &self.cancelled)) {
+                *current = None;
+            }
+        });
+        self.registry.active.remove(&self.id);
+    }
+}
+
+/// Whether the query running on this thread has been killed.
+pub fn cancelled() -> bool {
+    CURRENT.with(|c| c.borrow().as_ref().is_some_and(|flag| flag.load(Ordering::Relaxed)))
+}
+
+pub fn checkpoint() -> anyhow::Result<()> {
+    if cancelled() {
+        ABORTED.with(|a| a.set(true));
+        return Err(anyhow::anyhow!("Query cancelled"));
+    }
+    Ok(())
+}
+
+/// Runs `f` with kills of the current query ignored.
+pub fn uninterruptible<T>(f: impl FnOnce() -> T) -> T {
+    let saved = CURRENT.with(|c| c.borrow_mut().take());
+    let out = f();
+    CURRENT.with(|c| *c.borrow_mut() = saved);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kill_flags_only_the_running_query() {
+        let registry = Arc::new(QueryRegistry::new());
+        {
+            let guard = registry.start("default", "127.0.0.1:1", "default", "SELECT users");
+            let queries = registry.list();
+            assert_eq!(queries.len(), 1);
+            assert!(!cancelled());
+            assert!(registry.kill(guard.id));
+            assert!(cancelled());
+            assert!(uninterruptible(|| checkpoint().is_ok()));
+            assert!(!guard.aborted());
+            assert!(checkpoint().is_err());
+            assert!(guard.aborted());
+        }
+        // Finished queries are gone and no longer cancel this thread
+        assert!(registry.list().is_empty());
+        assert!(!cancelled());
+        assert!(!registry.kill(1));
+    }
+}
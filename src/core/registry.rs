@@ -1,58 +1,140 @@
 use dashmap::DashMap;
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock, Weak};
 use crate::core::memory::DatabaseEngine;
 use crate::core::persistence::AofLogger;
+use crate::core::security::SecurityStore;
+use crate::core::auth::OidcProvider;
+use crate::core::config::{self, Config};
+use crate::core::backend::{self, KvBackend};
+use crate::core::executor::{execute_command, Session};
+use crate::core::replication::ReplicationManager;
+use crate::core::queries::QueryRegistry;
+use crate::query::Command;
+
+/// How often each database checks whether its AOF is due for a rewrite.
+const AUTO_REWRITE_CHECK: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Builds the keyspace backend of a database, given its name.
+pub type BackendFactory = dyn Fn(&str) -> anyhow::Result<Arc<dyn KvBackend>> + Send + Sync;
 
 pub struct DatabaseRegistry {
     engines: DashMap<String, Arc<DatabaseEngine>>,
     aofs: DashMap<String, Arc<AofLogger>>,
-    pub max_connections: usize,
+    /// Server settings, shared with every database
+    pub config: Arc<Config>,
+    /// Users of every database, persisted in `{data_dir}/users.json`
+    pub security: Arc<SecurityStore>,
+    /// Replication role and links, for every database at once
+    pub replication: Arc<ReplicationManager>,
+    /// Running commands of every database, so `KILL QUERY` reaches them all
+    pub queries: Arc<QueryRegistry>,
+    /// Overrides the configured backends (embedders)
+    backend_factory: Option<Box<BackendFactory>>,
+    /// This registry, once shared, handed to the engines it creates
+    this: OnceLock<Weak<DatabaseRegistry>>,
+}
+
+impl Default for DatabaseRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl DatabaseRegistry {
-    pub fn new(max_connections: usize) -> Self {
+    pub fn new() -> Self {
+        let config = config::shared();
+        let security = SecurityStore::persistent(format!("{}/users.json", config.data_dir()));
+        if let Some(oidc) = config.settings().oidc {
+            crate::core::logger::info(&format!("External logins checked by {}", oidc.introspection_url));
+            security.set_provider(Arc::new(OidcProvider::new(oidc)));
+        }
         Self {
             engines: DashMap::new(),
             aofs: DashMap::new(),
-            max_connections,
+            security: Arc::new(security),
+            replication: Arc::new(ReplicationManager::new()),
+            queries: Arc::new(QueryRegistry::new()),
+            config,
+            backend_factory: None,
+            this: OnceLock::new(),
         }
     }
 
+    /// Creates every database's keyspace with `factory` instead of the
+    /// configured backend, so an embedding application can bring its own
+    /// storage.
+    pub fn with_backend_factory(
+        mut self,
+        factory: impl Fn(&str) -> anyhow::Result<Arc<dyn KvBackend>> + Send + Sync + 'static,
+    ) -> Self {
+        self.backend_factory = Some(Box::new(factory));
+        self
+    }
+
+    /// Lets the databases opened from now on reach this registry (replication
+    /// of every database, SHOW DATABASES, DROP DATABASE).
+    pub fn attach(self: &Arc<Self>) {
+        let _ = self.this.set(Arc::downgrade(self));
+    }
+
     pub fn get_or_create(&self, db_name: &str) -> anyhow::Result<(Arc<DatabaseEngine>, Arc<AofLogger>, bool)> {
         if let (Some(engine), Some(aof)) = (self.engines.get(db_name), self.aofs.get(db_name)) {
             return Ok((engine.clone(), aof.clone(), false));
         }
 
         // Create new
-        let mut engine_raw = DatabaseEngine::new(db_name.to_string());
-        engine_raw.max_connections = self.max_connections;
+        let backend = match &self.backend_factory {
+            Some(factory) => factory(db_name)?,
+            None => backend::open(self.config.backend_for(db_name), db_name)?,
+        };
+        let mut engine_raw = DatabaseEngine::with_backend(db_name.to_string(), backend);
+        engine_raw.config = self.config.clone();
+        engine_raw.security = self.security.clone();
+        engine_raw.replication = self.replication.clone();
+        engine_raw.queries = self.queries.clone();
+        engine_raw.registry = self.this.get().cloned().unwrap_or_default();
 
-        // Recovery: Check for Snapshot if AOF doesn't exist (assuming AOF is preferred source of truth)
-        let data_dir = std::env::var("DB_DATA_DIR").unwrap_or_else(|_| "data".to_string());
+        // Recovery: the snapshot, then the AOF from the position the snapshot
+        // recorded. An AOF rewritten since, or a snapshot without a position,
+        // leaves the AOF alone as the source of truth; a snapshot with no AOF
+        // is loaded on its own (a restore)
+        let data_dir = self.config.data_dir();
         let aof_path = format!("{}/{}.db", data_dir, db_name);
-        
-        // Only load snapshot if AOF does not exist (start fresh or restore) 
-        // OR if we implement AOF-on-top-of-Snapshot logic later.
-        if !std::path::Path::new(&aof_path).exists() {
-             let dump_path = format!("{}/{}_dump.json", data_dir, db_name);
-             if std::path::Path::new(&dump_path).exists() {
-                 crate::core::logger::info(&format!("Loading Snapshot for {}...", db_name));
-                 match crate::core::snapshot::SnapshotManager::load(&dump_path) {
-                     Ok(snap) => {
-                         engine_raw.load_from_snapshot(snap);
-                         crate::core::logger::info("Snapshot loaded successfully.");
-                     },
-                     Err(e) => {
-                         crate::core::logger::error(&format!("Failed to load snapshot: {}", e));
-                     }
-                 }
-             }
+        let dump_path = format!("{}/{}_dump.json", data_dir, db_name);
+        let has_aof = std::path::Path::new(&aof_path).exists();
+        let mut replay_from = 0;
+        let mut restored = false;
+        if std::path::Path::new(&dump_path).exists() {
+            match crate::core::snapshot::SnapshotManager::load(&dump_path) {
+                Ok(snap) => match snap.aof {
+                    _ if !has_aof => {
+                        crate::core::logger::info(&format!("Loading snapshot for {}", db_name));
+                        engine_raw.load_from_snapshot(snap);
+                        restored = true;
+                    }
+                    Some(position) if position.matches(&aof_path) => {
+                        crate::core::logger::info(&format!("Loading snapshot for {}, then the AOF from byte {}", db_name, position.offset));
+                        engine_raw.load_from_snapshot(snap);
+                        replay_from = position.offset;
+                    }
+                    _ => crate::core::logger::info(&format!("Snapshot of {} does not match its AOF; replaying the whole AOF", db_name)),
+                },
+                Err(e) => crate::core::logger::error(&format!("Failed to load snapshot: {}", e)),
+            }
         }
 
         let engine = Arc::new(engine_raw);
         let aof = Arc::new(AofLogger::new(db_name)?);
 
-        crate::core::logger::info(&format!("Creating new database: {}", db_name));
+        crate::core::logger::info(&format!("Creating new database: {} ({} backend)", db_name, engine.flexible.backend().name()));
+        replay(&engine, &aof, replay_from);
+        // The new AOF starts with the restored state, so it is complete on its own
+        if restored && let Err(e) = aof.rewrite(engine.dump_commands()) {
+            crate::core::logger::error(&format!("AOF rewrite after restoring {} failed: {}", db_name, e));
+        }
+        spawn_auto_rewrite(&engine, &aof);
+        // Writes that came due while the database was down go out now
+        crate::core::schedule::spawn_timer(&engine, &aof);
 
         self.engines.insert(db_name.to_string(), engine.clone());
         self.aofs.insert(db_name.to_string(), aof.clone());
@@ -65,4 +147,242 @@ impl DatabaseRegistry {
         let aof = self.aofs.get(db_name)?.clone();
         Some((engine, aof))
     }
+
+    /// Names of the open databases and of those with an AOF or a snapshot
+    /// in the data directory, sorted.
+    pub fn names(&self) -> Vec<String> {
+        let on_disk = std::fs::read_dir(self.config.data_dir()).into_iter().flatten().flatten()
+            .filter_map(|entry| {
+                let file = entry.file_name().into_string().ok()?;
+                file.strip_suffix(".db").or_else(|| file.strip_suffix("_dump.json")).map(String::from)
+            });
+        let mut names: Vec<String> = self.engines.iter().map(|kv| kv.key().clone()).chain(on_disk).collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// Opens every database [`names`](Self::names) lists, and returns them
+    /// sorted by name.
+    pub fn open_all(&self) -> Vec<(String, Arc<DatabaseEngine>, Arc<AofLogger>)> {
+        for name in self.names() {
+            if let Err(e) = self.get_or_create(&name) {
+                crate::core::logger::error(&format!("Could not open database {}: {}", name, e));
+            }
+        }
+        self.databases()
+    }
+
+    /// Closes database `name` and deletes its files: AOF, snapshot, disk
+    /// keyspace and cold tier. Returns false if there was no such database.
+    pub fn drop_database(&self, name: &str) -> anyhow::Result<bool> {
+        if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+            anyhow::bail!("Invalid database name '{}'", name);
+        }
+        if !self.names().iter().any(|n| n == name) {
+            return Ok(false);
+        }
+        // Sessions still in it open it again, empty, on their next command;
+        // writes already running finish and are logged before the files go
+        let engine = self.engines.remove(name).map(|(_, engine)| engine);
+        self.aofs.remove(name);
+        let _drained = engine.as_ref().map(|engine| (engine.log_gate.write().unwrap(), engine.locks.lock_all()));
+        let dir = self.config.data_dir();
+        for file in [format!("{}/{}.db", dir, name), format!("{}/{}_dump.json", dir, name)] {
+            match std::fs::remove_file(&file) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        for subdir in [format!("{}/{}_kv", dir, name), format!("{}/{}_cold", dir, name)] {
+            match std::fs::remove_dir_all(&subdir) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        crate::core::logger::info(&format!("Dropped database: {}", name));
+        Ok(true)
+    }
+
+    /// Every open database, sorted by name.
+    pub fn databases(&self) -> Vec<(String, Arc<DatabaseEngine>, Arc<AofLogger>)> {
+        let mut names: Vec<String> = self.engines.iter().map(|kv| kv.key().clone()).collect();
+        names.sort();
+        names.into_iter()
+            .filter_map(|name| self.get(&name).map(|(engine, aof)| (name, engine, aof)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+impl DatabaseRegistry {
+    /// Opens database `name` in memory, with an AOF that writes nothing.
+    pub(crate) fn open_detached(self: &Arc<Self>, name: &str) -> Arc<DatabaseEngine> {
+        let mut engine = DatabaseEngine::ephemeral(name.to_string());
+        engine.registry = Arc::downgrade(self);
+        engine.queries = self.queries.clone();
+        let engine = Arc::new(engine);
+        self.engines.insert(name.to_string(), engine.clone());
+        self.aofs.insert(name.to_string(), Arc::new(AofLogger::detached(&format!("{}.db", name))));
+        engine
+    }
+}
+
+/// Rebuilds a database just opened from its AOF, from byte `from` on,
+/// without logging the commands again.
+fn replay(engine: &Arc<DatabaseEngine>, aof: &AofLogger, from: u64) {
+    let Ok(cmds) = aof.load(from) else { return };
+    if cmds.is_empty() {
+        return;
+    }
+    crate::core::logger::info(&format!("Replaying {} AOF commands for {}", cmds.len(), engine.db_name));
+    let mut replay_session = Session::system("SYSTEM_RECOVERY", &engine.db_name);
+    for cmd_str in cmds {
+        if let Ok((_, cmd)) = crate::net::parser::parse_command(&cmd_str) {
+            // Users come from the users file once it exists
+            if engine.security.restored() && matches!(cmd, Command::AclSetUser { .. } | Command::AclDelUser { .. }) {
+                continue;
+            }
+            execute_command(engine, cmd, aof, &mut replay_session);
+        }
+    }
+    crate::core::logger::info("AOF Replay complete.");
+}
+
+/// Rewrites the AOF of a database once it outgrows the `auto_aof_rewrite_*`
+/// thresholds; stops when the database is closed or dropped.
+fn spawn_auto_rewrite(engine: &Arc<DatabaseEngine>, aof: &Arc<AofLogger>) {
+    let (engine, aof) = (Arc::downgrade(engine), Arc::downgrade(aof));
+    std::thread::spawn(move || loop {
+        std::thread::sleep(AUTO_REWRITE_CHECK);
+        let (Some(engine), Some(aof)) = (engine.upgrade(), aof.upgrade()) else { break };
+        let (min_size, percentage) = engine.config.auto_aof_rewrite();
+        if !aof.rewrite_due(min_size, percentage) {
+            continue;
+        }
+        crate::core::logger::info(&format!("Starting automatic AOF rewrite for {} ({} bytes)", engine.db_name, aof.size()));
+        execute_command(&engine, Command::RewriteAof, &aof, &mut Session::system("AUTO_REWRITE", &engine.db_name));
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::config::Settings;
+    use crate::core::executor::CommandOutput;
+    use crate::core::output::OutputFormat;
+    use std::path::Path;
+
+    /// A registry keeping its databases in a directory of its own.
+    fn registry(name: &str) -> (Arc<DatabaseRegistry>, String) {
+        let dir = std::env::temp_dir().join(format!("toridb_registry_{}_{}", name, std::process::id())).to_string_lossy().into_owned();
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut registry = DatabaseRegistry::new();
+        registry.config = Arc::new(Config::new(Settings { data_dir: dir.clone(), ..Settings::default() }));
+        let registry = Arc::new(registry);
+        registry.attach();
+        (registry, dir)
+    }
+
+    fn run(engine: &Arc<DatabaseEngine>, line: &str) -> CommandOutput {
+        let (_, cmd) = crate::net::parser::parse_command(line).unwrap();
+        let mut session = Session::system("test", &engine.db_name);
+        execute_command(engine, cmd, &AofLogger::detached("test.db"), &mut session).0
+    }
+
+    #[test]
+    fn dropping_a_database_deletes_its_files() {
+        let (registry, dir) = registry("drop");
+        for file in ["shop.db", "shop_dump.json", "shop_kv/0001", "shop_cold/0001", "other.db"] {
+            let path = Path::new(&dir).join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+        }
+        assert_eq!(registry.names(), ["other", "shop"]);
+
+        assert!(registry.drop_database("shop").unwrap());
+        for file in ["shop.db", "shop_dump.json", "shop_kv", "shop_cold"] {
+            assert!(!Path::new(&dir).join(file).exists(), "{} left behind", file);
+        }
+        assert!(Path::new(&dir).join("other.db").exists());
+        assert_eq!(registry.names(), ["other"]);
+        assert!(!registry.drop_database("shop").unwrap());
+
+        for name in ["", ".hidden", "../other", "a/b", "a\\b"] {
+            assert!(registry.drop_database(name).is_err(), "{:?} accepted", name);
+        }
+        assert!(Path::new(&dir).join("other.db").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn dropping_a_database_waits_for_its_running_writes() {
+        let (registry, dir) = registry("drain");
+        let engine = registry.open_detached("busy");
+        let writing = engine.log_gate.read().unwrap();
+        std::thread::scope(|s| {
+            let dropping = s.spawn(|| registry.drop_database("busy"));
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            assert!(!dropping.is_finished());
+            // Closed already: no session opens the old engine from here on
+            assert!(registry.get("busy").is_none());
+            drop(writing);
+            assert!(dropping.join().unwrap().unwrap());
+        });
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn show_and_drop_databases() {
+        let (registry, dir) = registry("commands");
+        let shop = registry.open_detached("shop");
+        let other = registry.open_detached("other");
+        run(&shop, "SET a 1");
+        run(&shop, "SET b 2");
+        run(&shop, "CREATE TABLE users id:int:pk");
+        std::fs::write(Path::new(&dir).join("archive.db"), "").unwrap();
+
+        assert!(matches!(run(&shop, "DBSIZE"), CommandOutput::Int(2)));
+        assert!(matches!(run(&other, "DBSIZE"), CommandOutput::Int(0)));
+        let CommandOutput::Rows(set) = run(&other, "SHOW DATABASES") else { panic!("no rows") };
+        let rows: Vec<String> = set.rows.iter().map(|row| row.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(" ")).collect();
+        // The one only on disk is listed without counts
+        assert_eq!(rows, ["archive NULL NULL", "other 0 0", "shop 2 1"]);
+
+        let err = run(&shop, "DROP DATABASE shop").to_text(OutputFormat::Text);
+        assert!(err.contains("Cannot drop the database in use"), "{}", err);
+        assert!(matches!(run(&other, "DROP DATABASE shop"), CommandOutput::Ok));
+        let err = run(&other, "DROP DATABASE shop").to_text(OutputFormat::Text);
+        assert!(err.contains("does not exist"), "{}", err);
+        assert!(matches!(run(&other, "DROP DATABASE archive"), CommandOutput::Ok));
+        let CommandOutput::Rows(set) = run(&other, "SHOW DATABASES") else { panic!("no rows") };
+        assert_eq!(set.rows.len(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn queries_are_killed_from_any_database() {
+        let (registry, dir) = registry("queries");
+        let shop = registry.open_detached("shop");
+        let other = registry.open_detached("other");
+        let query = shop.queries.register("shop", "127.0.0.1:1", "default", "SELECT * FROM users");
+        let CommandOutput::Bulk(list) = run(&other, "ACTIVE QUERIES") else { panic!("no list") };
+        assert!(list.contains("db=shop addr=127.0.0.1:1 user=default"), "{}", list);
+        let id = registry.queries.list()[0].id;
+        assert!(matches!(run(&other, &format!("KILL QUERY {}", id)), CommandOutput::Ok));
+        assert!(query.killed());
+        drop(query);
+        assert!(matches!(run(&other, &format!("KILL QUERY {}", id)), CommandOutput::Err(_)));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn drop_database_needs_a_server() {
+        let engine = Arc::new(DatabaseEngine::ephemeral("alone".to_string()));
+        let err = run(&engine, "DROP DATABASE shop").to_text(OutputFormat::Text);
+        assert!(err.contains("needs a server"), "{}", err);
+        let CommandOutput::Rows(set) = run(&engine, "SHOW DATABASES") else { panic!("no rows") };
+        assert_eq!(set.rows.len(), 1);
+    }
 }
@@ -1,4 +1,49 @@
-use std::sync::{Arc, RwLock};
+//! # Replication
+//!
+//! A master forwards every logged write to its replicas. A replica keeps a
+//! link to its master: it logs in, sends `PSYNC`, replaces its data with
+//! the snapshot the master sends back, then applies the writes that follow.
+//! When the link drops, the replica reconnects with exponential backoff and
+//! resyncs, until it is promoted or pointed at another master.
+//!
+//! A full sync is `+FULLRESYNC <bytes> <crc32>`, then the binary snapshot
+//! (see `core::snapshot`) as one bulk string, then `+SYNC_COMPLETE`. A
+//! snapshot whose size or checksum differs from the announced ones is
+//! dropped along with the connection, and the replica syncs again.
+//!
+//! The master numbers the writes it forwards: its replication offset. The
+//! full sync header ends with the offset the snapshot starts from, and the
+//! replica counts every write it applies on top of it. Every second, and
+//! whenever the master sends `+GETACK`, the replica answers with
+//! `REPLCONF ACK <offset>`, which is how the master knows how far each
+//! replica has got (INFO, `WAIT`).
+//!
+//! Replication covers every database of the server. The full sync sends
+//! one snapshot per database, each after `+SELECTDB <name>`, and the
+//! master sends `+SELECTDB` again before a write whenever the database
+//! changes, so the replica applies it to (and logs it in) the right one.
+//!
+//! ## Failover
+//! A replica also announces the port it serves clients on
+//! (`REPLCONF LISTENING-PORT`), and the master tells every replica the
+//! addresses of all of them with `+PEERS <own address> [<peer> ...]`. A
+//! promoted replica ([`promote`]) uses them to point its peers and the old
+//! master at itself. `FAILOVER` on a master waits for its most up-to-date
+//! replica to catch up, then sends it `+PROMOTE`. With `failover_timeout`
+//! set, replicas promote themselves once the master has been unreachable
+//! that long, one after the other in address order, so the first one to
+//! take over turns the others into its replicas. The master sends `+PING`
+//! on an idle link, so a replica notices a master that stopped answering.
+//!
+//! ## Checks
+//! `REPLCHECK` sends `+REPLCHECK <id> <db>` after the writes already forwarded;
+//! the replica answers `REPLCONF DIGEST <id> <name>=<hex> ...` with the
+//! digests of its data (see `core::digest`) once it has applied them, and
+//! the master compares them with its own from the same point.
+
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum ValidRole {
@@ -6,12 +51,190 @@ pub enum ValidRole {
     Replica { master_addr: String, master_port: u16 },
 }
 
+use crate::core::digest;
 use dashmap::DashMap;
 use tokio::sync::mpsc;
 
+/// Delay before the first reconnection; doubled after every failed attempt.
+const RECONNECT_MIN: Duration = Duration::from_millis(250);
+/// Longest delay between two reconnections.
+const RECONNECT_MAX: Duration = Duration::from_secs(30);
+/// Longest wait for the master to accept a connection.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// How often an idle link checks whether it should stop.
+const LINK_POLL: Duration = Duration::from_secs(1);
+/// How often a replica acknowledges its offset unprompted.
+const ACK_PERIOD: Duration = Duration::from_secs(1);
+/// How often a master pings an idle link.
+pub const HEARTBEAT: Duration = Duration::from_secs(1);
+/// Silence after which a replica drops its link to the master.
+const MASTER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What a master sends down a replica's link.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReplicaMessage {
+    /// A logged write
+    Command(String),
+    /// Asks the replica to acknowledge its offset right away
+    GetAck,
+    /// The address of the replica and those of its peers
+    Peers { me: String, others: Vec<String> },
+    /// Asks the replica to take over as master (FAILOVER)
+    Promote,
+    /// Asks the replica for the digests of a database (REPLCHECK)
+    Check { id: u64, db: String },
+    /// The writes that follow belong to this database
+    SelectDb(String),
+}
+
+impl ReplicaMessage {
+    /// The message as sent on the link.
+    pub fn to_frame(&self) -> String {
+        match self {
+            ReplicaMessage::Command(cmd) => format!("${}\r\n{}\r\n", cmd.len(), cmd),
+            ReplicaMessage::GetAck => "+GETACK\r\n".to_string(),
+            ReplicaMessage::Peers { me, others } => format!("+PEERS {}\r\n", std::iter::once(me).chain(others).cloned().collect::<Vec<_>>().join(" ")),
+            ReplicaMessage::Promote => "+PROMOTE\r\n".to_string(),
+            ReplicaMessage::Check { id, db } => format!("+REPLCHECK {} {}\r\n", id, db),
+            ReplicaMessage::SelectDb(db) => format!("+SELECTDB {}\r\n", db),
+        }
+    }
+}
+
+/// A `REPLCONF` line a replica sends on its link.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReplConf {
+    /// `REPLCONF ACK <offset>`
+    Ack(u64),
+    /// `REPLCONF LISTENING-PORT <port>`
+    ListeningPort(u16),
+    /// `REPLCONF DIGEST <id> <name>=<hex> ...`, the answer to `+REPLCHECK <id>`
+    Digest { id: u64, digests: digest::Digests },
+}
+
+/// A replica connected to this master.
+#[derive(Debug)]
+pub struct ReplicaLink {
+    sender: mpsc::Sender<ReplicaMessage>,
+    /// Offset the replica last acknowledged
+    acked: AtomicU64,
+    /// Unix ms of the last acknowledgement, 0 if none yet
+    acked_at_ms: AtomicU64,
+    /// `ip:port` the replica serves clients on, once announced
+    listen_addr: Mutex<Option<String>>,
+    /// Digests from the replica's last answer to a check, with its id
+    digests: Mutex<Option<(u64, digest::Digests)>>,
+    /// Database of the last write sent, None until the first
+    db: Mutex<Option<String>>,
+}
+
+/// Where a replica connects, and how it logs in.
+#[derive(Debug, Clone)]
+pub struct MasterLink {
+    pub host: String,
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+/// State of a replica's link to its master, for INFO.
+#[derive(Debug, Default)]
+pub struct LinkState {
+    up: AtomicBool,
+    syncing: AtomicBool,
+    /// Connection attempts since the link was last up
+    attempts: AtomicU64,
+    /// Times the link came back up after being lost
+    reconnects: AtomicU64,
+    /// Commands applied from the master
+    applied: AtomicU64,
+    /// Size of the last snapshot received
+    sync_bytes: AtomicU64,
+    /// Snapshots rejected for a size or checksum mismatch
+    checksum_failures: AtomicU64,
+    /// Unix ms of the last data read from the master, 0 if none
+    last_io_ms: AtomicU64,
+    /// Unix ms the link went down, 0 while up
+    down_since_ms: AtomicU64,
+    /// Master offset of the last write applied
+    offset: AtomicU64,
+    /// This replica's address and those of its peers, as the master last
+    /// announced them; kept while the link is down, for failover
+    peers: Mutex<Option<(String, Vec<String>)>>,
+}
+
+impl LinkState {
+    fn reset(&self) {
+        for counter in [&self.attempts, &self.reconnects, &self.applied, &self.sync_bytes, &self.checksum_failures, &self.last_io_ms, &self.offset] {
+            counter.store(0, Ordering::Relaxed);
+        }
+        self.up.store(false, Ordering::Relaxed);
+        self.syncing.store(false, Ordering::Relaxed);
+        self.down_since_ms.store(now_ms(), Ordering::Relaxed);
+        *self.peers.lock().unwrap() = None;
+    }
+
+    /// Position of this replica among itself and its peers, by address:
+    /// the order in which they promote themselves. None until announced.
+    fn promotion_rank(&self) -> Option<usize> {
+        let peers = self.peers.lock().unwrap();
+        let (me, others) = peers.as_ref()?;
+        Some(others.iter().filter(|other| *other < me).count())
+    }
+
+    fn went_down(&self) {
+        if self.up.swap(false, Ordering::Relaxed) {
+            self.down_since_ms.store(now_ms(), Ordering::Relaxed);
+        }
+        self.syncing.store(false, Ordering::Relaxed);
+    }
+
+    /// `master_*` lines of INFO replication.
+    fn info(&self) -> String {
+        let seconds_ago = |ms: u64| if ms == 0 { -1 } else { (now_ms().saturating_sub(ms) / 1000) as i64 };
+        let up = self.up.load(Ordering::Relaxed);
+        let mut info = format!(
+            "master_link_status:{}\r\nmaster_sync_in_progress:{}\r\nmaster_sync_last_bytes:{}\r\nmaster_sync_checksum_failures:{}\r\nmaster_last_io_seconds_ago:{}\r\nmaster_reconnect_attempts:{}\r\nmaster_reconnects_total:{}\r\nreplicated_commands_total:{}\r\nreplica_repl_offset:{}",
+            if up { "up" } else { "down" },
+            self.syncing.load(Ordering::Relaxed) as u8,
+            self.sync_bytes.load(Ordering::Relaxed),
+            self.checksum_failures.load(Ordering::Relaxed),
+            seconds_ago(self.last_io_ms.load(Ordering::Relaxed)),
+            self.attempts.load(Ordering::Relaxed),
+            self.reconnects.load(Ordering::Relaxed),
+            self.applied.load(Ordering::Relaxed),
+            self.offset.load(Ordering::Relaxed),
+        );
+        let peers = self.peers.lock().unwrap().as_ref().map_or(0, |(_, others)| others.len());
+        info.push_str(&format!("\r\nreplica_peers:{}", peers));
+        if !up {
+            info.push_str(&format!("\r\nmaster_link_down_since_seconds:{}", seconds_ago(self.down_since_ms.load(Ordering::Relaxed))));
+        }
+        info
+    }
+}
+
+fn now_ms() -> u64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
 pub struct ReplicationManager {
     pub role: Arc<RwLock<ValidRole>>,
-    pub replicas: Arc<DashMap<String, mpsc::Sender<String>>>,
+    pub replicas: Arc<DashMap<String, ReplicaLink>>,
+    /// Writes forwarded to replicas so far, while a master
+    offset: AtomicU64,
+    /// Link to the master, while a replica
+    pub link: Arc<LinkState>,
+    /// Bumped on every role change, so a link task knows when to stop
+    generation: AtomicU64,
+    /// The master followed, with its credentials, while a replica
+    master_link: Mutex<Option<MasterLink>>,
+    /// Writes are refused while a FAILOVER hands over to a replica
+    failing_over: AtomicBool,
+    /// Id of the last REPLCHECK sent
+    checks: AtomicU64,
+    /// Client writes started but not yet forwarded to the replicas
+    unforwarded: AtomicU64,
 }
 
 impl ReplicationManager {
@@ -19,43 +242,246 @@ impl ReplicationManager {
         Self {
             role: Arc::new(RwLock::new(ValidRole::Master)),
             replicas: Arc::new(DashMap::new()),
+            offset: AtomicU64::new(0),
+            link: Arc::new(LinkState::default()),
+            generation: AtomicU64::new(0),
+            master_link: Mutex::new(None),
+            failing_over: AtomicBool::new(false),
+            checks: AtomicU64::new(0),
+            unforwarded: AtomicU64::new(0),
+        }
+    }
+
+    /// Registers a replica about to be synced, returning the offset its
+    /// snapshot starts from.
+    pub fn add_replica(&self, addr: String, sender: mpsc::Sender<ReplicaMessage>) -> u64 {
+        let link = ReplicaLink { sender, acked: AtomicU64::new(0), acked_at_ms: AtomicU64::new(0), listen_addr: Mutex::new(None), digests: Mutex::new(None), db: Mutex::new(None) };
+        self.replicas.insert(addr, link);
+        self.offset.load(Ordering::SeqCst)
+    }
+
+    /// Forgets a replica whose link ended, and tells the others.
+    pub fn remove_replica(&self, addr: &str) {
+        if self.replicas.remove(addr).is_some_and(|(_, link)| link.listen_addr.lock().unwrap().is_some()) {
+            self.announce_peers();
+        }
+    }
+
+    /// Handles a `REPLCONF` line from the replica at `addr`.
+    pub fn replconf(&self, addr: &str, conf: ReplConf) {
+        match conf {
+            ReplConf::Ack(offset) => self.ack(addr, offset),
+            ReplConf::ListeningPort(port) => {
+                let Some(link) = self.replicas.get(addr) else { return };
+                let ip = addr.rsplit_once(':').map_or(addr, |(ip, _)| ip);
+                *link.listen_addr.lock().unwrap() = Some(format!("{}:{}", ip, port));
+                drop(link);
+                self.announce_peers();
+            }
+            ReplConf::Digest { id, digests } => {
+                if let Some(link) = self.replicas.get(addr) {
+                    *link.digests.lock().unwrap() = Some((id, digests));
+                }
+            }
+        }
+    }
+
+    /// Sends every replica that announced its port the addresses of all of them.
+    fn announce_peers(&self) {
+        let addrs: Vec<(String, String)> = self.replicas.iter()
+            .filter_map(|r| r.listen_addr.lock().unwrap().clone().map(|listen| (r.key().clone(), listen)))
+            .collect();
+        for (key, me) in &addrs {
+            let others = addrs.iter().filter(|(k, _)| k != key).map(|(_, listen)| listen.clone()).collect();
+            if let Some(link) = self.replicas.get(key) {
+                let _ = link.sender.try_send(ReplicaMessage::Peers { me: me.clone(), others });
+            }
         }
     }
 
-    pub fn add_replica(&self, addr: String, sender: mpsc::Sender<String>) {
-        self.replicas.insert(addr, sender);
+    /// The replica to hand over to: the one that acknowledged the highest
+    /// offset among those that announced their port. Returns its link key
+    /// and address.
+    pub fn failover_target(&self) -> Option<(String, String)> {
+        self.replicas.iter()
+            .filter_map(|r| r.listen_addr.lock().unwrap().clone().map(|listen| (r.acked.load(Ordering::SeqCst), r.key().clone(), listen)))
+            .max_by_key(|(acked, _, _)| *acked)
+            .map(|(_, key, listen)| (key, listen))
     }
-    
-    pub fn propagate(&self, command: &str) {
+
+    /// Offset the replica at `addr` last acknowledged.
+    pub fn acked_offset(&self, addr: &str) -> Option<u64> {
+        self.replicas.get(addr).filter(|r| r.acked_at_ms.load(Ordering::Relaxed) > 0).map(|r| r.acked.load(Ordering::SeqCst))
+    }
+
+    /// Tells the replica at `addr` to take over. False if it is gone.
+    pub fn send_promote(&self, addr: &str) -> bool {
+        self.replicas.get(addr).is_some_and(|r| r.sender.try_send(ReplicaMessage::Promote).is_ok())
+    }
+
+    /// The replica a REPLCHECK names, by link or client address; the only
+    /// replica when no name is given. Returns its link key.
+    pub fn find_replica(&self, name: Option<&str>) -> Option<String> {
+        match name {
+            Some(name) => self.replicas.iter()
+                .find(|r| r.key() == name || r.listen_addr.lock().unwrap().as_deref() == Some(name))
+                .map(|r| r.key().clone()),
+            None if self.replicas.len() == 1 => self.replicas.iter().next().map(|r| r.key().clone()),
+            None => None,
+        }
+    }
+
+    /// Asks the replica at `addr` for the digests of database `db`,
+    /// returning the id of the check. The request queues behind the writes
+    /// already sent, so the replica answers once it has applied them.
+    pub fn send_check(&self, addr: &str, db: &str) -> Option<u64> {
+        let id = self.checks.fetch_add(1, Ordering::SeqCst) + 1;
+        let check = ReplicaMessage::Check { id, db: db.to_string() };
+        self.replicas.get(addr).is_some_and(|r| r.sender.try_send(check).is_ok()).then_some(id)
+    }
+
+    /// The digests the replica at `addr` sent in answer to check `id`, once arrived.
+    pub fn check_result(&self, addr: &str, id: u64) -> Option<digest::Digests> {
+        let link = self.replicas.get(addr)?;
+        let digests = link.digests.lock().unwrap();
+        digests.as_ref().filter(|(got, _)| *got == id).map(|(_, digests)| digests.clone())
+    }
+
+    /// Refuses (or accepts again) writes while a FAILOVER is under way.
+    pub fn set_failing_over(&self, on: bool) {
+        self.failing_over.store(on, Ordering::SeqCst);
+    }
+
+    pub fn is_failing_over(&self) -> bool {
+        self.failing_over.load(Ordering::SeqCst)
+    }
+
+    /// Forwards a write made in database `db` to every replica, preceded by
+    /// `+SELECTDB` when the replica's last write was in another database.
+    pub fn propagate(&self, db: &str, command: &str) {
          // If we are master, broadcast
          if self.is_master() {
+             self.offset.fetch_add(1, Ordering::SeqCst);
              for r in self.replicas.iter() {
-                 let _ = r.value().try_send(command.to_string());
+                 let mut current = r.db.lock().unwrap();
+                 if current.as_deref() != Some(db) {
+                     if r.sender.try_send(ReplicaMessage::SelectDb(db.to_string())).is_err() {
+                         continue;
+                     }
+                     *current = Some(db.to_string());
+                 }
+                 let _ = r.value().sender.try_send(ReplicaMessage::Command(command.to_string()));
              }
          }
     }
 
+
+    /// Counts a client write from before it runs until [`forwarded`](Self::forwarded).
+    pub fn forwarding(&self) {
+        self.unforwarded.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn forwarded(&self) {
+        self.unforwarded.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// Client writes that may have run without reaching the replicas yet.
+    pub fn unforwarded(&self) -> u64 {
+        self.unforwarded.load(Ordering::SeqCst)
+    }
+
+    /// Current replication offset of this master.
+    pub fn offset(&self) -> u64 {
+        self.offset.load(Ordering::SeqCst)
+    }
+
+    /// Records `REPLCONF ACK <offset>` from the replica at `addr`.
+    pub fn ack(&self, addr: &str, offset: u64) {
+        if let Some(link) = self.replicas.get(addr) {
+            link.acked.fetch_max(offset, Ordering::SeqCst);
+            link.acked_at_ms.store(now_ms(), Ordering::Relaxed);
+        }
+    }
+
+    /// Asks every replica to acknowledge its offset now.
+    pub fn request_acks(&self) {
+        for r in self.replicas.iter() {
+            let _ = r.value().sender.try_send(ReplicaMessage::GetAck);
+        }
+    }
+
+    /// Replicas that acknowledged `offset` or later.
+    pub fn acked_replicas(&self, offset: u64) -> usize {
+        self.replicas.iter()
+            .filter(|r| r.acked_at_ms.load(Ordering::Relaxed) > 0 && r.acked.load(Ordering::SeqCst) >= offset)
+            .count()
+    }
+
+    /// Commands queued for each replica but not yet sent to it.
+    pub fn backlog(&self) -> Vec<(String, usize)> {
+        self.replicas.iter()
+            .map(|r| (r.key().clone(), r.sender.max_capacity() - r.sender.capacity()))
+            .collect()
+    }
+
     pub fn set_replica_of(&self, host: String, port: u16) {
         let mut w = self.role.write().unwrap();
         *w = ValidRole::Replica { master_addr: host.clone(), master_port: port };
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        self.failing_over.store(false, Ordering::SeqCst);
+        self.link.reset();
         crate::core::logger::debug(&format!("Replication: Switched to Replica of {}:{}", host, port));
-        // TODO: Spawn connection task
     }
 
     pub fn set_master(&self) {
         let mut w = self.role.write().unwrap();
         *w = ValidRole::Master;
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        self.failing_over.store(false, Ordering::SeqCst);
+        *self.master_link.lock().unwrap() = None;
         crate::core::logger::debug("Replication: Switched to Master");
     }
 
+    /// `host:port` of the master this node follows, if it is a replica.
+    pub fn master(&self) -> Option<String> {
+        match &*self.role.read().unwrap() {
+            ValidRole::Replica { master_addr, master_port } => Some(format!("{}:{}", master_addr, master_port)),
+            ValidRole::Master => None,
+        }
+    }
+
     pub fn is_master(&self) -> bool {
          matches!(*self.role.read().unwrap(), ValidRole::Master)
     }
-    
-    pub fn get_role_string(&self) -> String {
+
+    /// True while no role change happened since `generation` was read.
+    fn follows(&self, generation: u64) -> bool {
+        self.generation.load(Ordering::SeqCst) == generation
+    }
+
+    /// Body of the `# Replication` INFO section: the role, then the link
+    /// state on a replica, or each replica with the commands it has yet to
+    /// receive on a master.
+    pub fn info(&self) -> String {
         match &*self.role.read().unwrap() {
-            ValidRole::Master => "role:master".to_string(),
-            ValidRole::Replica { master_addr, master_port } => format!("role:replica\nmaster_host:{}\nmaster_port:{}", master_addr, master_port),
+            ValidRole::Master => {
+                let offset = self.offset();
+                let mut info = format!("role:master\r\nconnected_replicas:{}\r\nmaster_repl_offset:{}", self.replicas.len(), offset);
+                for (i, r) in self.replicas.iter().enumerate() {
+                    let acked = r.acked.load(Ordering::SeqCst);
+                    let acked_at = r.acked_at_ms.load(Ordering::Relaxed);
+                    let ack_age = if acked_at == 0 { -1 } else { (now_ms().saturating_sub(acked_at) / 1000) as i64 };
+                    let listen = r.listen_addr.lock().unwrap().clone().unwrap_or_else(|| "-".to_string());
+                    info.push_str(&format!(
+                        "\r\nreplica{}:addr={},listen_addr={},lag_commands={},ack_offset={},ack_lag_commands={},last_ack_seconds_ago={}",
+                        i, r.key(), listen, r.sender.max_capacity() - r.sender.capacity(), acked, offset.saturating_sub(acked), ack_age
+                    ));
+                }
+                info
+            }
+            ValidRole::Replica { master_addr, master_port } => {
+                format!("role:replica\r\nmaster_host:{}\r\nmaster_port:{}\r\n{}", master_addr, master_port, self.link.info())
+            }
         }
     }
 }
@@ -63,82 +489,548 @@ impl ReplicationManager {
 use tokio::net::TcpStream;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use crate::core::memory::DatabaseEngine;
-use crate::core::executor::{execute_command, Session};
-use crate::net::parser::parse_command;
-use crate::net::resp::decode;
+use crate::core::executor::{apply_replicated, Session};
+use crate::net::parser::{parse_command, quote_string};
+use crate::net::resp::{decode, Decoder, RespValue};
 use bytes::BytesMut;
 
 use crate::core::persistence::AofLogger;
+use crate::core::snapshot::SnapshotManager;
 
-#[allow(dead_code)]
-pub fn start_replication_task(engine: Arc<DatabaseEngine>, aof: Arc<AofLogger>, host: String, port: u16) {
+/// Follows the master of `link` until the node is promoted or pointed at
+/// another master, reconnecting whenever the connection is lost.
+pub fn start_replication_task(engine: Arc<DatabaseEngine>, aof: Arc<AofLogger>, link: MasterLink) {
+    let generation = engine.replication.generation.load(Ordering::SeqCst);
+    *engine.replication.master_link.lock().unwrap() = Some(link.clone());
     tokio::spawn(async move {
-        crate::core::logger::debug(&format!("Replication: Connecting to {}:{}...", host, port));
-        match TcpStream::connect(format!("{}:{}", host, port)).await {
-            Ok(mut stream) => {
-                crate::core::logger::debug("Replication: Connected to Master.");
-                
-                // Handshake
-                // 1. PING
-                if let Err(e) = stream.write_all(b"*1\r\n$4\r\nPING\r\n").await {
-                    crate::core::logger::error(&format!("Rep: Failed to send PING: {}", e)); return;
-                }
-                
-                // 2. PSYNC
-                // Wait for PONG? Or pipeline?
-                // The master loop processes one by one.
-                // We should assume PONG comes back.
-                
-                // Send PSYNC
-                if let Err(e) = stream.write_all(b"*1\r\n$5\r\nPSYNC\r\n").await {
-                    crate::core::logger::error(&format!("Rep: Failed to send PSYNC: {}", e)); return;
+        let state = engine.replication.link.clone();
+        let mut delay = RECONNECT_MIN;
+        let mut ever_up = false;
+        while engine.replication.follows(generation) {
+            state.attempts.fetch_add(1, Ordering::Relaxed);
+            crate::core::logger::debug(&format!("Replication: Connecting to {}:{}...", link.host, link.port));
+            let outcome = follow_master(&engine, &aof, &link, generation, &mut ever_up).await;
+            state.went_down();
+            match outcome {
+                Ok(()) => break,
+                Err(e) => crate::core::logger::warn(&format!("Replication: link to {}:{} lost: {}", link.host, link.port, e)),
+            }
+            // A link that got up starts the backoff over
+            if state.attempts.load(Ordering::Relaxed) == 0 {
+                delay = RECONNECT_MIN;
+            }
+            // Automatic failover, once the master has been gone long enough:
+            // each replica waits its turn, so the first one to take over can
+            // turn the others into its replicas
+            let mut wait = delay;
+            let timeout = crate::core::config::shared().failover_timeout();
+            if let (Some(timeout), Some(rank), true) = (timeout, state.promotion_rank(), ever_up) {
+                let deadline = timeout * (rank as u32 + 1);
+                let down_for = Duration::from_millis(now_ms().saturating_sub(state.down_since_ms.load(Ordering::Relaxed)));
+                if down_for >= deadline {
+                    if engine.replication.follows(generation) {
+                        crate::core::logger::warn(&format!("Replication: master {}:{} unreachable for {}s, promoting", link.host, link.port, down_for.as_secs()));
+                        match promote(&engine) {
+                            Ok(()) => log_promotion(&aof),
+                            Err(e) => crate::core::logger::error(&format!("Replication: promotion failed: {}", e)),
+                        }
+                    }
+                    break;
                 }
+                wait = wait.min(deadline - down_for);
+            }
+            tokio::time::sleep(wait).await;
+            delay = (delay * 2).min(RECONNECT_MAX);
+        }
+        crate::core::logger::debug("Rep: Node no longer follows this master. Dropping replication link.");
+    });
+}
 
-                let mut buffer = BytesMut::with_capacity(4096);
-                let mut session = Session {
-                    user: engine.security.get_user("default"), 
-                    _addr: format!("master-{}:{}", host, port),
-                    connected_at: std::time::Instant::now(),
-                    current_db: engine.db_name.clone(),
-                    tx_buffer: None,
-                };
-                
-                loop {
-                     let _n = match stream.read_buf(&mut buffer).await {
-                        Ok(n) if n == 0 => { crate::core::logger::debug("Rep: Master closed connection."); break; },
-                        Ok(n) => n,
-                        Err(e) => { crate::core::logger::error(&format!("Rep: Read Error: {}", e)); break; },
-                    };
-                    
-                    while let Ok(Some(resp_val)) = decode(&mut buffer) {
-                        // Check for Master protocol messages
-                        match &resp_val {
-                            crate::net::resp::RespValue::SimpleString(s) => {
-                                if s == "PONG" { continue; }
-                                if s.starts_with("FULLRESYNC") {
-                                    crate::core::logger::debug("Rep: Full Sync Started. Receiving Snapshot...");
-                                    // Engine Clear logic could go here if we had it
-                                    continue;
-                                }
-                                if s == "SYNC_COMPLETE" {
-                                    crate::core::logger::debug("Rep: Snapshot Received. Entering Propagation Mode.");
-                                    continue;
-                                }
-                            },
-                            _ => {}
-                        }
+/// One connection to the master: handshake, full sync, then propagation.
+/// Returns Ok once the node stops following this master, Err when the
+/// connection fails.
+async fn follow_master(engine: &Arc<DatabaseEngine>, aof: &Arc<AofLogger>, link: &MasterLink, generation: u64, ever_up: &mut bool) -> Result<(), String> {
+    let state = &engine.replication.link;
+    let addr = format!("{}:{}", link.host, link.port);
+    let mut stream = match tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(&addr)).await {
+        Ok(Ok(stream)) => stream,
+        Ok(Err(e)) => return Err(format!("connect failed: {}", e)),
+        Err(_) => return Err("connect timed out".to_string()),
+    };
+    crate::core::logger::debug("Replication: Connected to Master.");
+
+    // Handshake: AUTH (if credentials were given), PING, PSYNC; the replies
+    // are read from the stream below
+    let mut handshake = Vec::new();
+    if let Some(password) = &link.password {
+        let username = link.username.as_deref().unwrap_or("default");
+        handshake.push(vec!["AUTH", username, password.as_str()]);
+    }
+    handshake.push(vec!["PING"]);
+    handshake.push(vec!["PSYNC"]);
+    // Read by the master once the link is in propagation mode
+    let port = crate::core::config::shared().settings().port.to_string();
+    handshake.push(vec!["REPLCONF", "LISTENING-PORT", &port]);
+    for args in handshake {
+        stream.write_all(&command_frame(&args)).await.map_err(|e| format!("handshake failed: {}", e))?;
+    }
+
+    let mut buffer = BytesMut::with_capacity(4096);
+    let mut decoder = Decoder::default();
+    let mut session = Session::system(&format!("master-{}", addr), &engine.db_name);
+    // Database the master's writes go to, as its `+SELECTDB` lines say,
+    // and those a full sync replaced
+    let mut current = (engine.clone(), aof.clone());
+    let mut synced: Vec<(Arc<DatabaseEngine>, Arc<AofLogger>)> = Vec::new();
+    // Size, CRC32 and offset announced by FULLRESYNC, until the snapshot arrives
+    let mut announced: Option<(usize, u32, u64)> = None;
+    let mut last_ack = std::time::Instant::now();
+    let mut ack_requested = false;
 
-                        if let Some(cmd_str) = resp_val.to_command_string() {
-                            if let Ok((_, cmd)) = parse_command(&cmd_str) {
-                                // Replicas should write to AOF as well?
-                                // Yes, to persist the replicated state.
-                                execute_command(&engine, cmd, &aof, &mut session);
-                            }
+    loop {
+        let read = tokio::time::timeout(LINK_POLL, stream.read_buf(&mut buffer)).await;
+        // Promoted by a failover (or REPLICAOF NO ONE), or following another master
+        if !engine.replication.follows(generation) {
+            return Ok(());
+        }
+        match read {
+            Err(_) if state.up.load(Ordering::Relaxed)
+                && now_ms().saturating_sub(state.last_io_ms.load(Ordering::Relaxed)) >= MASTER_TIMEOUT.as_millis() as u64 => {
+                return Err(format!("no data from master for {}s", MASTER_TIMEOUT.as_secs()));
+            }
+            Err(_) => {}
+            Ok(Ok(0)) => return Err("master closed the connection".to_string()),
+            Ok(Ok(_)) => state.last_io_ms.store(now_ms(), Ordering::Relaxed),
+            Ok(Err(e)) => return Err(format!("read error: {}", e)),
+        }
+
+        while let Ok(Some(resp_val)) = decoder.decode(&mut buffer) {
+            // Check for Master protocol messages
+            let cmd_str = match resp_val {
+                RespValue::SimpleString(s) if s == "PONG" || s == "OK" || s == "PING" => continue,
+                RespValue::SimpleString(s) if s == "GETACK" => {
+                    ack_requested = true;
+                    continue;
+                }
+                RespValue::SimpleString(s) if s.starts_with("PEERS ") => {
+                    let mut addrs = s.split_whitespace().skip(1).map(String::from);
+                    if let Some(me) = addrs.next() {
+                        *state.peers.lock().unwrap() = Some((me, addrs.collect()));
+                    }
+                    continue;
+                }
+                RespValue::SimpleString(s) if s.starts_with("SELECTDB ") => {
+                    let name = &s["SELECTDB ".len()..];
+                    current = open_database(engine, aof, name)?;
+                    session.current_db = name.to_string();
+                    continue;
+                }
+                RespValue::SimpleString(s) if s.starts_with("REPLCHECK ") => {
+                    // Writes sent before the check are applied by now
+                    let mut words = s.split_whitespace().skip(1);
+                    let (Some(id), Some(name)) = (words.next(), words.next()) else { continue };
+                    let (target, _) = open_database(engine, aof, name)?;
+                    let digests = tokio::task::spawn_blocking(move || digest::format(&digest::digests(&target))).await.map_err(|e| e.to_string())?;
+                    let mut args = vec!["REPLCONF", "DIGEST", id];
+                    args.extend(digests.split(' '));
+                    stream.write_all(&command_frame(&args)).await.map_err(|e| format!("digest reply failed: {}", e))?;
+                    continue;
+                }
+                RespValue::SimpleString(s) if s == "PROMOTE" => {
+                    crate::core::logger::info(&format!("Replication: master {} handed over, promoting", addr));
+                    promote(engine)?;
+                    log_promotion(aof);
+                    return Ok(());
+                }
+                RespValue::SimpleString(s) if s.starts_with("FULLRESYNC") => {
+                    crate::core::logger::debug("Rep: Full Sync Started. Receiving Snapshot...");
+                    state.syncing.store(true, Ordering::Relaxed);
+                    announced = Some(parse_fullresync(&s).ok_or_else(|| format!("unexpected full sync header '{}'", s))?);
+                    continue;
+                }
+                RespValue::BulkString(Some(bytes)) if announced.is_some() => {
+                    let (size, crc, offset) = announced.take().unwrap();
+                    if bytes.len() != size || crc32fast::hash(&bytes) != crc {
+                        state.checksum_failures.fetch_add(1, Ordering::Relaxed);
+                        return Err(format!("snapshot of {} bytes does not match the announced {} bytes / crc {:08x}", bytes.len(), size, crc));
+                    }
+                    state.sync_bytes.store(size as u64, Ordering::Relaxed);
+                    // The snapshot replaces whatever the replica held
+                    let target = current.0.clone();
+                    tokio::task::spawn_blocking(move || {
+                        let snapshot = SnapshotManager::decode(&bytes)?;
+                        let _guard = target.locks.lock_all();
+                        target.restore_state(snapshot);
+                        Ok::<_, std::io::Error>(())
+                    }).await.map_err(|e| e.to_string())?.map_err(|e| {
+                        state.checksum_failures.fetch_add(1, Ordering::Relaxed);
+                        format!("snapshot rejected: {}", e)
+                    })?;
+                    state.offset.store(offset, Ordering::Relaxed);
+                    synced.push(current.clone());
+                    continue;
+                }
+                RespValue::SimpleString(s) if s == "SYNC_COMPLETE" => {
+                    if announced.is_some() {
+                        return Err("full sync ended before the snapshot arrived".to_string());
+                    }
+                    crate::core::logger::debug("Rep: Snapshot Received. Entering Propagation Mode.");
+                    // The AOFs restart from the synced state
+                    for (engine, aof) in synced.drain(..) {
+                        let _guard = engine.locks.lock_all();
+                        if let Err(e) = aof.rewrite(engine.dump_commands()) {
+                            crate::core::logger::error(&format!("Rep: AOF rewrite of {} after sync failed: {}", engine.db_name, e));
                         }
                     }
+                    state.syncing.store(false, Ordering::Relaxed);
+                    state.up.store(true, Ordering::Relaxed);
+                    state.attempts.store(0, Ordering::Relaxed);
+                    if std::mem::replace(ever_up, true) {
+                        state.reconnects.fetch_add(1, Ordering::Relaxed);
+                    }
+                    crate::core::logger::info(&format!("Replication: in sync with master {}", addr));
+                    continue;
                 }
-            },
-            Err(e) => crate::core::logger::error(&format!("Replication: Failed to connect: {}", e)),
+                RespValue::Error(e) => return Err(format!("master replied: {}", e)),
+                // The master forwards logged command lines as bulk strings
+                RespValue::BulkString(Some(bytes)) => String::from_utf8_lossy(&bytes).into_owned(),
+                other => match other.to_command_string() {
+                    Some(cmd_str) => cmd_str,
+                    None => continue,
+                },
+            };
+
+            if let Ok((_, cmd)) = parse_command(&cmd_str) {
+                apply_replicated(&current.0, cmd, &cmd_str, &current.1, &mut session);
+                state.applied.fetch_add(1, Ordering::Relaxed);
+            }
+            state.offset.fetch_add(1, Ordering::Relaxed);
         }
-    });
+
+        // After the writes read so far, so an ACK covers them
+        if state.up.load(Ordering::Relaxed) && (ack_requested || last_ack.elapsed() >= ACK_PERIOD) {
+            let offset = state.offset.load(Ordering::Relaxed).to_string();
+            stream.write_all(&command_frame(&["REPLCONF", "ACK", &offset])).await.map_err(|e| format!("ack failed: {}", e))?;
+            last_ack = std::time::Instant::now();
+            ack_requested = false;
+        }
+    }
+}
+
+/// Database `name` of this replica: the one REPLICAOF ran in, or another
+/// opened through the registry.
+fn open_database(engine: &Arc<DatabaseEngine>, aof: &Arc<AofLogger>, name: &str) -> Result<(Arc<DatabaseEngine>, Arc<AofLogger>), String> {
+    if name == engine.db_name {
+        return Ok((engine.clone(), aof.clone()));
+    }
+    let registry = engine.registry.upgrade().ok_or_else(|| format!("cannot open database {}", name))?;
+    registry.get_or_create(name).map(|(engine, aof, _)| (engine, aof)).map_err(|e| format!("cannot open database {}: {}", name, e))
+}
+
+/// Makes this replica the master: it takes over the cluster slots of its
+/// master, stops following it, then points its peers and the old master at
+/// itself (REPLICAOF PROMOTE, FAILOVER, automatic failover).
+pub fn promote(engine: &Arc<DatabaseEngine>) -> Result<(), String> {
+    let replication = &engine.replication;
+    let link = match replication.master_link.lock().unwrap().clone() {
+        Some(link) if !replication.is_master() => link,
+        _ => return Err("Only a replica can be promoted".to_string()),
+    };
+    let master = format!("{}:{}", link.host, link.port);
+    let peers = replication.link.peers.lock().unwrap().clone();
+    if engine.cluster.nodes.contains_key(&master) {
+        let slots = engine.cluster.failover(&master).map_err(|e| e.to_string())?;
+        crate::core::logger::info(&format!("Failover: took over {} slots from {}", slots, master));
+    }
+    replication.set_master();
+    crate::core::logger::info(&format!("Replication: promoted to master, was following {}", master));
+
+    match peers {
+        Some((me, others)) => {
+            // The old master first: it refuses writes until it follows this node
+            let targets = std::iter::once(master).chain(others).collect();
+            tokio::spawn(redirect_nodes(targets, me, link, engine.db_name.clone()));
+        }
+        None => crate::core::logger::warn("Replication: no known peers; point the other replicas at this node with REPLICAOF"),
+    }
+    Ok(())
+}
+
+/// Records a promotion in the AOF, so a restart doesn't follow the old master again.
+fn log_promotion(aof: &AofLogger) {
+    if let Err(e) = aof.log(&crate::core::worker::promotion()) {
+        crate::core::logger::error(&format!("AOF Error: {}", e));
+    }
+}
+
+/// Points each of `targets` at `me` with REPLICAOF, logging in with the
+/// credentials of the link to the old master. Nodes that can't be reached
+/// are skipped.
+async fn redirect_nodes(targets: Vec<String>, me: String, link: MasterLink, db_name: String) {
+    let (host, port) = me.rsplit_once(':').unwrap_or((me.as_str(), "8569"));
+    let mut frames = Vec::new();
+    let replicaof = match &link.password {
+        Some(password) => {
+            let username = link.username.as_deref().unwrap_or("default");
+            frames.extend(command_frame(&["AUTH", username, password]));
+            // Inline, so the URI is read as one quoted argument
+            format!("REPLICAOF {}\r\n", quote_string(&format!("db://{}:{}+{}", username, password, me))).into_bytes()
+        }
+        None => command_frame(&["REPLICAOF", host, port]),
+    };
+    frames.extend(command_frame(&["USE", &db_name]));
+    frames.extend(replicaof);
+    let replies = if link.password.is_some() { 3 } else { 2 };
+
+    for target in targets {
+        let outcome = async {
+            let mut stream = tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(&target)).await
+                .map_err(|_| "connect timed out".to_string())?
+                .map_err(|e| e.to_string())?;
+            stream.write_all(&frames).await.map_err(|e| e.to_string())?;
+            let mut buffer = BytesMut::new();
+            let mut seen = 0;
+            while seen < replies {
+                match tokio::time::timeout(CONNECT_TIMEOUT, stream.read_buf(&mut buffer)).await {
+                    Ok(Ok(n)) if n > 0 => {}
+                    _ => return Err("no reply".to_string()),
+                }
+                while let Ok(Some(reply)) = decode(&mut buffer) {
+                    if let RespValue::Error(e) = reply {
+                        return Err(e);
+                    }
+                    seen += 1;
+                }
+            }
+            Ok::<_, String>(())
+        }.await;
+        match outcome {
+            Ok(()) => crate::core::logger::info(&format!("Failover: {} now replicates from {}", target, me)),
+            Err(e) => crate::core::logger::warn(&format!("Failover: could not point {} at {}: {}", target, me, e)),
+        }
+    }
+}
+
+/// A command as a RESP array of bulk strings.
+fn command_frame(args: &[&str]) -> Vec<u8> {
+    RespValue::Array(Some(args.iter().map(|a| RespValue::BulkString(Some(a.as_bytes().to_vec()))).collect())).serialize()
+}
+
+/// `(bytes, crc32, offset)` of a `FULLRESYNC <bytes> <crc32 hex> <offset>` line.
+fn parse_fullresync(line: &str) -> Option<(usize, u32, u64)> {
+    let mut parts = line.split_whitespace().skip(1);
+    let size = parts.next()?.parse().ok()?;
+    let crc = u32::from_str_radix(parts.next()?, 16).ok()?;
+    let offset = parts.next()?.parse().ok()?;
+    Some((size, crc, offset))
+}
+
+/// A `REPLCONF ACK <offset>`, `REPLCONF LISTENING-PORT <port>` or
+/// `REPLCONF DIGEST <id> <digests>` line from a replica.
+pub fn parse_replconf(line: &str) -> Option<ReplConf> {
+    let mut parts = line.split_whitespace();
+    if !parts.next()?.eq_ignore_ascii_case("REPLCONF") {
+        return None;
+    }
+    let option = parts.next()?;
+    let value = parts.next()?;
+    if option.eq_ignore_ascii_case("ACK") {
+        value.parse().ok().map(ReplConf::Ack)
+    } else if option.eq_ignore_ascii_case("LISTENING-PORT") {
+        value.parse().ok().map(ReplConf::ListeningPort)
+    } else if option.eq_ignore_ascii_case("DIGEST") {
+        Some(ReplConf::Digest { id: value.parse().ok()?, digests: digest::parse(parts)? })
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn role_changes_end_the_link() {
+        let manager = ReplicationManager::new();
+        assert!(manager.info().starts_with("role:master\r\nconnected_replicas:0"));
+
+        manager.set_replica_of("10.0.0.1".to_string(), 8569);
+        let generation = manager.generation.load(Ordering::SeqCst);
+        assert!(manager.follows(generation));
+        let info = manager.info();
+        assert!(info.contains("master_link_status:down\r\n") && info.contains("master_link_down_since_seconds:0"), "{}", info);
+
+        manager.link.up.store(true, Ordering::Relaxed);
+        assert!(manager.info().contains("master_link_status:up\r\n"));
+        assert!(!manager.info().contains("master_link_down_since_seconds"));
+
+        manager.set_master();
+        assert!(!manager.follows(generation));
+
+        assert_eq!(parse_fullresync("FULLRESYNC 1024 00c0ffee 7"), Some((1024, 0xc0ffee, 7)));
+        assert_eq!(parse_fullresync("FULLRESYNC 12 0 0"), Some((12, 0, 0)));
+        assert_eq!(parse_fullresync("FULLRESYNC 12 0"), None);
+        assert_eq!(parse_replconf("REPLCONF ACK 42"), Some(ReplConf::Ack(42)));
+        assert_eq!(parse_replconf("replconf ack x"), None);
+        assert_eq!(parse_replconf("SET ACK 1"), None);
+        assert_eq!(parse_replconf("REPLCONF LISTENING-PORT 8570"), Some(ReplConf::ListeningPort(8570)));
+        assert_eq!(
+            parse_replconf("REPLCONF DIGEST 3 keyspace=00000000000000ff table:users=0000000000000001"),
+            Some(ReplConf::Digest { id: 3, digests: vec![("keyspace".to_string(), 255), ("table:users".to_string(), 1)] })
+        );
+        assert_eq!(parse_replconf("REPLCONF DIGEST 3 keyspace"), None);
+    }
+
+    #[test]
+    fn acks_track_each_replica() {
+        let manager = ReplicationManager::new();
+        let (tx, mut rx) = mpsc::channel(8);
+        assert_eq!(manager.add_replica("r1".to_string(), tx), 0);
+        let (tx2, _rx2) = mpsc::channel(8);
+        manager.add_replica("r2".to_string(), tx2);
+        manager.propagate("default", "SET a 1");
+        manager.propagate("default", "SET b 2");
+        assert_eq!(manager.offset(), 2);
+        assert_eq!(rx.try_recv(), Ok(ReplicaMessage::SelectDb("default".to_string())));
+        assert_eq!(rx.try_recv(), Ok(ReplicaMessage::Command("SET a 1".to_string())));
+
+        // A replica counts once it acknowledged, and only up to its offset
+        assert_eq!(manager.acked_replicas(0), 0);
+        manager.ack("r1", 2);
+        manager.ack("r2", 1);
+        assert_eq!(manager.acked_replicas(2), 1);
+        assert_eq!(manager.acked_replicas(1), 2);
+        assert!(manager.info().contains("master_repl_offset:2"));
+        assert!(manager.info().contains("ack_offset=1,ack_lag_commands=1"), "{}", manager.info());
+
+        manager.request_acks();
+        assert_eq!(rx.try_recv(), Ok(ReplicaMessage::Command("SET b 2".to_string())));
+        assert_eq!(rx.try_recv(), Ok(ReplicaMessage::GetAck));
+
+        // Writes of another database are announced as such
+        manager.propagate("shop", "SET c 3");
+        manager.propagate("default", "DEL a");
+        assert_eq!(rx.try_recv(), Ok(ReplicaMessage::SelectDb("shop".to_string())));
+        assert_eq!(rx.try_recv(), Ok(ReplicaMessage::Command("SET c 3".to_string())));
+        assert_eq!(rx.try_recv(), Ok(ReplicaMessage::SelectDb("default".to_string())));
+        assert_eq!(rx.try_recv(), Ok(ReplicaMessage::Command("DEL a".to_string())));
+        assert_eq!(manager.offset(), 4);
+    }
+
+    #[test]
+    fn failover_goes_to_the_most_acknowledged_reachable_replica() {
+        let manager = ReplicationManager::new();
+        let mut receivers = Vec::new();
+        for (name, port, acked) in [("r1", Some(8571), 5), ("r2", Some(8572), 9), ("r3", None, 20)] {
+            let (tx, rx) = mpsc::channel(8);
+            receivers.push(rx);
+            manager.add_replica(name.to_string(), tx);
+            manager.ack(name, acked);
+            if let Some(port) = port {
+                manager.replconf(name, ReplConf::ListeningPort(port));
+            }
+        }
+        // r3 never said where it serves clients, so it cannot take over
+        assert_eq!(manager.failover_target(), Some(("r2".to_string(), "r2:8572".to_string())));
+        assert_eq!(manager.acked_offset("r2"), Some(9));
+        assert!(manager.send_promote("r2"));
+        assert!(std::iter::from_fn(|| receivers[1].try_recv().ok()).any(|message| message == ReplicaMessage::Promote));
+        manager.remove_replica("r2");
+        assert_eq!(manager.failover_target(), Some(("r1".to_string(), "r1:8571".to_string())));
+        assert!(!manager.send_promote("r2"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn promotion_takes_the_master_slots_and_points_the_others_here() {
+        // Stands in for the old master, answering every command with +OK
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let old_master = listener.local_addr().unwrap();
+        let received = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buffer = BytesMut::new();
+            let mut commands = Vec::new();
+            while commands.len() < 2 && stream.read_buf(&mut buffer).await.unwrap() > 0 {
+                while let Ok(Some(frame)) = decode(&mut buffer) {
+                    commands.push(frame.to_args().unwrap().join(" "));
+                    stream.write_all(b"+OK\r\n").await.unwrap();
+                }
+            }
+            commands
+        });
+
+        let engine = Arc::new(DatabaseEngine::ephemeral("shop".to_string()));
+        let replication = &engine.replication;
+        assert!(promote(&engine).is_err(), "a master cannot be promoted");
+
+        replication.set_replica_of(old_master.ip().to_string(), old_master.port());
+        *replication.master_link.lock().unwrap() = Some(MasterLink { host: old_master.ip().to_string(), port: old_master.port(), username: None, password: None });
+        *replication.link.peers.lock().unwrap() = Some(("127.0.0.1:8570".to_string(), Vec::new()));
+        let range = crate::core::cluster::SlotRange { start: 0, end: 99, _node_addr: old_master.to_string() };
+        engine.cluster.nodes.insert(old_master.to_string(), vec![range]);
+
+        promote(&engine).unwrap();
+        assert!(replication.is_master());
+        assert!(engine.cluster.owns_slot_number(42));
+        assert!(engine.cluster.nodes.get(&old_master.to_string()).unwrap().is_empty());
+        let commands = tokio::time::timeout(Duration::from_secs(5), received).await.unwrap().unwrap();
+        assert_eq!(commands, ["USE shop", "REPLICAOF 127.0.0.1 8570"]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn replicas_apply_each_database_to_its_own() {
+        let master = |name: &str, key: &str| {
+            let engine = Arc::new(DatabaseEngine::ephemeral(name.to_string()));
+            let (_, cmd) = parse_command(&format!("SET {} 1", key)).unwrap();
+            crate::core::executor::execute_command(&engine, cmd, &AofLogger::detached("test.db"), &mut Session::system("test", name));
+            SnapshotManager::encode(&SnapshotManager::capture(&engine)).unwrap()
+        };
+        let snapshots = [("default", master("default", "a")), ("shop", master("shop", "s"))];
+
+        // Stands in for the master: a full sync of both databases, then
+        // writes to one and the other
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut frame = Vec::new();
+            for (name, snapshot) in &snapshots {
+                frame.extend(format!("+SELECTDB {}\r\n+FULLRESYNC {} {:08x} 0\r\n${}\r\n", name, snapshot.len(), crc32fast::hash(snapshot), snapshot.len()).into_bytes());
+                frame.extend_from_slice(snapshot);
+                frame.extend_from_slice(b"\r\n");
+            }
+            frame.extend_from_slice(b"+SYNC_COMPLETE\r\n");
+            for message in [
+                ReplicaMessage::SelectDb("shop".to_string()),
+                ReplicaMessage::Command("SET b 2".to_string()),
+                ReplicaMessage::SelectDb("default".to_string()),
+                ReplicaMessage::Command("SET c 3".to_string()),
+            ] {
+                frame.extend(message.to_frame().into_bytes());
+            }
+            stream.write_all(&frame).await.unwrap();
+            // Open until the replica hangs up
+            let mut buffer = BytesMut::new();
+            while stream.read_buf(&mut buffer).await.is_ok_and(|n| n > 0) {}
+        });
+
+        let registry = Arc::new(crate::core::registry::DatabaseRegistry::new());
+        registry.attach();
+        let (default, shop) = (registry.open_detached("default"), registry.open_detached("shop"));
+        default.replication.set_replica_of(addr.ip().to_string(), addr.port());
+        let link = MasterLink { host: addr.ip().to_string(), port: addr.port(), username: None, password: None };
+        start_replication_task(default.clone(), Arc::new(AofLogger::detached("default.db")), link);
+
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while default.flexible.get("c").is_none() && std::time::Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        default.replication.set_master();
+        let keys = |engine: &DatabaseEngine| {
+            let mut keys = engine.flexible.keys("*");
+            keys.sort();
+            keys
+        };
+        assert_eq!(keys(&default), ["a", "c"]);
+        assert_eq!(keys(&shop), ["b", "s"]);
+    }
 }
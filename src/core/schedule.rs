@@ -0,0 +1,263 @@
+//! # Scheduled Writes
+//!
+//! `SCHEDULE <key> <value> AT <unix_ms> [PUSH]` holds a write back until the
+//! given time: the key is then set to the value, or with `PUSH` the value is
+//! appended to the list at the key, as a delayed job for a consumer popping
+//! that list. Until then the key is left as it is.
+//!
+//! Pending writes live in a queue ordered by due time, which a timer thread
+//! per database drains every [`TICK`]; a `SCHEDULE` already due is delivered
+//! before its reply. Only the master delivers: it runs the write under the
+//! key's lock and logs and replicates it as the `SET` or `RPUSH` it made,
+//! together with the `UNSCHEDULE` taking it off the queue. A replay or a
+//! replica therefore applies the write at the point it happened, and a
+//! replica only queues `SCHEDULE`s until it is promoted. AOF rewrites and
+//! snapshots keep the writes still pending.
+
+use crate::core::executor::{execute_command, Session};
+use crate::core::logger;
+use crate::core::memory::DatabaseEngine;
+use crate::core::persistence::AofLogger;
+use crate::core::worker;
+use crate::query::Command;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How often each database looks for due writes.
+pub const TICK: Duration = Duration::from_millis(50);
+
+/// A write waiting for its time.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScheduledWrite {
+    pub key: String,
+    pub value: String,
+    /// Unix ms at which the write is applied
+    pub at_ms: u64,
+    /// Append to the list at `key` instead of setting it
+    pub push: bool,
+}
+
+impl ScheduledWrite {
+    /// The `SCHEDULE` command that queues this write again.
+    pub fn to_command(&self) -> String {
+        Command::Schedule { key: self.key.clone(), value: self.value.clone(), at_ms: self.at_ms, push: self.push }.to_string()
+    }
+
+    /// The write made once due.
+    pub fn delivery(&self) -> Command {
+        if self.push {
+            Command::RPush { key: self.key.clone(), values: vec![self.value.clone()] }
+        } else {
+            Command::Set { key: self.key.clone(), value: self.value.clone() }
+        }
+    }
+}
+
+/// Pending writes of a database, by due time then arrival.
+#[derive(Debug, Default)]
+pub struct Schedule {
+    pending: Mutex<BTreeMap<(u64, u64), ScheduledWrite>>,
+    next_seq: AtomicU64,
+    delivered: AtomicU64,
+    /// Held while due writes are delivered, so they go out in order
+    delivering: Mutex<()>,
+}
+
+impl Schedule {
+    pub fn add(&self, write: ScheduledWrite) {
+        let seq = self.next_seq.fetch_add(1, Ordering::Relaxed);
+        self.pending.lock().unwrap().insert((write.at_ms, seq), write);
+    }
+
+    /// Removes and returns the writes due at `now_ms`, oldest first.
+    pub fn take_due(&self, now_ms: u64) -> Vec<ScheduledWrite> {
+        let mut pending = self.pending.lock().unwrap();
+        if pending.first_key_value().is_none_or(|((at_ms, _), _)| *at_ms > now_ms) {
+            return Vec::new();
+        }
+        let later = pending.split_off(&(now_ms + 1, 0));
+        std::mem::replace(&mut *pending, later).into_values().collect()
+    }
+
+    /// Takes the writes due at `now_ms` off the queue and hands them to
+    /// `deliver` one by one, oldest first.
+    pub fn deliver_due(&self, now_ms: u64, mut deliver: impl FnMut(ScheduledWrite)) {
+        let _delivering = self.delivering.lock().unwrap();
+        for write in self.take_due(now_ms) {
+            deliver(write);
+            self.delivered.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Drops the pending writes of `key`, or with `at_ms` the first one
+    /// queued for that time. Returns how many were dropped.
+    pub fn cancel(&self, key: &str, at_ms: Option<u64>) -> usize {
+        let mut pending = self.pending.lock().unwrap();
+        let doomed: Vec<(u64, u64)> = pending.iter()
+            .filter(|((at, _), write)| write.key == key && at_ms.is_none_or(|ms| ms == *at))
+            .map(|(id, _)| *id)
+            .take(if at_ms.is_some() { 1 } else { usize::MAX })
+            .collect();
+        for id in &doomed {
+            pending.remove(id);
+        }
+        doomed.len()
+    }
+
+    /// Writes still pending, in the order they will be applied.
+    pub fn pending(&self) -> Vec<ScheduledWrite> {
+        self.pending.lock().unwrap().values().cloned().collect()
+    }
+
+    pub fn clear(&self) {
+        self.pending.lock().unwrap().clear();
+    }
+
+    /// Drops the pending writes `keep` rejects.
+    pub fn retain(&self, mut keep: impl FnMut(&ScheduledWrite) -> bool) {
+        self.pending.lock().unwrap().retain(|_, write| keep(write));
+    }
+
+    /// `# Schedule` section of INFO.
+    pub fn info(&self, now_ms: u64) -> String {
+        let pending = self.pending.lock().unwrap();
+        let next = pending.first_key_value().map_or(-1, |((at_ms, _), _)| at_ms.saturating_sub(now_ms) as i64);
+        format!(
+            "# Schedule\r\nscheduled_pending:{}\r\nscheduled_next_in_ms:{}\r\nscheduled_delivered_total:{}\r\n",
+            pending.len(), next, self.delivered.load(Ordering::Relaxed)
+        )
+    }
+}
+
+/// Delivers the due writes of `engine` every [`TICK`], until the database
+/// is closed or dropped.
+pub fn spawn_timer(engine: &Arc<DatabaseEngine>, aof: &Arc<AofLogger>) {
+    let (engine, aof) = (Arc::downgrade(engine), Arc::downgrade(aof));
+    std::thread::spawn(move || loop {
+        std::thread::sleep(TICK);
+        let (Some(engine), Some(aof)) = (engine.upgrade(), aof.upgrade()) else { break };
+        let _gate = engine.log_gate.read().unwrap();
+        deliver_due(&engine, &aof);
+    });
+}
+
+/// Makes the writes that are due, then logs and replicates them. The caller
+/// holds `log_gate`, as for any write.
+pub fn deliver_due(engine: &Arc<DatabaseEngine>, aof: &AofLogger) {
+    for line in deliver(engine, aof) {
+        if let Err(e) = aof.log(&line) {
+            logger::error(&format!("AOF Error: {}", e));
+        }
+        engine.replication.propagate(&engine.db_name, &line);
+    }
+}
+
+/// Makes the writes that are due, each under its key's lock, and returns
+/// the lines recording them. A replica waits for its master's instead.
+fn deliver(engine: &Arc<DatabaseEngine>, aof: &AofLogger) -> Vec<String> {
+    if !engine.replication.is_master() {
+        return Vec::new();
+    }
+    let mut session = Session::system("SCHEDULE", &engine.db_name);
+    let mut lines = Vec::new();
+    engine.flexible.deliver_due(|write| {
+        let cmd = write.delivery();
+        let line = cmd.to_string();
+        let (res, _) = execute_command(engine, cmd, aof, &mut session);
+        if res.is_error() {
+            logger::error(&format!("Scheduled write to '{}' failed: {}", write.key, res.to_text(crate::core::output::OutputFormat::Text)));
+            return;
+        }
+        let dequeue = Command::Unschedule { key: write.key, at_ms: Some(write.at_ms) };
+        lines.extend(worker::transaction([line, dequeue.to_string()]));
+    });
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(key: &str, at_ms: u64) -> ScheduledWrite {
+        ScheduledWrite { key: key.to_string(), value: "a b".to_string(), at_ms, push: false }
+    }
+
+    #[test]
+    fn due_writes_come_out_in_time_order() {
+        let schedule = Schedule::default();
+        schedule.add(write("c", 300));
+        schedule.add(write("a", 100));
+        schedule.add(write("b", 100));
+        assert!(schedule.take_due(99).is_empty());
+        let due: Vec<String> = schedule.take_due(100).into_iter().map(|w| w.key).collect();
+        assert_eq!(due, ["a", "b"]);
+        assert_eq!(schedule.pending(), vec![write("c", 300)]);
+        assert!(schedule.info(250).contains("scheduled_pending:1\r\nscheduled_next_in_ms:50\r\n"));
+
+        assert_eq!(write("c", 300).to_command(), "SCHEDULE c \"a b\" AT 300");
+        let push = ScheduledWrite { push: true, ..write("jobs", 5) };
+        assert_eq!(push.to_command(), "SCHEDULE jobs \"a b\" AT 5 PUSH");
+    }
+
+    #[test]
+    fn cancelling_drops_the_first_write_due_then() {
+        let schedule = Schedule::default();
+        for (key, at_ms) in [("a", 100), ("a", 100), ("a", 200), ("b", 100)] {
+            schedule.add(write(key, at_ms));
+        }
+        assert_eq!(schedule.cancel("a", Some(100)), 1);
+        assert_eq!(schedule.cancel("a", Some(300)), 0);
+        assert_eq!(schedule.pending(), vec![write("a", 100), write("b", 100), write("a", 200)]);
+        assert_eq!(schedule.cancel("a", None), 2);
+        assert_eq!(schedule.pending(), vec![write("b", 100)]);
+    }
+
+    #[test]
+    fn deliveries_are_logged_as_the_writes_they_made() {
+        use crate::core::output::OutputFormat;
+        use crate::net::parser::parse_command;
+
+        let run = |engine: &Arc<DatabaseEngine>, line: &str| {
+            let (_, cmd) = parse_command(line).unwrap();
+            let (res, _) = execute_command(engine, cmd, &AofLogger::detached("test.db"), &mut Session::system("test", "test"));
+            res.to_text(OutputFormat::Text)
+        };
+        let pushed = {
+            let control = Arc::new(DatabaseEngine::ephemeral("test".to_string()));
+            run(&control, "RPUSH jobs first second");
+            run(&control, "LRANGE jobs 0 -1")
+        };
+        let later = crate::core::backend::now_unix_ms() + 60_000;
+        let scheduled = [
+            "SCHEDULE greeting 'hello world' AT 1".to_string(),
+            "SCHEDULE jobs first AT 2 PUSH".to_string(),
+            "SCHEDULE jobs second AT 2 PUSH".to_string(),
+            format!("SCHEDULE jobs last AT {} PUSH", later),
+        ];
+        let engine = Arc::new(DatabaseEngine::ephemeral("test".to_string()));
+        for line in &scheduled {
+            run(&engine, line);
+        }
+        // Nothing happens before the delivery, however late
+        assert_eq!(run(&engine, "GET greeting"), run(&engine, "GET missing"));
+
+        let lines = deliver(&engine, &AofLogger::detached("test.db"));
+        assert_eq!(lines[..4], ["BEGIN", "SET greeting \"hello world\"", "UNSCHEDULE greeting AT 1", "COMMIT"]);
+        assert_eq!(lines.len(), 12);
+        assert!(run(&engine, "GET greeting").contains("hello world"));
+        assert_eq!(run(&engine, "LRANGE jobs 0 -1"), pushed);
+        assert!(deliver(&engine, &AofLogger::detached("test.db")).is_empty());
+
+        // A replay applies the writes where the log has them, once
+        let replayed = Arc::new(DatabaseEngine::ephemeral("test".to_string()));
+        for line in scheduled.iter().chain(&lines) {
+            run(&replayed, line);
+        }
+        assert_eq!(run(&replayed, "LRANGE jobs 0 -1"), pushed);
+        assert!(deliver(&replayed, &AofLogger::detached("test.db")).is_empty());
+        assert_eq!(replayed.flexible.dump_commands().filter(|line| line.starts_with("SCHEDULE")).count(), 1);
+    }
+}
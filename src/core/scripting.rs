@@ -0,0 +1,316 @@
+//! # Server-Side Scripts
+//!
+//! `EVAL` runs a [Rhai](https://rhai.rs) script on the server, so a read
+//! followed by a write (a rate limiter, a compare-and-set) happens as one
+//! step, without another client's write in between.
+//!
+//! A script sees the keys and arguments it was given as the `KEYS` and
+//! `ARGV` arrays of strings, and runs commands with:
+//! - `command([name, arg, ...])`: any data command, with its reply converted to
+//!   a script value; a failing command stops the script with its error.
+//! - `get(key)`, `set(key, value)`, `del(key)`, `incr(key)`: shorthands.
+//! - `query(sql)`: a SQL statement; `SELECT` gives an array of maps, one per
+//!   row, keyed by column name.
+//!
+//! The value of the script's last expression is the reply. Scripts are
+//! compiled once and cached under the SHA1 of their source, for `EVALSHA`;
+//! the cache is shared by every database, holds the last [`MAX_SCRIPTS`]
+//! loaded, and is emptied by `SCRIPT FLUSH` or a restart. How calls are
+//! locked, checked and logged is up to the executor, which hands [`run`]
+//! the function running them.
+//!
+//! A script holds the locks of its keys while it runs, so it is bounded:
+//! it stops with an error after [`TIME_BUDGET`], [`MAX_OPERATIONS`] steps,
+//! [`MAX_CALL_LEVELS`] nested calls, or once a string, array or map it
+//! builds grows past [`MAX_SIZE`].
+
+use crate::core::executor::CommandOutput;
+use crate::core::queries;
+use crate::core::types::UnifiedValue;
+use rhai::{AST, Array, Dynamic, Engine, EvalAltResult, Map, Scope};
+use sha1::{Digest, Sha1};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, LazyLock, RwLock};
+use std::time::{Duration, Instant};
+
+/// Longest a script may run.
+pub const TIME_BUDGET: Duration = Duration::from_secs(5);
+/// Most steps a script may take.
+pub const MAX_OPERATIONS: u64 = 100_000_000;
+/// Deepest a script's function calls may nest.
+pub const MAX_CALL_LEVELS: usize = 64;
+/// Longest string, and most elements of an array or map, a script may build.
+pub const MAX_SIZE: usize = 1 << 20;
+/// Scripts kept for `EVALSHA`; loading another drops the oldest.
+pub const MAX_SCRIPTS: usize = 1000;
+
+/// Runs one command for a script: its name and arguments, as given to `command`.
+pub type CallFn = dyn Fn(Vec<String>) -> Result<CommandOutput, String> + Send + Sync;
+
+#[derive(Debug)]
+pub struct Script {
+    ast: AST,
+}
+
+/// Compiled scripts by SHA1, with the SHA1s in the order they were loaded.
+#[derive(Default)]
+struct Cache {
+    scripts: HashMap<String, Arc<Script>>,
+    order: VecDeque<String>,
+}
+
+static SCRIPTS: LazyLock<RwLock<Cache>> = LazyLock::new(Default::default);
+
+/// Lowercase hex SHA1 of a script's source, its name for `EVALSHA`.
+pub fn sha1_hex(source: &str) -> String {
+    Sha1::digest(source.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Compiles a script and caches it (`EVAL`, `SCRIPT LOAD`), returning its
+/// SHA1 with it.
+pub fn load(source: &str) -> Result<(String, Arc<Script>), String> {
+    let sha = sha1_hex(source);
+    if let Some(script) = cached(&sha) {
+        return Ok((sha, script));
+    }
+    let ast = limited_engine().compile(source).map_err(|e| format!("Script compile error: {}", e))?;
+    let script = Arc::new(Script { ast });
+    let mut cache = SCRIPTS.write().unwrap_or_else(|e| e.into_inner());
+    if cache.scripts.insert(sha.clone(), script.clone()).is_none() {
+        cache.order.push_back(sha.clone());
+        while cache.order.len() > MAX_SCRIPTS {
+            let oldest = cache.order.pop_front().unwrap();
+            cache.scripts.remove(&oldest);
+        }
+    }
+    Ok((sha, script))
+}
+
+/// A Rhai engine with the limits every script runs under.
+fn limited_engine() -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+    engine.set_max_call_levels(MAX_CALL_LEVELS);
+    engine.set_max_string_size(MAX_SIZE);
+    engine.set_max_array_size(MAX_SIZE);
+    engine.set_max_map_size(MAX_SIZE);
+    engine
+}
+
+/// A script loaded before, by its SHA1 (any case).
+pub fn cached(sha: &str) -> Option<Arc<Script>> {
+    SCRIPTS.read().unwrap_or_else(|e| e.into_inner()).scripts.get(&sha.to_ascii_lowercase()).cloned()
+}
+
+/// Empties the cache (`SCRIPT FLUSH`).
+pub fn flush() {
+    *SCRIPTS.write().unwrap_or_else(|e| e.into_inner()) = Cache::default();
+}
+
+/// Runs a script, with `call` running the commands it makes. A killed query
+/// stops the script at its next step, as does running past [`TIME_BUDGET`].
+pub fn run(script: &Script, keys: Vec<String>, args: Vec<String>, call: Arc<CallFn>) -> Result<CommandOutput, String> {
+    run_within(TIME_BUDGET, script, keys, args, call)
+}
+
+fn run_within(budget: Duration, script: &Script, keys: Vec<String>, args: Vec<String>, call: Arc<CallFn>) -> Result<CommandOutput, String> {
+    let mut engine = limited_engine();
+    engine.on_print(|s| crate::core::logger::info(&format!("Script: {}", s)));
+    engine.on_debug(|s, _, _| crate::core::logger::info(&format!("Script: {}", s)));
+    let started = Instant::now();
+    engine.on_progress(move |_| {
+        if queries::checkpoint().is_err() {
+            Some(Dynamic::from("Script killed"))
+        } else if started.elapsed() > budget {
+            Some(Dynamic::from(format!("Script timed out after {} ms", budget.as_millis())))
+        } else {
+            None
+        }
+    });
+
+    let run = move |args: Vec<String>| call(args).and_then(to_dynamic).map_err(Box::<EvalAltResult>::from);
+    let words = |words: &[&str]| words.iter().map(|w| w.to_string()).collect::<Vec<String>>();
+    let f = run.clone();
+    engine.register_fn("command", move |args: Array| f(args.iter().map(Dynamic::to_string).collect()));
+    let f = run.clone();
+    engine.register_fn("get", move |key: &str| f(words(&["GET", key])));
+    let f = run.clone();
+    engine.register_fn("set", move |key: &str, value: Dynamic| f(words(&["SET", key, &value.to_string()])));
+    let f = run.clone();
+    engine.register_fn("del", move |key: &str| f(words(&["DEL", key])));
+    let f = run.clone();
+    engine.register_fn("incr", move |key: &str| f(words(&["INCR", key])));
+    // A statement is one argument, so it is parsed rather than bound
+    let f = run;
+    engine.register_fn("query", move |sql: &str| f(vec![sql.to_string()]));
+
+    let mut scope = Scope::new();
+    scope.push_constant("KEYS", keys.into_iter().map(Dynamic::from).collect::<Array>());
+    scope.push_constant("ARGV", args.into_iter().map(Dynamic::from).collect::<Array>());
+    match engine.eval_ast_with_scope::<Dynamic>(&mut scope, &script.ast) {
+        Ok(value) => Ok(from_dynamic(value)),
+        Err(e) => match *e {
+            EvalAltResult::ErrorTerminated(reason, _) => Err(reason.to_string()),
+            EvalAltResult::ErrorRuntime(message, pos) if !pos.is_none() => Err(format!("Script error: {} ({})", message, pos)),
+            e => Err(format!("Script error: {}", e)),
+        },
+    }
+}
+
+/// A command's reply as a script value; an error reply is an error.
+fn to_dynamic(output: CommandOutput) -> Result<Dynamic, String> {
+    Ok(match output {
+        CommandOutput::Ok => Dynamic::from("OK"),
+        CommandOutput::Status(s) | CommandOutput::Bulk(s) => Dynamic::from(s),
+        CommandOutput::Int(n) => Dynamic::from(n),
+        CommandOutput::Nil => Dynamic::UNIT,
+        CommandOutput::Array(items) => Dynamic::from(items.into_iter().map(to_dynamic).collect::<Result<Array, _>>()?),
+        CommandOutput::Rows(result) => {
+            let rows = result.rows.into_iter().map(|row| {
+                let map: Map = result.columns.iter().zip(row).map(|(column, value)| (column.as_str().into(), value_to_dynamic(value))).collect();
+                Dynamic::from(map)
+            });
+            Dynamic::from(rows.collect::<Array>())
+        }
+        CommandOutput::Err(e) => return Err(e),
+        CommandOutput::FullResync => Dynamic::UNIT,
+    })
+}
+
+fn value_to_dynamic(value: UnifiedValue) -> Dynamic {
+    match value {
+        UnifiedValue::Null => Dynamic::UNIT,
+        UnifiedValue::Integer(n) | UnifiedValue::DateTime(n) => Dynamic::from(n),
+        UnifiedValue::Float(f) => Dynamic::from(f),
+        UnifiedValue::Boolean(b) => Dynamic::from(b),
+        UnifiedValue::String(s) | UnifiedValue::Blob(s) => Dynamic::from(s),
+        other => Dynamic::from(other.to_string()),
+    }
+}
+
+/// The reply for a script's result: `()` and `false` are nil and `true` is
+/// 1, as in Redis; a map is a flat array of keys and values.
+fn from_dynamic(value: Dynamic) -> CommandOutput {
+    if value.is_unit() {
+        return CommandOutput::Nil;
+    }
+    if let Ok(b) = value.as_bool() {
+        return if b { CommandOutput::Int(1) } else { CommandOutput::Nil };
+    }
+    if let Ok(n) = value.as_int() {
+        return CommandOutput::Int(n);
+    }
+    if value.is_array() {
+        return CommandOutput::Array(value.cast::<Array>().into_iter().map(from_dynamic).collect());
+    }
+    if value.is_map() {
+        let map = value.cast::<Map>();
+        return CommandOutput::Array(map.into_iter().flat_map(|(k, v)| [CommandOutput::Bulk(k.to_string()), from_dynamic(v)]).collect());
+    }
+    CommandOutput::Bulk(value.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    // Runs `source` against a map standing for the keyspace
+    fn eval(source: &str, keys: &[&str], args: &[&str]) -> (Result<CommandOutput, String>, Vec<Vec<String>>) {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let store = Arc::new(Mutex::new(HashMap::<String, String>::new()));
+        let seen = calls.clone();
+        let call = move |args: Vec<String>| -> Result<CommandOutput, String> {
+            seen.lock().unwrap().push(args.clone());
+            let mut store = store.lock().unwrap();
+            match args[0].as_str() {
+                "GET" => Ok(store.get(&args[1]).cloned().map_or(CommandOutput::Nil, CommandOutput::Bulk)),
+                "SET" => {
+                    store.insert(args[1].clone(), args[2].clone());
+                    Ok(CommandOutput::Ok)
+                }
+                "INCR" => {
+                    let n = store.get(&args[1]).map_or(0, |v| v.parse::<i64>().unwrap()) + 1;
+                    store.insert(args[1].clone(), n.to_string());
+                    Ok(CommandOutput::Int(n))
+                }
+                other => Err(format!("Unknown command '{}'", other)),
+            }
+        };
+        let (_, script) = load(source).unwrap();
+        let words = |w: &[&str]| w.iter().map(|s| s.to_string()).collect();
+        let out = run(&script, words(keys), words(args), Arc::new(call));
+        let calls = calls.lock().unwrap().clone();
+        (out, calls)
+    }
+
+    #[test]
+    fn scripts_run_commands() {
+        let limiter = r#"
+            let n = incr(KEYS[0]);
+            n <= parse_int(ARGV[0])
+        "#;
+        let (out, calls) = eval(limiter, &["hits"], &["1"]);
+        assert!(matches!(out, Ok(CommandOutput::Int(1))));
+        assert_eq!(calls, [["INCR", "hits"]]);
+
+        let (out, _) = eval(r#"set("a", 5); [get("a"), get("b"), command(["INCR", "a"])]"#, &[], &[]);
+        match out.unwrap() {
+            CommandOutput::Array(items) => {
+                assert!(matches!(&items[..], [CommandOutput::Bulk(a), CommandOutput::Nil, CommandOutput::Int(6)] if a == "5"));
+            }
+            other => panic!("{:?}", other),
+        }
+        assert!(matches!(eval("false", &[], &[]).0, Ok(CommandOutput::Nil)));
+    }
+
+    #[test]
+    fn failures_stop_the_script() {
+        let (out, calls) = eval(r#"command(["NOPE"]); set("a", 1)"#, &[], &[]);
+        assert!(out.unwrap_err().contains("Unknown command 'NOPE'"));
+        assert_eq!(calls.len(), 1);
+        assert!(load("let = ;").unwrap_err().starts_with("Script compile error"));
+        assert!(eval("throw \"boom\"", &[], &[]).0.unwrap_err().contains("boom"));
+    }
+
+    /// Held by the tests that look at what the cache holds
+    static CACHE: Mutex<()> = Mutex::new(());
+
+    #[test]
+    fn scripts_are_cached_by_sha1() {
+        let _cache = CACHE.lock().unwrap();
+        assert_eq!(sha1_hex(""), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        let (sha, _) = load("40 + 2").unwrap();
+        assert!(cached(&sha.to_uppercase()).is_some());
+        assert!(cached("0000000000000000000000000000000000000000").is_none());
+    }
+
+    #[test]
+    fn scripts_run_within_limits() {
+        let error = |source: &str| eval(source, &[], &[]).0.unwrap_err();
+        assert!(error(r#"let s = "ab"; loop { s += s; }"#).contains("too large"));
+        let message = error("let a = [1]; loop { a += a; }");
+        assert!(message.contains("too large"), "{}", message);
+        assert!(error("fn down(n) { down(n + 1) } down(0)").contains("Stack overflow"));
+        // A script waiting on its commands still runs out of time
+        let (_, script) = load(r#"loop { command(["SLOW"]); }"#).unwrap();
+        let slow = |_: Vec<String>| -> Result<CommandOutput, String> {
+            std::thread::sleep(Duration::from_millis(50));
+            Ok(CommandOutput::Ok)
+        };
+        let out = run_within(Duration::from_millis(200), &script, Vec::new(), Vec::new(), Arc::new(slow));
+        assert_eq!(out.unwrap_err(), "Script timed out after 200 ms");
+    }
+
+    #[test]
+    fn the_cache_keeps_the_latest_scripts() {
+        let _cache = CACHE.lock().unwrap();
+        let (first, _) = load("\"first of many\"").unwrap();
+        for n in 0..MAX_SCRIPTS {
+            load(&format!("{} + 0", n)).unwrap();
+        }
+        assert!(cached(&first).is_none());
+        let cache = SCRIPTS.read().unwrap();
+        assert!(cache.scripts.len() <= MAX_SCRIPTS && cache.order.len() == cache.scripts.len());
+    }
+}
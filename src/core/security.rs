@@ -1,6 +1,16 @@
 use dashmap::DashMap;
 use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
 use crate::query::Command;
+use crate::core::auth::AuthProvider;
+use crate::core::logger;
+use crate::core::glob::glob_match;
+
+/// How long a username the external provider turned down is refused
+/// without asking it again.
+pub const PROVIDER_RETRY_AFTER: Duration = Duration::from_secs(1);
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct User {
@@ -13,11 +23,19 @@ impl User {
     pub fn can_execute(&self, cmd: &Command) -> bool {
         let cmd_name = match cmd {
             Command::ReplicaOf { .. } => "admin", // Requires admin/all permissions
+            Command::Failover { .. } => "admin",
+            Command::ReplCheck { .. } => "admin",
             Command::Set { .. } => "set",
             Command::Get { .. } => "get",
+            Command::GetRange { .. } => "get",
+            Command::GetEx { .. } => "getex",
             Command::Ttl { .. } => "ttl",
+            Command::PExpireAt { .. } => "pexpireat",
+            Command::Schedule { .. } => "schedule",
+            Command::Unschedule { .. } => "unschedule",
             Command::Incr { .. } => "incr",
             Command::Decr { .. } => "decr",
+            Command::CIncr { .. } => "cincr",
             Command::LPush { .. } => "lpush",
             Command::RPush { .. } => "rpush",
             Command::LPop { .. } => "lpop",
@@ -37,74 +55,292 @@ impl User {
             Command::Update { .. } => "update",
             Command::Delete { .. } => "delete",
             Command::Del { .. } => "delete",
-            Command::CreateIndex { .. } => "createindex",
+            Command::Scan { .. } => "scan",
+            Command::Keys { .. } => "keys",
+            Command::Type { .. } => "type",
+            Command::Exists { .. } => "exists",
+            Command::DbSize => "dbsize",
+            Command::CreateIndex { .. } | Command::CreateVectorIndex { .. } => "createindex",
+            Command::DropIndex { .. } => "dropindex",
+            Command::ShowIndexes { .. } => "select",
+            Command::ShowTables => "select",
+            Command::ShowDatabases => "showdatabases",
+            Command::DropDatabase { .. } => "dropdatabase",
+            Command::Describe { .. } => "select",
+            Command::ShowCreateTable { .. } => "select",
+            Command::Reindex { .. } => "admin",
+            Command::CheckTable { .. } => "admin",
+            Command::ExportTable { .. } => "exporttable",
+            Command::ImportTable { .. } => "importtable",
+            Command::AdvisorSuggest => "admin",
             Command::AclSetUser { .. } => "acl",
             Command::AclList => "acl",
             Command::AclGetUser { .. } => "acl",
             Command::AclDelUser { .. } => "acl",
+            Command::AclUsage { .. } => "acl",
             Command::Auth { .. } => "auth",
             Command::Ping => "ping",
+            Command::Quit => "ping",
+            Command::Reset => "ping",
+            Command::ReadOnly | Command::ReadWrite => "ping",
+            Command::ObjectHelp => "ping",
             Command::Save => "save",
+            Command::BackupVerify { .. } => "admin",
+            Command::AofStats => "admin",
+            Command::AofCat { .. } => "admin",
+            Command::ExportKeys { .. } => "admin",
+            Command::Dump { .. } => "dump",
+            Command::Restore { .. } => "restore",
             Command::RewriteAof => "rewriteaof",
             Command::SetEx { .. } => "setex",
+            Command::Expire { .. } => "expire",
             Command::ClientList => "client",
             Command::ClientKill { .. } => "client",
+            Command::ActiveQueries => "client",
+            Command::KillQuery { .. } => "client",
             Command::Psync => "admin",
+            Command::Wait { .. } => "wait",
             Command::Info => "info",
+            Command::Metrics => "info",
+            Command::MetricsReset => "config",
+            Command::MemoryUsage { .. } => "memory",
+            Command::MemoryStats => "memory",
+            Command::ConfigGet { .. } => "config",
+            Command::ConfigSet { .. } => "config",
             Command::ClusterInfo => "cluster",
             Command::ClusterSlots => "cluster",
             Command::ClusterMeet { .. } => "cluster",
             Command::ClusterAddSlots { .. } => "cluster",
+            Command::ClusterDelSlots { .. } => "cluster",
+            Command::ClusterForget { .. } => "cluster",
+            Command::ClusterFailover => "cluster",
+            Command::ClusterSetSlot { .. } => "cluster",
+            Command::Migrate { .. } => "admin",
+            Command::Asking => "ping",
             Command::ZAdd { .. } => "zadd",
             Command::ZRange { .. } => "zrange",
             Command::ZScore { .. } => "zscore",
+            Command::ZPop { max: false, .. } => "zpopmin",
+            Command::ZPop { max: true, .. } => "zpopmax",
+            Command::BZPop { max: false, .. } => "bzpopmin",
+            Command::BZPop { max: true, .. } => "bzpopmax",
+            Command::GeoAdd { .. } => "geoadd",
+            Command::GeoDist { .. } => "geodist",
+            Command::GeoSearch { .. } => "geosearch",
+            Command::TopKReserve { .. } => "topk",
+            Command::TopKAdd { .. } => "topk",
+            Command::TopKIncrBy { .. } => "topk",
+            Command::TopKList { .. } => "topk",
+            Command::SetBit { .. } => "setbit",
+            Command::GetBit { .. } => "getbit",
+            Command::BitCount { .. } => "bitcount",
+            Command::BitOp { .. } => "bitop",
+            Command::PfAdd { .. } => "pfadd",
+            Command::PfCount { .. } => "pfcount",
+            Command::PfMerge { .. } => "pfmerge",
+            Command::Eval { .. } => "eval",
+            Command::EvalSha { .. } => "evalsha",
+            Command::ScriptLoad { .. } | Command::ScriptExists { .. } | Command::ScriptFlush => "script",
+            Command::XAdd { .. } => "xadd",
+            Command::XRange { .. } => "xrange",
+            Command::XLen { .. } => "xlen",
+            Command::XRead { .. } => "xread",
             Command::Use { .. } => "use",
+            Command::SetOutput { .. } => "output",
+            Command::SetBinding { .. } => "binding",
+            Command::SetVariable { .. } => "variables",
+            Command::ShowVariables => "variables",
             Command::Begin => "transaction",
             Command::Commit => "transaction",
             Command::Rollback => "transaction",
+            Command::Watch { .. } => "transaction",
+            Command::Unwatch => "transaction",
             Command::VectorSearch { .. } => "select",
+            Command::Explain { .. } | Command::Declare { .. } | Command::Fetch { .. } | Command::Close { .. } => "select",
         };
 
-        // Simplified rule checking
-        if self.rules.contains(&"+@all".to_string()) {
-            return true;
-        }
+        let has = |rule: String| self.rules.contains(&rule);
+        let tables = cmd.tables();
+        // `+@read`, `-@dangerous`, ...
+        let category_rule = |sign: char| self.rules.iter().any(|rule| {
+            rule.strip_prefix(sign).and_then(|r| r.strip_prefix('@')).is_some_and(|category| in_category(cmd, category))
+        });
 
-        if self.rules.contains(&format!("-{}", cmd_name)) {
+        // Denials win over any grant, +@all included
+        if has(format!("-{}", cmd_name)) || category_rule('-') || tables.iter().any(|t| has(format!("-{}@{}", cmd_name, t))) {
             return false;
         }
 
-        if self.rules.contains(&format!("+{}", cmd_name)) {
-            return true;
-        }
+        // Granted by command, by category, or table by table (`+select@orders`)
+        let granted = has(format!("+{}", cmd_name))
+            || category_rule('+')
+            || (!tables.is_empty() && tables.iter().all(|t| has(format!("+{}@{}", cmd_name, t))));
 
-        false
+        granted && cmd.keys().into_iter().all(|key| self.can_access_key(key))
+    }
+
+    /// Daily limit set by a `quota:<kind>=<n>` rule (`quota:cmds=1000000`,
+    /// `quota:bytes=...`); the last such rule wins.
+    pub fn quota(&self, kind: &str) -> Option<u64> {
+        self.rules.iter().rev().find_map(|rule| {
+            let (k, n) = rule.strip_prefix("quota:")?.split_once('=')?;
+            if k.eq_ignore_ascii_case(kind) { n.parse().ok() } else { None }
+        })
+    }
+
+    /// Whether the user's key patterns (`~cache:*`) cover `key`. Users
+    /// without any pattern may access every key.
+    pub fn can_access_key(&self, key: &str) -> bool {
+        let mut patterns = self.rules.iter().filter_map(|rule| rule.strip_prefix('~')).peekable();
+        patterns.peek().is_none() || patterns.any(|pattern| glob_match(pattern, key))
+    }
+}
+
+/// What a user sent on one UTC day.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Usage {
+    /// Days since the Unix epoch
+    pub day: u64,
+    pub commands: u64,
+    pub bytes: u64,
+}
+
+impl Usage {
+    /// This usage as of `day`: zero if it was counted on an earlier day.
+    fn on(self, day: u64) -> Self {
+        if self.day == day { self } else { Usage { day, ..Default::default() } }
+    }
+}
+
+/// Current UTC day, as days since the Unix epoch.
+fn today() -> u64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs() / 86_400
+}
+
+/// ACL categories a rule can name as `+@category` / `-@category`.
+pub const CATEGORIES: &[&str] = &["all", "read", "write", "admin", "dangerous", "keyspace", "sql", "scripting", "connection"];
+
+/// The first `+@category` / `-@category` rule naming no known category.
+pub fn unknown_category(rules: &[String]) -> Option<&str> {
+    rules.iter().map(String::as_str).find(|rule| {
+        rule.strip_prefix(['+', '-']).and_then(|r| r.strip_prefix('@')).is_some_and(|category| !CATEGORIES.contains(&category))
+    })
+}
+
+/// Whether `cmd` belongs to an ACL category. Every command is either `@write`
+/// (it changes data), `@admin` (server management), or `@read`; session
+/// commands such as PING or USE are `@connection` and also count as `@read`.
+pub fn in_category(cmd: &Command, category: &str) -> bool {
+    use Command::*;
+    match category {
+        "all" => true,
+        "write" => cmd.is_write(),
+        "admin" => matches!(cmd,
+            ReplicaOf { .. } | Failover { .. } | ReplCheck { .. } | Psync | ShowDatabases | DropDatabase { .. } | Info | Metrics | MetricsReset | MemoryStats | ConfigGet { .. } | ConfigSet { .. } | Save | BackupVerify { .. } | AofStats | AofCat { .. } | ExportKeys { .. } | RewriteAof |
+            ClusterMeet { .. } | ClusterAddSlots { .. } | ClusterDelSlots { .. } | ClusterForget { .. } | ClusterFailover | ClusterSetSlot { .. } | Migrate { .. } |
+            Reindex { .. } | CheckTable { .. } | AdvisorSuggest |
+            AclSetUser { .. } | AclGetUser { .. } | AclList | AclDelUser { .. } | AclUsage { .. } |
+            ClientList | ClientKill { .. } | ActiveQueries | KillQuery { .. }
+        ),
+        "read" => !in_category(cmd, "write") && !in_category(cmd, "admin"),
+        "dangerous" => matches!(cmd,
+            Keys { .. } | ExportKeys { .. } | ConfigSet { .. } | ReplicaOf { .. } | Failover { .. } | Psync | Save | RewriteAof | DropDatabase { .. } |
+            ClusterMeet { .. } | ClusterAddSlots { .. } | ClusterDelSlots { .. } | ClusterForget { .. } | ClusterFailover | ClusterSetSlot { .. } | Migrate { .. } |
+            AclSetUser { .. } | AclDelUser { .. } | ClientKill { .. } | KillQuery { .. } | Restore { .. } | ImportTable { .. }
+        ),
+        "keyspace" => matches!(cmd,
+            Del { .. } | Exists { .. } | Type { .. } | Scan { .. } | Keys { .. } |
+            Ttl { .. } | Expire { .. } | PExpireAt { .. } | MemoryUsage { .. } | DbSize | Dump { .. } | Restore { .. }
+        ),
+        "sql" => !cmd.tables().is_empty() || matches!(cmd, ShowTables | ShowIndexes { .. } | AdvisorSuggest | Fetch { .. } | Close { .. }),
+        "scripting" => matches!(cmd, Eval { .. } | EvalSha { .. } | ScriptLoad { .. } | ScriptExists { .. } | ScriptFlush),
+        "connection" => matches!(cmd,
+            Ping | Quit | Reset | ReadOnly | ReadWrite | Asking | Use { .. } | SetOutput { .. } | SetBinding { .. } | SetVariable { .. } | ShowVariables |
+            Begin | Rollback | Watch { .. } | Unwatch | ObjectHelp | ClusterInfo | ClusterSlots | Wait { .. }
+        ),
+        _ => false,
     }
 }
 
+/// Users and their rules, shared by every database of a server.
+///
+/// A persistent store keeps its users in a JSON file (password hashes only),
+/// rewritten whole on every change. The file wins over the AOF: once users
+/// were loaded from it, replayed ACL commands are skipped.
+///
+/// Usernames that are not local users can be vouched for by an external
+/// [`AuthProvider`] (see [`crate::core::auth`]).
 pub struct SecurityStore {
     users: DashMap<String, User>,
+    /// Users file; None keeps users in memory only
+    path: Option<PathBuf>,
+    /// True if the users came from an existing users file
+    restored: bool,
+    /// Serializes rewrites of the users file
+    save_lock: Mutex<()>,
+    /// Consulted for usernames that are not local users
+    provider: RwLock<Option<Arc<dyn AuthProvider>>>,
+    /// When the provider last rejected (or failed to check) each username
+    provider_failures: DashMap<String, Instant>,
+    /// Today's usage per user, for quotas and `ACL USAGE`
+    usage: DashMap<String, Usage>,
 }
 
 impl SecurityStore {
     pub fn new() -> Self {
-        let store = Self {
+        Self::empty(None).with_default_user()
+    }
+
+    /// A store saved to `path`, starting from the users found there (or just
+    /// the default user if the file does not exist yet).
+    pub fn persistent(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let mut store = Self::empty(Some(path.clone()));
+        match std::fs::read_to_string(&path) {
+            Ok(data) => match serde_json::from_str::<Vec<User>>(&data) {
+                Ok(users) => {
+                    for user in users {
+                        store.users.insert(user.username.clone(), user);
+                    }
+                    store.restored = true;
+                    logger::info(&format!("Loaded {} users from {}", store.users.len(), path.display()));
+                    return store;
+                }
+                // Keep the file for inspection; it is only replaced on the next ACL change
+                Err(e) => logger::error(&format!("Ignoring unreadable users file {}: {}", path.display(), e)),
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => logger::error(&format!("Failed to read users file {}: {}", path.display(), e)),
+        }
+        store.with_default_user()
+    }
+
+    fn empty(path: Option<PathBuf>) -> Self {
+        Self {
             users: DashMap::new(),
-        };
-        
+            path,
+            restored: false,
+            save_lock: Mutex::new(()),
+            provider: RwLock::new(None),
+            provider_failures: DashMap::new(),
+            usage: DashMap::new(),
+        }
+    }
+
+    fn with_default_user(self) -> Self {
         // Default admin user
-        let default_pass = std::env::var("DB_PASSWORD").unwrap_or_else(|_| "secret".to_string());
+        let default_pass = crate::core::config::shared().settings().password;
         
         // Hash the default password
         let hashed = bcrypt::hash(default_pass, bcrypt::DEFAULT_COST).unwrap_or_else(|_| "bcrypt_failed".to_string());
         
-        store.users.insert("default".to_string(), User {
+        self.users.insert("default".to_string(), User {
             username: "default".to_string(),
             password: hashed,
             rules: vec!["+@all".to_string()],
         });
         
-        store
+        self
     }
 
     pub fn authenticate(&self, username: &str, password: &str) -> bool {
@@ -115,6 +351,36 @@ impl SecurityStore {
         false
     }
 
+    /// Installs the external provider, replacing any previous one.
+    pub fn set_provider(&self, provider: Arc<dyn AuthProvider>) {
+        *self.provider.write().unwrap() = Some(provider);
+    }
+
+    /// `AUTH`: the user to log in as. Local users are checked here only;
+    /// other usernames go to the external provider, at most once per
+    /// [`PROVIDER_RETRY_AFTER`] after it turned them down.
+    pub fn login(&self, username: &str, password: &str) -> Option<User> {
+        if self.users.contains_key(username) {
+            return AuthProvider::verify(self, username, password).ok().flatten();
+        }
+        let provider = self.provider.read().unwrap().clone()?;
+        if self.provider_failures.get(username).is_some_and(|at| at.elapsed() < PROVIDER_RETRY_AFTER) {
+            return None;
+        }
+        let user = provider.verify(username, password).unwrap_or_else(|e| {
+            logger::error(&format!("{} authentication of '{}' failed: {}", provider.name(), username, e));
+            None
+        });
+        match &user {
+            Some(_) => { self.provider_failures.remove(username); }
+            None => {
+                self.provider_failures.retain(|_, at| at.elapsed() < PROVIDER_RETRY_AFTER);
+                self.provider_failures.insert(username.to_string(), Instant::now());
+            }
+        }
+        user
+    }
+
     pub fn get_user(&self, username: &str) -> Option<User> {
         self.users.get(username).map(|u| u.clone())
     }
@@ -123,6 +389,7 @@ impl SecurityStore {
         // If password is already a bcrypt hash, don't re-hash (useful for AOF replay)
         if user.password.starts_with("$2a$") || user.password.starts_with("$2b$") || user.password.starts_with("$2y$") {
             self.users.insert(user.username.clone(), user.clone());
+            self.save();
             return user.password;
         }
 
@@ -130,6 +397,7 @@ impl SecurityStore {
         if let Ok(hashed) = bcrypt::hash(&user.password, bcrypt::DEFAULT_COST) {
             user.password = hashed.clone();
             self.users.insert(user.username.clone(), user);
+            self.save();
             return hashed;
         }
         "error".to_string()
@@ -137,9 +405,207 @@ impl SecurityStore {
 
     pub fn delete_user(&self, username: &str) {
         self.users.remove(username);
+        self.save();
+    }
+
+    /// Counts a command of `bytes` sent by `user`, unless it would exceed one
+    /// of the user's daily quotas; the error then says which one.
+    pub fn charge(&self, user: &User, bytes: usize) -> Result<(), String> {
+        self.charge_on(user, bytes as u64, today())
+    }
+
+    fn charge_on(&self, user: &User, bytes: u64, day: u64) -> Result<(), String> {
+        let mut usage = self.usage.entry(user.username.clone()).or_default();
+        let today = usage.on(day);
+        if let Some(limit) = user.quota("cmds").filter(|&limit| today.commands >= limit) {
+            return Err(format!("QUOTA User '{}' reached its daily quota of {} commands", user.username, limit));
+        }
+        if let Some(limit) = user.quota("bytes").filter(|&limit| today.bytes + bytes > limit) {
+            return Err(format!("QUOTA User '{}' reached its daily quota of {} bytes", user.username, limit));
+        }
+        *usage = Usage { commands: today.commands + 1, bytes: today.bytes + bytes, ..today };
+        Ok(())
+    }
+
+    /// What `username` sent today.
+    pub fn usage(&self, username: &str) -> Usage {
+        let day = today();
+        self.usage.get(username).map_or(Usage { day, ..Default::default() }, |usage| usage.on(day))
     }
 
     pub fn list_users(&self) -> Vec<String> {
         self.users.iter().map(|kv| kv.key().clone()).collect()
     }
+
+    /// True if the users were loaded from the users file, which then
+    /// supersedes ACL commands found in AOFs.
+    pub fn restored(&self) -> bool {
+        self.restored
+    }
+
+    /// Rewrites the users file (through a temporary file, so a crash never
+    /// leaves it half written).
+    fn save(&self) {
+        let Some(path) = &self.path else { return };
+        let _guard = self.save_lock.lock().unwrap_or_else(|e| e.into_inner());
+        let mut users: Vec<User> = self.users.iter().map(|kv| kv.value().clone()).collect();
+        users.sort_by(|a, b| a.username.cmp(&b.username));
+
+        let result = (|| -> std::io::Result<()> {
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            let tmp = path.with_extension("json.tmp");
+            std::fs::write(&tmp, serde_json::to_vec_pretty(&users)?)?;
+            std::fs::rename(&tmp, path)
+        })();
+        if let Err(e) = result {
+            logger::error(&format!("Failed to save users file {}: {}", path.display(), e));
+        }
+    }
+}
+
+impl AuthProvider for SecurityStore {
+    fn name(&self) -> &'static str {
+        "local"
+    }
+
+    fn verify(&self, username: &str, password: &str) -> anyhow::Result<Option<User>> {
+        Ok(if SecurityStore::authenticate(self, username, password) { self.get_user(username) } else { None })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Pre-hashed, so the tests never pay for bcrypt
+    fn user(name: &str) -> User {
+        User {
+            username: name.to_string(),
+            password: "$2b$04$abcdefghijklmnopqrstuu5Gb1Jkq0e8P3nC1Kk0W7u1Zr8yXb6W".to_string(),
+            rules: vec!["+get".to_string()],
+        }
+    }
+
+    #[test]
+    fn table_and_key_rules() {
+        let mut tenant = user("tenant");
+        tenant.rules = ["+select@orders", "+insert@orders", "-delete@users", "+get", "~cache:*", "~s?ssion"]
+            .map(String::from).to_vec();
+        let parse = |cmd: &str| crate::net::parser::parse_command(cmd).unwrap().1;
+
+        assert!(tenant.can_execute(&parse("SELECT * FROM orders")));
+        assert!(tenant.can_execute(&parse("INSERT orders 1 2")));
+        assert!(!tenant.can_execute(&parse("SELECT * FROM users")));
+        // Every joined table needs the grant
+        assert!(!tenant.can_execute(&parse("SELECT * FROM orders JOIN users ON orders.uid = users.id")));
+        assert!(tenant.can_execute(&parse("GET cache:1")));
+        assert!(tenant.can_execute(&parse("GET session")));
+        assert!(!tenant.can_execute(&parse("GET other:1")));
+
+        let mut admin = user("admin");
+        admin.rules = ["+@all", "-delete@users"].map(String::from).to_vec();
+        assert!(admin.can_execute(&parse("DELETE FROM orders WHERE id = 1")));
+        assert!(!admin.can_execute(&parse("DELETE FROM users WHERE id = 1")));
+        assert!(admin.can_access_key("anything"));
+    }
+
+    #[test]
+    fn category_rules() {
+        let mut analyst = user("analyst");
+        analyst.rules = vec!["+@read".to_string()];
+        let parse = |cmd: &str| crate::net::parser::parse_command(cmd).unwrap().1;
+
+        for allowed in ["SELECT * FROM orders", "GET k", "HGETALL h", "USE reports", "PING", "EXPLAIN SELECT * FROM orders"] {
+            assert!(analyst.can_execute(&parse(allowed)), "{}", allowed);
+        }
+        for denied in ["SET k v", "DEL k", "INSERT orders 1", "GETEX k EX 10", "ACL LIST", "SAVE", "COMMIT"] {
+            assert!(!analyst.can_execute(&parse(denied)), "{}", denied);
+        }
+
+        let mut operator = user("operator");
+        operator.rules = ["+@all", "-@dangerous", "-@admin"].map(String::from).to_vec();
+        assert!(operator.can_execute(&parse("DELETE FROM orders WHERE id = 1")));
+        assert!(!operator.can_execute(&parse("KEYS *")));
+        assert!(!operator.can_execute(&parse("CLIENT LIST")));
+
+        assert!(in_category(&parse("DEL k"), "keyspace"));
+        assert!(in_category(&parse("SHOW TABLES"), "sql"));
+        assert!(!in_category(&parse("GET k"), "sql"));
+        assert!(in_category(&parse("EVALSHA abc 0"), "scripting"));
+
+        let rules = |rules: &[&str]| rules.iter().map(|r| r.to_string()).collect::<Vec<_>>();
+        assert_eq!(unknown_category(&rules(&["+@all", "-@scripting", "+get", "~user@*"])), None);
+        assert_eq!(unknown_category(&rules(&["+@read", "-@writes"])), Some("-@writes"));
+    }
+
+    #[test]
+    fn daily_quotas() {
+        let store = SecurityStore::empty(None);
+        let mut bob = user("bob");
+        bob.rules.extend(["quota:cmds=2", "quota:bytes=100"].map(String::from));
+        assert_eq!((bob.quota("cmds"), bob.quota("bytes"), user("x").quota("cmds")), (Some(2), Some(100), None));
+
+        assert!(store.charge_on(&bob, 10, 1).is_ok());
+        assert!(store.charge_on(&bob, 95, 1).unwrap_err().contains("100 bytes"));
+        assert!(store.charge_on(&bob, 10, 1).is_ok());
+        assert!(store.charge_on(&bob, 10, 1).unwrap_err().contains("2 commands"));
+        assert_eq!(store.usage.get("bob").map(|u| (u.commands, u.bytes)), Some((2, 20)));
+        // A new day starts over
+        assert!(store.charge_on(&bob, 10, 2).is_ok());
+        assert_eq!(store.usage.get("bob").map(|u| (u.day, u.commands)), Some((2, 1)));
+    }
+
+    #[test]
+    fn only_unknown_usernames_reach_the_provider() {
+        struct Counting(std::sync::atomic::AtomicUsize);
+        impl AuthProvider for Counting {
+            fn name(&self) -> &'static str {
+                "counting"
+            }
+            fn verify(&self, username: &str, password: &str) -> anyhow::Result<Option<User>> {
+                self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                Ok((password == "token").then(|| User { username: username.to_string(), password: String::new(), rules: Vec::new() }))
+            }
+        }
+        let provider = Arc::new(Counting(Default::default()));
+        let calls = || provider.0.load(std::sync::atomic::Ordering::SeqCst);
+        let store = SecurityStore::empty(None);
+        store.users.insert("local".to_string(), user("local"));
+        store.set_provider(provider.clone());
+
+        // A local user's wrong password stays here
+        assert!(store.login("local", "wrong").is_none());
+        assert_eq!(calls(), 0);
+
+        assert!(store.login("ext", "token").is_some());
+        assert!(store.login("ext", "bad").is_none());
+        assert_eq!(calls(), 2);
+        // Refused without asking again until the retry delay passed
+        assert!(store.login("ext", "token").is_none());
+        assert_eq!(calls(), 2);
+        store.provider_failures.insert("ext".to_string(), Instant::now() - PROVIDER_RETRY_AFTER);
+        assert!(store.login("ext", "token").is_some());
+        assert_eq!(calls(), 3);
+    }
+
+    #[test]
+    fn users_survive_a_restart() {
+        let path = std::env::temp_dir().join(format!("toridb_users_{}.json", std::process::id()));
+        std::fs::write(&path, serde_json::to_vec(&vec![user("default")]).unwrap()).unwrap();
+
+        let store = SecurityStore::persistent(&path);
+        assert!(store.restored());
+        store.set_user(user("alice"));
+        store.set_user(user("bob"));
+        store.delete_user("bob");
+
+        let reloaded = SecurityStore::persistent(&path);
+        let mut names = reloaded.list_users();
+        names.sort();
+        assert_eq!(names, ["alice", "default"]);
+        assert_eq!(reloaded.get_user("alice").unwrap().rules, ["+get"]);
+        let _ = std::fs::remove_file(&path);
+    }
 }
@@ -1,66 +1,218 @@
+//! # Snapshots
+//!
+//! A snapshot is the whole state of a database: the flexible keyspace (with
+//! expiries, sorted sets and Top-K sketches) and the tables. `SAVE` writes it
+//! as JSON. The binary form, used to ship a full sync to a replica, is:
+//!
+//! ```text
+//! TORISNAP <version: u8> <crc32: u32 BE> <length: u64 BE> <deflate(JSON)>
+//! ```
+//!
+//! where the CRC32 and the length are those of the uncompressed JSON, so a
+//! reader can tell a damaged transfer from a good one. [`SnapshotManager::load`]
+//! accepts both forms.
+
+use flate2::Compression;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
 use std::collections::HashMap;
 use std::fs::File;
-use std::io::{self, BufReader, BufWriter};
+use std::io::{self, BufReader, BufWriter, Read, Write};
 use std::sync::Arc;
+use crate::core::flexible::KeyspaceSnapshot;
 use crate::core::memory::DatabaseEngine;
-use crate::core::structured::Table;
+use crate::core::persistence::{AofLogger, AofPosition};
+use crate::core::structured::{Table, TableIndexes};
+
+/// First bytes of a binary snapshot.
+const MAGIC: &[u8; 8] = b"TORISNAP";
+const VERSION: u8 = 1;
+/// Magic, version, checksum and length.
+const HEADER_LEN: usize = MAGIC.len() + 1 + 4 + 8;
 
 #[derive(Serialize, Deserialize)]
 pub struct SnapshotData {
-    pub flexible_data: HashMap<String, Value>,
+    #[serde(flatten)]
+    pub keyspace: KeyspaceSnapshot,
     pub structured_data: HashMap<String, Table>,
+    /// Named and vector indexes of each table that has some; snapshots
+    /// written before this was recorded only get their constraint indexes back
+    #[serde(default)]
+    pub indexes: HashMap<String, TableIndexes>,
     pub timestamp: u64,
+    /// Position of the AOF matching this state, for `SAVE` snapshots:
+    /// recovery replays the AOF from there
+    #[serde(default)]
+    pub aof: Option<AofPosition>,
 }
 
 pub struct SnapshotManager;
 
 impl SnapshotManager {
-    pub fn save(engine: &Arc<DatabaseEngine>, path: &str) -> io::Result<()> {
-        let flexible = engine.flexible.export();
-        let structured = engine.structured.export();
-        
-        let snapshot = SnapshotData {
-            flexible_data: flexible,
-            structured_data: structured,
+    /// The current state of `engine`.
+    pub fn capture(engine: &DatabaseEngine) -> SnapshotData {
+        SnapshotData {
+            keyspace: engine.flexible.export(),
+            structured_data: engine.structured.export(),
+            indexes: engine.structured.export_indexes(),
             timestamp: std::time::SystemTime::now()
                 .duration_since(std::time::UNIX_EPOCH)
                 .unwrap()
                 .as_secs(),
-        };
+            aof: None,
+        }
+    }
 
-        let file = File::create(path)?;
-        let writer = BufWriter::new(file);
-        serde_json::to_writer_pretty(writer, &snapshot)?;
-        
-        Ok(())
+    /// Writes the current state to `path`, with the position of `aof` it
+    /// matches. Writes wait while both are taken.
+    pub fn save(engine: &Arc<DatabaseEngine>, aof: &AofLogger, path: &str) -> io::Result<()> {
+        let snapshot = {
+            let _guard = engine.locks.lock_all();
+            let mut snapshot = Self::capture(engine);
+            // Without one the snapshot is only loaded when there is no AOF
+            snapshot.aof = aof.mark().ok();
+            snapshot
+        };
+        // Written aside and renamed, so a crash never leaves half a snapshot
+        let tmp = format!("{}.tmp", path);
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        serde_json::to_writer_pretty(&mut writer, &snapshot)?;
+        writer.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        std::fs::rename(&tmp, path)
     }
 
+    /// Reads a snapshot file, JSON or binary.
     pub fn load(path: &str) -> io::Result<SnapshotData> {
-        let file = File::open(path)?;
-        let reader = BufReader::new(file);
-        let snapshot: SnapshotData = serde_json::from_reader(reader)?;
-        Ok(snapshot)
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        if data.starts_with(MAGIC) {
+            return Self::decode(&data);
+        }
+        serde_json::from_slice(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// The binary form of `snapshot`.
+    pub fn encode(snapshot: &SnapshotData) -> io::Result<Vec<u8>> {
+        let json = serde_json::to_vec(snapshot)?;
+        frame(MAGIC.to_vec(), &json)
+    }
+
+    /// Reads the binary form, failing on a bad header or checksum.
+    pub fn decode(data: &[u8]) -> io::Result<SnapshotData> {
+        if data.len() < HEADER_LEN || !data.starts_with(MAGIC) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a binary snapshot"));
+        }
+        let json = unframe(&data[MAGIC.len()..], "snapshot")?;
+        serde_json::from_slice(&json).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
     }
 
     pub fn to_string(engine: &Arc<DatabaseEngine>) -> io::Result<String> {
-        let flexible = engine.flexible.export();
-        let structured = engine.structured.export();
-        
-        let snapshot = SnapshotData {
-            flexible_data: flexible,
-            structured_data: structured,
-            timestamp: std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_secs(),
-        };
-        
-        serde_json::to_string(&snapshot).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        serde_json::to_string(&Self::capture(engine)).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
     }
 
     pub fn from_string(data: &str) -> io::Result<SnapshotData> {
         serde_json::from_str(data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
     }
 }
+
+/// Appends `json` to `out` as the binary form after its magic: version,
+/// checksum and length of the JSON, then the JSON compressed.
+pub(crate) fn frame(mut out: Vec<u8>, json: &[u8]) -> io::Result<Vec<u8>> {
+    out.reserve(HEADER_LEN + json.len() / 4);
+    out.push(VERSION);
+    out.extend_from_slice(&crc32fast::hash(json).to_be_bytes());
+    out.extend_from_slice(&(json.len() as u64).to_be_bytes());
+    let mut encoder = DeflateEncoder::new(out, Compression::fast());
+    encoder.write_all(json)?;
+    encoder.finish()
+}
+
+/// The JSON of a [`frame`], failing on a bad version, length or checksum;
+/// `what` names the data in the errors.
+pub(crate) fn unframe(data: &[u8], what: &str) -> io::Result<Vec<u8>> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    let body = HEADER_LEN - MAGIC.len();
+    if data.len() < body {
+        return Err(invalid(format!("{} too short", what)));
+    }
+    let version = data[0];
+    if version != VERSION {
+        return Err(invalid(format!("unsupported {} version {}", what, version)));
+    }
+    let crc = u32::from_be_bytes(data[1..5].try_into().unwrap());
+    let len = u64::from_be_bytes(data[5..body].try_into().unwrap());
+
+    let mut json = Vec::new();
+    DeflateDecoder::new(&data[body..]).take(len).read_to_end(&mut json)?;
+    if json.len() as u64 != len {
+        return Err(invalid(format!("{} truncated: {} of {} bytes", what, json.len(), len)));
+    }
+    if crc32fast::hash(&json) != crc {
+        return Err(invalid(format!("{} checksum mismatch", what)));
+    }
+    Ok(json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn binary_snapshots_round_trip_and_detect_damage() {
+        let engine = DatabaseEngine::ephemeral("snap".to_string());
+        engine.flexible.set("plain".to_string(), serde_json::json!("v"));
+        engine.flexible.set("temp".to_string(), serde_json::json!(1));
+        let expire_at = crate::core::backend::now_unix_ms() + 3_600_000;
+        engine.flexible.expire_at("temp", expire_at);
+        engine.flexible.zadd("board", vec![(2.0, "b".to_string()), (1.0, "a".to_string())], Default::default());
+
+        let bytes = SnapshotManager::encode(&SnapshotManager::capture(&engine)).unwrap();
+        assert!(bytes.starts_with(MAGIC));
+        let copy = DatabaseEngine::ephemeral("copy".to_string());
+        copy.restore_state(SnapshotManager::decode(&bytes).unwrap());
+        // Expiries go through an Instant, so allow for rounding
+        assert!(copy.flexible.expire_time_ms("temp").is_some_and(|ms| ms.abs_diff(expire_at) <= 2));
+        assert_eq!(copy.flexible.expire_time_ms("plain"), None);
+        assert_eq!(copy.flexible.key_count(), 3);
+
+        // Flipping a bit of the compressed body, or cutting it short, is caught
+        let mut damaged = bytes.clone();
+        let last = damaged.len() - 3;
+        damaged[last] ^= 0x10;
+        assert!(SnapshotManager::decode(&damaged).is_err());
+        assert!(SnapshotManager::decode(&bytes[..bytes.len() - 8]).is_err());
+
+        // JSON snapshots from before expiries were recorded still load
+        let old: SnapshotData = serde_json::from_str(r#"{"flexible_data":{"k":"v"},"structured_data":{},"timestamp":0}"#).unwrap();
+        assert_eq!(old.keyspace.values.len(), 1);
+        assert!(old.keyspace.expirations.is_empty());
+        assert!(old.indexes.is_empty());
+    }
+
+    #[test]
+    fn snapshots_keep_secondary_indexes() {
+        use crate::core::hnsw::Metric;
+        use crate::core::structured::{Column, DataType};
+        let engine = DatabaseEngine::ephemeral("snap".to_string());
+        let column = |name: &str, data_type| Column {
+            name: name.into(), data_type, is_primary_key: name == "id", references: None,
+            fk_action: Default::default(), not_null: false, unique: false, default: None,
+        };
+        let store = &engine.structured;
+        store.create_table("docs".into(), vec![column("id", DataType::Integer), column("data", DataType::Json), column("emb", DataType::Vector(None))]).unwrap();
+        store.insert("docs", vec![Some("1".into()), Some(r#"{"city": "Rome"}"#.into()), Some("[1, 0]".into())]).unwrap();
+        store.create_index("by_city", "docs", "data->city").unwrap();
+        store.create_vector_index("by_emb", "docs", "emb", 2, Metric::L2).unwrap();
+        let indexes = |engine: &DatabaseEngine| engine.structured.list_indexes(Some("docs")).unwrap()
+            .into_iter().map(|i| (i.name, i.column, i.vector)).collect::<Vec<_>>();
+        assert_eq!(indexes(&engine).len(), 3);
+
+        let bytes = SnapshotManager::encode(&SnapshotManager::capture(&engine)).unwrap();
+        let copy = DatabaseEngine::ephemeral("copy".to_string());
+        copy.restore_state(SnapshotManager::decode(&bytes).unwrap());
+        assert_eq!(indexes(&copy), indexes(&engine));
+        assert!(copy.structured.check_table("docs").unwrap().is_empty());
+    }
+}
@@ -0,0 +1,193 @@
+//! # Streams
+//!
+//! An append-only log of entries, each a list of field-value pairs under an
+//! ID of the form `<ms>-<seq>`: the unix time in milliseconds it was added
+//! at, and a sequence number telling apart entries of the same millisecond.
+//! IDs only ever grow, so readers can pick up where they left off by asking
+//! for the entries after the last ID they saw (`XREAD`).
+//!
+//! Entries are kept in a `BTreeMap`, so ranges and reads after an ID are
+//! logarithmic in the length of the stream.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Field-value pairs of an entry, in the order they were added.
+pub type Fields = Vec<(String, String)>;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    pub const MIN: StreamId = StreamId { ms: 0, seq: 0 };
+    pub const MAX: StreamId = StreamId { ms: u64::MAX, seq: u64::MAX };
+
+    /// Parses `<ms>-<seq>`, or a bare `<ms>` with the sequence `missing_seq`
+    /// (0 for the start of a range, the largest for its end).
+    pub fn parse(s: &str, missing_seq: u64) -> Result<Self, String> {
+        let invalid = || format!("Invalid stream ID '{}'", s);
+        let (ms, seq) = match s.split_once('-') {
+            Some((ms, seq)) => (ms.parse().map_err(|_| invalid())?, seq.parse().map_err(|_| invalid())?),
+            None => (s.parse().map_err(|_| invalid())?, missing_seq),
+        };
+        Ok(Self { ms, seq })
+    }
+
+    /// The ID right after this one, if any.
+    fn next(self) -> Option<Self> {
+        match self.seq.checked_add(1) {
+            Some(seq) => Some(Self { ms: self.ms, seq }),
+            None => self.ms.checked_add(1).map(|ms| Self { ms, seq: 0 }),
+        }
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+// As their text form, so they can key a JSON object in snapshots
+impl Serialize for StreamId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for StreamId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        Self::parse(&s, 0).map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Stream {
+    entries: BTreeMap<StreamId, Fields>,
+    /// Highest ID added so far
+    last_id: StreamId,
+}
+
+impl Stream {
+    /// Appends an entry. `id` is `*` to take the current time, `<ms>-*` for
+    /// the next sequence number of that millisecond, or an explicit ID, which
+    /// must be above every ID in the stream.
+    pub fn add(&mut self, id: &str, fields: Fields, now_ms: u64) -> Result<StreamId, String> {
+        const TOO_SMALL: &str = "The ID specified in XADD is equal or smaller than the target stream top item";
+        let id = match id.strip_suffix("-*") {
+            _ if id == "*" && now_ms > self.last_id.ms => StreamId { ms: now_ms, seq: 0 },
+            // Same millisecond as the last entry, or the clock went back
+            _ if id == "*" => self.last_id.next().ok_or("The stream has exhausted the last possible ID")?,
+            Some(ms) => {
+                let ms: u64 = ms.parse().map_err(|_| format!("Invalid stream ID '{}'", id))?;
+                if ms > self.last_id.ms {
+                    StreamId { ms, seq: 0 }
+                } else if ms == self.last_id.ms {
+                    // Also gives 0-1 on an empty stream, 0-0 not being valid
+                    let seq = self.last_id.seq.checked_add(1).ok_or(TOO_SMALL)?;
+                    StreamId { ms, seq }
+                } else {
+                    return Err(TOO_SMALL.to_string());
+                }
+            }
+            None => StreamId::parse(id, 0)?,
+        };
+        if id == StreamId::MIN {
+            return Err("The ID specified in XADD must be greater than 0-0".to_string());
+        }
+        if id <= self.last_id {
+            return Err(TOO_SMALL.to_string());
+        }
+        self.entries.insert(id, fields);
+        self.last_id = id;
+        Ok(id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn last_id(&self) -> StreamId {
+        self.last_id
+    }
+
+    /// Entries with IDs from `start` to `end`, both included, at most `count`.
+    pub fn range(&self, start: StreamId, end: StreamId, count: Option<usize>) -> Vec<(StreamId, Fields)> {
+        if start > end {
+            return Vec::new();
+        }
+        self.entries.range(start..=end).take(count.unwrap_or(usize::MAX)).map(|(id, fields)| (*id, fields.clone())).collect()
+    }
+
+    /// Entries with IDs above `after`, at most `count` (XREAD).
+    pub fn read_after(&self, after: StreamId, count: Option<usize>) -> Vec<(StreamId, Fields)> {
+        match after.next() {
+            Some(start) => self.range(start, StreamId::MAX, count),
+            None => Vec::new(),
+        }
+    }
+
+    /// Every entry, oldest first.
+    pub fn entries(&self) -> impl Iterator<Item = (&StreamId, &Fields)> {
+        self.entries.iter()
+    }
+
+    /// Rough bytes held by the stream.
+    pub fn memory_bytes(&self) -> usize {
+        std::mem::size_of::<Self>() + self.entries.values().map(|fields| {
+            std::mem::size_of::<(StreamId, Fields)>() + fields.iter().map(|(f, v)| f.len() + v.len() + std::mem::size_of::<(String, String)>()).sum::<usize>()
+        }).sum::<usize>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(pairs: &[(&str, &str)]) -> Fields {
+        pairs.iter().map(|(f, v)| (f.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn ids_only_grow() {
+        let mut stream = Stream::default();
+        assert_eq!(stream.add("*", fields(&[("a", "1")]), 100).unwrap(), StreamId { ms: 100, seq: 0 });
+        // Same millisecond, or a clock gone backwards: next sequence number
+        assert_eq!(stream.add("*", fields(&[("a", "2")]), 100).unwrap(), StreamId { ms: 100, seq: 1 });
+        assert_eq!(stream.add("*", fields(&[("a", "3")]), 50).unwrap(), StreamId { ms: 100, seq: 2 });
+        assert_eq!(stream.add("100-*", fields(&[]), 0).unwrap(), StreamId { ms: 100, seq: 3 });
+        assert_eq!(stream.add("200-5", fields(&[]), 0).unwrap(), StreamId { ms: 200, seq: 5 });
+        assert!(stream.add("200-5", fields(&[]), 0).is_err());
+        assert!(stream.add("150-*", fields(&[]), 0).is_err());
+        assert!(Stream::default().add("0-0", fields(&[]), 0).is_err());
+        assert_eq!(Stream::default().add("0-*", fields(&[]), 0).unwrap(), StreamId { ms: 0, seq: 1 });
+        assert_eq!(stream.len(), 5);
+        assert_eq!(stream.last_id().to_string(), "200-5");
+    }
+
+    #[test]
+    fn ranges_and_reads() {
+        let mut stream = Stream::default();
+        for (i, ms) in [10, 20, 20, 30].into_iter().enumerate() {
+            stream.add(&format!("{}-*", ms), fields(&[("n", &i.to_string())]), 0).unwrap();
+        }
+        let ids = |entries: Vec<(StreamId, Fields)>| entries.into_iter().map(|(id, _)| id.to_string()).collect::<Vec<_>>();
+        assert_eq!(ids(stream.range(StreamId::parse("20", 0).unwrap(), StreamId::parse("20", u64::MAX).unwrap(), None)), ["20-0", "20-1"]);
+        assert_eq!(ids(stream.range(StreamId::MIN, StreamId::MAX, Some(2))), ["10-0", "20-0"]);
+        assert_eq!(ids(stream.read_after(StreamId::parse("20-0", 0).unwrap(), None)), ["20-1", "30-0"]);
+        assert!(stream.read_after(stream.last_id(), None).is_empty());
+        assert!(StreamId::parse("x-1", 0).is_err());
+
+        let json = serde_json::to_string(&stream).unwrap();
+        assert_eq!(serde_json::from_str::<Stream>(&json).unwrap(), stream);
+    }
+}
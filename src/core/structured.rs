@@ -10,15 +10,28 @@
 //! ## Indexing
 //! - **Standard Indexes**: Uses `DashMap` for equality lookups (O(1)).
 //! - **Range Indexes**: Uses `BTreeMap` protected by `RwLock` for range queries and sorting (O(log n)).
+//! - **Vector Indexes**: An HNSW graph per vector column for approximate
+//!   nearest-neighbour `SEARCH` (see [`hnsw`]).
 
 use dashmap::DashMap;
 use serde::{Deserialize, Serialize};
-use std::sync::{Arc, RwLock};
-use std::collections::{BTreeMap, HashMap};
+use std::cell::Cell;
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::ops::Bound::{Included, Excluded, Unbounded};
 use anyhow::{Result, anyhow};
-use crate::query::{Operator, Filter, Selector, AlterOp, JoinClause};
+use crate::query::{between_bounds, Operator, Filter, Selector, AlterOp, JoinClause, Expr, Projection};
+use crate::query::planner::{self, AccessPath, IndexCatalog, JoinStep, JoinStrategy, QueryPlan};
+use crate::query::{eval, like};
+use crate::query::advisor::{IndexAdvisor, Suggestion};
 use crate::core::types::UnifiedValue;
+use crate::core::hnsw::{self, Hnsw, Metric};
+use crate::core::datetime;
+use crate::net::parser::quote_string;
+use crate::core::queries;
+use crate::core::digest;
+use crate::core::profile;
+use std::time::Instant;
 
 /// Supported Data Types for SQL Columns
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -30,16 +43,105 @@ pub enum DataType {
     DateTime, // Stored as ISO8601 string
     Blob,     // Stored as Base64 string
     Json,     // Stored as UnifiedValue::Object or Array
-    Vector,   // Stored as UnifiedValue::Vector
+    Vector(Option<usize>), // Stored as UnifiedValue::Vector, with the declared dimension if any
+}
+
+impl DataType {
+    /// Type name as written in column definitions (`int`, `string`,
+    /// `vector(768)`, ...).
+    pub fn keyword(&self) -> String {
+        match self {
+            DataType::Integer => "int".into(),
+            DataType::String => "string".into(),
+            DataType::Boolean => "bool".into(),
+            DataType::Float => "float".into(),
+            DataType::DateTime => "datetime".into(),
+            DataType::Blob => "blob".into(),
+            DataType::Json => "json".into(),
+            DataType::Vector(None) => "vector".into(),
+            DataType::Vector(Some(dims)) => format!("vector({})", dims),
+        }
+    }
+
+    /// Reads a type name from a column definition. Unknown names are
+    /// strings; `vector(N)` declares the dimension of every vector stored.
+    pub fn parse(type_name: &str) -> Result<DataType> {
+        let upper = type_name.to_uppercase();
+        if let Some(dims) = upper.strip_prefix("VECTOR(").and_then(|rest| rest.strip_suffix(')')) {
+            let dims: usize = dims.trim().parse().map_err(|_| anyhow!("Invalid vector dimension '{}'", dims))?;
+            if !(1..=hnsw::MAX_DIMS).contains(&dims) {
+                return Err(anyhow!("Vector dimension must be between 1 and {}", hnsw::MAX_DIMS));
+            }
+            return Ok(DataType::Vector(Some(dims)));
+        }
+        Ok(match upper.as_str() {
+            "INT" | "INTEGER" => DataType::Integer,
+            "BOOL" | "BOOLEAN" => DataType::Boolean,
+            "FLOAT" | "DOUBLE" => DataType::Float,
+            "DATETIME" | "TIMESTAMP" => DataType::DateTime,
+            "BLOB" | "BYTES" => DataType::Blob,
+            "JSON" => DataType::Json,
+            "VECTOR" => DataType::Vector(None),
+            _ => DataType::String,
+        })
+    }
+}
+
+/// Reads a column type, including the bare `"Vector"` written by snapshots
+/// from before vector columns had a dimension.
+fn deserialize_data_type<'de, D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<DataType, D::Error> {
+    let value = serde_json::Value::deserialize(deserializer)?;
+    if value == "Vector" {
+        return Ok(DataType::Vector(None));
+    }
+    serde_json::from_value(value).map_err(serde::de::Error::custom)
+}
+
+/// What happens to referencing rows when the key they point at is deleted
+/// or changed.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum FkAction {
+    /// Not enforced: referencing rows keep the old value
+    #[default]
+    NoAction,
+    /// The delete or update fails while rows still reference the key
+    Restrict,
+    /// Referencing rows are deleted, or follow the new key
+    Cascade,
+    /// Referencing columns are set to NULL
+    SetNull,
+}
+
+impl FkAction {
+    /// DDL modifier (`cascade`, ...), `None` for the default.
+    pub fn keyword(&self) -> Option<&'static str> {
+        match self {
+            FkAction::NoAction => None,
+            FkAction::Restrict => Some("restrict"),
+            FkAction::Cascade => Some("cascade"),
+            FkAction::SetNull => Some("setnull"),
+        }
+    }
 }
 
 /// Represents a single column definition in a table.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Column {
     pub name: String,
+    #[serde(deserialize_with = "deserialize_data_type")]
     pub data_type: DataType,
     pub is_primary_key: bool,
     pub references: Option<(String, String)>, // (table, column)
+    #[serde(default)]
+    pub fk_action: FkAction,
+    #[serde(default)]
+    pub not_null: bool,
+    /// Enforced through an automatically created hash index
+    #[serde(default)]
+    pub unique: bool,
+    /// Literal stored when INSERT leaves the column out
+    #[serde(default)]
+    pub default: Option<String>,
 }
 
 /// In-memory representation of an SQL Table.
@@ -52,15 +154,264 @@ pub struct Table {
     pub rows: BTreeMap<u64, Vec<UnifiedValue>>, 
 }
 
+/// A nearest-neighbour query for [`StructuredStore::vector_search`].
+#[derive(Clone, Copy)]
+pub struct VectorQuery<'a> {
+    pub vector: &'a [f64],
+    pub limit: usize,
+    /// Defaults to the metric of the column's vector index, else cosine
+    pub metric: Option<Metric>,
+    /// Lowest similarity, or for L2 the largest distance, to keep
+    pub threshold: Option<f64>,
+    /// Only rows matching it are ranked
+    pub filter: Option<&'a Filter>,
+}
+
+/// Rows produced by a SELECT, with one name per result column.
+#[derive(Debug, Clone)]
+pub struct ResultSet {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<UnifiedValue>>,
+}
+
+impl ResultSet {
+    /// Type of each column, taken from its first non-null value
+    /// (`null` when the column has none).
+    pub fn column_types(&self) -> Vec<&'static str> {
+        (0..self.columns.len())
+            .map(|i| {
+                self.rows.iter()
+                    .filter_map(|row| row.get(i))
+                    .find(|v| !matches!(v, UnifiedValue::Null))
+                    .map_or("null", UnifiedValue::type_name)
+            })
+            .collect()
+    }
+}
+
+/// Row deletions and cell updates of one DELETE or UPDATE, including the
+/// ones caused by foreign key actions.
+#[derive(Default)]
+struct WritePlan {
+    /// table -> row ids
+    deletes: BTreeMap<String, std::collections::BTreeSet<u64>>,
+    /// table -> row id -> [(column index, new value)]
+    sets: BTreeMap<String, BTreeMap<u64, Vec<(usize, UnifiedValue)>>>,
+    /// (table, column index) -> value -> rows the plan sets to it
+    assigned: HashMap<(String, usize), HashMap<UnifiedValue, HashSet<u64>>>,
+}
+
+impl WritePlan {
+    fn set(&mut self, table: &str, id: u64, col_idx: usize, val: UnifiedValue) {
+        let previous = self.planned(table, id, col_idx).cloned();
+        let assigned = self.assigned.entry((table.to_string(), col_idx)).or_default();
+        if let Some(ids) = previous.and_then(|previous| assigned.get_mut(&previous)) {
+            ids.remove(&id);
+        }
+        assigned.entry(val.clone()).or_default().insert(id);
+        self.sets.entry(table.to_string()).or_default().entry(id).or_default().push((col_idx, val));
+    }
+
+    /// The value the plan gives a cell, if it changes it.
+    fn planned(&self, table: &str, id: u64, col_idx: usize) -> Option<&UnifiedValue> {
+        let cells = self.sets.get(table)?.get(&id)?;
+        cells.iter().rev().find(|(c, _)| *c == col_idx).map(|(_, v)| v)
+    }
+
+    fn deletes(&self, table: &str, id: u64) -> bool {
+        self.deletes.get(table).is_some_and(|ids| ids.contains(&id))
+    }
+}
+
+/// Write locks on the tables one DELETE or UPDATE can change, by name.
+type TableGuards<'a> = BTreeMap<String, RwLockWriteGuard<'a, Table>>;
+
+/// Rows by value of a column without a hash index, built by one scan when a
+/// write first looks a value up in it.
+type ValueLookups = HashMap<(String, usize), HashMap<UnifiedValue, Vec<u64>>>;
+
+/// Referencing column: `(table, column index, action)`
+type FkRef = (String, usize, FkAction);
+
+/// Output of an index join with the number of probes that hit
+type IndexJoin = (Vec<HashMap<String, UnifiedValue>>, usize);
+
+/// A value leaving `table.column`: deleted with its row (`new: None`) or
+/// replaced by `new`.
+struct KeyChange {
+    table: String,
+    column: String,
+    old: UnifiedValue,
+    new: Option<UnifiedValue>,
+}
+
+impl KeyChange {
+    fn row_removed(table: &str, columns: &[Column], row: &[UnifiedValue]) -> Vec<KeyChange> {
+        columns.iter().zip(row)
+            .filter(|(_, val)| !matches!(val, UnifiedValue::Null))
+            .map(|(col, val)| KeyChange { table: table.to_string(), column: col.name.clone(), old: val.clone(), new: None })
+            .collect()
+    }
+}
+
+/// A named index, as listed by SHOW INDEXES.
+#[derive(Debug, Clone)]
+pub struct IndexInfo {
+    pub table: String,
+    pub name: String,
+    /// Column or JSON path
+    pub column: String,
+    /// "primary key" or "unique" when CREATE TABLE built it for a constraint
+    pub constraint: Option<&'static str>,
+    /// Dimensions and metric of a vector index
+    pub vector: Option<(usize, Metric)>,
+}
+
+impl IndexInfo {
+    /// The statement that creates the index.
+    pub fn statement(&self) -> String {
+        match self.vector {
+            Some((dims, metric)) => format!("CREATE VECTOR INDEX {} ON {}({}) DIMS {} METRIC {}", self.name, self.table, self.column, dims, metric),
+            None => format!("CREATE INDEX {} ON {}({})", self.name, self.table, self.column),
+        }
+    }
+}
+
+/// An HNSW index over one vector column (`CREATE VECTOR INDEX`).
+struct VectorIndex {
+    column: String,
+    graph: RwLock<Hnsw>,
+}
+
+/// A table as listed by SHOW TABLES.
+#[derive(Debug, Clone)]
+pub struct TableInfo {
+    pub name: String,
+    pub columns: usize,
+    pub rows: usize,
+}
+
+/// Tables as they were before a transaction, put back if it fails. Until it
+/// is released or rolled back, queries read these copies instead of the tables.
+pub struct Savepoint {
+    /// Table with its index catalog, or `None` for a table that did not exist
+    tables: Vec<(String, Option<SavedTable>)>,
+}
+
+struct SavedTable {
+    table: SharedTable,
+    indexes: TableIndexes,
+}
+
+/// The named indexes of a table, enough to rebuild them over its rows.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TableIndexes {
+    /// Index name -> column or JSON path
+    pub catalog: BTreeMap<String, String>,
+    /// Vector indexes: name, column, dimensions and metric
+    pub vectors: Vec<(String, String, usize, Metric)>,
+}
+
+type SharedTable = Arc<RwLock<Table>>;
+
+thread_local! {
+    /// Set while this thread runs a transaction, whose own queries read the
+    /// tables it is writing rather than the copies other queries see.
+    static IN_TRANSACTION: Cell<bool> = const { Cell::new(false) };
+}
+
+/// The tables as one query sees them, see [`StructuredStore::read_view`].
+pub struct ReadView<'a> {
+    store: StructuredStore,
+    _gate: Option<RwLockReadGuard<'a, ()>>,
+}
+
+impl ReadView<'_> {
+    /// The view narrowed to `tables`, with one more, read-only table (see
+    /// [`StructuredStore::with_table`]).
+    pub fn with_table(mut self, table: Table, tables: &[&str]) -> Self {
+        self.store = self.store.with_table(table, tables);
+        self
+    }
+}
+
+impl std::ops::Deref for ReadView<'_> {
+    type Target = StructuredStore;
+
+    fn deref(&self) -> &StructuredStore {
+        &self.store
+    }
+}
+
+/// Virtual table over the flexible keyspace, see [`StructuredStore::with_table`].
+pub const KV_TABLE: &str = "__kv__";
+
+/// How writes read the key of a hash / range index from a row.
+enum KeySource {
+    Column(usize),
+    /// `->` path into the JSON column at this position
+    JsonPath(usize, String),
+    /// A path into a column that is not JSON: the key is always NULL
+    Null,
+}
+
+impl KeySource {
+    fn of(columns: &[Column], expr: &str) -> Option<Self> {
+        let Some(arrow_pos) = expr.find("->") else {
+            return columns.iter().position(|c| c.name == expr).map(KeySource::Column);
+        };
+        let col_idx = columns.iter().position(|c| c.name == expr[..arrow_pos])?;
+        Some(match columns[col_idx].data_type {
+            DataType::Json => KeySource::JsonPath(col_idx, expr[arrow_pos..].to_string()),
+            _ => KeySource::Null,
+        })
+    }
+
+    /// The column the key comes from, if any.
+    fn column(&self) -> Option<usize> {
+        match self {
+            KeySource::Column(idx) | KeySource::JsonPath(idx, _) => Some(*idx),
+            KeySource::Null => None,
+        }
+    }
+
+    fn key(&self, row: &[UnifiedValue]) -> UnifiedValue {
+        match self {
+            KeySource::Column(idx) => row[*idx].clone(),
+            KeySource::JsonPath(idx, path) => StructuredStore::json_path_value(&row[*idx], path).unwrap_or(UnifiedValue::Null),
+            KeySource::Null => UnifiedValue::Null,
+        }
+    }
+}
+
 /// The core registry for relational data and indexing.
 #[derive(Clone)]
 pub struct StructuredStore {
     /// Registry of tables: name -> table_instance (thread-safe)
-    tables: Arc<DashMap<String, RwLock<Table>>>,
+    tables: Arc<DashMap<String, Arc<RwLock<Table>>>>,
     /// Equality indexes: table_name -> col_name -> value -> row_ids
     indexes: Arc<DashMap<String, DashMap<String, DashMap<UnifiedValue, Vec<u64>>>>>,
     /// Sorted/Range indexes: table_name -> col_name -> BTreeMap<value, row_ids>
     range_indexes: Arc<DashMap<String, DashMap<String, RwLock<BTreeMap<UnifiedValue, Vec<u64>>>>>>,
+    /// Index catalog: table_name -> index name -> column (or JSON path)
+    index_names: Arc<DashMap<String, BTreeMap<String, String>>>,
+    /// Vector indexes: table_name -> index name -> HNSW graph of a column
+    vector_indexes: Arc<DashMap<String, BTreeMap<String, Arc<VectorIndex>>>>,
+    /// Full-scan statistics behind ADVISOR SUGGEST
+    advisor: Arc<IndexAdvisor>,
+    /// Tables a running transaction writes, as queries see them meanwhile:
+    /// the copy saved when it started, or `None` for a table it creates
+    published: Arc<Mutex<HashMap<String, Option<SharedTable>>>>,
+    /// Held by each read view while it is used; a transaction takes it to
+    /// publish its copies, so no query that reads the live tables overlaps it
+    read_gate: Arc<RwLock<()>>,
+    /// In a read view: tables read from a transaction's copy, which have no indexes
+    unindexed: Arc<HashSet<String>>,
+    /// Tables each table references through foreign keys, kept apart so
+    /// finding them takes no table lock
+    references: Arc<DashMap<String, Vec<String>>>,
+    /// Cached [`index_layout`](Self::index_layout) of each table
+    index_layouts: Arc<DashMap<String, Arc<Vec<(String, KeySource)>>>>,
 }
 
 impl StructuredStore {
@@ -79,96 +430,222 @@ impl StructuredStore {
 
 
 
-    // For AOF Rewrite
-    pub fn dump_commands(&self) -> Vec<String> {
-        let mut commands = Vec::new();
-        
-        // 1. Tables and Data
-        for kv in self.tables.iter() {
-            if let Ok(table) = kv.value().read() {
-                // CREATE TABLE
-                let cols_def = table.columns.iter()
-                    .map(|c| {
-                        let type_str = match c.data_type {
-                            DataType::Integer => "int",
-                            DataType::String => "string",
-                            DataType::Boolean => "bool",
-                            DataType::Float => "float",
-                            DataType::DateTime => "datetime",
-                            DataType::Blob => "blob",
-                            DataType::Json => "json",
-                            DataType::Vector => "vector",
-                        };
-                        let base = if c.is_primary_key {
-                            format!("{}:{}:pk", c.name, type_str)
-                        } else {
-                            format!("{}:{}", c.name, type_str)
-                        };
-                        
-                        if let Some((ref t, ref col)) = c.references {
-                            format!("{}:fk({}.{})", base, t, col)
-                        } else {
-                            base
-                        }
-                    })
-                    .collect::<Vec<_>>()
-                    .join(" ");
-                
-                commands.push(format!("CREATE TABLE {} {}", table.name, cols_def));
-
-                // INSERTs
-                for (_, row) in &table.rows {
-                    let vals = row.iter()
-                        .map(|v| match v {
-                            UnifiedValue::String(s) => format!("\"{}\"", s), // Quote strings
-                            UnifiedValue::DateTime(i) => format!("{}", i),
-                            UnifiedValue::Blob(b) => format!("\"{}\"", b),
-                            UnifiedValue::Object(_) | UnifiedValue::Array(_) => {
-                                // Serialize JSON back to string
-                                serde_json::to_string(v).unwrap_or_else(|_| "{}".to_string())
-                            },
-                            _ => format!("{}", v), // Display impl handles others
-                        }) 
-                        .collect::<Vec<_>>()
-                        .join(" ");
-                    commands.push(format!("INSERT {} {}", table.name, vals));
-                }
-            }
+    // For AOF Rewrite (lazy: rows are pulled in small batches per table)
+    pub fn dump_commands(&self) -> impl Iterator<Item = String> + '_ {
+        let tables = self.tables_parents_first();
+        DumpCommands {
+            store: self,
+            tables: tables.into_iter(),
+            current: None,
+            pending: VecDeque::new(),
+            indexes_done: false,
         }
+    }
 
-        // 2. Indexes
-        for kv in self.indexes.iter() {
-            let table_name = kv.key();
-            for col_entry in kv.value().iter() {
-                let col_name = col_entry.key();
-                // CREATE INDEX idx_table_col ON table(col)
-                commands.push(format!("CREATE INDEX idx_{}_{} ON {}({})", table_name, col_name, table_name, col_name));
+    /// Table names ordered so that tables referenced by foreign keys come
+    /// before the tables referencing them, letting a replay pass FK checks.
+    fn tables_parents_first(&self) -> Vec<String> {
+        let parents: HashMap<String, Vec<String>> = self.tables.iter()
+            .map(|kv| {
+                let refs = kv.value().read()
+                    .map(|t| t.columns.iter().filter_map(|c| c.references.as_ref().map(|(t, _)| t.clone())).collect())
+                    .unwrap_or_default();
+                (kv.key().clone(), refs)
+            })
+            .collect();
+
+        fn visit(name: &str, parents: &HashMap<String, Vec<String>>, seen: &mut std::collections::HashSet<String>, out: &mut Vec<String>) {
+            if !parents.contains_key(name) || !seen.insert(name.to_string()) {
+                return;
             }
+            for parent in &parents[name] {
+                visit(parent, parents, seen, out);
+            }
+            out.push(name.to_string());
+        }
+
+        let mut names: Vec<&String> = parents.keys().collect();
+        names.sort();
+        let mut seen = std::collections::HashSet::new();
+        let mut out = Vec::new();
+        for name in names {
+            visit(name, &parents, &mut seen, &mut out);
+        }
+        out
+    }
+
+    fn dump_table_schema(&self, table_name: &str) -> Option<String> {
+        let table_lock = self.tables.get(table_name)?;
+        let table = table_lock.read().ok()?;
+
+        // CREATE TABLE
+        let cols_def = table.columns.iter()
+            .map(|c| {
+                let type_str = c.data_type.keyword();
+                let base = if c.is_primary_key {
+                    format!("{}:{}:pk", c.name, type_str)
+                } else {
+                    format!("{}:{}", c.name, type_str)
+                };
+
+                let mut def = if let Some((ref t, ref col)) = c.references {
+                    format!("{}:fk({}.{})", base, t, col)
+                } else {
+                    base
+                };
+                if let Some(action) = c.fk_action.keyword() {
+                    def.push_str(&format!(":{}", action));
+                }
+                if c.not_null {
+                    def.push_str(":notnull");
+                }
+                if c.unique {
+                    def.push_str(":unique");
+                }
+                if let Some(ref default) = c.default {
+                    def.push_str(&format!(":default({})", quote_string(default)));
+                }
+                def
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        Some(format!("CREATE TABLE {} {}", table.name, cols_def))
+    }
+
+    /// Pushes INSERTs for up to `DUMP_BATCH_ROWS` rows after `after` and returns
+    /// the last row id emitted, or None once the table is exhausted.
+    fn dump_table_rows(&self, table_name: &str, after: Option<u64>, out: &mut VecDeque<String>) -> Option<u64> {
+        let table_lock = self.tables.get(table_name)?;
+        let table = table_lock.read().ok()?;
+
+        let lower = match after {
+            Some(id) => Excluded(id),
+            None => Unbounded,
+        };
+
+        let mut last = None;
+        for (id, row) in table.rows.range((lower, Unbounded)).take(DUMP_BATCH_ROWS) {
+            let vals = row.iter()
+                .map(|v| match v {
+                    UnifiedValue::String(s) => quote_string(s),
+                    UnifiedValue::DateTime(i) => format!("{}", i),
+                    UnifiedValue::Blob(b) => quote_string(b),
+                    UnifiedValue::Object(_) | UnifiedValue::Array(_) => {
+                        // Serialize JSON back to a quoted string literal
+                        quote_string(&serde_json::to_string(v).unwrap_or_else(|_| "{}".to_string()))
+                    },
+                    _ => format!("{}", v), // Display impl handles others
+                })
+                .collect::<Vec<_>>()
+                .join(" ");
+            out.push_back(format!("INSERT {} {}", table.name, vals));
+            last = Some(*id);
         }
+        last
+    }
 
+    /// Named indexes, except those CREATE TABLE sets up for its constraints.
+    fn dump_index_commands(&self) -> Vec<String> {
+        let mut commands = Vec::new();
+        for index in self.list_indexes(None).unwrap_or_default() {
+            if index.constraint.is_none() {
+                commands.push(index.statement());
+            }
+        }
         commands
     }
 
-    pub fn import_from(tables: std::collections::HashMap<String, Table>) -> Self {
+    pub fn import_from(tables: std::collections::HashMap<String, Table>, indexes: HashMap<String, TableIndexes>) -> Self {
         let store = Self::new();
         for (name, table) in tables {
-            // Rebuild PK/Unique indexes
-            // We need to identify which columns need indexing.
-            // Currently only PK is auto-indexed.
-            let idx_cols: Vec<String> = table.columns.iter()
-                .filter(|c| c.is_primary_key)
-                .map(|c| c.name.clone())
-                .collect();
+            store.add_table(name.clone(), table, indexes.get(&name));
+        }
+        store
+    }
+
+    /// Registers a loaded table and builds its indexes: those of its
+    /// constraints, then the ones in `indexes`.
+    fn add_table(&self, name: String, table: Table, indexes: Option<&TableIndexes>) {
+        let idx_cols: Vec<(String, String)> = table.columns.iter()
+            .filter_map(|c| Some((Self::constraint_index(&name, c)?.0, c.name.clone())))
+            .collect();
+
+        self.note_references(&name, &table);
+        self.tables.insert(name.clone(), Arc::new(RwLock::new(table)));
+
+        for (index_name, col) in idx_cols {
+            let _ = self.create_index(&index_name, &name, &col);
+        }
+        if let Some(indexes) = indexes {
+            self.rebuild_indexes(&name, indexes);
+        }
+    }
 
-            // Insert table
-            store.tables.insert(name.clone(), std::sync::RwLock::new(table));
+    /// A copy of one table with its named indexes (EXPORT TABLE).
+    pub fn export_table(&self, name: &str) -> Result<(Table, TableIndexes)> {
+        let table = self.tables.get(name).ok_or_else(|| anyhow!("Table not found"))?
+            .read().map_err(|_| anyhow!("Lock poison"))?.clone();
+        Ok((table, self.table_indexes(name)))
+    }
 
-            // Create indices
-            for col in idx_cols {
-                let _ = store.create_index(&name, &col, "HASH");
+    /// Adds a table from [`export_table`](Self::export_table) under `name`,
+    /// with its indexes (IMPORT TABLE). An existing table is only replaced
+    /// with `replace`, and the tables it references must exist.
+    pub fn import_table(&self, name: &str, mut table: Table, mut indexes: TableIndexes, replace: bool) -> Result<()> {
+        if name == KV_TABLE {
+            return Err(anyhow!("Table name '{}' is reserved", KV_TABLE));
+        }
+        if self.tables.contains_key(name) && !replace {
+            return Err(anyhow!("Table already exists"));
+        }
+        for column in &mut table.columns {
+            // Constraint indexes come back under the new name
+            if let Some((index_name, _)) = Self::constraint_index(&table.name, column) {
+                indexes.catalog.remove(&index_name);
+            }
+            if let Some((parent, _)) = &mut column.references {
+                // A table referencing itself keeps doing so under its new name
+                if *parent == table.name {
+                    *parent = name.to_string();
+                } else if !self.tables.contains_key(parent.as_str()) {
+                    return Err(anyhow!("Referenced table '{}' not found", parent));
+                }
             }
         }
-        store
+        table.name = name.to_string();
+        self.remove_table(name);
+        self.add_table(name.to_string(), table, Some(&indexes));
+        Ok(())
+    }
+
+    /// The named indexes of every table, for snapshots.
+    pub fn export_indexes(&self) -> HashMap<String, TableIndexes> {
+        self.tables.iter()
+            .map(|kv| (kv.key().clone(), self.table_indexes(kv.key())))
+            .filter(|(_, indexes)| *indexes != TableIndexes::default())
+            .collect()
+    }
+
+    fn table_indexes(&self, table_name: &str) -> TableIndexes {
+        TableIndexes {
+            catalog: self.index_names.get(table_name).map(|n| n.clone()).unwrap_or_default(),
+            vectors: self.vector_specs(table_name),
+        }
+    }
+
+    /// Builds the indexes of `indexes` not registered on the table yet (those
+    /// of its constraints come with it).
+    fn rebuild_indexes(&self, table_name: &str, indexes: &TableIndexes) {
+        for (index_name, column) in &indexes.catalog {
+            if self.index_names.get(table_name).is_some_and(|names| names.contains_key(index_name)) {
+                continue;
+            }
+            let _ = self.create_index(index_name, table_name, column);
+        }
+        for (index_name, column, dims, metric) in &indexes.vectors {
+            let _ = self.create_vector_index(index_name, table_name, column, *dims, *metric);
+        }
     }
 }
 
@@ -178,12 +655,87 @@ impl StructuredStore {
             tables: Arc::new(DashMap::new()),
             indexes: Arc::new(DashMap::new()),
             range_indexes: Arc::new(DashMap::new()),
+            index_names: Arc::new(DashMap::new()),
+            vector_indexes: Arc::new(DashMap::new()),
+            advisor: Arc::new(IndexAdvisor::new()),
+            published: Arc::new(Mutex::new(HashMap::new())),
+            read_gate: Arc::new(RwLock::new(())),
+            unindexed: Arc::new(HashSet::new()),
+            references: Arc::new(DashMap::new()),
+            index_layouts: Arc::new(DashMap::new()),
+        }
+    }
+
+    /// Records the tables `table` references, for [`dependents`](Self::dependents).
+    fn note_references(&self, name: &str, table: &Table) {
+        let parents = table.columns.iter().filter_map(|c| c.references.as_ref().map(|(t, _)| t.clone())).collect();
+        self.references.insert(name.to_string(), parents);
+    }
+
+    /// The tables for one query. A table a transaction is writing is read as
+    /// it was when the transaction started, so a query never sees part of a
+    /// transaction; such a copy is scanned without indexes. Queries of the
+    /// transaction itself read the live tables.
+    pub fn read_view(&self) -> ReadView<'_> {
+        if IN_TRANSACTION.get() {
+            return ReadView { store: self.clone(), _gate: None };
+        }
+        let gate = self.read_gate.read().unwrap_or_else(|e| e.into_inner());
+        let published = self.published.lock().unwrap_or_else(|e| e.into_inner());
+        if published.is_empty() {
+            return ReadView { store: self.clone(), _gate: Some(gate) };
+        }
+        let tables: DashMap<String, SharedTable> = self.tables.iter()
+            .filter(|kv| !published.contains_key(kv.key()))
+            .map(|kv| (kv.key().clone(), kv.value().clone()))
+            .collect();
+        for (name, copy) in published.iter() {
+            if let Some(copy) = copy {
+                tables.insert(name.clone(), copy.clone());
+            }
+        }
+        let store = Self {
+            tables: Arc::new(tables),
+            unindexed: Arc::new(published.keys().cloned().collect()),
+            // Scans forced by a missing index say nothing about the workload
+            advisor: Arc::new(IndexAdvisor::new()),
+            ..self.clone()
+        };
+        ReadView { store, _gate: Some(gate) }
+    }
+
+    /// A view of the store with one more, read-only table, for a query over
+    /// a virtual table such as [`KV_TABLE`]. The view holds only the tables
+    /// the query names in `tables`; they and their indexes are shared with
+    /// the store. The extra table is visible to this view only and has no
+    /// indexes.
+    pub fn with_table(&self, table: Table, tables: &[&str]) -> StructuredStore {
+        let tables: DashMap<String, Arc<RwLock<Table>>> = tables.iter()
+            .filter_map(|name| self.tables.get(*name).map(|kv| (kv.key().clone(), kv.value().clone())))
+            .collect();
+        tables.insert(table.name.clone(), Arc::new(RwLock::new(table)));
+        Self {
+            tables: Arc::new(tables),
+            // Index suggestions for the virtual table could never be applied
+            advisor: Arc::new(IndexAdvisor::new()),
+            ..self.clone()
         }
     }
 
-    pub fn create_index(&self, _index_name: &str, table_name: &str, column_expr: &str) -> Result<()> {
+    /// Builds (or rebuilds) the hash and range index of a column or JSON path.
+    /// A non-empty `index_name` is recorded in the catalog; names are unique
+    /// per table, and an empty name only rebuilds.
+    pub fn create_index(&self, index_name: &str, table_name: &str, column_expr: &str) -> Result<()> {
         if let Some(table_lock) = self.tables.get(table_name) {
             let table = table_lock.read().map_err(|_| anyhow!("Lock poison"))?;
+
+            if let Some(names) = self.index_names.get(table_name)
+                && names.get(index_name).is_some_and(|col| col != column_expr) {
+                return Err(anyhow!("Index '{}' already exists on {}", index_name, table_name));
+            }
+            if self.vector_indexes.get(table_name).is_some_and(|v| v.contains_key(index_name)) {
+                return Err(anyhow!("Index '{}' already exists on {}", index_name, table_name));
+            }
             
             // Check if this is a JSON path index (column->path)
             let is_json_path = column_expr.contains("->");
@@ -227,75 +779,203 @@ impl StructuredStore {
                 .entry(table_name.to_string())
                 .or_insert_with(DashMap::new)
                 .insert(column_expr.to_string(), RwLock::new(range_map));
+            self.invalidate_layout(table_name);
+
+            if !index_name.is_empty() {
+                self.index_names
+                    .entry(table_name.to_string())
+                    .or_default()
+                    .insert(index_name.to_string(), column_expr.to_string());
+            }
             
             Ok(())
         } else {
             Err(anyhow!("Table not found"))
         }
     }
-    
-    /// Helper: Extract a value from a row using a JSON path expression
-    fn extract_json_path_value(row: &Vec<UnifiedValue>, columns: &Vec<Column>, path_expr: &str) -> Option<UnifiedValue> {
-        if let Some(arrow_pos) = path_expr.find("->") {
-            let col_name = &path_expr[..arrow_pos];
-            let json_path = &path_expr[arrow_pos..];
-            
-            let col_idx = columns.iter().position(|c| c.name == col_name)?;
-            let col_type = &columns[col_idx].data_type;
-            
-            if !matches!(col_type, DataType::Json) {
-                return None;
+
+    /// Builds an HNSW index over a vector column, which `SEARCH` then uses
+    /// instead of comparing every row. Vectors in the column must have `dims`
+    /// dimensions, now and on later writes.
+    pub fn create_vector_index(&self, index_name: &str, table_name: &str, column: &str, dims: usize, metric: Metric) -> Result<()> {
+        if !(1..=hnsw::MAX_DIMS).contains(&dims) {
+            return Err(anyhow!("DIMS must be between 1 and {}", hnsw::MAX_DIMS));
+        }
+        let table_lock = self.tables.get(table_name).ok_or(anyhow!("Table not found"))?;
+        let table = table_lock.read().map_err(|_| anyhow!("Lock poison"))?;
+        let col_idx = table.columns.iter().position(|c| c.name == column)
+            .ok_or(anyhow!("Column not found"))?;
+        match table.columns[col_idx].data_type {
+            DataType::Vector(Some(declared)) if declared != dims => {
+                return Err(anyhow!("Column '{}' is declared vector({}), the index cannot use {} dimensions", column, declared, dims));
             }
-            
-            let mut current = row[col_idx].clone();
-            let mut remaining = json_path;
-            
-            while !remaining.is_empty() {
-                if remaining.starts_with("->>") {
-                    remaining = &remaining[3..];
-                } else if remaining.starts_with("->") {
-                    remaining = &remaining[2..];
-                } else {
-                    break;
-                }
-                
-                let key_end = remaining.find(|c: char| !c.is_alphanumeric() && c != '_')
-                    .unwrap_or(remaining.len());
-                let key = &remaining[..key_end];
-                remaining = &remaining[key_end..];
-                
-                match &current {
-                    UnifiedValue::Object(map) => {
-                        current = map.get(key).cloned().unwrap_or(UnifiedValue::Null);
-                    },
-                    UnifiedValue::Array(arr) => {
-                        if let Ok(idx) = key.parse::<usize>() {
-                            current = arr.get(idx).cloned().unwrap_or(UnifiedValue::Null);
-                        } else {
-                            return None;
-                        }
-                    },
-                    _ => return None,
+            DataType::Vector(_) => {}
+            _ => return Err(anyhow!("Column '{}' is not a vector column", column)),
+        }
+        if self.index_names.get(table_name).is_some_and(|n| n.contains_key(index_name))
+            || self.vector_indexes.get(table_name).is_some_and(|v| v.contains_key(index_name))
+        {
+            return Err(anyhow!("Index '{}' already exists on {}", index_name, table_name));
+        }
+        if self.vector_index_on(table_name, column).is_some() {
+            return Err(anyhow!("Column {}.{} already has a vector index", table_name, column));
+        }
+
+        let mut graph = Hnsw::new(dims, metric);
+        for (id, row) in &table.rows {
+            if let UnifiedValue::Vector(v) = &row[col_idx] {
+                if v.len() != dims {
+                    return Err(anyhow!("Row {} has {} dimensions in '{}', the index expects {}", id, v.len(), column, dims));
                 }
+                graph.insert(*id, v);
             }
-            
-            Some(current)
-        } else {
-            None
         }
+        self.vector_indexes
+            .entry(table_name.to_string())
+            .or_default()
+            .insert(index_name.to_string(), Arc::new(VectorIndex { column: column.to_string(), graph: RwLock::new(graph) }));
+        Ok(())
+    }
+
+    fn vector_index_on(&self, table_name: &str, column: &str) -> Option<Arc<VectorIndex>> {
+        self.vector_indexes.get(table_name)?.values().find(|index| index.column == column).cloned()
+    }
+
+    fn vector_indexes_of(&self, table_name: &str) -> Vec<Arc<VectorIndex>> {
+        self.vector_indexes.get(table_name).map(|v| v.values().cloned().collect()).unwrap_or_default()
+    }
+
+    /// Name, column, dimensions and metric of each vector index of a table.
+    fn vector_specs(&self, table_name: &str) -> Vec<(String, String, usize, Metric)> {
+        self.vector_indexes.get(table_name).map(|v| v.iter().map(|(name, index)| {
+            let graph = index.graph.read().unwrap_or_else(|e| e.into_inner());
+            (name.clone(), index.column.clone(), graph.dims, graph.metric)
+        }).collect()).unwrap_or_default()
+    }
+
+    /// Refuses a row whose vectors do not fit the vector indexes of the table.
+    fn check_vector_dims(&self, table_name: &str, columns: &[Column], row: &[UnifiedValue]) -> Result<()> {
+        for index in self.vector_indexes_of(table_name) {
+            let Some(col_idx) = columns.iter().position(|c| c.name == index.column) else { continue };
+            let dims = index.graph.read().map_err(|_| anyhow!("Lock poison"))?.dims;
+            if let UnifiedValue::Vector(v) = &row[col_idx]
+                && v.len() != dims
+            {
+                return Err(anyhow!("Constraint violation: Vector for '{}' must have {} dimensions", index.column, dims));
+            }
+        }
+        Ok(())
+    }
+
+    /// Puts a row's vectors into the vector indexes of its table, or takes
+    /// them out where the column is null.
+    fn index_row_vectors(&self, table_name: &str, columns: &[Column], id: u64, row: &[UnifiedValue]) {
+        for index in self.vector_indexes_of(table_name) {
+            let Some(col_idx) = columns.iter().position(|c| c.name == index.column) else { continue };
+            let mut graph = index.graph.write().unwrap_or_else(|e| e.into_inner());
+            match &row[col_idx] {
+                UnifiedValue::Vector(v) => { graph.insert(id, v); }
+                _ => graph.remove(id),
+            }
+        }
+    }
+
+    /// Helper: Extract a value from a row using a JSON path expression
+    fn extract_json_path_value(row: &Vec<UnifiedValue>, columns: &Vec<Column>, path_expr: &str) -> Option<UnifiedValue> {
+        let arrow_pos = path_expr.find("->")?;
+        let col_name = &path_expr[..arrow_pos];
+        let col_idx = columns.iter().position(|c| c.name == col_name)?;
+        if !matches!(columns[col_idx].data_type, DataType::Json) {
+            return None;
+        }
+        Self::json_path_value(&row[col_idx], &path_expr[arrow_pos..])
+    }
+
+    /// Follows a `->key->0` path (`->>` also accepted) into a JSON value.
+    fn json_path_value(value: &UnifiedValue, json_path: &str) -> Option<UnifiedValue> {
+        let mut current = value;
+        let mut remaining = json_path;
+
+        while !remaining.is_empty() {
+            if remaining.starts_with("->>") {
+                remaining = &remaining[3..];
+            } else if remaining.starts_with("->") {
+                remaining = &remaining[2..];
+            } else {
+                break;
+            }
+
+            let key_end = remaining.find(|c: char| !c.is_alphanumeric() && c != '_')
+                .unwrap_or(remaining.len());
+            let key = &remaining[..key_end];
+            remaining = &remaining[key_end..];
+
+            current = match current {
+                UnifiedValue::Object(map) => match map.get(key) {
+                    Some(v) => v,
+                    None => return Some(UnifiedValue::Null),
+                },
+                UnifiedValue::Array(arr) => match arr.get(key.parse::<usize>().ok()?) {
+                    Some(v) => v,
+                    None => return Some(UnifiedValue::Null),
+                },
+                _ => return None,
+            };
+        }
+
+        Some(current.clone())
+    }
+
+    /// Where each hash / range index of a table reads its key from a row,
+    /// worked out once and kept until the table's indexes or columns change.
+    /// Callers hold a lock on the table, which index builds take too.
+    fn index_layout(&self, table_name: &str, table: &Table) -> Arc<Vec<(String, KeySource)>> {
+        if let Some(layout) = self.index_layouts.get(table_name) {
+            return layout.clone();
+        }
+        let mut exprs: Vec<String> = self.indexes.get(table_name)
+            .map(|idx| idx.iter().map(|e| e.key().clone()).collect())
+            .unwrap_or_default();
+        if let Some(ranges) = self.range_indexes.get(table_name) {
+            exprs.extend(ranges.iter().map(|e| e.key().clone()));
+        }
+        exprs.sort();
+        exprs.dedup();
+        let layout: Arc<Vec<_>> = Arc::new(exprs.into_iter()
+            .filter_map(|expr| Some((expr.clone(), KeySource::of(&table.columns, &expr)?)))
+            .collect());
+        self.index_layouts.insert(table_name.to_string(), layout.clone());
+        layout
+    }
+
+    /// Forgets the [`index_layout`](Self::index_layout) of a table.
+    fn invalidate_layout(&self, table_name: &str) {
+        self.index_layouts.remove(table_name);
     }
 
     pub fn create_table(&self, name: String, columns: Vec<Column>) -> Result<()> {
         if self.tables.contains_key(&name) {
             return Err(anyhow!("Table already exists"));
         }
+        if name == KV_TABLE {
+            return Err(anyhow!("Table name '{}' is reserved", KV_TABLE));
+        }
         
         // Auto-create indices for Primary Keys
         // We do this by creating the table first, then calling create_index internally?
         // Or just setting up the structure.
         // For simplicity, we just init the table. Index creation usually happens explicitly or we can bootstrap it.
         // PLAN: Auto-index PKs.
-        
+
+        for col in &columns {
+            if col.fk_action != FkAction::NoAction && col.references.is_none() {
+                return Err(anyhow!("Referential action on '{}' requires a foreign key", col.name));
+            }
+            if col.fk_action == FkAction::SetNull && (col.not_null || col.is_primary_key) {
+                return Err(anyhow!("Column '{}' cannot be NULL, so it cannot use setnull", col.name));
+            }
+        }
+
         let table = Table {
             name: name.clone(),
             columns: columns.clone(),
@@ -304,196 +984,273 @@ impl StructuredStore {
         };
         
         // Insert table first
-        self.tables.insert(name.clone(), RwLock::new(table));
+        self.note_references(&name, &table);
+        self.tables.insert(name.clone(), Arc::new(RwLock::new(table)));
         
-        // Now create indices for PKs
+        // Now create indices for PKs and unique columns
         for col in columns {
-            if col.is_primary_key {
+            if let Some((index_name, _)) = Self::constraint_index(&name, &col) {
                 // Ignore error if fails (shouldn't fails on empty table)
-                let _ = self.create_index(&format!("pk_{}_{}", name, col.name), &name, &col.name);
+                let _ = self.create_index(&index_name, &name, &col.name);
             }
         }
         
         Ok(())
     }
 
-    pub fn insert(&self, table_name: &str, values: Vec<String>) -> Result<()> {
-        if let Some(table_lock) = self.tables.get(table_name) {
-            let mut table = table_lock.write().map_err(|_| anyhow!("Lock poison"))?;
-            if values.len() != table.columns.len() {
-                return Err(anyhow!("Column count mismatch"));
-            }
-
-            // Parse values to UnifiedValue
-            let mut parsed_values = Vec::new();
-            for (i, val_str) in values.iter().enumerate() {
-                let col_type = &table.columns[i].data_type;
-                let val = match col_type {
-                    DataType::Integer => UnifiedValue::Integer(val_str.parse().unwrap_or(0)),
-                    DataType::Float => UnifiedValue::Float(val_str.parse().unwrap_or(0.0)),
-                    DataType::Boolean => UnifiedValue::Boolean(val_str.parse().unwrap_or(false)),
-                    DataType::String => UnifiedValue::String(val_str.clone()),
-                    DataType::DateTime => UnifiedValue::DateTime(val_str.parse().unwrap_or(0)),
-                    DataType::Blob => UnifiedValue::Blob(val_str.clone()),
-                    DataType::Json => {
-                        // Parse JSON string into UnifiedValue
-                        serde_json::from_str::<serde_json::Value>(val_str)
-                            .map(|v| UnifiedValue::from(v))
-                            .unwrap_or(UnifiedValue::Null)
-                    },
-                    DataType::Vector => {
-                        if val_str.trim().starts_with('[') {
-                            if let Ok(vec) = serde_json::from_str::<Vec<f64>>(val_str) {
-                                UnifiedValue::Vector(vec)
-                            } else {
-                                // Try convert simple array of strings/nums?
-                                // Fallback: Null or Error.
-                                // Let's return Null if invalid vector format.
-                                UnifiedValue::Null 
-                            }
-                        } else {
-                            UnifiedValue::Null
-                        }
-                    },
-                };
-                parsed_values.push(val);
-            }
-
-            // Check Primary Key Uniqueness (O(1) via Index)
-            if let Some(pk_idx) = table.columns.iter().position(|c| c.is_primary_key) {
-                let pk_val = &parsed_values[pk_idx];
-                let pk_col_name = &table.columns[pk_idx].name;
-                
-                // Look up in index
-                if let Some(table_indexes) = self.indexes.get(table_name) {
-                     if let Some(col_index) = table_indexes.get(pk_col_name) {
-                         if col_index.contains_key(pk_val) {
-                             return Err(anyhow!("Constraint violation: Duplicate primary key '{}'", pk_val));
-                         }
-                     }
+    /// Converts a literal to the column's type. Vectors must be a list of
+    /// numbers, with the declared dimension when the column has one, and
+    /// datetimes a timestamp or an ISO-8601 date.
+    fn typed_value(col: &Column, val_str: &str) -> Result<UnifiedValue> {
+        Ok(match &col.data_type {
+            DataType::Integer => UnifiedValue::Integer(val_str.parse().unwrap_or(0)),
+            DataType::Float => UnifiedValue::Float(val_str.parse().unwrap_or(0.0)),
+            DataType::Boolean => UnifiedValue::Boolean(val_str.parse().unwrap_or(false)),
+            DataType::String => UnifiedValue::String(val_str.to_string()),
+            DataType::DateTime => UnifiedValue::DateTime(datetime::parse(val_str).ok_or_else(|| {
+                anyhow!("Invalid datetime for '{}': expected a Unix timestamp or an ISO-8601 date like 2024-01-01T12:00:00Z", col.name)
+            })?),
+            DataType::Blob => UnifiedValue::Blob(val_str.to_string()),
+            DataType::Json => {
+                // Parse JSON string into UnifiedValue
+                serde_json::from_str::<serde_json::Value>(val_str)
+                    .map(UnifiedValue::from)
+                    .unwrap_or(UnifiedValue::Null)
+            },
+            DataType::Vector(dims) => {
+                let v = serde_json::from_str::<Vec<f64>>(val_str)
+                    .map_err(|_| anyhow!("Invalid vector for '{}': expected a list of numbers like [0.5, 1]", col.name))?;
+                if let Some(dims) = dims
+                    && v.len() != *dims
+                {
+                    return Err(anyhow!("Constraint violation: Vector for '{}' must have {} dimensions, got {}", col.name, dims, v.len()));
                 }
+                UnifiedValue::Vector(v)
+            },
+        })
+    }
+
+    /// True if a row other than `except` holds `val` in column `col_idx`.
+    /// Uses the column's hash index when there is one.
+    fn value_taken(&self, table_name: &str, table: &Table, col_idx: usize, val: &UnifiedValue, except: Option<u64>) -> bool {
+        let col_name = &table.columns[col_idx].name;
+        if let Some(table_indexes) = self.indexes.get(table_name)
+            && let Some(col_index) = table_indexes.get(col_name) {
+            return col_index.get(val).is_some_and(|ids| ids.iter().any(|id| Some(*id) != except));
+        }
+        table.rows.iter().any(|(id, row)| Some(*id) != except && &row[col_idx] == val)
+    }
+
+    /// Verifies that `val` exists in the referenced table's index.
+    fn check_reference(&self, ref_table_name: &str, ref_col_name: &str, val: &UnifiedValue) -> Result<()> {
+        let exists = if let Some(ref_indexes) = self.indexes.get(ref_table_name) {
+            if let Some(ref_col_index) = ref_indexes.get(ref_col_name) {
+                ref_col_index.get(val).is_some_and(|ids| !ids.is_empty())
+            } else {
+                // Reference column not indexed? Fallback or Error.
+                return Err(anyhow!("Referenced column '{}.{}' is not indexed. FK targets must be indexed.", ref_table_name, ref_col_name));
             }
+        } else {
+             return Err(anyhow!("Referenced table '{}' not found", ref_table_name));
+        };
 
-            // Check Foreign Key Constraints (O(1) via Index)
-            for (i, col) in table.columns.iter().enumerate() {
-                if let Some((ref ref_table_name, ref ref_col_name)) = col.references {
-                    let val = &parsed_values[i];
-                    
-                    // Verify that the referenced value exists in the referenced table's index
-                    let exists = if let Some(ref_indexes) = self.indexes.get(ref_table_name) {
-                        if let Some(ref_col_index) = ref_indexes.get(ref_col_name) {
-                            ref_col_index.contains_key(val)
-                        } else {
-                            // Reference column not indexed? Fallback or Error.
-                            return Err(anyhow!("Referenced column '{}.{}' is not indexed. FK targets must be indexed.", ref_table_name, ref_col_name));
-                        }
-                    } else {
-                         return Err(anyhow!("Referenced table '{}' not found", ref_table_name));
-                    };
+        if !exists {
+             return Err(anyhow!("Constraint violation: FK '{}' not found in '{}.{}'", val, ref_table_name, ref_col_name));
+        }
+        Ok(())
+    }
+
+    pub fn insert(&self, table_name: &str, values: Vec<Option<String>>) -> Result<()> {
+        self.insert_many(table_name, vec![values]).map(|_| ())
+    }
 
-                    if !exists {
-                         return Err(anyhow!("Constraint violation: FK '{}' not found in '{}.{}'", val, ref_table_name, ref_col_name));
+    /// Inserts `rows` under a single lock of the table, all or none: a row
+    /// failing its checks takes the rows inserted before it back out.
+    pub fn insert_many(&self, table_name: &str, rows: Vec<Vec<Option<String>>>) -> Result<usize> {
+        let table_lock = self.tables.get(table_name).ok_or_else(|| anyhow!("Table not found"))?;
+        let mut table = table_lock.write().map_err(|_| anyhow!("Lock poison"))?;
+        let layout = self.index_layout(table_name, &table);
+        let mut inserted = Vec::with_capacity(rows.len());
+        for values in rows {
+            match self.insert_row(table_name, &mut table, &layout, values) {
+                Ok(row_id) => inserted.push(row_id),
+                Err(e) => {
+                    for row_id in inserted {
+                        self.remove_row(table_name, &mut table, row_id);
                     }
+                    return Err(e);
                 }
             }
+        }
+        Ok(inserted.len())
+    }
 
-            let row_id = table.next_row_id;
-            table.next_row_id += 1;
-            
-            table.rows.insert(row_id, parsed_values.clone());
-            
-            // Maintain indexes
-            drop(table); // Release read lock
-            
-            // 1. Maintain Hash Indexes
+    /// Checks one row against the table's constraints, stores it and indexes it.
+    fn insert_row(&self, table_name: &str, table: &mut Table, layout: &[(String, KeySource)], values: Vec<Option<String>>) -> Result<u64> {
+        if values.len() > table.columns.len() {
+            return Err(anyhow!("Column count mismatch"));
+        }
+
+        // Parse values to UnifiedValue; omitted trailing columns take their DEFAULT (or NULL)
+        let mut parsed_values = Vec::new();
+        for (i, col) in table.columns.iter().enumerate() {
+            let val_str = match values.get(i) {
+                Some(v) => v.as_deref(),
+                None => col.default.as_deref(),
+            };
+            let val = match val_str {
+                Some(v) => Self::typed_value(col, v)?,
+                None => UnifiedValue::Null,
+            };
+            if col.not_null && matches!(val, UnifiedValue::Null) {
+                return Err(anyhow!("Constraint violation: Column '{}' cannot be NULL", col.name));
+            }
+            if col.unique && !matches!(val, UnifiedValue::Null) && self.value_taken(table_name, table, i, &val, None) {
+                return Err(anyhow!("Constraint violation: Duplicate value '{}' for unique column '{}'", val, col.name));
+            }
+            parsed_values.push(val);
+        }
+
+        // Check Primary Key Uniqueness (O(1) via Index)
+        if let Some(pk_idx) = table.columns.iter().position(|c| c.is_primary_key) {
+            let pk_val = &parsed_values[pk_idx];
+            let pk_col_name = &table.columns[pk_idx].name;
+            if matches!(pk_val, UnifiedValue::Null) {
+                return Err(anyhow!("Constraint violation: Primary key '{}' cannot be NULL", pk_col_name));
+            }
+
+            // Look up in index
             if let Some(table_indexes) = self.indexes.get(table_name) {
-                let table_lock = self.tables.get(table_name).unwrap();
-                let table = table_lock.read().map_err(|_| anyhow!("Lock poison"))?;
-                
-                for col_entry in table_indexes.iter() {
-                    let col_name = col_entry.key();
-                    if let Some(col_idx) = table.columns.iter().position(|c| &c.name == col_name) {
-                        let val = &parsed_values[col_idx];
-                        col_entry.value().entry(val.clone()).or_insert_with(Vec::new).push(row_id);
-                    }
+                 if let Some(col_index) = table_indexes.get(pk_col_name) {
+                     if col_index.contains_key(pk_val) {
+                         return Err(anyhow!("Constraint violation: Duplicate primary key '{}'", pk_val));
+                     }
+                 }
+            }
+        }
+
+        // Check Foreign Key Constraints (O(1) via Index)
+        for (i, col) in table.columns.iter().enumerate() {
+            if let Some((ref ref_table_name, ref ref_col_name)) = col.references {
+                let val = &parsed_values[i];
+                if matches!(val, UnifiedValue::Null) {
+                    continue; // NULL references nothing
                 }
+                self.check_reference(ref_table_name, ref_col_name, val)?;
             }
+        }
+        self.check_vector_dims(table_name, &table.columns, &parsed_values)?;
 
-            // 2. Maintain Range Indexes (B-Tree)
-            if let Some(table_range_indexes) = self.range_indexes.get(table_name) {
-                let table_lock = self.tables.get(table_name).unwrap();
-                let table = table_lock.read().map_err(|_| anyhow!("Lock poison"))?;
+        let row_id = table.next_row_id;
+        table.next_row_id += 1;
 
-                for col_entry in table_range_indexes.iter() {
-                    let col_name = col_entry.key();
-                    if let Some(col_idx) = table.columns.iter().position(|c| &c.name == col_name) {
-                        let val = &parsed_values[col_idx];
-                        let mut btree = col_entry.value().write().map_err(|_| anyhow!("Lock poison"))?;
-                        btree.entry(val.clone()).or_insert_with(Vec::new).push(row_id);
-                    }
-                }
+        self.index_row_vectors(table_name, &table.columns, row_id, &parsed_values);
+
+        let hash_indexes = self.indexes.get(table_name);
+        let range_indexes = self.range_indexes.get(table_name);
+        for (expr, source) in layout {
+            let key = source.key(&parsed_values);
+            if let Some(col_index) = hash_indexes.as_ref().and_then(|idx| idx.get(expr)) {
+                col_index.entry(key.clone()).or_insert_with(Vec::new).push(row_id);
+            }
+            if let Some(range) = range_indexes.as_ref().and_then(|idx| idx.get(expr)) {
+                let mut btree = range.write().map_err(|_| anyhow!("Lock poison"))?;
+                btree.entry(key).or_insert_with(Vec::new).push(row_id);
             }
-            
-            Ok(())
-        } else {
-            Err(anyhow!("Table not found"))
         }
+
+        table.rows.insert(row_id, parsed_values);
+        Ok(row_id)
     }
 
 
 
 
 
-    fn evaluate_condition(&self, row_val: &UnifiedValue, target_val: &str, col_type: &DataType, op: &Operator) -> bool {
-        // Parse target_val to UnifiedValue for comparison
-        let target = match col_type {
-            DataType::Integer => UnifiedValue::Integer(target_val.parse().unwrap_or(0)),
-            DataType::Float => UnifiedValue::Float(target_val.parse().unwrap_or(0.0)),
-            DataType::Boolean => UnifiedValue::Boolean(target_val.parse().unwrap_or(false)),
-            DataType::String => UnifiedValue::String(target_val.to_string()),
-            DataType::DateTime => UnifiedValue::DateTime(target_val.parse().unwrap_or(0)),
-            DataType::Blob => UnifiedValue::Blob(target_val.to_string()),
-            DataType::Json => serde_json::from_str::<serde_json::Value>(target_val)
+    /// Reads a comparison literal as a value of the column's type.
+    fn literal_value(col_type: &DataType, literal: &str) -> UnifiedValue {
+        match col_type {
+            DataType::Integer => UnifiedValue::Integer(literal.parse().unwrap_or(0)),
+            DataType::Float => UnifiedValue::Float(literal.parse().unwrap_or(0.0)),
+            DataType::Boolean => UnifiedValue::Boolean(literal.parse().unwrap_or(false)),
+            DataType::String => UnifiedValue::String(literal.to_string()),
+            DataType::DateTime => UnifiedValue::DateTime(datetime::parse(literal).unwrap_or(0)),
+            DataType::Blob => UnifiedValue::Blob(literal.to_string()),
+            DataType::Json => serde_json::from_str::<serde_json::Value>(literal)
                 .map(UnifiedValue::from)
                 .unwrap_or(UnifiedValue::Null),
-            DataType::Vector => UnifiedValue::Null, 
-        };
+            DataType::Vector(_) => UnifiedValue::Null,
+        }
+    }
+
+    fn evaluate_condition(&self, row_val: &UnifiedValue, target_val: &str, col_type: &DataType, op: &Operator) -> bool {
+        // NULL never compares true; only IS [NOT] NULL can test for it
+        if matches!(row_val, UnifiedValue::Null) && !matches!(op, Operator::IsNull | Operator::IsNotNull) {
+            return false;
+        }
+
+        let target = Self::literal_value(col_type, target_val);
 
         match op {
+            Operator::IsNull => matches!(row_val, UnifiedValue::Null),
+            Operator::IsNotNull => !matches!(row_val, UnifiedValue::Null),
             Operator::Eq => row_val == &target,
             Operator::Neq => row_val != &target,
             Operator::Gt => row_val > &target,
             Operator::Lt => row_val < &target,
             Operator::Gte => row_val >= &target,
             Operator::Lte => row_val <= &target,
-            Operator::Like => {
+            Operator::Like | Operator::ILike => {
                 if let (UnifiedValue::String(s), UnifiedValue::String(p)) = (row_val, &target) {
-                     let pattern = p.replace('%', ".*").replace('_', ".");
-                     regex::Regex::new(&format!("^{}$", pattern))
-                        .map(|re| re.is_match(s))
-                        .unwrap_or(false)
+                    like::is_match(p, s, matches!(op, Operator::ILike))
                 } else {
                     false
                 }
             },
             Operator::In => {
-                // target_val is a comma-separated string, we need to parse each part
-                let parts: Vec<&str> = target_val.split(',').collect();
-                parts.iter().any(|part| {
-                     let t = match col_type {
-                        DataType::Integer => UnifiedValue::Integer(part.parse().unwrap_or(0)),
-                        DataType::Float => UnifiedValue::Float(part.parse().unwrap_or(0.0)),
-                        DataType::Boolean => UnifiedValue::Boolean(part.parse().unwrap_or(false)),
-                        DataType::String => UnifiedValue::String(part.to_string()),
-                        DataType::DateTime => UnifiedValue::DateTime(part.parse().unwrap_or(0)),
-                        DataType::Blob => UnifiedValue::Blob(part.to_string()),
-                        DataType::Json => serde_json::from_str::<serde_json::Value>(part)
-                            .map(UnifiedValue::from)
-                            .unwrap_or(UnifiedValue::Null),
-                        DataType::Vector => UnifiedValue::Null,
-                    };
-                    row_val == &t
-                })
+                // target_val packs the list, see `join_value_list`
+                let parts = crate::query::split_value_list(target_val);
+                parts.iter().any(|part| row_val == &Self::literal_value(col_type, part))
+            }
+            Operator::Between => {
+                // Both bounds packed like an IN list, and both included
+                let Some((low, high)) = between_bounds(target_val) else { return false };
+                &Self::literal_value(col_type, &low) <= row_val && row_val <= &Self::literal_value(col_type, &high)
+            }
+        }
+    }
+
+    /// Whether a group passes HAVING. Conditions may name the group columns and
+    /// any aggregate (`COUNT(*) > 5 AND SUM(amount) < 100`), which is computed
+    /// over the group by `aggregate`; any other name, like `count` or an alias,
+    /// reads the selected aggregate `agg_val`.
+    fn having_matches(
+        &self,
+        having: &Filter,
+        group_cols: &[String],
+        key: &[UnifiedValue],
+        agg_val: &UnifiedValue,
+        aggregate: impl Fn(&Selector) -> Result<UnifiedValue>,
+    ) -> Result<bool> {
+        let mut named: HashMap<String, UnifiedValue> = group_cols.iter().cloned().zip(key.iter().cloned()).collect();
+        for agg in having.aggregates() {
+            named.insert(agg.to_string(), aggregate(agg)?);
+        }
+        Ok(self.evaluate_having(having, &|name| named.get(name).unwrap_or(agg_val).clone()))
+    }
+
+    fn evaluate_having(&self, filter: &Filter, resolve: &dyn Fn(&str) -> UnifiedValue) -> bool {
+        match filter {
+            Filter::Condition(name, op, value) => {
+                let val = resolve(name);
+                self.evaluate_condition(&val, value, &Self::value_data_type(&val), op)
+            }
+            Filter::And(left, right) => self.evaluate_having(left, resolve) && self.evaluate_having(right, resolve),
+            Filter::Or(left, right) => self.evaluate_having(left, resolve) || self.evaluate_having(right, resolve),
+            Filter::Not(inner) => !self.evaluate_having(inner, resolve),
+            Filter::Computed(expr, op, value) => {
+                let val = eval::eval(expr, resolve);
+                self.evaluate_condition(&val, value, &Self::value_data_type(&val), op)
             }
         }
     }
@@ -514,54 +1271,9 @@ impl StructuredStore {
                 return None;
             }
             
-            let mut current = row[col_idx].clone();
-            
-            // Parse path segments (split by -> or ->>)
-            let path_str = json_path;
-            let mut remaining = path_str;
-            
-            while !remaining.is_empty() {
-                // Skip leading arrow
-                if remaining.starts_with("->>") {
-                    remaining = &remaining[3..];
-                } else if remaining.starts_with("->") {
-                    remaining = &remaining[2..];
-                } else {
-                    break;
-                }
-                
-                // Extract key (alphanumeric)
-                let key_end = remaining.find(|c: char| !c.is_alphanumeric() && c != '_')
-                    .unwrap_or(remaining.len());
-                let key = &remaining[..key_end];
-                remaining = &remaining[key_end..];
-                
-                // Navigate into JSON
-                match &current {
-                    UnifiedValue::Object(map) => {
-                        current = map.get(key).cloned().unwrap_or(UnifiedValue::Null);
-                    },
-                    UnifiedValue::Array(arr) => {
-                        // Support numeric index access
-                        if let Ok(idx) = key.parse::<usize>() {
-                            current = arr.get(idx).cloned().unwrap_or(UnifiedValue::Null);
-                        } else {
-                            return None;
-                        }
-                    },
-                    _ => return None,
-                }
-            }
-            
-            // Determine result type based on the extracted value
-            let result_type = match &current {
-                UnifiedValue::Integer(_) => DataType::Integer,
-                UnifiedValue::Float(_) => DataType::Float,
-                UnifiedValue::Boolean(_) => DataType::Boolean,
-                UnifiedValue::String(_) => DataType::String,
-                _ => DataType::Json,
-            };
-            
+            // The same path rules as joined rows
+            let current = Self::navigate_json_path(row[col_idx].clone(), json_path);
+            let result_type = Self::value_data_type(&current);
             Some((current, result_type))
         } else {
             // Simple column reference
@@ -570,6 +1282,21 @@ impl StructuredStore {
         }
     }
 
+    /// Column type matching a value, used to parse comparison literals when
+    /// there is no declared type (JSON paths, joined rows).
+    fn value_data_type(val: &UnifiedValue) -> DataType {
+        match val {
+            UnifiedValue::Integer(_) => DataType::Integer,
+            UnifiedValue::Float(_) => DataType::Float,
+            UnifiedValue::Boolean(_) => DataType::Boolean,
+            UnifiedValue::String(_) => DataType::String,
+            UnifiedValue::DateTime(_) => DataType::DateTime,
+            UnifiedValue::Blob(_) => DataType::Blob,
+            UnifiedValue::Vector(_) => DataType::Vector(None),
+            UnifiedValue::Null | UnifiedValue::Array(_) | UnifiedValue::Object(_) => DataType::Json,
+        }
+    }
+
     fn evaluate_filter(&self, filter: &Filter, row: &Vec<UnifiedValue>, columns: &Vec<Column>) -> bool {
         match filter {
             Filter::Condition(col_expr, op, val) => {
@@ -577,7 +1304,8 @@ impl StructuredStore {
                 if let Some((row_val, col_type)) = self.resolve_json_path(row, columns, col_expr) {
                     self.evaluate_condition(&row_val, val, &col_type, op)
                 } else {
-                    false 
+                    // A missing JSON path reads as NULL
+                    matches!(op, Operator::IsNull)
                 }
             },
             Filter::And(left, right) => {
@@ -586,87 +1314,86 @@ impl StructuredStore {
             Filter::Or(left, right) => {
                 self.evaluate_filter(left, row, columns) || self.evaluate_filter(right, row, columns)
             }
+            Filter::Not(inner) => !self.evaluate_filter(inner, row, columns),
+            Filter::Computed(expr, op, value) => {
+                // The literal takes the type of the computed value
+                let val = eval::eval(expr, &|col| self.resolve_json_path(row, columns, col).map_or(UnifiedValue::Null, |(v, _)| v));
+                self.evaluate_condition(&val, value, &Self::value_data_type(&val), op)
+            }
         }
     }
 
-    fn get_optimized_indices(&self, table_name: &str, filter: &Filter) -> Option<Vec<u64>> {
-        match filter {
-            Filter::Condition(col, op, val) => {
-                 // JSON path expressions (column->path) - check for JSON path index
-                 let is_json_path = col.contains("->");
-                 
-                 // Determine the target value type
-                 let target = if is_json_path {
-                     // For JSON paths, parse the target value based on what it looks like
-                     // Since we extract the actual type from JSON, we need to parse accordingly
-                     if let Ok(i) = val.parse::<i64>() {
-                         UnifiedValue::Integer(i)
-                     } else if let Ok(f) = val.parse::<f64>() {
-                         UnifiedValue::Float(f)
-                     } else if val == "true" || val == "false" {
-                         UnifiedValue::Boolean(val.parse().unwrap_or(false))
-                     } else {
-                         UnifiedValue::String(val.to_string())
-                     }
-                 } else {
-                     // Regular column - get type from table schema
-                     let col_type = if let Some(table_lock) = self.tables.get(table_name) {
-                         if let Ok(table) = table_lock.read() {
-                             table.columns.iter().find(|c| c.name == *col)?.data_type.clone()
-                         } else { return None; }
-                     } else { return None; };
-
-                     match col_type {
-                        DataType::Integer => UnifiedValue::Integer(val.parse().unwrap_or(0)),
-                        DataType::Float => UnifiedValue::Float(val.parse().unwrap_or(0.0)),
-                        DataType::Boolean => UnifiedValue::Boolean(val.parse().unwrap_or(false)),
-                        DataType::String => UnifiedValue::String(val.to_string()),
-                        DataType::DateTime => UnifiedValue::DateTime(val.parse().unwrap_or(0)),
-                        DataType::Blob => UnifiedValue::Blob(val.to_string()),
-                        DataType::Json => serde_json::from_str::<serde_json::Value>(val)
-                            .map(UnifiedValue::from)
-                            .unwrap_or(UnifiedValue::Null),
-                        DataType::Vector => UnifiedValue::Null,
-                    }
-                 };
-
-                // a. Try Equality Index (Hash) - works for both regular and JSON path
-                if matches!(op, Operator::Eq) {
-                    if let Some(table_indexes) = self.indexes.get(table_name) {
-                        if let Some(col_index) = table_indexes.get(col) {
-                            if let Some(row_indices) = col_index.get(&target) {
-                                return Some(row_indices.clone());
-                            } else {
-                                return Some(Vec::new());
-                            }
-                        }
-                    }
-                }
-                
-                // b. Try Range Index (B-Tree) - works for both regular and JSON path
-                if matches!(op, Operator::Gt | Operator::Gte | Operator::Lt | Operator::Lte) {
-                    if let Some(table_ranges) = self.range_indexes.get(table_name) {
-                        if let Some(col_range_lock) = table_ranges.get(col) {
-                            if let Ok(btree) = col_range_lock.read() {
-                                let row_indices: Vec<u64> = match op {
-                                    Operator::Gt => btree.range((Excluded(target), Unbounded)).flat_map(|(_, v)| v).cloned().collect(),
-                                    Operator::Gte => btree.range((Included(target), Unbounded)).flat_map(|(_, v)| v).cloned().collect(),
-                                    Operator::Lt => btree.range((Unbounded, Excluded(target))).flat_map(|(_, v)| v).cloned().collect(),
-                                    Operator::Lte => btree.range((Unbounded, Included(target))).flat_map(|(_, v)| v).cloned().collect(),
-                                    _ => unreachable!(),
-                                };
-                                return Some(row_indices);
-                            }
-                        }
-                    }
+    /// Converts a filter literal into the value type stored in the index.
+    fn index_target(&self, columns: &[Column], col: &str, val: &str) -> Option<UnifiedValue> {
+        if col.contains("->") {
+            // For JSON paths, parse the target value based on what it looks like
+            // Since we extract the actual type from JSON, we need to parse accordingly
+            return Some(if let Ok(i) = val.parse::<i64>() {
+                UnifiedValue::Integer(i)
+            } else if let Ok(f) = val.parse::<f64>() {
+                UnifiedValue::Float(f)
+            } else if val == "true" || val == "false" {
+                UnifiedValue::Boolean(val.parse().unwrap_or(false))
+            } else {
+                UnifiedValue::String(val.to_string())
+            });
+        }
+
+        // Regular column - get type from table schema
+        let col_type = &columns.iter().find(|c| c.name == *col)?.data_type;
+        Some(Self::literal_value(col_type, val))
+    }
+
+    /// Executes a planned access path, returning candidate row ids.
+    /// `None` means every row is a candidate (full scan).
+    fn resolve_access_path(&self, table_name: &str, columns: &[Column], access: &AccessPath) -> Option<Vec<u64>> {
+        if self.unindexed.contains(table_name) {
+            return None;
+        }
+        match access {
+            AccessPath::FullScan => None,
+            AccessPath::RangeIndex { column, op: Operator::Like, value } => {
+                let prefix = like::literal_prefix(value);
+                let table_ranges = self.range_indexes.get(table_name)?;
+                let col_range_lock = table_ranges.get(column)?;
+                let btree = col_range_lock.read().ok()?;
+                Some(btree.range((Included(UnifiedValue::String(prefix.to_string())), Unbounded))
+                    .take_while(|(key, _)| matches!(key, UnifiedValue::String(s) if s.starts_with(prefix)))
+                    .flat_map(|(_, v)| v).cloned().collect())
+            }
+            AccessPath::HashIndex { column, value } => {
+                let target = self.index_target(columns, column, value)?;
+                let table_indexes = self.indexes.get(table_name)?;
+                let col_index = table_indexes.get(column)?;
+                Some(col_index.get(&target).map(|ids| ids.clone()).unwrap_or_default())
+            }
+            AccessPath::RangeIndex { column, op: Operator::Between, value } => {
+                let (low, high) = between_bounds(value)?;
+                let (low, high) = (self.index_target(columns, column, &low)?, self.index_target(columns, column, &high)?);
+                if low > high {
+                    return Some(Vec::new());
                 }
-                None
+                let table_ranges = self.range_indexes.get(table_name)?;
+                let col_range_lock = table_ranges.get(column)?;
+                let btree = col_range_lock.read().ok()?;
+                Some(btree.range(low..=high).flat_map(|(_, v)| v).cloned().collect())
             }
-            Filter::And(left, right) => {
-                let left_indices = self.get_optimized_indices(table_name, left);
-                let right_indices = self.get_optimized_indices(table_name, right);
-                
-                match (left_indices, right_indices) {
+            AccessPath::RangeIndex { column, op, value } => {
+                let target = self.index_target(columns, column, value)?;
+                let table_ranges = self.range_indexes.get(table_name)?;
+                let col_range_lock = table_ranges.get(column)?;
+                let btree = col_range_lock.read().ok()?;
+                let row_indices: Vec<u64> = match op {
+                    Operator::Gt => btree.range((Excluded(target), Unbounded)).flat_map(|(_, v)| v).cloned().collect(),
+                    Operator::Gte => btree.range((Included(target), Unbounded)).flat_map(|(_, v)| v).cloned().collect(),
+                    Operator::Lt => btree.range((Unbounded, Excluded(target))).flat_map(|(_, v)| v).cloned().collect(),
+                    Operator::Lte => btree.range((Unbounded, Included(target))).flat_map(|(_, v)| v).cloned().collect(),
+                    _ => return None,
+                };
+                Some(row_indices)
+            }
+            AccessPath::Intersect(left, right) => {
+                match (self.resolve_access_path(table_name, columns, left), self.resolve_access_path(table_name, columns, right)) {
                     (Some(l), Some(r)) => {
                         let r_set: std::collections::HashSet<u64> = r.into_iter().collect();
                         Some(l.into_iter().filter(|i| r_set.contains(i)).collect())
@@ -676,11 +1403,8 @@ impl StructuredStore {
                     (None, None) => None,
                 }
             }
-            Filter::Or(left, right) => {
-                let left_indices = self.get_optimized_indices(table_name, left);
-                let right_indices = self.get_optimized_indices(table_name, right);
-                
-                match (left_indices, right_indices) {
+            AccessPath::Union(left, right) => {
+                match (self.resolve_access_path(table_name, columns, left), self.resolve_access_path(table_name, columns, right)) {
                     (Some(l), Some(r)) => {
                         let mut set: std::collections::HashSet<u64> = l.into_iter().collect();
                         set.extend(r);
@@ -692,123 +1416,238 @@ impl StructuredStore {
         }
     }
 
+    fn get_optimized_indices(&self, table_name: &str, table: &Table, filter: &Filter) -> Option<Vec<u64>> {
+        let access = planner::plan_access(self, table_name, filter);
+        if access == AccessPath::FullScan {
+            self.advisor.record_full_scan(self, table_name, filter, |col| table.columns.iter().any(|c| c.name == col));
+        }
+        self.resolve_access_path(table_name, &table.columns, &access)
+    }
+
+    /// `CREATE INDEX` statements for columns that full scans keep filtering on.
+    pub fn suggest_indexes(&self) -> Vec<Suggestion> {
+        self.advisor.suggest(self)
+    }
+
+    /// Describes how a SELECT would be executed (EXPLAIN).
+    #[allow(clippy::too_many_arguments)]
+    pub fn explain(
+        &self,
+        table_name: &str,
+        alias: Option<&str>,
+        join: Option<&[JoinClause]>,
+        filter: Option<&Filter>,
+        group_by: Option<&[String]>,
+        order_by: Option<&[(String, bool)]>,
+        limit: Option<usize>,
+        offset: Option<usize>,
+    ) -> Result<QueryPlan> {
+        if !self.tables.contains_key(table_name) {
+            return Err(anyhow!("Table not found"));
+        }
+        for j in join.unwrap_or(&[]) {
+            if !self.tables.contains_key(&j.table) {
+                return Err(anyhow!("Table {} not found", j.table));
+            }
+        }
+        if join.is_some_and(|j| !j.is_empty()) {
+            return Ok(planner::plan_select(self, table_name, join, filter, group_by, order_by, limit, offset));
+        }
+        // Plan with the columns as `select` reads them
+        let prefix = format!("{}.", alias.unwrap_or(table_name));
+        let unqualify = Self::unqualifier(&prefix);
+        let filter = filter.cloned().map(|f| f.map_columns(&unqualify));
+        Ok(planner::plan_select(self, table_name, join, filter.as_ref(), group_by, order_by, limit, offset))
+    }
+
+    /// Strips the `table.` or `alias.` qualifier a single-table query may put on its columns.
+    fn unqualifier(prefix: &str) -> impl Fn(String) -> String + '_ {
+        move |col| col.strip_prefix(prefix).map(str::to_string).unwrap_or(col)
+    }
+
     pub fn select(
         &self, 
         table_name: &str, 
+        alias: Option<&str>,
         selector: Selector,
         join: Option<Vec<JoinClause>>,
         filter: Option<Filter>,
         group_by: Option<Vec<String>>,
         having: Option<Filter>,
-        order_by: Option<(String, bool)>,
+        order_by: Option<Vec<(String, bool)>>,
         limit: Option<usize>,
         offset: Option<usize>
-    ) -> Result<Vec<Vec<String>>> {
+    ) -> Result<ResultSet> {
+        // Aggregates have no value for a single row
+        let projects_aggregate = matches!(&selector, Selector::Columns(projs) | Selector::Distinct(projs)
+            if projs.iter().any(|p| !p.expr.aggregates().is_empty()));
+        if projects_aggregate || filter.as_ref().is_some_and(|f| !f.aggregates().is_empty()) {
+            return Err(anyhow!("Aggregates like COUNT(*) can only be selected alone or used in HAVING"));
+        }
+
+        let qualifier = alias.unwrap_or(table_name);
         if let Some(ref joins) = join {
             if !joins.is_empty() {
-                return self.select_joined(table_name, selector, joins, filter, group_by, having, order_by, limit, offset);
+                return self.select_joined(table_name, qualifier, selector, joins, filter, group_by, having, order_by, limit, offset);
             }
         }
 
+        let prefix = format!("{}.", qualifier);
+        let unqualify = Self::unqualifier(&prefix);
+        let selector = selector.map_columns(&unqualify);
+        let filter = filter.map(|f| f.map_columns(&unqualify));
+        let group_by = group_by.map(|cols| cols.into_iter().map(&unqualify).collect::<Vec<_>>());
+        let having = having.map(|f| f.map_columns(&unqualify));
+        let order_by = order_by.map(|keys| keys.into_iter().map(|(col, asc)| (unqualify(col), asc)).collect::<Vec<_>>());
+
         if let Some(table_lock) = self.tables.get(table_name) {
             let table = table_lock.read().map_err(|_| anyhow!("Lock poison"))?;
+            let is_aggregate_selector = matches!(selector, Selector::Count | Selector::CountDistinct(_) | Selector::Sum(_) | Selector::Avg(_) | Selector::Max(_) | Selector::Min(_));
+            // Rows come out in their final order when nothing groups, sorts or
+            // dedups them, so the scan skips OFFSET and stops after LIMIT: a page
+            // (or a cursor's FETCH) never holds more rows than it returns
+            let paged_scan = group_by.is_none() && order_by.is_none() && !is_aggregate_selector && !matches!(selector, Selector::Distinct(_));
+            let (skip, take) = if paged_scan { (offset.unwrap_or(0), limit.unwrap_or(usize::MAX)) } else { (0, usize::MAX) };
+            let offset = offset.filter(|_| !paged_scan);
             
             // 1. Filter (WHERE) - Try optimized index traversal
+            let started = Instant::now();
+            let mut index_hits = None;
             let mut rows: Vec<Vec<UnifiedValue>> = if let Some(ref f) = filter {
-                if let Some(row_indices) = self.get_optimized_indices(table_name, f) {
+                if let Some(row_indices) = self.get_optimized_indices(table_name, &table, f) {
+                    index_hits = Some(row_indices.len());
                     // Use optimized candidates
                     row_indices.iter()
+                        .take_while(|_| !queries::cancelled())
                         .filter_map(|&id| table.rows.get(&id))
                         .filter(|row| self.evaluate_filter(f, row, &table.columns))
+                        .skip(skip)
+                        .take(take)
                         .cloned()
                         .collect()
                 } else {
                     // Fall back to full scan
                     table.rows.values()
+                        .take_while(|_| !queries::cancelled())
                         .filter(|row| self.evaluate_filter(f, row, &table.columns))
+                        .skip(skip)
+                        .take(take)
                         .cloned()
                         .collect()
                 }
             } else {
-                table.rows.values().cloned().collect()
+                table.rows.values().skip(skip).take(take).cloned().collect()
             };
+            queries::checkpoint()?;
+            profile::stage(started, || format!("Access {}", table_name), index_hits.unwrap_or(table.rows.len()), rows.len(), || {
+                Some(index_hits.map_or("full scan".to_string(), |hits| format!("index hits: {}", hits)))
+            });
 
             // 2. Grouping & Aggregation
-            let is_aggregate_selector = matches!(selector, Selector::Count | Selector::Sum(_) | Selector::Avg(_) | Selector::Max(_) | Selector::Min(_));
-            
             if let Some(ref group_cols) = group_by {
-                // Determine indices of grouping columns
-                let mut group_indices = Vec::new();
+                // Group keys are columns or JSON paths into one (`data->country`)
                 for col in group_cols {
-                    if let Some(idx) = table.columns.iter().position(|c| c.name == *col) {
-                        group_indices.push(idx);
-                    } else {
+                    let base = col.split("->").next().unwrap_or(col);
+                    if !table.columns.iter().any(|c| c.name == base) {
                         return Err(anyhow!("Group column '{}' not found", col));
                     }
                 }
 
                 // Partition into buckets
+                let started = Instant::now();
+                let rows_in = rows.len();
                 let mut buckets: std::collections::HashMap<Vec<UnifiedValue>, Vec<Vec<UnifiedValue>>> = std::collections::HashMap::new();
                 
                 for row in rows {
-                    let key: Vec<UnifiedValue> = group_indices.iter().map(|&i| row[i].clone()).collect();
+                    let key: Vec<UnifiedValue> = group_cols.iter()
+                        .map(|col| self.resolve_json_path(&row, &table.columns, col).map_or(UnifiedValue::Null, |(v, _)| v))
+                        .collect();
                     buckets.entry(key).or_insert_with(Vec::new).push(row);
                 }
 
-                // Aggregate each bucket
+                // Aggregate each bucket, keeping the groups that pass HAVING
                 rows = Vec::new();
                 for (key, bucket_rows) in buckets {
                     let agg_val = self.compute_aggregate(&selector, &bucket_rows, &table.columns)?;
+                    if let Some(ref having_filter) = having {
+                        let aggregate = |agg: &Selector| self.compute_aggregate(agg, &bucket_rows, &table.columns);
+                        if !self.having_matches(having_filter, group_cols, &key, &agg_val, aggregate)? {
+                            continue;
+                        }
+                    }
                     // Result Row schema: [Group Col 1, Group Col 2, ..., Aggregate Value]
                     let mut res_row = key;
                     res_row.push(agg_val);
                     rows.push(res_row);
                 }
-
-                // HAVING: Filter aggregated results
-                if let Some(having_filter) = having {
-                    // HAVING filters on the aggregated column (last column in result row)
-                    let agg_col_idx = rows.first().map(|r| r.len().saturating_sub(1)).unwrap_or(0);
-                    rows.retain(|row| {
-                        if let Some(agg_val) = row.get(agg_col_idx) {
-                             // Create a temporary column definition for the aggregate value
-                             // We assume it's a Number (Int or Float) for now based on aggregation
-                            let agg_type = match agg_val {
-                                UnifiedValue::Integer(_) => DataType::Integer,
-                                UnifiedValue::Float(_) => DataType::Float,
-                                _ => DataType::String,
-                             };
-                            
-                            match &having_filter {
-                                Filter::Condition(_, op, value) => {
-                                    self.evaluate_condition(agg_val, value, &agg_type, op)
-                                }
-                                _ => true, // Complex filters not supported in HAVING yet
-                            }
-                        } else {
-                            false
-                        }
-                    });
-                }
+                profile::stage(started, || format!("Group By {}", group_cols.join(", ")), rows_in, rows.len(), || None);
 
             } else if is_aggregate_selector {
                 // Global aggregation
+                let started = Instant::now();
                 let agg_val = self.compute_aggregate(&selector, &rows, &table.columns)?;
+                profile::stage(started, || "Aggregate".to_string(), rows.len(), 1, || None);
                 rows = vec![vec![agg_val]];
             }
 
-            // 3. Order
-            if !is_aggregate_selector && group_by.is_none() {
-                 if let Some((col_name, ascending)) = order_by {
-                    if let Some(col_idx) = table.columns.iter().position(|c| c.name == col_name) {
-                        rows.sort_by(|a, b| {
-                            let cmp = a[col_idx].cmp(&b[col_idx]);
-                            if ascending { cmp } else { cmp.reverse() }
-                        });
+            // 3. Order (grouped rows can only be ordered by their group columns)
+            if let Some(order_keys) = order_by {
+                let started = Instant::now();
+                if let Some(ref group_cols) = group_by {
+                    let positions = Self::group_order_positions(group_cols, &order_keys)?;
+                    let keys = rows.iter()
+                        .map(|row| positions.iter().map(|&i| row[i].clone()).collect())
+                        .collect();
+                    rows = Self::sort_by_keys(rows, keys, &order_keys);
+                } else if !is_aggregate_selector {
+                    for (col, _) in &order_keys {
+                        let base = col.split("->").next().unwrap_or(col);
+                        if !table.columns.iter().any(|c| c.name == base) {
+                            return Err(anyhow!("Column '{}' not found", col));
+                        }
+                    }
+                    let keys = rows.iter()
+                        .map(|row| order_keys.iter()
+                            .map(|(col, _)| self.resolve_json_path(row, &table.columns, col).map(|(v, _)| v).unwrap_or(UnifiedValue::Null))
+                            .collect())
+                        .collect();
+                    rows = Self::sort_by_keys(rows, keys, &order_keys);
+                }
+                profile::stage(started, || "Sort".to_string(), rows.len(), rows.len(), || None);
+            }
+
+            // Project expressions; DISTINCT keeps the first row of each projected combination
+            if let (Selector::Columns(projs) | Selector::Distinct(projs), None) = (&selector, &group_by) {
+                let clean = |col: &str| -> String {
+                    if let Some(pos) = col.find('.') { col[pos+1..].to_string() } else { col.to_string() }
+                };
+                for col_name in projs.iter().flat_map(|p| p.expr.columns()) {
+                    let clean_name = clean(col_name);
+                    let base = clean_name.split("->").next().unwrap_or(&clean_name);
+                    if !table.columns.iter().any(|c| c.name == base) {
+                        return Err(anyhow!("Column '{}' not found", col_name));
                     }
                 }
+                let started = Instant::now();
+                rows = rows.iter()
+                    .map(|row| projs.iter()
+                        .map(|p| eval::eval(&p.expr, &|col| {
+                            self.resolve_json_path(row, &table.columns, &clean(col)).map(|(v, _)| v).unwrap_or(UnifiedValue::Null)
+                        }))
+                        .collect())
+                    .collect();
+                profile::stage(started, || "Project".to_string(), rows.len(), rows.len(), || None);
+                if matches!(selector, Selector::Distinct(_)) {
+                    let started = Instant::now();
+                    let rows_in = rows.len();
+                    let mut seen = std::collections::HashSet::new();
+                    rows.retain(|row| seen.insert(row.clone()));
+                    profile::stage(started, || "Distinct".to_string(), rows_in, rows.len(), || None);
+                }
             }
 
+            let started = Instant::now();
+            let rows_in = rows.len();
             // 4. Offset
             if let Some(n) = offset {
                 rows = rows.into_iter().skip(n).collect();
@@ -818,35 +1657,19 @@ impl StructuredStore {
             if let Some(n) = limit {
                 rows.truncate(n);
             }
-            
-            // Format to String for return
-            let mut string_rows = Vec::new();
-            for row in rows {
-                if is_aggregate_selector || group_by.is_some() {
-                     string_rows.push(row.iter().map(|v| v.to_string()).collect());
-                } else {
-                    match &selector {
-                        Selector::All => {
-                             string_rows.push(row.iter().map(|v| v.to_string()).collect());
-                        },
-                        Selector::Columns(cols) => {
-                             let mut proj = Vec::new();
-                             for col_name in cols {
-                                 let clean_name = if let Some(pos) = col_name.find('.') { &col_name[pos+1..] } else { col_name };
-                                 if let Some(idx) = table.columns.iter().position(|c| c.name == clean_name) {
-                                     proj.push(row[idx].to_string());
-                                 } else {
-                                      return Err(anyhow!("Column '{}' not found", col_name));
-                                 }
-                             }
-                             string_rows.push(proj);
-                        },
-                        _ => string_rows.push(row.iter().map(|v| v.to_string()).collect()) 
-                    }
-                }
+            if limit.is_some() || offset.is_some() {
+                profile::stage(started, || "Limit".to_string(), rows_in, rows.len(), || None);
             }
+            
+            // Project and name the result columns
+            let columns = match (&selector, &group_by) {
+                (_, Some(group_cols)) => Self::grouped_columns(group_cols, &selector),
+                (Selector::All, None) => table.columns.iter().map(|c| c.name.clone()).collect(),
+                (Selector::Columns(projs) | Selector::Distinct(projs), None) => projs.iter().map(|p| p.name()).collect(),
+                (_, None) => vec![selector.to_string()],
+            };
 
-            Ok(string_rows)
+            Ok(ResultSet { columns, rows })
         } else {
             Err(anyhow!("Table not found"))
         }
@@ -863,15 +1686,7 @@ impl StructuredStore {
                         return Err(anyhow!("Column '{}' already exists", col_name));
                     }
                     
-                    let data_type = match col_type_str.to_uppercase().as_str() {
-                        "INT" | "INTEGER" => DataType::Integer,
-                        "BOOL" | "BOOLEAN" => DataType::Boolean,
-                        "FLOAT" | "DOUBLE" => DataType::Float,
-                        "DATETIME" | "TIMESTAMP" => DataType::DateTime,
-                        "BLOB" | "BYTES" => DataType::Blob,
-                        "JSON" => DataType::Json,
-                        _ => DataType::String,
-                    };
+                    let data_type = DataType::parse(&col_type_str)?;
 
                     // Add Column
                     table.columns.push(Column {
@@ -879,6 +1694,10 @@ impl StructuredStore {
                         data_type: data_type.clone(),
                         is_primary_key: false, // Cannot add PK via ALTER
                         references: None,      // Simple ADD for now
+                        fk_action: FkAction::NoAction,
+                        not_null: false,
+                        unique: false,
+                        default: None,
                     });
 
                     // Backfill Rows
@@ -890,7 +1709,7 @@ impl StructuredStore {
                         DataType::String => UnifiedValue::String("".to_string()),
                         DataType::Blob => UnifiedValue::Blob("".to_string()),
                         DataType::Json => UnifiedValue::Null,
-                        DataType::Vector => UnifiedValue::Null,
+                        DataType::Vector(_) => UnifiedValue::Null,
                     };
                     
                     for row in table.rows.values_mut() {
@@ -906,6 +1725,9 @@ impl StructuredStore {
                         
                         // Remove Column
                         table.columns.remove(idx);
+                        self.note_references(table_name, &table);
+                        self.advisor.forget_column(table_name, &col_name);
+                        self.remove_indexes_on(table_name, &col_name);
                         
                         // Remove Data
                         for row in table.rows.values_mut() {
@@ -918,157 +1740,380 @@ impl StructuredStore {
                     }
                 }
             }
+            // Column positions moved
+            self.invalidate_layout(table_name);
             Ok(())
         } else {
             Err(anyhow!("Table not found"))
         }
     }
 
-    pub fn update(&self, table_name: &str, filter: Option<Filter>, set: (String, String)) -> Result<()> {
-        if let Some(table_lock) = self.tables.get(table_name) {
-            let mut table = table_lock.write().map_err(|_| anyhow!("Lock poison"))?;
-            
-            let (set_col, set_val) = set;
-            let set_idx = table.columns.iter().position(|c| c.name == set_col)
-                .ok_or(anyhow!("Set column not found"))?;
-            
-            let columns = table.columns.clone();
-
-            // Pre-calculate new value
-            let col_type = &columns[set_idx].data_type;
-            let new_val = match col_type {
-                DataType::Integer => UnifiedValue::Integer(set_val.parse().unwrap_or(0)),
-                DataType::Float => UnifiedValue::Float(set_val.parse().unwrap_or(0.0)),
-                DataType::Boolean => UnifiedValue::Boolean(set_val.parse().unwrap_or(false)),
-                DataType::String => UnifiedValue::String(set_val.clone()),
-                DataType::DateTime => UnifiedValue::DateTime(set_val.parse().unwrap_or(0)),
-                DataType::Blob => UnifiedValue::Blob(set_val.clone()),
-                DataType::Json => serde_json::from_str::<serde_json::Value>(&set_val)
-                    .map(UnifiedValue::from)
-                    .unwrap_or(UnifiedValue::Null),
-                DataType::Vector => UnifiedValue::Null, // Update vector via string? Maybe later.
-            };
+    /// Sets one column on every matching row. Returns the number of rows matched.
+    ///
+    /// The table and the tables its foreign key actions reach stay
+    /// write-locked from the constraint checks until the rows are written.
+    pub fn update(&self, table_name: &str, filter: Option<Filter>, set: (String, Option<String>)) -> Result<usize> {
+        let shared = self.dependent_tables(table_name)?;
+        let mut tables = Self::write_all(&shared)?;
+        let table = tables.get(table_name).ok_or(anyhow!("Table not found"))?;
+
+        let (set_col, set_val) = set;
+        let set_idx = table.columns.iter().position(|c| c.name == set_col)
+            .ok_or(anyhow!("Set column not found"))?;
+        let column = &table.columns[set_idx];
+
+        // Pre-calculate new value
+        let new_val = match set_val {
+            None => UnifiedValue::Null,
+            Some(set_val) => Self::typed_value(column, &set_val)?,
+        };
+        if let UnifiedValue::Vector(v) = &new_val
+            && let Some(index) = self.vector_index_on(table_name, &set_col)
+        {
+            let dims = index.graph.read().map_err(|_| anyhow!("Lock poison"))?.dims;
+            if v.len() != dims {
+                return Err(anyhow!("Constraint violation: Vector for '{}' must have {} dimensions", set_col, dims));
+            }
+        }
+        if column.is_primary_key && matches!(new_val, UnifiedValue::Null) {
+            return Err(anyhow!("Constraint violation: Primary key '{}' cannot be NULL", set_col));
+        }
+        if column.not_null && matches!(new_val, UnifiedValue::Null) {
+            return Err(anyhow!("Constraint violation: Column '{}' cannot be NULL", set_col));
+        }
 
-            // Identify rows to update
-            let mut ids_to_update = Vec::new();
-            for (id, row) in &table.rows {
-                 let matches = if let Some(ref f) = filter {
-                    self.evaluate_filter(f, row, &columns)
-                } else {
-                    true 
-                };
-                if matches {
-                    ids_to_update.push(*id);
+        if let Some((ref ref_table, ref ref_col)) = column.references
+            && !matches!(new_val, UnifiedValue::Null) {
+            self.check_reference(ref_table, ref_col, &new_val)?;
+        }
+
+        // Identify rows to update
+        let ids_to_update = self.matching_ids(table_name, table, filter.as_ref());
+
+        // Unique columns: at most one matched row, and no other row may hold the value
+        if (column.unique || column.is_primary_key) && !matches!(new_val, UnifiedValue::Null) && !ids_to_update.is_empty()
+            && (ids_to_update.len() > 1 || self.value_taken(table_name, table, set_idx, &new_val, Some(ids_to_update[0])))
+        {
+            return Err(anyhow!("Constraint violation: Duplicate value '{}' for unique column '{}'", new_val, set_col));
+        }
+
+        let affected = ids_to_update.len();
+        let mut plan = WritePlan::default();
+        let mut changes = Vec::new();
+        for id in ids_to_update {
+            let old_val = table.rows[&id][set_idx].clone();
+            if old_val != new_val {
+                changes.push(KeyChange { table: table_name.to_string(), column: set_col.clone(), old: old_val, new: Some(new_val.clone()) });
+            }
+            plan.set(table_name, id, set_idx, new_val.clone());
+        }
+
+        // A killed query stops here, before anything is modified
+        self.propagate(&tables, &mut plan, changes)?;
+        queries::checkpoint()?;
+        self.apply(&mut tables, plan);
+        Ok(affected)
+    }
+
+    /// Ids of the rows matching `filter`, in id order. Candidates come from an
+    /// index when the planner finds one, and are re-checked against the filter.
+    fn matching_ids(&self, table_name: &str, table: &Table, filter: Option<&Filter>) -> Vec<u64> {
+        let Some(f) = filter else {
+            return table.rows.keys().copied().collect();
+        };
+        match self.get_optimized_indices(table_name, table, f) {
+            Some(mut candidates) => {
+                candidates.sort_unstable();
+                candidates.dedup();
+                candidates.into_iter()
+                    .take_while(|_| !queries::cancelled())
+                    .filter(|id| table.rows.get(id).is_some_and(|row| self.evaluate_filter(f, row, &table.columns)))
+                    .collect()
+            }
+            None => table.rows.iter()
+                .take_while(|_| !queries::cancelled())
+                .filter(|(_, row)| self.evaluate_filter(f, row, &table.columns))
+                .map(|(id, _)| *id)
+                .collect(),
+        }
+    }
+
+    /// Deletes every matching row. Returns the number of rows matched; rows
+    /// removed by cascading foreign keys are not counted.
+    pub fn delete(&self, table_name: &str, filter: Option<Filter>) -> Result<usize> {
+        let shared = self.dependent_tables(table_name)?;
+        let mut tables = Self::write_all(&shared)?;
+        let table = tables.get(table_name).ok_or(anyhow!("Table not found"))?;
+
+        // 1. Find IDs to delete (no filter = delete all)
+        let ids_to_delete = self.matching_ids(table_name, table, filter.as_ref());
+
+        // 2. Follow foreign key actions, then delete and update indices
+        let affected = ids_to_delete.len();
+        let mut plan = WritePlan::default();
+        let mut changes = Vec::new();
+        for id in ids_to_delete {
+            changes.extend(KeyChange::row_removed(table_name, &table.columns, &table.rows[&id]));
+            plan.deletes.entry(table_name.to_string()).or_default().insert(id);
+        }
+
+        // A killed query stops here, before anything is modified
+        self.propagate(&tables, &mut plan, changes)?;
+        queries::checkpoint()?;
+        self.apply(&mut tables, plan);
+        Ok(affected)
+    }
+
+    /// `table_name` and its [`dependents`](Self::dependents), sorted by name:
+    /// writers locking several tables always lock them in the same order.
+    fn dependent_tables(&self, table_name: &str) -> Result<Vec<(String, SharedTable)>> {
+        if !self.tables.contains_key(table_name) {
+            return Err(anyhow!("Table not found"));
+        }
+        let mut names = self.dependents([table_name]);
+        names.sort();
+        Ok(names.into_iter()
+            .filter_map(|name| {
+                let table = self.tables.get(&name)?.clone();
+                Some((name, table))
+            })
+            .collect())
+    }
+
+    fn write_all(shared: &[(String, SharedTable)]) -> Result<TableGuards<'_>> {
+        shared.iter()
+            .map(|(name, table)| Ok((name.clone(), table.write().map_err(|_| anyhow!("Lock poison"))?)))
+            .collect()
+    }
+
+    /// Foreign keys with a referential action that point at `table.column`;
+    /// the tables holding them are among the locked dependents.
+    fn referencing_fks(tables: &TableGuards, table: &str, column: &str) -> Vec<FkRef> {
+        let mut fks = Vec::new();
+        for (name, child) in tables {
+            for (i, col) in child.columns.iter().enumerate() {
+                if col.fk_action != FkAction::NoAction
+                    && col.references.as_ref().is_some_and(|(t, c)| t == table && c == column) {
+                    fks.push((name.clone(), i, col.fk_action));
                 }
             }
-            
-            for id in ids_to_update {
-                if let Some(row) = table.rows.get_mut(&id) {
-                    let old_val = row[set_idx].clone();
-                    // Update value
-                    row[set_idx] = new_val.clone();
-                    
-                    // Maintain Hash Indexes
-                    if let Some(table_indexes) = self.indexes.get(table_name) {
-                        if let Some(col_index) = table_indexes.get(&set_col) {
-                            // Remove from old
-                            if let Some(mut rows_vec) = col_index.get_mut(&old_val) {
-                                rows_vec.retain(|&x| x != id);
-                            }
-                            // Add to new
-                            col_index.entry(new_val.clone()).or_insert_with(Vec::new).push(id);
+        }
+        fks
+    }
+
+    /// Rows holding `val` in column `col_idx`, through the column's hash
+    /// index, or else a value lookup built by one scan of the table.
+    fn rows_holding(&self, lookups: &mut ValueLookups, table_name: &str, table: &Table, col_idx: usize, val: &UnifiedValue) -> Vec<u64> {
+        if let Some(table_indexes) = self.indexes.get(table_name)
+            && let Some(col_index) = table_indexes.get(&table.columns[col_idx].name)
+        {
+            return col_index.get(val).map(|ids| ids.clone()).unwrap_or_default();
+        }
+        let lookup = lookups.entry((table_name.to_string(), col_idx)).or_insert_with(|| {
+            let mut lookup: HashMap<UnifiedValue, Vec<u64>> = HashMap::new();
+            for (id, row) in &table.rows {
+                lookup.entry(row[col_idx].clone()).or_default().push(*id);
+            }
+            lookup
+        });
+        lookup.get(val).cloned().unwrap_or_default()
+    }
+
+    /// True if a row that survives `plan` holds `val` in column `col_idx`
+    /// once the plan is applied.
+    fn key_still_held(&self, lookups: &mut ValueLookups, plan: &WritePlan, table_name: &str, table: &Table, col_idx: usize, val: &UnifiedValue) -> bool {
+        let moved_in = plan.assigned.get(&(table_name.to_string(), col_idx))
+            .and_then(|values| values.get(val))
+            .is_some_and(|ids| ids.iter().any(|id| !plan.deletes(table_name, *id)));
+        moved_in || self.rows_holding(lookups, table_name, table, col_idx, val).into_iter()
+            .any(|id| !plan.deletes(table_name, id) && plan.planned(table_name, id, col_idx).is_none_or(|v| v == val))
+    }
+
+    /// Applies the referential actions triggered by `changes`, transitively,
+    /// adding the resulting deletions and updates to `plan`. Nothing is
+    /// modified yet, so a RESTRICT, NOT NULL or UNIQUE violation leaves every
+    /// table untouched.
+    fn propagate(&self, tables: &TableGuards, plan: &mut WritePlan, mut changes: Vec<KeyChange>) -> Result<()> {
+        let mut fk_cache: HashMap<(String, String), Vec<FkRef>> = HashMap::new();
+        let mut lookups = ValueLookups::new();
+        while let Some(change) = changes.pop() {
+            if matches!(change.old, UnifiedValue::Null) || change.new.as_ref() == Some(&change.old) {
+                continue;
+            }
+            let fks = fk_cache.entry((change.table.clone(), change.column.clone()))
+                .or_insert_with(|| Self::referencing_fks(tables, &change.table, &change.column))
+                .clone();
+            if fks.is_empty() {
+                continue;
+            }
+            let parent = tables.get(&change.table).ok_or(anyhow!("Table not found"))?;
+            let Some(parent_col) = parent.columns.iter().position(|c| c.name == change.column) else { continue };
+            if self.key_still_held(&mut lookups, plan, &change.table, parent, parent_col, &change.old) {
+                continue;
+            }
+
+            for (child_name, child_col, action) in fks {
+                let child = tables.get(&child_name).ok_or(anyhow!("Table not found"))?;
+                let hits: Vec<u64> = self.rows_holding(&mut lookups, &child_name, child, child_col, &change.old).into_iter()
+                    .filter(|id| !plan.deletes(&child_name, *id))
+                    .collect();
+                if hits.is_empty() {
+                    continue;
+                }
+                let column = &child.columns[child_col];
+
+                match action {
+                    FkAction::NoAction => {}
+                    FkAction::Restrict => {
+                        return Err(anyhow!("Constraint violation: '{}.{}' = '{}' is still referenced by '{}.{}'",
+                            change.table, change.column, change.old, child_name, column.name));
+                    }
+                    FkAction::Cascade if change.new.is_none() => {
+                        for id in hits {
+                            changes.extend(KeyChange::row_removed(&child_name, &child.columns, &child.rows[&id]));
+                            plan.deletes.entry(child_name.clone()).or_default().insert(id);
                         }
                     }
-                     // Maintain Range Indexes
-                    if let Some(table_ranges) = self.range_indexes.get(table_name) {
-                         if let Some(col_range) = table_ranges.get(&set_col) {
-                             if let Ok(mut btree) = col_range.write() {
-                                 // Remove from old
-                                 if let Some(rows_vec) = btree.get_mut(&old_val) {
-                                     rows_vec.retain(|&x| x != id);
-                                 }
-                                 // Add to new
-                                 btree.entry(new_val.clone()).or_insert_with(Vec::new).push(id);
-                             }
+                    FkAction::Cascade | FkAction::SetNull => {
+                        // A cascaded update follows the new key; SET NULL clears the reference
+                        let new = match action {
+                            FkAction::Cascade => change.new.clone().unwrap_or(UnifiedValue::Null),
+                            _ => UnifiedValue::Null,
+                        };
+                        if matches!(new, UnifiedValue::Null) && (column.not_null || column.is_primary_key) {
+                            return Err(anyhow!("Constraint violation: Column '{}' cannot be NULL", column.name));
+                        }
+                        // The new key must stay unique in the child too
+                        if (column.unique || column.is_primary_key) && !matches!(new, UnifiedValue::Null)
+                            && (hits.len() > 1 || self.key_still_held(&mut lookups, plan, &child_name, child, child_col, &new))
+                        {
+                            return Err(anyhow!("Constraint violation: Duplicate value '{}' for unique column '{}.{}'", new, child_name, column.name));
+                        }
+                        for id in hits {
+                            plan.set(&child_name, id, child_col, new.clone());
                         }
+                        changes.push(KeyChange { table: child_name.clone(), column: column.name.clone(), old: change.old.clone(), new: Some(new) });
                     }
                 }
             }
-            Ok(())
-        } else {
-             Err(anyhow!("Table not found"))
         }
+        Ok(())
     }
 
-    pub fn delete(&self, table_name: &str, filter: Option<Filter>) -> Result<()> {
-        if let Some(table_lock) = self.tables.get(table_name) {
-            let mut table = table_lock.write().map_err(|_| anyhow!("Lock poison"))?;
-            let columns = table.columns.clone();
-
-            // 1. Find IDs to delete
-            let mut ids_to_delete = Vec::new();
-            for (id, row) in &table.rows {
-                if let Some(ref f) = filter {
-                     if self.evaluate_filter(f, row, &columns) {
-                         ids_to_delete.push(*id);
-                     }
-                } else {
-                    // No filter = delete all
-                    ids_to_delete.push(*id);
+    /// Executes a write plan: deletions first, then cell updates of surviving rows.
+    fn apply(&self, tables: &mut TableGuards, plan: WritePlan) {
+        for (table_name, ids) in &plan.deletes {
+            let Some(table) = tables.get_mut(table_name) else { continue };
+            for id in ids {
+                self.remove_row(table_name, table, *id);
+            }
+        }
+        for (table_name, rows) in plan.sets {
+            let Some(table) = tables.get_mut(&table_name) else { continue };
+            let deleted = plan.deletes.get(&table_name);
+            for (id, cells) in rows {
+                if deleted.is_some_and(|d| d.contains(&id)) {
+                    continue;
+                }
+                for (col_idx, val) in cells {
+                    self.set_cell(&table_name, table, id, col_idx, val);
                 }
             }
+        }
+    }
 
-            // 2. Delete and Update Indices
-            for id in ids_to_delete {
-                if let Some(row) = table.rows.remove(&id) {
-                    // Maintain Hash Indexes
-                    if let Some(table_indexes) = self.indexes.get(table_name) {
-                         for col_entry in table_indexes.iter() {
-                             let col_name = col_entry.key();
-                             if let Some(col_idx) = columns.iter().position(|c| &c.name == col_name) {
-                                  let val = &row[col_idx];
-                                  if let Some(mut rows_vec) = col_entry.value().get_mut(val) {
-                                      rows_vec.retain(|&x| x != id);
-                                  }
-                             }
-                         }
-                    }
-                    
-                    // Maintain Range Indexes
-                     if let Some(table_ranges) = self.range_indexes.get(table_name) {
-                         for col_entry in table_ranges.iter() {
-                             let col_name = col_entry.key();
-                             if let Some(col_idx) = columns.iter().position(|c| &c.name == col_name) {
-                                  let val = &row[col_idx];
-                                  if let Ok(mut btree) = col_entry.value().write() {
-                                       if let Some(rows_vec) = btree.get_mut(val) {
-                                           rows_vec.retain(|&x| x != id);
-                                       }
-                                  }
-                             }
-                         }
-                    }
+    /// Removes a row and its index entries.
+    fn remove_row(&self, table_name: &str, table: &mut Table, id: u64) {
+        let layout = self.index_layout(table_name, table);
+        let Some(row) = table.rows.remove(&id) else { return };
+        let hash_indexes = self.indexes.get(table_name);
+        let range_indexes = self.range_indexes.get(table_name);
+        for (expr, source) in layout.iter() {
+            let key = source.key(&row);
+            if let Some(col_index) = hash_indexes.as_ref().and_then(|idx| idx.get(expr)) {
+                if let Some(mut rows_vec) = col_index.get_mut(&key) {
+                    rows_vec.retain(|&x| x != id);
                 }
+                // Drop emptied keys so the value can be reused (PK / unique / FK checks)
+                col_index.remove_if(&key, |_, ids| ids.is_empty());
+            }
+            if let Some(range) = range_indexes.as_ref().and_then(|idx| idx.get(expr))
+                && let Ok(mut btree) = range.write()
+                && let Some(rows_vec) = btree.get_mut(&key)
+            {
+                rows_vec.retain(|&x| x != id);
             }
-            Ok(())
-        } else {
-            Err(anyhow!("Table not found"))
         }
-    }
+
+        // Maintain Vector Indexes
+        for index in self.vector_indexes_of(table_name) {
+            index.graph.write().unwrap_or_else(|e| e.into_inner()).remove(id);
+        }
+    }
+
+    /// Overwrites one cell and moves the row between index entries.
+    fn set_cell(&self, table_name: &str, table: &mut Table, id: u64, set_idx: usize, new_val: UnifiedValue) {
+        let set_col = table.columns[set_idx].name.clone();
+        let layout = self.index_layout(table_name, table);
+        let Some(row) = table.rows.get_mut(&id) else { return };
+        // Keys of the indexes on the column, JSON paths into it included
+        let moved: Vec<(&String, UnifiedValue, UnifiedValue)> = {
+            let affected: Vec<_> = layout.iter().filter(|(_, source)| source.column() == Some(set_idx)).collect();
+            let old_keys: Vec<UnifiedValue> = affected.iter().map(|(_, source)| source.key(row)).collect();
+            row[set_idx] = new_val.clone();
+            affected.into_iter().zip(old_keys).map(|((expr, source), old)| (expr, old, source.key(row))).collect()
+        };
+
+        // Maintain Vector Indexes
+        if let Some(index) = self.vector_index_on(table_name, &set_col) {
+            let mut graph = index.graph.write().unwrap_or_else(|e| e.into_inner());
+            match &new_val {
+                UnifiedValue::Vector(v) => { graph.insert(id, v); }
+                _ => graph.remove(id),
+            }
+        }
+
+        let hash_indexes = self.indexes.get(table_name);
+        let range_indexes = self.range_indexes.get(table_name);
+        for (expr, old_key, new_key) in moved {
+            if let Some(col_index) = hash_indexes.as_ref().and_then(|idx| idx.get(expr)) {
+                if let Some(mut rows_vec) = col_index.get_mut(&old_key) {
+                    rows_vec.retain(|&x| x != id);
+                }
+                col_index.remove_if(&old_key, |_, ids| ids.is_empty());
+                col_index.entry(new_key.clone()).or_insert_with(Vec::new).push(id);
+            }
+            if let Some(range) = range_indexes.as_ref().and_then(|idx| idx.get(expr))
+                && let Ok(mut btree) = range.write()
+            {
+                if let Some(rows_vec) = btree.get_mut(&old_key) {
+                    rows_vec.retain(|&x| x != id);
+                }
+                btree.entry(new_key).or_insert_with(Vec::new).push(id);
+            }
+        }
+    }
 
     fn compute_aggregate(&self, selector: &Selector, rows: &Vec<Vec<UnifiedValue>>, columns: &Vec<Column>) -> Result<UnifiedValue> {
+        // Values of the aggregated column or JSON path (`data->amount`) in every row
+        let values = |col: &str| -> Result<Vec<UnifiedValue>> {
+            let base = col.split("->").next().unwrap_or(col);
+            if !columns.iter().any(|c| c.name == base) {
+                return Err(anyhow!("Aggregate column not found"));
+            }
+            Ok(rows.iter().map(|r| self.resolve_json_path(r, columns, col).map_or(UnifiedValue::Null, |(v, _)| v)).collect())
+        };
         match selector {
             Selector::Count => Ok(UnifiedValue::Integer(rows.len() as i64)),
+            Selector::CountDistinct(col) => Ok(Self::count_distinct(values(col)?.iter())),
             Selector::Sum(col) | Selector::Avg(col) | Selector::Max(col) | Selector::Min(col) => {
-                 let col_idx = columns.iter().position(|c| c.name == *col)
-                    .ok_or(anyhow!("Aggregate column not found"))?;
-                
+                 let values = values(col)?;
                  let mut nums: Vec<f64> = Vec::new();
                  let mut ints: Vec<i64> = Vec::new();
                  let mut all_ints = true;
 
-                 for r in rows {
-                     match r[col_idx] {
+                 for val in &values {
+                     match *val {
                          UnifiedValue::Integer(i) => ints.push(i),
                          UnifiedValue::Float(f) => {
                              all_ints = false;
@@ -1097,61 +2142,94 @@ impl StructuredStore {
                      },
                      Selector::Max(_) => {
                          // Re-scan for Max (or sort?)
-                         let max = rows.iter().map(|r| &r[col_idx]).max();
-                         Ok(max.cloned().unwrap_or(UnifiedValue::Null))
+                         Ok(values.into_iter().max().unwrap_or(UnifiedValue::Null))
                      },
                      Selector::Min(_) => {
-                         let min = rows.iter().map(|r| &r[col_idx]).min();
-                         Ok(min.cloned().unwrap_or(UnifiedValue::Null))
+                         Ok(values.into_iter().min().unwrap_or(UnifiedValue::Null))
                      },
                      _ => unreachable!()
                  }
             },
-            Selector::All | Selector::Columns(_) => Err(anyhow!("Cannot aggregate with * or list")),
+            Selector::All | Selector::Columns(_) | Selector::Distinct(_) => Err(anyhow!("Cannot aggregate with * or list")),
         }
     }
 
+    /// COUNT(DISTINCT col): NULLs are not counted.
+    fn count_distinct<'a>(values: impl Iterator<Item = &'a UnifiedValue>) -> UnifiedValue {
+        let unique: std::collections::HashSet<&UnifiedValue> = values
+            .filter(|v| !matches!(v, UnifiedValue::Null))
+            .collect();
+        UnifiedValue::Integer(unique.len() as i64)
+    }
+
     fn select_joined(
         &self,
         table_name: &str,
+        qualifier: &str,
         selector: Selector,
-        joins: &Vec<JoinClause>,
+        joins: &[JoinClause],
         filter: Option<Filter>,
         group_by: Option<Vec<String>>,
         having: Option<Filter>,
-        _order_by: Option<(String, bool)>, 
+        order_by: Option<Vec<(String, bool)>>,
         limit: Option<usize>,
         offset: Option<usize>
-    ) -> Result<Vec<Vec<String>>> {
-        let mut rows = self.scan_table_map(table_name)?;
-        
-        for join in joins {
-            let right_rows = self.scan_table_map(&join.table)?;
-            let mut joined = Vec::new();
-            
-            for l_row in &rows {
-                for r_row in &right_rows {
-                    let l_val = self.resolve_val_map(l_row, &join.on_left);
-                    let r_val = self.resolve_val_map(r_row, &join.on_right);
-                    
-                    if l_val == r_val && l_val != UnifiedValue::Null {
-                        let mut new_row = l_row.clone();
-                        new_row.extend(r_row.clone());
-                        joined.push(new_row);
+    ) -> Result<ResultSet> {
+        // Every table needs its own qualifier; a table joined to itself needs an alias
+        let mut qualifiers = std::collections::HashSet::from([qualifier]);
+        for j in joins {
+            if !qualifiers.insert(j.qualifier()) {
+                return Err(anyhow!("Table name '{}' is used twice; give one an alias", j.qualifier()));
+            }
+        }
+
+        let started = Instant::now();
+        let mut rows = self.scan_table_map(table_name, qualifier)?;
+        profile::stage(started, || format!("Access {}", table_name), rows.len(), rows.len(), || Some("full scan".to_string()));
+
+        // Join order and strategy come from the planner (the same plan EXPLAIN shows)
+        let plan = planner::plan_select(self, table_name, Some(joins), None, None, None, None, None);
+        for (i, step) in plan.joins.iter().enumerate() {
+            let started = Instant::now();
+            let rows_in = rows.len();
+            let detail;
+            rows = match &step.strategy {
+                JoinStrategy::IndexLookup { column } => match self.index_join(&rows, step, column)? {
+                    Some((joined, hits)) => {
+                        detail = format!("index lookup on {}, hits: {} of {} probes", column, hits, rows_in);
+                        joined
                     }
+                    // Index dropped since planning
+                    None => {
+                        let right = self.scan_table_map(&step.table, &step.qualifier)?;
+                        detail = format!("hash join (build: right), {} rows scanned", right.len());
+                        self.hash_join(rows, right, step, true)
+                    }
+                },
+                JoinStrategy::HashJoin { build_right } => {
+                    let right = self.scan_table_map(&step.table, &step.qualifier)?;
+                    detail = format!("hash join (build: {}), {} rows scanned", if *build_right { "right" } else { "left" }, right.len());
+                    self.hash_join(rows, right, step, *build_right)
                 }
-            }
-            rows = joined;
+            };
+            queries::checkpoint()?;
+            profile::stage(started, || format!("Join {} {}", i + 1, step.table), rows_in, rows.len(), || Some(detail));
         }
         
         if let Some(f) = filter {
-            rows.retain(|row| self.evaluate_filter_map(&f, row));
+            let started = Instant::now();
+            let rows_in = rows.len();
+            rows.retain(|row| !queries::cancelled() && self.evaluate_filter_map(&f, row));
+            queries::checkpoint()?;
+            profile::stage(started, || "Filter".to_string(), rows_in, rows.len(), || None);
         }
         
-        let is_aggregate_selector = !matches!(selector, Selector::All | Selector::Columns(_));
+        let is_aggregate_selector = !matches!(selector, Selector::All | Selector::Columns(_) | Selector::Distinct(_));
 
         // GROUP BY Logic for JOINs
         if let Some(group_cols) = group_by {
+            let started = Instant::now();
+            let rows_in = rows.len();
             let mut buckets: HashMap<Vec<UnifiedValue>, Vec<HashMap<String, UnifiedValue>>> = HashMap::new();
             for row in rows {
                 let key: Vec<UnifiedValue> = group_cols.iter()
@@ -1160,62 +2238,109 @@ impl StructuredStore {
                 buckets.entry(key).or_insert_with(Vec::new).push(row);
             }
 
-            let mut agg_results = Vec::new();
+            let mut agg_results: Vec<Vec<UnifiedValue>> = Vec::new();
             for (key, bucket_rows) in buckets {
                 // Compute aggregate using map values
                 let agg_val = self.compute_aggregate_map(&selector, &bucket_rows)?;
                 
-                // Check HAVING
-                let mut matches_having = true;
-                if let Some(ref h_filter) = having {
-                    match h_filter {
-                        Filter::Condition(_, op, val_str) => {
-                             // Simplification: HAVING on aggregate value (last column)
-                             matches_having = self.evaluate_condition(&agg_val, val_str, &DataType::Float, op);
-                        },
-                        _ => {}
-                    }
-                }
-
+                let matches_having = match having {
+                    Some(ref h) => self.having_matches(h, &group_cols, &key, &agg_val, |agg| self.compute_aggregate_map(agg, &bucket_rows))?,
+                    None => true,
+                };
                 if matches_having {
-                    let mut res_row: Vec<String> = key.iter().map(|v| v.to_string()).collect();
-                    res_row.push(agg_val.to_string());
+                    let mut res_row = key;
+                    res_row.push(agg_val);
                     agg_results.push(res_row);
                 }
             }
-            return Ok(self.apply_limit_offset(agg_results, limit, offset));
+            profile::stage(started, || format!("Group By {}", group_cols.join(", ")), rows_in, agg_results.len(), || None);
+            if let Some(order_keys) = order_by {
+                let started = Instant::now();
+                let positions = Self::group_order_positions(&group_cols, &order_keys)?;
+                let keys = agg_results.iter()
+                    .map(|row| positions.iter().map(|&i| row[i].clone()).collect())
+                    .collect();
+                agg_results = Self::sort_by_keys(agg_results, keys, &order_keys);
+                profile::stage(started, || "Sort".to_string(), agg_results.len(), agg_results.len(), || None);
+            }
+            let columns = Self::grouped_columns(&group_cols, &selector);
+            return Ok(ResultSet { columns, rows: self.apply_limit_offset(agg_results, limit, offset) });
 
         } else if is_aggregate_selector {
             // Global aggregation over joined rows
+            let started = Instant::now();
             let agg_val = self.compute_aggregate_map(&selector, &rows)?;
-            return Ok(vec![vec![agg_val.to_string()]]);
+            profile::stage(started, || "Aggregate".to_string(), rows.len(), 1, || None);
+            return Ok(ResultSet { columns: vec![selector.to_string()], rows: vec![vec![agg_val]] });
         }
 
-        let mut results = Vec::new();
-        for row in rows {
-            match &selector {
-                Selector::Columns(cols) => {
-                    let mut proj = Vec::new();
-                    for col in cols {
-                         proj.push(self.resolve_val_map(&row, col).to_string());
+        // Order before LIMIT/OFFSET; the sort is stable, so ties keep join order
+        if let Some(order_keys) = order_by {
+            let started = Instant::now();
+            if let Some(first) = rows.first() {
+                for (col, _) in &order_keys {
+                    let base = col.split("->").next().unwrap_or(col);
+                    if !first.contains_key(base) {
+                        return Err(anyhow!("Column '{}' not found", col));
                     }
-                    results.push(proj);
-                },
-                Selector::All => {
-                    // Collect values from map, prefer qualified names? 
-                    // To keep it simple and stable, let's just return values.
-                    results.push(row.values().map(|v| v.to_string()).collect());
-                },
-                _ => unreachable!() 
+                }
             }
+            let keys = rows.iter()
+                .map(|row| order_keys.iter().map(|(col, _)| self.resolve_val_map(row, col)).collect())
+                .collect();
+            rows = Self::sort_by_keys(rows, keys, &order_keys);
+            profile::stage(started, || "Sort".to_string(), rows.len(), rows.len(), || None);
         }
-        
-        Ok(self.apply_limit_offset(results, limit, offset))
+
+        // `*` expands to the qualified columns of every table, in join order
+        let projs = match &selector {
+            Selector::Columns(projs) | Selector::Distinct(projs) => projs.clone(),
+            _ => {
+                let mut projs = Vec::new();
+                let tables = std::iter::once((table_name, qualifier)).chain(joins.iter().map(|j| (j.table.as_str(), j.qualifier())));
+                for (t, q) in tables {
+                    let table_lock = self.tables.get(t).ok_or(anyhow!("Table {} not found", t))?;
+                    let table = table_lock.read().map_err(|_| anyhow!("Lock poison"))?;
+                    projs.extend(table.columns.iter().map(|c| Projection {
+                        expr: Expr::Column(format!("{}.{}", q, c.name)),
+                        alias: None,
+                    }));
+                }
+                projs
+            }
+        };
+        let columns = projs.iter().map(|p| p.name()).collect();
+
+        let started = Instant::now();
+        let mut results: Vec<Vec<UnifiedValue>> = rows.iter()
+            .map(|row| projs.iter().map(|p| eval::eval(&p.expr, &|col| self.resolve_val_map(row, col))).collect())
+            .collect();
+        profile::stage(started, || "Project".to_string(), rows.len(), results.len(), || None);
+        if matches!(selector, Selector::Distinct(_)) {
+            let started = Instant::now();
+            let rows_in = results.len();
+            let mut seen = std::collections::HashSet::new();
+            results.retain(|row| seen.insert(row.clone()));
+            profile::stage(started, || "Distinct".to_string(), rows_in, results.len(), || None);
+        }
+
+        Ok(ResultSet { columns, rows: self.apply_limit_offset(results, limit, offset) })
+    }
+
+    /// Result column names of a grouped query: group columns, then the aggregate.
+    fn grouped_columns(group_cols: &[String], selector: &Selector) -> Vec<String> {
+        let mut cols = group_cols.to_vec();
+        cols.push(selector.to_string());
+        cols
     }
 
     fn compute_aggregate_map(&self, selector: &Selector, rows: &Vec<HashMap<String, UnifiedValue>>) -> Result<UnifiedValue> {
         match selector {
             Selector::Count => Ok(UnifiedValue::Integer(rows.len() as i64)),
+            Selector::CountDistinct(col) => {
+                let values: Vec<UnifiedValue> = rows.iter().map(|r| self.resolve_val_map(r, col)).collect();
+                Ok(Self::count_distinct(values.iter()))
+            }
             Selector::Sum(col) | Selector::Avg(col) | Selector::Max(col) | Selector::Min(col) => {
                  let mut nums: Vec<f64> = Vec::new();
                  for r in rows {
@@ -1242,33 +2367,125 @@ impl StructuredStore {
         }
     }
 
-    fn apply_limit_offset(&self, rows: Vec<Vec<String>>, limit: Option<usize>, offset: Option<usize>) -> Vec<Vec<String>> {
-        let start = offset.unwrap_or(0);
-        if start >= rows.len() { return Vec::new(); }
-        
-        let end = if let Some(l) = limit {
-            (start + l).min(rows.len())
+    fn apply_limit_offset<T>(&self, rows: Vec<T>, limit: Option<usize>, offset: Option<usize>) -> Vec<T> {
+        if limit.is_none() && offset.is_none() {
+            return rows;
+        }
+        let started = Instant::now();
+        let rows_in = rows.len();
+        let kept: Vec<T> = rows.into_iter().skip(offset.unwrap_or(0)).take(limit.unwrap_or(usize::MAX)).collect();
+        profile::stage(started, || "Limit".to_string(), rows_in, kept.len(), || None);
+        kept
+    }
+
+
+    /// Equi-join by hashing one side. Output keeps the nested-loop order
+    /// (left rows first, then matching right rows in table order).
+    fn hash_join(
+        &self,
+        left: Vec<HashMap<String, UnifiedValue>>,
+        right: Vec<HashMap<String, UnifiedValue>>,
+        step: &JoinStep,
+        build_right: bool,
+    ) -> Vec<HashMap<String, UnifiedValue>> {
+        let mut pairs: Vec<(usize, usize)> = Vec::new();
+
+        if build_right {
+            let mut buckets: HashMap<UnifiedValue, Vec<usize>> = HashMap::new();
+            for (j, r_row) in right.iter().enumerate() {
+                let key = self.resolve_val_map(r_row, &step.right_key);
+                if key != UnifiedValue::Null {
+                    buckets.entry(key).or_default().push(j);
+                }
+            }
+            for (i, l_row) in left.iter().enumerate().take_while(|_| !queries::cancelled()) {
+                if let Some(matches) = buckets.get(&self.resolve_val_map(l_row, &step.left_key)) {
+                    pairs.extend(matches.iter().map(|&j| (i, j)));
+                }
+            }
         } else {
-            rows.len()
-        };
-        
-        rows[start..end].to_vec()
+            let mut buckets: HashMap<UnifiedValue, Vec<usize>> = HashMap::new();
+            for (i, l_row) in left.iter().enumerate() {
+                let key = self.resolve_val_map(l_row, &step.left_key);
+                if key != UnifiedValue::Null {
+                    buckets.entry(key).or_default().push(i);
+                }
+            }
+            for (j, r_row) in right.iter().enumerate().take_while(|_| !queries::cancelled()) {
+                if let Some(matches) = buckets.get(&self.resolve_val_map(r_row, &step.right_key)) {
+                    pairs.extend(matches.iter().map(|&i| (i, j)));
+                }
+            }
+            pairs.sort_unstable();
+        }
+
+        pairs.into_iter()
+            .map(|(i, j)| {
+                let mut row = left[i].clone();
+                row.extend(right[j].clone());
+                row
+            })
+            .collect()
+    }
+
+    /// Equi-join probing the hash index of the joined table for every left row,
+    /// with the number of probes that found a key. Returns None if the index
+    /// no longer exists.
+    fn index_join(
+        &self,
+        left: &[HashMap<String, UnifiedValue>],
+        step: &JoinStep,
+        column: &str,
+    ) -> Result<Option<IndexJoin>> {
+        let lock = self.tables.get(&step.table).ok_or_else(|| anyhow!("Table {} not found", step.table))?;
+        let table = lock.read().map_err(|_| anyhow!("Lock poison"))?;
+        if self.unindexed.contains(&step.table) {
+            return Ok(None);
+        }
+        let Some(table_indexes) = self.indexes.get(&step.table) else { return Ok(None) };
+        let Some(col_index) = table_indexes.get(column) else { return Ok(None) };
+
+        let mut joined = Vec::new();
+        let mut hits = 0;
+        for l_row in left.iter().take_while(|_| !queries::cancelled()) {
+            let key = self.resolve_val_map(l_row, &step.left_key);
+            if key == UnifiedValue::Null {
+                continue;
+            }
+            if let Some(row_ids) = col_index.get(&key) {
+                hits += 1;
+                for id in row_ids.iter() {
+                    if let Some(row_vals) = table.rows.get(id) {
+                        let mut row = l_row.clone();
+                        row.extend(Self::row_to_map(&table, &step.qualifier, row_vals));
+                        joined.push(row);
+                    }
+                }
+            }
+        }
+        Ok(Some((joined, hits)))
     }
 
+    /// A row keyed by `qualifier.column` (the qualifier being the table's name
+    /// or alias) and by the bare column name.
+    fn row_to_map(table: &Table, qualifier: &str, row_vals: &[UnifiedValue]) -> HashMap<String, UnifiedValue> {
+        let mut map = HashMap::new();
+        for (i, col) in table.columns.iter().enumerate() {
+            map.insert(format!("{}.{}", qualifier, col.name), row_vals[i].clone());
+            map.insert(col.name.clone(), row_vals[i].clone());
+        }
+        map
+    }
 
-    fn scan_table_map(&self, table_name: &str) -> Result<Vec<HashMap<String, UnifiedValue>>> {
+    fn scan_table_map(&self, table_name: &str, qualifier: &str) -> Result<Vec<HashMap<String, UnifiedValue>>> {
         if let Some(lock) = self.tables.get(table_name) {
             let table = lock.read().map_err(|_| anyhow!("Lock poison"))?;
-            let mut res = Vec::new();
-            for row_vals in table.rows.values() {
-                let mut map = HashMap::new();
-                for (i, col) in table.columns.iter().enumerate() {
-                    map.insert(format!("{}.{}", table.name, col.name), row_vals[i].clone());
-                    map.insert(col.name.clone(), row_vals[i].clone()); 
-                }
-                res.push(map);
-            }
-            Ok(res)
+            let rows = table.rows.values()
+                .take_while(|_| !queries::cancelled())
+                .map(|row_vals| Self::row_to_map(&table, qualifier, row_vals))
+                .collect();
+            queries::checkpoint()?;
+            Ok(rows)
         } else {
             Err(anyhow!("Table {} not found", table_name))
         }
@@ -1278,94 +2495,1408 @@ impl StructuredStore {
         if let Some(v) = row.get(col) {
             return v.clone();
         }
-        // Try arrow?
-        if let Some(pos) = col.find("->") {
-             let base = &col[0..pos];
-             // let path = &col[pos+2..]; 
-             // Logic to extract JSON would go here. 
-             // For now, MVP returns Null if not simple column or if path resolution fails
-             if let Some(_) = row.get(base) {
-                 return UnifiedValue::Null; 
-             }
+        if let Some(pos) = col.find("->")
+            && let Some(base) = row.get(&col[..pos]) {
+            return Self::navigate_json_path(base.clone(), &col[pos..]);
         }
         UnifiedValue::Null
     }
 
+    /// Follows a `->key->0` path into a JSON value; missing steps yield Null.
+    fn navigate_json_path(mut current: UnifiedValue, path: &str) -> UnifiedValue {
+        for key in path.split("->").map(|k| k.trim_start_matches('>')).filter(|k| !k.is_empty()) {
+            current = match &current {
+                UnifiedValue::Object(map) => map.get(key).cloned().unwrap_or(UnifiedValue::Null),
+                UnifiedValue::Array(arr) => key.parse::<usize>().ok()
+                    .and_then(|idx| arr.get(idx).cloned())
+                    .unwrap_or(UnifiedValue::Null),
+                _ => return UnifiedValue::Null,
+            };
+        }
+        current
+    }
+
+    /// Maps ORDER BY keys of a grouped query to positions in its result rows.
+    fn group_order_positions(group_cols: &[String], order_keys: &[(String, bool)]) -> Result<Vec<usize>> {
+        order_keys.iter()
+            .map(|(col, _)| group_cols.iter().position(|g| g == col)
+                .ok_or(anyhow!("ORDER BY column '{}' must appear in GROUP BY", col)))
+            .collect()
+    }
+
+    /// Stable multi-key sort: `keys[i]` holds the ORDER BY values of `rows[i]`.
+    fn sort_by_keys<T>(rows: Vec<T>, keys: Vec<Vec<UnifiedValue>>, order_keys: &[(String, bool)]) -> Vec<T> {
+        let mut keyed: Vec<(Vec<UnifiedValue>, T)> = keys.into_iter().zip(rows).collect();
+        keyed.sort_by(|(a, _), (b, _)| {
+            for (i, (_, ascending)) in order_keys.iter().enumerate() {
+                let cmp = a[i].cmp(&b[i]);
+                if cmp != std::cmp::Ordering::Equal {
+                    return if *ascending { cmp } else { cmp.reverse() };
+                }
+            }
+            std::cmp::Ordering::Equal
+        });
+        keyed.into_iter().map(|(_, row)| row).collect()
+    }
+
     fn evaluate_filter_map(&self, filter: &Filter, row: &HashMap<String, UnifiedValue>) -> bool {
         match filter {
-            Filter::Condition(col, _op, val_str) => {
+            Filter::Condition(col, op, val_str) => {
+                // Same semantics as single-table filters; the literal takes the value's type
                 let val = self.resolve_val_map(row, col);
-                let target = UnifiedValue::String(val_str.clone()); 
-                val == target 
+                let col_type = Self::value_data_type(&val);
+                self.evaluate_condition(&val, val_str, &col_type, op)
             },
             Filter::And(l, r) => self.evaluate_filter_map(l, row) && self.evaluate_filter_map(r, row),
             Filter::Or(l, r) => self.evaluate_filter_map(l, row) || self.evaluate_filter_map(r, row),
+            Filter::Not(inner) => !self.evaluate_filter_map(inner, row),
+            Filter::Computed(expr, op, val_str) => {
+                let val = eval::eval(expr, &|col| self.resolve_val_map(row, col));
+                self.evaluate_condition(&val, val_str, &Self::value_data_type(&val), op)
+            }
         }
     }
 
-    pub fn restore(&self, tables: HashMap<String, Table>) {
+    pub fn restore(&self, tables: HashMap<String, Table>, indexes: HashMap<String, TableIndexes>) {
         self.tables.clear();
         self.indexes.clear();
         self.range_indexes.clear();
+        self.index_names.clear();
+        self.vector_indexes.clear();
+        self.advisor.clear();
+        self.references.clear();
+        self.index_layouts.clear();
         
         for (name, table) in tables {
-            let idx_cols: Vec<String> = table.columns.iter()
-                .filter(|c| c.is_primary_key)
-                .map(|c| c.name.clone())
-                .collect();
+            self.add_table(name.clone(), table, indexes.get(&name));
+        }
+    }
 
-            self.tables.insert(name.clone(), RwLock::new(table));
-            
-            for col in idx_cols {
-                let _ = self.create_index(&name, &col, "HASH");
+    /// Saves `tables` and their [`dependents`](Self::dependents), since
+    /// referential actions can change those too.
+    ///
+    /// The copies are published to queries of other threads until
+    /// [`release`](Self::release) or [`rollback_to`](Self::rollback_to); this
+    /// waits for the queries already reading the live tables.
+    pub fn savepoint<'a>(&self, tables: impl IntoIterator<Item = &'a str>) -> Savepoint {
+        let mut saved = Vec::new();
+        for name in self.dependents(tables) {
+            let table = self.tables.get(&name).and_then(|t| t.read().ok().map(|t| t.clone()));
+            let indexes = self.table_indexes(&name);
+            saved.push((name, table.map(|t| SavedTable { table: Arc::new(RwLock::new(t)), indexes })));
+        }
+        IN_TRANSACTION.set(true);
+        if !saved.is_empty() {
+            let _gate = self.read_gate.write().unwrap_or_else(|e| e.into_inner());
+            let mut published = self.published.lock().unwrap_or_else(|e| e.into_inner());
+            for (name, saved) in &saved {
+                published.insert(name.clone(), saved.as_ref().map(|s| s.table.clone()));
             }
         }
+        Savepoint { tables: saved }
     }
 
-    pub fn vector_search(&self, table_name: &str, col_name: &str, query: &Vec<f64>, limit: usize) -> Result<Vec<String>> {
-        if let Some(table_lock) = self.tables.get(table_name) {
-            let table = table_lock.read().map_err(|_| anyhow!("Lock poison"))?;
-            
-            let col_idx = table.columns.iter().position(|c| c.name == col_name)
-                .ok_or(anyhow!("Column not found"))?;
-            
-            // Collect (similarity, row)
-            let mut candidates: Vec<(f64, &Vec<UnifiedValue>)> = Vec::new();
-            
-            let query_val = UnifiedValue::Vector(query.clone());
+    /// `tables` and every table referencing them through foreign keys,
+    /// directly or not: the tables a write to `tables` can change.
+    pub fn dependents<'a>(&self, tables: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+        let mut pending: Vec<String> = tables.into_iter().map(str::to_string).collect();
+        let mut found = Vec::new();
+        let mut seen = HashSet::new();
+        while let Some(name) = pending.pop() {
+            if !seen.insert(name.clone()) {
+                continue;
+            }
+            for kv in self.references.iter() {
+                if kv.value().contains(&name) {
+                    pending.push(kv.key().clone());
+                }
+            }
+            found.push(name);
+        }
+        found
+    }
 
-            for row in table.rows.values() {
-                let vec_val = &row[col_idx];
-                // Type check handled by cosine_similarity logic (returns None if mismatch)
-                if let Some(score) = vec_val.cosine_similarity(&query_val) {
-                    candidates.push((score, row));
+    /// Ends a savepoint whose transaction went through: queries read the
+    /// tables again.
+    pub fn release(&self, savepoint: Savepoint) {
+        self.unpublish(&savepoint);
+    }
+
+    /// Puts the tables of a savepoint back as they were, rebuilding their
+    /// indexes; tables created since are dropped. A table that still exists
+    /// gets its saved contents swapped in place, so it never goes missing.
+    /// Queries of other threads wait until it is done.
+    pub fn rollback_to(&self, savepoint: Savepoint) {
+        let _gate = self.read_gate.write().unwrap_or_else(|e| e.into_inner());
+        for (name, saved) in &savepoint.tables {
+            let Some(saved) = saved else {
+                self.remove_table(name);
+                continue;
+            };
+            let live = self.tables.get(name).map(|t| t.value().clone());
+            match live {
+                Some(live) => {
+                    let mut live = live.write().unwrap_or_else(|e| e.into_inner());
+                    std::mem::swap(&mut *live, &mut *saved.table.write().unwrap_or_else(|e| e.into_inner()));
+                    self.note_references(name, &live);
+                }
+                None => {
+                    self.note_references(name, &saved.table.read().unwrap_or_else(|e| e.into_inner()));
+                    self.tables.insert(name.clone(), saved.table.clone());
                 }
             }
-            
-            // Sort Descending (Higher similarity first)
-            candidates.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
-            
-            // Take limit
-            let results = candidates.into_iter()
-                .take(limit)
-                .map(|(score, row)| {
-                    // Format row
-                    let vals: Vec<String> = row.iter().map(|v| v.to_string()).collect();
-                    // Append Score? Or pure row?
-                    // Let's return pure row for compatibility with select *
-                    // But usually search needs score. 
-                    // Let's valid JSON format for output?
-                    // Executor expects Vec<String> -> displayed as internal strings
-                    // I'll return SPACE separated for now, maybe with score prepended?
-                    // "(score: 0.99) id name ..."
-                    let row_str = vals.join(" ");
-                    format!("(score: {:.4}) {}", score, row_str) 
+            self.remove_indexes(name);
+            self.rebuild_indexes(name, &saved.indexes);
+        }
+        self.unpublish(&savepoint);
+    }
+
+    /// Forgets a table with its indexes.
+    fn remove_table(&self, name: &str) {
+        self.tables.remove(name);
+        self.references.remove(name);
+        self.remove_indexes(name);
+    }
+
+    fn remove_indexes(&self, name: &str) {
+        self.indexes.remove(name);
+        self.range_indexes.remove(name);
+        self.index_names.remove(name);
+        self.vector_indexes.remove(name);
+        self.invalidate_layout(name);
+    }
+
+    fn unpublish(&self, savepoint: &Savepoint) {
+        IN_TRANSACTION.set(false);
+        let mut published = self.published.lock().unwrap_or_else(|e| e.into_inner());
+        for (name, _) in &savepoint.tables {
+            published.remove(name);
+        }
+    }
+
+    /// The `limit` rows closest to the query vector in a vector column, best
+    /// first, with a leading `score` column. The metric defaults to the
+    /// column's vector index, else cosine; the index answers (approximately)
+    /// when its metric is the one asked for, otherwise rows are compared one
+    /// by one. Rows beyond the threshold (a minimum similarity, or for L2 a
+    /// maximum distance) are left out, and so are rows the filter rejects.
+    pub fn vector_search(&self, table_name: &str, col_name: &str, search: &VectorQuery) -> Result<ResultSet> {
+        let VectorQuery { vector: query, limit, metric, threshold, filter } = *search;
+        let index = self.vector_index_on(table_name, col_name);
+        let (index_metric, index_dims) = index.as_ref().map_or((None, None), |index| {
+            let graph = index.graph.read().unwrap_or_else(|e| e.into_inner());
+            (Some(graph.metric), Some(graph.dims))
+        });
+        let metric = metric.or(index_metric).unwrap_or(Metric::Cosine);
+
+        let table_lock = self.tables.get(table_name).ok_or(anyhow!("Table not found"))?;
+        let table = table_lock.read().map_err(|_| anyhow!("Lock poison"))?;
+        let col_idx = table.columns.iter().position(|c| c.name == col_name)
+            .ok_or(anyhow!("Column not found"))?;
+        let DataType::Vector(declared) = table.columns[col_idx].data_type else {
+            return Err(anyhow!("Column '{}' is not a vector column", col_name));
+        };
+        if query.is_empty() {
+            return Err(anyhow!("Query vector is empty"));
+        }
+        // Without a declared dimension or an index, rows of another length are skipped
+        if let Some(dims) = declared.or(index_dims)
+            && query.len() != dims
+        {
+            return Err(anyhow!("Query vector must have {} dimensions, got {}", dims, query.len()));
+        }
+
+        // The filter goes first, through the hash and range indexes when it can
+        let allowed: Option<HashSet<u64>> = filter.map(|f| self.matching_ids(table_name, &table, Some(f)).into_iter().collect());
+        queries::checkpoint()?;
+        // A transaction's private copy of the table has no indexes to consult,
+        // and a filter keeping few rows is cheaper to rank directly
+        let selective = allowed.as_ref().is_some_and(|ids| ids.len() * 10 <= table.rows.len());
+        let candidates = match &index {
+            Some(index) if index_metric == Some(metric) && !selective && !self.unindexed.contains(table_name) => {
+                let graph = index.graph.read().map_err(|_| anyhow!("Lock poison"))?;
+                let ef = hnsw::EF_SEARCH.max(limit);
+                Some(match &allowed {
+                    Some(ids) => graph.search_filtered(query, limit, ef, |id| ids.contains(&id)),
+                    None => graph.search(query, limit, ef),
                 })
+            }
+            _ => None,
+        };
+
+        let score = |row: &Vec<UnifiedValue>| {
+            let UnifiedValue::Vector(v) = &row[col_idx] else { return None };
+            let score = metric.score(v, query)?;
+            let within = threshold.is_none_or(|t| if metric.higher_is_closer() { score >= t } else { score <= t });
+            within.then_some(score)
+        };
+
+        // Index hits are re-scored exactly from the stored rows
+        let mut hits: Vec<(f64, &Vec<UnifiedValue>)> = match (candidates, &allowed) {
+            (Some(ids), _) => ids.iter()
+                .filter_map(|(id, _)| table.rows.get(id))
+                .filter_map(|row| Some((score(row)?, row)))
+                .collect(),
+            (None, Some(ids)) => ids.iter()
+                .take_while(|_| !queries::cancelled())
+                .filter_map(|id| table.rows.get(id))
+                .filter_map(|row| Some((score(row)?, row)))
+                .collect(),
+            (None, None) => table.rows.values()
+                .take_while(|_| !queries::cancelled())
+                .filter_map(|row| Some((score(row)?, row)))
+                .collect(),
+        };
+        queries::checkpoint()?;
+
+        hits.sort_by(|a, b| if metric.higher_is_closer() { b.0.total_cmp(&a.0) } else { a.0.total_cmp(&b.0) });
+
+        let columns = std::iter::once("score".to_string())
+            .chain(table.columns.iter().map(|c| c.name.clone()))
+            .collect();
+        let rows = hits.into_iter()
+            .take(limit)
+            .map(|(score, row)| std::iter::once(UnifiedValue::Float(score)).chain(row.iter().cloned()).collect())
+            .collect();
+        Ok(ResultSet { columns, rows })
+    }
+
+    /// Rebuilds the hash and range index of `column`, or every index of the table.
+    /// Returns how many indexes were rebuilt.
+    pub fn reindex(&self, table_name: &str, column: Option<&str>) -> Result<usize> {
+        if !self.tables.contains_key(table_name) {
+            return Err(anyhow!("Table not found"));
+        }
+        let mut cols: Vec<String> = Vec::new();
+        if let Some(table_indexes) = self.indexes.get(table_name) {
+            cols.extend(table_indexes.iter().map(|e| e.key().clone()));
+        }
+        if let Some(table_range_indexes) = self.range_indexes.get(table_name) {
+            cols.extend(table_range_indexes.iter().map(|e| e.key().clone()));
+        }
+        cols.sort();
+        cols.dedup();
+        let mut vectors = self.vector_specs(table_name);
+
+        if let Some(col) = column {
+            vectors.retain(|(_, c, _, _)| c == col);
+            cols.retain(|c| c == col);
+            if cols.is_empty() && vectors.is_empty() {
+                return Err(anyhow!("No index on {}.{}", table_name, col));
+            }
+        }
+
+        // create_index replaces both the hash and the range index from current rows
+        for col in &cols {
+            self.create_index("", table_name, col)?;
+        }
+        for (name, col, dims, metric) in &vectors {
+            if let Some(mut table_vectors) = self.vector_indexes.get_mut(table_name) {
+                table_vectors.remove(name);
+            }
+            self.create_vector_index(name, table_name, col, *dims, *metric)?;
+        }
+        Ok(cols.len() + vectors.len())
+    }
+
+    /// Name and constraint of the index CREATE TABLE builds for a primary
+    /// key or unique column.
+    fn constraint_index(table_name: &str, col: &Column) -> Option<(String, &'static str)> {
+        if col.is_primary_key {
+            Some((format!("pk_{}_{}", table_name, col.name), "primary key"))
+        } else if col.unique {
+            Some((format!("uq_{}_{}", table_name, col.name), "unique"))
+        } else {
+            None
+        }
+    }
+
+    /// Every named index of one table or of all tables, sorted by table and
+    /// index name.
+    pub fn list_indexes(&self, table_name: Option<&str>) -> Result<Vec<IndexInfo>> {
+        let tables = match table_name {
+            Some(t) if !self.tables.contains_key(t) => return Err(anyhow!("Table not found")),
+            Some(t) => vec![t.to_string()],
+            None => self.table_names(),
+        };
+        let mut out = Vec::new();
+        for table in tables {
+            let names = self.index_names.get(&table).map(|n| n.clone()).unwrap_or_default();
+            let start = out.len();
+            let constraints: Vec<(String, &'static str)> = self.tables.get(&table)
+                .and_then(|lock| lock.read().ok().map(|t| t.columns.iter().filter_map(|c| Self::constraint_index(&table, c)).collect()))
+                .unwrap_or_default();
+            for (name, column) in names {
+                let constraint = constraints.iter().find(|(n, _)| *n == name).map(|(_, c)| *c);
+                out.push(IndexInfo { table: table.clone(), name, column, constraint, vector: None });
+            }
+            for (name, index) in self.vector_indexes.get(&table).map(|v| v.clone()).unwrap_or_default() {
+                let graph = index.graph.read().unwrap_or_else(|e| e.into_inner());
+                let vector = Some((graph.dims, graph.metric));
+                out.push(IndexInfo { table: table.clone(), name, column: index.column.clone(), constraint: None, vector });
+            }
+            out[start..].sort_by(|a, b| a.name.cmp(&b.name));
+        }
+        Ok(out)
+    }
+
+    /// Drops a named index. The underlying structure goes away with the last
+    /// name on its column; indexes backing a constraint, or the only index on
+    /// a foreign key target, cannot be dropped.
+    pub fn drop_index(&self, index_name: &str, table_name: &str) -> Result<()> {
+        if let Some(mut vectors) = self.vector_indexes.get_mut(table_name)
+            && vectors.remove(index_name).is_some()
+        {
+            return Ok(());
+        }
+        let indexes = self.list_indexes(Some(table_name))?;
+        let IndexInfo { column, constraint, .. } = indexes.iter()
+            .find(|i| i.name == index_name)
+            .ok_or(anyhow!("Index '{}' not found on {}", index_name, table_name))?;
+        if let Some(constraint) = constraint {
+            return Err(anyhow!("Cannot drop index '{}': it enforces the {} constraint on {}.{}", index_name, constraint, table_name, column));
+        }
+
+        let shared = indexes.iter().any(|i| i.column == *column && i.name != index_name && i.vector.is_none());
+        if !shared {
+            let referenced = self.tables.iter().any(|kv| kv.value().read().is_ok_and(|t| {
+                t.columns.iter().any(|c| c.references.as_ref().is_some_and(|(rt, rc)| rt == table_name && rc == column))
+            }));
+            if referenced {
+                return Err(anyhow!("Cannot drop index '{}': {}.{} is referenced by a foreign key", index_name, table_name, column));
+            }
+            self.remove_index_structures(table_name, column);
+        }
+        if let Some(mut names) = self.index_names.get_mut(table_name) {
+            names.remove(index_name);
+        }
+        Ok(())
+    }
+
+    /// Removes every index on a column, JSON paths into it included.
+    fn remove_indexes_on(&self, table_name: &str, column: &str) {
+        if let Some(mut vectors) = self.vector_indexes.get_mut(table_name) {
+            vectors.retain(|_, index| index.column != column);
+        }
+        let Some(mut names) = self.index_names.get_mut(table_name) else { return };
+        let on_column = |col: &str| col.split("->").next() == Some(column);
+        let cols: Vec<String> = names.values().filter(|c| on_column(c)).cloned().collect();
+        names.retain(|_, col| !on_column(col));
+        drop(names);
+        for col in cols {
+            self.remove_index_structures(table_name, &col);
+        }
+    }
+
+    fn remove_index_structures(&self, table_name: &str, column: &str) {
+        if let Some(table_indexes) = self.indexes.get(table_name) {
+            table_indexes.remove(column);
+        }
+        if let Some(table_ranges) = self.range_indexes.get(table_name) {
+            table_ranges.remove(column);
+        }
+        self.invalidate_layout(table_name);
+    }
+
+    /// Digest of a table's columns and rows, whatever their row ids (REPLCHECK).
+    pub fn digest(&self, name: &str) -> Option<u64> {
+        let lock = self.tables.get(name)?;
+        let table = lock.read().ok()?;
+        let mut hashes: Vec<u64> = table.rows.values().map(digest::entry).collect();
+        hashes.push(digest::entry(&table.columns));
+        Some(digest::combine(hashes))
+    }
+
+    pub fn has_table(&self, name: &str) -> bool {
+        self.tables.contains_key(name)
+    }
+
+    pub fn table_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.tables.iter().map(|kv| kv.key().clone()).collect();
+        names.sort();
+        names
+    }
+
+    /// Every table with its column and row counts, sorted by name.
+    pub fn list_tables(&self) -> Vec<TableInfo> {
+        self.table_names().into_iter()
+            .filter_map(|name| {
+                let lock = self.tables.get(&name)?;
+                let table = lock.read().ok()?;
+                Some(TableInfo { columns: table.columns.len(), rows: table.rows.len(), name })
+            })
+            .collect()
+    }
+
+    /// Rough RAM used by the rows and index entries of every table.
+    pub fn memory_bytes(&self) -> usize {
+        self.table_names().iter().filter_map(|name| self.table_memory_bytes(name)).sum()
+    }
+
+    /// Rough RAM used by the rows and index entries of one table.
+    pub fn table_memory_bytes(&self, table_name: &str) -> Option<usize> {
+        let entry = |value: &UnifiedValue, ids: &Vec<u64>| value.approx_size() + ids.len() * std::mem::size_of::<u64>();
+        let mut bytes = 0;
+        {
+            let table_lock = self.tables.get(table_name)?;
+            let table = table_lock.read().ok()?;
+            for row in table.rows.values() {
+                bytes += std::mem::size_of::<u64>() + row.iter().map(|v| v.approx_size()).sum::<usize>();
+            }
+        }
+        if let Some(columns) = self.indexes.get(table_name) {
+            for index in columns.iter() {
+                bytes += index.value().iter().map(|e| entry(e.key(), e.value())).sum::<usize>();
+            }
+        }
+        if let Some(columns) = self.range_indexes.get(table_name) {
+            for index in columns.iter() {
+                if let Ok(index) = index.value().read() {
+                    bytes += index.iter().map(|(value, ids)| entry(value, ids)).sum::<usize>();
+                }
+            }
+        }
+        for index in self.vector_indexes_of(table_name) {
+            bytes += index.graph.read().ok()?.memory_bytes();
+        }
+        Some(bytes)
+    }
+
+    /// Column definitions of a table, in declaration order.
+    pub fn describe_table(&self, table_name: &str) -> Result<Vec<Column>> {
+        let table_lock = self.tables.get(table_name).ok_or(anyhow!("Table not found"))?;
+        let table = table_lock.read().map_err(|_| anyhow!("Lock poison"))?;
+        Ok(table.columns.clone())
+    }
+
+    /// The CREATE TABLE statement that recreates a table, followed by its
+    /// CREATE INDEX statements.
+    pub fn show_create_table(&self, table_name: &str) -> Result<Vec<String>> {
+        let create = self.dump_table_schema(table_name).ok_or(anyhow!("Table not found"))?;
+        let mut statements = vec![create];
+        for index in self.list_indexes(Some(table_name))? {
+            if index.constraint.is_none() {
+                statements.push(index.statement());
+            }
+        }
+        Ok(statements)
+    }
+
+    /// Validates a table against its own rows: hash/range index postings,
+    /// vector index members and foreign key references. Returns one line per discrepancy (empty = consistent).
+    pub fn check_table(&self, table_name: &str) -> Result<Vec<String>> {
+        let table_lock = self.tables.get(table_name).ok_or(anyhow!("Table not found"))?;
+        let table = table_lock.read().map_err(|_| anyhow!("Lock poison"))?;
+        let mut issues = Vec::new();
+
+        // 1. Index postings must match exactly what a fresh build would produce
+        let mut expected_by_index: HashMap<String, BTreeMap<UnifiedValue, Vec<u64>>> = HashMap::new();
+        let mut indexed_cols: Vec<String> = Vec::new();
+        if let Some(table_indexes) = self.indexes.get(table_name) {
+            indexed_cols.extend(table_indexes.iter().map(|e| e.key().clone()));
+        }
+        if let Some(table_range_indexes) = self.range_indexes.get(table_name) {
+            indexed_cols.extend(table_range_indexes.iter().map(|e| e.key().clone()));
+        }
+        indexed_cols.sort();
+        indexed_cols.dedup();
+        for col in indexed_cols {
+            let expected = Self::expected_postings(&table, &col)
+                .ok_or(anyhow!("Index on unknown column '{}'", col))?;
+            expected_by_index.insert(col, expected);
+        }
+
+        if let Some(table_indexes) = self.indexes.get(table_name) {
+            for entry in table_indexes.iter() {
+                let actual: BTreeMap<UnifiedValue, Vec<u64>> = entry.value().iter()
+                    .map(|kv| (kv.key().clone(), kv.value().clone()))
+                    .collect();
+                Self::diff_postings(&format!("hash index {}.{}", table_name, entry.key()), &expected_by_index[entry.key()], &actual, &mut issues);
+            }
+        }
+        if let Some(table_range_indexes) = self.range_indexes.get(table_name) {
+            for entry in table_range_indexes.iter() {
+                let actual = entry.value().read().map_err(|_| anyhow!("Lock poison"))?.clone();
+                Self::diff_postings(&format!("range index {}.{}", table_name, entry.key()), &expected_by_index[entry.key()], &actual, &mut issues);
+            }
+        }
+
+        // Vector indexes must hold exactly the rows with a vector they can index
+        for index in self.vector_indexes_of(table_name) {
+            let col_idx = table.columns.iter().position(|c| c.name == index.column)
+                .ok_or(anyhow!("Index on unknown column '{}'", index.column))?;
+            let graph = index.graph.read().map_err(|_| anyhow!("Lock poison"))?;
+            let expected: Vec<u64> = table.rows.iter()
+                .filter(|(_, row)| matches!(&row[col_idx], UnifiedValue::Vector(v) if graph.accepts(v)))
+                .map(|(id, _)| *id)
                 .collect();
-            
-            Ok(results)
+            let missing = expected.iter().filter(|id| !graph.contains(**id)).count();
+            let extra = graph.len() - (expected.len() - missing);
+            if missing > 0 || extra > 0 {
+                issues.push(format!("vector index {}.{}: {} row(s) missing, {} extra", table_name, index.column, missing, extra));
+            }
+        }
+
+        // 2. Every non-null FK value must exist in the referenced column
+        for (i, col) in table.columns.iter().enumerate() {
+            let Some((ref ref_table, ref ref_col)) = col.references else { continue };
+            let referenced: Option<std::collections::HashSet<UnifiedValue>> = if ref_table == table_name {
+                table.columns.iter().position(|c| &c.name == ref_col)
+                    .map(|ri| table.rows.values().map(|r| r[ri].clone()).collect())
+            } else {
+                match self.tables.get(ref_table) {
+                    Some(lock) => {
+                        let other = lock.read().map_err(|_| anyhow!("Lock poison"))?;
+                        other.columns.iter().position(|c| &c.name == ref_col)
+                            .map(|ri| other.rows.values().map(|r| r[ri].clone()).collect())
+                    }
+                    None => None,
+                }
+            };
+            let Some(referenced) = referenced else {
+                issues.push(format!("foreign key {}.{}: target {}.{} does not exist", table_name, col.name, ref_table, ref_col));
+                continue;
+            };
+            for (row_id, row) in &table.rows {
+                let val = &row[i];
+                if *val != UnifiedValue::Null && !referenced.contains(val) {
+                    issues.push(format!("foreign key {}.{}: row {} references missing {}.{} = {}", table_name, col.name, row_id, ref_table, ref_col, val));
+                }
+            }
+        }
+
+        Ok(issues)
+    }
+
+    /// Postings a fresh index build over `column_expr` would contain, with sorted row ids.
+    fn expected_postings(table: &Table, column_expr: &str) -> Option<BTreeMap<UnifiedValue, Vec<u64>>> {
+        let col_idx = if column_expr.contains("->") {
+            None
         } else {
-            Err(anyhow!("Table not found"))
+            Some(table.columns.iter().position(|c| c.name == column_expr)?)
+        };
+        let mut postings: BTreeMap<UnifiedValue, Vec<u64>> = BTreeMap::new();
+        for (row_id, row) in &table.rows {
+            let val = match col_idx {
+                Some(idx) => row[idx].clone(),
+                None => Self::extract_json_path_value(row, &table.columns, column_expr).unwrap_or(UnifiedValue::Null),
+            };
+            // BTreeMap iteration keeps row ids ascending
+            postings.entry(val).or_default().push(*row_id);
+        }
+        Some(postings)
+    }
+
+    fn diff_postings(label: &str, expected: &BTreeMap<UnifiedValue, Vec<u64>>, actual: &BTreeMap<UnifiedValue, Vec<u64>>, issues: &mut Vec<String>) {
+        for (val, ids) in actual {
+            let want = expected.get(val).map(|v| v.as_slice()).unwrap_or(&[]);
+            for id in ids {
+                if want.binary_search(id).is_err() {
+                    issues.push(format!("{}: stale posting row {} under {}", label, id, val));
+                }
+            }
+        }
+        for (val, ids) in expected {
+            let mut have = actual.get(val).cloned().unwrap_or_default();
+            have.sort_unstable();
+            for id in ids {
+                if have.binary_search(id).is_err() {
+                    issues.push(format!("{}: row {} missing from posting {}", label, id, val));
+                }
+            }
+        }
+    }
+}
+
+impl IndexCatalog for StructuredStore {
+    fn row_count(&self, table: &str) -> usize {
+        self.tables.get(table)
+            .and_then(|lock| lock.read().ok().map(|t| t.rows.len()))
+            .unwrap_or(0)
+    }
+
+    fn has_hash_index(&self, table: &str, column: &str) -> bool {
+        !self.unindexed.contains(table) && self.indexes.get(table).map(|idx| idx.contains_key(column)).unwrap_or(false)
+    }
+
+    fn has_range_index(&self, table: &str, column: &str) -> bool {
+        !self.unindexed.contains(table) && self.range_indexes.get(table).map(|idx| idx.contains_key(column)).unwrap_or(false)
+    }
+
+    fn candidate_count(&self, table: &str, access: &AccessPath) -> usize {
+        let Some(lock) = self.tables.get(table) else { return 0 };
+        let Ok(table_ref) = lock.read() else { return 0 };
+        match self.resolve_access_path(table, &table_ref.columns, access) {
+            Some(ids) => ids.len(),
+            None => table_ref.rows.len(),
+        }
+    }
+}
+
+/// Rows fetched per table lock acquisition while streaming a dump.
+const DUMP_BATCH_ROWS: usize = 256;
+
+/// Lazy AOF rewrite generator: schema, then rows batch by batch, then indexes.
+struct DumpCommands<'a> {
+    store: &'a StructuredStore,
+    tables: std::vec::IntoIter<String>,
+    /// Table being dumped and the last row id already emitted
+    current: Option<(String, Option<u64>)>,
+    pending: VecDeque<String>,
+    indexes_done: bool,
+}
+
+impl Iterator for DumpCommands<'_> {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        loop {
+            if let Some(cmd) = self.pending.pop_front() {
+                return Some(cmd);
+            }
+
+            if let Some((name, after)) = self.current.take() {
+                if let Some(last) = self.store.dump_table_rows(&name, after, &mut self.pending) {
+                    self.current = Some((name, Some(last)));
+                }
+                continue;
+            }
+
+            if let Some(name) = self.tables.next() {
+                // Table may have vanished since the name list was taken
+                if let Some(create) = self.store.dump_table_schema(&name) {
+                    self.pending.push_back(create);
+                    self.current = Some((name, None));
+                }
+                continue;
+            }
+
+            if !self.indexes_done {
+                self.indexes_done = true;
+                self.pending.extend(self.store.dump_index_commands());
+                continue;
+            }
+
+            return None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A nullable column; `id` is the primary key
+    fn column(name: &str, data_type: DataType) -> Column {
+        Column { name: name.into(), data_type, is_primary_key: name == "id", references: None, fk_action: Default::default(), not_null: false, unique: false, default: None }
+    }
+
+    fn row_count(store: &StructuredStore) -> usize {
+        store.select("t", None, Selector::All, None, None, None, None, None, None, None).map_or(0, |r| r.rows.len())
+    }
+
+    #[test]
+    fn queries_do_not_see_a_running_transaction() {
+        let store = StructuredStore::new();
+        store.create_table("t".into(), vec![
+            Column { name: "id".into(), data_type: DataType::Integer, is_primary_key: true, references: None, fk_action: Default::default(), not_null: true, unique: false, default: None },
+        ]).unwrap();
+        store.create_index("by_id", "t", "id").unwrap();
+        store.insert("t", vec![Some("1".into())]).unwrap();
+        let elsewhere = |store: &StructuredStore| std::thread::scope(|s| s.spawn(|| row_count(&store.read_view())).join().unwrap());
+
+        let savepoint = store.savepoint(["t", "new"]);
+        store.insert("t", vec![Some("2".into())]).unwrap();
+        store.create_table("new".into(), Vec::new()).unwrap();
+        assert_eq!(row_count(&store.read_view()), 2);
+        assert_eq!(elsewhere(&store), 1);
+        std::thread::scope(|s| s.spawn(|| assert!(store.read_view().table_names().iter().all(|t| t != "new"))).join().unwrap());
+        store.release(savepoint);
+        assert_eq!(elsewhere(&store), 2);
+
+        let savepoint = store.savepoint(["t"]);
+        store.insert("t", vec![Some("3".into())]).unwrap();
+        store.rollback_to(savepoint);
+        assert_eq!(elsewhere(&store), 2);
+        assert_eq!(row_count(&store.read_view()), 2);
+    }
+
+    #[test]
+    fn rollback_restores_tables_in_place() {
+        let store = StructuredStore::new();
+        store.create_table("t".into(), vec![column("id", DataType::Integer), column("score", DataType::Integer)]).unwrap();
+        store.create_index("by_score", "t", "score").unwrap();
+        store.insert("t", vec![Some("1".into()), Some("10".into())]).unwrap();
+        let live = store.tables.get("t").unwrap().clone();
+        let index_names = || store.list_indexes(Some("t")).unwrap().into_iter().map(|i| i.name).collect::<Vec<_>>();
+        let indexes = index_names();
+        let by_score = |score: &str| {
+            let filter = Filter::Condition("score".into(), Operator::Eq, score.into());
+            store.select("t", None, Selector::All, None, Some(filter), None, None, None, None, None).unwrap().rows.len()
+        };
+
+        let savepoint = store.savepoint(["t", "new"]);
+        store.insert("t", vec![Some("2".into()), Some("20".into())]).unwrap();
+        store.update("t", Some(Filter::Condition("id".into(), Operator::Eq, "1".into())), ("score".into(), Some("30".into()))).unwrap();
+        store.create_table("new".into(), vec![column("id", DataType::Integer)]).unwrap();
+
+        // Another thread keeps reading the table while it is rolled back
+        let done = std::sync::atomic::AtomicBool::new(false);
+        std::thread::scope(|s| {
+            let reader = s.spawn(|| {
+                let mut reads = 0;
+                while !done.load(std::sync::atomic::Ordering::Relaxed) || reads == 0 {
+                    assert_eq!(row_count(&store.read_view()), 1);
+                    reads += 1;
+                }
+            });
+            store.rollback_to(savepoint);
+            done.store(true, std::sync::atomic::Ordering::Relaxed);
+            reader.join().unwrap();
+        });
+
+        assert!(Arc::ptr_eq(&live, &store.tables.get("t").unwrap()));
+        assert_eq!(row_count(&store), 1);
+        assert_eq!((by_score("10"), by_score("20"), by_score("30")), (1, 0, 0));
+        assert!(store.check_table("t").unwrap().is_empty());
+        assert_eq!(index_names(), indexes);
+        assert!(!store.table_names().contains(&"new".to_string()));
+    }
+    #[test]
+    fn batched_inserts_are_all_or_nothing() {
+        let store = StructuredStore::new();
+        store.create_table("t".into(), vec![column("id", DataType::Integer), column("score", DataType::Integer)]).unwrap();
+        store.create_index("by_score", "t", "score").unwrap();
+        let rows = |ids: &[i64]| ids.iter().map(|id| vec![Some(id.to_string()), Some((id * 10).to_string())]).collect::<Vec<_>>();
+
+        assert_eq!(store.insert_many("t", rows(&[1, 2, 3])).unwrap(), 3);
+        // A duplicate within the batch, or against an existing row, rejects the whole batch
+        for batch in [rows(&[4, 5, 4]), rows(&[6, 7, 2])] {
+            assert!(store.insert_many("t", batch).unwrap_err().to_string().contains("Duplicate primary key"));
+        }
+        assert_eq!(row_count(&store.read_view()), 3);
+        assert!(store.check_table("t").unwrap().is_empty());
+        store.insert_many("t", rows(&[4, 5])).unwrap();
+        assert_eq!(row_count(&store.read_view()), 5);
+    }
+
+    #[test]
+    fn json_path_indexes_follow_writes() {
+        let store = StructuredStore::new();
+        let columns = vec![column("id", DataType::Integer), column("note", DataType::String), column("data", DataType::Json)];
+        store.create_table("t".into(), columns).unwrap();
+        let city = |id: i64, city: &str| vec![Some(id.to_string()), Some("n".into()), Some(format!(r#"{{"city": "{}"}}"#, city))];
+        store.insert("t", city(1, "Rome")).unwrap();
+        store.create_index("by_city", "t", "data->city").unwrap();
+        store.insert_many("t", vec![city(2, "Rome"), city(3, "Oslo")]).unwrap();
+        store.update("t", Some(Filter::Condition("id".into(), Operator::Eq, "1".into())), ("data".into(), Some(r#"{"city": "Oslo"}"#.into()))).unwrap();
+        store.delete("t", Some(Filter::Condition("id".into(), Operator::Eq, "3".into()))).unwrap();
+        assert_eq!(store.check_table("t").unwrap(), Vec::<String>::new());
+        let in_city = |name: &str| {
+            let filter = Filter::Condition("data->city".into(), Operator::Eq, name.into());
+            store.select("t", None, Selector::All, None, Some(filter), None, None, None, None, None).unwrap().rows.len()
+        };
+        assert_eq!((in_city("Rome"), in_city("Oslo")), (1, 1));
+
+        // Dropping a column moves the JSON column; writes must follow it
+        store.alter_table("t", AlterOp::Drop("note".into())).unwrap();
+        store.insert("t", vec![Some("4".into()), Some(r#"{"city": "Rome"}"#.into())]).unwrap();
+        assert_eq!(store.check_table("t").unwrap(), Vec::<String>::new());
+        assert_eq!(in_city("Rome"), 2);
+    }
+
+    #[test]
+    fn vector_index_follows_writes() {
+        let store = StructuredStore::new();
+        store.create_table("docs".into(), vec![column("id", DataType::Integer), column("emb", DataType::Vector(None))]).unwrap();
+        for (id, emb) in [(1, "[1, 0]"), (2, "[0, 1]"), (3, "[0.7, 0.7]")] {
+            store.insert("docs", vec![Some(id.to_string()), Some(emb.into())]).unwrap();
+        }
+        store.create_vector_index("by_emb", "docs", "emb", 2, Metric::L2).unwrap();
+        let nearest = |vector: Vec<f64>| {
+            let query = VectorQuery { vector: &vector, limit: 1, metric: None, threshold: None, filter: None };
+            store.vector_search("docs", "emb", &query).unwrap().rows[0][1].clone()
+        };
+        assert_eq!(nearest(vec![0.9, 0.1]), UnifiedValue::Integer(1));
+
+        let by_id = |id: &str| Some(Filter::Condition("id".into(), Operator::Eq, id.into()));
+        store.update("docs", by_id("2"), ("emb".into(), Some("[1, 0.1]".into()))).unwrap();
+        assert_eq!(nearest(vec![1.0, 0.2]), UnifiedValue::Integer(2));
+        store.delete("docs", by_id("2")).unwrap();
+        assert_eq!(nearest(vec![1.0, 0.2]), UnifiedValue::Integer(1));
+
+        let err = store.insert("docs", vec![Some("4".into()), Some("[1, 2, 3]".into())]).unwrap_err();
+        assert!(err.to_string().contains("must have 2 dimensions"));
+        let query = VectorQuery { vector: &[1.0], limit: 1, metric: None, threshold: None, filter: None };
+        assert!(store.vector_search("docs", "emb", &query).is_err());
+
+        let savepoint = store.savepoint(["docs"]);
+        store.insert("docs", vec![Some("5".into()), Some("[1, 0]".into())]).unwrap();
+        store.rollback_to(savepoint);
+        assert!(store.check_table("docs").unwrap().is_empty());
+        let vectors = || store.list_indexes(Some("docs")).unwrap().into_iter().filter_map(|i| i.vector).collect::<Vec<_>>();
+        assert_eq!(vectors(), vec![(2, Metric::L2)]);
+        store.drop_index("by_emb", "docs").unwrap();
+        assert!(vectors().is_empty());
+    }
+
+    #[test]
+    fn vector_search_metrics_and_threshold() {
+        let store = StructuredStore::new();
+        store.create_table("docs".into(), vec![column("id", DataType::Integer), column("emb", DataType::Vector(None))]).unwrap();
+        for (id, emb) in [(1, "[1, 0]"), (2, "[3, 1]"), (3, "[0, 1]")] {
+            store.insert("docs", vec![Some(id.to_string()), Some(emb.into())]).unwrap();
+        }
+        let search = |metric, threshold| {
+            let query = VectorQuery { vector: &[1.0, 0.0], limit: 3, metric, threshold, filter: None };
+            let result = store.vector_search("docs", "emb", &query).unwrap();
+            assert_eq!(result.columns, ["score", "id", "emb"]);
+            result.rows.into_iter().map(|row| (row[1].clone(), row[0].clone())).collect::<Vec<_>>()
+        };
+        let id = UnifiedValue::Integer;
+        assert_eq!(search(None, None)[0], (id(1), UnifiedValue::Float(1.0)));
+        assert_eq!(search(Some(Metric::Dot), None)[0], (id(2), UnifiedValue::Float(3.0)));
+        assert_eq!(search(Some(Metric::L2), None).last().unwrap().0, id(2));
+        assert_eq!(search(Some(Metric::L2), Some(1.0)), vec![(id(1), UnifiedValue::Float(0.0))]);
+        assert_eq!(search(None, Some(0.9)).len(), 2);
+
+        // An index of another metric is bypassed, not misused
+        store.create_vector_index("by_emb", "docs", "emb", 2, Metric::L2).unwrap();
+        assert_eq!(search(None, None)[0].0, id(1));
+        assert_eq!(search(Some(Metric::Dot), None)[0].0, id(2));
+    }
+
+    #[test]
+    fn vector_search_ranks_filtered_rows() {
+        let store = StructuredStore::new();
+        store.create_table("docs".into(), vec![column("id", DataType::Integer), column("price", DataType::Integer), column("emb", DataType::Vector(None))]).unwrap();
+        for id in 0..300 {
+            let angle = id as f64 / 300.0;
+            store.insert("docs", vec![Some(id.to_string()), Some((id % 10).to_string()), Some(format!("[{}, {}]", angle.cos(), angle.sin()))]).unwrap();
+        }
+        let ids = |filter: &Filter| {
+            let query = VectorQuery { vector: &[1.0, 0.0], limit: 3, metric: None, threshold: None, filter: Some(filter) };
+            store.vector_search("docs", "emb", &query).unwrap().rows.into_iter().map(|row| row[1].clone()).collect::<Vec<_>>()
+        };
+        let cheap = Filter::Condition("price".into(), Operator::Lt, "5".into());
+        let rare = Filter::Condition("price".into(), Operator::Eq, "7".into());
+        let expected = |first: [i64; 3]| first.map(UnifiedValue::Integer).to_vec();
+
+        // Brute force, then through the vector index: broad filters walk the
+        // graph, selective ones rank the matching rows directly
+        for indexed in [false, true] {
+            if indexed {
+                store.create_vector_index("by_emb", "docs", "emb", 2, Metric::Cosine).unwrap();
+            }
+            assert_eq!(ids(&cheap), expected([0, 1, 2]));
+            assert_eq!(ids(&rare), expected([7, 17, 27]));
+        }
+    }
+
+    #[test]
+    fn vector_columns_enforce_their_dimension() {
+        let store = StructuredStore::new();
+        store.create_table("docs".into(), vec![column("id", DataType::Integer), column("emb", DataType::parse("vector(2)").unwrap())]).unwrap();
+        store.insert("docs", vec![Some("1".into()), Some("[1, 0]".into())]).unwrap();
+        store.insert("docs", vec![Some("2".into()), None]).unwrap();
+
+        let err = store.insert("docs", vec![Some("3".into()), Some("[1, 0, 0]".into())]).unwrap_err();
+        assert!(err.to_string().contains("must have 2 dimensions, got 3"));
+        let err = store.insert("docs", vec![Some("3".into()), Some("one two".into())]).unwrap_err();
+        assert!(err.to_string().contains("Invalid vector for 'emb'"));
+        let by_id = Some(Filter::Condition("id".into(), Operator::Eq, "1".into()));
+        assert!(store.update("docs", by_id, ("emb".into(), Some("[1]".into()))).is_err());
+        assert!(store.create_vector_index("by_emb", "docs", "emb", 3, Metric::Cosine).is_err());
+
+        let search = |vector: &[f64]| store.vector_search("docs", "emb", &VectorQuery { vector, limit: 1, metric: None, threshold: None, filter: None });
+        assert!(search(&[1.0, 0.0, 0.0]).unwrap_err().to_string().contains("must have 2 dimensions"));
+        assert!(search(&[]).is_err());
+        assert_eq!(search(&[1.0, 0.0]).unwrap().rows.len(), 1);
+
+        assert!(DataType::parse("vector(0)").is_err());
+        assert_eq!(DataType::parse("VECTOR(768)").unwrap().keyword(), "vector(768)");
+        // Snapshots from before declared dimensions
+        let old: Column = serde_json::from_str(r#"{"name":"emb","data_type":"Vector","is_primary_key":false,"references":null}"#).unwrap();
+        assert!(matches!(old.data_type, DataType::Vector(None)));
+        let saved: Column = serde_json::from_str(&serde_json::to_string(&store.describe_table("docs").unwrap()[1]).unwrap()).unwrap();
+        assert!(matches!(saved.data_type, DataType::Vector(Some(2))));
+    }
+
+    #[test]
+    fn not_null_unique_and_default_columns() {
+        let store = StructuredStore::new();
+        store.create_table("t".into(), vec![
+            column("id", DataType::Integer),
+            Column { unique: true, ..column("email", DataType::String) },
+            Column { not_null: true, default: Some("new".into()), ..column("status", DataType::String) },
+            Column { default: Some("7".into()), ..column("score", DataType::Integer) },
+        ]).unwrap();
+        let row = |values: &[Option<&str>]| values.iter().map(|v| v.map(String::from)).collect::<Vec<_>>();
+        let error = |result: Result<usize>| result.unwrap_err().to_string();
+
+        // Omitted trailing columns take their default, typed like the column
+        store.insert("t", row(&[Some("1"), Some("a@x")])).unwrap();
+        let rows = store.select("t", None, Selector::All, None, None, None, None, None, None, None).unwrap().rows;
+        assert_eq!(rows[0][2], UnifiedValue::String("new".into()));
+        assert_eq!(rows[0][3], UnifiedValue::Integer(7));
+
+        assert!(error(store.insert_many("t", vec![row(&[Some("2"), Some("a@x")])])).contains("Duplicate value 'a@x' for unique column 'email'"));
+        // NULLs never collide, and an explicit NULL skips the default
+        store.insert_many("t", vec![row(&[Some("3"), None]), row(&[Some("4"), None, Some("old"), None])]).unwrap();
+        assert!(error(store.insert_many("t", vec![row(&[Some("5"), Some("b@x"), None])])).contains("Column 'status' cannot be NULL"));
+        assert_eq!(row_count(&store), 3);
+
+        let id = |id: i64| Some(Filter::Condition("id".into(), Operator::Eq, id.to_string()));
+        let set = |col: &str, val: Option<&str>| (col.to_string(), val.map(String::from));
+        assert!(error(store.update("t", id(3), set("email", Some("a@x")))).contains("Duplicate value"));
+        // Two rows cannot both take one value, even a free one
+        let many = Some(Filter::Condition("id".into(), Operator::Gte, "3".into()));
+        assert!(error(store.update("t", many, set("email", Some("c@x")))).contains("Duplicate value"));
+        assert_eq!(store.update("t", id(1), set("email", Some("a@x"))).unwrap(), 1);
+        assert_eq!(store.update("t", id(3), set("email", Some("c@x"))).unwrap(), 1);
+        assert!(error(store.update("t", id(1), set("status", None))).contains("Column 'status' cannot be NULL"));
+        assert_eq!(store.update("t", id(1), set("score", None)).unwrap(), 1);
+        assert!(store.check_table("t").unwrap().is_empty());
+    }
+
+    #[test]
+    fn like_uses_the_range_index_for_prefixes() {
+        let store = StructuredStore::new();
+        store.create_table("users".into(), vec![column("id", DataType::Integer), column("name", DataType::String)]).unwrap();
+        for (id, name) in ["ana", "Anabel", "andres", "bob", "an.a", "anx"].iter().enumerate() {
+            store.insert("users", vec![Some(id.to_string()), Some(name.to_string())]).unwrap();
+        }
+        let names = |op: Operator, pattern: &str| {
+            let filter = Filter::Condition("name".into(), op, pattern.into());
+            let rows = store.select("users", None, Selector::All, None, Some(filter), None, None, None, None, None).unwrap().rows;
+            let mut names = rows.into_iter().map(|row| row[1].to_string()).collect::<Vec<_>>();
+            names.sort();
+            names
+        };
+        let plan = |pattern: &str| {
+            let filter = Filter::Condition("name".into(), Operator::Like, pattern.into());
+            store.explain("users", None, None, Some(&filter), None, None, None, None).unwrap().access
+        };
+
+        for indexed in [false, true] {
+            if indexed {
+                store.create_index("by_name", "users", "name").unwrap();
+            }
+            assert_eq!(names(Operator::Like, "an%"), ["an.a", "ana", "andres", "anx"]);
+            assert_eq!(names(Operator::Like, "an.%"), ["an.a"]);
+            assert_eq!(names(Operator::Like, "an_"), ["ana", "anx"]);
+            assert_eq!(names(Operator::ILike, "AN%A%"), ["Anabel", "an.a", "ana"]);
+        }
+        assert!(matches!(plan("an%"), AccessPath::RangeIndex { op: Operator::Like, .. }));
+        assert_eq!(plan("%a"), AccessPath::FullScan);
+    }
+
+    #[test]
+    fn between_and_not() {
+        let store = StructuredStore::new();
+        store.create_table("users".into(), vec![column("id", DataType::Integer), column("age", DataType::Integer)]).unwrap();
+        for (id, age) in [(1, "15"), (2, "18"), (3, "25"), (4, "30"), (5, "31")] {
+            store.insert("users", vec![Some(id.to_string()), Some(age.into())]).unwrap();
+        }
+        store.insert("users", vec![Some("6".into()), None]).unwrap();
+        let ids = |filter: Filter| {
+            let rows = store.select("users", None, Selector::All, None, Some(filter), None, None, None, None, None).unwrap().rows;
+            let mut ids = rows.into_iter().map(|row| row[0].clone()).collect::<Vec<_>>();
+            ids.sort();
+            ids
+        };
+        let between = |low: &str, high: &str| Filter::Condition("age".into(), Operator::Between, crate::query::join_value_list(&[low.into(), high.into()]));
+        let expected = |ids: &[i64]| ids.iter().map(|id| UnifiedValue::Integer(*id)).collect::<Vec<_>>();
+
+        for indexed in [false, true] {
+            if indexed {
+                store.create_index("by_age", "users", "age").unwrap();
+            }
+            assert_eq!(ids(between("18", "30")), expected(&[2, 3, 4]));
+            assert_eq!(ids(between("30", "18")), expected(&[]));
+            // NOT negates the whole condition, so a NULL age fails the inner test and matches
+            assert_eq!(ids(Filter::Not(Box::new(between("18", "30")))), expected(&[1, 5, 6]));
+        }
+        let plan = store.explain("users", None, None, Some(&between("18", "30")), None, None, None, None).unwrap();
+        assert_eq!(plan.scan_rows, 3);
+    }
+
+    #[test]
+    fn computed_conditions() {
+        let store = StructuredStore::new();
+        store.create_table("items".into(), vec![column("id", DataType::Integer), column("name", DataType::String), column("price", DataType::Integer)]).unwrap();
+        for (id, name, price) in [(1, "Bob", "80"), (2, "alice", "90"), (3, "BOB", "-100")] {
+            store.insert("items", vec![Some(id.to_string()), Some(name.into()), Some(price.into())]).unwrap();
+        }
+        let ids = |condition: &str| {
+            let (_, cmd) = crate::net::parser::parse_command(&format!("SELECT * FROM items WHERE {}", condition)).unwrap();
+            let crate::query::Command::Select { filter, .. } = cmd else { panic!("expected SELECT") };
+            let rows = store.select("items", None, Selector::All, None, filter, None, None, None, None, None).unwrap().rows;
+            rows.into_iter().map(|row| row[0].to_string()).collect::<Vec<_>>()
+        };
+        assert_eq!(ids("price * 1.2 > 100"), ["2"]);
+        assert_eq!(ids("LOWER(name) = \"bob\""), ["1", "3"]);
+        assert_eq!(ids("LENGTH(name) > 3"), ["2"]);
+        assert_eq!(ids("ABS(price) >= 90 AND NOT upper(name) = 'ALICE'"), ["3"]);
+        // Non-numeric arithmetic is NULL and matches nothing
+        assert!(ids("name + 1 > 0").is_empty());
+    }
+
+    #[test]
+    fn datetime_literals() {
+        let store = StructuredStore::new();
+        store.create_table("events".into(), vec![column("id", DataType::Integer), column("at", DataType::DateTime)]).unwrap();
+        for (id, at) in [(1, "2023-12-31T23:00:00Z"), (2, "2024-01-01"), (3, "1704153600"), (4, "2024-01-02T12:00:00+02:00")] {
+            store.insert("events", vec![Some(id.to_string()), Some(at.into())]).unwrap();
+        }
+        let err = store.insert("events", vec![Some("5".into()), Some("next tuesday".into())]).unwrap_err();
+        assert!(err.to_string().contains("Invalid datetime for 'at'"));
+        let by_id = Some(Filter::Condition("id".into(), Operator::Eq, "1".into()));
+        assert!(store.update("events", by_id, ("at".into(), Some("2024-02-30".into()))).is_err());
+
+        let ids = |condition: &str| {
+            let (_, cmd) = crate::net::parser::parse_command(&format!("SELECT * FROM events WHERE {}", condition)).unwrap();
+            let crate::query::Command::Select { filter, .. } = cmd else { panic!("expected SELECT") };
+            let rows = store.select("events", None, Selector::All, None, filter, None, None, None, None, None).unwrap().rows;
+            let mut ids = rows.into_iter().map(|row| row[0].to_string()).collect::<Vec<_>>();
+            ids.sort();
+            ids
+        };
+        for indexed in [false, true] {
+            if indexed {
+                store.create_index("by_at", "events", "at").unwrap();
+            }
+            assert_eq!(ids("at >= '2024-01-01'"), ["2", "3", "4"]);
+            assert_eq!(ids("at BETWEEN '2024-01-01T00:00:00Z' AND '2024-01-02'"), ["2", "3"]);
+            assert_eq!(ids("at = '2024-01-02T10:00:00Z'"), ["4"]);
+        }
+        assert_eq!(ids("DATE_ADD(at, 1, HOUR) = '2024-01-01'"), ["1"]);
+        assert_eq!(ids("DATE_DIFF(at, '2024-01-01', DAY) >= 1"), ["3", "4"]);
+        assert_eq!(ids("DATE_DIFF(at, 1704067200, DAY) >= 1"), ["3", "4"]);
+    }
+
+    #[test]
+    fn having_over_named_aggregates() {
+        let store = StructuredStore::new();
+        store.create_table("orders".into(), vec![column("id", DataType::Integer), column("team", DataType::String), column("amount", DataType::Integer)]).unwrap();
+        let orders = [("a", 10), ("a", 20), ("a", 30), ("b", 200), ("b", 5), ("c", 1), ("c", 2), ("c", 3), ("c", 4)];
+        for (id, (team, amount)) in orders.iter().enumerate() {
+            store.insert("orders", vec![Some(id.to_string()), Some(team.to_string()), Some(amount.to_string())]).unwrap();
+        }
+        let query = |sql: &str| {
+            let (_, cmd) = crate::net::parser::parse_command(sql).unwrap();
+            let crate::query::Command::Select { selector, filter, group_by, having, .. } = cmd else { panic!("expected SELECT") };
+            store.select("orders", None, selector, None, filter, group_by, having, Some(vec![("team".into(), true)]), None, None)
+        };
+        let teams = |having: &str| {
+            let rows = query(&format!("SELECT COUNT(*) FROM orders GROUP BY team HAVING {}", having)).unwrap().rows;
+            rows.into_iter().map(|row| row[0].to_string()).collect::<Vec<_>>()
+        };
+        assert_eq!(teams("COUNT(*) > 2 AND SUM(amount) < 100"), ["a", "c"]);
+        assert_eq!(teams("max(amount) >= 100 OR avg(amount) < 5"), ["b", "c"]);
+        assert_eq!(teams("NOT (COUNT(*) = 3 OR MIN(amount) = 1)"), ["b"]);
+        assert_eq!(teams("SUM(amount) / COUNT(*) > 50"), ["b"]);
+        // Group columns read the group's value; other names the selected aggregate
+        assert_eq!(teams("team = 'c' OR count = 2"), ["b", "c"]);
+
+        let summed = query("SELECT SUM(amount) FROM orders GROUP BY team HAVING COUNT(DISTINCT amount) = 4").unwrap();
+        assert_eq!(summed.columns, ["team", "SUM(amount)"]);
+        assert_eq!(summed.rows, vec![vec![UnifiedValue::String("c".into()), UnifiedValue::Integer(10)]]);
+
+        assert!(query("SELECT COUNT(*) FROM orders GROUP BY team HAVING SUM(missing) > 1").is_err());
+        assert!(query("SELECT * FROM orders WHERE COUNT(*) > 1").is_err());
+        assert!(query("SELECT team, SUM(amount) FROM orders GROUP BY team").is_err());
+    }
+
+    #[test]
+    fn group_by_json_paths() {
+        let store = StructuredStore::new();
+        store.create_table("users".into(), vec![column("id", DataType::Integer), column("data", DataType::Json)]).unwrap();
+        let docs = [
+            r#"{"country": "ES", "spent": 10}"#,
+            r#"{"country": "ES", "spent": 15}"#,
+            r#"{"country": "FR", "spent": 7.5}"#,
+            r#"{"spent": 1}"#,
+        ];
+        for (id, doc) in docs.iter().enumerate() {
+            store.insert("users", vec![Some(id.to_string()), Some(doc.to_string())]).unwrap();
+        }
+        let query = |sql: &str| {
+            let (_, cmd) = crate::net::parser::parse_command(sql).unwrap();
+            let crate::query::Command::Select { selector, filter, group_by, having, order_by, .. } = cmd else { panic!("expected SELECT") };
+            store.select("users", None, selector, None, filter, group_by, having, order_by, None, None)
+        };
+        let summed = query("SELECT SUM(data->spent) FROM users GROUP BY data->country ORDER BY data->country").unwrap();
+        assert_eq!(summed.columns, ["data->country", "SUM(data->spent)"]);
+        assert_eq!(summed.rows, vec![
+            vec![UnifiedValue::Null, UnifiedValue::Integer(1)],
+            vec![UnifiedValue::String("ES".into()), UnifiedValue::Integer(25)],
+            vec![UnifiedValue::String("FR".into()), UnifiedValue::Float(7.5)],
+        ]);
+        let counted = query("SELECT COUNT(*) FROM users GROUP BY data->country HAVING data->country = 'ES'").unwrap();
+        assert_eq!(counted.rows, vec![vec![UnifiedValue::String("ES".into()), UnifiedValue::Integer(2)]]);
+        assert!(query("SELECT COUNT(*) FROM users GROUP BY meta->country").is_err());
+    }
+
+    #[test]
+    fn distinct_json_paths() {
+        let store = StructuredStore::new();
+        store.create_table("users".into(), vec![column("id", DataType::Integer), column("data", DataType::Json)]).unwrap();
+        store.create_table("orders".into(), vec![column("id", DataType::Integer), column("user_id", DataType::Integer)]).unwrap();
+        let docs = [
+            r#"{"city": "Rome", "geo": {"zone": 1}}"#,
+            r#"{"city": "Rome", "geo": {"zone": 1}}"#,
+            r#"{"city": "Oslo", "geo": {"zone": 2}}"#,
+            r#"{"geo": {"zone": 2}}"#,
+        ];
+        for (id, doc) in docs.iter().enumerate() {
+            store.insert("users", vec![Some(id.to_string()), Some(doc.to_string())]).unwrap();
+            store.insert("orders", vec![Some(id.to_string()), Some(id.to_string())]).unwrap();
+        }
+        let query = |sql: &str| {
+            let ("", cmd) = crate::net::parser::parse_command(sql).unwrap() else { panic!("unparsed input in {}", sql) };
+            let crate::query::Command::Select { table, alias, selector, join, filter, group_by, having, order_by, limit, offset } = cmd else { panic!("expected SELECT") };
+            store.select(&table, alias.as_deref(), selector, join, filter, group_by, having, order_by, limit, offset).unwrap()
+        };
+        let strings = |rows: Vec<Vec<UnifiedValue>>| rows.into_iter().map(|r| r.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(" ")).collect::<Vec<_>>();
+
+        // A missing path is one more NULL value
+        let cities = query("SELECT DISTINCT data->city FROM users");
+        assert_eq!(cities.columns, ["data->city"]);
+        assert_eq!(strings(cities.rows), ["Rome", "Oslo", "NULL"]);
+        assert_eq!(strings(query("SELECT DISTINCT data->city, data->geo->zone FROM users WHERE id > 0").rows), ["Rome 1", "Oslo 2", "NULL 2"]);
+        assert_eq!(strings(query("SELECT DISTINCT u.data->geo->zone FROM orders o JOIN users u ON o.user_id = u.id").rows), ["1", "2"]);
+        assert_eq!(strings(query("SELECT COUNT(DISTINCT data->city) FROM users").rows), ["2"]);
+        assert_eq!(strings(query("SELECT COUNT(DISTINCT u.data->geo->zone) FROM orders o JOIN users u ON o.user_id = u.id").rows), ["2"]);
+    }
+
+    #[test]
+    fn table_aliases() {
+        let store = StructuredStore::new();
+        store.create_table("staff".into(), vec![column("id", DataType::Integer), column("name", DataType::String), column("manager", DataType::Integer)]).unwrap();
+        for (id, name, manager) in [(1, "ana", "NULL"), (2, "bob", "1"), (3, "eve", "2")] {
+            let manager = (manager != "NULL").then(|| manager.to_string());
+            store.insert("staff", vec![Some(id.to_string()), Some(name.into()), manager]).unwrap();
+        }
+        let query = |sql: &str| {
+            let ("", cmd) = crate::net::parser::parse_command(sql).unwrap() else { panic!("unparsed input in {}", sql) };
+            let crate::query::Command::Select { table, alias, selector, join, filter, group_by, having, order_by, limit, offset } = cmd else { panic!("expected SELECT") };
+            store.select(&table, alias.as_deref(), selector, join, filter, group_by, having, order_by, limit, offset)
+        };
+        let strings = |rows: Vec<Vec<UnifiedValue>>| rows.into_iter().map(|r| r.iter().map(|v| v.to_string()).collect::<Vec<_>>()).collect::<Vec<_>>();
+
+        // Self-join: each side is read through its own alias
+        let pairs = query("SELECT e.name, m.name AS boss FROM staff e JOIN staff m ON e.manager = m.id ORDER BY e.id").unwrap();
+        assert_eq!(pairs.columns, ["e.name", "boss"]);
+        assert_eq!(strings(pairs.rows), [["bob", "ana"], ["eve", "bob"]]);
+        let all = query("SELECT * FROM staff e JOIN staff m ON m.id = e.manager WHERE m.name = 'ana'").unwrap();
+        assert_eq!(all.columns, ["e.id", "e.name", "e.manager", "m.id", "m.name", "m.manager"]);
+        assert_eq!(all.rows.len(), 1);
+        assert!(query("SELECT * FROM staff JOIN staff ON staff.manager = staff.id").is_err());
+
+        // A single table's alias qualifies every clause
+        let one = query("SELECT s.name FROM staff s WHERE s.id >= 2 ORDER BY s.name DESC").unwrap();
+        assert_eq!(strings(one.rows), [["eve"], ["bob"]]);
+        let counted = query("SELECT COUNT(*) FROM staff AS s GROUP BY s.manager HAVING SUM(s.id) >= 2 ORDER BY s.manager").unwrap();
+        assert_eq!(counted.columns, ["manager", "COUNT(*)"]);
+        assert_eq!(strings(counted.rows), [["1", "1"], ["2", "1"]]);
+    }
+
+    #[test]
+    fn chained_joins() {
+        let store = StructuredStore::new();
+        store.create_table("users".into(), vec![column("id", DataType::Integer), column("name", DataType::String), column("country", DataType::String)]).unwrap();
+        store.create_table("orders".into(), vec![column("id", DataType::Integer), column("user_id", DataType::Integer), column("total", DataType::Integer)]).unwrap();
+        store.create_table("items".into(), vec![column("id", DataType::Integer), column("order_id", DataType::Integer), column("sku", DataType::String)]).unwrap();
+        store.create_table("countries".into(), vec![column("code", DataType::String), column("name", DataType::String)]).unwrap();
+        for row in [["1", "ana", "ES"], ["2", "bob", "FR"]] {
+            store.insert("users", row.iter().map(|v| Some(v.to_string())).collect()).unwrap();
+        }
+        for row in [["10", "1", "30"], ["11", "2", "5"], ["12", "1", "7"]] {
+            store.insert("orders", row.iter().map(|v| Some(v.to_string())).collect()).unwrap();
+        }
+        for row in [["100", "10", "pen"], ["101", "10", "ink"], ["102", "11", "cup"], ["103", "12", "pad"]] {
+            store.insert("items", row.iter().map(|v| Some(v.to_string())).collect()).unwrap();
+        }
+        for row in [["ES", "Spain"], ["FR", "France"]] {
+            store.insert("countries", row.iter().map(|v| Some(v.to_string())).collect()).unwrap();
+        }
+        let query = |sql: &str| {
+            let ("", cmd) = crate::net::parser::parse_command(sql).unwrap() else { panic!("unparsed input in {}", sql) };
+            let crate::query::Command::Select { table, alias, selector, join, filter, group_by, having, order_by, limit, offset } = cmd else { panic!("expected SELECT") };
+            let rows = store.select(&table, alias.as_deref(), selector, join, filter, group_by, having, order_by, limit, offset).unwrap().rows;
+            rows.into_iter().map(|r| r.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(" ")).collect::<Vec<_>>()
+        };
+
+        // Written so that the items join refers to orders, which comes after it;
+        // the planner joins orders first. Same-named columns stay apart by qualifier.
+        let rows = query("SELECT u.name, c.name, o.id, i.sku FROM users u \
+            JOIN items i ON i.order_id = o.id JOIN orders o ON o.user_id = u.id JOIN countries c ON c.code = u.country \
+            WHERE o.total > 6 ORDER BY i.sku");
+        assert_eq!(rows, ["ana Spain 10 ink", "ana Spain 12 pad", "ana Spain 10 pen"]);
+
+        let per_country = query("SELECT COUNT(*) FROM users u JOIN orders o ON o.user_id = u.id \
+            JOIN items i ON i.order_id = o.id JOIN countries c ON c.code = u.country GROUP BY c.name ORDER BY c.name");
+        assert_eq!(per_country, ["France 1", "Spain 3"]);
+    }
+
+    #[test]
+    fn joined_filters_work_like_single_table_ones() {
+        let store = StructuredStore::new();
+        store.create_table("users".into(), vec![column("id", DataType::Integer), column("age", DataType::Integer), column("profile", DataType::Json)]).unwrap();
+        store.create_table("orders".into(), vec![column("id", DataType::Integer), column("user_id", DataType::Integer), column("total", DataType::Float)]).unwrap();
+        for (id, age, profile) in [(1, Some("30"), r#"{"city": "Rome"}"#), (2, Some("17"), r#"{"city": "Oslo"}"#), (3, None, "{}")] {
+            store.insert("users", vec![Some(id.to_string()), age.map(String::from), Some(profile.into())]).unwrap();
+        }
+        for (id, user, total) in [(10, 1, "9.5"), (11, 2, "20"), (12, 3, "31.25"), (13, 1, "40")] {
+            store.insert("orders", vec![Some(id.to_string()), Some(user.to_string()), Some(total.into())]).unwrap();
+        }
+        let query = |sql: String| {
+            let ("", cmd) = crate::net::parser::parse_command(&sql).unwrap() else { panic!("unparsed input in {}", sql) };
+            let crate::query::Command::Select { table, alias, selector, join, filter, group_by, having, order_by, limit, offset } = cmd else { panic!("expected SELECT") };
+            let rows = store.select(&table, alias.as_deref(), selector, join, filter, group_by, having, order_by, limit, offset).unwrap().rows;
+            rows.into_iter().map(|r| r[0].to_string()).collect::<Vec<_>>()
+        };
+        let ids = |filter: &str| query(format!("SELECT orders.id FROM orders JOIN users ON orders.user_id = users.id WHERE {} ORDER BY orders.id", filter));
+        let single = |filter: &str| query(format!("SELECT id FROM users WHERE {} ORDER BY id", filter));
+
+        // Literals take the type of the value: numbers compare as numbers
+        assert_eq!(ids("orders.total > 10"), ["11", "12", "13"]);
+        assert_eq!(ids("orders.total BETWEEN 9 AND 21"), ["10", "11"]);
+        assert_eq!(ids("users.age < 18"), ["11"]);
+        assert_eq!(ids("users.age IS NULL"), ["12"]);
+        assert_eq!(ids("users.id IN (2, 3) AND orders.total >= 31.25"), ["12"]);
+        // JSON paths resolve the same on joined rows as on a single table
+        for (filter, users, orders) in [
+            ("profile->city = 'Rome'", vec!["1"], vec!["10", "13"]),
+            ("profile->city LIKE 'O%'", vec!["2"], vec!["11"]),
+            ("profile->city IS NULL", vec!["3"], vec!["12"]),
+            ("profile->city->x IS NULL", vec!["1", "2", "3"], vec!["10", "11", "12", "13"]),
+        ] {
+            assert_eq!(single(filter), users, "{}", filter);
+            assert_eq!(ids(&format!("users.{}", filter)), orders, "{}", filter);
+        }
+    }
+
+    /// `parents(id, code UNIQUE)` and `children(id, code)`, where
+    /// `children.code` references `parents.code` with `action`.
+    fn family(action: FkAction, unique_child: bool) -> StructuredStore {
+        let store = StructuredStore::new();
+        store.create_table("parents".into(), vec![column("id", DataType::Integer), Column { unique: true, ..column("code", DataType::String) }]).unwrap();
+        let code = Column { references: Some(("parents".into(), "code".into())), fk_action: action, unique: unique_child, ..column("code", DataType::String) };
+        store.create_table("children".into(), vec![column("id", DataType::Integer), code]).unwrap();
+        for (id, code) in [(1, "a"), (2, "b"), (3, "c")] {
+            store.insert("parents", vec![Some(id.to_string()), Some(code.into())]).unwrap();
+        }
+        store
+    }
+
+    fn rows(store: &StructuredStore, table: &str) -> Vec<String> {
+        let rows = store.select(table, None, Selector::All, None, None, None, None, None, None, None).unwrap().rows;
+        let mut rows: Vec<String> = rows.into_iter().map(|r| r.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(" ")).collect();
+        rows.sort();
+        rows
+    }
+
+    fn by_id(id: i64) -> Option<Filter> {
+        Some(Filter::Condition("id".into(), Operator::Eq, id.to_string()))
+    }
+
+    fn add_children(store: &StructuredStore, children: &[(i64, &str)]) {
+        for (id, code) in children {
+            store.insert("children", vec![Some(id.to_string()), Some(code.to_string())]).unwrap();
+        }
+    }
+
+    #[test]
+    fn cascade_follows_deletes_and_updates_down_every_level() {
+        let store = family(FkAction::Cascade, false);
+        store.create_index("by_child_code", "children", "code").unwrap();
+        add_children(&store, &[(10, "a"), (11, "a"), (12, "b")]);
+        let toy = Column { references: Some(("children".into(), "id".into())), fk_action: FkAction::Cascade, ..column("child", DataType::Integer) };
+        store.create_table("toys".into(), vec![column("id", DataType::Integer), toy]).unwrap();
+        for (id, child) in [(100, 10), (101, 12)] {
+            store.insert("toys", vec![Some(id.to_string()), Some(child.to_string())]).unwrap();
+        }
+
+        // Only the matched row counts; the cascaded ones do not
+        assert_eq!(store.delete("parents", by_id(1)).unwrap(), 1);
+        assert_eq!(rows(&store, "children"), ["12 b"]);
+        assert_eq!(rows(&store, "toys"), ["101 12"]);
+
+        assert_eq!(store.update("parents", by_id(2), ("code".into(), Some("z".into()))).unwrap(), 1);
+        assert_eq!(rows(&store, "children"), ["12 z"]);
+        for table in ["parents", "children", "toys"] {
+            assert_eq!(store.check_table(table).unwrap(), Vec::<String>::new());
+        }
+    }
+
+    #[test]
+    fn set_null_clears_references_on_delete_and_update() {
+        let store = family(FkAction::SetNull, false);
+        add_children(&store, &[(10, "a"), (11, "b"), (12, "c")]);
+        store.delete("parents", by_id(1)).unwrap();
+        store.update("parents", by_id(2), ("code".into(), Some("z".into()))).unwrap();
+        assert_eq!(rows(&store, "children"), ["10 NULL", "11 NULL", "12 c"]);
+    }
+
+    #[test]
+    fn restrict_rejects_the_write_and_changes_nothing() {
+        let store = family(FkAction::Restrict, false);
+        add_children(&store, &[(10, "a")]);
+        let before = rows(&store, "parents");
+        for result in [store.delete("parents", None), store.update("parents", by_id(1), ("code".into(), Some("z".into())))] {
+            assert!(result.unwrap_err().to_string().contains("still referenced"));
+        }
+        assert_eq!(rows(&store, "parents"), before);
+        assert_eq!(rows(&store, "children"), ["10 a"]);
+
+        // Unreferenced keys are free to go
+        store.update("parents", by_id(2), ("code".into(), Some("z".into()))).unwrap();
+        store.delete("parents", by_id(3)).unwrap();
+        assert_eq!(rows(&store, "parents"), ["1 a", "2 z"]);
+    }
+
+    #[test]
+    fn no_action_leaves_referencing_rows_alone() {
+        let store = family(FkAction::NoAction, false);
+        add_children(&store, &[(10, "a"), (11, "b")]);
+        store.delete("parents", by_id(1)).unwrap();
+        store.update("parents", by_id(2), ("code".into(), Some("z".into()))).unwrap();
+        assert_eq!(rows(&store, "children"), ["10 a", "11 b"]);
+    }
+
+    #[test]
+    fn cascaded_updates_keep_child_columns_unique() {
+        let store = family(FkAction::Cascade, true);
+        add_children(&store, &[(10, "a"), (11, "b")]);
+        // Following "a" -> "b" would give two children the same unique code
+        let err = store.update("parents", by_id(1), ("code".into(), Some("b".into()))).unwrap_err();
+        assert!(err.to_string().contains("Duplicate value"), "{}", err);
+        store.delete("parents", by_id(2)).unwrap();
+        store.update("parents", by_id(1), ("code".into(), Some("b".into()))).unwrap();
+        assert_eq!(rows(&store, "children"), ["10 b"]);
+        assert_eq!(store.check_table("children").unwrap(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn keys_another_row_still_holds_trigger_no_action() {
+        // Without a unique parent column two rows can share a key
+        let store = StructuredStore::new();
+        store.create_table("parents".into(), vec![column("id", DataType::Integer), column("code", DataType::String)]).unwrap();
+        store.create_index("by_code", "parents", "code").unwrap();
+        let code = Column { references: Some(("parents".into(), "code".into())), fk_action: FkAction::Cascade, ..column("code", DataType::String) };
+        store.create_table("children".into(), vec![column("id", DataType::Integer), code]).unwrap();
+        for (id, code) in [(1, "a"), (2, "a"), (3, "b")] {
+            store.insert("parents", vec![Some(id.to_string()), Some(code.into())]).unwrap();
         }
+        add_children(&store, &[(10, "a"), (11, "b")]);
+
+        store.delete("parents", by_id(1)).unwrap();
+        assert_eq!(rows(&store, "children"), ["10 a", "11 b"]);
+        // Another row takes the key over in the same statement
+        let moved = Some(Filter::Condition("id".into(), Operator::Gt, "1".into()));
+        store.update("parents", moved, ("code".into(), Some("b".into()))).unwrap();
+        assert_eq!(rows(&store, "children"), ["10 b", "11 b"]);
     }
 }
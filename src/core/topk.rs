@@ -0,0 +1,141 @@
+//! # Top-K (Heavy Hitters)
+//!
+//! Tracks the most frequent items of a stream in bounded memory.
+//!
+//! Item counts are estimated with a **Count-Min Sketch**: `depth` rows of
+//! `width` counters, each row indexed by its own hash of the item. An add
+//! increments one counter per row and the estimate is the smallest of them,
+//! so collisions can only overestimate. The `k` items with the highest
+//! estimates are kept in a candidate list; a new item replaces the weakest
+//! candidate once its estimate is higher.
+//!
+//! Memory is `width * depth` counters plus `k` items, whatever the number of
+//! distinct items seen.
+//!
+//! Rows hash with xxh3 seeded by the row number, which is stable across
+//! builds and platforms, so a sketch persisted with its counters (snapshots,
+//! AOF rewrites, `DUMP`) keeps counting the same items in the same slots.
+
+use serde::{Deserialize, Serialize};
+use xxhash_rust::xxh3::xxh3_64_with_seed;
+
+pub const DEFAULT_K: usize = 10;
+pub const DEFAULT_WIDTH: usize = 1024;
+pub const DEFAULT_DEPTH: usize = 5;
+
+const MAX_K: usize = 1000;
+const MAX_COUNTERS: usize = 1 << 24;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TopK {
+    k: usize,
+    width: usize,
+    depth: usize,
+    /// Row-major `depth x width` counters
+    counters: Vec<u64>,
+    /// Current top candidates with their estimated counts (unordered)
+    items: Vec<(String, u64)>,
+}
+
+impl TopK {
+    pub fn new(k: usize, width: usize, depth: usize) -> Result<Self, String> {
+        if k == 0 || k > MAX_K {
+            return Err(format!("TOPK size must be between 1 and {}", MAX_K));
+        }
+        if width == 0 || depth == 0 || width.saturating_mul(depth) > MAX_COUNTERS {
+            return Err(format!("TOPK width * depth must be between 1 and {}", MAX_COUNTERS));
+        }
+        Ok(Self { k, width, depth, counters: vec![0; width * depth], items: Vec::new() })
+    }
+
+    /// `(k, width, depth)`
+    pub fn params(&self) -> (usize, usize, usize) {
+        (self.k, self.width, self.depth)
+    }
+
+    /// Rough bytes held by the sketch.
+    pub fn memory_bytes(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + self.counters.len() * std::mem::size_of::<u64>()
+            + self.items.iter().map(|(item, _)| item.len() + std::mem::size_of::<(String, u64)>()).sum::<usize>()
+    }
+
+    /// Counts `incr` occurrences of `item`. Returns the candidate it pushed out
+    /// of the top list, if any.
+    pub fn add(&mut self, item: &str, incr: u64) -> Option<String> {
+        let mut estimate = u64::MAX;
+        for row in 0..self.depth {
+            let slot = row * self.width + self.column(row, item);
+            self.counters[slot] = self.counters[slot].saturating_add(incr);
+            estimate = estimate.min(self.counters[slot]);
+        }
+
+        if let Some(entry) = self.items.iter_mut().find(|(m, _)| m == item) {
+            entry.1 = estimate;
+            return None;
+        }
+        if self.items.len() < self.k {
+            self.items.push((item.to_string(), estimate));
+            return None;
+        }
+
+        let (weakest, &(_, min)) = self.items.iter().enumerate().min_by_key(|(_, (_, c))| *c)?;
+        if estimate > min {
+            let (expelled, _) = std::mem::replace(&mut self.items[weakest], (item.to_string(), estimate));
+            return Some(expelled);
+        }
+        None
+    }
+
+    /// Top items by estimated count, highest first (ties by item).
+    pub fn list(&self) -> Vec<(String, u64)> {
+        let mut items = self.items.clone();
+        items.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        items
+    }
+
+    fn column(&self, row: usize, item: &str) -> usize {
+        (xxh3_64_with_seed(item.as_bytes(), row as u64) % self.width as u64) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_heavy_hitters_in_bounded_memory() {
+        let mut topk = TopK::new(3, 64, 4).unwrap();
+        for i in 0..2000 {
+            topk.add(&format!("noise{}", i), 1);
+            if i % 4 == 0 {
+                topk.add("hot", 1);
+            }
+            if i % 10 == 0 {
+                topk.add("warm", 1);
+            }
+        }
+        let top: Vec<String> = topk.list().into_iter().map(|(item, _)| item).collect();
+        assert_eq!(top.len(), 3);
+        assert_eq!(&top[..2], ["hot", "warm"]);
+        // Estimates never undercount
+        assert!(topk.list()[0].1 >= 500);
+    }
+
+    #[test]
+    fn reports_expelled_candidates() {
+        let mut topk = TopK::new(1, 64, 2).unwrap();
+        assert_eq!(topk.add("a", 1), None);
+        assert_eq!(topk.add("b", 5), Some("a".to_string()));
+        assert_eq!(topk.list(), vec![("b".to_string(), 5)]);
+        assert!(TopK::new(0, 16, 2).is_err());
+    }
+
+    #[test]
+    fn slots_do_not_depend_on_the_build() {
+        let topk = TopK::new(1, 1024, 3).unwrap();
+        let slots: Vec<usize> = (0..3).map(|row| topk.column(row, "item")).collect();
+        assert_eq!(slots, (0..3).map(|row| (xxh3_64_with_seed(b"item", row) % 1024) as usize).collect::<Vec<_>>());
+        assert_ne!(slots[0], slots[1]);
+    }
+}
@@ -18,6 +18,32 @@ pub enum UnifiedValue {
 }
 
 impl UnifiedValue {
+    /// Type name as used in column definitions (`int`, `string`, ...).
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            UnifiedValue::Null => "null",
+            UnifiedValue::Integer(_) => "int",
+            UnifiedValue::Float(_) => "float",
+            UnifiedValue::String(_) => "string",
+            UnifiedValue::Boolean(_) => "bool",
+            UnifiedValue::DateTime(_) => "datetime",
+            UnifiedValue::Blob(_) => "blob",
+            UnifiedValue::Array(_) | UnifiedValue::Object(_) => "json",
+            UnifiedValue::Vector(_) => "vector",
+        }
+    }
+
+    /// Rough bytes held by the value, heap included (memory metrics).
+    pub fn approx_size(&self) -> usize {
+        std::mem::size_of::<Self>() + match self {
+            UnifiedValue::String(s) | UnifiedValue::Blob(s) => s.len(),
+            UnifiedValue::Array(items) => items.iter().map(|v| v.approx_size()).sum(),
+            UnifiedValue::Object(map) => map.iter().map(|(k, v)| k.len() + v.approx_size()).sum(),
+            UnifiedValue::Vector(v) => v.len() * std::mem::size_of::<f64>(),
+            _ => 0,
+        }
+    }
+
     pub fn cosine_similarity(&self, other: &Self) -> Option<f64> {
         match (self, other) {
             (UnifiedValue::Vector(a), UnifiedValue::Vector(b)) => {
@@ -0,0 +1,67 @@
+//! # Watched Keys
+//!
+//! `WATCH` makes the next `COMMIT` abort if a watched key was written in the
+//! meantime. Rather than tracking every key, writes bump one of [`BUCKETS`]
+//! version counters picked by a hash of the key; `WATCH` remembers the
+//! versions of its keys' buckets and `COMMIT` compares them. Two keys sharing
+//! a bucket can make a transaction abort when only the other one changed,
+//! which optimistic clients handle by retrying anyway; a change is never
+//! missed. Nothing has to be cleaned up when a client goes away.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use xxhash_rust::xxh3::xxh3_64;
+
+/// Version counters per database.
+const BUCKETS: usize = 16384;
+
+/// Write versions of a database's keys, by bucket.
+pub struct KeyVersions {
+    buckets: Box<[AtomicU64]>,
+}
+
+impl Default for KeyVersions {
+    fn default() -> Self {
+        Self { buckets: (0..BUCKETS).map(|_| AtomicU64::new(0)).collect() }
+    }
+}
+
+impl KeyVersions {
+    fn bucket(&self, key: &str) -> &AtomicU64 {
+        &self.buckets[xxh3_64(key.as_bytes()) as usize % BUCKETS]
+    }
+
+    /// Version to compare against later; changes with every write to `key`.
+    pub fn version(&self, key: &str) -> u64 {
+        self.bucket(key).load(Ordering::Acquire)
+    }
+
+    /// Records a write to `key`.
+    pub fn touch(&self, key: &str) {
+        self.bucket(key).fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// Records a write to every key (keys removed in bulk).
+    pub fn touch_all(&self) {
+        for bucket in self.buckets.iter() {
+            bucket.fetch_add(1, Ordering::AcqRel);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_change_the_version() {
+        let versions = KeyVersions::default();
+        let before = versions.version("a");
+        versions.touch("b");
+        assert!(versions.version("a") == before || xxh3_64(b"a") % BUCKETS as u64 == xxh3_64(b"b") % BUCKETS as u64);
+        versions.touch("a");
+        assert_ne!(versions.version("a"), before);
+        let now = versions.version("a");
+        versions.touch_all();
+        assert_ne!(versions.version("a"), now);
+    }
+}
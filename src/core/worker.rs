@@ -1,15 +1,20 @@
 use std::sync::Arc;
 use tokio::sync::{mpsc, oneshot, Mutex};
-use crate::core::executor::{execute_command, Session};
-use crate::query::Command;
+use crate::core::executor::{self, execute_command, CommandOutput, Session, Waiting};
+use crate::core::memory::DatabaseEngine;
+use crate::core::persistence::AofLogger;
+use crate::query::{Command, ExpiryUpdate};
 use crate::core::registry::DatabaseRegistry;
+use crate::core::metrics::METRICS;
+use crate::core::security::in_category;
+use crate::core::flexible::xadd_command;
 
 pub struct CommandRequest {
     pub cmd: Command,
     pub raw_cmd: String,
     pub session: Session,
-    // Returns: (Modified Session, Response String, AOF Hash info)
-    pub resp_tx: oneshot::Sender<(Session, String, Option<String>)>,
+    // Returns: (Modified Session, Response, AOF Hash info)
+    pub resp_tx: oneshot::Sender<(Session, CommandOutput, Option<String>)>,
 }
 
 #[derive(Clone)]
@@ -20,6 +25,7 @@ pub struct WorkerPool {
 
 impl WorkerPool {
     pub fn new(size: usize, registry: Arc<DatabaseRegistry>) -> Self {
+        registry.attach();
         let (tx, rx) = mpsc::channel::<CommandRequest>(1024);
         let rx = Arc::new(Mutex::new(rx));
 
@@ -37,67 +43,104 @@ impl WorkerPool {
                     match req_opt {
                         Some(mut req) => {
                             // Resolve engine and AOF dynamically
-                            let (engine, aof, is_new) = match registry.get_or_create(&req.session.current_db) {
+                            let (engine, aof, _) = match registry.get_or_create(&req.session.current_db) {
                                 Ok(res) => res,
                                 Err(e) => {
-                                    let _ = req.resp_tx.send((req.session, format!("ERROR: Registry Failed: {}", e), None));
+                                    let _ = req.resp_tx.send((req.session, CommandOutput::err(format!("Registry Failed: {}", e)), None));
                                     continue;
                                 }
                             };
 
-                            // AOF Replay (Recovery)
-                            if is_new {
-                                if let Ok(cmds) = aof.load() {
-                                    if !cmds.is_empty() {
-                                        crate::core::logger::info(&format!("Replaying {} AOF commands for {}", cmds.len(), req.session.current_db));
-                                        
-                                        // Use a temporary session for replay
-                                        let mut replay_session = Session {
-                                            user: Some(crate::core::security::User {
-                                                username: "system".to_string(),
-                                                password: "".to_string(),
-                                                rules: vec!["+@all".to_string()],
-                                            }),
-                                            _addr: "SYSTEM_RECOVERY".to_string(),
-                                            connected_at: std::time::Instant::now(),
-                                            current_db: req.session.current_db.clone(),
-                                            tx_buffer: None,
-                                        };
-
-                                        for cmd_str in cmds {
-                                             if let Ok((_, cmd)) = crate::net::parser::parse_command(&cmd_str) {
-                                                 // Execute without re-logging
-                                                 execute_command(&engine, cmd, &aof, &mut replay_session);
-                                             }
-                                        }
-                                        crate::core::logger::info("AOF Replay complete.");
-                                    }
-                                }
+                            let cmd_for_log = req.cmd.clone();
+                            // REPLCHECK waits for writes to reach the replication stream
+                            let forwarding = cmd_for_log.is_write();
+                            if forwarding {
+                                engine.replication.forwarding();
                             }
+                            // Kept until the write is logged (see `DatabaseEngine::log_gate`)
+                            let _gate = forwarding.then(|| tokio::task::block_in_place(|| engine.log_gate.read().unwrap()));
+                            let _saving = matches!(cmd_for_log, Command::Save)
+                                .then(|| tokio::task::block_in_place(|| engine.log_gate.write().unwrap()));
+                            // Commands run synchronously; hand this thread's other tasks off meanwhile,
+                            // so a long query does not stall other connections (or their KILL QUERY)
+                            let (res, hash, aborted) = tokio::task::block_in_place(|| {
+                                let user = req.session.user.as_ref().map_or("-", |u| u.username.as_str());
+                                // Credentials never show up in ACTIVE QUERIES
+                                let shown = match &req.cmd {
+                                    Command::Auth { .. } | Command::AclSetUser { .. } | Command::Migrate { auth: Some(_), .. } => req.raw_cmd.split_whitespace().next().unwrap_or_default(),
+                                    _ => req.raw_cmd.as_str(),
+                                };
+                                // Session commands are neither counted nor refused by quotas;
+                                // a refused write is not logged, like a killed one
+                                if let Some(user) = &req.session.user
+                                    && !matches!(req.cmd, Command::Auth { .. }) && !in_category(&req.cmd, "connection")
+                                    && let Err(e) = engine.security.charge(user, req.raw_cmd.len())
+                                {
+                                    return (CommandOutput::err(e), None, true);
+                                }
+                                let query = engine.queries.start(&engine.db_name, &req.session._addr, user, shown);
+                                let started = std::time::Instant::now();
+                                let (res, hash) = execute_command(&engine, req.cmd, &aof, &mut req.session);
+                                METRICS.record(&cmd_for_log, started.elapsed(), req.raw_cmd.len(), &res);
+                                (res, hash, query.aborted())
+                            });
 
-                            let cmd_for_log = req.cmd.clone();
-                            let (res, hash) = execute_command(&engine, req.cmd, &aof, &mut req.session);
+                            // A blocking command waits on its own task, without this worker
+                            if let Some(waiting) = req.session.waiting.take() {
+                                if forwarding {
+                                    engine.replication.forwarded();
+                                }
+                                tokio::spawn(wait(engine.clone(), aof.clone(), cmd_for_log, req.raw_cmd, req.session, req.resp_tx, waiting));
+                                continue;
+                            }
                             
                             // AOF Logging Logic
-                            let log_cmd = match &cmd_for_log {
-                                Command::AclSetUser { username, rules, .. } => { // password masked/handled via hash
-                                    if let Some(h) = &hash {
-                                        format!("ACL SETUSER {} \"{}\" {}", username, h, rules.join(" "))
+                            let log_cmds = match &cmd_for_log {
+                                // A transaction is logged as a whole once it committed, so a
+                                // replay applies all of it or none; one that rolled back left
+                                // nothing to replay
+                                Command::Begin if !res.is_error() => {
+                                    req.session.tx_log.clear();
+                                    Vec::new()
+                                }
+                                Command::Commit => {
+                                    let staged = std::mem::take(&mut req.session.tx_log);
+                                    if !matches!(res, CommandOutput::Array(_)) || staged.is_empty() {
+                                        Vec::new()
                                     } else {
-                                        req.raw_cmd.clone()
+                                        transaction(staged)
                                     }
                                 }
-                                _ => req.raw_cmd.clone(),
+                                _ => log_lines(&cmd_for_log, &req.raw_cmd, hash.as_deref()),
+                            };
+
+                            // A staged write waits for its COMMIT
+                            let log_cmds = if matches!(&res, CommandOutput::Status(s) if s == "QUEUED") {
+                                if cmd_for_log.is_write() {
+                                    req.session.tx_log.extend(log_cmds);
+                                }
+                                Vec::new()
+                            } else {
+                                log_cmds
                             };
 
-                            // Log if it is a write command
-                            if cmd_for_log.is_write() {
+                            // Log if it is a write command (a killed one changed nothing)
+                            if cmd_for_log.is_write() && !aborted {
                                 crate::core::logger::info(&format!("Client {} writing data in {}", req.session._addr, req.session.current_db));
-                                if let Err(e) = aof.log(&log_cmd) {
-                                    crate::core::logger::error(&format!("AOF Error: {}", e));
+                                for log_cmd in &log_cmds {
+                                    if cmd_for_log.is_logged() && let Err(e) = aof.log(log_cmd) {
+                                        crate::core::logger::error(&format!("AOF Error: {}", e));
+                                    }
+                                    // Propagate to replicas
+                                    engine.replication.propagate(&req.session.current_db, log_cmd);
                                 }
-                                // Propagate to replicas
-                                engine.replication.propagate(&log_cmd);
+                            }
+                            if forwarding {
+                                engine.replication.forwarded();
+                            }
+                            // A write scheduled for a time already past is made before the reply
+                            if matches!(cmd_for_log, Command::Schedule { .. }) && !res.is_error() {
+                                tokio::task::block_in_place(|| crate::core::schedule::deliver_due(&engine, &aof));
                             }
 
                             let _ = req.resp_tx.send((req.session, res, hash));
@@ -114,7 +157,7 @@ impl WorkerPool {
         }
     }
 
-    pub async fn execute(&self, cmd: Command, raw_cmd: String, session: Session) -> Result<(Session, String, Option<String>), String> {
+    pub async fn execute(&self, cmd: Command, raw_cmd: String, session: Session) -> Result<(Session, CommandOutput, Option<String>), String> {
         let (resp_tx, resp_rx) = oneshot::channel();
         let req = CommandRequest {
             cmd,
@@ -128,3 +171,160 @@ impl WorkerPool {
         resp_rx.await.map_err(|_| "Worker dropped request".to_string())
     }
 }
+
+/// Retries a blocking command every [`executor::WAIT_POLL`] until it
+/// completes, then logs what it wrote and replies.
+async fn wait(
+    engine: Arc<DatabaseEngine>,
+    aof: Arc<AofLogger>,
+    cmd: Command,
+    raw_cmd: String,
+    mut session: Session,
+    resp_tx: oneshot::Sender<(Session, CommandOutput, Option<String>)>,
+    mut waiting: Waiting,
+) {
+    let user = session.user.as_ref().map_or("-", |u| u.username.as_str()).to_string();
+    // Listed in ACTIVE QUERIES, so KILL QUERY ends the wait
+    let query = engine.queries.register(&engine.db_name, &session._addr, &user, &raw_cmd);
+    let (res, hash) = loop {
+        tokio::time::sleep(executor::WAIT_POLL).await;
+        let killed = query.killed();
+        // An attempt that writes is logged before the next write gets in,
+        // as in the worker loop
+        let forwarding = cmd.is_write();
+        if forwarding {
+            engine.replication.forwarding();
+        }
+        let done = tokio::task::block_in_place(|| {
+            let _gate = forwarding.then(|| engine.log_gate.read().unwrap());
+            let (res, hash) = executor::resume(&engine, &mut waiting, &mut session, &aof, killed)?;
+            if cmd.is_logged() && !res.is_error() {
+                for line in log_lines(&cmd, &raw_cmd, hash.as_deref()) {
+                    if let Err(e) = aof.log(&line) {
+                        crate::core::logger::error(&format!("AOF Error: {}", e));
+                    }
+                    engine.replication.propagate(&session.current_db, &line);
+                }
+            }
+            Some((res, hash))
+        });
+        if forwarding {
+            engine.replication.forwarded();
+        }
+        if let Some(done) = done {
+            break done;
+        }
+    };
+    drop(query);
+    let _ = resp_tx.send((session, res, hash));
+}
+
+/// The AOF lines for a command that ran, from its command line and the
+/// value it reported (the expiry, ID or password hash it ended up with).
+/// Transactions are logged by the worker as a whole.
+pub fn log_lines(cmd: &Command, raw_cmd: &str, hash: Option<&str>) -> Vec<String> {
+    match cmd {
+        Command::AclSetUser { username, rules, .. } => { // password masked/handled via hash
+            if let Some(h) = hash {
+                vec![Command::AclSetUser { username: username.clone(), password: h.to_string(), rules: rules.clone() }.to_string()]
+            } else {
+                vec![raw_cmd.to_string()]
+            }
+        }
+        // Relative TTLs drift on replay; pin the absolute expiry right after SETEX
+        Command::SetEx { key, .. } | Command::Restore { key, .. } => {
+            let mut cmds = vec![raw_cmd.to_string()];
+            cmds.extend(pinned_expiry(key, hash));
+            cmds
+        }
+        // EXPIRE (and its jitter) is logged as the expiry it produced
+        Command::Expire { key, .. } => pinned_expiry(key, hash).into_iter().collect(),
+        // So is a relative GETEX; the read itself needs no logging
+        Command::GetEx { key, expiry: Some(ExpiryUpdate::After(_)) } => pinned_expiry(key, hash).into_iter().collect(),
+        // A blocking pop is logged as the pop it made, if any
+        Command::BZPop { max, .. } => {
+            hash.iter().map(|key| Command::ZPop { key: key.to_string(), count: Some(1), max: *max }.to_string()).collect()
+        }
+        // An entry is logged with the ID it got, so a replay does not pick
+        // a new one; a queued one has none yet
+        Command::XAdd { key, fields, .. } => match hash {
+            Some(id) => vec![xadd_command(key, id, fields)],
+            None => vec![raw_cmd.to_string()],
+        },
+        // Migrated keys left this node: the hash is the DEL removing them
+        Command::Migrate { .. } => hash.iter().map(|line| line.to_string()).collect(),
+        // A promotion is replayed as what it did to this node
+        Command::ReplicaOf { host, .. } if host.eq_ignore_ascii_case("PROMOTE") => {
+            vec![promotion()]
+        }
+        // A script is logged as the writes it made, applied as one
+        Command::Eval { .. } | Command::EvalSha { .. } => match hash {
+            Some(lines) if lines.contains('\n') => {
+                transaction(lines.split('\n').map(String::from))
+            }
+            Some(line) => vec![line.to_string()],
+            None => Vec::new(),
+        },
+        _ => vec![raw_cmd.to_string()],
+    }
+}
+
+/// A `PEXPIREAT` pinning the expiry in ms that a command reported.
+fn pinned_expiry(key: &str, hash: Option<&str>) -> Option<String> {
+    let timestamp_ms = hash?.parse().ok()?;
+    Some(Command::PExpireAt { key: key.to_string(), timestamp_ms }.to_string())
+}
+
+/// Writes wrapped in `BEGIN` / `COMMIT`, so a replay applies all of them or none.
+pub(crate) fn transaction(lines: impl IntoIterator<Item = String>) -> Vec<String> {
+    std::iter::once(Command::Begin.to_string()).chain(lines).chain(std::iter::once(Command::Commit.to_string())).collect()
+}
+
+/// The line recording that this node was promoted to master.
+pub fn promotion() -> String {
+    Command::ReplicaOf { host: "NO".to_string(), port: "ONE".to_string() }.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::replication::ReplicaMessage;
+
+    #[test]
+    fn blocking_pops_are_logged_as_the_pop_they_made() {
+        let (min, max) = (
+            Command::BZPop { keys: vec!["a".into(), "b".into()], timeout_ms: 0, max: false },
+            Command::BZPop { keys: vec!["a".into()], timeout_ms: 0, max: true },
+        );
+        assert_eq!(log_lines(&min, "BZPOPMIN a b 0", Some("b")), vec![Command::ZPop { key: "b".into(), count: Some(1), max: false }.to_string()]);
+        assert_eq!(log_lines(&max, "BZPOPMAX a 0", Some("a")), vec![Command::ZPop { key: "a".into(), count: Some(1), max: true }.to_string()]);
+        // A pop that timed out changed nothing
+        assert!(log_lines(&min, "BZPOPMIN a b 0", None).is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn deletes_are_logged_and_replicated() {
+        assert_eq!(log_lines(&Command::Del { keys: vec!["a".into(), "b".into()] }, "DEL a b", None), ["DEL a b"]);
+
+        let registry = Arc::new(DatabaseRegistry::new());
+        let engine = registry.open_detached("worker_del");
+        let (tx, mut rx) = tokio::sync::mpsc::channel(16);
+        engine.replication.add_replica("r1".to_string(), tx);
+        let pool = WorkerPool::new(1, registry.clone());
+        let mut session = Session::system("test", "worker_del");
+        for line in ["SET a 1", "DEL a", "BEGIN", "SET b 1", "DEL b", "COMMIT"] {
+            let (_, cmd) = crate::net::parser::parse_command(line).unwrap();
+            let (next, res, _) = pool.execute(cmd, line.to_string(), session).await.unwrap();
+            assert!(!res.is_error(), "{}: {}", line, res.to_text(crate::core::output::OutputFormat::Text));
+            session = next;
+        }
+        let sent: Vec<String> = std::iter::from_fn(|| rx.try_recv().ok())
+            .filter_map(|message| match message {
+                ReplicaMessage::Command(line) => Some(line),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(sent, ["SET a 1", "DEL a", "BEGIN", "SET b 1", "DEL b", "COMMIT"]);
+        assert_eq!(engine.flexible.exists(&["a".to_string(), "b".to_string()]), 0);
+    }
+}
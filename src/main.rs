@@ -8,94 +8,178 @@
 //! - **Worker Pool**: Parallel query execution with predictable resource usage.
 //! - **Multi-Tenancy**: Dynamic database context switching via `USE` and connection URIs.
 
-use tokio::net::TcpListener;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use std::sync::Arc;
-use toridb::net::parser::parse_command;
-use toridb::net::resp::{decode, RespValue};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use toridb::net::binding::{self, ArgBinding};
+use toridb::net::listener;
+use toridb::net::parser::{generate_values, parse_command};
+use toridb::net::prometheus;
+use toridb::net::variables;
+use toridb::query::Command;
+use toridb::net::resp::{decode, Decoder, ProtocolError, PROTOCOL_STATS};
 use toridb::core::worker::WorkerPool;
-use toridb::core::executor::Session;
+use toridb::core::metrics::METRICS;
+use toridb::core::executor::{CommandOutput, Session};
 use toridb::core::logger;
+use toridb::core::config::{self, Settings};
 use toridb::core::registry::DatabaseRegistry;
+use toridb::core::snapshot::SnapshotManager;
+use toridb::core::replication::{self, ReplicaMessage};
 
 use bytes::BytesMut;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let mut host = std::env::var("DB_HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
-    let mut port = std::env::var("DB_PORT").unwrap_or_else(|_| "8569".to_string());
+    // `toridb --check-aof <file> [--fix | --skip]` checks an AOF instead of serving
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().is_some_and(|arg| arg == "--check-aof") {
+        std::process::exit(toridb::core::aof_check::run(&args[1..]));
+    }
+
+    // Defaults < toridb.toml < DB_* variables < DB_URI
+    let mut settings = Settings::load()?;
     let mut db_name = std::env::var("DB_NAME").unwrap_or_else(|_| "data".to_string());
-    let mut data_dir = std::env::var("DB_DATA_DIR").unwrap_or_else(|_| "data".to_string());
-    let mut workers = std::env::var("DB_WORKERS")
-        .ok()
-        .and_then(|s| s.parse().ok())
-        .unwrap_or(50);
-    let mut max_connections = 100;
     
     // Check DB_URI
     if let Ok(uri_str) = std::env::var("DB_URI") {
         if let Ok(uri) = toridb::core::uri::ConnectionUri::parse(&uri_str) {
-            host = uri.host.clone();
-            port = uri.port.to_string();
+            settings.host = uri.host.clone();
+            settings.port = uri.port;
             db_name = uri.db_name_default();
             
             // Apply query arguments
-            workers = uri.get_query_param("workers", workers);
-            max_connections = uri.get_query_param("max_connections", max_connections);
+            settings.workers = uri.get_query_param("workers", settings.workers);
+            settings.max_connections = uri.get_query_param("max_connections", settings.max_connections);
             if let Some(d) = uri.query.get("data_dir") {
-                data_dir = d.clone();
-                unsafe { std::env::set_var("DB_DATA_DIR", &data_dir); }
+                settings.data_dir = d.clone();
             }
         }
     }
+    let Settings { host, port, metrics_port, reuse_port, max_frame_bytes, workers, data_dir, .. } = settings.clone();
+    config::init(settings);
+    let config = config::shared();
 
-    let addr = format!("{}:{}", host, port);
-    let listener = TcpListener::bind(&addr).await?; 
-    logger::info(&format!("ToriDB Server running on {} (DB: {}, Data Dir: {})", addr, db_name, data_dir));
+    // DB_HOST may list several addresses, all served by the same accept loop
+    let mut listeners = Vec::new();
+    for addr in listener::bind_addrs(&host, port)? {
+        let bound = listener::bind(addr, reuse_port).map_err(|e| anyhow::anyhow!("Failed to bind {}: {}", addr, e))?;
+        listeners.push(bound);
+    }
+    let addrs: Vec<String> = listeners.iter().filter_map(|l| l.local_addr().ok()).map(|a| a.to_string()).collect();
+    logger::info(&format!("ToriDB Server running on {} (DB: {}, Data Dir: {})", addrs.join(", "), db_name, data_dir));
+    let mut connections = listener::accept_all(listeners);
 
     // Initialize Registry and Worker Pool
-    let registry = Arc::new(DatabaseRegistry::new(max_connections));
+    let registry = Arc::new(DatabaseRegistry::new());
     let worker_pool = WorkerPool::new(workers, registry.clone());
 
     // We no longer need to manually load engine/aof here, 
     // it will be loaded by workers when first accessed.
     
-    loop {
-        let (mut socket, addr) = listener.accept().await?;
+    let open_connections = Arc::new(AtomicUsize::new(0));
+    if metrics_port != 0 {
+        prometheus::spawn(listener::bind_addrs(&host, metrics_port)?, registry.clone(), open_connections.clone()).await?;
+    }
+    while let Some((mut socket, addr)) = connections.recv().await {
         let worker_pool = worker_pool.clone();
         let current_db = db_name.clone();
         let addr_str = addr.to_string();
+
+        // `max_connections` can change at runtime (CONFIG SET)
+        if open_connections.fetch_add(1, Ordering::SeqCst) >= config.max_connections() {
+            open_connections.fetch_sub(1, Ordering::SeqCst);
+            logger::warn(&format!("Rejected connection from {}: max_connections reached", addr_str));
+            tokio::spawn(async move {
+                let _ = socket.write_all(b"-ERR max number of clients reached\r\n").await;
+            });
+            continue;
+        }
+        let open_connections = open_connections.clone();
         
         logger::info(&format!("New connection from {}", addr_str));
 
         tokio::spawn(async move {
             let mut buffer = BytesMut::with_capacity(4096);
-            let mut session = Session { 
-                user: None, 
-                _addr: addr_str.clone(),
-                connected_at: std::time::Instant::now(),
-                current_db,
-                tx_buffer: None,
-            };
+            let mut decoder = Decoder::default();
+            let mut session = Session::connection(&addr_str, &current_db);
 
-            loop {
-                let _n = match socket.read_buf(&mut buffer).await {
-                    Ok(n) if n == 0 => break, 
-                    Ok(n) => n,
-                    Err(_) => break,
-                };
+            'conn: loop {
+                match socket.read_buf(&mut buffer).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => PROTOCOL_STATS.record_read(n),
+                }
                 
-                while let Ok(Some(resp_val)) = decode(&mut buffer) {
-                    let input_str = match resp_val.to_command_string() {
-                        Some(s) => s,
+                loop {
+                    // Strict binding applies to RESP arrays only; inline text is always parsed
+                    let is_array = buffer.first() == Some(&b'*');
+                    let decoded = match decoder.decode(&mut buffer) {
+                        Ok(None) if buffer.len() > max_frame_bytes => {
+                            Err(ProtocolError::TooLarge(format!("frame exceeds {} bytes", max_frame_bytes)).into())
+                        }
+                        other => other,
+                    };
+                    let resp_val = match decoded {
+                        Ok(Some(val)) => val,
+                        Ok(None) => break,
+                        // The stream can't be framed anymore: report and hang up
+                        Err(e) => {
+                            PROTOCOL_STATS.record_error(&e);
+                            logger::warn(&format!("Protocol error from {}: {}", addr_str, e));
+                            let _ = socket.write_all(format!("-ERR Protocol error: {}\r\n", e).as_bytes()).await;
+                            break 'conn;
+                        }
+                    };
+                    PROTOCOL_STATS.record_frame(!is_array);
+                    let strict = is_array && session.binding == ArgBinding::Strict;
+                    let bound = if strict {
+                        resp_val.to_args().and_then(|args| binding::bind(&args))
+                    } else {
+                        None
+                    };
+
+                    let (input_str, parsed) = match bound {
+                        Some(Ok((command, line))) => (line, Ok(command)),
+                        Some(Err(e)) => (String::new(), Err(e)),
                         None => {
-                            let _ = socket.write_all(b"-ERR invalid command format\r\n").await;
-                            continue;
+                            let input_str = match resp_val.to_command_string() {
+                                Some(s) => generate_values(&variables::substitute(&s, &session.variables), !is_array),
+                                None => {
+                                    let _ = socket.write_all(b"-ERR invalid command format\r\n").await;
+                                    continue;
+                                }
+                            };
+                            let parsed = match parse_command(&input_str) {
+                                Ok((rest, _)) if strict && !rest.trim().is_empty() => {
+                                    Err(format!("Unexpected input after command: '{}'", rest.trim()))
+                                }
+                                Ok((_, command)) => Ok(command),
+                                Err(_) => Err("Syntax Error".to_string()),
+                            };
+                            (input_str, parsed)
                         }
                     };
 
-                    let response = match parse_command(&input_str) {
-                        Ok((_, command)) => {
+                    // QUIT and RESET need no session or permissions: QUIT hangs up, RESET
+                    // drops the transaction, login, options and variables of the connection
+                    match parsed {
+                        Ok(Command::Quit) => {
+                            let _ = socket.write_all(b"+OK\r\n").await;
+                            break 'conn;
+                        }
+                        Ok(Command::Reset) => {
+                            session = Session { connected_at: session.connected_at, ..Session::connection(&addr_str, &current_db) };
+                            if socket.write_all(b"+RESET\r\n").await.is_err() {
+                                break 'conn;
+                            }
+                            continue;
+                        }
+                        _ => {}
+                    }
+
+                    let name = parsed.as_ref().ok().map(|command| command.name());
+                    let response = match parsed {
+                        Ok(command) => {
                             // Execute via Worker Pool
                             let (new_session, res) = match worker_pool.execute(command, input_str.clone(), session).await {
                                 Ok((s, r, _hash)) => (s, r),
@@ -108,68 +192,102 @@ async fn main() -> anyhow::Result<()> {
                             session = new_session;
                             res
                         },
-                        Err(_) => "ERROR: Syntax Error".to_string(),
+                        Err(e) => CommandOutput::Err(e),
                     };
-                    
+
                     // Handle PSYNC - switch to replica propagation mode
-                    if response == "_PSYNC_OK" {
-                        // PSYNC currently needs careful handling with multi-db. 
-                        // For now we assume they sync the 'current' DB or the default.
+                    if let CommandOutput::FullResync = response {
                         let (engine, _, _) = worker_pool.registry.get_or_create(&session.current_db).unwrap();
 
-                        let (tx, mut rx) = tokio::sync::mpsc::channel::<String>(1024);
-                        engine.replication.add_replica(addr_str.clone(), tx);
+                        let (tx, mut rx) = tokio::sync::mpsc::channel::<ReplicaMessage>(1024);
+                        let offset = engine.replication.add_replica(addr_str.clone(), tx);
                         
-                        // Full Sync: Send current state as commands
-                        let snapshot_cmds = engine.generate_rewrite_commands();
-                        let _ = socket.write_all(format!("+FULLRESYNC {} {}\r\n", snapshot_cmds.len(), 0).as_bytes()).await;
-                        for cmd in snapshot_cmds {
-                            let resp_cmd = format!("${}\r\n{}\r\n", cmd.len(), cmd);
-                            if socket.write_all(resp_cmd.as_bytes()).await.is_err() {
-                                engine.replication.replicas.remove(&addr_str);
+                        // Full Sync: for every database, name it with SELECTDB, announce
+                        // the size and CRC32 of a binary snapshot and the offset it
+                        // starts from, and send it as one bulk string; then the writes
+                        // that follow
+                        let registry = worker_pool.registry.clone();
+                        let snapshots = tokio::task::spawn_blocking(move || {
+                            registry.open_all().into_iter()
+                                .map(|(name, db, _)| SnapshotManager::encode(&SnapshotManager::capture(&db)).map(|bytes| (name, bytes)))
+                                .collect::<std::io::Result<Vec<_>>>()
+                        }).await;
+                        let snapshots = match snapshots {
+                            Ok(Ok(snapshots)) => snapshots,
+                            Ok(Err(e)) => {
+                                logger::error(&format!("Replication: snapshot for {} failed: {}", addr_str, e));
+                                engine.replication.remove_replica(&addr_str);
+                                let _ = socket.write_all(format!("-ERR full sync failed: {}\r\n", e).as_bytes()).await;
+                                return;
+                            }
+                            Err(_) => {
+                                engine.replication.remove_replica(&addr_str);
                                 return;
                             }
+                        };
+                        let mut frame = Vec::new();
+                        for (name, snapshot) in &snapshots {
+                            frame.extend(format!("+SELECTDB {}\r\n+FULLRESYNC {} {:08x} {}\r\n${}\r\n", name, snapshot.len(), crc32fast::hash(snapshot), offset, snapshot.len()).into_bytes());
+                            frame.extend_from_slice(snapshot);
+                            frame.extend_from_slice(b"\r\n");
+                        }
+                        frame.extend_from_slice(b"+SYNC_COMPLETE\r\n");
+                        logger::info(&format!("Replication: full sync of {} databases ({} bytes) to {}", snapshots.len(), frame.len(), addr_str));
+                        if socket.write_all(&frame).await.is_err() {
+                            engine.replication.remove_replica(&addr_str);
+                            return;
                         }
-                        let _ = socket.write_all(b"+SYNC_COMPLETE\r\n").await;
                         
-                        // Propagation loop: forward commands to this replica
+                        // Propagation loop: forward commands to this replica, ping it
+                        // while idle, and record the offsets and port it reports
+                        let mut heartbeat = tokio::time::interval(replication::HEARTBEAT);
                         loop {
-                            match rx.recv().await {
-                                Some(cmd) => {
-                                    // Send as RESP inline command (simplified)
-                                    let resp_cmd = format!("${}\r\n{}\r\n", cmd.len(), cmd);
-                                    if socket.write_all(resp_cmd.as_bytes()).await.is_err() {
+                            tokio::select! {
+                                message = rx.recv() => {
+                                    let Some(message) = message else {
+                                        break; // Channel closed
+                                    };
+                                    if socket.write_all(message.to_frame().as_bytes()).await.is_err() {
+                                        break;
+                                    }
+                                    heartbeat.reset();
+                                }
+                                _ = heartbeat.tick() => {
+                                    if socket.write_all(b"+PING\r\n").await.is_err() {
+                                        break;
+                                    }
+                                }
+                                read = socket.read_buf(&mut buffer) => {
+                                    if !matches!(read, Ok(n) if n > 0) {
                                         break;
                                     }
+                                    while let Ok(Some(frame)) = decode(&mut buffer) {
+                                        if let Some(conf) = frame.to_command_string().as_deref().and_then(replication::parse_replconf) {
+                                            engine.replication.replconf(&addr_str, conf);
+                                        }
+                                    }
                                 }
-                                None => break, // Channel closed
                             }
                         }
                         // Replica loop ended, cleanup
-                        engine.replication.replicas.remove(&addr_str);
+                        engine.replication.remove_replica(&addr_str);
                         return;
                     }
                     
-                    let resp_out = if response == "nil" {
-                        RespValue::BulkString(None)
-                    } else if response.starts_with("ERROR:") {
-                        RespValue::Error(response.replace("ERROR: ", "").trim().to_string())
-                    } else if response.starts_with("(integer)") {
-                        let val = response.replace("(integer) ", "").trim().parse::<i64>().unwrap_or(0);
-                        RespValue::Integer(val)
-                    } else if response == "OK" || response == "PONG" {
-                        RespValue::SimpleString(response)
-                    } else {
-                        RespValue::BulkString(Some(response.as_bytes().to_vec()))
-                    };
-
-                    if let Err(_) = socket.write_all(&resp_out.serialize()).await {
+                    // Result sets are encoded per the session's output format
+                    let reply = response.to_resp(session.output).serialize();
+                    if let Some(name) = name {
+                        METRICS.record_reply(name, reply.len());
+                    }
+                    if let Err(_) = socket.write_all(&reply).await {
                         break;
                     }
                 }
             }
             // Unregister client
+            open_connections.fetch_sub(1, Ordering::SeqCst);
             logger::info(&format!("Client disconnected: {}", addr_str));
         });
     }
+    Ok(())
 }
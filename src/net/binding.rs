@@ -0,0 +1,353 @@
+//! # Argument Binding
+//!
+//! RESP arrays are normally joined into one command line and re-parsed, so the
+//! contents of a value can leak into the syntax: a value such as `"x"` loses
+//! its quotes, and a SQL part carrying ` WHERE ` changes the query.
+//!
+//! In strict mode (`SET binding = strict`) the Redis-style commands and
+//! `INSERT` bind every array element to exactly one argument, with exact
+//! arity checks and no re-parsing. Other commands still go through the parser,
+//! but must be consumed entirely; leftover input is an error instead of being
+//! ignored. Inline (plain text) commands are always parsed.
+//!
+//! Bound commands are logged in canonical form: keys and numbers verbatim,
+//! values quoted with [`quote_string`], so AOF replay and replicas parse back
+//! the very same command.
+
+use crate::net::parser::{is_identifier, is_key, quote_string, resolve_alias};
+use crate::query::{Command, ZAddFlags};
+use crate::core::bitmap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ArgBinding {
+    /// Join and re-parse (default)
+    #[default]
+    Parsed,
+    Strict,
+}
+
+impl std::str::FromStr for ArgBinding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "parsed" => Ok(ArgBinding::Parsed),
+            "strict" => Ok(ArgBinding::Strict),
+            other => Err(format!("Unknown binding mode '{}'", other)),
+        }
+    }
+}
+
+/// Binds a command's arguments one-to-one.
+///
+/// Returns `None` for commands that are not bound strictly (they go to the
+/// parser), otherwise the command and its canonical command line.
+pub fn bind(parts: &[String]) -> Option<Result<(Command, String), String>> {
+    let (name, rest) = parts.split_first()?;
+    // `DELETE FROM` is SQL; other aliases bind as the command they stand for
+    if name.eq_ignore_ascii_case("DELETE") && rest.first().is_some_and(|w| w.eq_ignore_ascii_case("FROM")) {
+        return None;
+    }
+    let name = resolve_alias(name);
+    let mut args = Args { name: &name, rest, line: name.clone() };
+
+    let bound = match name.as_str() {
+        // `SET <option> = <value>` is session syntax
+        "SET" if rest.len() == 3 && rest[1] == "=" => return None,
+        "SET" => args.arity(2, Some(2)).and_then(|_| Ok(Command::Set { key: args.key(0)?, value: args.value(1) })),
+        "SETEX" => args.arity(3, Some(5)).and_then(|_| Ok(Command::SetEx { key: args.key(0)?, ttl: args.number(1)?, value: args.value(2), jitter: args.jitter(3)? })),
+        "EXPIRE" => args.arity(2, Some(4)).and_then(|_| Ok(Command::Expire { key: args.key(0)?, seconds: args.number(1)?, jitter: args.jitter(2)? })),
+        "GET" => args.arity(1, Some(1)).and_then(|_| Ok(Command::Get { key: args.key(0)? })),
+        "GETRANGE" => args.arity(3, Some(3)).and_then(|_| Ok(Command::GetRange { key: args.key(0)?, start: args.number(1)?, end: args.number(2)? })),
+        "DEL" => args.arity(1, None).and_then(|_| Ok(Command::Del { keys: args.keys()? })),
+        "EXISTS" => args.arity(1, None).and_then(|_| Ok(Command::Exists { keys: args.keys()? })),
+        "WATCH" => args.arity(1, None).and_then(|_| Ok(Command::Watch { keys: args.keys()? })),
+        "TYPE" => args.arity(1, Some(1)).and_then(|_| Ok(Command::Type { key: args.key(0)? })),
+        "TTL" => args.arity(1, Some(1)).and_then(|_| Ok(Command::Ttl { key: args.key(0)? })),
+        "PEXPIREAT" => args.arity(2, Some(2)).and_then(|_| Ok(Command::PExpireAt { key: args.key(0)?, timestamp_ms: args.number(1)? })),
+        "INCR" => args.arity(1, Some(1)).and_then(|_| Ok(Command::Incr { key: args.key(0)? })),
+        "DECR" => args.arity(1, Some(1)).and_then(|_| Ok(Command::Decr { key: args.key(0)? })),
+        "LPUSH" => args.arity(2, None).and_then(|_| Ok(Command::LPush { key: args.key(0)?, values: args.values(1) })),
+        "RPUSH" => args.arity(2, None).and_then(|_| Ok(Command::RPush { key: args.key(0)?, values: args.values(1) })),
+        "LPOP" => args.arity(1, Some(2)).and_then(|_| Ok(Command::LPop { key: args.key(0)?, count: args.opt_number(1)? })),
+        "RPOP" => args.arity(1, Some(2)).and_then(|_| Ok(Command::RPop { key: args.key(0)?, count: args.opt_number(1)? })),
+        "LRANGE" => args.arity(3, Some(3)).and_then(|_| Ok(Command::LRange { key: args.key(0)?, start: args.number(1)?, stop: args.number(2)? })),
+        "HSET" => args.arity(3, Some(3)).and_then(|_| Ok(Command::HSet { key: args.key(0)?, field: args.value(1), value: args.value(2) })),
+        "HGET" => args.arity(2, Some(2)).and_then(|_| Ok(Command::HGet { key: args.key(0)?, field: args.value(1) })),
+        "HGETALL" => args.arity(1, Some(1)).and_then(|_| Ok(Command::HGetAll { key: args.key(0)? })),
+        "SADD" => args.arity(2, None).and_then(|_| Ok(Command::SAdd { key: args.key(0)?, members: args.values(1) })),
+        "SMEMBERS" => args.arity(1, Some(1)).and_then(|_| Ok(Command::SMembers { key: args.key(0)? })),
+        "ZADD" => args.arity(3, None).and_then(|_| {
+            let key = args.key(0)?;
+            let (flags, from) = args.zadd_flags(1);
+            Ok(Command::ZAdd { key, flags, members: args.score_members(from)? })
+        }),
+        "ZRANGE" => args.arity(3, Some(3)).and_then(|_| Ok(Command::ZRange { key: args.key(0)?, start: args.number(1)?, stop: args.number(2)? })),
+        "ZSCORE" => args.arity(2, Some(2)).and_then(|_| Ok(Command::ZScore { key: args.key(0)?, member: args.value(1) })),
+        "GEOADD" => args.arity(4, None).and_then(|_| {
+            let key = args.key(0)?;
+            let (flags, from) = args.zadd_flags(1);
+            if flags.gt || flags.lt {
+                return Err("GEOADD takes NX, XX and CH only".to_string());
+            }
+            Ok(Command::GeoAdd { key, flags, members: args.located_members(from)? })
+        }),
+        "TOPK.ADD" => args.arity(2, None).and_then(|_| Ok(Command::TopKAdd { key: args.key(0)?, items: args.values(1) })),
+        "SETBIT" => args.arity(3, Some(3)).and_then(|_| Ok(Command::SetBit { key: args.key(0)?, offset: args.bit_offset(1)?, value: args.bit(2)? })),
+        "PFADD" => args.arity(1, None).and_then(|_| Ok(Command::PfAdd { key: args.key(0)?, elements: args.values(1) })),
+        "XADD" => args.arity(4, None).and_then(|_| Ok(Command::XAdd { key: args.key(0)?, id: args.key(1)?, fields: args.field_values(2)? })),
+        "EVAL" => args.arity(2, None).and_then(|_| {
+            let script = args.value(0);
+            let (keys, values) = args.script_keys(1)?;
+            Ok(Command::Eval { script, keys, args: values })
+        }),
+        "EVALSHA" => args.arity(2, None).and_then(|_| {
+            let sha = args.value(0);
+            let (keys, values) = args.script_keys(1)?;
+            Ok(Command::EvalSha { sha, keys, args: values })
+        }),
+        "JSON.GET" => args.arity(1, Some(2)).and_then(|_| Ok(Command::JsonGet { key: args.key(0)?, path: (rest.len() == 2).then(|| args.value(1)) })),
+        "JSON.SET" => args.arity(3, Some(3)).and_then(|_| Ok(Command::JsonSet { key: args.key(0)?, path: args.value(1), value: args.value(2) })),
+        "INSERT" => args.arity(2, None).and_then(|_| Ok(Command::Insert { table: args.table(0)?, rows: vec![args.values(1).into_iter().map(Some).collect()] })),
+        _ => return None,
+    };
+
+    Some(bound.map(|cmd| (cmd, args.line)))
+}
+
+/// Arguments of one command; every accessor appends its canonical form to
+/// `line`, so they must be called in argument order.
+struct Args<'a> {
+    name: &'a str,
+    rest: &'a [String],
+    line: String,
+}
+
+impl Args<'_> {
+    fn arity(&self, min: usize, max: Option<usize>) -> Result<(), String> {
+        if self.rest.len() < min || max.is_some_and(|max| self.rest.len() > max) {
+            return Err(format!("Wrong number of arguments for '{}'", self.name));
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, token: &str) {
+        self.line.push(' ');
+        self.line.push_str(token);
+    }
+
+    fn key(&mut self, i: usize) -> Result<String, String> {
+        let key = &self.rest[i];
+        if !is_key(key) {
+            return Err(format!("Invalid key '{}'", key));
+        }
+        self.push(key);
+        Ok(key.clone())
+    }
+
+    fn keys(&mut self) -> Result<Vec<String>, String> {
+        (0..self.rest.len()).map(|i| self.key(i)).collect()
+    }
+
+    fn table(&mut self, i: usize) -> Result<String, String> {
+        let table = &self.rest[i];
+        if !is_identifier(table) {
+            return Err(format!("Invalid table name '{}'", table));
+        }
+        self.push(table);
+        Ok(table.clone())
+    }
+
+    fn value(&mut self, i: usize) -> String {
+        let value = self.rest[i].clone();
+        self.push(&quote_string(&value));
+        value
+    }
+
+    fn values(&mut self, from: usize) -> Vec<String> {
+        (from..self.rest.len()).map(|i| self.value(i)).collect()
+    }
+
+    fn number<T: std::str::FromStr + ToString>(&mut self, i: usize) -> Result<T, String> {
+        let n: T = self.rest[i].parse()
+            .map_err(|_| format!("Invalid number '{}' for '{}'", self.rest[i], self.name))?;
+        self.push(&n.to_string());
+        Ok(n)
+    }
+
+    fn opt_number<T: std::str::FromStr + ToString>(&mut self, i: usize) -> Result<Option<T>, String> {
+        if i < self.rest.len() { self.number(i).map(Some) } else { Ok(None) }
+    }
+
+    /// Optional trailing `JITTER <percent>` starting at `i`.
+    fn jitter(&mut self, i: usize) -> Result<Option<u8>, String> {
+        match self.rest.get(i) {
+            None => Ok(None),
+            Some(word) if word.eq_ignore_ascii_case("JITTER") && self.rest.len() == i + 2 => {
+                self.push("JITTER");
+                self.number(i + 1).map(Some)
+            }
+            Some(_) => Err(format!("Wrong number of arguments for '{}'", self.name)),
+        }
+    }
+
+    fn zadd_flags(&mut self, from: usize) -> (ZAddFlags, usize) {
+        let mut flags = ZAddFlags::default();
+        let mut i = from;
+        while i < self.rest.len() && flags.set(&self.rest[i]) {
+            let word = self.rest[i].to_ascii_uppercase();
+            self.push(&word);
+            i += 1;
+        }
+        (flags, i)
+    }
+
+    fn score_members(&mut self, from: usize) -> Result<Vec<(f64, String)>, String> {
+        let len = self.rest.len();
+        if from >= len || !(len - from).is_multiple_of(2) {
+            return Err(format!("Wrong number of arguments for '{}'", self.name));
+        }
+        (from..len).step_by(2).map(|i| Ok((self.score(i)?, self.value(i + 1)))).collect()
+    }
+
+    fn located_members(&mut self, from: usize) -> Result<Vec<(f64, f64, String)>, String> {
+        let len = self.rest.len();
+        if from >= len || !(len - from).is_multiple_of(3) {
+            return Err(format!("Wrong number of arguments for '{}'", self.name));
+        }
+        (from..len).step_by(3).map(|i| Ok((self.number(i)?, self.number(i + 1)?, self.value(i + 2)))).collect()
+    }
+
+    /// `numkeys key ... arg ...` of `EVAL` and `EVALSHA`.
+    fn script_keys(&mut self, from: usize) -> Result<(Vec<String>, Vec<String>), String> {
+        let numkeys: usize = self.number(from)?;
+        if numkeys > self.rest.len() - from - 1 {
+            return Err(format!("Number of keys is greater than the number of arguments for '{}'", self.name));
+        }
+        let keys = (from + 1..from + 1 + numkeys).map(|i| self.key(i)).collect::<Result<_, _>>()?;
+        Ok((keys, self.values(from + 1 + numkeys)))
+    }
+
+    fn bit_offset(&mut self, i: usize) -> Result<u64, String> {
+        let offset: u64 = self.number(i)?;
+        if offset > bitmap::MAX_OFFSET {
+            return Err(format!("Bit offset '{}' is out of range", offset));
+        }
+        Ok(offset)
+    }
+
+    fn bit(&mut self, i: usize) -> Result<bool, String> {
+        match self.rest[i].as_str() {
+            "0" | "1" => Ok(self.number::<u8>(i)? == 1),
+            other => Err(format!("Bit value '{}' must be 0 or 1", other)),
+        }
+    }
+
+    fn field_values(&mut self, from: usize) -> Result<Vec<(String, String)>, String> {
+        let len = self.rest.len();
+        if from >= len || !(len - from).is_multiple_of(2) {
+            return Err(format!("Wrong number of arguments for '{}'", self.name));
+        }
+        Ok((from..len).step_by(2).map(|i| (self.value(i), self.value(i + 1))).collect())
+    }
+
+    fn score(&mut self, i: usize) -> Result<f64, String> {
+        let score: f64 = self.number(i)?;
+        if !score.is_finite() {
+            return Err(format!("Invalid score '{}'", self.rest[i]));
+        }
+        Ok(score)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::parser::parse_command;
+    use crate::net::resp::RespValue;
+
+    const ADVERSARIAL: &[&str] = &[
+        "plain",
+        "",
+        "two words",
+        "x WHERE id = 1",
+        "\"quoted\"",
+        "'single'",
+        "ends with \\",
+        "back\\\"slash",
+        "line\nbreak\r\ttab",
+        "SET other 1",
+        "NULL",
+    ];
+
+    fn args(parts: &[&str]) -> Vec<String> {
+        parts.iter().map(|p| p.to_string()).collect()
+    }
+
+    fn bound(parts: &[&str]) -> (Command, String) {
+        bind(&args(parts)).expect("bound").expect("valid")
+    }
+
+    #[test]
+    fn values_bind_verbatim_and_replay_identically() {
+        for value in ADVERSARIAL {
+            let (cmd, line) = bound(&["SET", "k", value]);
+            assert_eq!(cmd, Command::Set { key: "k".to_string(), value: value.to_string() });
+            let (rest, replayed) = parse_command(&line).unwrap();
+            assert_eq!(rest, "", "leftover for {:?}", value);
+            assert_eq!(replayed, cmd, "replay of {:?}", value);
+
+            let (cmd, line) = bound(&["ZADD", "z", "xx", "1.5", value, "-2", value]);
+            assert_eq!(parse_command(&line).unwrap().1, cmd);
+
+            let (cmd, line) = bound(&["GEOADD", "g", "ch", "13.361389", "38.115556", value]);
+            assert_eq!(parse_command(&line).unwrap().1, cmd);
+
+            let (cmd, line) = bound(&["HSET", "h", value, value]);
+            assert_eq!(parse_command(&line).unwrap().1, cmd);
+
+            let (cmd, line) = bound(&["XADD", "s", "5-1", value, value]);
+            assert_eq!(parse_command(&line).unwrap().1, cmd);
+
+            let (cmd, line) = bound(&["EVAL", value, "1", "k", value]);
+            assert_eq!(parse_command(&line).unwrap().1, cmd);
+
+            let (cmd, line) = bound(&["INSERT", "users", "1", value]);
+            assert_eq!(cmd, Command::Insert { table: "users".to_string(), rows: vec![vec![Some("1".to_string()), Some(value.to_string())]] });
+            assert_eq!(parse_command(&line).unwrap().1, cmd);
+        }
+    }
+
+    #[test]
+    fn arity_and_keys_are_checked() {
+        assert!(bind(&args(&["SET", "k", "v", "extra"])).unwrap().is_err());
+        assert!(bind(&args(&["GET"])).unwrap().is_err());
+        assert!(bind(&args(&["GET", "bad key"])).unwrap().is_err());
+        assert!(bind(&args(&["LRANGE", "l", "0", "x"])).unwrap().is_err());
+        assert!(bind(&args(&["INSERT", "users; DROP", "1"])).unwrap().is_err());
+        assert!(bind(&args(&["SETBIT", "b", "7", "2"])).unwrap().is_err());
+        assert!(bind(&args(&["SETBIT", "b", "4294967296", "1"])).unwrap().is_err());
+        assert!(bind(&args(&["GEOADD", "g", "gt", "1", "2", "m"])).unwrap().is_err());
+        assert!(bind(&args(&["GEOADD", "g", "1", "2", "m", "3"])).unwrap().is_err());
+        assert!(bind(&args(&["EVAL", "1", "2", "k"])).unwrap().is_err());
+        // Session options and SQL queries are left to the parser
+        assert!(bind(&args(&["SET", "output", "=", "json"])).is_none());
+        assert!(bind(&args(&["SELECT", "*", "FROM", "users"])).is_none());
+        assert!(bind(&args(&["DELETE", "FROM", "users"])).is_none());
+    }
+
+    #[test]
+    fn aliases_bind_as_their_command() {
+        assert_eq!(bound(&["substr", "k", "0", "-1"]), (Command::GetRange { key: "k".to_string(), start: 0, end: -1 }, "GETRANGE k 0 -1".to_string()));
+        assert_eq!(bound(&["DELETE", "a", "b"]).1, "DEL a b");
+    }
+
+    #[test]
+    fn parsed_mode_keeps_spaced_values_intact() {
+        for value in ["ends with \\", "a \\n b", "tab\tsep", ""] {
+            let parts = ["SET", "k", value].map(|p| RespValue::BulkString(Some(p.as_bytes().to_vec())));
+            let line = RespValue::Array(Some(parts.to_vec())).to_command_string().unwrap();
+            assert_eq!(parse_command(&line).unwrap().1, Command::Set { key: "k".to_string(), value: value.to_string() });
+        }
+    }
+}
@@ -0,0 +1,139 @@
+//! # Generated Values
+//!
+//! `UUID()`, `ULID()` and `NOW()` outside quotes are replaced by a freshly
+//! generated value before a command is parsed, so they work wherever a
+//! literal or a key does: `INSERT sessions UUID() bob`,
+//! `SET session:ULID() active`, `WHERE expires_at < NOW()`. That covers
+//! inline commands and SQL statements; the elements of a RESP array sent to
+//! any other command are values, taken as sent (see
+//! `parser::generate_values`).
+//! Like session variables, the substitution happens on the command text, so
+//! the AOF and the replicas receive the generated value, not the call.
+//!
+//! - `UUID()`: a random version 4 UUID, `xxxxxxxx-xxxx-4xxx-yxxx-xxxxxxxxxxxx`.
+//! - `ULID()`: 26 characters of Crockford base32, a millisecond timestamp
+//!   followed by 80 random bits. ULIDs generated in the same millisecond
+//!   increment the random part, so they sort in generation order.
+//! - `NOW()`: the current Unix time in seconds, as stored in `datetime`
+//!   columns.
+//!
+//! A call glued to a longer name (`myUUID()`) is left as written, as are
+//! arguments bound in strict mode.
+
+use std::sync::Mutex;
+use crate::core::datetime;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const CROCKFORD: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+/// Mask of the random part of a ULID.
+const ULID_RANDOM_MASK: u128 = (1 << 80) - 1;
+
+/// Last ULID issued, as (milliseconds, random part).
+static LAST_ULID: Mutex<(u64, u128)> = Mutex::new((0, 0));
+
+/// A random (version 4) UUID.
+pub fn uuid() -> String {
+    let mut bytes: [u8; 16] = rand::random();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
+
+/// A ULID, greater than every ULID issued before by this process.
+pub fn ulid() -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0);
+    let mut last = LAST_ULID.lock().unwrap();
+    let (ms, random) = if now > last.0 {
+        (now, rand::random::<u128>() & ULID_RANDOM_MASK)
+    } else if last.1 < ULID_RANDOM_MASK {
+        // Same millisecond (or the clock went back): stay after the last one
+        (last.0, last.1 + 1)
+    } else {
+        (last.0 + 1, 0)
+    };
+    *last = (ms, random);
+    encode_ulid(ms, random)
+}
+
+fn encode_ulid(ms: u64, random: u128) -> String {
+    let value = ((ms as u128 & ((1 << 48) - 1)) << 80) | random;
+    (0..26).rev().map(|i| CROCKFORD[((value >> (i * 5)) & 0x1f) as usize] as char).collect()
+}
+
+/// Replaces the `UUID()` and `ULID()` calls of `input` outside quotes.
+pub fn substitute(input: &str) -> String {
+    if !input.contains("()") {
+        return input.to_string();
+    }
+    let mut out = String::with_capacity(input.len());
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    let mut prev: Option<char> = None;
+    let mut pos = 0;
+    while let Some(c) = input[pos..].chars().next() {
+        if let Some(q) = quote {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == q {
+                quote = None;
+            }
+        } else if c == '"' || c == '\'' {
+            quote = Some(c);
+        } else if !prev.is_some_and(|p| p.is_alphanumeric() || p == '_') {
+            let is_call = |name: &str| input.get(pos..pos + name.len()).is_some_and(|call| call.eq_ignore_ascii_case(name));
+            let generated = if is_call("UUID()") {
+                Some((uuid(), 6))
+            } else if is_call("ULID()") {
+                Some((ulid(), 6))
+            } else if is_call("NOW()") {
+                Some((datetime::now().to_string(), 5))
+            } else {
+                None
+            };
+            if let Some((value, len)) = generated {
+                out.push_str(&value);
+                pos += len;
+                prev = Some(')');
+                continue;
+            }
+        }
+        out.push(c);
+        prev = Some(c);
+        pos += c.len_utf8();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn calls_become_identifiers() {
+        let id = uuid();
+        assert_eq!(id.len(), 36);
+        assert_eq!(&id[14..15], "4");
+        assert!("89ab".contains(&id[19..20]), "{}", id);
+
+        let ulids: Vec<String> = (0..1000).map(|_| ulid()).collect();
+        assert!(ulids.iter().all(|u| u.len() == 26));
+        assert!(ulids.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(encode_ulid(1, 0), "00000000010000000000000000");
+        assert_eq!(encode_ulid((1 << 48) - 1, ULID_RANDOM_MASK), "7ZZZZZZZZZZZZZZZZZZZZZZZZZ");
+
+        let line = substitute("INSERT t uuid() session:ULID() 'UUID()' myUUID()");
+        let parts: Vec<&str> = line.split(' ').collect();
+        assert_eq!(parts[2].len(), 36);
+        assert!(parts[3].starts_with("session:") && parts[3].len() == 34);
+        assert_eq!(parts[4..], ["'UUID()'", "myUUID()"]);
+        assert_eq!(substitute("GET é()"), "GET é()");
+
+        let line = substitute("SELECT * FROM t WHERE at < now() AND note = 'NOW()' AND x = snow()");
+        let now: i64 = line.split(' ').nth(7).unwrap().parse().unwrap();
+        assert!((now - datetime::now()).abs() <= 1);
+        assert!(line.ends_with("note = 'NOW()' AND x = snow()"));
+    }
+}
@@ -0,0 +1,117 @@
+//! # Listeners
+//!
+//! The server can listen on several addresses at once, e.g. loopback plus a
+//! private VPC address, configured as a comma-separated list in `DB_HOST`:
+//! `127.0.0.1,10.0.0.5,[::]`. An entry may carry its own port
+//! (`10.0.0.5:9000`); otherwise `DB_PORT` is used. Binding the IPv6 wildcard
+//! `[::]` makes a dual-stack listener that also accepts IPv4 clients.
+//!
+//! Every listener feeds the same accept loop through [`accept_all`].
+//! `SO_REUSEADDR` is always set so restarts don't wait for `TIME_WAIT`
+//! sockets; `SO_REUSEPORT` (several processes sharing a port) is opt-in.
+
+use std::io;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::time::Duration;
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+
+const BACKLOG: i32 = 1024;
+
+/// Pause after a failed accept, so a persistent error (e.g. out of file
+/// descriptors) doesn't spin the loop.
+pub const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Addresses named by a `DB_HOST` list, in order and without duplicates.
+/// Host names are resolved.
+pub fn bind_addrs(hosts: &str, port: u16) -> anyhow::Result<Vec<SocketAddr>> {
+    let mut addrs = Vec::new();
+    for entry in hosts.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let resolved: Vec<SocketAddr> = if let Ok(addr) = entry.parse::<SocketAddr>() {
+            vec![addr]
+        } else if let Ok(ip) = entry.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+            vec![SocketAddr::new(ip, port)]
+        } else {
+            let target = if entry.contains(':') { entry.to_string() } else { format!("{}:{}", entry, port) };
+            target.to_socket_addrs()
+                .map_err(|e| anyhow::anyhow!("Invalid bind address '{}': {}", entry, e))?
+                .collect()
+        };
+        for addr in resolved {
+            if !addrs.contains(&addr) {
+                addrs.push(addr);
+            }
+        }
+    }
+    if addrs.is_empty() {
+        return Err(anyhow::anyhow!("No bind address given"));
+    }
+    Ok(addrs)
+}
+
+/// Binds one listener. The IPv6 wildcard also accepts IPv4 connections.
+pub fn bind(addr: SocketAddr, reuse_port: bool) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    socket.set_reuse_address(true)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(reuse_port)?;
+    #[cfg(not(unix))]
+    let _ = reuse_port;
+    if let SocketAddr::V6(v6) = addr && v6.ip().is_unspecified() {
+        socket.set_only_v6(false)?;
+    }
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(BACKLOG)?;
+    TcpListener::from_std(socket.into())
+}
+
+/// Accepts on every listener, delivering the connections on one channel.
+pub fn accept_all(listeners: Vec<TcpListener>) -> mpsc::Receiver<(TcpStream, SocketAddr)> {
+    let (tx, rx) = mpsc::channel(BACKLOG as usize);
+    for listener in listeners {
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok(conn) => {
+                        if tx.send(conn).await.is_err() {
+                            break;
+                        }
+                    }
+                    // Usually transient (e.g. out of file descriptors); keep listening
+                    Err(e) => {
+                        crate::core::logger::error(&format!("Accept failed: {}", e));
+                        tokio::time::sleep(ACCEPT_BACKOFF).await;
+                    }
+                }
+            }
+        });
+    }
+    rx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn host_lists() {
+        let addrs = bind_addrs("127.0.0.1, 10.0.0.5:9000,[::],::1,127.0.0.1", 8569).unwrap();
+        let expected: Vec<SocketAddr> = ["127.0.0.1:8569", "10.0.0.5:9000", "[::]:8569", "[::1]:8569"]
+            .iter().map(|a| a.parse().unwrap()).collect();
+        assert_eq!(addrs, expected);
+        assert!(bind_addrs(" , ", 8569).is_err());
+    }
+
+    #[tokio::test]
+    async fn dual_stack_wildcard_accepts_ipv4() {
+        let Ok(listener) = bind("[::]:0".parse().unwrap(), false) else { return }; // no IPv6 here
+        let port = listener.local_addr().unwrap().port();
+        let mut conns = accept_all(vec![listener]);
+        TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let (_, peer) = conns.recv().await.unwrap();
+        assert!(peer.ip().to_canonical().is_loopback());
+    }
+}
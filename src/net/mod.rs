@@ -1,2 +1,7 @@
+pub mod binding;
+pub mod functions;
+pub mod listener;
 pub mod parser;
+pub mod prometheus;
 pub mod resp;
+pub mod variables;
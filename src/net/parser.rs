@@ -1,14 +1,23 @@
 
 use nom::{
     branch::alt,
-    bytes::complete::{tag, tag_no_case, take_while},
-    character::complete::{alpha1, char, multispace0, multispace1},
-    combinator::{map, opt, recognize},
-    multi::{separated_list1, many0},
-    sequence::{delimited, pair, preceded, tuple},
+    bytes::complete::{tag, tag_no_case, take_while, take_while1},
+    character::complete::{alpha1, alphanumeric1, char, digit1, multispace0, multispace1},
+    combinator::{map, map_opt, map_res, opt, recognize, verify},
+    multi::{separated_list1, many0, many1},
+    sequence::{delimited, pair, preceded, separated_pair, terminated, tuple},
     IResult,
 };
-use crate::query::{Command, Operator, Filter, Selector, AlterOp, JoinType, JoinClause};
+use crate::query::eval;
+use crate::query::{Command, Operator, Filter, join_value_list, Selector, AlterOp, JoinType, JoinClause, Expr, ArithOp, Projection, ScalarFn, ZAddFlags, ColumnDef, ExpiryUpdate, GeoOrigin, SlotState};
+use crate::core::structured::FkAction;
+use crate::core::topk;
+use crate::core::bitmap;
+use crate::core::geo;
+use crate::core::hnsw::Metric;
+use crate::core::datetime::TimeUnit;
+use crate::core::types::UnifiedValue;
+use crate::net::{functions, variables};
 
 fn parse_identifier(input: &str) -> IResult<&str, &str> {
     recognize(pair(
@@ -17,13 +26,65 @@ fn parse_identifier(input: &str) -> IResult<&str, &str> {
     ))(input)
 }
 
+// Braces are allowed for cluster hash tags: `{user:1}:cart`
 fn parse_key(input: &str) -> IResult<&str, &str> {
     recognize(pair(
-        alt((alpha1, nom::character::complete::digit1, tag("_"), tag("+"), tag("-"), tag("@"), tag("$"), tag("*"))),
-        take_while(|c: char| c.is_alphanumeric() || c == '_' || c == ':' || c == '-' || c == '.' || c == '+' || c == '@' || c == '$' || c == '*')
+        alt((alpha1, nom::character::complete::digit1, tag("_"), tag("+"), tag("-"), tag("@"), tag("$"), tag("*"), tag("{"))),
+        take_while(|c: char| c.is_alphanumeric() || c == '_' || c == ':' || c == '-' || c == '.' || c == '+' || c == '@' || c == '$' || c == '*' || c == '{' || c == '}')
     ))(input)
 }
 
+/// Redis synonyms accepted for compatibility, as (alias, command).
+pub const COMMAND_ALIASES: &[(&str, &str)] = &[
+    ("SUBSTR", "GETRANGE"),
+    // Only in key-value form: `DELETE FROM` stays the SQL statement
+    ("DELETE", "DEL"),
+];
+
+/// The command an alias stands for, or `word` itself, uppercased.
+pub fn resolve_alias(word: &str) -> String {
+    let upper = word.to_ascii_uppercase();
+    COMMAND_ALIASES.iter()
+        .find(|(alias, _)| *alias == upper)
+        .map_or(upper, |(_, command)| command.to_string())
+}
+
+// Command name or one of its aliases, in any case
+fn command_name<'a>(name: &'static str) -> impl FnMut(&'a str) -> IResult<&'a str, &'a str> {
+    verify(take_while1(|c: char| c.is_ascii_alphanumeric()), move |word: &str| resolve_alias(word) == name)
+}
+
+/// True if `s` is a whole key, i.e. it can be written unquoted in a command.
+pub fn is_key(s: &str) -> bool {
+    matches!(parse_key(s), Ok(("", _)))
+}
+
+/// True if `s` is a whole table/column identifier.
+pub fn is_identifier(s: &str) -> bool {
+    matches!(parse_identifier(s), Ok(("", _)))
+}
+
+/// Quotes `s` as a string literal that parses back to exactly `s`.
+///
+/// Backslashes are escaped as well, so a backslash already in the value can
+/// never combine with the next character into an escape sequence.
+pub fn quote_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            _ => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
 fn parse_quoted_string(input: &str) -> IResult<&str, String> {
     let (input, _) = char('\"')(input)?;
     let mut res = String::new();
@@ -102,6 +163,27 @@ fn parse_string(input: &str) -> IResult<&str, String> {
     ))(input)
 }
 
+fn is_null_keyword(s: &str) -> bool {
+    s.eq_ignore_ascii_case("NULL")
+}
+
+// Value that may be the bare keyword NULL (None); a quoted "NULL" is a string
+fn parse_literal(input: &str) -> IResult<&str, Option<String>> {
+    alt((
+        map(verify(parse_key, is_null_keyword), |_| None),
+        map(parse_string, Some),
+    ))(input)
+}
+
+// Comparison operand; a bare NULL is only valid through `= NULL` / `!= NULL`
+fn parse_operand(input: &str) -> IResult<&str, String> {
+    alt((
+        parse_quoted_string,
+        parse_single_quoted_string,
+        map(verify(parse_key, |k: &str| !is_null_keyword(k)), |s| s.to_string())
+    ))(input)
+}
+
 fn parse_set(input: &str) -> IResult<&str, Command> {
     map(
         tuple((
@@ -111,7 +193,7 @@ fn parse_set(input: &str) -> IResult<&str, Command> {
             multispace1,
             parse_string,
         )),
-        |(_, _, key, _, value)| Command::Set { key: key.to_string(), value: value.trim().to_string() }
+        |(_, _, key, _, value)| Command::Set { key: key.to_string(), value }
     )(input)
 }
 
@@ -127,17 +209,53 @@ fn parse_get(input: &str) -> IResult<&str, Command> {
     )(input)
 }
 
+// DEL key [key ...]
 fn parse_del(input: &str) -> IResult<&str, Command> {
+    map(
+        verify(
+            tuple((
+                command_name("DEL"),
+                multispace1,
+                separated_list1(multispace1, parse_string)
+            )),
+            |(name, _, keys): &(&str, &str, Vec<String>)| !(name.eq_ignore_ascii_case("DELETE") && keys[0].eq_ignore_ascii_case("FROM"))
+        ),
+        |(_, _, keys)| Command::Del { keys }
+    )(input)
+}
+
+// GETRANGE key start end
+fn parse_getrange(input: &str) -> IResult<&str, Command> {
     map(
         tuple((
-            tag_no_case("DEL"),
+            command_name("GETRANGE"),
             multispace1,
-            separated_list1(multispace1, parse_key)
+            parse_key,
+            multispace1,
+            nom::character::complete::i64,
+            multispace1,
+            nom::character::complete::i64,
         )),
-        |(_, _, keys)| Command::Del { keys: keys.iter().map(|k| k.to_string()).collect() }
+        |(_, _, key, _, start, _, end)| Command::GetRange { key: key.to_string(), start, end }
+    )(input)
+}
+
+// GETEX key [EX seconds | PX milliseconds | EXAT unix-seconds | PXAT unix-milliseconds | PERSIST]
+fn parse_getex(input: &str) -> IResult<&str, Command> {
+    let expiry = alt((
+        map(preceded(pair(tag_no_case("EXAT"), multispace1), nom::character::complete::u64), |s| ExpiryUpdate::At(s.saturating_mul(1000))),
+        map(preceded(pair(tag_no_case("PXAT"), multispace1), nom::character::complete::u64), ExpiryUpdate::At),
+        map(preceded(pair(tag_no_case("EX"), multispace1), nom::character::complete::u64), |s| ExpiryUpdate::After(s.saturating_mul(1000))),
+        map(preceded(pair(tag_no_case("PX"), multispace1), nom::character::complete::u64), ExpiryUpdate::After),
+        map(tag_no_case("PERSIST"), |_| ExpiryUpdate::Persist),
+    ));
+    map(
+        tuple((tag_no_case("GETEX"), multispace1, parse_key, opt(preceded(multispace1, expiry)))),
+        |(_, _, key, expiry)| Command::GetEx { key: key.to_string(), expiry }
     )(input)
 }
 
+// SETEX key ttl value [JITTER percent]
 fn parse_setex(input: &str) -> IResult<&str, Command> {
     map(
         tuple((
@@ -148,11 +266,13 @@ fn parse_setex(input: &str) -> IResult<&str, Command> {
             nom::character::complete::digit1,
             multispace1,
             parse_string,
+            parse_jitter,
         )),
-        |(_, _, key, _, ttl_str, _, value)| {
+        |(_, _, key, _, ttl_str, _, value, jitter)| {
             Command::SetEx { 
                 key: key.to_string(), 
-                value: value.trim().to_string(),
+                value,
+                jitter,
                 ttl: ttl_str.parse().unwrap_or(0)
             }
         }
@@ -171,6 +291,187 @@ fn parse_ttl(input: &str) -> IResult<&str, Command> {
     )(input)
 }
 
+// Optional ` JITTER <percent>` suffix of TTL commands
+fn parse_jitter(input: &str) -> IResult<&str, Option<u8>> {
+    opt(preceded(tuple((multispace1, tag_no_case("JITTER"), multispace1)), nom::character::complete::u8))(input)
+}
+
+// EXPIRE key seconds [JITTER percent]
+fn parse_expire(input: &str) -> IResult<&str, Command> {
+    map(
+        tuple((
+            tag_no_case("EXPIRE"),
+            multispace1,
+            parse_key,
+            multispace1,
+            nom::character::complete::u64,
+            parse_jitter,
+        )),
+        |(_, _, key, _, seconds, jitter)| Command::Expire { key: key.to_string(), seconds, jitter }
+    )(input)
+}
+
+// PEXPIREAT key unix_ms
+fn parse_pexpireat(input: &str) -> IResult<&str, Command> {
+    map(
+        tuple((
+            tag_no_case("PEXPIREAT"),
+            multispace1,
+            parse_key,
+            multispace1,
+            nom::character::complete::u64,
+        )),
+        |(_, _, key, _, timestamp_ms)| Command::PExpireAt { key: key.to_string(), timestamp_ms }
+    )(input)
+}
+
+// SCHEDULE key value AT unix_ms [PUSH]
+fn parse_schedule(input: &str) -> IResult<&str, Command> {
+    map(
+        tuple((
+            tag_no_case("SCHEDULE"),
+            multispace1,
+            parse_key,
+            multispace1,
+            parse_string,
+            multispace1,
+            tag_no_case("AT"),
+            multispace1,
+            nom::character::complete::u64,
+            opt(preceded(multispace1, tag_no_case("PUSH"))),
+        )),
+        |(_, _, key, _, value, _, _, _, at_ms, push)| Command::Schedule { key: key.to_string(), value, at_ms, push: push.is_some() }
+    )(input)
+}
+
+// UNSCHEDULE key [AT unix_ms]
+fn parse_unschedule(input: &str) -> IResult<&str, Command> {
+    map(
+        tuple((
+            tag_no_case("UNSCHEDULE"),
+            multispace1,
+            parse_key,
+            opt(preceded(tuple((multispace1, tag_no_case("AT"), multispace1)), nom::character::complete::u64)),
+        )),
+        |(_, _, key, at_ms)| Command::Unschedule { key: key.to_string(), at_ms }
+    )(input)
+}
+
+// Glob pattern: quoted, or any run of non-whitespace characters
+fn parse_pattern(input: &str) -> IResult<&str, String> {
+    alt((
+        parse_quoted_string,
+        parse_single_quoted_string,
+        map(take_while1(|c: char| !c.is_whitespace()), |s: &str| s.to_string())
+    ))(input)
+}
+
+enum ScanOption {
+    Match(String),
+    Count(usize),
+}
+
+// SCAN cursor [MATCH pattern] [COUNT n]
+fn parse_scan(input: &str) -> IResult<&str, Command> {
+    let scan_option = alt((
+        map(
+            tuple((multispace1, tag_no_case("MATCH"), multispace1, parse_pattern)),
+            |(_, _, _, p)| ScanOption::Match(p)
+        ),
+        map(
+            tuple((multispace1, tag_no_case("COUNT"), multispace1, nom::character::complete::u64)),
+            |(_, _, _, n)| ScanOption::Count(n as usize)
+        ),
+    ));
+
+    map(
+        tuple((
+            tag_no_case("SCAN"),
+            multispace1,
+            nom::character::complete::u64,
+            many0(scan_option),
+        )),
+        |(_, _, cursor, options)| {
+            let mut pattern = None;
+            let mut count = None;
+            for option in options {
+                match option {
+                    ScanOption::Match(p) => pattern = Some(p),
+                    ScanOption::Count(n) => count = Some(n),
+                }
+            }
+            Command::Scan { cursor, pattern, count }
+        }
+    )(input)
+}
+
+// KEYS pattern
+fn parse_keys(input: &str) -> IResult<&str, Command> {
+    map(
+        tuple((tag_no_case("KEYS"), multispace1, parse_pattern)),
+        |(_, _, pattern)| Command::Keys { pattern }
+    )(input)
+}
+
+// EXPORT KEYS [MATCH pattern] TO path
+fn parse_export(input: &str) -> IResult<&str, Command> {
+    map(
+        tuple((
+            tag_no_case("EXPORT"), multispace1, tag_no_case("KEYS"),
+            opt(preceded(tuple((multispace1, tag_no_case("MATCH"), multispace1)), parse_pattern)),
+            multispace1, tag_no_case("TO"), multispace1, parse_pattern,
+        )),
+        |(_, _, _, pattern, _, _, _, path)| Command::ExportKeys { pattern, path }
+    )(input)
+}
+
+// DUMP key
+fn parse_dump(input: &str) -> IResult<&str, Command> {
+    map(
+        tuple((tag_no_case("DUMP"), multispace1, parse_key)),
+        |(_, _, key)| Command::Dump { key: key.to_string() }
+    )(input)
+}
+
+// RESTORE key ttl payload [REPLACE] [ABSTTL]
+fn parse_restore(input: &str) -> IResult<&str, Command> {
+    map(
+        tuple((
+            tag_no_case("RESTORE"), multispace1, parse_key, multispace1, map_res(digit1, str::parse::<u64>), multispace1, parse_string,
+            many0(preceded(multispace1, alt((tag_no_case("REPLACE"), tag_no_case("ABSTTL"))))),
+        )),
+        |(_, _, key, _, ttl, _, payload, options)| Command::Restore {
+            key: key.to_string(), ttl, payload,
+            replace: options.iter().any(|o| o.eq_ignore_ascii_case("REPLACE")),
+            absttl: options.iter().any(|o| o.eq_ignore_ascii_case("ABSTTL")),
+        }
+    )(input)
+}
+
+// TYPE key
+fn parse_type(input: &str) -> IResult<&str, Command> {
+    map(
+        tuple((tag_no_case("TYPE"), multispace1, parse_key)),
+        |(_, _, key)| Command::Type { key: key.to_string() }
+    )(input)
+}
+
+fn parse_dbsize(input: &str) -> IResult<&str, Command> {
+    map(tag_no_case("DBSIZE"), |_| Command::DbSize)(input)
+}
+
+// EXISTS key [key ...]
+fn parse_exists(input: &str) -> IResult<&str, Command> {
+    map(
+        tuple((
+            tag_no_case("EXISTS"),
+            multispace1,
+            separated_list1(multispace1, parse_key)
+        )),
+        |(_, _, keys)| Command::Exists { keys: keys.iter().map(|k| k.to_string()).collect() }
+    )(input)
+}
+
 // AUTH password
 fn parse_auth(input: &str) -> IResult<&str, Command> {
     alt((
@@ -209,34 +510,22 @@ fn parse_decr(input: &str) -> IResult<&str, Command> {
     )(input)
 }
 
-// CREATE TABLE name (col1 type [PK], col2 type)
-// Syntax: CREATE TABLE name col:type[:pk] col:type ...
-fn parse_create_table(input: &str) -> IResult<&str, Command> {
-    let parse_col_def = map(
+// CINCR key [increment]
+fn parse_cincr(input: &str) -> IResult<&str, Command> {
+    map(
         tuple((
-            parse_identifier,
-            char(':'),
-            parse_identifier,
-            // Optional :pk
-            opt(preceded(char(':'), alt((tag_no_case("pk"), tag_no_case("primary key"))))),
-            // Optional :fk(table.col)
-            opt(preceded(
-                tuple((char(':'), tag_no_case("fk"))), 
-                delimited(
-                    char('('), 
-                    pair(parse_identifier, preceded(char('.'), parse_identifier)),
-                    char(')')
-                )
-            ))
-        )),
-        |(name, _, dtype, pk, fk)| (
-            name.to_string(), 
-            dtype.to_string(), 
-            pk.is_some(), 
-            fk.map(|(t, c)| (t.to_string(), c.to_string()))
-        )
-    );
+            tag_no_case("CINCR"),
+            multispace1,
+            parse_key,
+            opt(preceded(multispace1, nom::character::complete::i64)),
+        )),
+        |(_, _, key, by)| Command::CIncr { key: key.to_string(), by: by.unwrap_or(1) }
+    )(input)
+}
 
+// CREATE TABLE name (col1 type [PK], col2 type)
+// Syntax: CREATE TABLE name col:type[:pk][:fk(t.c)][:notnull][:unique][:default(v)] ...
+fn parse_create_table(input: &str) -> IResult<&str, Command> {
     map(
         tuple((
             tag_no_case("CREATE"),
@@ -251,6 +540,65 @@ fn parse_create_table(input: &str) -> IResult<&str, Command> {
     )(input)
 }
 
+enum ColumnModifier {
+    PrimaryKey,
+    References(String, String),
+    OnChange(FkAction),
+    NotNull,
+    Unique,
+    Default(String),
+}
+
+fn parse_col_def(input: &str) -> IResult<&str, ColumnDef> {
+    let modifier = alt((
+        map(alt((tag_no_case("pk"), tag_no_case("primary key"))), |_| ColumnModifier::PrimaryKey),
+        map(
+            preceded(
+                tag_no_case("fk"),
+                delimited(char('('), pair(parse_identifier, preceded(char('.'), parse_identifier)), char(')'))
+            ),
+            |(t, c)| ColumnModifier::References(t.to_string(), c.to_string())
+        ),
+        map(tag_no_case("cascade"), |_| ColumnModifier::OnChange(FkAction::Cascade)),
+        map(tag_no_case("restrict"), |_| ColumnModifier::OnChange(FkAction::Restrict)),
+        map(tag_no_case("setnull"), |_| ColumnModifier::OnChange(FkAction::SetNull)),
+        map(tag_no_case("notnull"), |_| ColumnModifier::NotNull),
+        map(tag_no_case("unique"), |_| ColumnModifier::Unique),
+        map(
+            preceded(tag_no_case("default"), delimited(char('('), parse_string, char(')'))),
+            ColumnModifier::Default
+        ),
+    ));
+
+    map(
+        tuple((
+            parse_identifier,
+            char(':'),
+            parse_type_name,
+            many0(preceded(char(':'), modifier)),
+        )),
+        |(name, _, dtype, modifiers)| {
+            let mut def = ColumnDef { name: name.to_string(), data_type: dtype.to_string(), ..Default::default() };
+            for m in modifiers {
+                match m {
+                    ColumnModifier::PrimaryKey => def.primary_key = true,
+                    ColumnModifier::References(t, c) => def.references = Some((t, c)),
+                    ColumnModifier::OnChange(action) => def.fk_action = action,
+                    ColumnModifier::NotNull => def.not_null = true,
+                    ColumnModifier::Unique => def.unique = true,
+                    ColumnModifier::Default(v) => def.default = Some(v),
+                }
+            }
+            def
+        }
+    )(input)
+}
+
+// Column type: `int`, `string`, ... or `vector(768)`
+fn parse_type_name(input: &str) -> IResult<&str, &str> {
+    recognize(pair(parse_identifier, opt(delimited(char('('), digit1, char(')')))))(input)
+}
+
 // ALTER TABLE name ADD/DROP ...
 fn parse_alter_table(input: &str) -> IResult<&str, Command> {
     let parse_add = map(
@@ -259,7 +607,7 @@ fn parse_alter_table(input: &str) -> IResult<&str, Command> {
             multispace1,
             parse_identifier,
             char(':'),
-            parse_identifier
+            parse_type_name
         )),
         |(_, _, col, _, dtype)| AlterOp::Add(col.to_string(), dtype.to_string())
     );
@@ -458,32 +806,41 @@ fn parse_json_set(input: &str) -> IResult<&str, Command> {
     )(input)
 }
 
-// INSERT INTO table (val1, val2) -> Simplified: INSERT table val1 val2
+// INSERT table val1 val2 ... | INSERT [INTO] table VALUES (val1, val2), (val3, val4), ...
 fn parse_insert(input: &str) -> IResult<&str, Command> {
-    map(
-        tuple((
-            tag("INSERT"),
-            multispace1,
-            parse_identifier,
-            multispace1,
-            separated_list1(multispace1, parse_string)
-        )),
-        |(_, _, table, _, values)| Command::Insert { table: table.to_string(), values }
-    )(input)
+    let list_separator = || tuple((multispace0, char(','), multispace0));
+    let row = delimited(
+        pair(char('('), multispace0),
+        separated_list1(list_separator(), parse_literal),
+        pair(multispace0, char(')'))
+    );
+    alt((
+        map(
+            tuple((
+                tag_no_case("INSERT"), multispace1, opt(pair(tag_no_case("INTO"), multispace1)),
+                parse_identifier, multispace1, tag_no_case("VALUES"), multispace0,
+                separated_list1(list_separator(), row),
+            )),
+            |(_, _, _, table, _, _, _, rows)| Command::Insert { table: table.to_string(), rows }
+        ),
+        map(
+            tuple((
+                tag("INSERT"),
+                multispace1,
+                parse_identifier,
+                multispace1,
+                separated_list1(multispace1, parse_literal)
+            )),
+            |(_, _, table, _, values)| Command::Insert { table: table.to_string(), rows: vec![values] }
+        ),
+    ))(input)
 }
 
-
-
-// ... existing imports ...
-
-
-
-// ... existing imports ...
-
 fn parse_operator(input: &str) -> IResult<&str, Operator> {
     alt((
-        map(tag("LIKE"), |_| Operator::Like),
-        map(tag("IN"), |_| Operator::In),
+        map(tag_no_case("LIKE"), |_| Operator::Like),
+        map(tag_no_case("ILIKE"), |_| Operator::ILike),
+        map(tag_no_case("IN"), |_| Operator::In),
         map(tag("="), |_| Operator::Eq),
         map(tag("!="), |_| Operator::Neq),
         map(tag(">="), |_| Operator::Gte),
@@ -493,18 +850,18 @@ fn parse_operator(input: &str) -> IResult<&str, Operator> {
     ))(input)
 }
 
-// Helper to parse a list of values: (val1, val2, ...)
+// Helper to parse a list of values: (val1, 'val 2', "val,3", ...)
 fn parse_value_list(input: &str) -> IResult<&str, String> {
     delimited(
-        char('('),
+        pair(char('('), multispace0),
         map(
             separated_list1(
                 tuple((multispace0, char(','), multispace0)),
                 parse_string
             ),
-            |vals| vals.join(",")
+            |vals| join_value_list(&vals)
         ),
-        char(')')
+        pair(multispace0, char(')'))
     )(input)
 }
 
@@ -554,90 +911,662 @@ fn parse_column_expr(input: &str) -> IResult<&str, String> {
     Ok((remaining, result))
 }
 
-// Atom: col op val  (col can be column->path)
-fn parse_condition(input: &str) -> IResult<&str, Filter> {
+// Numeric literal in a projection: 3, -7 or 1.5
+fn parse_number_literal(input: &str) -> IResult<&str, Expr> {
+    map(
+        recognize(tuple((opt(char('-')), digit1, opt(pair(char('.'), digit1))))),
+        |n: &str| if n.contains('.') {
+            Expr::Literal(UnifiedValue::Float(n.parse().unwrap_or(0.0)))
+        } else {
+            Expr::Literal(UnifiedValue::Integer(n.parse().unwrap_or(0)))
+        }
+    )(input)
+}
+
+// LOWER(name), ABS(a - b), ...
+fn parse_function_call(input: &str) -> IResult<&str, Expr> {
+    map(
+        pair(
+            map_res(parse_identifier, str::parse::<ScalarFn>),
+            delimited(pair(char('('), multispace0), parse_expr, pair(multispace0, char(')'))),
+        ),
+        |(func, arg)| Expr::Call(func, Box::new(arg))
+    )(input)
+}
+
+// DATE_ADD(at, amount, unit) / DATE_DIFF(to, from, unit)
+fn parse_date_function(input: &str) -> IResult<&str, Expr> {
+    let arg = |input| delimited(multispace0, parse_expr, multispace0)(input);
     map(
         tuple((
-            parse_column_expr,
-            multispace1,
-            parse_operator,
-            multispace1,
-            alt((
-                parse_value_list, // Try parsing list first for IN
-                parse_string
-            )),
+            alt((tag_no_case("DATE_ADD"), tag_no_case("DATE_DIFF"))),
+            char('('),
+            arg,
+            char(','),
+            arg,
+            char(','),
+            delimited(multispace0, map_res(alpha1, str::parse::<TimeUnit>), multispace0),
+            char(')'),
         )),
-        |(col, _, op, _, val)| Filter::Condition(col, op, val)
+        |(name, _, first, _, second, _, unit, _)| if name.eq_ignore_ascii_case("DATE_ADD") {
+            Expr::DateAdd(Box::new(first), Box::new(second), unit)
+        } else {
+            Expr::DateDiff(Box::new(first), Box::new(second), unit)
+        }
     )(input)
 }
 
-fn parse_atom(input: &str) -> IResult<&str, Filter> {
+fn parse_expr_factor(input: &str) -> IResult<&str, Expr> {
     alt((
-        delimited(
-            tuple((char('('), multispace0)),
-            parse_filter,
-            tuple((multispace0, char(')')))
-        ),
-        parse_condition,
+        parse_number_literal,
+        map(alt((parse_quoted_string, parse_single_quoted_string)), |s| Expr::Literal(UnifiedValue::String(s))),
+        parse_date_function,
+        map(parse_aggregate, |agg| Expr::Aggregate(Box::new(agg))),
+        parse_function_call,
+        map(parse_column_expr, Expr::Column),
+        delimited(pair(char('('), multispace0), parse_expr, pair(multispace0, char(')'))),
     ))(input)
 }
 
-// Term: Atom AND Atom AND ...
-fn parse_and_term(input: &str) -> IResult<&str, Filter> {
-    let (input, first) = parse_atom(input)?;
-    let (input, rest) = nom::multi::fold_many0(
-        preceded(tuple((multispace1, tag("AND"), multispace1)), parse_atom),
-        move || first.clone(),
-        |acc, val| Filter::And(Box::new(acc), Box::new(val))
-    )(input)?;
-    Ok((input, rest))
+// Left-associative chain of `operand (op operand)*`
+fn fold_binary<'a>(
+    input: &'a str,
+    operand: fn(&str) -> IResult<&str, Expr>,
+    ops: &'static str,
+) -> IResult<&'a str, Expr> {
+    let (input, first) = operand(input)?;
+    let (input, rest) = many0(pair(
+        delimited(multispace0, nom::character::complete::one_of(ops), multispace0),
+        operand,
+    ))(input)?;
+    let expr = rest.into_iter().fold(first, |acc, (op, rhs)| {
+        let op = match op {
+            '+' => ArithOp::Add,
+            '-' => ArithOp::Sub,
+            '*' => ArithOp::Mul,
+            _ => ArithOp::Div,
+        };
+        Expr::Binary(Box::new(acc), op, Box::new(rhs))
+    });
+    Ok((input, expr))
 }
 
-// Expr: Term OR Term OR ...
-fn parse_filter(input: &str) -> IResult<&str, Filter> {
-    let (input, first) = parse_and_term(input)?;
-    let (input, rest) = nom::multi::fold_many0(
-        preceded(tuple((multispace1, tag("OR"), multispace1)), parse_and_term),
-        move || first.clone(),
-        |acc, val| Filter::Or(Box::new(acc), Box::new(val))
-    )(input)?;
-    Ok((input, rest))
+fn parse_expr_term(input: &str) -> IResult<&str, Expr> {
+    fold_binary(input, parse_expr_factor, "*/")
 }
 
-// ZSET Commands
-fn parse_zadd(input: &str) -> IResult<&str, Command> {
-    map(
-        tuple((tag_no_case("ZADD"), multispace1, parse_key, multispace1, nom::number::complete::double, multispace1, parse_string)),
-        |(_, _, key, _, score, _, member)| Command::ZAdd { key: key.to_string(), score, member }
-    )(input)
+// Arithmetic over columns and numbers; * and / bind tighter than + and -
+fn parse_expr(input: &str) -> IResult<&str, Expr> {
+    fold_binary(input, parse_expr_term, "+-")
 }
 
-fn parse_zrange(input: &str) -> IResult<&str, Command> {
+// expr [AS alias]
+fn parse_projection(input: &str) -> IResult<&str, Projection> {
     map(
-        tuple((tag_no_case("ZRANGE"), multispace1, parse_key, multispace1, nom::character::complete::i64, multispace1, nom::character::complete::i64)),
-        |(_, _, key, _, start, _, stop)| Command::ZRange { key: key.to_string(), start, stop }
+        pair(
+            parse_expr,
+            opt(preceded(tuple((multispace1, tag_no_case("AS"), multispace1)), parse_identifier)),
+        ),
+        |(expr, alias)| Projection { expr, alias: alias.map(|a| a.to_string()) }
     )(input)
 }
 
-fn parse_zscore(input: &str) -> IResult<&str, Command> {
-    map(
-        tuple((tag_no_case("ZSCORE"), multispace1, parse_key, multispace1, parse_string)),
-        |(_, _, key, _, member)| Command::ZScore { key: key.to_string(), member }
-    )(input)
+// Right-hand side of a comparison. DATE_ADD / DATE_DIFF over constants (NOW()
+// has been replaced by a timestamp already) is folded into its value, so
+// `at > DATE_ADD(NOW(), -7, DAY)` stays an indexable condition on `at`
+fn parse_comparand(input: &str) -> IResult<&str, String> {
+    alt((
+        map_opt(parse_date_function, |expr| match eval::eval(&expr, &|_| UnifiedValue::Null) {
+            UnifiedValue::DateTime(n) | UnifiedValue::Integer(n) if expr.columns().is_empty() => Some(n.to_string()),
+            _ => None,
+        }),
+        parse_operand,
+    ))(input)
+}
+
+// A plain column keeps the indexable `Condition` form
+fn comparison(left: Expr, op: Operator, val: String) -> Filter {
+    match left {
+        Expr::Column(col) => Filter::Condition(col, op, val),
+        expr => Filter::Computed(expr, op, val),
+    }
+}
+
+// Atom: expr op val  (expr can be column->path, arithmetic or a function call)
+fn parse_condition(input: &str) -> IResult<&str, Filter> {
+    alt((
+        parse_null_test,
+        parse_between,
+        map(
+            tuple((
+                parse_expr,
+                multispace1,
+                parse_operator,
+                alt((
+                    preceded(multispace0, parse_value_list), // Try parsing list first for IN
+                    preceded(multispace1, parse_comparand)
+                )),
+            )),
+            |(left, _, op, val)| comparison(left, op, val)
+        ),
+    ))(input)
+}
+
+// col IS [NOT] NULL; `= NULL` / `!= NULL` compare with an unknown value,
+// which never holds, so they match no row
+fn parse_null_test(input: &str) -> IResult<&str, Filter> {
+    let is_null = alt((
+        map(tuple((tag_no_case("IS"), multispace1, tag_no_case("NOT"), multispace1)), |_| Some(Operator::IsNotNull)),
+        map(pair(tag_no_case("IS"), multispace1), |_| Some(Operator::IsNull)),
+        map(pair(alt((tag("!="), tag("="))), multispace1), |_| None),
+    ));
+    map(
+        tuple((
+            parse_expr,
+            multispace1,
+            is_null,
+            verify(parse_key, is_null_keyword),
+        )),
+        |(left, _, op, _)| match op {
+            Some(op) => comparison(left, op, String::new()),
+            None => Filter::And(
+                Box::new(comparison(left.clone(), Operator::IsNull, String::new())),
+                Box::new(comparison(left, Operator::IsNotNull, String::new())),
+            ),
+        }
+    )(input)
+}
+
+// col BETWEEN low AND high, both bounds included
+fn parse_between(input: &str) -> IResult<&str, Filter> {
+    map(
+        tuple((
+            parse_expr,
+            delimited(multispace1, tag_no_case("BETWEEN"), multispace1),
+            parse_comparand,
+            delimited(multispace1, tag_no_case("AND"), multispace1),
+            parse_comparand,
+        )),
+        |(left, _, low, _, high)| comparison(left, Operator::Between, join_value_list(&[low, high]))
+    )(input)
+}
+
+fn parse_parenthesized_filter(input: &str) -> IResult<&str, Filter> {
+    delimited(
+        tuple((char('('), multispace0)),
+        parse_filter,
+        tuple((multispace0, char(')')))
+    )(input)
+}
+
+fn parse_atom(input: &str) -> IResult<&str, Filter> {
+    alt((
+        parse_parenthesized_filter,
+        // NOT (...) or NOT cond; a column named like `notes` is not a NOT
+        map(
+            preceded(
+                tag_no_case("NOT"),
+                alt((preceded(multispace0, parse_parenthesized_filter), preceded(multispace1, parse_atom))),
+            ),
+            |inner| Filter::Not(Box::new(inner))
+        ),
+        parse_condition,
+    ))(input)
+}
+
+// Term: Atom AND Atom AND ...
+fn parse_and_term(input: &str) -> IResult<&str, Filter> {
+    let (input, first) = parse_atom(input)?;
+    let (input, rest) = nom::multi::fold_many0(
+        preceded(tuple((multispace1, tag_no_case("AND"), multispace1)), parse_atom),
+        move || first.clone(),
+        |acc, val| Filter::And(Box::new(acc), Box::new(val))
+    )(input)?;
+    Ok((input, rest))
+}
+
+// Expr: Term OR Term OR ...
+fn parse_filter(input: &str) -> IResult<&str, Filter> {
+    let (input, first) = parse_and_term(input)?;
+    let (input, rest) = nom::multi::fold_many0(
+        preceded(tuple((multispace1, tag_no_case("OR"), multispace1)), parse_and_term),
+        move || first.clone(),
+        |acc, val| Filter::Or(Box::new(acc), Box::new(val))
+    )(input)?;
+    Ok((input, rest))
+}
+
+// ZSET Commands
+// ZADD key [NX|XX] [GT|LT] [CH] score member [score member ...]
+fn parse_zadd(input: &str) -> IResult<&str, Command> {
+    let flag = verify(alpha1, |word: &str| ZAddFlags::default().set(word));
+    map(
+        tuple((
+            tag_no_case("ZADD"),
+            multispace1,
+            parse_key,
+            many0(preceded(multispace1, flag)),
+            many1(preceded(multispace1, separated_pair(nom::number::complete::double, multispace1, parse_string))),
+        )),
+        |(_, _, key, words, members)| {
+            let mut flags = ZAddFlags::default();
+            for word in words {
+                flags.set(word);
+            }
+            Command::ZAdd { key: key.to_string(), flags, members }
+        }
+    )(input)
+}
+
+fn parse_zrange(input: &str) -> IResult<&str, Command> {
+    map(
+        tuple((tag_no_case("ZRANGE"), multispace1, parse_key, multispace1, nom::character::complete::i64, multispace1, nom::character::complete::i64)),
+        |(_, _, key, _, start, _, stop)| Command::ZRange { key: key.to_string(), start, stop }
+    )(input)
+}
+
+fn parse_zscore(input: &str) -> IResult<&str, Command> {
+    map(
+        tuple((tag_no_case("ZSCORE"), multispace1, parse_key, multispace1, parse_string)),
+        |(_, _, key, _, member)| Command::ZScore { key: key.to_string(), member }
+    )(input)
+}
+
+// ZPOPMIN key [count] | ZPOPMAX key [count]
+fn parse_zpop(input: &str) -> IResult<&str, Command> {
+    map(
+        tuple((
+            alt((tag_no_case("ZPOPMIN"), tag_no_case("ZPOPMAX"))),
+            multispace1,
+            parse_key,
+            opt(preceded(multispace1, map_res(digit1, |s: &str| s.parse::<usize>()))),
+        )),
+        |(name, _, key, count)| Command::ZPop { key: key.to_string(), count, max: name.eq_ignore_ascii_case("ZPOPMAX") }
+    )(input)
+}
+
+// BZPOPMIN key [key ...] timeout | BZPOPMAX key [key ...] timeout
+// The timeout is in seconds, fractions allowed; 0 waits the longest a wait may take
+fn parse_bzpop(input: &str) -> IResult<&str, Command> {
+    let (rest, (name, words)) = tuple((
+        alt((tag_no_case("BZPOPMIN"), tag_no_case("BZPOPMAX"))),
+        many1(preceded(multispace1, parse_key)),
+    ))(input)?;
+    let fail = || nom::Err::Error(nom::error::Error::new(input, nom::error::ErrorKind::Verify));
+    let (timeout, keys) = words.split_last().ok_or_else(fail)?;
+    let timeout: f64 = timeout.parse().map_err(|_| fail())?;
+    if keys.is_empty() || !timeout.is_finite() || timeout < 0.0 {
+        return Err(fail());
+    }
+    Ok((rest, Command::BZPop {
+        keys: keys.iter().map(|k| k.to_string()).collect(),
+        timeout_ms: (timeout * 1000.0).round() as u64,
+        max: name.eq_ignore_ascii_case("BZPOPMAX"),
+    }))
+}
+
+// GEOADD key [NX|XX] [CH] longitude latitude member [longitude latitude member ...]
+fn parse_geoadd(input: &str) -> IResult<&str, Command> {
+    let flag = verify(alpha1, |word: &str| ["NX", "XX", "CH"].iter().any(|f| word.eq_ignore_ascii_case(f)));
+    let coord = || preceded(multispace1, nom::number::complete::double);
+    map(
+        tuple((
+            command_name("GEOADD"),
+            multispace1,
+            parse_key,
+            many0(preceded(multispace1, flag)),
+            many1(tuple((coord(), coord(), preceded(multispace1, parse_string)))),
+        )),
+        |(_, _, key, words, members)| {
+            let mut flags = ZAddFlags::default();
+            for word in words {
+                flags.set(word);
+            }
+            Command::GeoAdd { key: key.to_string(), flags, members }
+        }
+    )(input)
+}
+
+fn parse_geo_unit(input: &str) -> IResult<&str, geo::GeoUnit> {
+    map_res(alpha1, |unit: &str| unit.parse::<geo::GeoUnit>())(input)
+}
+
+// GEODIST key member1 member2 [M|KM|FT|MI]
+fn parse_geodist(input: &str) -> IResult<&str, Command> {
+    map(
+        tuple((
+            command_name("GEODIST"),
+            multispace1,
+            parse_key,
+            preceded(multispace1, parse_string),
+            preceded(multispace1, parse_string),
+            opt(preceded(multispace1, parse_geo_unit)),
+        )),
+        |(_, _, key, member1, member2, unit)| Command::GeoDist {
+            key: key.to_string(), member1, member2, unit: unit.unwrap_or(geo::GeoUnit::Meters),
+        }
+    )(input)
+}
+
+// GEOSEARCH key FROMMEMBER member | FROMLONLAT longitude latitude
+//   BYRADIUS radius M|KM|FT|MI [ASC|DESC] [COUNT n] [WITHCOORD] [WITHDIST]
+// The options come in any order
+fn parse_geosearch(input: &str) -> IResult<&str, Command> {
+    enum Opt { Desc(bool), Count(usize), WithCoord, WithDist }
+    let from = alt((
+        map(preceded(pair(tag_no_case("FROMMEMBER"), multispace1), parse_string), GeoOrigin::Member),
+        map(
+            preceded(tag_no_case("FROMLONLAT"), pair(preceded(multispace1, nom::number::complete::double), preceded(multispace1, nom::number::complete::double))),
+            |(lon, lat)| GeoOrigin::LonLat(lon, lat),
+        ),
+    ));
+    let option = alt((
+        map(tag_no_case("ASC"), |_| Opt::Desc(false)),
+        map(tag_no_case("DESC"), |_| Opt::Desc(true)),
+        map(preceded(pair(tag_no_case("COUNT"), multispace1), map_res(digit1, |s: &str| s.parse::<usize>())), Opt::Count),
+        map(tag_no_case("WITHCOORD"), |_| Opt::WithCoord),
+        map(tag_no_case("WITHDIST"), |_| Opt::WithDist),
+    ));
+    map(
+        tuple((
+            command_name("GEOSEARCH"),
+            multispace1,
+            parse_key,
+            multispace1,
+            from,
+            preceded(tuple((multispace1, tag_no_case("BYRADIUS"), multispace1)), nom::number::complete::double),
+            preceded(multispace1, parse_geo_unit),
+            many0(preceded(multispace1, option)),
+        )),
+        |(_, _, key, _, from, radius, unit, options)| {
+            let (mut desc, mut count, mut with_coord, mut with_dist) = (false, None, false, false);
+            for option in options {
+                match option {
+                    Opt::Desc(d) => desc = d,
+                    Opt::Count(n) => count = Some(n),
+                    Opt::WithCoord => with_coord = true,
+                    Opt::WithDist => with_dist = true,
+                }
+            }
+            Command::GeoSearch { key: key.to_string(), from, radius, unit, desc, count, with_coord, with_dist }
+        }
+    )(input)
+}
+
+// TOPK.RESERVE key k [width depth]
+fn parse_topk_reserve(input: &str) -> IResult<&str, Command> {
+    map(
+        tuple((
+            tag_no_case("TOPK.RESERVE"),
+            multispace1,
+            parse_key,
+            multispace1,
+            nom::character::complete::u64,
+            opt(tuple((multispace1, nom::character::complete::u64, multispace1, nom::character::complete::u64))),
+        )),
+        |(_, _, key, _, k, dims)| {
+            let (width, depth) = dims.map_or((topk::DEFAULT_WIDTH, topk::DEFAULT_DEPTH), |(_, w, _, d)| (w as usize, d as usize));
+            Command::TopKReserve { key: key.to_string(), k: k as usize, width, depth }
+        }
+    )(input)
+}
+
+// TOPK.ADD key item [item ...]
+fn parse_topk_add(input: &str) -> IResult<&str, Command> {
+    map(
+        tuple((tag_no_case("TOPK.ADD"), multispace1, parse_key, many1(preceded(multispace1, parse_string)))),
+        |(_, _, key, items)| Command::TopKAdd { key: key.to_string(), items }
+    )(input)
+}
+
+// TOPK.INCRBY key item increment [item increment ...]
+fn parse_topk_incrby(input: &str) -> IResult<&str, Command> {
+    map(
+        tuple((
+            tag_no_case("TOPK.INCRBY"),
+            multispace1,
+            parse_key,
+            many1(preceded(multispace1, separated_pair(parse_string, multispace1, nom::character::complete::u64))),
+        )),
+        |(_, _, key, items)| Command::TopKIncrBy { key: key.to_string(), items }
+    )(input)
+}
+
+// TOPK.LIST key [WITHCOUNT]
+fn parse_topk_list(input: &str) -> IResult<&str, Command> {
+    map(
+        tuple((tag_no_case("TOPK.LIST"), multispace1, parse_key, opt(preceded(multispace1, tag_no_case("WITHCOUNT"))))),
+        |(_, _, key, with_count)| Command::TopKList { key: key.to_string(), with_count: with_count.is_some() }
+    )(input)
+}
+
+// Bit offset, at most bitmap::MAX_OFFSET
+fn parse_bit_offset(input: &str) -> IResult<&str, u64> {
+    verify(nom::character::complete::u64, |offset| *offset <= bitmap::MAX_OFFSET)(input)
+}
+
+// SETBIT key offset 0|1
+fn parse_setbit(input: &str) -> IResult<&str, Command> {
+    map(
+        tuple((command_name("SETBIT"), multispace1, parse_key, multispace1, parse_bit_offset, multispace1, verify(nom::character::complete::u8, |v| *v <= 1))),
+        |(_, _, key, _, offset, _, value)| Command::SetBit { key: key.to_string(), offset, value: value == 1 }
+    )(input)
+}
+
+// GETBIT key offset
+fn parse_getbit(input: &str) -> IResult<&str, Command> {
+    map(
+        tuple((command_name("GETBIT"), multispace1, parse_key, multispace1, parse_bit_offset)),
+        |(_, _, key, _, offset)| Command::GetBit { key: key.to_string(), offset }
+    )(input)
+}
+
+// BITCOUNT key [start end [BYTE|BIT]]
+fn parse_bitcount(input: &str) -> IResult<&str, Command> {
+    map(
+        tuple((
+            command_name("BITCOUNT"),
+            multispace1,
+            parse_key,
+            opt(tuple((
+                preceded(multispace1, nom::character::complete::i64),
+                preceded(multispace1, nom::character::complete::i64),
+                opt(preceded(multispace1, alt((tag_no_case("BYTE"), tag_no_case("BIT"))))),
+            ))),
+        )),
+        |(_, _, key, range)| Command::BitCount {
+            key: key.to_string(),
+            range: range.map(|(start, end, _)| (start, end)),
+            bits: range.and_then(|(_, _, unit)| unit).is_some_and(|unit| unit.eq_ignore_ascii_case("BIT")),
+        }
+    )(input)
+}
+
+// BITOP AND|OR|XOR|NOT destkey srckey [srckey ...]
+// NOT takes exactly one source
+fn parse_bitop(input: &str) -> IResult<&str, Command> {
+    map(
+        verify(
+            tuple((
+                command_name("BITOP"),
+                multispace1,
+                map_res(alpha1, |op: &str| op.parse::<bitmap::BitOp>()),
+                multispace1,
+                parse_key,
+                many1(preceded(multispace1, parse_key)),
+            )),
+            |(_, _, op, _, _, sources)| *op != bitmap::BitOp::Not || sources.len() == 1
+        ),
+        |(_, _, op, _, dest, sources)| Command::BitOp { op, dest: dest.to_string(), sources: sources.into_iter().map(String::from).collect() }
+    )(input)
+}
+
+// PFADD key [element ...]
+fn parse_pfadd(input: &str) -> IResult<&str, Command> {
+    map(
+        tuple((command_name("PFADD"), multispace1, parse_key, many0(preceded(multispace1, parse_string)))),
+        |(_, _, key, elements)| Command::PfAdd { key: key.to_string(), elements }
+    )(input)
+}
+
+// PFCOUNT key [key ...]
+fn parse_pfcount(input: &str) -> IResult<&str, Command> {
+    map(
+        tuple((command_name("PFCOUNT"), many1(preceded(multispace1, parse_key)))),
+        |(_, keys)| Command::PfCount { keys: keys.into_iter().map(String::from).collect() }
+    )(input)
+}
+
+// PFMERGE destkey [sourcekey ...]
+fn parse_pfmerge(input: &str) -> IResult<&str, Command> {
+    map(
+        tuple((command_name("PFMERGE"), multispace1, parse_key, many0(preceded(multispace1, parse_key)))),
+        |(_, _, dest, sources)| Command::PfMerge { dest: dest.to_string(), sources: sources.into_iter().map(String::from).collect() }
+    )(input)
+}
+
+// XADD key <* | ms-* | ms-seq> field value [field value ...]
+fn parse_xadd(input: &str) -> IResult<&str, Command> {
+    map(
+        tuple((
+            command_name("XADD"),
+            multispace1,
+            parse_key,
+            multispace1,
+            parse_key,
+            many1(preceded(multispace1, separated_pair(parse_string, multispace1, parse_string))),
+        )),
+        |(_, _, key, _, id, fields)| Command::XAdd { key: key.to_string(), id: id.to_string(), fields }
+    )(input)
+}
+
+// XRANGE key start end [COUNT n]
+fn parse_xrange(input: &str) -> IResult<&str, Command> {
+    map(
+        tuple((
+            command_name("XRANGE"),
+            multispace1,
+            parse_key,
+            multispace1,
+            parse_key,
+            multispace1,
+            parse_key,
+            opt(preceded(tuple((multispace1, tag_no_case("COUNT"), multispace1)), map_res(digit1, |s: &str| s.parse::<usize>()))),
+        )),
+        |(_, _, key, _, start, _, end, count)| Command::XRange { key: key.to_string(), start: start.to_string(), end: end.to_string(), count }
+    )(input)
+}
+
+// XLEN key
+fn parse_xlen(input: &str) -> IResult<&str, Command> {
+    map(
+        tuple((command_name("XLEN"), multispace1, parse_key)),
+        |(_, _, key)| Command::XLen { key: key.to_string() }
+    )(input)
+}
+
+// XREAD [COUNT n] [BLOCK ms] STREAMS key [key ...] id [id ...]
+// One ID per key, in the same order; BLOCK 0 waits the longest a wait may take
+fn parse_xread(input: &str) -> IResult<&str, Command> {
+    let number = || map_res(digit1, |s: &str| s.parse::<u64>());
+    let (rest, (_, count, block_ms, _, _, words)) = tuple((
+        command_name("XREAD"),
+        opt(preceded(tuple((multispace1, tag_no_case("COUNT"), multispace1)), number())),
+        opt(preceded(tuple((multispace1, tag_no_case("BLOCK"), multispace1)), number())),
+        multispace1,
+        tag_no_case("STREAMS"),
+        many1(preceded(multispace1, parse_key)),
+    ))(input)?;
+    if !words.len().is_multiple_of(2) {
+        return Err(nom::Err::Error(nom::error::Error::new(input, nom::error::ErrorKind::Verify)));
+    }
+    let (keys, ids) = words.split_at(words.len() / 2);
+    Ok((rest, Command::XRead {
+        count: count.map(|n| n as usize),
+        block_ms,
+        keys: keys.iter().map(|k| k.to_string()).collect(),
+        ids: ids.iter().map(|id| id.to_string()).collect(),
+    }))
 }
 
 fn parse_ping(input: &str) -> IResult<&str, Command> {
     map(tag("PING"), |_| Command::Ping)(input)
 }
 
+fn parse_quit(input: &str) -> IResult<&str, Command> {
+    map(tag_no_case("QUIT"), |_| Command::Quit)(input)
+}
+
+fn parse_reset(input: &str) -> IResult<&str, Command> {
+    map(tag_no_case("RESET"), |_| Command::Reset)(input)
+}
+
+// READONLY | READWRITE
+fn parse_readonly(input: &str) -> IResult<&str, Command> {
+    alt((
+        map(tag_no_case("READONLY"), |_| Command::ReadOnly),
+        map(tag_no_case("READWRITE"), |_| Command::ReadWrite),
+        map(tag_no_case("ASKING"), |_| Command::Asking),
+    ))(input)
+}
+
+// MIGRATE host port SLOT slot [COUNT n] [AUTH password | AUTH2 username password]
+fn parse_migrate(input: &str) -> IResult<&str, Command> {
+    let parse_auth = alt((
+        map(
+            tuple((tag_no_case("AUTH2"), multispace1, parse_string, multispace1, parse_string)),
+            |(_, _, username, _, password)| (username, password)
+        ),
+        map(
+            tuple((tag_no_case("AUTH"), multispace1, parse_string)),
+            |(_, _, password)| ("default".to_string(), password)
+        ),
+    ));
+    map(
+        tuple((
+            tag_no_case("MIGRATE"), multispace1, parse_string, multispace1, nom::character::complete::u16,
+            multispace1, tag_no_case("SLOT"), multispace1, nom::character::complete::u16,
+            opt(preceded(tuple((multispace1, tag_no_case("COUNT"), multispace1)), map_res(digit1, str::parse::<usize>))),
+            opt(preceded(multispace1, parse_auth)),
+        )),
+        |(_, _, host, _, port, _, _, _, slot, count, auth)| Command::Migrate { host, port, slot, count, auth }
+    )(input)
+}
+
+// OBJECT HELP
+fn parse_object(input: &str) -> IResult<&str, Command> {
+    map(tuple((tag_no_case("OBJECT"), multispace1, tag_no_case("HELP"))), |_| Command::ObjectHelp)(input)
+}
+
 fn parse_save(input: &str) -> IResult<&str, Command> {
     map(tag("SAVE"), |_| Command::Save)(input)
 }
 
+// BACKUP VERIFY <path>
+fn parse_backup(input: &str) -> IResult<&str, Command> {
+    map(
+        tuple((tag_no_case("BACKUP"), multispace1, tag_no_case("VERIFY"), multispace1, parse_pattern)),
+        |(_, _, _, _, path)| Command::BackupVerify { path }
+    )(input)
+}
+
+// AOF STATS | AOF CAT [from [count]]
+fn parse_aof(input: &str) -> IResult<&str, Command> {
+    let (input, _) = tuple((tag_no_case("AOF"), multispace1))(input)?;
+    let number = || preceded(multispace1, map(nom::character::complete::u64, |n| n as usize));
+    alt((
+        map(tag_no_case("STATS"), |_| Command::AofStats),
+        map(
+            tuple((tag_no_case("CAT"), opt(number()), opt(number()))),
+            |(_, from, count)| Command::AofCat { from: from.unwrap_or(1).max(1), count: count.unwrap_or(20) }
+        ),
+    ))(input)
+}
+
 // UPDATE table SET col=val [WHERE filter]
 fn parse_update(input: &str) -> IResult<&str, Command> {
     let parse_where = preceded(
-        tuple((multispace1, tag("WHERE"), multispace1)),
+        tuple((multispace1, tag_no_case("WHERE"), multispace1)),
         parse_filter
     );
 
@@ -653,7 +1582,7 @@ fn parse_update(input: &str) -> IResult<&str, Command> {
             multispace1,
             char('='),
             multispace1,
-            parse_string,
+            parse_literal,
             opt(parse_where)
         )),
         |(_, _, table, _, _, _, set_col, _, _, _, set_val, filter)| {
@@ -669,7 +1598,7 @@ fn parse_update(input: &str) -> IResult<&str, Command> {
 // DELETE FROM table [WHERE filter]
 fn parse_delete(input: &str) -> IResult<&str, Command> {
     let parse_where = preceded(
-        tuple((multispace1, tag("WHERE"), multispace1)),
+        tuple((multispace1, tag_no_case("WHERE"), multispace1)),
         parse_filter
     );
     
@@ -677,7 +1606,7 @@ fn parse_delete(input: &str) -> IResult<&str, Command> {
         tuple((
             tag("DELETE"),
             multispace1,
-            tag("FROM"),
+            tag_no_case("FROM"),
             multispace1,
             parse_identifier,
             opt(parse_where)
@@ -691,9 +1620,23 @@ fn parse_delete(input: &str) -> IResult<&str, Command> {
     )(input)
 }
 
+// Words that end a table reference instead of naming an alias
+const CLAUSE_KEYWORDS: [&str; 13] = [
+    "AS", "ON", "JOIN", "INNER", "LEFT", "RIGHT", "WHERE", "GROUP", "HAVING", "ORDER", "LIMIT", "OFFSET", "FETCH",
+];
+
+// `[AS] alias` after a table name
+fn parse_table_alias(input: &str) -> IResult<&str, &str> {
+    preceded(
+        pair(multispace1, opt(pair(tag_no_case("AS"), multispace1))),
+        verify(parse_identifier, |name: &str| !CLAUSE_KEYWORDS.iter().any(|k| name.eq_ignore_ascii_case(k))),
+    )(input)
+}
+
 fn parse_join_clause(input: &str) -> IResult<&str, JoinClause> {
     let (input, _) = tuple((multispace1, tag_no_case("JOIN"), multispace1))(input)?;
     let (input, table) = parse_identifier(input)?;
+    let (input, alias) = opt(parse_table_alias)(input)?;
     let (input, _) = tuple((multispace1, tag_no_case("ON"), multispace1))(input)?;
     
     // Parse left operand (e.g. users.id)
@@ -707,22 +1650,87 @@ fn parse_join_clause(input: &str) -> IResult<&str, JoinClause> {
     Ok((input, JoinClause {
         join_type: JoinType::Inner,
         table: table.to_string(),
+        alias: alias.map(str::to_string),
         on_left: left,
         on_right: right,
     }))
 }
 
-// SELECT [COUNT(*) | * | col1, col2] FROM table [JOIN...] [WHERE...] [ORDER BY col [ASC|DESC]] [LIMIT n]
+// EXPLAIN [ANALYZE] SELECT ...
+fn parse_explain(input: &str) -> IResult<&str, Command> {
+    map(
+        tuple((tag_no_case("EXPLAIN"), multispace1, opt(pair(tag_no_case("ANALYZE"), multispace1)), parse_select)),
+        |(_, _, analyze, query)| Command::Explain { query: Box::new(query), analyze: analyze.is_some() }
+    )(input)
+}
+
+// DECLARE name CURSOR FOR SELECT ... | FETCH [n | NEXT | ALL] FROM name | CLOSE name | CLOSE ALL
+fn parse_cursor(input: &str) -> IResult<&str, Command> {
+    let count = alt((
+        map(map_res(digit1, |s: &str| s.parse::<usize>()), Some),
+        map(tag_no_case("NEXT"), |_| Some(1)),
+        map(tag_no_case("ALL"), |_| None),
+    ));
+    alt((
+        map(
+            tuple((
+                tag_no_case("DECLARE"), multispace1, parse_identifier, multispace1,
+                tag_no_case("CURSOR"), multispace1, tag_no_case("FOR"), multispace1, parse_select,
+            )),
+            |(_, _, name, _, _, _, _, _, query)| Command::Declare { name: name.to_string(), query: Box::new(query) }
+        ),
+        map(
+            tuple((
+                tag_no_case("FETCH"), multispace1, opt(terminated(count, multispace1)),
+                tag_no_case("FROM"), multispace1, parse_identifier,
+            )),
+            |(_, _, count, _, _, name)| Command::Fetch { name: name.to_string(), count: count.unwrap_or(Some(1)) }
+        ),
+        map(
+            tuple((tag_no_case("CLOSE"), multispace1, parse_identifier)),
+            |(_, _, name)| Command::Close { name: (!name.eq_ignore_ascii_case("ALL")).then(|| name.to_string()) }
+        ),
+    ))(input)
+}
+
+// COUNT(*), COUNT(DISTINCT col), SUM(col), AVG(col), MAX(col) or MIN(col)
+fn parse_aggregate(input: &str) -> IResult<&str, Selector> {
+    alt((
+        map(
+            delimited(tuple((tag_no_case("COUNT(DISTINCT"), multispace1)), parse_column_expr, char(')')),
+            Selector::CountDistinct
+        ),
+        map(tag_no_case("COUNT(*)"), |_| Selector::Count),
+        map(
+            delimited(tag_no_case("SUM("), parse_column_expr, char(')')),
+            Selector::Sum
+        ),
+        map(
+            delimited(tag_no_case("AVG("), parse_column_expr, char(')')),
+            Selector::Avg
+        ),
+        map(
+            delimited(tag_no_case("MAX("), parse_column_expr, char(')')),
+            Selector::Max
+        ),
+        map(
+            delimited(tag_no_case("MIN("), parse_column_expr, char(')')),
+            Selector::Min
+        ),
+    ))(input)
+}
+
+// SELECT [COUNT(*) | * | col1, col2] FROM table [JOIN...] [WHERE...] [ORDER BY col [ASC|DESC], ...] [LIMIT n]
 fn parse_select(input: &str) -> IResult<&str, Command> {
     // Legacy: SELECT table [WHERE...]
     let parse_where_legacy = preceded(
-        tuple((multispace1, tag("WHERE"), multispace1)),
+        tuple((multispace1, tag_no_case("WHERE"), multispace1)),
         parse_filter
     );
 
     let parse_legacy_select = map(
         tuple((
-            tag("SELECT"),
+            tag_no_case("SELECT"),
             multispace1,
             parse_identifier,
             opt(parse_where_legacy)
@@ -730,6 +1738,7 @@ fn parse_select(input: &str) -> IResult<&str, Command> {
         |(_, _, table, filter)| {
              Command::Select { 
                  table: table.to_string(), 
+                 alias: None,
                  selector: Selector::All, 
                  join: None,
                  filter, 
@@ -742,42 +1751,34 @@ fn parse_select(input: &str) -> IResult<&str, Command> {
         }
     );
 
-    // Full: SELECT selector FROM table [JOIN...] [WHERE...] [ORDER BY...] [LIMIT...]
-    let parse_selector = alt((
-        map(alt((tag("COUNT(*)"), tag("COUNT"), tag("count(*)"), tag("count"))), |_| Selector::Count),
-        map(
-            delimited(tag("SUM("), parse_column_expr, char(')')),
-            |col| Selector::Sum(col)
-        ),
-        map(
-            delimited(tag("AVG("), parse_column_expr, char(')')),
-            |col| Selector::Avg(col)
-        ),
-        map(
-            delimited(tag("MAX("), parse_column_expr, char(')')),
-            |col| Selector::Max(col)
-        ),
+    // Full: SELECT selector FROM table [JOIN...] [WHERE...] [ORDER BY...] [LIMIT...]
+    let parse_selector = alt((
+        parse_aggregate,
+        map(tag_no_case("COUNT"), |_| Selector::Count),
+        map(tag("*"), |_| Selector::All),
         map(
-            delimited(tag("MIN("), parse_column_expr, char(')')),
-            |col| Selector::Min(col)
+            preceded(
+                tuple((tag_no_case("DISTINCT"), multispace1)),
+                separated_list1(tuple((multispace0, char(','), multispace0)), parse_projection)
+            ),
+            Selector::Distinct
         ),
-        map(tag("*"), |_| Selector::All),
         map(
             separated_list1(
                 tuple((multispace0, char(','), multispace0)), 
-                parse_column_expr
+                parse_projection
             ),
-            |cols| Selector::Columns(cols)
+            Selector::Columns
         ),
     ));
 
     let parse_where = preceded(
-        tuple((multispace1, tag("WHERE"), multispace1)),
+        tuple((multispace1, tag_no_case("WHERE"), multispace1)),
         parse_filter
     );
 
     let parse_group_by = preceded(
-        tuple((multispace1, tag("GROUP"), multispace1, tag("BY"), multispace1)),
+        tuple((multispace1, tag_no_case("GROUP"), multispace1, tag_no_case("BY"), multispace1)),
         separated_list1(
             tuple((multispace0, char(','), multispace0)), 
             parse_column_expr
@@ -785,56 +1786,77 @@ fn parse_select(input: &str) -> IResult<&str, Command> {
     );
 
     let parse_having = preceded(
-        tuple((multispace1, tag("HAVING"), multispace1)),
+        tuple((multispace1, tag_no_case("HAVING"), multispace1)),
         parse_filter
     );
 
     let parse_order_by = preceded(
-        tuple((multispace1, tag("ORDER"), multispace1, tag("BY"), multispace1)),
-        pair(
-            parse_column_expr,
-            opt(preceded(multispace1, alt((tag("ASC"), tag("DESC")))))
+        tuple((multispace1, tag_no_case("ORDER"), multispace1, tag_no_case("BY"), multispace1)),
+        separated_list1(
+            tuple((multispace0, char(','), multispace0)),
+            pair(
+                parse_column_expr,
+                opt(preceded(multispace1, alt((tag_no_case("ASC"), tag_no_case("DESC")))))
+            )
         )
     );
 
     let parse_limit = preceded(
-        tuple((multispace1, tag("LIMIT"), multispace1)),
+        tuple((multispace1, tag_no_case("LIMIT"), multispace1)),
         nom::character::complete::digit1
     );
 
-    let parse_offset = preceded(
-        tuple((multispace1, tag("OFFSET"), multispace1)),
-        nom::character::complete::digit1
+    // OFFSET n [ROW | ROWS]
+    let parse_offset = || terminated(
+        preceded(tuple((multispace1, tag_no_case("OFFSET"), multispace1)), nom::character::complete::digit1),
+        opt(preceded(multispace1, alt((tag_no_case("ROWS"), tag_no_case("ROW"))))),
+    );
+
+    // ANSI: FETCH {FIRST | NEXT} [n] {ROW | ROWS} ONLY, n defaulting to 1
+    let parse_fetch = map(
+        tuple((
+            multispace1, tag_no_case("FETCH"), multispace1, alt((tag_no_case("FIRST"), tag_no_case("NEXT"))),
+            opt(preceded(multispace1, nom::character::complete::digit1)),
+            multispace1, alt((tag_no_case("ROWS"), tag_no_case("ROW"))), multispace1, tag_no_case("ONLY"),
+        )),
+        |(_, _, _, _, count, ..)| count.unwrap_or("1")
     );
 
+    // [LIMIT n] [OFFSET n], or [OFFSET n ROWS] FETCH FIRST n ROWS ONLY
+    let parse_paging = alt((
+        map(pair(opt(parse_offset()), parse_fetch), |(offset, limit)| (Some(limit), offset)),
+        pair(opt(parse_limit), opt(parse_offset())),
+    ));
+
     let parse_full_select = map(
         tuple((
-            tag("SELECT"),
+            tag_no_case("SELECT"),
             multispace1,
             parse_selector,
             multispace1,
-            tag("FROM"),
+            tag_no_case("FROM"),
             multispace1,
             parse_identifier,
+            opt(parse_table_alias),
             many0(parse_join_clause),
             opt(parse_where),
             opt(parse_group_by),
             opt(parse_having),
             opt(parse_order_by),
-            opt(parse_limit),
-            opt(parse_offset)
+            parse_paging
         )),
-        |(_, _, selector, _, _, _, table, joins, filter, group_by, having, order, limit_str, offset_str)| {
+        |(_, _, selector, _, _, _, table, alias, joins, filter, group_by, having, order, (limit_str, offset_str))| {
             let join = if joins.is_empty() { None } else { Some(joins) };
             let group_by = group_by.map(|cols: Vec<String>| cols);
-            let order_by = order.map(|(col, dir)| {
-                (col, dir.unwrap_or("ASC") == "ASC")
+            let order_by = order.map(|keys: Vec<(String, Option<&str>)>| {
+                keys.into_iter().map(|(col, dir)| (col, dir.is_none_or(|d| d.eq_ignore_ascii_case("ASC")))).collect()
             });
             let limit = limit_str.and_then(|s| s.parse::<usize>().ok());
             let offset = offset_str.and_then(|s| s.parse::<usize>().ok());
             
             Command::Select {
                 table: table.to_string(),
+                alias: alias.map(str::to_string),
                 selector,
                 join,
                 filter,
@@ -850,6 +1872,97 @@ fn parse_select(input: &str) -> IResult<&str, Command> {
     alt((parse_full_select, parse_legacy_select))(input)
 }
 
+// REINDEX table [column]
+fn parse_reindex(input: &str) -> IResult<&str, Command> {
+    map(
+        tuple((
+            tag_no_case("REINDEX"),
+            multispace1,
+            parse_identifier,
+            opt(preceded(multispace1, parse_column_expr)),
+        )),
+        |(_, _, table, column)| Command::Reindex { table: table.to_string(), column }
+    )(input)
+}
+
+// CHECK TABLE name
+fn parse_check_table(input: &str) -> IResult<&str, Command> {
+    map(
+        tuple((tag_no_case("CHECK"), multispace1, tag_no_case("TABLE"), multispace1, parse_identifier)),
+        |(_, _, _, _, table)| Command::CheckTable { table: table.to_string() }
+    )(input)
+}
+
+// EXPORT TABLE name
+fn parse_export_table(input: &str) -> IResult<&str, Command> {
+    map(
+        tuple((tag_no_case("EXPORT"), multispace1, tag_no_case("TABLE"), multispace1, parse_identifier)),
+        |(_, _, _, _, table)| Command::ExportTable { table: table.to_string() }
+    )(input)
+}
+
+// IMPORT TABLE name payload [REPLACE]
+fn parse_import_table(input: &str) -> IResult<&str, Command> {
+    map(
+        tuple((
+            tag_no_case("IMPORT"), multispace1, tag_no_case("TABLE"), multispace1, parse_identifier, multispace1, parse_string,
+            opt(preceded(multispace1, tag_no_case("REPLACE"))),
+        )),
+        |(_, _, _, _, table, _, payload, replace)| Command::ImportTable { table: table.to_string(), payload, replace: replace.is_some() }
+    )(input)
+}
+
+// DROP INDEX name ON table
+fn parse_drop_index(input: &str) -> IResult<&str, Command> {
+    map(
+        tuple((
+            tag_no_case("DROP"), multispace1, tag_no_case("INDEX"), multispace1,
+            parse_identifier, multispace1, tag_no_case("ON"), multispace1, parse_identifier,
+        )),
+        |(_, _, _, _, index_name, _, _, _, table)| Command::DropIndex {
+            index_name: index_name.to_string(),
+            table: table.to_string(),
+        }
+    )(input)
+}
+
+// SHOW INDEXES [FROM table]
+fn parse_show_indexes(input: &str) -> IResult<&str, Command> {
+    map(
+        tuple((
+            tag_no_case("SHOW"), multispace1, tag_no_case("INDEXES"),
+            opt(preceded(tuple((multispace1, tag_no_case("FROM"), multispace1)), parse_identifier)),
+        )),
+        |(_, _, _, table)| Command::ShowIndexes { table: table.map(|t| t.to_string()) }
+    )(input)
+}
+
+// SHOW TABLES | DESCRIBE table | SHOW CREATE TABLE table
+fn parse_show_schema(input: &str) -> IResult<&str, Command> {
+    alt((
+        map(tuple((tag_no_case("SHOW"), multispace1, tag_no_case("TABLES"))), |_| Command::ShowTables),
+        map(
+            tuple((alt((tag_no_case("DESCRIBE"), tag_no_case("DESC"))), multispace1, parse_identifier)),
+            |(_, _, table)| Command::Describe { table: table.to_string() }
+        ),
+        map(
+            tuple((
+                tag_no_case("SHOW"), multispace1, tag_no_case("CREATE"), multispace1,
+                tag_no_case("TABLE"), multispace1, parse_identifier,
+            )),
+            |(_, _, _, _, _, _, table)| Command::ShowCreateTable { table: table.to_string() }
+        ),
+    ))(input)
+}
+
+// ADVISOR SUGGEST
+fn parse_advisor(input: &str) -> IResult<&str, Command> {
+    map(
+        tuple((tag_no_case("ADVISOR"), multispace1, tag_no_case("SUGGEST"))),
+        |_| Command::AdvisorSuggest
+    )(input)
+}
+
 // CREATE INDEX idx ON table(col) or CREATE INDEX idx ON table(col->path)
 fn parse_create_index(input: &str) -> IResult<&str, Command> {
     map(
@@ -877,13 +1990,47 @@ fn parse_create_index(input: &str) -> IResult<&str, Command> {
     )(input)
 }
 
+// CREATE VECTOR INDEX [name] ON table(col) DIMS n [METRIC cosine|l2]
+fn parse_create_vector_index(input: &str) -> IResult<&str, Command> {
+    let name = verify(parse_identifier, |word: &str| !word.eq_ignore_ascii_case("ON"));
+    map_res(
+        tuple((
+            tag_no_case("CREATE"), multispace1, tag_no_case("VECTOR"), multispace1, tag_no_case("INDEX"), multispace1,
+            opt(terminated(name, multispace1)),
+            tag_no_case("ON"), multispace1,
+            parse_identifier,
+            delimited(char('('), parse_identifier, char(')')),
+            preceded(tuple((multispace1, tag_no_case("DIMS"), multispace1)), map_res(digit1, str::parse::<usize>)),
+            opt(preceded(tuple((multispace1, tag_no_case("METRIC"), multispace1)), alphanumeric1)),
+        )),
+        |(_, _, _, _, _, _, name, _, _, table, column, dims, metric)| {
+            Ok::<_, String>(Command::CreateVectorIndex {
+                index_name: name.map_or_else(|| format!("vec_{}_{}", table, column), str::to_string),
+                table: table.to_string(),
+                column: column.to_string(),
+                dims,
+                metric: metric.map_or(Ok(Metric::Cosine), str::parse)?,
+            })
+        }
+    )(input)
+}
+
+// +cmd, -cmd@table, ~key-pattern, quota:cmds=n
+fn parse_acl_rule(input: &str) -> IResult<&str, String> {
+    alt((
+        map(recognize(pair(char('~'), parse_key)), |rule: &str| rule.to_string()),
+        map(recognize(tuple((tag_no_case("quota:"), alpha1, char('='), digit1))), |rule: &str| rule.to_string()),
+        parse_string,
+    ))(input)
+}
+
 fn parse_acl(input: &str) -> IResult<&str, Command> {
     let (input, _) = tag("ACL")(input)?;
     let (input, _) = multispace1(input)?;
     
     alt((
         map(
-            tuple((tag_no_case("SETUSER"), multispace1, parse_identifier, multispace1, parse_string, multispace1, separated_list1(multispace1, parse_string))),
+            tuple((tag_no_case("SETUSER"), multispace1, parse_identifier, multispace1, parse_string, multispace1, separated_list1(multispace1, parse_acl_rule))),
             |(_, _, username, _, password, _, rules)| Command::AclSetUser { username: username.to_string(), password, rules }
         ),
         map(
@@ -895,6 +2042,10 @@ fn parse_acl(input: &str) -> IResult<&str, Command> {
             tuple((tag_no_case("DELUSER"), multispace1, parse_identifier)),
             |(_, _, username)| Command::AclDelUser { username: username.to_string() }
         ),
+        map(
+            preceded(tag_no_case("USAGE"), opt(preceded(multispace1, parse_identifier))),
+            |username| Command::AclUsage { username: username.map(str::to_string) }
+        ),
     ))(input)
 }
 
@@ -905,6 +2056,52 @@ fn parse_rewrite_aof(input: &str) -> IResult<&str, Command> {
     )(input)
 }
 
+// SET output = json|text (session option, must be tried before SET key value)
+fn parse_set_output(input: &str) -> IResult<&str, Command> {
+    map_res(
+        tuple((
+            tag_no_case("SET"),
+            multispace1,
+            tag_no_case("output"),
+            delimited(multispace0, char('='), multispace0),
+            alpha1,
+        )),
+        |(_, _, _, _, format): (_, _, _, _, &str)| format.parse().map(|format| Command::SetOutput { format })
+    )(input)
+}
+
+// SET binding = strict|parsed (session option, must be tried before SET key value)
+fn parse_set_binding(input: &str) -> IResult<&str, Command> {
+    map_res(
+        tuple((
+            tag_no_case("SET"),
+            multispace1,
+            tag_no_case("binding"),
+            delimited(multispace0, char('='), multispace0),
+            alpha1,
+        )),
+        |(_, _, _, _, mode): (_, _, _, _, &str)| mode.parse().map(|mode| Command::SetBinding { mode })
+    )(input)
+}
+
+// SET @name = value | SHOW VARIABLES (session variables, tried before SET key value)
+fn parse_variables(input: &str) -> IResult<&str, Command> {
+    alt((
+        map(
+            tuple((
+                tag_no_case("SET"), multispace1, char('@'), parse_identifier,
+                delimited(multispace0, char('='), multispace0),
+                alt((
+                    map(alt((parse_quoted_string, parse_single_quoted_string)), UnifiedValue::String),
+                    map(parse_key, variables::bare_value),
+                )),
+            )),
+            |(_, _, _, name, _, value)| Command::SetVariable { name: name.to_string(), value }
+        ),
+        map(tuple((tag_no_case("SHOW"), multispace1, tag_no_case("VARIABLES"))), |_| Command::ShowVariables),
+    ))(input)
+}
+
 fn parse_use(input: &str) -> IResult<&str, Command> {
     map(
         tuple((tag_no_case("USE"), multispace1, parse_string)),
@@ -912,6 +2109,17 @@ fn parse_use(input: &str) -> IResult<&str, Command> {
     )(input)
 }
 
+// SHOW DATABASES | DROP DATABASE name
+fn parse_databases(input: &str) -> IResult<&str, Command> {
+    alt((
+        map(tuple((tag_no_case("SHOW"), multispace1, tag_no_case("DATABASES"))), |_| Command::ShowDatabases),
+        map(
+            tuple((tag_no_case("DROP"), multispace1, tag_no_case("DATABASE"), multispace1, parse_string)),
+            |(_, _, _, _, name)| Command::DropDatabase { name }
+        ),
+    ))(input)
+}
+
 fn parse_client(input: &str) -> IResult<&str, Command> {
     let (input, _) = tag_no_case("CLIENT")(input)?;
     let (input, _) = multispace1(input)?;
@@ -925,10 +2133,46 @@ fn parse_client(input: &str) -> IResult<&str, Command> {
     ))(input)
 }
 
+// ACTIVE QUERIES | KILL QUERY <id>
+fn parse_queries(input: &str) -> IResult<&str, Command> {
+    alt((
+        map(tuple((tag_no_case("ACTIVE"), multispace1, tag_no_case("QUERIES"))), |_| Command::ActiveQueries),
+        map(
+            tuple((tag_no_case("KILL"), multispace1, tag_no_case("QUERY"), multispace1, map_res(digit1, str::parse::<u64>))),
+            |(_, _, _, _, id)| Command::KillQuery { id }
+        ),
+    ))(input)
+}
+
 fn parse_replicaof(input: &str) -> IResult<&str, Command> {
     map(
-        tuple((tag_no_case("REPLICAOF"), multispace1, parse_string, multispace1, parse_string)),
-        |(_, _, host, _, port)| Command::ReplicaOf { host, port }
+        // A db:// URI carries its own port
+        tuple((tag_no_case("REPLICAOF"), multispace1, parse_string, opt(preceded(multispace1, parse_string)))),
+        |(_, _, host, port)| Command::ReplicaOf { host, port: port.unwrap_or_default() }
+    )(input)
+}
+
+// FAILOVER [TIMEOUT ms]
+fn parse_failover(input: &str) -> IResult<&str, Command> {
+    map(
+        tuple((
+            tag_no_case("FAILOVER"),
+            opt(preceded(tuple((multispace1, tag_no_case("TIMEOUT"), multispace1)), nom::character::complete::u64)),
+        )),
+        |(_, timeout_ms)| Command::Failover { timeout_ms: timeout_ms.unwrap_or(5000) }
+    )(input)
+}
+
+// REPLCHECK [replica] [TIMEOUT ms]
+fn parse_replcheck(input: &str) -> IResult<&str, Command> {
+    let timeout = preceded(tuple((multispace1, tag_no_case("TIMEOUT"), multispace1)), nom::character::complete::u64);
+    map(
+        tuple((
+            tag_no_case("REPLCHECK"),
+            opt(preceded(multispace1, verify(parse_string, |s: &str| !s.eq_ignore_ascii_case("TIMEOUT")))),
+            opt(timeout),
+        )),
+        |(_, replica, timeout_ms)| Command::ReplCheck { replica, timeout_ms: timeout_ms.unwrap_or(5000) }
     )(input)
 }
 
@@ -939,6 +2183,20 @@ fn parse_psync(input: &str) -> IResult<&str, Command> {
     )(input)
 }
 
+// WAIT numreplicas timeout_ms
+fn parse_wait(input: &str) -> IResult<&str, Command> {
+    map(
+        tuple((
+            tag_no_case("WAIT"),
+            multispace1,
+            nom::character::complete::u64,
+            multispace1,
+            nom::character::complete::u64,
+        )),
+        |(_, _, replicas, _, timeout_ms)| Command::Wait { replicas: replicas as usize, timeout_ms }
+    )(input)
+}
+
 fn parse_info(input: &str) -> IResult<&str, Command> {
     map(
         tag_no_case("INFO"),
@@ -946,6 +2204,42 @@ fn parse_info(input: &str) -> IResult<&str, Command> {
     )(input)
 }
 
+// METRICS | METRICS RESET
+fn parse_metrics(input: &str) -> IResult<&str, Command> {
+    let (input, _) = tag_no_case("METRICS")(input)?;
+    map(
+        opt(tuple((multispace1, tag_no_case("RESET")))),
+        |reset| if reset.is_some() { Command::MetricsReset } else { Command::Metrics }
+    )(input)
+}
+
+// MEMORY USAGE key | MEMORY STATS
+fn parse_memory(input: &str) -> IResult<&str, Command> {
+    let (input, _) = tuple((tag_no_case("MEMORY"), multispace1))(input)?;
+    alt((
+        map(
+            tuple((tag_no_case("USAGE"), multispace1, parse_key)),
+            |(_, _, key)| Command::MemoryUsage { key: key.to_string() }
+        ),
+        map(tag_no_case("STATS"), |_| Command::MemoryStats),
+    ))(input)
+}
+
+// CONFIG GET pattern | CONFIG SET param value
+fn parse_config(input: &str) -> IResult<&str, Command> {
+    let (input, _) = tuple((tag_no_case("CONFIG"), multispace1))(input)?;
+    alt((
+        map(
+            tuple((tag_no_case("GET"), multispace1, parse_pattern)),
+            |(_, _, pattern)| Command::ConfigGet { pattern }
+        ),
+        map(
+            tuple((tag_no_case("SET"), multispace1, parse_identifier, multispace1, parse_pattern)),
+            |(_, _, param, _, value)| Command::ConfigSet { param: param.to_string(), value }
+        ),
+    ))(input)
+}
+
 fn parse_cluster(input: &str) -> IResult<&str, Command> {
     let (input, _) = tag_no_case("CLUSTER")(input)?;
     let (input, _) = multispace1(input)?;
@@ -961,6 +2255,27 @@ fn parse_cluster(input: &str) -> IResult<&str, Command> {
             tuple((tag_no_case("ADDSLOTS"), multispace1, separated_list1(multispace1, nom::character::complete::u16))),
             |(_, _, slots)| Command::ClusterAddSlots { slots }
         ),
+        map(
+            tuple((tag_no_case("DELSLOTS"), multispace1, separated_list1(multispace1, nom::character::complete::u16))),
+            |(_, _, slots)| Command::ClusterDelSlots { slots }
+        ),
+        map(
+            tuple((tag_no_case("FORGET"), multispace1, parse_string)),
+            |(_, _, node)| Command::ClusterForget { node }
+        ),
+        map(tag_no_case("FAILOVER"), |_| Command::ClusterFailover),
+        map(
+            tuple((
+                tag_no_case("SETSLOT"), multispace1, nom::character::complete::u16, multispace1,
+                alt((
+                    map(preceded(pair(tag_no_case("MIGRATING"), multispace1), parse_string), SlotState::Migrating),
+                    map(preceded(pair(tag_no_case("IMPORTING"), multispace1), parse_string), SlotState::Importing),
+                    map(preceded(pair(tag_no_case("NODE"), multispace1), parse_string), SlotState::Node),
+                    map(tag_no_case("STABLE"), |_| SlotState::Stable),
+                )),
+            )),
+            |(_, _, slot, _, state)| Command::ClusterSetSlot { slot, state }
+        ),
     ))(input)
 }
 
@@ -976,6 +2291,53 @@ fn parse_rollback(input: &str) -> IResult<&str, Command> {
     map(tag_no_case("ROLLBACK"), |_| Command::Rollback)(input)
 }
 
+// WATCH key [key ...]
+fn parse_watch(input: &str) -> IResult<&str, Command> {
+    map(
+        tuple((command_name("WATCH"), multispace1, separated_list1(multispace1, parse_key))),
+        |(_, _, keys)| Command::Watch { keys: keys.iter().map(|k| k.to_string()).collect() }
+    )(input)
+}
+
+fn parse_unwatch(input: &str) -> IResult<&str, Command> {
+    map(command_name("UNWATCH"), |_| Command::Unwatch)(input)
+}
+
+// EVAL script numkeys [key ...] [arg ...] | EVALSHA sha1 numkeys [key ...] [arg ...]
+// The first numkeys words are the keys the script may touch
+fn parse_eval(input: &str) -> IResult<&str, Command> {
+    let (rest, (name, _, script, _, numkeys, mut args)) = tuple((
+        alt((command_name("EVAL"), command_name("EVALSHA"))),
+        multispace1,
+        parse_string,
+        multispace1,
+        map_res(digit1, str::parse::<usize>),
+        many0(preceded(multispace1, parse_string)),
+    ))(input)?;
+    if numkeys > args.len() || !args[..numkeys].iter().all(|key| is_key(key)) {
+        return Err(nom::Err::Error(nom::error::Error::new(input, nom::error::ErrorKind::Verify)));
+    }
+    let keys: Vec<String> = args.drain(..numkeys).collect();
+    let cmd = if name.eq_ignore_ascii_case("EVALSHA") {
+        Command::EvalSha { sha: script, keys, args }
+    } else {
+        Command::Eval { script, keys, args }
+    };
+    Ok((rest, cmd))
+}
+
+// SCRIPT LOAD script | SCRIPT EXISTS sha1 [sha1 ...] | SCRIPT FLUSH
+fn parse_script(input: &str) -> IResult<&str, Command> {
+    preceded(
+        pair(command_name("SCRIPT"), multispace1),
+        alt((
+            map(preceded(pair(tag_no_case("LOAD"), multispace1), parse_string), |script| Command::ScriptLoad { script }),
+            map(preceded(tag_no_case("EXISTS"), many1(preceded(multispace1, parse_string))), |shas| Command::ScriptExists { shas }),
+            map(tag_no_case("FLUSH"), |_| Command::ScriptFlush),
+        ))
+    )(input)
+}
+
 fn parse_float(input: &str) -> IResult<&str, f64> {
     let (input, number_str) = recognize(tuple((
         opt(tag("-")),
@@ -997,8 +2359,14 @@ fn parse_vector(input: &str) -> IResult<&str, Vec<f64>> {
     )(input)
 }
 
+// SEARCH table column [v1, v2, ...] [LIMIT] k [METRIC cosine|euclidean|dot] [THRESHOLD x] [WHERE filter]
 fn parse_search(input: &str) -> IResult<&str, Command> {
-    map(
+    enum Opt<'a> { Metric(&'a str), Threshold(f64) }
+    let option = alt((
+        map(preceded(pair(tag_no_case("METRIC"), multispace1), alphanumeric1), Opt::Metric),
+        map(preceded(pair(tag_no_case("THRESHOLD"), multispace1), nom::number::complete::double), Opt::Threshold),
+    ));
+    map_res(
         tuple((
             tag_no_case("SEARCH"),
             multispace1,
@@ -1008,45 +2376,86 @@ fn parse_search(input: &str) -> IResult<&str, Command> {
             multispace1,
             parse_vector, // [1.0, 2.0]
             multispace1,
-            nom::character::complete::digit1
+            preceded(opt(pair(tag_no_case("LIMIT"), multispace1)), nom::character::complete::digit1),
+            many0(preceded(multispace1, option)),
+            opt(preceded(tuple((multispace1, tag_no_case("WHERE"), multispace1)), parse_filter)),
         )),
-        |(_, _, table, _, col, _, vec, _, limit_str)| {
+        |(_, _, table, _, col, _, vec, _, limit_str, options, filter)| {
             let limit = limit_str.parse::<usize>().unwrap_or(10);
-            Command::VectorSearch {
+            let (mut metric, mut threshold) = (None, None);
+            for option in options {
+                match option {
+                    Opt::Metric(name) => metric = Some(name.parse::<Metric>()?),
+                    Opt::Threshold(t) => threshold = Some(t),
+                }
+            }
+            Ok::<_, String>(Command::VectorSearch {
                 table: table.to_string(),
                 column: col.to_string(),
                 vector: vec,
                 limit,
-            }
+                metric,
+                threshold,
+                filter,
+            })
         }
     )(input)
 }
 
+/// Statements whose arguments are read as SQL text, even when they come in
+/// a RESP array.
+const SQL_STATEMENTS: [&str; 7] = ["SELECT", "INSERT", "UPDATE", "EXPLAIN", "DECLARE", "CREATE", "ALTER"];
+
+/// Replaces the `UUID()`, `ULID()` and `NOW()` calls of a command line
+/// (see `net::functions`) where they are syntax: anywhere in an `inline`
+/// command, and in SQL statements. The elements of a RESP array sent to
+/// any other command are binary-safe values and are kept as sent.
+pub fn generate_values(line: &str, inline: bool) -> String {
+    let mut words = line.split_whitespace();
+    let first = words.next().unwrap_or_default();
+    let sql = SQL_STATEMENTS.iter().any(|statement| first.eq_ignore_ascii_case(statement))
+        || (first.eq_ignore_ascii_case("DELETE") && words.next().is_some_and(|word| word.eq_ignore_ascii_case("FROM")));
+    if inline || sql {
+        functions::substitute(line)
+    } else {
+        line.to_string()
+    }
+}
+
 pub fn parse_command(input: &str) -> IResult<&str, Command> {
     let (remaining, _) = multispace0(input)?;
     
     // Group 1: General/Admin
     if let Ok(result) = alt((
         alt((
-            parse_set, parse_get, parse_del, parse_setex, parse_ttl,
+            parse_set_output, parse_set_binding, parse_variables, parse_set, parse_get, parse_getrange, parse_getex, parse_del, parse_setex, parse_expire, parse_ttl, parse_pexpireat, parse_schedule, parse_unschedule, parse_wait,
             parse_auth, parse_acl,
-            parse_incr,
-            parse_decr,
-            parse_use,
+            alt((parse_incr, parse_decr, parse_cincr)),
+            alt((parse_use, parse_databases)),
             parse_rewrite_aof,
         )),
         alt((
             parse_ping,
+            parse_quit,
+            alt((parse_reset, parse_readonly)),
+            parse_object,
             parse_save,
+            parse_backup,
+            parse_aof,
             parse_client,
-            parse_replicaof,
+            parse_queries,
+            alt((parse_replicaof, parse_failover, parse_replcheck, parse_migrate)),
             parse_psync,
             parse_info,
+            parse_metrics,
+            parse_memory,
+            parse_config,
             parse_cluster,
             parse_search,
-            parse_begin,
+            alt((parse_begin, parse_watch, parse_unwatch)),
             parse_commit,
             parse_rollback,
+            alt((parse_eval, parse_script)),
         ))
     ))(remaining) {
         return Ok(result);
@@ -1054,24 +2463,41 @@ pub fn parse_command(input: &str) -> IResult<&str, Command> {
 
     // Group 2: Structured (SQL-like)
     if let Ok(result) = alt((
+        parse_create_vector_index,
         parse_create_index,
+        parse_drop_index,
+        parse_show_indexes,
+        parse_show_schema,
         parse_create_table,
+        parse_reindex,
+        parse_check_table,
+        alt((parse_export_table, parse_import_table)),
+        parse_advisor,
         parse_alter_table,
         parse_insert,
         parse_select,
+        parse_explain,
+        parse_cursor,
         parse_update,
         parse_delete,
     ))(remaining) {
         return Ok(result);
     }
 
-    // Group 3: Flexible (Lists, Hashes, Sets, JSON, ZSET)
+    // Group 3: Flexible (Lists, Hashes, Sets, JSON, ZSET, Geo, TOPK, Streams, HLL, Bitmaps)
     if let Ok(result) = alt((
-        parse_lpush, parse_rpush, parse_lpop, parse_rpop, parse_lrange,
-        parse_hset, parse_hget, parse_hgetall,
-        parse_sadd, parse_smembers,
-        parse_zadd, parse_zrange, parse_zscore,
+        alt((parse_lpush, parse_rpush, parse_lpop, parse_rpop, parse_lrange)),
+        alt((parse_hset, parse_hget, parse_hgetall)),
+        alt((parse_sadd, parse_smembers)),
+        alt((parse_zadd, parse_zrange, parse_zscore, parse_zpop, parse_bzpop)),
+        alt((parse_geoadd, parse_geodist, parse_geosearch)),
+        alt((parse_topk_reserve, parse_topk_add, parse_topk_incrby, parse_topk_list)),
+        alt((parse_xadd, parse_xrange, parse_xlen, parse_xread)),
+        alt((parse_pfadd, parse_pfcount, parse_pfmerge)),
+        alt((parse_setbit, parse_getbit, parse_bitcount, parse_bitop)),
         parse_json_get, parse_json_set,
+        parse_scan, parse_keys, parse_export, parse_type, parse_exists, parse_dbsize,
+        alt((parse_dump, parse_restore)),
     ))(remaining) {
         return Ok(result);
     }
@@ -1079,3 +2505,459 @@ pub fn parse_command(input: &str) -> IResult<&str, Command> {
     // Fallback or explicit error
     Err(nom::Err::Error(nom::error::Error::new(input, nom::error::ErrorKind::Tag)))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::split_value_list;
+
+    fn cond(col: &str, op: Operator, val: &str) -> Filter {
+        Filter::Condition(col.to_string(), op, val.to_string())
+    }
+
+    // Filters of each clause that takes one, with `{}` standing for the condition
+    fn filter_of(template: &str, condition: &str) -> (Option<Filter>, Option<Filter>) {
+        let (_, cmd) = parse_command(&template.replace("{}", condition)).unwrap();
+        match cmd {
+            Command::Select { filter, having, .. } => (filter, having),
+            Command::Update { filter, .. } | Command::Delete { filter, .. } | Command::VectorSearch { filter, .. } => (filter, None),
+            other => panic!("unexpected command {:?}", other),
+        }
+    }
+
+    #[test]
+    fn operators_parse_in_every_filter_clause() {
+        let cases = [
+            ("x = 1", cond("x", Operator::Eq, "1")),
+            ("x != 1", cond("x", Operator::Neq, "1")),
+            ("x > 1", cond("x", Operator::Gt, "1")),
+            ("x >= 1", cond("x", Operator::Gte, "1")),
+            ("x < 1", cond("x", Operator::Lt, "1")),
+            ("x <= 1", cond("x", Operator::Lte, "1")),
+            ("x LIKE 'a%'", cond("x", Operator::Like, "a%")),
+            ("x like 'a%'", cond("x", Operator::Like, "a%")),
+            ("x ILIKE 'a%'", cond("x", Operator::ILike, "a%")),
+            ("x IN (1, 2)", cond("x", Operator::In, "1,2")),
+            ("x in ('a b', \"c\")", cond("x", Operator::In, "a b,c")),
+            ("x In(1,2)", cond("x", Operator::In, "1,2")),
+            ("x IS NULL", cond("x", Operator::IsNull, "")),
+            ("x is not null", cond("x", Operator::IsNotNull, "")),
+            ("x = NULL", Filter::And(Box::new(cond("x", Operator::IsNull, "")), Box::new(cond("x", Operator::IsNotNull, "")))),
+            ("x != null", Filter::And(Box::new(cond("x", Operator::IsNull, "")), Box::new(cond("x", Operator::IsNotNull, "")))),
+            ("x = 'NULL'", cond("x", Operator::Eq, "NULL")),
+            ("x BETWEEN 1 AND 5", cond("x", Operator::Between, "1,5")),
+            ("x between 'a,b' and \"c\"", cond("x", Operator::Between, "a\\,b,c")),
+            ("NOT (x = 1)", Filter::Not(Box::new(cond("x", Operator::Eq, "1")))),
+            ("not x = 1", Filter::Not(Box::new(cond("x", Operator::Eq, "1")))),
+            ("x * 1.5 > 10", Filter::Computed(
+                Expr::Binary(Box::new(Expr::Column("x".into())), ArithOp::Mul, Box::new(Expr::Literal(UnifiedValue::Float(1.5)))),
+                Operator::Gt, "10".into(),
+            )),
+            ("lower(x) = \"bob\"", Filter::Computed(Expr::Call(ScalarFn::Lower, Box::new(Expr::Column("x".into()))), Operator::Eq, "bob".into())),
+            ("LENGTH(TRIM(x)) BETWEEN 1 AND 5", Filter::Computed(
+                Expr::Call(ScalarFn::Length, Box::new(Expr::Call(ScalarFn::Trim, Box::new(Expr::Column("x".into()))))),
+                Operator::Between, "1,5".into(),
+            )),
+            ("at > DATE_ADD(1704067200, -1, DAY)", cond("at", Operator::Gt, "1703980800")),
+            ("at BETWEEN '2024-01-01' AND date_add('2024-01-01', 2, hours)", cond("at", Operator::Between, "2024-01-01,1704074400")),
+            ("COUNT(*) > 5 AND sum(x) < 100", Filter::And(
+                Box::new(Filter::Computed(Expr::Aggregate(Box::new(Selector::Count)), Operator::Gt, "5".into())),
+                Box::new(Filter::Computed(Expr::Aggregate(Box::new(Selector::Sum("x".into()))), Operator::Lt, "100".into())),
+            )),
+            ("COUNT(DISTINCT x) BETWEEN 2 AND 4", Filter::Computed(
+                Expr::Aggregate(Box::new(Selector::CountDistinct("x".into()))), Operator::Between, "2,4".into(),
+            )),
+            ("DATE_DIFF(at, '2024-01-01', DAY) >= 7", Filter::Computed(
+                Expr::DateDiff(Box::new(Expr::Column("at".into())), Box::new(Expr::Literal(UnifiedValue::String("2024-01-01".into()))), TimeUnit::Day),
+                Operator::Gte, "7".into(),
+            )),
+        ];
+        let where_clauses = [
+            "SELECT t WHERE {}",
+            "SELECT * FROM t WHERE {}",
+            "SELECT * FROM t JOIN u ON t.id = u.tid WHERE {}",
+            "UPDATE t SET y = 1 WHERE {}",
+            "DELETE FROM t WHERE {}",
+            "SEARCH t v [1, 0] LIMIT 5 WHERE {}",
+        ];
+        for (condition, expected) in &cases {
+            for template in where_clauses {
+                assert_eq!(filter_of(template, condition).0.as_ref(), Some(expected), "{}", template);
+            }
+            let having = filter_of("SELECT COUNT FROM t GROUP BY y HAVING {}", condition).1;
+            assert_eq!(having.as_ref(), Some(expected), "HAVING {}", condition);
+        }
+    }
+
+    #[test]
+    fn filters_combine_case_insensitively() {
+        let (filter, _) = filter_of("SELECT t WHERE {}", "a = 1 and b in (2, 3) OR c like 'x%'");
+        let expected = Filter::Or(
+            Box::new(Filter::And(
+                Box::new(cond("a", Operator::Eq, "1")),
+                Box::new(cond("b", Operator::In, "2,3")),
+            )),
+            Box::new(cond("c", Operator::Like, "x%")),
+        );
+        assert_eq!(filter, Some(expected));
+
+        // BETWEEN's AND is not a conjunction; NOT binds to the next atom
+        let (filter, _) = filter_of("SELECT t WHERE {}", "NOT(a BETWEEN 1 AND 2) AND notes = 3");
+        let expected = Filter::And(
+            Box::new(Filter::Not(Box::new(cond("a", Operator::Between, "1,2")))),
+            Box::new(cond("notes", Operator::Eq, "3")),
+        );
+        assert_eq!(filter, Some(expected));
+    }
+
+    #[test]
+    fn select_clauses_are_case_insensitive() {
+        let paging = |query: &str| match parse_command(query).unwrap() {
+            ("", Command::Select { order_by, limit, offset, .. }) => (order_by, limit, offset),
+            other => panic!("unexpected parse of {}: {:?}", query, other),
+        };
+        let desc = Some(vec![("age".to_string(), false)]);
+        assert_eq!(paging("select * from users where age > 1 order by age desc limit 10 offset 5"), (desc.clone(), Some(10), Some(5)));
+        assert_eq!(paging("SELECT * FROM users ORDER BY age DESC OFFSET 5 ROWS FETCH FIRST 10 ROWS ONLY"), (desc, Some(10), Some(5)));
+        assert_eq!(paging("SELECT * FROM users fetch next row only"), (None, Some(1), None));
+        assert!(matches!(
+            parse_command("select count(*) from users group by team having count > 1"),
+            Ok(("", Command::Select { selector: Selector::Count, group_by: Some(_), having: Some(_), .. }))
+        ));
+    }
+
+    #[test]
+    fn maintenance_commands_are_case_insensitive() {
+        assert_eq!(parse_command("reindex users").unwrap(), ("", Command::Reindex { table: "users".into(), column: None }));
+        assert_eq!(parse_command("Reindex users profile->city").unwrap(), ("", Command::Reindex { table: "users".into(), column: Some("profile->city".into()) }));
+        assert_eq!(parse_command("check Table users").unwrap(), ("", Command::CheckTable { table: "users".into() }));
+    }
+
+    #[test]
+    fn table_aliases() {
+        let tables = |query: &str| match parse_command(query).unwrap() {
+            ("", Command::Select { alias, join, .. }) => {
+                let joins = join.unwrap_or_default().into_iter().map(|j| (j.table, j.alias, j.on_left, j.on_right)).collect::<Vec<_>>();
+                (alias, joins)
+            }
+            other => panic!("unexpected parse of {}: {:?}", query, other),
+        };
+        assert_eq!(
+            tables("SELECT u.name, o.total FROM users u JOIN orders AS o ON u.id = o.user_id WHERE o.total > 5"),
+            (Some("u".into()), vec![("orders".into(), Some("o".into()), "u.id".into(), "o.user_id".into())])
+        );
+        assert_eq!(
+            tables("SELECT e.name, m.name FROM staff e JOIN staff m ON e.manager = m.id"),
+            (Some("e".into()), vec![("staff".into(), Some("m".into()), "e.manager".into(), "m.id".into())])
+        );
+        // Clause keywords are never taken for aliases
+        assert_eq!(tables("SELECT * FROM users where id = 1 order by id limit 1"), (None, vec![]));
+        assert_eq!(tables("SELECT * FROM users JOIN orders ON users.id = orders.user_id"),
+            (None, vec![("orders".into(), None, "users.id".into(), "orders.user_id".into())]));
+    }
+
+    #[test]
+    fn insert_rows() {
+        let insert = |rows: Vec<Vec<Option<&str>>>| Command::Insert {
+            table: "users".into(),
+            rows: rows.into_iter().map(|row| row.into_iter().map(|v| v.map(String::from)).collect()).collect(),
+        };
+        assert_eq!(parse_command("INSERT users 1 'Ana Lee' NULL").unwrap(), ("", insert(vec![vec![Some("1"), Some("Ana Lee"), None]])));
+        assert_eq!(
+            parse_command("INSERT INTO users VALUES (1, 'Ana, Lee'), (2,\"Bo\",NULL) , ( 3 )").unwrap(),
+            ("", insert(vec![vec![Some("1"), Some("Ana, Lee")], vec![Some("2"), Some("Bo"), None], vec![Some("3")]]))
+        );
+        assert_eq!(parse_command("insert users values (1, x)").unwrap(), ("", insert(vec![vec![Some("1"), Some("x")]])));
+        // A table whose name starts with INTO
+        assert_eq!(parse_command("INSERT into_users VALUES (1)").unwrap().1, Command::Insert { table: "into_users".into(), rows: vec![vec![Some("1".into())]] });
+    }
+
+    #[test]
+    fn cursor_commands() {
+        match parse_command("DECLARE big CURSOR FOR SELECT * FROM events WHERE id > 10").unwrap() {
+            ("", Command::Declare { name, query }) => {
+                assert_eq!(name, "big");
+                assert!(matches!(*query, Command::Select { ref table, filter: Some(_), .. } if table == "events"));
+            }
+            other => panic!("unexpected parse: {:?}", other),
+        }
+        let fetch = |name: &str, count| Command::Fetch { name: name.into(), count };
+        assert_eq!(parse_command("FETCH 100 FROM big").unwrap(), ("", fetch("big", Some(100))));
+        assert_eq!(parse_command("fetch next from big").unwrap(), ("", fetch("big", Some(1))));
+        assert_eq!(parse_command("FETCH FROM big").unwrap(), ("", fetch("big", Some(1))));
+        assert_eq!(parse_command("FETCH ALL FROM big").unwrap(), ("", fetch("big", None)));
+        assert_eq!(parse_command("CLOSE big").unwrap(), ("", Command::Close { name: Some("big".into()) }));
+        assert_eq!(parse_command("CLOSE ALL").unwrap(), ("", Command::Close { name: None }));
+        assert!(parse_command("DECLARE big CURSOR FOR DELETE FROM events").is_err());
+    }
+
+    #[test]
+    fn index_catalog_commands() {
+        assert_eq!(
+            parse_command("DROP INDEX by_age ON users").unwrap().1,
+            Command::DropIndex { index_name: "by_age".into(), table: "users".into() }
+        );
+        assert_eq!(parse_command("SHOW INDEXES").unwrap().1, Command::ShowIndexes { table: None });
+        assert_eq!(
+            parse_command("show indexes from users").unwrap().1,
+            Command::ShowIndexes { table: Some("users".into()) }
+        );
+    }
+
+    #[test]
+    fn search_options() {
+        let search = |metric, threshold| Command::VectorSearch {
+            table: "docs".into(), column: "emb".into(), vector: vec![1.0, -0.5], limit: 5, metric, threshold, filter: None,
+        };
+        assert_eq!(parse_command("SEARCH docs emb [1.0, -0.5] 5").unwrap().1, search(None, None));
+        assert_eq!(
+            parse_command("SEARCH docs emb [1.0, -0.5] 5 THRESHOLD 0.8 METRIC euclidean").unwrap().1,
+            search(Some(Metric::L2), Some(0.8))
+        );
+        assert_eq!(parse_command("search docs emb [1.0, -0.5] 5 metric DOT").unwrap().1, search(Some(Metric::Dot), None));
+        assert!(parse_command("SEARCH docs emb [1.0, -0.5] 5 METRIC hamming").is_err());
+        assert_eq!(
+            parse_command("SEARCH docs emb [1.0, -0.5] LIMIT 5 METRIC dot WHERE price < 100").unwrap().1,
+            Command::VectorSearch {
+                table: "docs".into(), column: "emb".into(), vector: vec![1.0, -0.5], limit: 5,
+                metric: Some(Metric::Dot), threshold: None, filter: Some(cond("price", Operator::Lt, "100")),
+            }
+        );
+    }
+
+    #[test]
+    fn vector_index_commands() {
+        assert_eq!(
+            parse_command("CREATE VECTOR INDEX ON docs(embedding) DIMS 3 METRIC cosine").unwrap().1,
+            Command::CreateVectorIndex {
+                index_name: "vec_docs_embedding".into(), table: "docs".into(), column: "embedding".into(), dims: 3, metric: Metric::Cosine,
+            }
+        );
+        assert_eq!(
+            parse_command("create vector index by_emb on docs(embedding) dims 128 metric L2").unwrap().1,
+            Command::CreateVectorIndex {
+                index_name: "by_emb".into(), table: "docs".into(), column: "embedding".into(), dims: 128, metric: Metric::L2,
+            }
+        );
+        assert!(parse_command("CREATE VECTOR INDEX ON docs(embedding)").is_err());
+        assert!(parse_command("CREATE VECTOR INDEX ON docs(embedding) DIMS 3 METRIC hamming").is_err());
+    }
+
+    #[test]
+    fn vector_column_dimensions() {
+        let Command::CreateTable { columns, .. } = parse_command("CREATE TABLE docs id:int:pk emb:vector(768):notnull raw:vector").unwrap().1 else {
+            panic!("expected CREATE TABLE");
+        };
+        let types: Vec<_> = columns.iter().map(|c| c.data_type.as_str()).collect();
+        assert_eq!(types, ["int", "vector(768)", "vector"]);
+        assert!(columns[1].not_null);
+        assert_eq!(
+            parse_command("ALTER TABLE docs ADD emb:vector(3)").unwrap().1,
+            Command::AlterTable { table: "docs".into(), op: AlterOp::Add("emb".into(), "vector(3)".into()) }
+        );
+    }
+
+    #[test]
+    fn column_constraints() {
+        let Command::CreateTable { columns, .. } = parse_command("CREATE TABLE t id:int:pk email:string:unique:notnull status:string:default(\"to do\") n:int:DEFAULT(0)").unwrap().1 else {
+            panic!("expected CREATE TABLE");
+        };
+        assert!(columns[1].unique && columns[1].not_null && columns[1].default.is_none());
+        assert_eq!(columns[2].default.as_deref(), Some("to do"));
+        assert!(!columns[2].unique && !columns[2].not_null);
+        assert_eq!(columns[3].default.as_deref(), Some("0"));
+    }
+
+    #[test]
+    fn watch_commands() {
+        assert_eq!(parse_command("WATCH a {u}:b").unwrap().1, Command::Watch { keys: vec!["a".into(), "{u}:b".into()] });
+        assert_eq!(parse_command("unwatch").unwrap().1, Command::Unwatch);
+        assert!(parse_command("WATCH").is_err());
+    }
+
+    #[test]
+    fn stream_commands() {
+        assert_eq!(
+            parse_command("XADD events * type click \"page\" \"/a b\"").unwrap().1,
+            Command::XAdd { key: "events".into(), id: "*".into(), fields: vec![("type".into(), "click".into()), ("page".into(), "/a b".into())] }
+        );
+        assert!(parse_command("XADD events *").is_err());
+        assert_eq!(
+            parse_command("xrange events - + COUNT 10").unwrap().1,
+            Command::XRange { key: "events".into(), start: "-".into(), end: "+".into(), count: Some(10) }
+        );
+        assert_eq!(parse_command("XLEN events").unwrap().1, Command::XLen { key: "events".into() });
+        assert_eq!(
+            parse_command("XREAD COUNT 5 BLOCK 1000 STREAMS a b 0 1700000000000-3").unwrap().1,
+            Command::XRead { count: Some(5), block_ms: Some(1000), keys: vec!["a".into(), "b".into()], ids: vec!["0".into(), "1700000000000-3".into()] }
+        );
+        assert_eq!(
+            parse_command("XREAD STREAMS a $").unwrap().1,
+            Command::XRead { count: None, block_ms: None, keys: vec!["a".into()], ids: vec!["$".into()] }
+        );
+        assert!(parse_command("XREAD STREAMS a b 0").is_err());
+    }
+
+    #[test]
+    fn bitmap_commands() {
+        assert_eq!(parse_command("SETBIT dau 4294967295 1").unwrap().1, Command::SetBit { key: "dau".into(), offset: 4294967295, value: true });
+        assert!(parse_command("SETBIT dau 4294967296 1").is_err());
+        assert!(parse_command("SETBIT dau 1 10").is_err());
+        assert_eq!(parse_command("getbit dau 7").unwrap().1, Command::GetBit { key: "dau".into(), offset: 7 });
+        assert_eq!(parse_command("BITCOUNT dau").unwrap().1, Command::BitCount { key: "dau".into(), range: None, bits: false });
+        assert_eq!(parse_command("BITCOUNT dau -2 -1 BIT").unwrap().1, Command::BitCount { key: "dau".into(), range: Some((-2, -1)), bits: true });
+        assert_eq!(
+            parse_command("BITOP or week d1 d2").unwrap().1,
+            Command::BitOp { op: bitmap::BitOp::Or, dest: "week".into(), sources: vec!["d1".into(), "d2".into()] }
+        );
+        assert!(parse_command("BITOP NOT inv d1 d2").is_err());
+        assert!(parse_command("BITOP NAND x a").is_err());
+    }
+
+    #[test]
+    fn geo_commands() {
+        assert_eq!(
+            parse_command("GEOADD Sicily CH 13.361389 38.115556 Palermo 15.087269 37.502669 \"Catania C\"").unwrap().1,
+            Command::GeoAdd {
+                key: "Sicily".into(),
+                flags: ZAddFlags { ch: true, ..Default::default() },
+                members: vec![(13.361389, 38.115556, "Palermo".into()), (15.087269, 37.502669, "Catania C".into())],
+            }
+        );
+        assert!(parse_command("GEOADD Sicily GT 13.3 38.1 Palermo").is_err());
+        assert_eq!(
+            parse_command("geodist Sicily Palermo Catania km").unwrap().1,
+            Command::GeoDist { key: "Sicily".into(), member1: "Palermo".into(), member2: "Catania".into(), unit: geo::GeoUnit::Kilometers }
+        );
+        assert_eq!(
+            parse_command("GEOSEARCH Sicily FROMLONLAT 15 37 BYRADIUS 200 km WITHDIST COUNT 2 DESC").unwrap().1,
+            Command::GeoSearch {
+                key: "Sicily".into(), from: GeoOrigin::LonLat(15.0, 37.0), radius: 200.0, unit: geo::GeoUnit::Kilometers,
+                desc: true, count: Some(2), with_coord: false, with_dist: true,
+            }
+        );
+        assert!(matches!(
+            parse_command("GEOSEARCH Sicily FROMMEMBER Palermo BYRADIUS 10 mi").unwrap().1,
+            Command::GeoSearch { from: GeoOrigin::Member(m), unit: geo::GeoUnit::Miles, .. } if m == "Palermo"
+        ));
+        assert!(parse_command("GEOSEARCH Sicily FROMLONLAT 15 37 BYRADIUS 200 parsecs").is_err());
+    }
+
+    #[test]
+    fn script_commands() {
+        assert_eq!(
+            parse_command("EVAL 'incr(KEYS[0])' 1 hits 10 \"a b\"").unwrap().1,
+            Command::Eval { script: "incr(KEYS[0])".into(), keys: vec!["hits".into()], args: vec!["10".into(), "a b".into()] }
+        );
+        assert_eq!(
+            parse_command("evalsha ABC123 0").unwrap().1,
+            Command::EvalSha { sha: "ABC123".into(), keys: vec![], args: vec![] }
+        );
+        assert!(parse_command("EVAL '1' 2 onlyone").is_err());
+        assert!(parse_command("EVAL '1' 1 \"not a key\"").is_err());
+        assert_eq!(parse_command("SCRIPT LOAD '40 + 2'").unwrap().1, Command::ScriptLoad { script: "40 + 2".into() });
+        assert_eq!(parse_command("script exists a b").unwrap().1, Command::ScriptExists { shas: vec!["a".into(), "b".into()] });
+        assert_eq!(parse_command("SCRIPT FLUSH").unwrap().1, Command::ScriptFlush);
+    }
+
+    #[test]
+    fn hyperloglog_commands() {
+        assert_eq!(
+            parse_command("PFADD visitors ana \"bo b\"").unwrap().1,
+            Command::PfAdd { key: "visitors".into(), elements: vec!["ana".into(), "bo b".into()] }
+        );
+        assert_eq!(parse_command("pfadd visitors").unwrap().1, Command::PfAdd { key: "visitors".into(), elements: vec![] });
+        assert_eq!(parse_command("PFCOUNT a b").unwrap().1, Command::PfCount { keys: vec!["a".into(), "b".into()] });
+        assert_eq!(parse_command("PFMERGE all a b").unwrap().1, Command::PfMerge { dest: "all".into(), sources: vec!["a".into(), "b".into()] });
+        assert!(parse_command("PFCOUNT").is_err());
+    }
+
+    #[test]
+    fn schema_introspection_commands() {
+        assert_eq!(parse_command("SHOW TABLES").unwrap().1, Command::ShowTables);
+        assert_eq!(parse_command("show databases").unwrap().1, Command::ShowDatabases);
+        assert_eq!(parse_command("DROP DATABASE shop").unwrap().1, Command::DropDatabase { name: "shop".into() });
+        assert_eq!(parse_command("DBSIZE").unwrap().1, Command::DbSize);
+        assert_eq!(parse_command("describe users").unwrap().1, Command::Describe { table: "users".into() });
+        assert_eq!(parse_command("DESC users").unwrap().1, Command::Describe { table: "users".into() });
+        assert_eq!(
+            parse_command("SHOW CREATE TABLE users").unwrap().1,
+            Command::ShowCreateTable { table: "users".into() }
+        );
+        assert!(matches!(parse_command("EXPLAIN SELECT * FROM users").unwrap().1, Command::Explain { analyze: false, .. }));
+        assert!(matches!(parse_command("explain analyze SELECT * FROM users").unwrap().1, Command::Explain { analyze: true, .. }));
+    }
+
+    #[test]
+    fn redis_aliases_and_shims() {
+        let getrange = Command::GetRange { key: "k".into(), start: 0, end: -1 };
+        assert_eq!(parse_command("GETRANGE k 0 -1").unwrap().1, getrange);
+        assert_eq!(parse_command("substr k 0 -1").unwrap().1, getrange);
+        assert_eq!(parse_command("DELETE a b").unwrap().1, Command::Del { keys: vec!["a".into(), "b".into()] });
+        assert!(matches!(parse_command("DELETE FROM users WHERE id = 1").unwrap().1, Command::Delete { .. }));
+        assert_eq!(parse_command("GETEX k").unwrap().1, Command::GetEx { key: "k".into(), expiry: None });
+        assert_eq!(
+            parse_command("GETEX k EX 10").unwrap().1,
+            Command::GetEx { key: "k".into(), expiry: Some(ExpiryUpdate::After(10_000)) }
+        );
+        assert_eq!(
+            parse_command("getex k persist").unwrap().1,
+            Command::GetEx { key: "k".into(), expiry: Some(ExpiryUpdate::Persist) }
+        );
+        assert_eq!(parse_command("QUIT").unwrap().1, Command::Quit);
+        assert_eq!(parse_command("reset").unwrap().1, Command::Reset);
+        assert_eq!(parse_command("OBJECT HELP").unwrap().1, Command::ObjectHelp);
+        assert_eq!(parse_command("memory usage user:1").unwrap().1, Command::MemoryUsage { key: "user:1".into() });
+        assert_eq!(parse_command("MEMORY STATS").unwrap().1, Command::MemoryStats);
+        assert_eq!(parse_command("AOF STATS").unwrap().1, Command::AofStats);
+        assert_eq!(parse_command("aof cat").unwrap().1, Command::AofCat { from: 1, count: 20 });
+        assert_eq!(parse_command("AOF CAT 40 5").unwrap().1, Command::AofCat { from: 40, count: 5 });
+    }
+
+    #[test]
+    fn counter_and_schedule_commands() {
+        assert_eq!(parse_command("CINCR views").unwrap().1, Command::CIncr { key: "views".into(), by: 1 });
+        assert_eq!(parse_command("cincr views -5").unwrap().1, Command::CIncr { key: "views".into(), by: -5 });
+        // The step is a whole number; anything else is left unparsed
+        assert!(parse_command("CINCR views 1.5").is_ok_and(|(rest, _)| !rest.is_empty()));
+        assert_eq!(
+            parse_command("schedule jobs 'send mail' at 1700000000000 push").unwrap().1,
+            Command::Schedule { key: "jobs".into(), value: "send mail".into(), at_ms: 1700000000000, push: true }
+        );
+        assert_eq!(parse_command("UNSCHEDULE jobs").unwrap().1, Command::Unschedule { key: "jobs".into(), at_ms: None });
+        assert_eq!(parse_command("unschedule jobs at 5").unwrap().1, Command::Unschedule { key: "jobs".into(), at_ms: Some(5) });
+    }
+
+    #[test]
+    fn generated_values_only_replace_syntax() {
+        // Inline text and SQL are syntax; a RESP value is taken as sent
+        assert_eq!(generate_values("SET k UUID()", false), "SET k UUID()");
+        assert_eq!(generate_values("DELETE UUID()", false), "DELETE UUID()");
+        assert_eq!(generate_values("SET k UUID()", true).len(), "SET k ".len() + 36);
+        for line in ["INSERT t UUID() bob", "delete from t WHERE id = UUID()"] {
+            assert!(!generate_values(line, false).contains("UUID()"), "{}", line);
+        }
+    }
+
+    #[test]
+    fn pop_commands() {
+        assert_eq!(parse_command("ZPOPMIN jobs").unwrap().1, Command::ZPop { key: "jobs".into(), count: None, max: false });
+        assert_eq!(parse_command("zpopmax jobs 3").unwrap().1, Command::ZPop { key: "jobs".into(), count: Some(3), max: true });
+        assert_eq!(
+            parse_command("BZPOPMIN a b 1.5").unwrap().1,
+            Command::BZPop { keys: vec!["a".into(), "b".into()], timeout_ms: 1500, max: false }
+        );
+        assert_eq!(parse_command("bzpopmax a 0").unwrap().1, Command::BZPop { keys: vec!["a".into()], timeout_ms: 0, max: true });
+        // A key and a timeout are both needed, and the timeout is a duration
+        for line in ["BZPOPMIN 5", "BZPOPMIN a", "BZPOPMIN a -1", "BZPOPMIN a inf", "BZPOPMIN a soon"] {
+            assert!(parse_command(line).is_err(), "{}", line);
+        }
+    }
+
+    #[test]
+    fn quoted_list_items_keep_commas() {
+        let (filter, _) = filter_of("SELECT t WHERE {}", r#"x IN ('a,b', "c\\d", e)"#);
+        let Some(Filter::Condition(_, Operator::In, value)) = filter else { panic!("expected IN") };
+        assert_eq!(split_value_list(&value), ["a,b", "c\\d", "e"]);
+    }
+}
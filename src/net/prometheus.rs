@@ -0,0 +1,199 @@
+//! # Prometheus Exporter
+//!
+//! With `metrics_port` set, the server answers `GET /metrics` on that port
+//! with its metrics in the Prometheus text format (version 0.0.4). Each
+//! subsystem writes its own families into an [`Exposition`]; this module
+//! adds the server-wide ones (connections, memory, AOF, replication) and
+//! serves the result over a minimal HTTP/1.1 listener. Rendering walks every
+//! keyspace, so it runs on tokio's blocking pool rather than a runtime worker.
+
+use crate::core::logger;
+use crate::core::metrics::{METRICS, resident_memory_bytes};
+use crate::core::registry::DatabaseRegistry;
+use crate::net::listener;
+use crate::net::resp::PROTOCOL_STATS;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// Largest request head read before answering.
+const MAX_REQUEST_BYTES: usize = 8 * 1024;
+
+/// How long a scraper has to send its request head.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A metrics page being written.
+#[derive(Default)]
+pub struct Exposition {
+    text: String,
+}
+
+impl Exposition {
+    /// Starts a metric family; `kind` is `counter`, `gauge` or `histogram`.
+    pub fn family(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.text, "# HELP {} {}", name, help);
+        let _ = writeln!(self.text, "# TYPE {} {}", name, kind);
+    }
+
+    pub fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: impl std::fmt::Display) {
+        self.text.push_str(name);
+        if !labels.is_empty() {
+            let labels: Vec<String> = labels.iter()
+                .map(|(k, v)| format!("{}=\"{}\"", k, v.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")))
+                .collect();
+            let _ = write!(self.text, "{{{}}}", labels.join(","));
+        }
+        let _ = writeln!(self.text, " {}", value);
+    }
+
+    /// A family with a single unlabeled sample.
+    pub fn single(&mut self, name: &str, kind: &str, help: &str, value: impl std::fmt::Display) {
+        self.family(name, kind, help);
+        self.sample(name, &[], value);
+    }
+
+    pub fn finish(self) -> String {
+        self.text
+    }
+}
+
+/// The whole metrics page.
+pub fn render(registry: &DatabaseRegistry, connections: usize) -> String {
+    let mut out = Exposition::default();
+    out.single("toridb_connected_clients", "gauge", "Open client connections.", connections);
+    out.single("toridb_max_clients", "gauge", "Connections accepted before new ones are refused.", registry.config.max_connections());
+    PROTOCOL_STATS.prometheus(&mut out);
+    METRICS.prometheus(&mut out);
+
+    let databases = registry.databases();
+    out.family("toridb_keys", "gauge", "Keys in the flexible keyspace, sorted sets and Top-K sketches included.");
+    for (name, engine, _) in &databases {
+        out.sample("toridb_keys", &[("db", name)], engine.flexible.key_count());
+    }
+    out.family("toridb_memory_bytes", "gauge", "Approximate RAM used by each store.");
+    for (name, engine, _) in &databases {
+        for (store, bytes) in engine.flexible.memory_bytes() {
+            out.sample("toridb_memory_bytes", &[("db", name), ("store", store)], bytes);
+        }
+        out.sample("toridb_memory_bytes", &[("db", name), ("store", "sql")], engine.structured.memory_bytes());
+    }
+    out.family("toridb_aof_queue_depth", "gauge", "Operations waiting to be written to the AOF.");
+    for (name, _, aof) in &databases {
+        out.sample("toridb_aof_queue_depth", &[("db", name)], aof.queue_depth());
+    }
+    out.family("toridb_master", "gauge", "1 if the database is a replication master, 0 on a replica.");
+    for (name, engine, _) in &databases {
+        out.sample("toridb_master", &[("db", name)], engine.replication.is_master() as u8);
+    }
+    out.family("toridb_replica_lag_commands", "gauge", "Commands queued for a replica but not yet sent to it.");
+    for (name, engine, _) in &databases {
+        for (replica, pending) in engine.replication.backlog() {
+            out.sample("toridb_replica_lag_commands", &[("db", name), ("replica", &replica)], pending);
+        }
+    }
+    if let Some(rss) = resident_memory_bytes() {
+        out.single("process_resident_memory_bytes", "gauge", "Resident memory size in bytes.", rss);
+    }
+    out.finish()
+}
+
+/// Binds the exporter on `addrs` and serves it in the background.
+pub async fn spawn(addrs: Vec<SocketAddr>, registry: Arc<DatabaseRegistry>, connections: Arc<AtomicUsize>) -> anyhow::Result<()> {
+    for addr in addrs {
+        let listener = listener::bind(addr, false)
+            .map_err(|e| anyhow::anyhow!("Failed to bind metrics endpoint {}: {}", addr, e))?;
+        logger::info(&format!("Prometheus metrics on http://{}/metrics", addr));
+        let registry = registry.clone();
+        let connections = connections.clone();
+        tokio::spawn(async move {
+            loop {
+                let socket = match listener.accept().await {
+                    Ok((socket, _)) => socket,
+                    Err(e) => {
+                        logger::error(&format!("Metrics accept failed: {}", e));
+                        tokio::time::sleep(listener::ACCEPT_BACKOFF).await;
+                        continue;
+                    }
+                };
+                let registry = registry.clone();
+                let connections = connections.clone();
+                tokio::spawn(async move {
+                    serve(socket, registry, connections.load(Ordering::Relaxed)).await;
+                });
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Answers one request, then closes the connection.
+async fn serve(mut socket: TcpStream, registry: Arc<DatabaseRegistry>, connections: usize) {
+    let mut head = Vec::new();
+    let mut chunk = [0u8; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        match tokio::time::timeout(READ_TIMEOUT, socket.read(&mut chunk)).await {
+            Ok(Ok(0) | Err(_)) | Err(_) => return,
+            Ok(Ok(n)) => head.extend_from_slice(&chunk[..n]),
+        }
+        if head.len() > MAX_REQUEST_BYTES {
+            break;
+        }
+    }
+    let request_line = String::from_utf8_lossy(&head).lines().next().unwrap_or_default().to_string();
+    let mut parts = request_line.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next().map(|p| p.split('?').next().unwrap_or(p))) {
+        (Some("GET"), Some("/metrics")) => {
+            match tokio::task::spawn_blocking(move || render(&registry, connections)).await {
+                Ok(page) => ("200 OK", page),
+                Err(_) => ("500 Internal Server Error", "Internal Server Error\n".to_string()),
+            }
+        }
+        (Some("GET"), _) => ("404 Not Found", "Not Found\n".to_string()),
+        _ => ("405 Method Not Allowed", "Method Not Allowed\n".to_string()),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status, body.len(), body
+    );
+    let _ = socket.write_all(response.as_bytes()).await;
+    let _ = socket.shutdown().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exposition_format() {
+        let mut out = Exposition::default();
+        out.family("toridb_keys", "gauge", "Keys.");
+        out.sample("toridb_keys", &[("db", "a\"b")], 3);
+        out.single("toridb_up", "gauge", "Up.", 1);
+        assert_eq!(out.finish(), "# HELP toridb_keys Keys.\n# TYPE toridb_keys gauge\ntoridb_keys{db=\"a\\\"b\"} 3\n# HELP toridb_up Up.\n# TYPE toridb_up gauge\ntoridb_up 1\n");
+    }
+
+    async fn scrape(registry: &Arc<DatabaseRegistry>, request: &str) -> String {
+        let listener = listener::bind("127.0.0.1:0".parse().unwrap(), false).unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        tokio::spawn(serve(socket, registry.clone(), 3));
+        client.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn serves_the_metrics_page() {
+        let registry = Arc::new(DatabaseRegistry::new());
+        let page = scrape(&registry, "GET /metrics?x=1 HTTP/1.1\r\nHost: a\r\n\r\n").await;
+        assert!(page.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(page.contains("\ntoridb_connected_clients 3\n"));
+        assert!(scrape(&registry, "GET / HTTP/1.1\r\n\r\n").await.starts_with("HTTP/1.1 404"));
+        assert!(scrape(&registry, "POST /metrics HTTP/1.1\r\n\r\n").await.starts_with("HTTP/1.1 405"));
+    }
+}
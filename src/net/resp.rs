@@ -1,5 +1,7 @@
 use bytes::{BytesMut, Buf};
-use anyhow::{Result, anyhow};
+use anyhow::Result;
+use std::sync::atomic::{AtomicU64, Ordering};
+use crate::net::parser::quote_string;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum RespValue {
@@ -38,165 +40,382 @@ impl RespValue {
         }
     }
 
-    pub fn to_command_string(&self) -> Option<String> {
+    /// Command arguments of an array, one string per element.
+    pub fn to_args(&self) -> Option<Vec<String>> {
         match self {
-            RespValue::Array(Some(parts)) => {
-                let mut res = String::new();
-                for (i, part) in parts.iter().enumerate() {
-                    if i > 0 { res.push(' '); }
-                    match part {
-                        RespValue::BulkString(Some(b)) => {
-                            let s = String::from_utf8_lossy(b);
-                            if s.contains(' ') || s.contains('\n') || s.contains('\r') || s.is_empty() {
-                                let escaped = s.replace("\"", "\\\"").replace("\n", "\\n").replace("\r", "\\r");
-                                res.push_str(&format!("\"{}\"", escaped));
-                            } else {
-                                res.push_str(&s);
-                            }
-                        }
-                        RespValue::SimpleString(s) => res.push_str(s),
-                        RespValue::Integer(i) => res.push_str(&i.to_string()),
-                        _ => return None,
-                    }
-                }
-                Some(res)
-            }
+            RespValue::Array(Some(parts)) => parts.iter()
+                .map(|part| match part {
+                    RespValue::BulkString(Some(b)) => Some(String::from_utf8_lossy(b).into_owned()),
+                    RespValue::SimpleString(s) => Some(s.clone()),
+                    RespValue::Integer(i) => Some(i.to_string()),
+                    _ => None,
+                })
+                .collect(),
             _ => None,
         }
     }
+
+    /// Joins an array into a command line for the parser.
+    ///
+    /// Parts containing whitespace (or empty ones) are quoted so they stay a
+    /// single token; other parts are passed through as syntax.
+    pub fn to_command_string(&self) -> Option<String> {
+        let args = self.to_args()?;
+        let parts: Vec<String> = args.into_iter()
+            .map(|s| if s.is_empty() || s.contains(char::is_whitespace) { quote_string(&s) } else { s })
+            .collect();
+        Some(parts.join(" "))
+    }
 }
 
-pub fn decode(buf: &mut BytesMut) -> Result<Option<RespValue>> {
-    if buf.is_empty() {
-        return Ok(None);
-    }
-
-    let prefix = buf[0];
-    match prefix {
-        b'+' => decode_simple_string(buf),
-        b'-' => decode_error(buf),
-        b':' => decode_integer(buf),
-        b'$' => decode_bulk_string(buf),
-        b'*' => decode_array(buf),
-        _ => {
-            // Support simple text/inline commands for backward compatibility and PING
-            decode_inline(buf)
+/// Longest bulk string a client may send (512 MiB, as in Redis).
+pub const MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+/// Most elements in one array.
+pub const MAX_ARRAY_LEN: usize = 1024 * 1024;
+/// Longest inline command or header line.
+pub const MAX_LINE_LEN: usize = 64 * 1024;
+/// Deepest array nesting.
+const MAX_DEPTH: usize = 8;
+
+/// Why a frame was rejected. Decoding stops at the first bad frame, as the
+/// rest of the stream can no longer be framed reliably.
+#[derive(Debug)]
+pub enum ProtocolError {
+    /// Not valid RESP: unknown type byte, bad length, missing CRLF, ...
+    Malformed(String),
+    /// Valid, but over one of the size limits.
+    TooLarge(String),
+}
+
+impl std::fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProtocolError::Malformed(detail) | ProtocolError::TooLarge(detail) => f.write_str(detail),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+fn malformed(detail: impl Into<String>) -> anyhow::Error {
+    ProtocolError::Malformed(detail.into()).into()
+}
+
+fn too_large(detail: impl Into<String>) -> anyhow::Error {
+    ProtocolError::TooLarge(detail.into()).into()
+}
+
+/// Server-wide wire counters, reported in the `# Stats` section of INFO.
+#[derive(Debug, Default)]
+pub struct ProtocolStats {
+    input_bytes: AtomicU64,
+    frames: AtomicU64,
+    inline_frames: AtomicU64,
+    malformed: AtomicU64,
+    too_large: AtomicU64,
+}
+
+pub static PROTOCOL_STATS: ProtocolStats = ProtocolStats::new();
+
+impl ProtocolStats {
+    pub const fn new() -> Self {
+        Self {
+            input_bytes: AtomicU64::new(0),
+            frames: AtomicU64::new(0),
+            inline_frames: AtomicU64::new(0),
+            malformed: AtomicU64::new(0),
+            too_large: AtomicU64::new(0),
+        }
+    }
+
+    pub fn record_read(&self, bytes: usize) {
+        self.input_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_frame(&self, inline: bool) {
+        self.frames.fetch_add(1, Ordering::Relaxed);
+        if inline {
+            self.inline_frames.fetch_add(1, Ordering::Relaxed);
         }
     }
+
+    pub fn record_error(&self, err: &anyhow::Error) {
+        match err.downcast_ref::<ProtocolError>() {
+            Some(ProtocolError::TooLarge(_)) => &self.too_large,
+            _ => &self.malformed,
+        }.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn info(&self) -> String {
+        let malformed = self.malformed.load(Ordering::Relaxed);
+        let too_large = self.too_large.load(Ordering::Relaxed);
+        format!(
+            "# Stats\r\ntotal_net_input_bytes:{}\r\ntotal_frames_received:{}\r\ninline_frames_received:{}\r\nprotocol_errors:{}\r\nprotocol_errors_malformed:{}\r\nprotocol_errors_too_large:{}\r\n",
+            self.input_bytes.load(Ordering::Relaxed),
+            self.frames.load(Ordering::Relaxed),
+            self.inline_frames.load(Ordering::Relaxed),
+            malformed + too_large,
+            malformed,
+            too_large
+        )
+    }
+
+    pub fn prometheus(&self, out: &mut crate::net::prometheus::Exposition) {
+        out.single("toridb_net_input_bytes_total", "counter", "Bytes read from clients.", self.input_bytes.load(Ordering::Relaxed));
+        out.single("toridb_frames_received_total", "counter", "Request frames received.", self.frames.load(Ordering::Relaxed));
+        out.family("toridb_protocol_errors_total", "counter", "Connections closed for invalid frames.");
+        out.sample("toridb_protocol_errors_total", &[("reason", "malformed")], self.malformed.load(Ordering::Relaxed));
+        out.sample("toridb_protocol_errors_total", &[("reason", "too_large")], self.too_large.load(Ordering::Relaxed));
+    }
 }
 
-fn read_line(buf: &mut BytesMut) -> Option<Vec<u8>> {
-    for i in 0..buf.len() {
-        if i + 1 < buf.len() && buf[i] == b'\r' && buf[i+1] == b'\n' {
-            let line = buf.split_to(i).to_vec();
-            buf.advance(2); // skip \r\n
-            return Some(line);
+/// Decodes the next frame of a one-off buffer, consuming it. `Ok(None)` means
+/// more input is needed and leaves the buffer untouched; an error means the
+/// stream is broken (see [`ProtocolError`]). Blank inline lines are skipped.
+/// A connection reading frames bit by bit keeps a [`Decoder`] instead.
+pub fn decode(buf: &mut BytesMut) -> Result<Option<RespValue>> {
+    Decoder::default().decode(buf)
+}
+
+/// Decodes the frames of one stream. Like [`decode`], a frame is consumed
+/// only once complete, but what was parsed of it is kept between calls, so
+/// a large frame arriving over many reads is parsed once, not once per read.
+/// The buffer must only be appended to in between.
+#[derive(Debug, Default)]
+pub struct Decoder {
+    /// Arrays of the current frame being filled, outermost first, each with
+    /// the number of items it expects
+    open: Vec<(Vec<RespValue>, usize)>,
+    /// Bytes of the current frame parsed so far
+    parsed: usize,
+    /// Bytes past `parsed` already searched for the end of the line
+    scanned: usize,
+}
+
+impl Decoder {
+    /// The next frame, as [`decode`].
+    pub fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<RespValue>> {
+        let decoded = self.resume(buf);
+        if decoded.is_err() {
+            *self = Self::default();
+        }
+        decoded
+    }
+
+    fn resume(&mut self, buf: &mut BytesMut) -> Result<Option<RespValue>> {
+        loop {
+            let rest = &buf[self.parsed..];
+            let Some(&prefix) = rest.first() else { return Ok(None) };
+            if self.open.is_empty() && !matches!(prefix, b'+' | b'-' | b':' | b'$' | b'*') {
+                // Simple text/inline commands, for backward compatibility and PING
+                match parse_inline(rest, self.scanned)? {
+                    Some((value, used)) => {
+                        buf.advance(used);
+                        self.scanned = 0;
+                        match value {
+                            Some(value) => return Ok(Some(value)),
+                            None => continue,
+                        }
+                    }
+                    None => {
+                        self.scanned = rest.len();
+                        return Ok(None);
+                    }
+                }
+            }
+
+            let Some((line, mut used)) = header_line(rest, self.scanned)? else {
+                self.scanned = rest.len();
+                return Ok(None);
+            };
+            self.scanned = 0;
+            let mut value = match prefix {
+                b'+' => RespValue::SimpleString(utf8(line)?),
+                b'-' => RespValue::Error(utf8(line)?),
+                b':' => {
+                    let text = utf8(line)?;
+                    RespValue::Integer(text.parse().map_err(|_| malformed(format!("invalid integer '{}'", text)))?)
+                }
+                b'$' => match length(line, "bulk")? {
+                    None => RespValue::BulkString(None),
+                    Some(len) if len > MAX_BULK_LEN => return Err(too_large(format!("bulk length {} exceeds {}", len, MAX_BULK_LEN))),
+                    Some(len) => {
+                        // Only the header is parsed again once the rest arrives
+                        if rest.len() < used + len + 2 {
+                            return Ok(None);
+                        }
+                        if &rest[used + len..used + len + 2] != b"\r\n" {
+                            return Err(malformed("bulk string not terminated by CRLF"));
+                        }
+                        let data = rest[used..used + len].to_vec();
+                        used += len + 2;
+                        RespValue::BulkString(Some(data))
+                    }
+                },
+                b'*' => match length(line, "multibulk")? {
+                    None => RespValue::Array(None),
+                    Some(len) if len > MAX_ARRAY_LEN => return Err(too_large(format!("multibulk length {} exceeds {}", len, MAX_ARRAY_LEN))),
+                    Some(_) if self.open.len() >= MAX_DEPTH => return Err(too_large("arrays nested too deeply")),
+                    Some(0) => RespValue::Array(Some(Vec::new())),
+                    Some(len) => {
+                        self.open.push((Vec::with_capacity(len.min(1024)), len));
+                        self.parsed += used;
+                        continue;
+                    }
+                },
+                other => return Err(malformed(format!("expected a type byte, got '{}'", (other as char).escape_default()))),
+            };
+            self.parsed += used;
+
+            // Completes the arrays this value fills
+            loop {
+                let Some((items, len)) = self.open.last_mut() else {
+                    buf.advance(std::mem::take(&mut self.parsed));
+                    return Ok(Some(value));
+                };
+                items.push(value);
+                if items.len() < *len {
+                    break;
+                }
+                let (items, _) = self.open.pop().unwrap();
+                value = RespValue::Array(Some(items));
+            }
         }
     }
-    None
 }
 
-fn decode_simple_string(buf: &mut BytesMut) -> Result<Option<RespValue>> {
-    if let Some(line) = read_line(&mut buf.split_off(1)) {
-        buf.advance(line.len() + 3); // '+' + line + \r\n
-        let s = String::from_utf8(line)?;
-        Ok(Some(RespValue::SimpleString(s)))
-    } else {
-        Ok(None)
+/// The header line after the type byte, and the bytes up to its end. The
+/// first `scanned` bytes are known to hold no line end.
+fn header_line(buf: &[u8], scanned: usize) -> Result<Option<(&[u8], usize)>> {
+    let from = scanned.saturating_sub(1).max(1);
+    match buf[from..].windows(2).position(|w| w == b"\r\n").map(|i| i + from) {
+        Some(cr) if cr > MAX_LINE_LEN + 1 => Err(too_large("header line too long")),
+        Some(cr) => Ok(Some((&buf[1..cr], cr + 2))),
+        None if buf.len() > MAX_LINE_LEN => Err(too_large("header line too long")),
+        None => Ok(None),
     }
 }
 
-fn decode_error(buf: &mut BytesMut) -> Result<Option<RespValue>> {
-    if let Some(line) = read_line(&mut buf.split_off(1)) {
-        buf.advance(line.len() + 3);
-        let s = String::from_utf8(line)?;
-        Ok(Some(RespValue::Error(s)))
-    } else {
-        Ok(None)
+fn utf8(line: &[u8]) -> Result<String> {
+    String::from_utf8(line.to_vec()).map_err(|_| malformed("invalid UTF-8 in header line"))
+}
+
+/// A `$`/`*` length: `None` for the null value (-1).
+fn length(line: &[u8], what: &str) -> Result<Option<usize>> {
+    let text = utf8(line)?;
+    match text.parse::<i64>() {
+        Ok(-1) => Ok(None),
+        Ok(len) if len >= 0 => Ok(Some(len as usize)),
+        _ => Err(malformed(format!("invalid {} length '{}'", what, text))),
     }
 }
 
-fn decode_integer(buf: &mut BytesMut) -> Result<Option<RespValue>> {
-    if let Some(line) = read_line(&mut buf.split_off(1)) {
-        buf.advance(line.len() + 3);
-        let s = String::from_utf8(line)?;
-        let i = s.parse::<i64>()?;
-        Ok(Some(RespValue::Integer(i)))
-    } else {
-        Ok(None)
+/// An inline command line (`None` for a blank one), and the bytes up to its
+/// end. The first `scanned` bytes are known to hold no line end.
+fn parse_inline(buf: &[u8], scanned: usize) -> Result<Option<(Option<RespValue>, usize)>> {
+    let Some(end) = buf[scanned..].iter().position(|&b| b == b'\n').map(|i| i + scanned) else {
+        return if buf.len() > MAX_LINE_LEN { Err(too_large("inline command too long")) } else { Ok(None) };
+    };
+    if end > MAX_LINE_LEN {
+        return Err(too_large("inline command too long"));
+    }
+    let s = String::from_utf8_lossy(&buf[..end]).trim().to_string();
+    if s.is_empty() {
+        return Ok(Some((None, end + 1)));
     }
+
+    // Convert inline to Array for parser
+    let parts: Vec<RespValue> = s.split_whitespace()
+        .map(|p| RespValue::BulkString(Some(p.as_bytes().to_vec())))
+        .collect();
+    Ok(Some((Some(RespValue::Array(Some(parts))), end + 1)))
 }
 
-fn decode_bulk_string(buf: &mut BytesMut) -> Result<Option<RespValue>> {
-    // We need to keep the original buffer intact if not enough data
-    let mut temp = buf.clone();
-    temp.advance(1); // skip '$'
-    
-    if let Some(line) = read_line(&mut temp) {
-        let len = String::from_utf8(line)?.parse::<isize>()?;
-        if len == -1 {
-            buf.advance(buf.len() - temp.len());
-            return Ok(Some(RespValue::BulkString(None)));
-        }
-        
-        let ulen = len as usize;
-        if temp.len() >= ulen + 2 {
-            let data = temp.split_to(ulen).to_vec();
-            if temp[0] != b'\r' || temp[1] != b'\n' {
-                return Err(anyhow!("Invalid bulk string terminator"));
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_all(input: &[u8]) -> (Vec<RespValue>, Option<anyhow::Error>) {
+        let mut buf = BytesMut::from(input);
+        let mut values = Vec::new();
+        loop {
+            match decode(&mut buf) {
+                Ok(Some(value)) => values.push(value),
+                Ok(None) => return (values, None),
+                Err(e) => return (values, Some(e)),
             }
-            buf.advance(buf.len() - temp.len() + 2);
-            Ok(Some(RespValue::BulkString(Some(data))))
-        } else {
-            Ok(None)
         }
-    } else {
-        Ok(None)
     }
-}
 
-fn decode_array(buf: &mut BytesMut) -> Result<Option<RespValue>> {
-    let mut temp = buf.clone();
-    temp.advance(1); // skip '*'
-    
-    if let Some(line) = read_line(&mut temp) {
-        let len = String::from_utf8(line)?.parse::<isize>()?;
-        if len == -1 {
-            buf.advance(buf.len() - temp.len());
-            return Ok(Some(RespValue::Array(None)));
+    #[test]
+    fn partial_frames_wait_for_more_input() {
+        let frame = b"*2\r\n$3\r\nGET\r\n$1\r\nk\r\n+OK\r\n\r\nPING\r\n";
+        for cut in 0..frame.len() {
+            let mut buf = BytesMut::from(&frame[..cut]);
+            let _ = decode(&mut buf).unwrap();
         }
-        
-        let count = len as usize;
-        let mut items = Vec::with_capacity(count);
-        
-        for _ in 0..count {
-            match decode(&mut temp)? {
-                Some(val) => items.push(val),
-                None => return Ok(None), // Incomplete array
+        let (values, err) = decode_all(frame);
+        assert!(err.is_none());
+        assert_eq!(values.len(), 3);
+        assert_eq!(values[1], RespValue::SimpleString("OK".into()));
+        assert_eq!(values[2].to_args().unwrap(), vec!["PING"]);
+    }
+
+    #[test]
+    fn bad_frames_are_protocol_errors() {
+        let kind = |input: &[u8]| {
+            let err = decode_all(input).1.expect("protocol error");
+            match err.downcast_ref::<ProtocolError>() {
+                Some(ProtocolError::TooLarge(_)) => "too_large",
+                Some(ProtocolError::Malformed(_)) => "malformed",
+                None => "other",
+            }
+        };
+        assert_eq!(kind(b"*1\r\n$3\r\nGETXX\r\n"), "malformed");
+        assert_eq!(kind(b"*-5\r\n"), "malformed");
+        assert_eq!(kind(b"*1\r\n!x\r\n"), "malformed");
+        assert_eq!(kind(b"$abc\r\n"), "malformed");
+        assert_eq!(kind(b"$999999999999\r\n"), "too_large");
+        assert_eq!(kind(b"*99999999\r\n"), "too_large");
+        assert_eq!(kind(&[b'*'; MAX_LINE_LEN + 2]), "too_large");
+        assert_eq!(kind(b"*1\r\n*1\r\n*1\r\n*1\r\n*1\r\n*1\r\n*1\r\n*1\r\n*1\r\n:1\r\n"), "too_large");
+    }
+
+    #[test]
+    fn a_decoder_resumes_frames_across_reads() {
+        let frame = b"*3\r\n$3\r\nSET\r\n*2\r\n:1\r\n*0\r\n$-1\r\nPING\r\n*1\r\n+OK\r\n";
+        let (expected, _) = decode_all(frame);
+        let mut decoder = Decoder::default();
+        let mut buf = BytesMut::new();
+        let mut values = Vec::new();
+        for &byte in frame {
+            buf.extend_from_slice(&[byte]);
+            // Nothing is consumed until a frame is complete
+            let before = buf.len();
+            match decoder.decode(&mut buf).unwrap() {
+                Some(value) => values.push(value),
+                None => assert_eq!(buf.len(), before),
             }
         }
-        
-        buf.advance(buf.len() - temp.len());
-        Ok(Some(RespValue::Array(Some(items))))
-    } else {
-        Ok(None)
-    }
-}
-
-fn decode_inline(buf: &mut BytesMut) -> Result<Option<RespValue>> {
-    for i in 0..buf.len() {
-        if buf[i] == b'\n' {
-            let line = buf.split_to(i).to_vec();
-            buf.advance(1); // skip \n
-            let s = String::from_utf8_lossy(&line).trim().to_string();
-            if s.is_empty() { return Ok(None); }
-            
-            // Convert inline to Array for parser
-            let parts: Vec<RespValue> = s.split_whitespace()
-                .map(|p| RespValue::BulkString(Some(p.as_bytes().to_vec())))
-                .collect();
-            return Ok(Some(RespValue::Array(Some(parts))));
+        assert!(buf.is_empty());
+        assert_eq!(values, expected);
+        assert_eq!(values.len(), 3);
+        assert_eq!(values[0], RespValue::Array(Some(vec![
+            RespValue::BulkString(Some(b"SET".to_vec())),
+            RespValue::Array(Some(vec![RespValue::Integer(1), RespValue::Array(Some(Vec::new()))])),
+            RespValue::BulkString(None),
+        ])));
+
+        // A slow header still fails on its length, and the decoder starts over
+        let mut buf = BytesMut::new();
+        for _ in 0..=MAX_LINE_LEN {
+            buf.extend_from_slice(b"*");
+            if decoder.decode(&mut buf).is_err() {
+                break;
+            }
         }
+        assert!(buf.len() > MAX_LINE_LEN);
+        let mut buf = BytesMut::from(&b":7\r\n"[..]);
+        assert_eq!(decoder.decode(&mut buf).unwrap(), Some(RespValue::Integer(7)));
     }
-    Ok(None)
 }
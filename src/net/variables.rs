@@ -0,0 +1,136 @@
+//! # Session Variables
+//!
+//! `SET @name = value` stores a value in the client's session. In every
+//! parsed command, `@name` outside quotes is then replaced by that value
+//! before parsing, so a variable can stand anywhere a literal can:
+//! `SELECT users LIMIT @batch`, `UPDATE users SET role = @role WHERE id = @id`.
+//!
+//! Values are substituted as literals (strings quoted with [`quote_string`]),
+//! which also makes the command logged to the AOF and sent to replicas
+//! self-contained. A `@name` that is not set, or that is part of a longer
+//! word (`bob@example`), is left as written. Arguments bound in strict mode
+//! are taken verbatim and never substituted.
+
+use std::collections::BTreeMap;
+use crate::core::types::UnifiedValue;
+use crate::net::parser::quote_string;
+
+pub type Variables = BTreeMap<String, UnifiedValue>;
+
+/// Value of an unquoted word in `SET @name = value`: NULL, a boolean, a
+/// number, or else the word as a string.
+pub fn bare_value(word: &str) -> UnifiedValue {
+    if word.eq_ignore_ascii_case("NULL") {
+        UnifiedValue::Null
+    } else if let Ok(b) = word.to_ascii_lowercase().parse::<bool>() {
+        UnifiedValue::Boolean(b)
+    } else if let Ok(i) = word.parse::<i64>() {
+        UnifiedValue::Integer(i)
+    } else if let Some(f) = word.parse::<f64>().ok().filter(|f| f.is_finite()) {
+        UnifiedValue::Float(f)
+    } else {
+        UnifiedValue::String(word.to_string())
+    }
+}
+
+fn literal(value: &UnifiedValue) -> String {
+    match value {
+        UnifiedValue::String(s) => quote_string(s),
+        other => other.to_string(),
+    }
+}
+
+/// Characters that continue a variable name.
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Characters that may precede `@` inside an unquoted key.
+fn is_word_char(c: char) -> bool {
+    is_name_char(c) || matches!(c, ':' | '-' | '.' | '+' | '@' | '$' | '*')
+}
+
+/// Replaces the set variables in `input`. The target of a `SET @name = ...`
+/// is kept, so one variable can be assigned from another.
+pub fn substitute(input: &str, vars: &Variables) -> String {
+    if vars.is_empty() || !input.contains('@') {
+        return input.to_string();
+    }
+    let target = assignment_target(input);
+    let mut out = String::with_capacity(input.len());
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    let mut prev: Option<char> = None;
+    let mut pos = 0;
+    while let Some(c) = input[pos..].chars().next() {
+        pos += c.len_utf8();
+        if let Some(q) = quote {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == q {
+                quote = None;
+            }
+        } else if c == '"' || c == '\'' {
+            quote = Some(c);
+        } else if c == '@' && !prev.is_some_and(is_word_char) && Some(pos - 1) != target {
+            let len = input[pos..].find(|c: char| !is_name_char(c)).unwrap_or(input.len() - pos);
+            if let Some(value) = vars.get(&input[pos..pos + len]) {
+                out.push_str(&literal(value));
+                prev = input[pos..pos + len].chars().last();
+                pos += len;
+                continue;
+            }
+        }
+        out.push(c);
+        prev = Some(c);
+    }
+    out
+}
+
+/// Byte offset of the `@` in a leading `SET @name`.
+fn assignment_target(input: &str) -> Option<usize> {
+    let trimmed = input.trim_start();
+    let head = trimmed.get(..3)?;
+    let rest = trimmed[3..].trim_start();
+    if !head.eq_ignore_ascii_case("SET") || rest.len() == trimmed.len() - 3 || !rest.starts_with('@') {
+        return None;
+    }
+    Some(input.len() - rest.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars() -> Variables {
+        Variables::from([
+            ("batch".to_string(), UnifiedValue::Integer(100)),
+            ("role".to_string(), UnifiedValue::String("a \"b\"".to_string())),
+            ("none".to_string(), UnifiedValue::Null),
+        ])
+    }
+
+    #[test]
+    fn replaces_set_variables_outside_quotes() {
+        assert_eq!(substitute("SELECT users LIMIT @batch", &vars()), "SELECT users LIMIT 100");
+        assert_eq!(
+            substitute("UPDATE users SET role = @role WHERE id=@batch", &vars()),
+            r#"UPDATE users SET role = "a \"b\"" WHERE id=100"#
+        );
+        assert_eq!(substitute("INSERT t @none '@batch' \"x\\\"@batch\"", &vars()), "INSERT t NULL '@batch' \"x\\\"@batch\"");
+        // Unset variables, words with an @ inside and the assignment target stay
+        assert_eq!(substitute("GET bob@batch @nope @batchy", &vars()), "GET bob@batch @nope @batchy");
+        assert_eq!(substitute("set  @batch = @batch", &vars()), "set  @batch = 100");
+    }
+
+    #[test]
+    fn bare_values_are_typed() {
+        assert_eq!(bare_value("42"), UnifiedValue::Integer(42));
+        assert_eq!(bare_value("-1.5"), UnifiedValue::Float(-1.5));
+        assert_eq!(bare_value("TRUE"), UnifiedValue::Boolean(true));
+        assert_eq!(bare_value("null"), UnifiedValue::Null);
+        assert_eq!(bare_value("inf"), UnifiedValue::String("inf".to_string()));
+    }
+}
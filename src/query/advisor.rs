@@ -0,0 +1,180 @@
+//! # Index Advisor
+//!
+//! Remembers which columns full-scan queries filtered on, and turns them into
+//! `CREATE INDEX` suggestions.
+//!
+//! Every `WHERE` that the planner could only answer with a full scan records
+//! the columns of its indexable conditions (`=`, `>`, `>=`, `<`, `<=`) that
+//! have no index yet. A suggestion's estimated benefit is the number of row
+//! reads those scans cost at the table's current size: scans x rows.
+//!
+//! Only a counter per `(table, column)` is kept, capped at `MAX_TRACKED`
+//! pairs; columns that gain an index are left out of the suggestions.
+
+use std::fmt;
+use dashmap::DashMap;
+use super::{Filter, Operator};
+use super::planner::IndexCatalog;
+
+const MAX_TRACKED: usize = 1024;
+
+#[derive(Debug, Default)]
+pub struct IndexAdvisor {
+    /// (table, column or JSON path) -> full scans that filtered on it
+    scans: DashMap<(String, String), u64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Suggestion {
+    pub table: String,
+    pub column: String,
+    pub scans: u64,
+    pub rows: usize,
+}
+
+impl Suggestion {
+    /// Row reads the observed scans cost at the current table size.
+    pub fn benefit(&self) -> u64 {
+        self.scans.saturating_mul(self.rows as u64)
+    }
+
+    pub fn index_name(&self) -> String {
+        let column: Vec<&str> = self.column.split(|c: char| !c.is_alphanumeric() && c != '_')
+            .filter(|part| !part.is_empty())
+            .collect();
+        format!("idx_{}_{}", self.table, column.join("_"))
+    }
+}
+
+impl fmt::Display for Suggestion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "CREATE INDEX {} ON {}({}) -- {} full scans of {} rows, est. benefit {} row reads",
+            self.index_name(), self.table, self.column, self.scans, self.rows, self.benefit()
+        )
+    }
+}
+
+impl IndexAdvisor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a full scan of `table` under `filter`. `known` tells whether a
+    /// column (the part before any `->`) exists in the table.
+    pub fn record_full_scan(&self, catalog: &dyn IndexCatalog, table: &str, filter: &Filter, known: impl Fn(&str) -> bool) {
+        let mut columns = Vec::new();
+        indexable_columns(filter, &mut columns);
+        columns.sort();
+        columns.dedup();
+        for column in columns {
+            let base = column.split("->").next().unwrap_or(&column);
+            if !known(base) || catalog.has_hash_index(table, &column) {
+                continue;
+            }
+            let key = (table.to_string(), column);
+            if let Some(mut count) = self.scans.get_mut(&key) {
+                *count += 1;
+            } else if self.scans.len() < MAX_TRACKED {
+                self.scans.insert(key, 1);
+            }
+        }
+    }
+
+    /// Suggestions for columns still lacking an index, highest benefit first.
+    pub fn suggest(&self, catalog: &dyn IndexCatalog) -> Vec<Suggestion> {
+        let mut suggestions: Vec<Suggestion> = self.scans.iter()
+            .filter(|e| !catalog.has_hash_index(&e.key().0, &e.key().1))
+            .map(|e| Suggestion {
+                table: e.key().0.clone(),
+                column: e.key().1.clone(),
+                scans: *e.value(),
+                rows: catalog.row_count(&e.key().0),
+            })
+            .filter(|s| s.rows > 0)
+            .collect();
+        suggestions.sort_by(|a, b| b.benefit().cmp(&a.benefit())
+            .then_with(|| a.table.cmp(&b.table))
+            .then_with(|| a.column.cmp(&b.column)));
+        suggestions
+    }
+
+    /// Forgets a dropped column, JSON paths into it included.
+    pub fn forget_column(&self, table: &str, column: &str) {
+        self.scans.retain(|(t, c), _| t != table || c.split("->").next() != Some(column));
+    }
+
+    pub fn clear(&self) {
+        self.scans.clear();
+    }
+}
+
+fn indexable_columns(filter: &Filter, out: &mut Vec<String>) {
+    match filter {
+        Filter::Condition(col, op, _) => {
+            if matches!(op, Operator::Eq | Operator::Gt | Operator::Gte | Operator::Lt | Operator::Lte | Operator::Between) {
+                out.push(col.clone());
+            }
+        }
+        Filter::And(left, right) | Filter::Or(left, right) => {
+            indexable_columns(left, out);
+            indexable_columns(right, out);
+        }
+        Filter::Not(_) | Filter::Computed(..) => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::planner::AccessPath;
+
+    struct MockCatalog;
+
+    impl IndexCatalog for MockCatalog {
+        fn row_count(&self, table: &str) -> usize { if table == "users" { 100 } else { 0 } }
+        fn has_hash_index(&self, _table: &str, column: &str) -> bool { column == "id" }
+        fn has_range_index(&self, _table: &str, column: &str) -> bool { column == "id" }
+        fn candidate_count(&self, _table: &str, _access: &AccessPath) -> usize { 100 }
+    }
+
+    fn cond(col: &str, op: Operator) -> Filter {
+        Filter::Condition(col.to_string(), op, "1".to_string())
+    }
+
+    #[test]
+    fn ranks_unindexed_filter_columns_by_benefit() {
+        let advisor = IndexAdvisor::new();
+        let known = |c: &str| c != "ghost";
+        let age_or_name = Filter::Or(Box::new(cond("age", Operator::Gt)), Box::new(cond("name", Operator::Eq)));
+        for _ in 0..3 {
+            advisor.record_full_scan(&MockCatalog, "users", &cond("age", Operator::Eq), known);
+        }
+        advisor.record_full_scan(&MockCatalog, "users", &age_or_name, known);
+        // Indexed, non-indexable, unknown and empty-table columns are not suggested
+        advisor.record_full_scan(&MockCatalog, "users", &cond("id", Operator::Eq), known);
+        advisor.record_full_scan(&MockCatalog, "users", &cond("bio", Operator::Like), known);
+        advisor.record_full_scan(&MockCatalog, "users", &cond("ghost", Operator::Eq), known);
+        advisor.record_full_scan(&MockCatalog, "empty", &cond("age", Operator::Eq), known);
+
+        let suggestions = advisor.suggest(&MockCatalog);
+        let columns: Vec<(&str, u64)> = suggestions.iter().map(|s| (s.column.as_str(), s.benefit())).collect();
+        assert_eq!(columns, [("age", 400), ("name", 100)]);
+        assert_eq!(
+            suggestions[0].to_string(),
+            "CREATE INDEX idx_users_age ON users(age) -- 4 full scans of 100 rows, est. benefit 400 row reads"
+        );
+
+        advisor.forget_column("users", "age");
+        assert_eq!(advisor.suggest(&MockCatalog).len(), 1);
+        advisor.clear();
+        assert!(advisor.suggest(&MockCatalog).is_empty());
+    }
+
+    #[test]
+    fn json_path_index_names() {
+        let s = Suggestion { table: "users".into(), column: "profile->>city".into(), scans: 1, rows: 1 };
+        assert_eq!(s.index_name(), "idx_users_profile_city");
+    }
+}
@@ -0,0 +1,141 @@
+//! Scalar expression evaluation, shared by SELECT lists, WHERE and HAVING.
+//!
+//! Expressions never fail: arithmetic on non-numbers, overflow, division by
+//! zero and functions given a value of the wrong type all yield NULL.
+
+use super::{ArithOp, Expr, ScalarFn};
+use crate::core::datetime;
+use crate::core::types::UnifiedValue;
+
+/// Evaluates `expr`, reading columns through `resolve`.
+pub fn eval(expr: &Expr, resolve: &dyn Fn(&str) -> UnifiedValue) -> UnifiedValue {
+    match expr {
+        Expr::Column(col) => resolve(col),
+        Expr::Literal(v) => v.clone(),
+        Expr::Binary(l, op, r) => arith(eval(l, resolve), *op, eval(r, resolve)),
+        Expr::Call(func, arg) => call(*func, eval(arg, resolve)),
+        Expr::DateAdd(at, amount, unit) => match (timestamp(&eval(at, resolve)), eval(amount, resolve)) {
+            (Some(at), UnifiedValue::Integer(n)) => n.checked_mul(unit.seconds())
+                .and_then(|secs| at.checked_add(secs))
+                .map_or(UnifiedValue::Null, UnifiedValue::DateTime),
+            _ => UnifiedValue::Null,
+        },
+        // Read like a column: HAVING resolves `SUM(x)` to the group's sum
+        Expr::Aggregate(agg) => resolve(&agg.to_string()),
+        Expr::DateDiff(to, from, unit) => match (timestamp(&eval(to, resolve)), timestamp(&eval(from, resolve))) {
+            (Some(to), Some(from)) => to.checked_sub(from).map_or(UnifiedValue::Null, |secs| UnifiedValue::Integer(secs / unit.seconds())),
+            _ => UnifiedValue::Null,
+        },
+    }
+}
+
+/// Seconds of a datetime, of an integer taken as a Unix timestamp (`NOW()`
+/// is replaced by one), or of an ISO-8601 string.
+fn timestamp(value: &UnifiedValue) -> Option<i64> {
+    match value {
+        UnifiedValue::DateTime(ts) | UnifiedValue::Integer(ts) => Some(*ts),
+        UnifiedValue::String(s) => datetime::parse(s),
+        _ => None,
+    }
+}
+
+/// Integer arithmetic stays integral (overflow and division by zero give NULL);
+/// any Float operand promotes the result to Float.
+fn arith(l: UnifiedValue, op: ArithOp, r: UnifiedValue) -> UnifiedValue {
+    if let (UnifiedValue::Integer(a), UnifiedValue::Integer(b)) = (&l, &r) {
+        let res = match op {
+            ArithOp::Add => a.checked_add(*b),
+            ArithOp::Sub => a.checked_sub(*b),
+            ArithOp::Mul => a.checked_mul(*b),
+            ArithOp::Div => a.checked_div(*b),
+        };
+        return res.map(UnifiedValue::Integer).unwrap_or(UnifiedValue::Null);
+    }
+    let as_f64 = |v: &UnifiedValue| match v {
+        UnifiedValue::Integer(i) => Some(*i as f64),
+        UnifiedValue::Float(f) => Some(*f),
+        _ => None,
+    };
+    match (as_f64(&l), as_f64(&r)) {
+        (Some(a), Some(b)) => {
+            let res = match op {
+                ArithOp::Add => a + b,
+                ArithOp::Sub => a - b,
+                ArithOp::Mul => a * b,
+                ArithOp::Div => a / b,
+            };
+            if res.is_finite() { UnifiedValue::Float(res) } else { UnifiedValue::Null }
+        }
+        _ => UnifiedValue::Null,
+    }
+}
+
+fn call(func: ScalarFn, arg: UnifiedValue) -> UnifiedValue {
+    match (func, arg) {
+        (ScalarFn::Lower, UnifiedValue::String(s)) => UnifiedValue::String(s.to_lowercase()),
+        (ScalarFn::Upper, UnifiedValue::String(s)) => UnifiedValue::String(s.to_uppercase()),
+        (ScalarFn::Trim, UnifiedValue::String(s)) => UnifiedValue::String(s.trim().to_string()),
+        (ScalarFn::Length, UnifiedValue::String(s) | UnifiedValue::Blob(s)) => UnifiedValue::Integer(s.chars().count() as i64),
+        (ScalarFn::Length, UnifiedValue::Array(items)) => UnifiedValue::Integer(items.len() as i64),
+        (ScalarFn::Length, UnifiedValue::Vector(v)) => UnifiedValue::Integer(v.len() as i64),
+        (ScalarFn::Abs, UnifiedValue::Integer(i)) => i.checked_abs().map_or(UnifiedValue::Null, UnifiedValue::Integer),
+        (ScalarFn::Abs, UnifiedValue::Float(f)) => UnifiedValue::Float(f.abs()),
+        (ScalarFn::Round, UnifiedValue::Integer(i)) => UnifiedValue::Integer(i),
+        (ScalarFn::Round, UnifiedValue::Float(f)) => UnifiedValue::Float(f.round()),
+        _ => UnifiedValue::Null,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::datetime::TimeUnit;
+
+    fn column(name: &str) -> Box<Expr> {
+        Box::new(Expr::Column(name.into()))
+    }
+
+    fn row(col: &str) -> UnifiedValue {
+        match col {
+            "name" => UnifiedValue::String("  Bob ".into()),
+            "price" => UnifiedValue::Integer(90),
+            "delta" => UnifiedValue::Float(-2.5),
+            "created" => UnifiedValue::DateTime(1_704_067_200),
+            _ => UnifiedValue::Null,
+        }
+    }
+
+    #[test]
+    fn arithmetic() {
+        let scaled = Expr::Binary(column("price"), ArithOp::Mul, Box::new(Expr::Literal(UnifiedValue::Float(1.2))));
+        assert_eq!(eval(&scaled, &row), UnifiedValue::Float(108.0));
+        let halved = Expr::Binary(column("price"), ArithOp::Div, Box::new(Expr::Literal(UnifiedValue::Integer(2))));
+        assert_eq!(eval(&halved, &row), UnifiedValue::Integer(45));
+        let by_zero = Expr::Binary(column("price"), ArithOp::Div, Box::new(Expr::Literal(UnifiedValue::Integer(0))));
+        assert_eq!(eval(&by_zero, &row), UnifiedValue::Null);
+        assert_eq!(eval(&Expr::Binary(column("name"), ArithOp::Add, column("price")), &row), UnifiedValue::Null);
+    }
+
+    #[test]
+    fn functions() {
+        let trimmed = Expr::Call(ScalarFn::Trim, column("name"));
+        assert_eq!(eval(&Expr::Call(ScalarFn::Lower, Box::new(trimmed.clone())), &row), UnifiedValue::String("bob".into()));
+        assert_eq!(eval(&Expr::Call(ScalarFn::Upper, column("name")), &row), UnifiedValue::String("  BOB ".into()));
+        assert_eq!(eval(&Expr::Call(ScalarFn::Length, Box::new(trimmed)), &row), UnifiedValue::Integer(3));
+        assert_eq!(eval(&Expr::Call(ScalarFn::Abs, column("delta")), &row), UnifiedValue::Float(2.5));
+        assert_eq!(eval(&Expr::Call(ScalarFn::Round, column("delta")), &row), UnifiedValue::Float(-3.0));
+        assert_eq!(eval(&Expr::Call(ScalarFn::Abs, column("name")), &row), UnifiedValue::Null);
+        assert_eq!(eval(&Expr::Call(ScalarFn::Lower, column("missing")), &row), UnifiedValue::Null);
+    }
+
+    #[test]
+    fn dates() {
+        let week_later = Expr::DateAdd(column("created"), Box::new(Expr::Literal(UnifiedValue::Integer(7))), TimeUnit::Day);
+        assert_eq!(eval(&week_later, &row), UnifiedValue::DateTime(1_704_672_000));
+        let back = Expr::DateDiff(Box::new(week_later), column("created"), TimeUnit::Hour);
+        assert_eq!(eval(&back, &row), UnifiedValue::Integer(168));
+        let from_timestamp = Expr::DateDiff(Box::new(Expr::Literal(UnifiedValue::Integer(1_704_067_199))), column("created"), TimeUnit::Minute);
+        assert_eq!(eval(&from_timestamp, &row), UnifiedValue::Integer(0));
+        assert_eq!(eval(&Expr::DateAdd(column("name"), column("price"), TimeUnit::Day), &row), UnifiedValue::Null);
+    }
+}
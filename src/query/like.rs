@@ -0,0 +1,89 @@
+//! SQL `LIKE` / `ILIKE` patterns: `%` matches any run of characters and `_`
+//! exactly one; everything else, regex metacharacters included, is literal.
+//!
+//! Patterns are compiled to a regex once and cached per thread, so a query
+//! testing every row of a table compiles its pattern a single time.
+
+use regex::{Regex, RegexBuilder};
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+/// Compiled patterns kept per thread before the cache starts over
+const CACHE_SIZE: usize = 64;
+
+thread_local! {
+    static COMPILED: RefCell<HashMap<(String, bool), Regex>> = RefCell::new(HashMap::new());
+}
+
+/// Literal text every match starts with: the pattern up to its first
+/// wildcard. Empty when the pattern starts with one.
+pub fn literal_prefix(pattern: &str) -> &str {
+    let end = pattern.find(['%', '_']).unwrap_or(pattern.len());
+    &pattern[..end]
+}
+
+fn compile(pattern: &str, case_insensitive: bool) -> Regex {
+    let mut source = String::from("^");
+    let mut literal = [0u8; 4];
+    for c in pattern.chars() {
+        match c {
+            '%' => source.push_str(".*"),
+            '_' => source.push('.'),
+            _ => source.push_str(&regex::escape(c.encode_utf8(&mut literal))),
+        }
+    }
+    source.push('$');
+    RegexBuilder::new(&source)
+        .case_insensitive(case_insensitive)
+        .dot_matches_new_line(true)
+        .build()
+        .expect("escaped LIKE pattern is a valid regex")
+}
+
+/// Whether `text` matches `pattern`, ignoring case for `ILIKE`.
+pub fn is_match(pattern: &str, text: &str, case_insensitive: bool) -> bool {
+    COMPILED.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        let key = (pattern.to_string(), case_insensitive);
+        if !cache.contains_key(&key) {
+            if cache.len() >= CACHE_SIZE {
+                cache.clear();
+            }
+            cache.insert(key.clone(), compile(pattern, case_insensitive));
+        }
+        cache[&key].is_match(text)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcards_and_literals() {
+        assert!(is_match("abc%", "abcdef", false));
+        assert!(is_match("a_c", "abc", false));
+        assert!(!is_match("a_c", "abbc", false));
+        assert!(is_match("%", "", false));
+        assert!(is_match("%\n%", "a\nb", false));
+        // Regex metacharacters match themselves only
+        assert!(is_match("v1.0%", "v1.0.2", false));
+        assert!(!is_match("v1.0%", "v1x0", false));
+        assert!(is_match("(a+b)*", "(a+b)*", false));
+    }
+
+    #[test]
+    fn case_insensitive() {
+        assert!(!is_match("Ana%", "ANAbel", false));
+        assert!(is_match("Ana%", "ANAbel", true));
+        assert!(is_match("é_", "És", true));
+    }
+
+    #[test]
+    fn prefixes() {
+        assert_eq!(literal_prefix("user:%"), "user:");
+        assert_eq!(literal_prefix("a_c%"), "a");
+        assert_eq!(literal_prefix("%x"), "");
+        assert_eq!(literal_prefix("exact"), "exact");
+    }
+}
@@ -1,3 +1,18 @@
+pub mod planner;
+pub mod advisor;
+pub mod like;
+pub mod eval;
+pub mod wire;
+
+use std::fmt;
+use crate::core::output::OutputFormat;
+use crate::net::binding::ArgBinding;
+use crate::core::types::UnifiedValue;
+use crate::core::structured::FkAction;
+use crate::core::bitmap::BitOp;
+use crate::core::geo::GeoUnit;
+use crate::core::hnsw::Metric;
+use crate::core::datetime::TimeUnit;
 
 
 #[derive(Debug, PartialEq, Clone)]
@@ -9,7 +24,11 @@ pub enum Operator {
     Gte,
     Lte,
     Like,
+    ILike,
     In,
+    Between, // value packs both bounds, see `join_value_list`
+    IsNull,    // value unused
+    IsNotNull, // value unused
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -17,19 +36,265 @@ pub enum Filter {
     Condition(String, Operator, String), // col, op, val
     And(Box<Filter>, Box<Filter>),
     Or(Box<Filter>, Box<Filter>),
+    Not(Box<Filter>),
+    /// Like `Condition`, comparing a computed value (`price * 1.2 > 100`)
+    Computed(Expr, Operator, String),
+}
+
+impl Filter {
+    /// The filter with every column renamed by `rename`.
+    pub fn map_columns(self, rename: &dyn Fn(String) -> String) -> Filter {
+        match self {
+            Filter::Condition(col, op, val) => Filter::Condition(rename(col), op, val),
+            Filter::And(l, r) => Filter::And(Box::new(l.map_columns(rename)), Box::new(r.map_columns(rename))),
+            Filter::Or(l, r) => Filter::Or(Box::new(l.map_columns(rename)), Box::new(r.map_columns(rename))),
+            Filter::Not(inner) => Filter::Not(Box::new(inner.map_columns(rename))),
+            Filter::Computed(expr, op, val) => Filter::Computed(expr.map_columns(rename), op, val),
+        }
+    }
+
+    /// Aggregates the filter refers to, which only HAVING can evaluate.
+    pub fn aggregates(&self) -> Vec<&Selector> {
+        match self {
+            Filter::Condition(..) => Vec::new(),
+            Filter::And(l, r) | Filter::Or(l, r) => {
+                let mut aggs = l.aggregates();
+                aggs.extend(r.aggregates());
+                aggs
+            }
+            Filter::Not(inner) => inner.aggregates(),
+            Filter::Computed(expr, ..) => expr.aggregates(),
+        }
+    }
+}
+
+/// Packs an `IN (...)` list into a single condition value: items are joined
+/// by `,`, with `,` and `\` inside an item escaped by a backslash.
+pub fn join_value_list(items: &[String]) -> String {
+    items.iter()
+        .map(|item| item.replace('\\', "\\\\").replace(',', "\\,"))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Inverse of [`join_value_list`].
+pub fn split_value_list(value: &str) -> Vec<String> {
+    let mut items = vec![String::new()];
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => items.last_mut().unwrap().extend(chars.next()),
+            ',' => items.push(String::new()),
+            _ => items.last_mut().unwrap().push(c),
+        }
+    }
+    items
+}
+
+/// Low and high bound of a `BETWEEN` condition value.
+pub fn between_bounds(value: &str) -> Option<(String, String)> {
+    let mut bounds = split_value_list(value).into_iter();
+    match (bounds.next(), bounds.next(), bounds.next()) {
+        (Some(low), Some(high), None) => Some((low, high)),
+        _ => None,
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Selector {
     All,
-    Columns(Vec<String>), // specific columns
+    Columns(Vec<Projection>), // specific columns or expressions
+    Distinct(Vec<Projection>), // same, duplicate rows removed
     Count,
+    CountDistinct(String), // column name
     Sum(String),  // column name
     Avg(String),
     Max(String),
     Min(String),
 }
 
+impl Selector {
+    /// The selector with the column of an aggregate renamed by `rename`.
+    pub fn map_columns(self, rename: &dyn Fn(String) -> String) -> Selector {
+        match self {
+            Selector::CountDistinct(col) => Selector::CountDistinct(rename(col)),
+            Selector::Sum(col) => Selector::Sum(rename(col)),
+            Selector::Avg(col) => Selector::Avg(rename(col)),
+            Selector::Max(col) => Selector::Max(rename(col)),
+            Selector::Min(col) => Selector::Min(rename(col)),
+            Selector::All | Selector::Columns(_) | Selector::Distinct(_) | Selector::Count => self,
+        }
+    }
+}
+
+/// Column name of a selector's result, e.g. `SUM(age)`; also how HAVING
+/// refers to an aggregate.
+impl fmt::Display for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Selector::Count => write!(f, "COUNT(*)"),
+            Selector::CountDistinct(col) => write!(f, "COUNT(DISTINCT {})", col),
+            Selector::Sum(col) => write!(f, "SUM({})", col),
+            Selector::Avg(col) => write!(f, "AVG({})", col),
+            Selector::Max(col) => write!(f, "MAX({})", col),
+            Selector::Min(col) => write!(f, "MIN({})", col),
+            Selector::All => write!(f, "*"),
+            Selector::Columns(projs) | Selector::Distinct(projs) => {
+                write!(f, "{}", projs.iter().map(|p| p.name()).collect::<Vec<_>>().join(", "))
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ArithOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+/// Built-in scalar function of one argument.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ScalarFn {
+    Lower,
+    Upper,
+    Trim,
+    Length,
+    Abs,
+    Round,
+}
+
+impl std::str::FromStr for ScalarFn {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "LOWER" => Ok(ScalarFn::Lower),
+            "UPPER" => Ok(ScalarFn::Upper),
+            "TRIM" => Ok(ScalarFn::Trim),
+            "LENGTH" => Ok(ScalarFn::Length),
+            "ABS" => Ok(ScalarFn::Abs),
+            "ROUND" => Ok(ScalarFn::Round),
+            _ => Err(format!("Unknown function '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for ScalarFn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ScalarFn::Lower => "LOWER",
+            ScalarFn::Upper => "UPPER",
+            ScalarFn::Trim => "TRIM",
+            ScalarFn::Length => "LENGTH",
+            ScalarFn::Abs => "ABS",
+            ScalarFn::Round => "ROUND",
+        })
+    }
+}
+
+/// Scalar expression in a SELECT list or a WHERE condition.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Expr {
+    Column(String), // column, table.column or json path
+    Literal(UnifiedValue),
+    Binary(Box<Expr>, ArithOp, Box<Expr>),
+    Call(ScalarFn, Box<Expr>),
+    /// `DATE_ADD(at, amount, unit)`
+    DateAdd(Box<Expr>, Box<Expr>, TimeUnit),
+    /// `DATE_DIFF(to, from, unit)`: whole units from `from` to `to`
+    DateDiff(Box<Expr>, Box<Expr>, TimeUnit),
+    /// `COUNT(*)`, `SUM(col)`, ... of the current group; HAVING only
+    Aggregate(Box<Selector>),
+}
+
+impl Expr {
+    /// Columns referenced by the expression, in order of appearance.
+    pub fn columns(&self) -> Vec<&str> {
+        match self {
+            Expr::Column(c) => vec![c.as_str()],
+            Expr::Literal(_) => Vec::new(),
+            Expr::Binary(l, _, r) | Expr::DateAdd(l, r, _) | Expr::DateDiff(l, r, _) => {
+                let mut cols = l.columns();
+                cols.extend(r.columns());
+                cols
+            }
+            Expr::Call(_, arg) => arg.columns(),
+            Expr::Aggregate(_) => Vec::new(),
+        }
+    }
+
+    /// The expression with every column, aggregated ones included, renamed by `rename`.
+    pub fn map_columns(self, rename: &dyn Fn(String) -> String) -> Expr {
+        let map = |e: Box<Expr>| Box::new(e.map_columns(rename));
+        match self {
+            Expr::Column(c) => Expr::Column(rename(c)),
+            Expr::Literal(_) => self,
+            Expr::Binary(l, op, r) => Expr::Binary(map(l), op, map(r)),
+            Expr::Call(func, arg) => Expr::Call(func, map(arg)),
+            Expr::DateAdd(at, amount, unit) => Expr::DateAdd(map(at), map(amount), unit),
+            Expr::DateDiff(to, from, unit) => Expr::DateDiff(map(to), map(from), unit),
+            Expr::Aggregate(agg) => Expr::Aggregate(Box::new(agg.map_columns(rename))),
+        }
+    }
+
+    /// Aggregates the expression refers to.
+    pub fn aggregates(&self) -> Vec<&Selector> {
+        match self {
+            Expr::Column(_) | Expr::Literal(_) => Vec::new(),
+            Expr::Binary(l, _, r) | Expr::DateAdd(l, r, _) | Expr::DateDiff(l, r, _) => {
+                let mut aggs = l.aggregates();
+                aggs.extend(r.aggregates());
+                aggs
+            }
+            Expr::Call(_, arg) => arg.aggregates(),
+            Expr::Aggregate(agg) => vec![agg.as_ref()],
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Column(c) => write!(f, "{}", c),
+            Expr::Literal(v) => write!(f, "{}", v),
+            Expr::Binary(l, op, r) => {
+                let op = match op {
+                    ArithOp::Add => "+",
+                    ArithOp::Sub => "-",
+                    ArithOp::Mul => "*",
+                    ArithOp::Div => "/",
+                };
+                // Nested operations keep their grouping
+                let side = |e: &Expr| match e {
+                    Expr::Binary(..) => format!("({})", e),
+                    _ => e.to_string(),
+                };
+                write!(f, "{} {} {}", side(l), op, side(r))
+            }
+            Expr::Call(func, arg) => write!(f, "{}({})", func, arg),
+            Expr::DateAdd(at, amount, unit) => write!(f, "DATE_ADD({}, {}, {})", at, amount, unit),
+            Expr::DateDiff(to, from, unit) => write!(f, "DATE_DIFF({}, {}, {})", to, from, unit),
+            Expr::Aggregate(agg) => write!(f, "{}", agg),
+        }
+    }
+}
+
+/// One item of a SELECT list: `expr [AS alias]`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Projection {
+    pub expr: Expr,
+    pub alias: Option<String>,
+}
+
+impl Projection {
+    /// Result column name: the alias, or the expression as written.
+    pub fn name(&self) -> String {
+        self.alias.clone().unwrap_or_else(|| self.expr.to_string())
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum JoinType {
     Inner,
@@ -41,32 +306,150 @@ pub enum JoinType {
 pub struct JoinClause {
     pub join_type: JoinType,
     pub table: String,
+    pub alias: Option<String>,
     pub on_left: String,  // table1.col
     pub on_right: String, // table2.col
 }
 
+impl JoinClause {
+    /// Name the joined table's columns are qualified with: its alias, or the table name.
+    pub fn qualifier(&self) -> &str {
+        self.alias.as_deref().unwrap_or(&self.table)
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum AlterOp {
     Add(String, String), // name, type
     Drop(String),        // name
 }
 
+/// Column in `CREATE TABLE`:
+/// `name:type[:pk][:fk(table.col)[:cascade|:restrict|:setnull]][:notnull][:unique][:default(value)]`.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct ColumnDef {
+    pub name: String,
+    pub data_type: String,
+    pub primary_key: bool,
+    pub references: Option<(String, String)>, // (table, column)
+    pub fk_action: FkAction,
+    pub not_null: bool,
+    pub unique: bool,
+    pub default: Option<String>,
+}
+
+/// ZADD options: `NX`/`XX` restrict adding vs updating, `GT`/`LT` only move a
+/// score in one direction, `CH` counts changed members in the reply.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct ZAddFlags {
+    pub nx: bool,
+    pub xx: bool,
+    pub gt: bool,
+    pub lt: bool,
+    pub ch: bool,
+}
+
+impl ZAddFlags {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.nx && self.xx {
+            return Err("XX and NX options at the same time are not compatible".to_string());
+        }
+        if (self.gt && self.lt) || (self.nx && (self.gt || self.lt)) {
+            return Err("GT, LT, and/or NX options at the same time are not compatible".to_string());
+        }
+        Ok(())
+    }
+
+    /// Sets the flag named `word`; false if it is not a ZADD flag.
+    pub fn set(&mut self, word: &str) -> bool {
+        match word.to_ascii_uppercase().as_str() {
+            "NX" => self.nx = true,
+            "XX" => self.xx = true,
+            "GT" => self.gt = true,
+            "LT" => self.lt = true,
+            "CH" => self.ch = true,
+            _ => return false,
+        }
+        true
+    }
+}
+
+/// Center of a GEOSEARCH.
+#[derive(Debug, PartialEq, Clone)]
+pub enum GeoOrigin {
+    /// The position of a member of the searched key
+    Member(String),
+    LonLat(f64, f64),
+}
+
+/// `CLUSTER SETSLOT` transition of a slot during resharding.
+#[derive(Debug, PartialEq, Clone)]
+pub enum SlotState {
+    /// Being moved from this node to the given one
+    Migrating(String),
+    /// Being moved to this node from the given one
+    Importing(String),
+    /// Neither: the migration is over or cancelled
+    Stable,
+    /// Now served by the given node
+    Node(String),
+}
+
+/// Expiry change requested by GETEX.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ExpiryUpdate {
+    After(u64), // EX/PX, in milliseconds
+    At(u64),    // EXAT/PXAT, Unix time in milliseconds
+    Persist,
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum Command {
     // Replication
-    ReplicaOf { host: String, port: String }, // "NO" "ONE" turns off replica
+    ReplicaOf { host: String, port: String }, // "NO" "ONE" turns off replica, "PROMOTE" also flips its peers
+    Failover { timeout_ms: u64 },
+    ReplCheck { replica: Option<String>, timeout_ms: u64 },
     Psync, // Subscribe to replication stream
+    Wait { replicas: usize, timeout_ms: u64 },
 
     // Observability
     Info,
+    Metrics,
+    MetricsReset,
+    MemoryUsage { key: String },
+    MemoryStats,
+    ConfigGet { pattern: String },
+    ConfigSet { param: String, value: String },
     ClusterInfo,
     ClusterSlots,
     ClusterMeet { host: String, port: u16 },
     ClusterAddSlots { slots: Vec<u16> },
+    ClusterDelSlots { slots: Vec<u16> },
+    ClusterForget { node: String },
+    ClusterFailover,
+    ClusterSetSlot { slot: u16, state: SlotState },
+    /// MIGRATE host port SLOT n [COUNT n] [AUTH password | AUTH2 user password]
+    Migrate { host: String, port: u16, slot: u16, count: Option<usize>, auth: Option<(String, String)> },
+    /// The next command may use a slot being imported here
+    Asking,
     // Flexible (KV)
     Set { key: String, value: String }, // Simplification: value is stringified JSON
     Get { key: String },
+    GetRange { key: String, start: i64, end: i64 },
+    GetEx { key: String, expiry: Option<ExpiryUpdate> },
     Del { keys: Vec<String> },
+
+    // Keyspace
+    Scan { cursor: u64, pattern: Option<String>, count: Option<usize> },
+    Keys { pattern: String },
+    ExportKeys { pattern: Option<String>, path: String },
+    Dump { key: String },
+    /// RESTORE key ttl payload [REPLACE] [ABSTTL]; ttl in ms (a Unix time
+    /// with ABSTTL), 0 for none
+    Restore { key: String, ttl: u64, payload: String, replace: bool, absttl: bool },
+    Type { key: String },
+    Exists { keys: Vec<String> },
+    DbSize,
     
     // Lists
     LPush { key: String, values: Vec<String> },
@@ -85,41 +468,113 @@ pub enum Command {
     SMembers { key: String },
     
     // Sorted Sets (ZSET)
-    ZAdd { key: String, score: f64, member: String },
+    ZAdd { key: String, flags: ZAddFlags, members: Vec<(f64, String)> }, // (score, member) pairs
     ZRange { key: String, start: i64, stop: i64 },
     ZScore { key: String, member: String },
+    ZPop { key: String, count: Option<usize>, max: bool }, // max: ZPOPMAX
+    BZPop { keys: Vec<String>, timeout_ms: u64, max: bool }, // timeout 0 waits up to MAX_WAIT
+
+    // Geospatial (sorted sets scored by geohash)
+    GeoAdd { key: String, flags: ZAddFlags, members: Vec<(f64, f64, String)> }, // (longitude, latitude, member)
+    GeoDist { key: String, member1: String, member2: String, unit: GeoUnit },
+    GeoSearch { key: String, from: GeoOrigin, radius: f64, unit: GeoUnit, desc: bool, count: Option<usize>, with_coord: bool, with_dist: bool },
+
+    // Top-K (heavy hitters)
+    TopKReserve { key: String, k: usize, width: usize, depth: usize },
+    TopKAdd { key: String, items: Vec<String> },
+    TopKIncrBy { key: String, items: Vec<(String, u64)> }, // (item, increment) pairs
+    TopKList { key: String, with_count: bool },
+
+    // Bitmaps
+    SetBit { key: String, offset: u64, value: bool },
+    GetBit { key: String, offset: u64 },
+    BitCount { key: String, range: Option<(i64, i64)>, bits: bool }, // bits: range in bits, not bytes
+    BitOp { op: BitOp, dest: String, sources: Vec<String> },
+
+    // HyperLogLog
+    PfAdd { key: String, elements: Vec<String> },
+    PfCount { keys: Vec<String> }, // several keys: the count of their union
+    PfMerge { dest: String, sources: Vec<String> },
+
+    // Streams
+    XAdd { key: String, id: String, fields: Vec<(String, String)> }, // id: `*`, `<ms>-*` or `<ms>-<seq>`
+    XRange { key: String, start: String, end: String, count: Option<usize> }, // `-` and `+` for the ends
+    XLen { key: String },
+    XRead { count: Option<usize>, block_ms: Option<u64>, keys: Vec<String>, ids: Vec<String> }, // id `$`: entries added from now on
+
+    // Scripting
+    Eval { script: String, keys: Vec<String>, args: Vec<String> },
+    EvalSha { sha: String, keys: Vec<String>, args: Vec<String> },
+    ScriptLoad { script: String },
+    ScriptExists { shas: Vec<String> },
+    ScriptFlush,
 
     // JSON
     JsonGet { key: String, path: Option<String> },
     JsonSet { key: String, path: String, value: String },
     
     // Structured (Relational)
-    CreateTable { name: String, columns: Vec<(String, String, bool, Option<(String, String)>)> }, // name, type, is_pk, references
+    CreateTable { name: String, columns: Vec<ColumnDef> },
     AlterTable { table: String, op: AlterOp },
-    Insert { table: String, values: Vec<String> },
+    Insert { table: String, rows: Vec<Vec<Option<String>>> }, // None is NULL
     Select { 
         table: String, 
+        alias: Option<String>, // FROM table alias
         selector: Selector,
         join: Option<Vec<JoinClause>>, // Support multiple joins potentially
         filter: Option<Filter>,
         group_by: Option<Vec<String>>,
         having: Option<Filter>,
-        order_by: Option<(String, bool)>, // (col, ascending)
+        order_by: Option<Vec<(String, bool)>>, // [(col or json path, ascending)]
         limit: Option<usize>,
         offset: Option<usize>,
     },
-    VectorSearch { table: String, column: String, vector: Vec<f64>, limit: usize },
-    Update { table: String, filter: Option<Filter>, set: (String, String) },
+    Explain { query: Box<Command>, analyze: bool }, // ANALYZE runs the query
+    /// `DECLARE name CURSOR FOR SELECT ...`: keeps the query in the session,
+    /// and each FETCH runs it for the next page
+    Declare { name: String, query: Box<Command> },
+    Fetch { name: String, count: Option<usize> }, // None fetches all remaining rows
+    Close { name: Option<String> }, // None closes every cursor
+    VectorSearch { table: String, column: String, vector: Vec<f64>, limit: usize, metric: Option<Metric>, threshold: Option<f64>, filter: Option<Filter> },
+    Update { table: String, filter: Option<Filter>, set: (String, Option<String>) }, // None is NULL
     Delete { table: String, filter: Option<Filter> },
     
     // System
     Ping,
+    Quit,
+    Reset,
+    ReadOnly,
+    ReadWrite,
+    ObjectHelp,
     Save,
+    BackupVerify { path: String },
+    AofStats,
+    AofCat { from: usize, count: usize }, // from: 1-based line
     CreateIndex { index_name: String, table: String, column: String },
+    CreateVectorIndex { index_name: String, table: String, column: String, dims: usize, metric: Metric },
+    DropIndex { index_name: String, table: String },
+    ShowIndexes { table: Option<String> },
+    ShowTables,
+    ShowDatabases,
+    DropDatabase { name: String },
+    Describe { table: String },
+    ShowCreateTable { table: String },
+    Reindex { table: String, column: Option<String> },
+    CheckTable { table: String },
+    ExportTable { table: String },
+    /// IMPORT TABLE name payload [REPLACE]
+    ImportTable { table: String, payload: String, replace: bool },
+    AdvisorSuggest,
     
     // TTL
-    SetEx { key: String, value: String, ttl: u64 },
+    SetEx { key: String, value: String, ttl: u64, jitter: Option<u8> }, // jitter: max extra TTL in percent
+    Expire { key: String, seconds: u64, jitter: Option<u8> },
     Ttl { key: String },
+    PExpireAt { key: String, timestamp_ms: u64 },
+    Schedule { key: String, value: String, at_ms: u64, push: bool },
+    /// Cancels the pending scheduled writes of a key; with a time, the first
+    /// one queued for that time only
+    Unschedule { key: String, at_ms: Option<u64> },
     
     // Auth & Atomic
     Auth { username: Option<String>, password: String },
@@ -127,48 +582,285 @@ pub enum Command {
     AclGetUser { username: String },
     AclList,
     AclDelUser { username: String },
+    /// ACL USAGE [username]
+    AclUsage { username: Option<String> },
 
     // Client/Management
     ClientList,
+    ActiveQueries,
+    KillQuery { id: u64 },
     ClientKill { addr: String },
     
     Incr { key: String },
     Decr { key: String },
+    CIncr { key: String, by: i64 }, // striped counter
     RewriteAof,
     Use { db_name: String },
+    SetOutput { format: OutputFormat },
+    SetBinding { mode: ArgBinding },
+    SetVariable { name: String, value: UnifiedValue },
+    ShowVariables,
     
     // Transactions
     Begin,
     Commit,
     Rollback,
+    /// Abort the next COMMIT if one of these keys is written before it
+    Watch { keys: Vec<String> },
+    Unwatch,
 
 }
 
 impl Command {
     pub fn get_key(&self) -> Option<&str> {
         match self {
-            Command::Set { key, .. } | Command::Get { key } | Command::SetEx { key, .. } |
-            Command::Ttl { key } | Command::Incr { key } | Command::Decr { key } |
+            Command::Set { key, .. } | Command::Get { key } | Command::GetRange { key, .. } | Command::GetEx { key, .. } | Command::SetEx { key, .. } | Command::Expire { key, .. } |
+            Command::Ttl { key } | Command::Incr { key } | Command::Decr { key } | Command::CIncr { key, .. } |
+            Command::Type { key } | Command::PExpireAt { key, .. } | Command::Schedule { key, .. } | Command::Unschedule { key, .. } | Command::Dump { key } | Command::Restore { key, .. } |
             Command::LPush { key, .. } | Command::RPush { key, .. } |
             Command::LPop { key, .. } | Command::RPop { key, .. } | Command::LRange { key, .. } |
             Command::HSet { key, .. } | Command::HGet { key, .. } | Command::HGetAll { key } |
             Command::SAdd { key, .. } | Command::SMembers { key } |
-            Command::ZAdd { key, .. } | Command::ZRange { key, .. } | Command::ZScore { key, .. } |
-            Command::JsonGet { key, .. } | Command::JsonSet { key, .. } => Some(key),
+            Command::ZAdd { key, .. } | Command::ZRange { key, .. } | Command::ZScore { key, .. } | Command::ZPop { key, .. } |
+            Command::GeoAdd { key, .. } | Command::GeoDist { key, .. } | Command::GeoSearch { key, .. } |
+            Command::TopKReserve { key, .. } | Command::TopKAdd { key, .. } |
+            Command::TopKIncrBy { key, .. } | Command::TopKList { key, .. } |
+            Command::XAdd { key, .. } | Command::XRange { key, .. } | Command::XLen { key } | Command::PfAdd { key, .. } |
+            Command::SetBit { key, .. } | Command::GetBit { key, .. } | Command::BitCount { key, .. } |
+            Command::JsonGet { key, .. } | Command::JsonSet { key, .. } | Command::MemoryUsage { key } => Some(key),
+            // A blocking pop goes by its first key; in a cluster they all share its slot
+            Command::BZPop { keys, .. } => keys.first().map(String::as_str),
             _ => None,
         }
     }
 
+    /// Every key the command reads or writes.
+    pub fn keys(&self) -> Vec<&str> {
+        match self {
+            Command::Del { keys } | Command::Exists { keys } | Command::BZPop { keys, .. } | Command::XRead { keys, .. } | Command::PfCount { keys } | Command::Watch { keys } |
+            Command::Eval { keys, .. } | Command::EvalSha { keys, .. } => keys.iter().map(String::as_str).collect(),
+            Command::PfMerge { dest, sources } | Command::BitOp { dest, sources, .. } => std::iter::once(dest).chain(sources).map(String::as_str).collect(),
+            _ => self.get_key().into_iter().collect(),
+        }
+    }
+
+    /// What decides the cluster slot of the command: its keys, then its
+    /// tables, each living in the slot of its name.
+    pub fn slot_keys(&self) -> Vec<&str> {
+        let mut keys = self.keys();
+        keys.extend(self.tables());
+        keys
+    }
+
+    /// Tables the command names (joined ones included).
+    pub fn tables(&self) -> Vec<&str> {
+        match self {
+            Command::Select { table, join, .. } => {
+                let mut tables = vec![table.as_str()];
+                tables.extend(join.iter().flatten().map(|j| j.table.as_str()));
+                tables
+            }
+            Command::Explain { query, .. } | Command::Declare { query, .. } => query.tables(),
+            Command::CreateTable { name: table, .. } | Command::AlterTable { table, .. } |
+            Command::Insert { table, .. } | Command::Update { table, .. } | Command::Delete { table, .. } |
+            Command::VectorSearch { table, .. } | Command::CreateIndex { table, .. } | Command::CreateVectorIndex { table, .. } |
+            Command::DropIndex { table, .. } | Command::Reindex { table, .. } | Command::CheckTable { table } |
+            Command::ExportTable { table } | Command::ImportTable { table, .. } |
+            Command::Describe { table } | Command::ShowCreateTable { table } => vec![table.as_str()],
+            Command::ShowIndexes { table } => table.iter().map(String::as_str).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Command keyword as a client writes it (`SET`, `CREATE TABLE`), for metrics.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::ReplicaOf { .. } => "REPLICAOF",
+            Command::Failover { .. } => "FAILOVER",
+            Command::ReplCheck { .. } => "REPLCHECK",
+            Command::Psync => "PSYNC",
+            Command::Wait { .. } => "WAIT",
+            Command::Info => "INFO",
+            Command::Metrics => "METRICS",
+            Command::MetricsReset => "METRICS RESET",
+            Command::MemoryUsage { .. } => "MEMORY USAGE",
+            Command::MemoryStats => "MEMORY STATS",
+            Command::ConfigGet { .. } => "CONFIG GET",
+            Command::ConfigSet { .. } => "CONFIG SET",
+            Command::ClusterInfo => "CLUSTER INFO",
+            Command::ClusterSlots => "CLUSTER SLOTS",
+            Command::ClusterMeet { .. } => "CLUSTER MEET",
+            Command::ClusterAddSlots { .. } => "CLUSTER ADDSLOTS",
+            Command::ClusterDelSlots { .. } => "CLUSTER DELSLOTS",
+            Command::ClusterForget { .. } => "CLUSTER FORGET",
+            Command::ClusterFailover => "CLUSTER FAILOVER",
+            Command::ClusterSetSlot { .. } => "CLUSTER SETSLOT",
+            Command::Migrate { .. } => "MIGRATE",
+            Command::Asking => "ASKING",
+            Command::Set { .. } => "SET",
+            Command::Get { .. } => "GET",
+            Command::GetRange { .. } => "GETRANGE",
+            Command::GetEx { .. } => "GETEX",
+            Command::Del { .. } => "DEL",
+            Command::Scan { .. } => "SCAN",
+            Command::Keys { .. } => "KEYS",
+            Command::ExportKeys { .. } => "EXPORT KEYS",
+            Command::Dump { .. } => "DUMP",
+            Command::Restore { .. } => "RESTORE",
+            Command::Type { .. } => "TYPE",
+            Command::Exists { .. } => "EXISTS",
+            Command::DbSize => "DBSIZE",
+            Command::LPush { .. } => "LPUSH",
+            Command::RPush { .. } => "RPUSH",
+            Command::LPop { .. } => "LPOP",
+            Command::RPop { .. } => "RPOP",
+            Command::LRange { .. } => "LRANGE",
+            Command::HSet { .. } => "HSET",
+            Command::HGet { .. } => "HGET",
+            Command::HGetAll { .. } => "HGETALL",
+            Command::SAdd { .. } => "SADD",
+            Command::SMembers { .. } => "SMEMBERS",
+            Command::ZAdd { .. } => "ZADD",
+            Command::ZRange { .. } => "ZRANGE",
+            Command::ZScore { .. } => "ZSCORE",
+            Command::ZPop { max: false, .. } => "ZPOPMIN",
+            Command::ZPop { max: true, .. } => "ZPOPMAX",
+            Command::BZPop { max: false, .. } => "BZPOPMIN",
+            Command::BZPop { max: true, .. } => "BZPOPMAX",
+            Command::GeoAdd { .. } => "GEOADD",
+            Command::GeoDist { .. } => "GEODIST",
+            Command::GeoSearch { .. } => "GEOSEARCH",
+            Command::TopKReserve { .. } => "TOPK.RESERVE",
+            Command::TopKAdd { .. } => "TOPK.ADD",
+            Command::TopKIncrBy { .. } => "TOPK.INCRBY",
+            Command::TopKList { .. } => "TOPK.LIST",
+            Command::SetBit { .. } => "SETBIT",
+            Command::GetBit { .. } => "GETBIT",
+            Command::BitCount { .. } => "BITCOUNT",
+            Command::BitOp { .. } => "BITOP",
+            Command::PfAdd { .. } => "PFADD",
+            Command::PfCount { .. } => "PFCOUNT",
+            Command::PfMerge { .. } => "PFMERGE",
+            Command::XAdd { .. } => "XADD",
+            Command::XRange { .. } => "XRANGE",
+            Command::XLen { .. } => "XLEN",
+            Command::XRead { .. } => "XREAD",
+            Command::Eval { .. } => "EVAL",
+            Command::EvalSha { .. } => "EVALSHA",
+            Command::ScriptLoad { .. } => "SCRIPT LOAD",
+            Command::ScriptExists { .. } => "SCRIPT EXISTS",
+            Command::ScriptFlush => "SCRIPT FLUSH",
+            Command::JsonGet { .. } => "JSON.GET",
+            Command::JsonSet { .. } => "JSON.SET",
+            Command::CreateTable { .. } => "CREATE TABLE",
+            Command::AlterTable { .. } => "ALTER TABLE",
+            Command::Insert { .. } => "INSERT",
+            Command::Select { .. } => "SELECT",
+            Command::Explain { .. } => "EXPLAIN",
+            Command::Declare { .. } => "DECLARE",
+            Command::Fetch { .. } => "FETCH",
+            Command::Close { .. } => "CLOSE",
+            Command::VectorSearch { .. } => "SEARCH",
+            Command::Update { .. } => "UPDATE",
+            Command::Delete { .. } => "DELETE",
+            Command::Ping => "PING",
+            Command::Quit => "QUIT",
+            Command::Reset => "RESET",
+            Command::ReadOnly => "READONLY",
+            Command::ReadWrite => "READWRITE",
+            Command::ObjectHelp => "OBJECT HELP",
+            Command::Save => "SAVE",
+            Command::BackupVerify { .. } => "BACKUP VERIFY",
+            Command::AofStats => "AOF STATS",
+            Command::AofCat { .. } => "AOF CAT",
+            Command::CreateIndex { .. } => "CREATE INDEX",
+            Command::CreateVectorIndex { .. } => "CREATE VECTOR INDEX",
+            Command::DropIndex { .. } => "DROP INDEX",
+            Command::ShowIndexes { .. } => "SHOW INDEXES",
+            Command::ShowTables => "SHOW TABLES",
+            Command::ShowDatabases => "SHOW DATABASES",
+            Command::DropDatabase { .. } => "DROP DATABASE",
+            Command::Describe { .. } => "DESCRIBE",
+            Command::ShowCreateTable { .. } => "SHOW CREATE TABLE",
+            Command::Reindex { .. } => "REINDEX",
+            Command::CheckTable { .. } => "CHECK TABLE",
+            Command::ExportTable { .. } => "EXPORT TABLE",
+            Command::ImportTable { .. } => "IMPORT TABLE",
+            Command::AdvisorSuggest => "ADVISOR SUGGEST",
+            Command::SetEx { .. } => "SETEX",
+            Command::Expire { .. } => "EXPIRE",
+            Command::Ttl { .. } => "TTL",
+            Command::PExpireAt { .. } => "PEXPIREAT",
+            Command::Schedule { .. } => "SCHEDULE",
+            Command::Unschedule { .. } => "UNSCHEDULE",
+            Command::Auth { .. } => "AUTH",
+            Command::AclSetUser { .. } => "ACL SETUSER",
+            Command::AclGetUser { .. } => "ACL GETUSER",
+            Command::AclList => "ACL LIST",
+            Command::AclDelUser { .. } => "ACL DELUSER",
+            Command::AclUsage { .. } => "ACL USAGE",
+            Command::ClientList => "CLIENT LIST",
+            Command::ActiveQueries => "ACTIVE QUERIES",
+            Command::KillQuery { .. } => "KILL QUERY",
+            Command::ClientKill { .. } => "CLIENT KILL",
+            Command::Incr { .. } => "INCR",
+            Command::Decr { .. } => "DECR",
+            Command::CIncr { .. } => "CINCR",
+            Command::RewriteAof => "REWRITEAOF",
+            Command::Use { .. } => "USE",
+            Command::SetOutput { .. } => "SET OUTPUT",
+            Command::SetBinding { .. } => "SET BINDING",
+            Command::SetVariable { .. } => "SET @VARIABLE",
+            Command::ShowVariables => "SHOW VARIABLES",
+            Command::Begin => "BEGIN",
+            Command::Commit => "COMMIT",
+            Command::Rollback => "ROLLBACK",
+            Command::Watch { .. } => "WATCH",
+            Command::Unwatch => "UNWATCH",
+        }
+    }
+
+    /// Schema changes, which make a transaction all-or-nothing.
+    pub fn is_ddl(&self) -> bool {
+        matches!(self, Command::CreateTable { .. } | Command::AlterTable { .. } | Command::CreateIndex { .. } | Command::CreateVectorIndex { .. } | Command::DropIndex { .. } | Command::ImportTable { .. })
+    }
+
+    /// Key-value writes that can add keys or grow a value, refused while
+    /// the keyspace is full under the `noeviction` policy.
+    pub fn grows_keyspace(&self) -> bool {
+        matches!(self,
+            Command::Set { .. } | Command::SetEx { .. } | Command::Incr { .. } | Command::Decr { .. } | Command::CIncr { .. } |
+            Command::LPush { .. } | Command::RPush { .. } | Command::HSet { .. } | Command::SAdd { .. } |
+            Command::ZAdd { .. } | Command::GeoAdd { .. } | Command::JsonSet { .. } | Command::Schedule { .. } |
+            Command::TopKReserve { .. } | Command::TopKAdd { .. } | Command::TopKIncrBy { .. } | Command::XAdd { .. } |
+            Command::PfAdd { .. } | Command::PfMerge { .. } | Command::SetBit { value: true, .. } | Command::BitOp { .. } | Command::Restore { .. })
+    }
+
+    /// Whether the command runs under the write locks of its keys and tables:
+    /// every write, plus the commands that need a consistent view of them
+    /// (AOF rewrites, index rebuilds and key exports).
+    pub fn needs_write_lock(&self) -> bool {
+        self.is_write() || matches!(self, Command::RewriteAof | Command::Reindex { .. } | Command::ExportKeys { .. })
+    }
+
+    /// Whether a successful run goes to the AOF. A dropped database leaves
+    /// no file behind, and replaying the drop would remove one made since.
+    pub fn is_logged(&self) -> bool {
+        self.is_write() && !matches!(self, Command::DropDatabase { .. })
+    }
+
     pub fn is_write(&self) -> bool {
         match self {
-            Command::Set { .. } | Command::CreateTable { .. } | Command::Insert { .. } |
+            Command::Set { .. } | Command::Del { .. } | Command::GetEx { expiry: Some(_), .. } | Command::CreateTable { .. } | Command::Insert { .. } |
             Command::Update { .. } | Command::Delete { .. } | Command::AclSetUser { .. } |
             Command::LPush { .. } | Command::RPush { .. } | Command::LPop { .. } | Command::RPop { .. } |
             Command::HSet { .. } | Command::SAdd { .. } | Command::JsonSet { .. } |
-            Command::SetEx { .. } | Command::Incr { .. } | Command::Decr { .. } |
-            Command::AlterTable { .. } | Command::CreateIndex { .. } | Command::ReplicaOf { .. } | 
-            Command::AclDelUser { .. } | Command::ClientKill { .. } | Command::ZAdd { .. } |
-            Command::Commit => true,
+            Command::SetEx { .. } | Command::Expire { .. } | Command::PExpireAt { .. } | Command::Schedule { .. } | Command::Unschedule { .. } | Command::Incr { .. } | Command::Decr { .. } | Command::CIncr { .. } |
+            Command::AlterTable { .. } | Command::CreateIndex { .. } | Command::CreateVectorIndex { .. } | Command::DropIndex { .. } | Command::ReplicaOf { .. } | 
+            Command::AclDelUser { .. } | Command::ClientKill { .. } | Command::ZAdd { .. } | Command::GeoAdd { .. } | Command::ZPop { .. } | Command::BZPop { .. } |
+            Command::Migrate { .. } | Command::TopKReserve { .. } | Command::TopKAdd { .. } | Command::TopKIncrBy { .. } | Command::XAdd { .. } |
+            Command::PfAdd { .. } | Command::PfMerge { .. } | Command::SetBit { .. } | Command::BitOp { .. } | Command::DropDatabase { .. } | Command::Commit |
+            Command::Eval { .. } | Command::EvalSha { .. } | Command::Restore { .. } | Command::ImportTable { .. } => true,
             _ => false,
         }
     }
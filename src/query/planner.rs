@@ -0,0 +1,458 @@
+//! # Query Planner
+//!
+//! Turns a parsed SELECT into an explicit `QueryPlan`: which access path each
+//! table is read through (full scan, hash index or range index), the order and
+//! strategy of joins, and rough row estimates.
+//!
+//! The planner only decides; the structured store executes the chosen paths.
+//! Index metadata and row counts are obtained through the `IndexCatalog` trait,
+//! which keeps this module independent of the storage layout.
+
+use std::fmt;
+use super::{between_bounds, like, Filter, JoinClause, JoinType, Operator};
+
+/// Metadata the planner needs from the storage engine.
+pub trait IndexCatalog {
+    /// Number of rows currently stored in a table.
+    fn row_count(&self, table: &str) -> usize;
+    /// Whether an equality (hash) index exists on the column or JSON path.
+    fn has_hash_index(&self, table: &str, column: &str) -> bool;
+    /// Whether a sorted (B-Tree) index exists on the column or JSON path.
+    fn has_range_index(&self, table: &str, column: &str) -> bool;
+    /// Number of candidate rows an access path yields.
+    fn candidate_count(&self, table: &str, access: &AccessPath) -> usize;
+}
+
+/// How the rows of a single table are located.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AccessPath {
+    FullScan,
+    HashIndex { column: String, value: String },
+    RangeIndex { column: String, op: Operator, value: String },
+    /// Rows present in both paths (AND)
+    Intersect(Box<AccessPath>, Box<AccessPath>),
+    /// Rows present in either path (OR)
+    Union(Box<AccessPath>, Box<AccessPath>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum JoinStrategy {
+    /// Probe the hash index of the joined table once per input row
+    IndexLookup { column: String },
+    /// Build a hash table on the smaller side and probe it with the other
+    HashJoin { build_right: bool },
+}
+
+#[derive(Debug, Clone)]
+pub struct JoinStep {
+    pub join_type: JoinType,
+    pub table: String,
+    /// Name the table's columns are qualified with: its alias or its name
+    pub qualifier: String,
+    /// Join key resolved against the rows produced so far
+    pub left_key: String,
+    /// Join key resolved against the rows of `table`
+    pub right_key: String,
+    pub strategy: JoinStrategy,
+    pub estimated_rows: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct QueryPlan {
+    pub table: String,
+    pub access: AccessPath,
+    /// Rows produced by the access path before the residual filter
+    pub scan_rows: usize,
+    /// Joins in execution order (left-deep)
+    pub joins: Vec<JoinStep>,
+    /// WHERE evaluated row by row (on candidates, or after the joins)
+    pub residual_filter: bool,
+    pub group_by: Option<Vec<String>>,
+    pub order_by: Option<Vec<(String, bool)>>,
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+    pub estimated_rows: usize,
+}
+
+/// Chooses the access path for a filter on a single table.
+pub fn plan_access(catalog: &dyn IndexCatalog, table: &str, filter: &Filter) -> AccessPath {
+    match filter {
+        Filter::Condition(col, op, val) => {
+            if matches!(op, Operator::Eq) && catalog.has_hash_index(table, col) {
+                return AccessPath::HashIndex { column: col.clone(), value: val.clone() };
+            }
+            if matches!(op, Operator::Gt | Operator::Gte | Operator::Lt | Operator::Lte | Operator::Between)
+                && catalog.has_range_index(table, col)
+            {
+                return AccessPath::RangeIndex { column: col.clone(), op: op.clone(), value: val.clone() };
+            }
+            // Strings sharing a prefix are adjacent in a range index
+            if matches!(op, Operator::Like)
+                && !like::literal_prefix(val).is_empty()
+                && catalog.has_range_index(table, col)
+            {
+                return AccessPath::RangeIndex { column: col.clone(), op: Operator::Like, value: val.clone() };
+            }
+            AccessPath::FullScan
+        }
+        Filter::And(left, right) => {
+            // Either side narrows the candidates; the residual filter checks the rest
+            match (plan_access(catalog, table, left), plan_access(catalog, table, right)) {
+                (AccessPath::FullScan, AccessPath::FullScan) => AccessPath::FullScan,
+                (l, AccessPath::FullScan) => l,
+                (AccessPath::FullScan, r) => r,
+                (l, r) => AccessPath::Intersect(Box::new(l), Box::new(r)),
+            }
+        }
+        Filter::Or(left, right) => {
+            // Both sides must be indexable, otherwise every row is a candidate
+            match (plan_access(catalog, table, left), plan_access(catalog, table, right)) {
+                (AccessPath::FullScan, _) | (_, AccessPath::FullScan) => AccessPath::FullScan,
+                (l, r) => AccessPath::Union(Box::new(l), Box::new(r)),
+            }
+        }
+        // Indexes hold column values: they cannot list the rows a NOT keeps, nor
+        // look up a computed value
+        Filter::Not(_) | Filter::Computed(..) => AccessPath::FullScan,
+    }
+}
+
+/// Builds the full plan for a SELECT.
+#[allow(clippy::too_many_arguments)]
+pub fn plan_select(
+    catalog: &dyn IndexCatalog,
+    table: &str,
+    joins: Option<&[JoinClause]>,
+    filter: Option<&Filter>,
+    group_by: Option<&[String]>,
+    order_by: Option<&[(String, bool)]>,
+    limit: Option<usize>,
+    offset: Option<usize>,
+) -> QueryPlan {
+    let joins = joins.unwrap_or(&[]);
+
+    // Joined queries read every table fully and filter after joining
+    let access = match filter {
+        Some(f) if joins.is_empty() => plan_access(catalog, table, f),
+        _ => AccessPath::FullScan,
+    };
+    let scan_rows = catalog.candidate_count(table, &access);
+
+    let mut rows = scan_rows;
+    let mut steps = Vec::new();
+    for join in join_order(catalog, joins) {
+        let right_rows = catalog.row_count(&join.table);
+        let (left_key, right_key) = orient_join_keys(join);
+
+        let prefix = format!("{}.", join.qualifier());
+        let right_column = right_key.strip_prefix(&prefix).unwrap_or(&right_key);
+        let strategy = if catalog.has_hash_index(&join.table, right_column) {
+            JoinStrategy::IndexLookup { column: right_column.to_string() }
+        } else {
+            JoinStrategy::HashJoin { build_right: right_rows <= rows }
+        };
+
+        // Equi-joins are assumed to follow a key relationship: the larger side bounds the output
+        let (preserved, other) = match join.join_type {
+            JoinType::Inner => (rows.min(right_rows), rows.max(right_rows)),
+            JoinType::Left => (rows, right_rows),
+            JoinType::Right => (right_rows, rows),
+        };
+        rows = if preserved == 0 { 0 } else { preserved.max(other) };
+        steps.push(JoinStep {
+            join_type: join.join_type.clone(),
+            table: join.table.clone(),
+            qualifier: join.qualifier().to_string(),
+            left_key,
+            right_key,
+            strategy,
+            estimated_rows: rows,
+        });
+    }
+
+    let mut estimated_rows = rows;
+    if let Some(n) = offset {
+        estimated_rows = estimated_rows.saturating_sub(n);
+    }
+    if let Some(n) = limit {
+        estimated_rows = estimated_rows.min(n);
+    }
+
+    QueryPlan {
+        table: table.to_string(),
+        access,
+        scan_rows,
+        joins: steps,
+        residual_filter: filter.is_some(),
+        group_by: group_by.map(|g| g.to_vec()),
+        order_by: order_by.map(|o| o.to_vec()),
+        limit,
+        offset,
+        estimated_rows,
+    }
+}
+
+/// Order to run the joins in. Inner joins commute, so they are chosen
+/// greedily: among the joins whose `ON` only needs tables joined already, the
+/// smallest table goes first (ties keep the written order), which keeps the
+/// intermediate results small. With an outer join the written order stands.
+fn join_order<'a>(catalog: &dyn IndexCatalog, joins: &'a [JoinClause]) -> Vec<&'a JoinClause> {
+    if joins.iter().any(|j| j.join_type != JoinType::Inner) {
+        return joins.iter().collect();
+    }
+    let mut pending: Vec<&JoinClause> = joins.iter().collect();
+    let mut ordered = Vec::with_capacity(joins.len());
+    while !pending.is_empty() {
+        let needs_pending = |join: &JoinClause| {
+            let (left_key, _) = orient_join_keys(join);
+            pending.iter().any(|p| p.qualifier() != join.qualifier() && left_key.starts_with(&format!("{}.", p.qualifier())))
+        };
+        let next = pending.iter().enumerate()
+            .filter(|(_, j)| !needs_pending(j))
+            .min_by_key(|(i, j)| (catalog.row_count(&j.table), *i))
+            .map_or(0, |(i, _)| i);
+        ordered.push(pending.remove(next));
+    }
+    ordered
+}
+
+/// Returns (key on the rows joined so far, key on the joined table).
+/// `ON b.x = a.y` is accepted as well as `ON a.y = b.x` when joining `b`.
+fn orient_join_keys(join: &JoinClause) -> (String, String) {
+    let prefix = format!("{}.", join.qualifier());
+    if join.on_left.starts_with(&prefix) && !join.on_right.starts_with(&prefix) {
+        (join.on_right.clone(), join.on_left.clone())
+    } else {
+        (join.on_left.clone(), join.on_right.clone())
+    }
+}
+
+fn op_symbol(op: &Operator) -> &'static str {
+    match op {
+        Operator::Eq => "=",
+        Operator::Neq => "!=",
+        Operator::Gt => ">",
+        Operator::Lt => "<",
+        Operator::Gte => ">=",
+        Operator::Lte => "<=",
+        Operator::Like => "LIKE",
+        Operator::ILike => "ILIKE",
+        Operator::In => "IN",
+        Operator::Between => "BETWEEN",
+        Operator::IsNull => "IS NULL",
+        Operator::IsNotNull => "IS NOT NULL",
+    }
+}
+
+fn join_keyword(join_type: &JoinType) -> &'static str {
+    match join_type {
+        JoinType::Inner => "INNER",
+        JoinType::Left => "LEFT",
+        JoinType::Right => "RIGHT",
+    }
+}
+
+impl fmt::Display for AccessPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccessPath::FullScan => write!(f, "Full Scan"),
+            AccessPath::HashIndex { column, value } => write!(f, "Hash Index ({} = {})", column, value),
+            AccessPath::RangeIndex { column, op: Operator::Between, value } => {
+                let (low, high) = between_bounds(value).unwrap_or_default();
+                write!(f, "Range Index ({} BETWEEN {} AND {})", column, low, high)
+            }
+            AccessPath::RangeIndex { column, op, value } => {
+                write!(f, "Range Index ({} {} {})", column, op_symbol(op), value)
+            }
+            AccessPath::Intersect(l, r) => write!(f, "Intersect[{}, {}]", l, r),
+            AccessPath::Union(l, r) => write!(f, "Union[{}, {}]", l, r),
+        }
+    }
+}
+
+impl fmt::Display for JoinStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JoinStrategy::IndexLookup { column } => write!(f, "Index Lookup ({})", column),
+            JoinStrategy::HashJoin { build_right: true } => write!(f, "Hash Join (build: right)"),
+            JoinStrategy::HashJoin { build_right: false } => write!(f, "Hash Join (build: left)"),
+        }
+    }
+}
+
+impl fmt::Display for QueryPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "SELECT FROM {} (estimated rows: {})", self.table, self.estimated_rows)?;
+        writeln!(f, "  -> Access {}: {} (rows: {})", self.table, self.access, self.scan_rows)?;
+        for (i, join) in self.joins.iter().enumerate() {
+            let table = if join.qualifier == join.table { join.table.clone() } else { format!("{} {}", join.table, join.qualifier) };
+            writeln!(
+                f,
+                "  -> Join {}: {} JOIN {} ON {} = {} using {} (rows: {})",
+                i + 1, join_keyword(&join.join_type), table, join.left_key, join.right_key, join.strategy, join.estimated_rows
+            )?;
+        }
+        if self.residual_filter {
+            let stage = if self.joins.is_empty() { "candidate rows" } else { "joined rows" };
+            writeln!(f, "  -> Filter: WHERE evaluated on {}", stage)?;
+        }
+        if let Some(cols) = &self.group_by {
+            writeln!(f, "  -> Group By: {}", cols.join(", "))?;
+        }
+        if let Some(keys) = &self.order_by {
+            let keys: Vec<String> = keys.iter()
+                .map(|(col, asc)| format!("{} {}", col, if *asc { "ASC" } else { "DESC" }))
+                .collect();
+            writeln!(f, "  -> Sort: {}", keys.join(", "))?;
+        }
+        if self.limit.is_some() || self.offset.is_some() {
+            let limit = self.limit.map(|n| n.to_string()).unwrap_or_else(|| "ALL".to_string());
+            writeln!(f, "  -> Limit: {} Offset: {}", limit, self.offset.unwrap_or(0))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct MockCatalog;
+
+    impl IndexCatalog for MockCatalog {
+        fn row_count(&self, table: &str) -> usize {
+            match table {
+                "countries" => 10,
+                "events" => 10_000,
+                _ => 100,
+            }
+        }
+        fn has_hash_index(&self, _table: &str, column: &str) -> bool { column == "email" }
+        fn has_range_index(&self, _table: &str, column: &str) -> bool { column == "age" }
+        fn candidate_count(&self, _table: &str, access: &AccessPath) -> usize {
+            if *access == AccessPath::FullScan { 100 } else { 5 }
+        }
+    }
+
+    fn cond(col: &str, op: Operator, val: &str) -> Filter {
+        Filter::Condition(col.to_string(), op, val.to_string())
+    }
+
+    #[test]
+    fn test_access_path_selection() {
+        let eq = cond("email", Operator::Eq, "a@b.c");
+        assert!(matches!(plan_access(&MockCatalog, "users", &eq), AccessPath::HashIndex { .. }));
+
+        let range = cond("age", Operator::Gte, "18");
+        assert!(matches!(plan_access(&MockCatalog, "users", &range), AccessPath::RangeIndex { .. }));
+
+        // Equality cannot use a range-only index
+        let eq_age = cond("age", Operator::Eq, "18");
+        assert_eq!(plan_access(&MockCatalog, "users", &eq_age), AccessPath::FullScan);
+
+        // AND keeps the indexable side, OR needs both
+        let and = Filter::And(Box::new(cond("name", Operator::Eq, "x")), Box::new(range.clone()));
+        assert!(matches!(plan_access(&MockCatalog, "users", &and), AccessPath::RangeIndex { .. }));
+        let or = Filter::Or(Box::new(cond("name", Operator::Eq, "x")), Box::new(range.clone()));
+        assert_eq!(plan_access(&MockCatalog, "users", &or), AccessPath::FullScan);
+        // LIKE scans the range index only when the pattern has a literal prefix
+        let prefix = cond("age", Operator::Like, "1%");
+        assert!(matches!(plan_access(&MockCatalog, "users", &prefix), AccessPath::RangeIndex { op: Operator::Like, .. }));
+        assert_eq!(plan_access(&MockCatalog, "users", &cond("age", Operator::Like, "%1")), AccessPath::FullScan);
+        assert_eq!(plan_access(&MockCatalog, "users", &cond("age", Operator::ILike, "1%")), AccessPath::FullScan);
+
+        // BETWEEN is one range scan; NOT cannot use an index
+        let between = cond("age", Operator::Between, "18,30");
+        assert_eq!(plan_access(&MockCatalog, "users", &between).to_string(), "Range Index (age BETWEEN 18 AND 30)");
+        assert_eq!(plan_access(&MockCatalog, "users", &Filter::Not(Box::new(range.clone()))), AccessPath::FullScan);
+
+        let or_indexed = Filter::Or(Box::new(eq), Box::new(range));
+        assert!(matches!(plan_access(&MockCatalog, "users", &or_indexed), AccessPath::Union(..)));
+    }
+
+    #[test]
+    fn test_plan_select_estimates() {
+        let filter = cond("age", Operator::Gt, "30");
+        let plan = plan_select(&MockCatalog, "users", None, Some(&filter), None, None, Some(2), None);
+        assert_eq!(plan.scan_rows, 5);
+        assert_eq!(plan.estimated_rows, 2);
+
+        let joins = vec![JoinClause {
+            join_type: JoinType::Inner,
+            table: "orders".to_string(),
+            alias: None,
+            on_left: "orders.user_id".to_string(),
+            on_right: "users.id".to_string(),
+        }];
+        let plan = plan_select(&MockCatalog, "users", Some(&joins), Some(&filter), None, None, None, None);
+        assert_eq!(plan.access, AccessPath::FullScan);
+        assert_eq!(plan.joins.len(), 1);
+        // Keys are oriented so the right key belongs to the joined table
+        assert_eq!(plan.joins[0].left_key, "users.id");
+        assert_eq!(plan.joins[0].right_key, "orders.user_id");
+        assert_eq!(plan.joins[0].strategy, JoinStrategy::HashJoin { build_right: true });
+
+        let indexed = vec![JoinClause {
+            join_type: JoinType::Inner,
+            table: "accounts".to_string(),
+            alias: None,
+            on_left: "users.email".to_string(),
+            on_right: "accounts.email".to_string(),
+        }];
+        let plan = plan_select(&MockCatalog, "users", Some(&indexed), None, None, None, None, None);
+        assert_eq!(plan.joins[0].strategy, JoinStrategy::IndexLookup { column: "email".to_string() });
+
+        // An alias qualifies the joined table's key in place of its name
+        let aliased = vec![JoinClause {
+            join_type: JoinType::Inner,
+            table: "accounts".to_string(),
+            alias: Some("a".to_string()),
+            on_left: "a.email".to_string(),
+            on_right: "u.email".to_string(),
+        }];
+        let plan = plan_select(&MockCatalog, "users", Some(&aliased), None, None, None, None, None);
+        assert_eq!((plan.joins[0].left_key.as_str(), plan.joins[0].right_key.as_str()), ("u.email", "a.email"));
+        assert_eq!(plan.joins[0].strategy, JoinStrategy::IndexLookup { column: "email".to_string() });
+        assert!(plan.to_string().contains("INNER JOIN accounts a ON u.email = a.email"));
+    }
+
+    fn join(table: &str, alias: Option<&str>, on_left: &str, on_right: &str) -> JoinClause {
+        JoinClause {
+            join_type: JoinType::Inner,
+            table: table.to_string(),
+            alias: alias.map(str::to_string),
+            on_left: on_left.to_string(),
+            on_right: on_right.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_greedy_join_order() {
+        let order = |joins: &[JoinClause]| {
+            let plan = plan_select(&MockCatalog, "users", Some(joins), None, None, None, None, None);
+            plan.joins.into_iter().map(|j| j.qualifier).collect::<Vec<_>>()
+        };
+        // Smallest table first
+        let star = [
+            join("events", None, "events.user_id", "users.id"),
+            join("orders", None, "orders.user_id", "users.id"),
+            join("countries", None, "countries.code", "users.country"),
+        ];
+        assert_eq!(order(&star), ["countries", "orders", "events"]);
+
+        // A join waits for the tables its ON refers to, whatever their size
+        let chain = [
+            join("events", Some("e"), "e.order_id", "o.id"),
+            join("orders", Some("o"), "o.user_id", "users.id"),
+            join("countries", Some("c"), "c.code", "e.country"),
+        ];
+        assert_eq!(order(&chain), ["o", "e", "c"]);
+        let plan = plan_select(&MockCatalog, "users", Some(&chain), None, None, None, None, None);
+        assert_eq!((plan.joins[1].left_key.as_str(), plan.joins[1].right_key.as_str()), ("o.id", "e.order_id"));
+
+        // Outer joins keep the written order
+        let mut outer = star.clone();
+        outer[1].join_type = JoinType::Left;
+        assert_eq!(order(&outer), ["events", "orders", "countries"]);
+    }
+}
@@ -0,0 +1,580 @@
+//! # Wire Form of Commands
+//!
+//! `Display` for [`Command`] writes a command back in the syntax the parser
+//! reads: `parse_command(&cmd.to_string())` gives the same command back,
+//! whatever its values contain. Lines the server writes itself to the AOF
+//! and the replication stream (expiries pinned by `EXPIRE`, the pop a
+//! blocking `BZPOPMIN` made, transaction markers...) are built this way.
+//!
+//! Values are written bare when they read back as themselves and quoted
+//! with [`quote_string`] otherwise, the string `NULL` included. Client
+//! commands keep their own line: it already is parser syntax, with variables
+//! and `NOW()` substituted.
+
+use std::fmt::{self, Display, Formatter};
+use crate::core::output::OutputFormat;
+use crate::core::types::UnifiedValue;
+use crate::net::binding::ArgBinding;
+use crate::net::parser::{is_key, quote_string};
+use super::{between_bounds, split_value_list, ArithOp, ColumnDef, Command, Expr, ExpiryUpdate, Filter, GeoOrigin, JoinClause, JoinType, Operator, Projection, Selector, SlotState, ZAddFlags};
+
+/// A value, bare if it parses back to itself.
+struct Word<'a>(&'a str);
+
+impl Display for Word<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if is_key(self.0) && !self.0.eq_ignore_ascii_case("NULL") {
+            f.write_str(self.0)
+        } else {
+            f.write_str(&quote_string(self.0))
+        }
+    }
+}
+
+/// A glob pattern or path: any run of non-whitespace, or quoted.
+struct Pattern<'a>(&'a str);
+
+impl Display for Pattern<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() || self.0.contains(char::is_whitespace) || self.0.starts_with(['"', '\'']) {
+            f.write_str(&quote_string(self.0))
+        } else {
+            f.write_str(self.0)
+        }
+    }
+}
+
+/// A float that does not read back as an integer.
+struct Float(f64);
+
+impl Display for Float {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let n = self.0.to_string();
+        if self.0.is_finite() && !n.contains('.') { write!(f, "{}.0", n) } else { f.write_str(&n) }
+    }
+}
+
+/// Values separated by spaces.
+fn words<'a>(values: impl IntoIterator<Item = &'a String>) -> String {
+    values.into_iter().map(|v| Word(v).to_string()).collect::<Vec<_>>().join(" ")
+}
+
+fn literal(value: &Option<String>) -> String {
+    value.as_deref().map_or_else(|| "NULL".to_string(), |v| Word(v).to_string())
+}
+
+struct WireExpr<'a>(&'a Expr);
+
+impl Display for WireExpr<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Expr::Column(c) => f.write_str(c),
+            Expr::Literal(UnifiedValue::Integer(i)) => write!(f, "{}", i),
+            Expr::Literal(UnifiedValue::Float(n)) => write!(f, "{}", Float(*n)),
+            Expr::Literal(other) => f.write_str(&quote_string(&other.to_string())),
+            Expr::Binary(l, op, r) => {
+                let op = match op {
+                    ArithOp::Add => "+",
+                    ArithOp::Sub => "-",
+                    ArithOp::Mul => "*",
+                    ArithOp::Div => "/",
+                };
+                // Parentheses keep the tree the expression was parsed into
+                let side = |e: &Expr| match e {
+                    Expr::Binary(..) => format!("({})", WireExpr(e)),
+                    _ => WireExpr(e).to_string(),
+                };
+                write!(f, "{} {} {}", side(l), op, side(r))
+            }
+            Expr::Call(func, arg) => write!(f, "{}({})", func, WireExpr(arg)),
+            Expr::DateAdd(at, amount, unit) => write!(f, "DATE_ADD({}, {}, {})", WireExpr(at), WireExpr(amount), unit),
+            Expr::DateDiff(to, from, unit) => write!(f, "DATE_DIFF({}, {}, {})", WireExpr(to), WireExpr(from), unit),
+            Expr::Aggregate(agg) => write!(f, "{}", agg),
+        }
+    }
+}
+
+struct WireFilter<'a>(&'a Filter);
+
+impl WireFilter<'_> {
+    fn comparison(f: &mut Formatter<'_>, left: &dyn Display, op: &Operator, val: &str) -> fmt::Result {
+        let op = match op {
+            Operator::Eq => "=",
+            Operator::Neq => "!=",
+            Operator::Gt => ">",
+            Operator::Lt => "<",
+            Operator::Gte => ">=",
+            Operator::Lte => "<=",
+            Operator::Like => "LIKE",
+            Operator::ILike => "ILIKE",
+            Operator::In => return write!(f, "{} IN ({})", left, split_value_list(val).iter().map(|v| Word(v).to_string()).collect::<Vec<_>>().join(", ")),
+            Operator::Between => {
+                let (low, high) = between_bounds(val).unwrap_or_default();
+                return write!(f, "{} BETWEEN {} AND {}", left, Word(&low), Word(&high));
+            }
+            Operator::IsNull => return write!(f, "{} IS NULL", left),
+            Operator::IsNotNull => return write!(f, "{} IS NOT NULL", left),
+        };
+        write!(f, "{} {} {}", left, op, Word(val))
+    }
+}
+
+impl Display for WireFilter<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        // AND binds tighter than OR, and both group to the left
+        let grouped = |filter: &Filter, bare: bool| if bare {
+            WireFilter(filter).to_string()
+        } else {
+            format!("({})", WireFilter(filter))
+        };
+        match self.0 {
+            Filter::Condition(col, op, val) => Self::comparison(f, col, op, val),
+            Filter::Computed(expr, op, val) => Self::comparison(f, &WireExpr(expr), op, val),
+            Filter::And(l, r) => write!(f, "{} AND {}",
+                grouped(l, !matches!(**l, Filter::Or(..))),
+                grouped(r, !matches!(**r, Filter::And(..) | Filter::Or(..)))),
+            Filter::Or(l, r) => write!(f, "{} OR {}", grouped(l, true), grouped(r, !matches!(**r, Filter::Or(..)))),
+            Filter::Not(inner) => write!(f, "NOT ({})", WireFilter(inner)),
+        }
+    }
+}
+
+fn projections(projs: &[Projection]) -> String {
+    projs.iter().enumerate().map(|(i, p)| {
+        // A lone aggregate first in the list would read as the aggregate selector
+        let expr = match &p.expr {
+            Expr::Aggregate(_) if i == 0 => format!("({})", WireExpr(&p.expr)),
+            expr => WireExpr(expr).to_string(),
+        };
+        match &p.alias {
+            Some(alias) => format!("{} AS {}", expr, alias),
+            None => expr,
+        }
+    }).collect::<Vec<_>>().join(", ")
+}
+
+fn selector(selector: &Selector) -> String {
+    match selector {
+        Selector::Columns(projs) => projections(projs),
+        Selector::Distinct(projs) => format!("DISTINCT {}", projections(projs)),
+        aggregate => aggregate.to_string(),
+    }
+}
+
+fn join(clause: &JoinClause) -> String {
+    let kind = match clause.join_type {
+        JoinType::Inner => "",
+        JoinType::Left => " LEFT",
+        JoinType::Right => " RIGHT",
+    };
+    let alias = clause.alias.as_ref().map_or_else(String::new, |a| format!(" AS {}", a));
+    format!("{} JOIN {}{} ON {} = {}", kind, clause.table, alias, clause.on_left, clause.on_right)
+}
+
+fn column_def(def: &ColumnDef) -> String {
+    let mut out = format!("{}:{}", def.name, def.data_type);
+    if def.primary_key {
+        out.push_str(":pk");
+    }
+    if let Some((table, column)) = &def.references {
+        out.push_str(&format!(":fk({}.{})", table, column));
+    }
+    if let Some(action) = def.fk_action.keyword() {
+        out.push_str(&format!(":{}", action));
+    }
+    if def.not_null {
+        out.push_str(":notnull");
+    }
+    if def.unique {
+        out.push_str(":unique");
+    }
+    if let Some(default) = &def.default {
+        out.push_str(&format!(":default({})", Word(default)));
+    }
+    out
+}
+
+fn flags(flags: &ZAddFlags) -> String {
+    [(flags.nx, " NX"), (flags.xx, " XX"), (flags.gt, " GT"), (flags.lt, " LT"), (flags.ch, " CH")]
+        .iter()
+        .filter(|(set, _)| *set)
+        .map(|(_, word)| *word)
+        .collect()
+}
+
+fn opt<T: Display>(prefix: &str, value: &Option<T>) -> String {
+    value.as_ref().map_or_else(String::new, |v| format!("{}{}", prefix, v))
+}
+
+fn jitter(jitter: &Option<u8>) -> String {
+    opt(" JITTER ", jitter)
+}
+
+fn filter(filter: &Option<Filter>) -> String {
+    filter.as_ref().map_or_else(String::new, |f| format!(" WHERE {}", WireFilter(f)))
+}
+
+impl Display for Command {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Command::ReplicaOf { host, port } if port.is_empty() => write!(f, "REPLICAOF {}", Word(host)),
+            Command::ReplicaOf { host, port } => write!(f, "REPLICAOF {} {}", Word(host), Word(port)),
+            Command::Failover { timeout_ms } => write!(f, "FAILOVER TIMEOUT {}", timeout_ms),
+            Command::ReplCheck { replica, timeout_ms } => {
+                write!(f, "REPLCHECK{} TIMEOUT {}", opt(" ", &replica.as_deref().map(Word)), timeout_ms)
+            }
+            Command::Psync => f.write_str("PSYNC"),
+            Command::Wait { replicas, timeout_ms } => write!(f, "WAIT {} {}", replicas, timeout_ms),
+            Command::Info => f.write_str("INFO"),
+            Command::Metrics => f.write_str("METRICS"),
+            Command::MetricsReset => f.write_str("METRICS RESET"),
+            Command::MemoryUsage { key } => write!(f, "MEMORY USAGE {}", key),
+            Command::MemoryStats => f.write_str("MEMORY STATS"),
+            Command::ConfigGet { pattern } => write!(f, "CONFIG GET {}", Pattern(pattern)),
+            Command::ConfigSet { param, value } => write!(f, "CONFIG SET {} {}", param, Pattern(value)),
+            Command::ClusterInfo => f.write_str("CLUSTER INFO"),
+            Command::ClusterSlots => f.write_str("CLUSTER SLOTS"),
+            Command::ClusterMeet { host, port } => write!(f, "CLUSTER MEET {} {}", Word(host), port),
+            Command::ClusterAddSlots { slots } | Command::ClusterDelSlots { slots } => {
+                let slots: Vec<String> = slots.iter().map(u16::to_string).collect();
+                write!(f, "{} {}", self.name(), slots.join(" "))
+            }
+            Command::ClusterForget { node } => write!(f, "CLUSTER FORGET {}", Word(node)),
+            Command::ClusterFailover => f.write_str("CLUSTER FAILOVER"),
+            Command::ClusterSetSlot { slot, state } => match state {
+                SlotState::Migrating(node) => write!(f, "CLUSTER SETSLOT {} MIGRATING {}", slot, Word(node)),
+                SlotState::Importing(node) => write!(f, "CLUSTER SETSLOT {} IMPORTING {}", slot, Word(node)),
+                SlotState::Node(node) => write!(f, "CLUSTER SETSLOT {} NODE {}", slot, Word(node)),
+                SlotState::Stable => write!(f, "CLUSTER SETSLOT {} STABLE", slot),
+            },
+            Command::Migrate { host, port, slot, count, auth } => {
+                write!(f, "MIGRATE {} {} SLOT {}{}", Word(host), port, slot, opt(" COUNT ", count))?;
+                match auth {
+                    Some((user, password)) => write!(f, " AUTH2 {} {}", Word(user), Word(password)),
+                    None => Ok(()),
+                }
+            }
+            Command::Asking => f.write_str("ASKING"),
+            Command::Set { key, value } => write!(f, "SET {} {}", key, Word(value)),
+            Command::Get { key } => write!(f, "GET {}", key),
+            Command::GetRange { key, start, end } => write!(f, "GETRANGE {} {} {}", key, start, end),
+            Command::GetEx { key, expiry } => match expiry {
+                None => write!(f, "GETEX {}", key),
+                Some(ExpiryUpdate::After(ms)) => write!(f, "GETEX {} PX {}", key, ms),
+                Some(ExpiryUpdate::At(ms)) => write!(f, "GETEX {} PXAT {}", key, ms),
+                Some(ExpiryUpdate::Persist) => write!(f, "GETEX {} PERSIST", key),
+            },
+            Command::Del { keys } => write!(f, "DEL {}", words(keys)),
+            Command::Scan { cursor, pattern, count } => {
+                write!(f, "SCAN {}{}{}", cursor, opt(" MATCH ", &pattern.as_deref().map(Pattern)), opt(" COUNT ", count))
+            }
+            Command::Keys { pattern } => write!(f, "KEYS {}", Pattern(pattern)),
+            Command::ExportKeys { pattern, path } => {
+                write!(f, "EXPORT KEYS{} TO {}", opt(" MATCH ", &pattern.as_deref().map(Pattern)), Pattern(path))
+            }
+            Command::Dump { key } => write!(f, "DUMP {}", key),
+            Command::Restore { key, ttl, payload, replace, absttl } => write!(
+                f, "RESTORE {} {} {}{}{}", key, ttl, Word(payload),
+                if *replace { " REPLACE" } else { "" }, if *absttl { " ABSTTL" } else { "" }
+            ),
+            Command::Type { key } => write!(f, "TYPE {}", key),
+            Command::Exists { keys } => write!(f, "EXISTS {}", keys.join(" ")),
+            Command::DbSize => f.write_str("DBSIZE"),
+            Command::LPush { key, values } => write!(f, "LPUSH {} {}", key, words(values)),
+            Command::RPush { key, values } => write!(f, "RPUSH {} {}", key, words(values)),
+            Command::LPop { key, count } => write!(f, "LPOP {}{}", key, opt(" ", count)),
+            Command::RPop { key, count } => write!(f, "RPOP {}{}", key, opt(" ", count)),
+            Command::LRange { key, start, stop } => write!(f, "LRANGE {} {} {}", key, start, stop),
+            Command::HSet { key, field, value } => write!(f, "HSET {} {} {}", key, Word(field), Word(value)),
+            Command::HGet { key, field } => write!(f, "HGET {} {}", key, Word(field)),
+            Command::HGetAll { key } => write!(f, "HGETALL {}", key),
+            Command::SAdd { key, members } => write!(f, "SADD {} {}", key, words(members)),
+            Command::SMembers { key } => write!(f, "SMEMBERS {}", key),
+            Command::ZAdd { key, flags: zflags, members } => {
+                let members: Vec<String> = members.iter().map(|(score, member)| format!("{} {}", score, Word(member))).collect();
+                write!(f, "ZADD {}{} {}", key, flags(zflags), members.join(" "))
+            }
+            Command::ZRange { key, start, stop } => write!(f, "ZRANGE {} {} {}", key, start, stop),
+            Command::ZScore { key, member } => write!(f, "ZSCORE {} {}", key, Word(member)),
+            Command::ZPop { key, count, .. } => write!(f, "{} {}{}", self.name(), key, opt(" ", count)),
+            Command::BZPop { keys, timeout_ms, .. } => {
+                write!(f, "{} {} {}.{:03}", self.name(), keys.join(" "), timeout_ms / 1000, timeout_ms % 1000)
+            }
+            Command::GeoAdd { key, flags: zflags, members } => {
+                let members: Vec<String> = members.iter().map(|(lon, lat, member)| format!("{} {} {}", lon, lat, Word(member))).collect();
+                write!(f, "GEOADD {}{} {}", key, flags(zflags), members.join(" "))
+            }
+            Command::GeoDist { key, member1, member2, unit } => write!(f, "GEODIST {} {} {} {}", key, Word(member1), Word(member2), unit),
+            Command::GeoSearch { key, from, radius, unit, desc, count, with_coord, with_dist } => {
+                match from {
+                    GeoOrigin::Member(member) => write!(f, "GEOSEARCH {} FROMMEMBER {}", key, Word(member))?,
+                    GeoOrigin::LonLat(lon, lat) => write!(f, "GEOSEARCH {} FROMLONLAT {} {}", key, lon, lat)?,
+                }
+                write!(f, " BYRADIUS {} {} {}{}", radius, unit, if *desc { "DESC" } else { "ASC" }, opt(" COUNT ", count))?;
+                write!(f, "{}{}", if *with_coord { " WITHCOORD" } else { "" }, if *with_dist { " WITHDIST" } else { "" })
+            }
+            Command::TopKReserve { key, k, width, depth } => write!(f, "TOPK.RESERVE {} {} {} {}", key, k, width, depth),
+            Command::TopKAdd { key, items } => write!(f, "TOPK.ADD {} {}", key, words(items)),
+            Command::TopKIncrBy { key, items } => {
+                let items: Vec<String> = items.iter().map(|(item, by)| format!("{} {}", Word(item), by)).collect();
+                write!(f, "TOPK.INCRBY {} {}", key, items.join(" "))
+            }
+            Command::TopKList { key, with_count } => write!(f, "TOPK.LIST {}{}", key, if *with_count { " WITHCOUNT" } else { "" }),
+            Command::SetBit { key, offset, value } => write!(f, "SETBIT {} {} {}", key, offset, u8::from(*value)),
+            Command::GetBit { key, offset } => write!(f, "GETBIT {} {}", key, offset),
+            Command::BitCount { key, range: None, .. } => write!(f, "BITCOUNT {}", key),
+            Command::BitCount { key, range: Some((start, end)), bits } => {
+                write!(f, "BITCOUNT {} {} {} {}", key, start, end, if *bits { "BIT" } else { "BYTE" })
+            }
+            Command::BitOp { op, dest, sources } => write!(f, "BITOP {} {} {}", op, dest, sources.join(" ")),
+            Command::PfAdd { key, elements } if elements.is_empty() => write!(f, "PFADD {}", key),
+            Command::PfAdd { key, elements } => write!(f, "PFADD {} {}", key, words(elements)),
+            Command::PfCount { keys } => write!(f, "PFCOUNT {}", keys.join(" ")),
+            Command::PfMerge { dest, sources } => write!(f, "PFMERGE {}", std::iter::once(dest).chain(sources).cloned().collect::<Vec<_>>().join(" ")),
+            Command::XAdd { key, id, fields } => {
+                let fields: Vec<String> = fields.iter().map(|(field, value)| format!("{} {}", Word(field), Word(value))).collect();
+                write!(f, "XADD {} {} {}", key, id, fields.join(" "))
+            }
+            Command::XRange { key, start, end, count } => write!(f, "XRANGE {} {} {}{}", key, start, end, opt(" COUNT ", count)),
+            Command::XLen { key } => write!(f, "XLEN {}", key),
+            Command::XRead { count, block_ms, keys, ids } => {
+                write!(f, "XREAD{}{} STREAMS {} {}", opt(" COUNT ", count), opt(" BLOCK ", block_ms), keys.join(" "), ids.join(" "))
+            }
+            Command::Eval { script: code, keys, args } | Command::EvalSha { sha: code, keys, args } => {
+                write!(f, "{} {} {}", self.name(), Word(code), keys.len())?;
+                for key in keys {
+                    write!(f, " {}", key)?;
+                }
+                for arg in args {
+                    write!(f, " {}", Word(arg))?;
+                }
+                Ok(())
+            }
+            Command::ScriptLoad { script } => write!(f, "SCRIPT LOAD {}", Word(script)),
+            Command::ScriptExists { shas } => write!(f, "SCRIPT EXISTS {}", words(shas)),
+            Command::ScriptFlush => f.write_str("SCRIPT FLUSH"),
+            Command::JsonGet { key, path } => write!(f, "JSON.GET {}{}", key, opt(" ", &path.as_deref().map(Word))),
+            Command::JsonSet { key, path, value } => write!(f, "JSON.SET {} {} {}", key, Word(path), Word(value)),
+            Command::CreateTable { name, columns } => {
+                write!(f, "CREATE TABLE {} {}", name, columns.iter().map(column_def).collect::<Vec<_>>().join(" "))
+            }
+            Command::AlterTable { table, op } => match op {
+                super::AlterOp::Add(column, data_type) => write!(f, "ALTER TABLE {} ADD {}:{}", table, column, data_type),
+                super::AlterOp::Drop(column) => write!(f, "ALTER TABLE {} DROP {}", table, column),
+            },
+            Command::Insert { table, rows } => {
+                let rows: Vec<String> = rows.iter()
+                    .map(|row| format!("({})", row.iter().map(literal).collect::<Vec<_>>().join(", ")))
+                    .collect();
+                write!(f, "INSERT INTO {} VALUES {}", table, rows.join(", "))
+            }
+            Command::Select { table, alias, selector: sel, join: joins, filter: filter_by, group_by, having, order_by, limit, offset } => {
+                write!(f, "SELECT {} FROM {}{}", selector(sel), table, opt(" AS ", alias))?;
+                for clause in joins.iter().flatten() {
+                    f.write_str(&join(clause))?;
+                }
+                f.write_str(&filter(filter_by))?;
+                if let Some(columns) = group_by {
+                    write!(f, " GROUP BY {}", columns.join(", "))?;
+                }
+                if let Some(having) = having {
+                    write!(f, " HAVING {}", WireFilter(having))?;
+                }
+                if let Some(keys) = order_by {
+                    let keys: Vec<String> = keys.iter().map(|(col, asc)| format!("{}{}", col, if *asc { "" } else { " DESC" })).collect();
+                    write!(f, " ORDER BY {}", keys.join(", "))?;
+                }
+                write!(f, "{}{}", opt(" LIMIT ", limit), opt(" OFFSET ", offset))
+            }
+            Command::Explain { query, analyze } => write!(f, "EXPLAIN {}{}", if *analyze { "ANALYZE " } else { "" }, query),
+            Command::Declare { name, query } => write!(f, "DECLARE {} CURSOR FOR {}", name, query),
+            Command::Fetch { name, count: Some(n) } => write!(f, "FETCH {} FROM {}", n, name),
+            Command::Fetch { name, count: None } => write!(f, "FETCH ALL FROM {}", name),
+            Command::Close { name } => write!(f, "CLOSE {}", name.as_deref().unwrap_or("ALL")),
+            Command::VectorSearch { table, column, vector, limit, metric, threshold, filter: filter_by } => {
+                let vector: Vec<String> = vector.iter().map(f64::to_string).collect();
+                write!(f, "SEARCH {} {} [{}] LIMIT {}{}{}{}", table, column, vector.join(", "), limit,
+                    opt(" METRIC ", metric), opt(" THRESHOLD ", threshold), filter(filter_by))
+            }
+            Command::Update { table, filter: filter_by, set: (column, value) } => {
+                write!(f, "UPDATE {} SET {} = {}{}", table, column, literal(value), filter(filter_by))
+            }
+            Command::Delete { table, filter: filter_by } => write!(f, "DELETE FROM {}{}", table, filter(filter_by)),
+            Command::Ping => f.write_str("PING"),
+            Command::Quit => f.write_str("QUIT"),
+            Command::Reset => f.write_str("RESET"),
+            Command::ReadOnly => f.write_str("READONLY"),
+            Command::ReadWrite => f.write_str("READWRITE"),
+            Command::ObjectHelp => f.write_str("OBJECT HELP"),
+            Command::Save => f.write_str("SAVE"),
+            Command::BackupVerify { path } => write!(f, "BACKUP VERIFY {}", Pattern(path)),
+            Command::AofStats => f.write_str("AOF STATS"),
+            Command::AofCat { from, count } => write!(f, "AOF CAT {} {}", from, count),
+            Command::CreateIndex { index_name, table, column } => write!(f, "CREATE INDEX {} ON {}({})", index_name, table, column),
+            Command::CreateVectorIndex { index_name, table, column, dims, metric } => {
+                write!(f, "CREATE VECTOR INDEX {} ON {}({}) DIMS {} METRIC {}", index_name, table, column, dims, metric)
+            }
+            Command::DropIndex { index_name, table } => write!(f, "DROP INDEX {} ON {}", index_name, table),
+            Command::ShowIndexes { table } => write!(f, "SHOW INDEXES{}", opt(" FROM ", table)),
+            Command::ShowTables => f.write_str("SHOW TABLES"),
+            Command::ShowDatabases => f.write_str("SHOW DATABASES"),
+            Command::DropDatabase { name } => write!(f, "DROP DATABASE {}", Word(name)),
+            Command::Describe { table } => write!(f, "DESCRIBE {}", table),
+            Command::ShowCreateTable { table } => write!(f, "SHOW CREATE TABLE {}", table),
+            Command::Reindex { table, column } => write!(f, "REINDEX {}{}", table, opt(" ", column)),
+            Command::CheckTable { table } => write!(f, "CHECK TABLE {}", table),
+            Command::ExportTable { table } => write!(f, "EXPORT TABLE {}", table),
+            Command::ImportTable { table, payload, replace } => {
+                write!(f, "IMPORT TABLE {} {}{}", table, Word(payload), if *replace { " REPLACE" } else { "" })
+            }
+            Command::AdvisorSuggest => f.write_str("ADVISOR SUGGEST"),
+            Command::SetEx { key, value, ttl, jitter: j } => write!(f, "SETEX {} {} {}{}", key, ttl, Word(value), jitter(j)),
+            Command::Expire { key, seconds, jitter: j } => write!(f, "EXPIRE {} {}{}", key, seconds, jitter(j)),
+            Command::Ttl { key } => write!(f, "TTL {}", key),
+            Command::PExpireAt { key, timestamp_ms } => write!(f, "PEXPIREAT {} {}", key, timestamp_ms),
+            Command::Schedule { key, value, at_ms, push } => {
+                write!(f, "SCHEDULE {} {} AT {}{}", key, Word(value), at_ms, if *push { " PUSH" } else { "" })
+            }
+            Command::Unschedule { key, at_ms } => write!(f, "UNSCHEDULE {}{}", key, opt(" AT ", at_ms)),
+            Command::Auth { username, password } => write!(f, "AUTH{} {}", opt(" ", username), Word(password)),
+            Command::AclSetUser { username, password, rules } => write!(f, "ACL SETUSER {} {} {}", username, Word(password), words(rules)),
+            Command::AclGetUser { username } => write!(f, "ACL GETUSER {}", username),
+            Command::AclList => f.write_str("ACL LIST"),
+            Command::AclDelUser { username } => write!(f, "ACL DELUSER {}", username),
+            Command::AclUsage { username } => write!(f, "ACL USAGE{}", opt(" ", username)),
+            Command::ClientList => f.write_str("CLIENT LIST"),
+            Command::ActiveQueries => f.write_str("ACTIVE QUERIES"),
+            Command::KillQuery { id } => write!(f, "KILL QUERY {}", id),
+            Command::ClientKill { addr } => write!(f, "CLIENT KILL {}", Word(addr)),
+            Command::Incr { key } => write!(f, "INCR {}", key),
+            Command::Decr { key } => write!(f, "DECR {}", key),
+            Command::CIncr { key, by } => write!(f, "CINCR {} {}", key, by),
+            Command::RewriteAof => f.write_str("REWRITEAOF"),
+            Command::Use { db_name } => write!(f, "USE {}", Word(db_name)),
+            Command::SetOutput { format } => write!(f, "SET output = {}", match format {
+                OutputFormat::Text => "text",
+                OutputFormat::Json => "json",
+                OutputFormat::Resp => "resp",
+            }),
+            Command::SetBinding { mode } => write!(f, "SET binding = {}", match mode {
+                ArgBinding::Parsed => "parsed",
+                ArgBinding::Strict => "strict",
+            }),
+            Command::SetVariable { name, value } => match value {
+                UnifiedValue::Null => write!(f, "SET @{} = NULL", name),
+                UnifiedValue::Boolean(b) => write!(f, "SET @{} = {}", name, b),
+                UnifiedValue::Integer(i) => write!(f, "SET @{} = {}", name, i),
+                UnifiedValue::Float(n) => write!(f, "SET @{} = {}", name, Float(*n)),
+                other => write!(f, "SET @{} = {}", name, quote_string(&other.to_string())),
+            },
+            Command::ShowVariables => f.write_str("SHOW VARIABLES"),
+            Command::Begin => f.write_str("BEGIN"),
+            Command::Commit => f.write_str("COMMIT"),
+            Command::Rollback => f.write_str("ROLLBACK"),
+            Command::Watch { keys } => write!(f, "WATCH {}", keys.join(" ")),
+            Command::Unwatch => f.write_str("UNWATCH"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::parser::parse_command;
+
+    fn parse(line: &str) -> Command {
+        match parse_command(line) {
+            Ok((rest, cmd)) if rest.trim().is_empty() => cmd,
+            other => panic!("{} does not parse: {:?}", line, other),
+        }
+    }
+
+    #[test]
+    fn commands_parse_back_from_their_wire_form() {
+        let lines = [
+            "REPLICAOF 10.0.0.1 8569", "REPLICAOF NO ONE", "REPLICAOF PROMOTE", "REPLICAOF 'db://10.0.0.1:8569/data'",
+            "FAILOVER", "REPLCHECK 10.0.0.2:8570 TIMEOUT 100", "REPLCHECK", "PSYNC", "WAIT 2 500",
+            "INFO", "METRICS", "METRICS RESET", "MEMORY USAGE k", "MEMORY STATS", "CONFIG GET max_*", "CONFIG SET max_memory 64mb",
+            "CLUSTER INFO", "CLUSTER SLOTS", "CLUSTER MEET 10.0.0.3 8569", "CLUSTER ADDSLOTS 1 2 3", "CLUSTER DELSLOTS 4",
+            "CLUSTER FORGET node1", "CLUSTER FAILOVER", "CLUSTER SETSLOT 7 MIGRATING 10.0.0.3:8569", "CLUSTER SETSLOT 7 STABLE",
+            "MIGRATE 10.0.0.3 8569 SLOT 7 COUNT 10 AUTH s3cret", "MIGRATE h 1 SLOT 2", "ASKING",
+            "SET k v", "SET k \"a \\\"b\\\"\\nc\"", "SET k 'NULL'", "GET k", "GETRANGE k 0 -1", "SUBSTR k 1 2",
+            "GETEX k", "GETEX k EX 10", "GETEX k PXAT 5", "GETEX k PERSIST", "DEL a b", "DELETE a",
+            "SCAN 0 MATCH user:* COUNT 5", "SCAN 3", "KEYS *", "EXPORT KEYS MATCH a* TO /tmp/out.resp", "EXPORT KEYS TO 'my file'", "DUMP k", "RESTORE k 0 01ab", "RESTORE k 5000 01ab REPLACE ABSTTL",
+            "TYPE k", "EXISTS a b", "DBSIZE",
+            "LPUSH l a 'b c'", "RPUSH l x", "LPOP l", "RPOP l 2", "LRANGE l 0 -1",
+            "HSET h f 'v w'", "HGET h f", "HGETALL h", "SADD s a b", "SMEMBERS s",
+            "ZADD z NX CH 1.5 a -2 'b c'", "ZADD z GT 1e3 m", "ZRANGE z 0 -1", "ZSCORE z m", "ZPOPMIN z", "ZPOPMAX z 3",
+            "BZPOPMIN a b 1.5", "BZPOPMAX z 0",
+            "GEOADD g XX 13.361389 38.115556 Palermo", "GEODIST g a b KM",
+            "GEOSEARCH g FROMMEMBER a BYRADIUS 200 KM DESC COUNT 2 WITHCOORD WITHDIST", "GEOSEARCH g FROMLONLAT 15 37 BYRADIUS 1.5 M",
+            "TOPK.RESERVE t 5", "TOPK.ADD t a 'b c'", "TOPK.INCRBY t a 3 b 1", "TOPK.LIST t WITHCOUNT",
+            "SETBIT b 7 1", "GETBIT b 7", "BITCOUNT b", "BITCOUNT b 0 -1 BIT", "BITOP NOT d s", "BITOP XOR d a b",
+            "PFADD h", "PFADD h a b", "PFCOUNT a b", "PFMERGE d a b",
+            "XADD s * f 'v w'", "XADD s 5-1 a b c d", "XRANGE s - + COUNT 2", "XLEN s", "XREAD COUNT 2 BLOCK 0 STREAMS a b $ 0-0",
+            "EVAL \"return get(KEYS[1])\" 1 k arg", "EVALSHA abc 0", "SCRIPT LOAD 'return 1'", "SCRIPT EXISTS a b", "SCRIPT FLUSH",
+            "JSON.GET k", "JSON.GET k $.a", "JSON.SET k $ '{\"a\": 1}'",
+            "CREATE TABLE users id:int:pk email:string:notnull:unique role:string:default(member) note:string:default('a b')",
+            "CREATE TABLE orders id:int:pk user_id:int:fk(users.id):cascade emb:vector(3)",
+            "ALTER TABLE users ADD age:int", "ALTER TABLE users DROP age",
+            "INSERT users 1 'Ann Lee' NULL 'NULL'", "INSERT INTO users VALUES (1, a), (2, NULL)",
+            "SELECT users", "SELECT users WHERE id = 1",
+            "SELECT * FROM users u JOIN orders AS o ON u.id = o.user_id WHERE o.total > 10 AND (u.role = admin OR u.role = 'super user') ORDER BY u.id DESC, o.id LIMIT 5 OFFSET 10",
+            "SELECT DISTINCT role, LOWER(name) AS n FROM users",
+            "SELECT role, COUNT(*), SUM(age) FROM users GROUP BY role HAVING COUNT(*) > 1 AND SUM(age) < 100",
+            "SELECT COUNT(*) FROM users", "SELECT COUNT FROM users", "SELECT COUNT(DISTINCT role) FROM users", "SELECT AVG(age) FROM users",
+            "SELECT (SUM(age)) FROM users",
+            "SELECT price * 1.2 AS gross, (a + b) * c, a - b - c, a - -3, 'x', 2.0 FROM t",
+            "SELECT * FROM t WHERE DATE_DIFF(NOW_TS, created, DAY) < 7 AND data->name->>first LIKE 'A%'",
+            "SELECT * FROM t WHERE x IN (1, 'a b', \"c,d\") OR NOT (y BETWEEN -5 AND 5) OR z IS NULL OR w IS NOT NULL",
+            "SELECT * FROM t WHERE a = 1 OR b = 2 AND c = 3", "SELECT * FROM t WHERE (a = 1 OR b = 2) AND c = 3",
+            "SELECT * FROM t WHERE a = 1 AND (b = 2 AND c = 3)", "SELECT * FROM t WHERE price * 2 >= 10 AND x ILIKE '%y'",
+            "SELECT * FROM t OFFSET 2 ROWS FETCH FIRST 3 ROWS ONLY",
+            "EXPLAIN SELECT * FROM t WHERE id = 1", "EXPLAIN ANALYZE SELECT t",
+            "DECLARE c CURSOR FOR SELECT * FROM t ORDER BY id", "FETCH FROM c", "FETCH 5 FROM c", "FETCH ALL FROM c", "CLOSE c", "CLOSE ALL",
+            "SEARCH docs emb [1, -0.5, 0.25] 3", "SEARCH docs emb [1, 0] LIMIT 2 METRIC dot THRESHOLD 0.5 WHERE id > 3",
+            "UPDATE users SET role = 'a b' WHERE id = 1", "UPDATE users SET role = NULL",
+            "DELETE FROM users", "DELETE FROM users WHERE id IN (1, 2)",
+            "PING", "QUIT", "RESET", "READONLY", "READWRITE", "OBJECT HELP", "SAVE", "BACKUP VERIFY /tmp/b", "AOF STATS", "AOF CAT", "AOF CAT 5 10",
+            "CREATE INDEX idx ON users(email)", "CREATE INDEX jidx ON users(data->name)",
+            "CREATE VECTOR INDEX ON docs(emb) DIMS 3", "CREATE VECTOR INDEX v ON docs(emb) DIMS 3 METRIC euclidean",
+            "DROP INDEX idx ON users", "SHOW INDEXES", "SHOW INDEXES FROM users", "SHOW TABLES", "SHOW DATABASES", "DROP DATABASE old",
+            "DESCRIBE users", "DESC users", "SHOW CREATE TABLE users", "REINDEX users", "REINDEX users email", "CHECK TABLE users", "EXPORT TABLE users", "IMPORT TABLE users 01ab REPLACE", "ADVISOR SUGGEST",
+            "SETEX k 10 v", "SETEX k 10 'v w' JITTER 5", "EXPIRE k 10", "EXPIRE k 10 JITTER 20", "TTL k", "PEXPIREAT k 1700000000000",
+            "SCHEDULE k 'v w' AT 1700000000000", "SCHEDULE jobs j AT 5 PUSH", "UNSCHEDULE k", "UNSCHEDULE jobs AT 5",
+            "AUTH secret", "AUTH bob 'pass word'",
+            "ACL SETUSER bob \"$2b$12$abc/def.\" +@all -set ~user:* quota:cmds=10", "ACL GETUSER bob", "ACL LIST", "ACL DELUSER bob", "ACL USAGE", "ACL USAGE bob",
+            "CLIENT LIST", "ACTIVE QUERIES", "KILL QUERY 3", "CLIENT KILL 127.0.0.1:5000",
+            "INCR k", "DECR k", "CINCR k", "CINCR k -5", "REWRITEAOF", "USE data",
+            "SET output = json", "SET binding = strict", "SET @n = 5", "SET @f = 1.0", "SET @s = 'a b'", "SET @b = true", "SET @z = NULL", "SHOW VARIABLES",
+            "BEGIN", "COMMIT", "ROLLBACK", "WATCH a b", "UNWATCH",
+        ];
+        for line in lines {
+            let cmd = parse(line);
+            let wire = cmd.to_string();
+            assert_eq!(parse(&wire), cmd, "{} was written as {}", line, wire);
+        }
+    }
+
+    #[test]
+    fn values_are_quoted_only_when_needed() {
+        let adversarial = ["plain", "two words", "NULL", "null", "", "quote\"d", "back\\slash", "line\nbreak", "tab\t", "it's", "a,b", "(x)", "-5", "WHERE"];
+        for value in adversarial {
+            let set = Command::Set { key: "k".to_string(), value: value.to_string() };
+            assert_eq!(parse(&set.to_string()), set);
+
+            let insert = Command::Insert { table: "t".to_string(), rows: vec![vec![Some(value.to_string()), None]] };
+            assert_eq!(parse(&insert.to_string()), insert);
+
+            let update = Command::Update {
+                table: "t".to_string(),
+                filter: Some(Filter::Condition("name".to_string(), Operator::In, crate::query::join_value_list(&[value.to_string(), "x".to_string()]))),
+                set: ("name".to_string(), Some(value.to_string())),
+            };
+            assert_eq!(parse(&update.to_string()), update);
+        }
+        assert_eq!(Command::Set { key: "k".to_string(), value: "v".to_string() }.to_string(), "SET k v");
+        assert_eq!(Command::Set { key: "k".to_string(), value: "NULL".to_string() }.to_string(), "SET k \"NULL\"");
+        assert_eq!(Command::PExpireAt { key: "k".to_string(), timestamp_ms: 5 }.to_string(), "PEXPIREAT k 5");
+    }
+}
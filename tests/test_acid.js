@@ -57,11 +57,11 @@ async function runTest() {
 
         res = await sendCommand(client, 'SET acid_test_1 100');
         console.log('SET (buffered):', res);
-        if (res !== 'QUEUED') throw new Error("Expected QUEUED, got " + res);
+        if (res !== 'QUEUED' && res !== '+QUEUED') throw new Error("Expected QUEUED, got " + res);
 
         res = await sendCommand(client, 'GET acid_test_1');
         console.log('GET (buffered):', res);
-        if (res !== 'QUEUED') throw new Error("Expected QUEUED");
+        if (res !== 'QUEUED' && res !== '+QUEUED') throw new Error("Expected QUEUED");
 
         res = await sendCommand(client, 'COMMIT');
         console.log('COMMIT:', res);
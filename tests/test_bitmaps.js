@@ -0,0 +1,92 @@
+const net = require('net');
+
+const PORT = 8569;
+
+function connect() {
+    return new Promise((resolve) => {
+        const client = new net.Socket();
+        client.connect(PORT, 'localhost', () => resolve(client));
+    });
+}
+
+function sendCommand(client, command) {
+    return new Promise((resolve, reject) => {
+        client.write(command + '\n');
+        client.once('data', (data) => {
+            const resp = data.toString().trim();
+            console.log(`REQ: ${command} -> RESP: ${resp.replace(/\r\n/g, ' ')}`);
+            resolve(resp);
+        });
+        client.once('error', reject);
+    });
+}
+
+async function runTests() {
+    const client = await connect();
+    try {
+        console.log('Connected to ToriDB');
+        await sendCommand(client, 'AUTH default secret');
+        const key = 'bits' + Date.now();
+
+        // Test 1: SETBIT returns the previous bit, GETBIT reads it back
+        console.log('\n--- Test 1: SETBIT / GETBIT ---');
+        let result = await sendCommand(client, `SETBIT ${key}:mon 10 1`);
+        if (result !== ':0') {
+            throw new Error('SETBIT: Expected previous bit 0');
+        }
+        result = await sendCommand(client, `SETBIT ${key}:mon 10 1`);
+        if (result !== ':1') {
+            throw new Error('SETBIT: Expected previous bit 1');
+        }
+        result = await sendCommand(client, `GETBIT ${key}:mon 11`);
+        if (result !== ':0') {
+            throw new Error('GETBIT: Unset bit read as 1');
+        }
+        console.log('Test 1 PASSED: bits set and read');
+
+        // Test 2: BITCOUNT over the whole bitmap and over ranges
+        console.log('\n--- Test 2: BITCOUNT ---');
+        await sendCommand(client, `SETBIT ${key}:mon 3 1`);
+        result = await sendCommand(client, `BITCOUNT ${key}:mon`);
+        if (result !== ':2') {
+            throw new Error('BITCOUNT: Expected 2');
+        }
+        result = await sendCommand(client, `BITCOUNT ${key}:mon 1 1`);
+        if (result !== ':1') {
+            throw new Error('BITCOUNT: Expected 1 in the second byte');
+        }
+        result = await sendCommand(client, `BITCOUNT ${key}:mon 0 4 BIT`);
+        if (result !== ':1') {
+            throw new Error('BITCOUNT: Expected 1 in the first bits');
+        }
+        console.log('Test 2 PASSED: bits counted');
+
+        // Test 3: BITOP combines bitmaps
+        console.log('\n--- Test 3: BITOP ---');
+        await sendCommand(client, `SETBIT ${key}:tue 10 1`);
+        await sendCommand(client, `SETBIT ${key}:tue 20 1`);
+        await sendCommand(client, `BITOP AND ${key}:both ${key}:mon ${key}:tue`);
+        result = await sendCommand(client, `BITCOUNT ${key}:both`);
+        if (result !== ':1') {
+            throw new Error('BITOP AND: Expected 1 common bit');
+        }
+        result = await sendCommand(client, `BITOP OR ${key}:any ${key}:mon ${key}:tue`);
+        if (result !== ':3') {
+            throw new Error('BITOP OR: Expected a 3 byte result');
+        }
+        result = await sendCommand(client, `BITCOUNT ${key}:any`);
+        if (result !== ':3') {
+            throw new Error('BITOP OR: Expected 3 bits');
+        }
+        console.log('Test 3 PASSED: bitmaps combined');
+
+        console.log('\n=== ALL BITMAP TESTS PASSED ===');
+    } catch (err) {
+        console.error('Test Failed:', err);
+        process.exit(1);
+    } finally {
+        client.end();
+    }
+}
+
+runTests();
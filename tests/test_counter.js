@@ -0,0 +1,65 @@
+const net = require('net');
+
+const client = new net.Socket();
+const PORT = 8569;
+
+function sendCommand(command) {
+    return new Promise((resolve, reject) => {
+        client.write(command + '\n');
+        client.once('data', (data) => {
+            const resp = data.toString().trim();
+            console.log(`REQ: ${command} -> RESP: ${resp}`);
+            resolve(resp);
+        });
+        client.once('error', reject);
+    });
+}
+
+async function runTests() {
+    try {
+        await new Promise((resolve) => client.connect(PORT, 'localhost', resolve));
+        console.log('Connected to ToriDB');
+
+        await sendCommand('AUTH default secret');
+        const key = 'views' + Date.now();
+
+        // Test 1: CINCR creates the counter and returns its total
+        console.log('\n--- Test 1: CINCR key [increment] ---');
+        let result = await sendCommand(`CINCR ${key}`);
+        if (result !== ':1') {
+            throw new Error('CINCR: Expected :1 for a new counter');
+        }
+        await sendCommand(`CINCR ${key} 10`);
+        result = await sendCommand(`CINCR ${key} -3`);
+        if (result !== ':8') {
+            throw new Error('CINCR: Expected total :8');
+        }
+        console.log('Test 1 PASSED: increments are summed');
+
+        // Test 2: The counter reads like any key
+        console.log('\n--- Test 2: GET / TYPE / DEL ---');
+        result = await sendCommand(`GET ${key}`);
+        if (!result.endsWith('8')) {
+            throw new Error('GET: Expected the counter total');
+        }
+        result = await sendCommand(`TYPE ${key}`);
+        if (result !== '+counter') {
+            throw new Error('TYPE: Expected counter');
+        }
+        await sendCommand(`DEL ${key}`);
+        result = await sendCommand(`GET ${key}`);
+        if (result !== '$-1') {
+            throw new Error('DEL: Counter still present');
+        }
+        console.log('Test 2 PASSED: counter behaves as a key');
+
+        console.log('\n=== ALL COUNTER TESTS PASSED ===');
+    } catch (err) {
+        console.error('Test Failed:', err);
+        process.exit(1);
+    } finally {
+        client.end();
+    }
+}
+
+runTests();
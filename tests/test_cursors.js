@@ -0,0 +1,94 @@
+const net = require('net');
+
+const PORT = 8569;
+
+function connect() {
+    return new Promise((resolve) => {
+        const client = new net.Socket();
+        client.connect(PORT, 'localhost', () => resolve(client));
+    });
+}
+
+function sendCommand(client, command) {
+    return new Promise((resolve, reject) => {
+        client.write(command + '\n');
+        client.once('data', (data) => {
+            const resp = data.toString().trim();
+            console.log(`REQ: ${command} -> RESP: ${resp.replace(/\r\n/g, ' ')}`);
+            resolve(resp);
+        });
+        client.once('error', reject);
+    });
+}
+
+// Ids of the rows in a text reply, in order
+function ids(resp) {
+    return [...resp.matchAll(/^\["(\d+)"/gm)].map(m => m[1]);
+}
+
+async function runTests() {
+    const client = await connect();
+    try {
+        console.log('Connected to ToriDB');
+        await sendCommand(client, 'AUTH default secret');
+        const table = 'cur' + Date.now();
+        await sendCommand(client, `CREATE TABLE ${table} id:int:pk name:string`);
+        for (let i = 1; i <= 25; i++) {
+            await sendCommand(client, `INSERT ${table} ${i} name${i}`);
+        }
+
+        // Test 1: pages of a result set
+        console.log('\n--- Test 1: DECLARE / FETCH ---');
+        let result = await sendCommand(client, `DECLARE page CURSOR FOR SELECT id, name FROM ${table} WHERE id > 5 ORDER BY id`);
+        if (result !== '+OK') {
+            throw new Error('DECLARE: Expected OK');
+        }
+        await sendCommand(client, `INSERT ${table} 100 late`);
+        const seen = [];
+        for (;;) {
+            const page = ids(await sendCommand(client, `FETCH 8 FROM page`));
+            if (page.length === 0) break;
+            if (page.length > 8) {
+                throw new Error('FETCH: Expected at most 8 rows');
+            }
+            seen.push(...page);
+        }
+        if (seen.length !== 20 || seen[0] !== '6' || seen[19] !== '25') {
+            throw new Error('FETCH: Expected rows 6 to 25 once each, without the later insert');
+        }
+        console.log('Test 1 PASSED: rows paged in order');
+
+        // Test 2: NEXT, ALL and CLOSE
+        console.log('\n--- Test 2: NEXT / ALL / CLOSE ---');
+        await sendCommand(client, `DECLARE rest CURSOR FOR SELECT id FROM ${table} WHERE id <= 3 ORDER BY id`);
+        if (ids(await sendCommand(client, `FETCH NEXT FROM rest`)).join() !== '1') {
+            throw new Error('FETCH NEXT: Expected the first row');
+        }
+        if (ids(await sendCommand(client, `FETCH ALL FROM rest`)).join() !== '2,3') {
+            throw new Error('FETCH ALL: Expected the remaining rows');
+        }
+        result = await sendCommand(client, `CLOSE rest`);
+        if (result !== '+OK') {
+            throw new Error('CLOSE: Expected OK');
+        }
+        result = await sendCommand(client, `FETCH NEXT FROM rest`);
+        if (!result.includes("Cursor 'rest' does not exist")) {
+            throw new Error('FETCH: Expected an error on a closed cursor');
+        }
+        await sendCommand(client, `CLOSE ALL`);
+        result = await sendCommand(client, `FETCH NEXT FROM page`);
+        if (!result.startsWith('-')) {
+            throw new Error('CLOSE ALL: Expected every cursor closed');
+        }
+        console.log('Test 2 PASSED: cursors closed');
+
+        console.log('\n=== ALL CURSOR TESTS PASSED ===');
+    } catch (err) {
+        console.error('Test Failed:', err);
+        process.exit(1);
+    } finally {
+        client.end();
+    }
+}
+
+runTests();
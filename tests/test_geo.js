@@ -0,0 +1,88 @@
+const net = require('net');
+
+const PORT = 8569;
+
+function connect() {
+    return new Promise((resolve) => {
+        const client = new net.Socket();
+        client.connect(PORT, 'localhost', () => resolve(client));
+    });
+}
+
+function sendCommand(client, command) {
+    return new Promise((resolve, reject) => {
+        client.write(command + '\n');
+        client.once('data', (data) => {
+            const resp = data.toString().trim();
+            console.log(`REQ: ${command} -> RESP: ${resp.replace(/\r\n/g, ' ')}`);
+            resolve(resp);
+        });
+        client.once('error', reject);
+    });
+}
+
+async function runTests() {
+    const client = await connect();
+    try {
+        console.log('Connected to ToriDB');
+        await sendCommand(client, 'AUTH default secret');
+        const key = 'geo' + Date.now();
+
+        // Test 1: GEOADD stores locations as sorted set members
+        console.log('\n--- Test 1: GEOADD ---');
+        let result = await sendCommand(client, `GEOADD ${key} 13.361389 38.115556 palermo 15.087269 37.502669 catania`);
+        if (result !== ':2') {
+            throw new Error('GEOADD: Expected 2 members added');
+        }
+        result = await sendCommand(client, `ZSCORE ${key} palermo`);
+        if (!result.includes('3479099956230698')) {
+            throw new Error('GEOADD: Expected the geohash as score');
+        }
+        result = await sendCommand(client, `GEOADD ${key} 200 10 nowhere`);
+        if (!result.startsWith('-')) {
+            throw new Error('GEOADD: Expected an error for an invalid longitude');
+        }
+        console.log('Test 1 PASSED: locations added');
+
+        // Test 2: GEODIST in several units
+        console.log('\n--- Test 2: GEODIST ---');
+        result = await sendCommand(client, `GEODIST ${key} palermo catania`);
+        if (!result.includes('166274.15')) {
+            throw new Error('GEODIST: Expected about 166274 meters');
+        }
+        result = await sendCommand(client, `GEODIST ${key} palermo catania km`);
+        if (!result.includes('166.2742')) {
+            throw new Error('GEODIST: Expected about 166 km');
+        }
+        result = await sendCommand(client, `GEODIST ${key} palermo rome`);
+        if (result !== '$-1') {
+            throw new Error('GEODIST: Expected nil for a missing member');
+        }
+        console.log('Test 2 PASSED: distances computed');
+
+        // Test 3: GEOSEARCH BYRADIUS, nearest first
+        console.log('\n--- Test 3: GEOSEARCH ---');
+        result = await sendCommand(client, `GEOSEARCH ${key} FROMLONLAT 15 37 BYRADIUS 100 km`);
+        if (!result.includes('catania') || result.includes('palermo')) {
+            throw new Error('GEOSEARCH: Expected only catania within 100 km');
+        }
+        result = await sendCommand(client, `GEOSEARCH ${key} FROMLONLAT 15 37 BYRADIUS 200 km`);
+        if (result.indexOf('catania') > result.indexOf('palermo')) {
+            throw new Error('GEOSEARCH: Expected catania (nearest) first');
+        }
+        result = await sendCommand(client, `GEOSEARCH ${key} FROMMEMBER palermo BYRADIUS 10 km WITHDIST`);
+        if (!result.includes('palermo') || !result.includes('0.0000') || result.includes('catania')) {
+            throw new Error('GEOSEARCH: Expected palermo at distance 0');
+        }
+        console.log('Test 3 PASSED: radius searches');
+
+        console.log('\n=== ALL GEO TESTS PASSED ===');
+    } catch (err) {
+        console.error('Test Failed:', err);
+        process.exit(1);
+    } finally {
+        client.end();
+    }
+}
+
+runTests();
@@ -0,0 +1,79 @@
+const net = require('net');
+
+const PORT = 8569;
+
+function connect() {
+    return new Promise((resolve) => {
+        const client = new net.Socket();
+        client.connect(PORT, 'localhost', () => resolve(client));
+    });
+}
+
+function sendCommand(client, command) {
+    return new Promise((resolve, reject) => {
+        client.write(command + '\n');
+        client.once('data', (data) => {
+            const resp = data.toString().trim();
+            console.log(`REQ: ${command} -> RESP: ${resp.replace(/\r\n/g, ' ')}`);
+            resolve(resp);
+        });
+        client.once('error', reject);
+    });
+}
+
+async function runTests() {
+    const client = await connect();
+    try {
+        console.log('Connected to ToriDB');
+        await sendCommand(client, 'AUTH default secret');
+        const key = 'hll' + Date.now();
+
+        // Test 1: PFADD reports changes, PFCOUNT counts distinct elements
+        console.log('\n--- Test 1: PFADD / PFCOUNT ---');
+        let result = await sendCommand(client, `PFADD ${key}:a x y z`);
+        if (result !== ':1') {
+            throw new Error('PFADD: Expected a change');
+        }
+        result = await sendCommand(client, `PFADD ${key}:a x`);
+        if (result !== ':0') {
+            throw new Error('PFADD: Repeated element changed the estimate');
+        }
+        result = await sendCommand(client, `PFCOUNT ${key}:a`);
+        if (result !== ':3') {
+            throw new Error('PFCOUNT: Expected 3');
+        }
+        console.log('Test 1 PASSED: distinct elements counted');
+
+        // Test 2: PFCOUNT of several keys and PFMERGE give the union
+        console.log('\n--- Test 2: Union ---');
+        await sendCommand(client, `PFADD ${key}:b z w`);
+        result = await sendCommand(client, `PFCOUNT ${key}:a ${key}:b`);
+        if (result !== ':4') {
+            throw new Error('PFCOUNT: Expected a union of 4');
+        }
+        await sendCommand(client, `PFMERGE ${key}:all ${key}:a ${key}:b`);
+        result = await sendCommand(client, `PFCOUNT ${key}:all`);
+        if (result !== ':4') {
+            throw new Error('PFMERGE: Expected a union of 4');
+        }
+        console.log('Test 2 PASSED: unions counted');
+
+        // Test 3: Other strings are refused
+        console.log('\n--- Test 3: Wrong type ---');
+        await sendCommand(client, `SET ${key}:s plain`);
+        result = await sendCommand(client, `PFADD ${key}:s x`);
+        if (!result.startsWith('-')) {
+            throw new Error('PFADD: Plain string accepted');
+        }
+        console.log('Test 3 PASSED: plain string refused');
+
+        console.log('\n=== ALL HLL TESTS PASSED ===');
+    } catch (err) {
+        console.error('Test Failed:', err);
+        process.exit(1);
+    } finally {
+        client.end();
+    }
+}
+
+runTests();
@@ -0,0 +1,69 @@
+const net = require('net');
+
+const client = new net.Socket();
+const PORT = 8569;
+
+function sendCommand(command) {
+    return new Promise((resolve, reject) => {
+        client.write(command + '\n');
+        client.once('data', (data) => {
+            const resp = data.toString().trim();
+            console.log(`REQ: ${command} -> RESP: ${resp}`);
+            resolve(resp);
+        });
+        client.once('error', reject);
+    });
+}
+
+async function runTests() {
+    try {
+        await new Promise((resolve) => client.connect(PORT, 'localhost', resolve));
+        console.log('Connected to ToriDB');
+
+        await sendCommand('AUTH default secret');
+
+        console.log('\n--- Setup: keys and a table ---');
+        const prefix = 'kvt' + Date.now();
+        const tableName = 'members_' + Date.now();
+        await sendCommand(`HSET ${prefix}:1 name Alice`);
+        await sendCommand(`HSET ${prefix}:2 name Bob`);
+        await sendCommand(`SET ${prefix}_other 1`);
+        await sendCommand(`CREATE TABLE ${tableName} name:string:pk city:string`);
+        await sendCommand(`INSERT ${tableName} Bob Rome`);
+
+        // Test 1: JSON path over the values of matching keys
+        console.log('\n--- Test 1: SELECT value->name FROM __kv__ ---');
+        let result = await sendCommand(`SELECT value->name FROM __kv__ WHERE key LIKE '${prefix}:%'`);
+        if (!result.includes('Alice') || !result.includes('Bob')) {
+            throw new Error('__kv__: Missing expected values (Alice, Bob)');
+        }
+        if (result.includes('"1"')) {
+            throw new Error('__kv__: Included a key outside the prefix');
+        }
+        console.log('Test 1 PASSED: keys are queryable');
+
+        // Test 2: Join with a regular table
+        console.log('\n--- Test 2: JOIN __kv__ with a table ---');
+        result = await sendCommand(`SELECT __kv__.key, ${tableName}.city FROM __kv__ JOIN ${tableName} ON __kv__.value->name = ${tableName}.name`);
+        if (!result.includes(`${prefix}:2`) || !result.includes('Rome') || result.includes(`${prefix}:1`)) {
+            throw new Error('__kv__ join: Unexpected rows');
+        }
+        console.log('Test 2 PASSED: __kv__ joins tables');
+
+        // Test 3: The name is reserved
+        result = await sendCommand('CREATE TABLE __kv__ id:int:pk');
+        if (!result.includes('reserved')) {
+            throw new Error('__kv__: CREATE TABLE should fail');
+        }
+        console.log('Test 3 PASSED: __kv__ is reserved');
+
+        console.log('\n=== ALL KV TABLE TESTS PASSED ===');
+    } catch (err) {
+        console.error('Test Failed:', err);
+        process.exit(1);
+    } finally {
+        client.end();
+    }
+}
+
+runTests();
@@ -0,0 +1,73 @@
+const net = require('net');
+
+const client = new net.Socket();
+const PORT = 8569;
+
+function sendCommand(command) {
+    return new Promise((resolve, reject) => {
+        client.write(command + '\n');
+        client.once('data', (data) => {
+            const resp = data.toString().trim();
+            console.log(`REQ: ${command} -> RESP: ${resp}`);
+            resolve(resp);
+        });
+        client.once('error', reject);
+    });
+}
+
+const sleep = (ms) => new Promise((resolve) => setTimeout(resolve, ms));
+
+async function runTests() {
+    try {
+        await new Promise((resolve) => client.connect(PORT, 'localhost', resolve));
+        console.log('Connected to ToriDB');
+
+        await sendCommand('AUTH default secret');
+        const prefix = 'sched' + Date.now();
+
+        // Test 1: A scheduled key stays invisible until its time
+        console.log('\n--- Test 1: SCHEDULE key value AT ---');
+        await sendCommand(`SCHEDULE ${prefix}:greeting "hello world" AT ${Date.now() + 400}`);
+        let result = await sendCommand(`GET ${prefix}:greeting`);
+        if (result !== '$-1') {
+            throw new Error('SCHEDULE: Key visible before its time');
+        }
+        await sleep(700);
+        result = await sendCommand(`GET ${prefix}:greeting`);
+        if (!result.includes('hello world')) {
+            throw new Error('SCHEDULE: Key not set once due');
+        }
+        console.log('Test 1 PASSED: key set at its time');
+
+        // Test 2: PUSH appends to a list, in due order
+        console.log('\n--- Test 2: SCHEDULE ... PUSH ---');
+        const now = Date.now();
+        await sendCommand(`SCHEDULE ${prefix}:jobs second AT ${now + 500} PUSH`);
+        await sendCommand(`SCHEDULE ${prefix}:jobs first AT ${now + 300} PUSH`);
+        await sendCommand(`RPUSH ${prefix}:jobs now`);
+        await sleep(800);
+        result = await sendCommand(`LRANGE ${prefix}:jobs 0 -1`);
+        if (result.indexOf('now') > result.indexOf('first') || result.indexOf('first') > result.indexOf('second')) {
+            throw new Error('SCHEDULE PUSH: Unexpected list order');
+        }
+        console.log('Test 2 PASSED: elements pushed in due order');
+
+        // Test 3: A time already past applies the write right away
+        console.log('\n--- Test 3: SCHEDULE in the past ---');
+        await sendCommand(`SCHEDULE ${prefix}:late 1 AT 1`);
+        result = await sendCommand(`GET ${prefix}:late`);
+        if (!result.includes('1')) {
+            throw new Error('SCHEDULE: Past write not applied');
+        }
+        console.log('Test 3 PASSED: past writes apply immediately');
+
+        console.log('\n=== ALL SCHEDULE TESTS PASSED ===');
+    } catch (err) {
+        console.error('Test Failed:', err);
+        process.exit(1);
+    } finally {
+        client.end();
+    }
+}
+
+runTests();
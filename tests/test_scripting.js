@@ -0,0 +1,90 @@
+const net = require('net');
+
+const PORT = 8569;
+
+function connect() {
+    return new Promise((resolve) => {
+        const client = new net.Socket();
+        client.connect(PORT, 'localhost', () => resolve(client));
+    });
+}
+
+function sendCommand(client, command) {
+    return new Promise((resolve, reject) => {
+        client.write(command + '\n');
+        client.once('data', (data) => {
+            const resp = data.toString().trim();
+            console.log(`REQ: ${command} -> RESP: ${resp.replace(/\r\n/g, ' ')}`);
+            resolve(resp);
+        });
+        client.once('error', reject);
+    });
+}
+
+// Allows ARGV[0] hits per window of ARGV[1] seconds; nil once over the limit
+const LIMITER = `'let n = incr(KEYS[0]); if n == 1 { command(["EXPIRE", KEYS[0], ARGV[1]]); } n <= parse_int(ARGV[0])'`;
+
+async function runTests() {
+    const client = await connect();
+    try {
+        console.log('Connected to ToriDB');
+        await sendCommand(client, 'AUTH default secret');
+        const key = 'limit' + Date.now();
+
+        // Test 1: a rate limiter reads and writes in one step
+        console.log('\n--- Test 1: EVAL rate limiter ---');
+        for (const expected of [':1', ':1', '$-1']) {
+            const result = await sendCommand(client, `EVAL ${LIMITER} 1 ${key} 2 60`);
+            if (result !== expected) {
+                throw new Error(`EVAL: Expected ${expected}`);
+            }
+        }
+        let result = await sendCommand(client, `TTL ${key}`);
+        if (!result.startsWith(':') || parseInt(result.slice(1)) <= 0) {
+            throw new Error('EVAL: Expected the window to expire');
+        }
+        console.log('Test 1 PASSED: third hit refused');
+
+        // Test 2: EVALSHA runs the cached script
+        console.log('\n--- Test 2: SCRIPT LOAD / EVALSHA ---');
+        result = await sendCommand(client, `SCRIPT LOAD '[KEYS[0], ARGV[0]]'`);
+        const sha = result.split('\r\n')[1];
+        if (!/^[0-9a-f]{40}$/.test(sha)) {
+            throw new Error('SCRIPT LOAD: Expected a SHA1');
+        }
+        result = await sendCommand(client, `EVALSHA ${sha} 1 ${key} hello`);
+        if (!result.includes(key) || !result.includes('hello')) {
+            throw new Error('EVALSHA: Expected KEYS and ARGV back');
+        }
+        result = await sendCommand(client, `EVALSHA ${'0'.repeat(40)} 0`);
+        if (!result.startsWith('-NOSCRIPT')) {
+            throw new Error('EVALSHA: Expected NOSCRIPT for an unknown script');
+        }
+        console.log('Test 2 PASSED: cached script ran');
+
+        // Test 3: a failing script leaves nothing behind
+        console.log('\n--- Test 3: rollback ---');
+        result = await sendCommand(client, `EVAL 'set(KEYS[0], "x"); throw "stop"' 1 ${key}:other`);
+        if (!result.includes('stop')) {
+            throw new Error('EVAL: Expected the script error');
+        }
+        result = await sendCommand(client, `GET ${key}:other`);
+        if (result !== '$-1') {
+            throw new Error('EVAL: Expected the write to be rolled back');
+        }
+        result = await sendCommand(client, `EVAL 'get("elsewhere")' 1 ${key}`);
+        if (!result.includes('undeclared key')) {
+            throw new Error('EVAL: Expected undeclared keys to be refused');
+        }
+        console.log('Test 3 PASSED: all-or-nothing');
+
+        console.log('\n=== ALL SCRIPTING TESTS PASSED ===');
+    } catch (err) {
+        console.error('Test Failed:', err);
+        process.exit(1);
+    } finally {
+        client.end();
+    }
+}
+
+runTests();
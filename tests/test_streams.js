@@ -0,0 +1,89 @@
+const net = require('net');
+
+const PORT = 8569;
+
+function connect() {
+    return new Promise((resolve) => {
+        const client = new net.Socket();
+        client.connect(PORT, 'localhost', () => resolve(client));
+    });
+}
+
+function sendCommand(client, command) {
+    return new Promise((resolve, reject) => {
+        client.write(command + '\n');
+        client.once('data', (data) => {
+            const resp = data.toString().trim();
+            console.log(`REQ: ${command} -> RESP: ${resp.replace(/\r\n/g, ' ')}`);
+            resolve(resp);
+        });
+        client.once('error', reject);
+    });
+}
+
+const sleep = (ms) => new Promise((resolve) => setTimeout(resolve, ms));
+
+async function runTests() {
+    const client = await connect();
+    const waiter = await connect();
+    try {
+        console.log('Connected to ToriDB');
+        await sendCommand(client, 'AUTH default secret');
+        await sendCommand(waiter, 'AUTH default secret');
+        const key = 'stream' + Date.now();
+
+        // Test 1: XADD assigns growing IDs, XLEN counts entries
+        console.log('\n--- Test 1: XADD / XLEN ---');
+        await sendCommand(client, `XADD ${key} 5-1 type click`);
+        let result = await sendCommand(client, `XADD ${key} 5-* type view`);
+        if (!result.includes('5-2')) {
+            throw new Error('XADD: Expected the next sequence number');
+        }
+        result = await sendCommand(client, `XADD ${key} 4-0 type old`);
+        if (!result.startsWith('-')) {
+            throw new Error('XADD: ID below the last one accepted');
+        }
+        result = await sendCommand(client, `XLEN ${key}`);
+        if (result !== ':2') {
+            throw new Error('XLEN: Expected 2 entries');
+        }
+        console.log('Test 1 PASSED: IDs only grow');
+
+        // Test 2: XRANGE returns entries in ID order
+        console.log('\n--- Test 2: XRANGE ---');
+        result = await sendCommand(client, `XRANGE ${key} - + COUNT 1`);
+        if (!result.includes('5-1') || result.includes('5-2') || !result.includes('click')) {
+            throw new Error('XRANGE: Expected only the first entry');
+        }
+        console.log('Test 2 PASSED: range read');
+
+        // Test 3: XREAD BLOCK waits for an entry added after the call
+        console.log('\n--- Test 3: XREAD BLOCK wakes on XADD ---');
+        const pending = sendCommand(waiter, `XREAD BLOCK 5000 STREAMS ${key} $`);
+        await sleep(200);
+        await sendCommand(client, `XADD ${key} * type buy`);
+        result = await pending;
+        if (!result.includes(key) || !result.includes('buy') || result.includes('click')) {
+            throw new Error('XREAD: New entry not delivered');
+        }
+        console.log('Test 3 PASSED: blocked read woken by XADD');
+
+        // Test 4: XREAD BLOCK times out with nil
+        console.log('\n--- Test 4: XREAD timeout ---');
+        result = await sendCommand(client, `XREAD BLOCK 200 STREAMS ${key} $`);
+        if (result !== '$-1') {
+            throw new Error('XREAD: Expected nil on timeout');
+        }
+        console.log('Test 4 PASSED: timeout returns nil');
+
+        console.log('\n=== ALL STREAM TESTS PASSED ===');
+    } catch (err) {
+        console.error('Test Failed:', err);
+        process.exit(1);
+    } finally {
+        client.end();
+        waiter.end();
+    }
+}
+
+runTests();
@@ -13,7 +13,8 @@ async function testTx() {
         await db.beginTransaction();
         await db.decr("bal:A");
         await db.incr("bal:B");
-        await db.commit();
+        const replies = await db.commit();
+        console.assert(JSON.stringify(replies) === "[99,1]", `COMMIT replies not typed: ${JSON.stringify(replies)}`);
 
         const a = await db.get("bal:A");
         const b = await db.get("bal:B");
@@ -29,6 +30,50 @@ async function testTx() {
         console.assert(a2 == 99, `Rollback failed: A=${a2}`);
         console.log("PASS: ROLLBACK");
 
+        // Schema migration: one failing statement undoes the whole script
+        await db.beginTransaction();
+        await db.query("CREATE TABLE tx_accounts id:int:pk owner:string");
+        await db.query("CREATE INDEX tx_owner ON tx_accounts(owner)");
+        await db.query("INSERT tx_accounts 1 alice");
+        await db.query("INSERT tx_accounts 1 bob"); // Duplicate primary key
+        const failed = await db.commit().then(() => false, () => true);
+        console.assert(failed, "Migration with a failing statement committed");
+        const tables = await db.query("SHOW TABLES");
+        console.assert(!JSON.stringify(tables).includes("tx_accounts"), `Table survived rollback: ${JSON.stringify(tables)}`);
+        console.log("PASS: DDL ROLLBACK");
+
+        // A failing command undoes the key-value writes queued before it
+        await db.beginTransaction();
+        await db.decr("bal:A");
+        await db.set("tx:new", "x");
+        await db.query("INSERT tx_missing 1 a"); // No such table
+        const kvFailed = await db.commit().then(() => false, () => true);
+        console.assert(kvFailed, "Transaction with a failing command committed");
+        const a3 = await db.get("bal:A");
+        const created = await db.get("tx:new");
+        console.assert(a3 == 99 && created == null, `Partial commit: A=${a3}, tx:new=${created}`);
+        console.log("PASS: KV ROLLBACK");
+
+        // WATCH: a write by another client in between aborts the commit
+        const other = new ToriDB("db://default:secret+127.0.0.1:8569/test_tx");
+        await other.connect();
+        await db.watch("bal:A");
+        await other.set("bal:A", 50);
+        await db.beginTransaction();
+        await db.set("bal:A", 0);
+        const aborted = await db.commit();
+        const a4 = await db.get("bal:A");
+        console.assert(aborted === null && a4 == 50, `Watched commit went through: ${JSON.stringify(aborted)}, A=${a4}`);
+
+        await db.watch("bal:A");
+        await other.set("bal:B", 7);
+        await db.beginTransaction();
+        await db.set("bal:A", 0);
+        const applied = await db.commit();
+        console.assert(Array.isArray(applied) && await db.get("bal:A") == 0, `Unrelated write aborted the commit: ${JSON.stringify(applied)}`);
+        other.disconnect();
+        console.log("PASS: WATCH");
+
     } catch (e) {
         console.error("FAIL:", e);
     } finally {
@@ -0,0 +1,144 @@
+const net = require('net');
+
+const PORT = 8569;
+
+function connect() {
+    return new Promise((resolve) => {
+        const client = new net.Socket();
+        client.connect(PORT, 'localhost', () => resolve(client));
+    });
+}
+
+function sendCommand(client, command) {
+    return new Promise((resolve, reject) => {
+        client.write(command + '\n');
+        client.once('data', (data) => {
+            const resp = data.toString().trim();
+            console.log(`REQ: ${command} -> RESP: ${resp.replace(/\r\n/g, ' ')}`);
+            resolve(resp);
+        });
+        client.once('error', reject);
+    });
+}
+
+// Ids of the rows in a SEARCH reply (score, id, emb), best first
+function ids(resp) {
+    return [...resp.matchAll(/^\["[^"]*", "(\d+)"/gm)].map(m => m[1]);
+}
+
+async function runTests() {
+    const client = await connect();
+    try {
+        console.log('Connected to ToriDB');
+        await sendCommand(client, 'AUTH default secret');
+        const table = 'vidx' + Date.now();
+        await sendCommand(client, `CREATE TABLE ${table} id:int:pk emb:vector`);
+        for (let i = 1; i <= 200; i++) {
+            const angle = i * Math.PI / 400;
+            await sendCommand(client, `INSERT ${table} ${i} '[${Math.cos(angle).toFixed(4)}, ${Math.sin(angle).toFixed(4)}, 0]'`);
+        }
+
+        // Test 1: SEARCH answers the same through the index
+        console.log('\n--- Test 1: CREATE VECTOR INDEX ---');
+        const before = ids(await sendCommand(client, `SEARCH ${table} emb [1, 0, 0] 3`));
+        let result = await sendCommand(client, `CREATE VECTOR INDEX ON ${table}(emb) DIMS 3 METRIC cosine`);
+        if (result !== '+OK') {
+            throw new Error('CREATE VECTOR INDEX: Expected OK');
+        }
+        const after = ids(await sendCommand(client, `SEARCH ${table} emb [1, 0, 0] 3`));
+        if (before.join() !== '1,2,3' || after.join() !== before.join()) {
+            throw new Error('SEARCH: Expected rows 1, 2, 3 with and without the index');
+        }
+        result = await sendCommand(client, `SHOW INDEXES FROM ${table}`);
+        if (!result.includes(`vec_${table}_emb`) || !result.includes('hnsw dims=3 metric=cosine')) {
+            throw new Error('SHOW INDEXES: Expected the vector index');
+        }
+        console.log('Test 1 PASSED: index used transparently');
+
+        // Test 2: writes keep the index current
+        console.log('\n--- Test 2: maintenance ---');
+        await sendCommand(client, `UPDATE ${table} SET emb = '[1, 0.0001, 0]' WHERE id = 150`);
+        await sendCommand(client, `DELETE FROM ${table} WHERE id = 1`);
+        const near = ids(await sendCommand(client, `SEARCH ${table} emb [1, 0, 0] 2`));
+        if (near.join() !== '150,2') {
+            throw new Error('SEARCH: Expected the updated row first and the deleted row gone');
+        }
+        result = await sendCommand(client, `CHECK TABLE ${table}`);
+        if (result !== '+OK') {
+            throw new Error('CHECK TABLE: Expected a consistent index');
+        }
+        console.log('Test 2 PASSED: index follows writes');
+
+        // Test 3: dimensions are enforced
+        console.log('\n--- Test 3: dimensions ---');
+        result = await sendCommand(client, `INSERT ${table} 500 '[1, 0]'`);
+        if (!result.includes('must have 3 dimensions')) {
+            throw new Error('INSERT: Expected a dimension error');
+        }
+        result = await sendCommand(client, `SEARCH ${table} emb [1, 0] 1`);
+        if (!result.startsWith('-')) {
+            throw new Error('SEARCH: Expected a dimension error');
+        }
+        console.log('Test 3 PASSED: dimensions enforced');
+
+        // Test 4: other metrics and a threshold
+        console.log('\n--- Test 4: METRIC / THRESHOLD ---');
+        await sendCommand(client, `INSERT ${table} 300 '[5, 5, 0]'`);
+        if (ids(await sendCommand(client, `SEARCH ${table} emb [1, 0, 0] 1 METRIC dot`))[0] !== '300') {
+            throw new Error('SEARCH: Expected the longest vector first by dot product');
+        }
+        const close = ids(await sendCommand(client, `SEARCH ${table} emb [1, 0, 0] 10 METRIC euclidean THRESHOLD 0.02`));
+        if (close.join() !== '150,2') {
+            throw new Error('SEARCH: Expected only rows within the distance');
+        }
+        result = await sendCommand(client, `SET output = json`);
+        result = await sendCommand(client, `SEARCH ${table} emb [1, 0, 0] 1`);
+        if (!JSON.parse(result.split('\r\n')[1])[0].score) {
+            throw new Error('SEARCH: Expected a score column');
+        }
+        console.log('Test 4 PASSED: metrics and threshold');
+
+        // Test 5: WHERE narrows the rows before ranking
+        console.log('\n--- Test 5: filtered SEARCH ---');
+        await sendCommand(client, `SET output = text`);
+        const ranged = ids(await sendCommand(client, `SEARCH ${table} emb [1, 0, 0] LIMIT 3 WHERE id > 100 AND id < 199`));
+        if (ranged.join() !== '150,101,102') {
+            throw new Error('SEARCH: Expected the nearest rows among the matching ones');
+        }
+        const few = ids(await sendCommand(client, `SEARCH ${table} emb [0, 1, 0] LIMIT 5 WHERE id IN (3, 4, 5)`));
+        if (few.join() !== '5,4,3') {
+            throw new Error('SEARCH: Expected only the listed rows');
+        }
+        console.log('Test 5 PASSED: filtered search');
+
+        // Test 6: a declared dimension without an index
+        console.log('\n--- Test 6: vector(n) columns ---');
+        await sendCommand(client, `CREATE TABLE ${table}_d id:int:pk emb:vector(2)`);
+        result = await sendCommand(client, `INSERT ${table}_d 1 '[1, 0, 0]'`);
+        if (!result.includes('must have 2 dimensions')) {
+            throw new Error('INSERT: Expected a dimension error');
+        }
+        result = await sendCommand(client, `INSERT ${table}_d 1 'not a vector'`);
+        if (!result.includes('Invalid vector')) {
+            throw new Error('INSERT: Expected a malformed vector error');
+        }
+        await sendCommand(client, `INSERT ${table}_d 1 '[1, 0]'`);
+        result = await sendCommand(client, `SEARCH ${table}_d emb [1, 0, 0] 1`);
+        if (!result.includes('must have 2 dimensions')) {
+            throw new Error('SEARCH: Expected a dimension error');
+        }
+        if (ids(await sendCommand(client, `SEARCH ${table}_d emb [1, 0] 1`)).join() !== '1') {
+            throw new Error('SEARCH: Expected the stored row');
+        }
+        console.log('Test 6 PASSED: declared dimensions enforced');
+
+        console.log('\n=== ALL VECTOR INDEX TESTS PASSED ===');
+    } catch (err) {
+        console.error('Test Failed:', err);
+        process.exit(1);
+    } finally {
+        client.end();
+    }
+}
+
+runTests();
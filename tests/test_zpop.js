@@ -0,0 +1,81 @@
+const net = require('net');
+
+const PORT = 8569;
+
+function connect() {
+    return new Promise((resolve) => {
+        const client = new net.Socket();
+        client.connect(PORT, 'localhost', () => resolve(client));
+    });
+}
+
+function sendCommand(client, command) {
+    return new Promise((resolve, reject) => {
+        client.write(command + '\n');
+        client.once('data', (data) => {
+            const resp = data.toString().trim();
+            console.log(`REQ: ${command} -> RESP: ${resp.replace(/\r\n/g, ' ')}`);
+            resolve(resp);
+        });
+        client.once('error', reject);
+    });
+}
+
+const sleep = (ms) => new Promise((resolve) => setTimeout(resolve, ms));
+
+async function runTests() {
+    const client = await connect();
+    const waiter = await connect();
+    try {
+        console.log('Connected to ToriDB');
+        await sendCommand(client, 'AUTH default secret');
+        await sendCommand(waiter, 'AUTH default secret');
+        const key = 'zpop' + Date.now();
+
+        // Test 1: ZPOPMIN / ZPOPMAX take members from either end
+        console.log('\n--- Test 1: ZPOPMIN / ZPOPMAX ---');
+        await sendCommand(client, `ZADD ${key} 1 low 5 mid 9 high`);
+        let result = await sendCommand(client, `ZPOPMIN ${key}`);
+        if (!result.includes('low') || !result.includes('1')) {
+            throw new Error('ZPOPMIN: Lowest member not returned');
+        }
+        result = await sendCommand(client, `ZPOPMAX ${key} 2`);
+        if (result.indexOf('high') < 0 || result.indexOf('high') > result.indexOf('mid')) {
+            throw new Error('ZPOPMAX: Members not returned highest first');
+        }
+        result = await sendCommand(client, `EXISTS ${key}`);
+        if (result !== ':0') {
+            throw new Error('ZPOPMAX: Emptied set not removed');
+        }
+        console.log('Test 1 PASSED: members popped by score');
+
+        // Test 2: BZPOPMIN waits for a member to be added
+        console.log('\n--- Test 2: BZPOPMIN blocks until ZADD ---');
+        const pending = sendCommand(waiter, `BZPOPMIN ${key} 5`);
+        await sleep(200);
+        await sendCommand(client, `ZADD ${key} 3 job`);
+        result = await pending;
+        if (!result.includes(key) || !result.includes('job')) {
+            throw new Error('BZPOPMIN: Added member not delivered');
+        }
+        console.log('Test 2 PASSED: blocked pop woken by ZADD');
+
+        // Test 3: BZPOPMAX times out with nil
+        console.log('\n--- Test 3: BZPOPMAX timeout ---');
+        result = await sendCommand(client, `BZPOPMAX ${key} 0.2`);
+        if (result !== '$-1') {
+            throw new Error('BZPOPMAX: Expected nil on timeout');
+        }
+        console.log('Test 3 PASSED: timeout returns nil');
+
+        console.log('\n=== ALL ZPOP TESTS PASSED ===');
+    } catch (err) {
+        console.error('Test Failed:', err);
+        process.exit(1);
+    } finally {
+        client.end();
+        waiter.end();
+    }
+}
+
+runTests();